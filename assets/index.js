@@ -4,22 +4,32 @@
  * @property {string} name
  * @property {number} mtime
  * @property {number} size
+ * @property {"image"|"video"|"audio"} [media_type]
  */
 
 /**
  * @typedef {object} DATA
  * @property {string} href
  * @property {string} uri_prefix
- * @property {"Index" | "Edit" | "View"} kind
+ * @property {"Index" | "Gallery" | "Edit" | "View"} kind
  * @property {PathItem[]} paths
  * @property {boolean} allow_upload
  * @property {boolean} allow_delete
  * @property {boolean} allow_search
  * @property {boolean} allow_archive
+ * @property {boolean} archive_progress
+ * @property {boolean} confirm_rm_dir
  * @property {boolean} auth
+ * @property {boolean} auth_form
  * @property {string} user
  * @property {boolean} dir_exists
+ * @property {boolean} upload_only
  * @property {string} editable
+ * @property {string} [readme]
+ * @property {"image"|"video"|"audio"} [media_type]
+ * @property {number} [duration]
+ * @property {string} [language]
+ * @property {string} [highlighted]
  */
 
 var DUFS_MAX_UPLOADINGS = 1;
@@ -35,6 +45,7 @@ var DATA;
  * @property {string} q
  * @property {string} sort
  * @property {string} order
+ * @property {string} gallery
  */
 const PARAMS = Object.fromEntries(new URLSearchParams(window.location.search).entries());
 
@@ -45,7 +56,12 @@ const IFRAME_FORMATS = [
   ".mp3", ".ogg", ".wav", ".m4a",
 ];
 
-const dirEmptyNote = PARAMS.q ? 'No results' : DATA.dir_exists ? 'Empty folder' : 'Folder will be created when a file is uploaded';
+/**
+ * Where the assets are served from, ending with `_`
+ */
+const assetsUrl = document.currentScript.src.replace(/index\.js$/, "");
+
+const dirEmptyNote = DATA.upload_only ? 'Uploaded files are not listed' : PARAMS.q ? 'No results' : DATA.dir_exists ? 'Empty folder' : 'Folder will be created when a file is uploaded';
 
 const ICONS = {
   dir: `<svg height="16" viewBox="0 0 14 16" width="14"><path fill-rule="evenodd" d="M13 4H7V3c0-.66-.31-1-1-1H1c-.55 0-1 .45-1 1v10c0 .55.45 1 1 1h12c.55 0 1-.45 1-1V5c0-.55-.45-1-1-1zM6 4H1V3h5v1z"></path></svg>`,
@@ -91,6 +107,25 @@ let $userBtn;
  * @type Element
  */
 let $userName;
+/**
+ * @type Element
+ */
+let $gallery;
+/**
+ * @type Element
+ */
+let $lightbox;
+/**
+ * The media files of the gallery, paged through in the lightbox
+ * @type PathItem[]
+ */
+let galleryMedia = [];
+let lightboxIndex = 0;
+/**
+ * The ETag of the file when it was opened in the editor, so saving doesn't overwrite newer changes
+ * @type string|null
+ */
+let editorETag = null;
 
 function ready() {
   $pathsTable = document.querySelector(".paths-table")
@@ -101,22 +136,33 @@ function ready() {
   $editor = document.querySelector(".editor");
   $userBtn = document.querySelector(".user-btn");
   $userName = document.querySelector(".user-name");
+  $gallery = document.querySelector(".gallery");
+  $lightbox = document.querySelector(".lightbox");
+
+  // Filled in with `--site-name` by the server
+  const siteName = document.title || "Dufs";
 
   addBreadcrumb(DATA.href, DATA.uri_prefix);
 
   if (DATA.kind == "Index") {
-    document.title = `Index of ${DATA.href} - Dufs`;
+    document.title = `Index of ${DATA.href} - ${siteName}`;
+    document.querySelector(".index-page").classList.remove("hidden");
+
+    setupIndexPage();
+
+  } else if (DATA.kind == "Gallery") {
+    document.title = `Gallery of ${DATA.href} - ${siteName}`;
     document.querySelector(".index-page").classList.remove("hidden");
 
     setupIndexPage();
 
   } else if (DATA.kind == "Edit") {
-    document.title = `Edit ${DATA.href} - Dufs`;
+    document.title = `Edit ${DATA.href} - ${siteName}`;
     document.querySelector(".editor-page").classList.remove("hidden");;
 
     setupEditorPage();
   } else if (DATA.kind == "View") {
-    document.title = `View ${DATA.href} - Dufs`;
+    document.title = `View ${DATA.href} - ${siteName}`;
     document.querySelector(".editor-page").classList.remove("hidden");;
 
     setupEditorPage();
@@ -262,7 +308,10 @@ function addBreadcrumb(href, uri_prefix) {
       path += encodeURIComponent(name);
     }
     const encodedName = encodedStr(name);
-    if (i === 0) {
+    if (i === 0 && $breadcrumb.dataset.logo) {
+      $breadcrumb.insertAdjacentHTML("beforeend", `<a href="${path}" title="Root"><img class="logo" alt="Root"></a>`);
+      $breadcrumb.querySelector(".logo").src = $breadcrumb.dataset.logo;
+    } else if (i === 0) {
       $breadcrumb.insertAdjacentHTML("beforeend", `<a href="${path}" title="Root"><svg width="16" height="16" viewBox="0 0 16 16"><path d="M6.5 14.5v-3.505c0-.245.25-.495.5-.495h2c.25 0 .5.25.5.5v3.5a.5.5 0 0 0 .5.5h4a.5.5 0 0 0 .5-.5v-7a.5.5 0 0 0-.146-.354L13 5.793V2.5a.5.5 0 0 0-.5-.5h-1a.5.5 0 0 0-.5.5v1.293L8.354 1.146a.5.5 0 0 0-.708 0l-6 6A.5.5 0 0 0 1.5 7.5v7a.5.5 0 0 0 .5.5h4a.5.5 0 0 0 .5-.5z"/></svg></a>`);
     } else if (i === len - 1) {
       $breadcrumb.insertAdjacentHTML("beforeend", `<b>${encodedName}</b>`);
@@ -281,6 +330,9 @@ function setupIndexPage() {
     $download.href = baseUrl() + "?zip";
     $download.title = "Download folder as a .zip file";
     $download.classList.remove("hidden");
+    if (DATA.archive_progress) {
+      setupArchiveProgress();
+    }
   }
 
   if (DATA.allow_upload) {
@@ -298,8 +350,223 @@ function setupIndexPage() {
     setupSearch()
   }
 
+  if (DATA.dir_exists && !DATA.upload_only) {
+    const $galleryToggle = document.querySelector(".gallery-toggle");
+    if (DATA.kind == "Gallery") {
+      $galleryToggle.href = baseUrl();
+      $galleryToggle.title = "List view";
+    } else {
+      $galleryToggle.href = baseUrl() + "?gallery";
+    }
+    $galleryToggle.classList.remove("hidden");
+  }
+
+  if (DATA.readme) {
+    const $readme = document.querySelector(".readme");
+    $readme.innerHTML = DATA.readme;
+    $readme.classList.remove("hidden");
+  }
+
+  if (DATA.kind == "Gallery") {
+    renderGallery();
+    setupLightbox();
+    return;
+  }
+
   renderPathsTableHead();
   renderPathsTableBody();
+
+  if (DATA.dir_exists && !DATA.upload_only && !PARAMS.q) {
+    setupWatch();
+    setupEvents();
+  }
+}
+
+/**
+ * Render the folders and media files as a grid of thumbnails
+ */
+function renderGallery() {
+  if (!DATA.paths || DATA.paths.length === 0) {
+    $emptyFolder.textContent = dirEmptyNote;
+    $emptyFolder.classList.remove("hidden");
+    return;
+  }
+  $gallery.classList.remove("hidden");
+  galleryMedia = DATA.paths.filter(v => v.media_type);
+  for (const file of DATA.paths) {
+    const encodedName = encodedStr(file.name);
+    const url = newUrl(file.name);
+    if (!file.media_type) {
+      $gallery.insertAdjacentHTML("beforeend", `
+<a class="gallery-item" href="${url}/?gallery" title="${encodedName}">
+  <div class="gallery-preview">${getPathSvg(file.path_type)}</div>
+  <div class="gallery-name">${encodedName}</div>
+</a>`);
+      continue;
+    }
+    let preview;
+    if (file.media_type === "image") {
+      preview = `<img loading="lazy" src="${url}?thumb" alt="${encodedName}">`;
+    } else if (file.media_type === "video") {
+      preview = `<video preload="metadata" muted src="${url}#t=0.1"></video>`;
+    } else {
+      preview = ICONS.file;
+    }
+    $gallery.insertAdjacentHTML("beforeend", `
+<div class="gallery-item" data-index="${galleryMedia.indexOf(file)}" title="${encodedName}">
+  <div class="gallery-preview">${preview}</div>
+  <div class="gallery-name">${encodedName}</div>
+</div>`);
+  }
+  $gallery.addEventListener("click", e => {
+    const $item = e.target.closest(".gallery-item[data-index]");
+    if ($item) showLightbox(Number($item.dataset.index));
+  });
+}
+
+function setupLightbox() {
+  $lightbox.querySelector(".lightbox-prev").addEventListener("click", () => showLightbox(lightboxIndex - 1));
+  $lightbox.querySelector(".lightbox-next").addEventListener("click", () => showLightbox(lightboxIndex + 1));
+  $lightbox.querySelector(".lightbox-close").addEventListener("click", closeLightbox);
+  $lightbox.addEventListener("click", e => {
+    if (e.target === $lightbox) closeLightbox();
+  });
+  document.addEventListener("keydown", e => {
+    if ($lightbox.classList.contains("hidden")) return;
+    if (e.key === "Escape") {
+      closeLightbox();
+    } else if (e.key === "ArrowLeft") {
+      showLightbox(lightboxIndex - 1);
+    } else if (e.key === "ArrowRight") {
+      showLightbox(lightboxIndex + 1);
+    }
+  });
+}
+
+/**
+ * Show a media file of the gallery full size
+ * @param {number} index
+ */
+function showLightbox(index) {
+  const len = galleryMedia.length;
+  lightboxIndex = (index + len) % len;
+  const file = galleryMedia[lightboxIndex];
+  const encodedName = encodedStr(file.name);
+  const url = newUrl(file.name);
+  let media;
+  if (file.media_type === "image") {
+    media = `<img src="${url}" alt="${encodedName}">`;
+  } else if (file.media_type === "video") {
+    media = `<video src="${url}" controls autoplay></video>`;
+  } else {
+    media = `<audio src="${url}" controls autoplay></audio>`;
+  }
+  $lightbox.querySelector(".lightbox-content").innerHTML = `${media}<div class="gallery-name">${encodedName}</div>`;
+  $lightbox.classList.remove("hidden");
+}
+
+function closeLightbox() {
+  $lightbox.classList.add("hidden");
+  // Stop playing videos and audios
+  $lightbox.querySelector(".lightbox-content").innerHTML = "";
+}
+
+/**
+ * Live-update the table with the changes of the folder
+ */
+function setupWatch() {
+  if (!window.EventSource) return;
+  DATA.paths = DATA.paths || [];
+  const source = new EventSource(baseUrl() + "?watch");
+  const update = event => {
+    const file = JSON.parse(event.data);
+    const index = DATA.paths.findIndex(v => v && v.name === file.name);
+    const $old = index >= 0 ? document.getElementById(`addPath${index}`) : null;
+    if (index >= 0) {
+      DATA.paths[index] = null;
+    }
+    if (event.type === "delete") {
+      $old?.remove();
+      if (!DATA.paths.find(v => !!v)) {
+        $pathsTable.classList.add("hidden");
+        $emptyFolder.textContent = dirEmptyNote;
+        $emptyFolder.classList.remove("hidden");
+      }
+      return;
+    }
+    DATA.paths.push(file);
+    const newIndex = DATA.paths.length - 1;
+    addPath(file, newIndex);
+    // Keep the place of a modified entry
+    $old?.replaceWith(document.getElementById(`addPath${newIndex}`));
+    $pathsTable.classList.remove("hidden");
+    $emptyFolder.classList.add("hidden");
+  };
+  for (const type of ["create", "modify", "delete"]) {
+    source.addEventListener(type, update);
+  }
+}
+
+/**
+ * Show the progress of zip downloads, whose size is unknown to the browser
+ */
+function setupArchiveProgress() {
+  if (!window.EventSource) return;
+  const $progress = document.querySelector(".archive-progress");
+  document.addEventListener("click", e => {
+    const $link = e.target.closest("a");
+    if (!$link || !$link.href.endsWith("?zip")) return;
+    e.preventDefault();
+    const id = Math.random().toString(36).slice(2) + Date.now().toString(36);
+    const source = new EventSource(`${assetsUrl}downloads/${id}`);
+    let started = false;
+    const start = () => {
+      if (started) return;
+      started = true;
+      const $download = document.createElement("a");
+      $download.href = `${$link.href}&download-id=${id}`;
+      $download.download = "";
+      $download.click();
+    };
+    // Follow the download before it starts, so no progress is missed
+    source.addEventListener("open", start);
+    source.addEventListener("error", () => {
+      start();
+      source.close();
+      $progress.classList.add("hidden");
+    });
+    source.addEventListener("progress", message => {
+      const { sent, total } = JSON.parse(message.data);
+      $progress.value = total ? sent / total : 0;
+      $progress.title = `Zipping ${formatPercent(total ? sent * 100 / total : 0)}`;
+      $progress.classList.remove("hidden");
+    });
+    source.addEventListener("done", () => {
+      source.close();
+      $progress.classList.add("hidden");
+    });
+  });
+}
+
+/**
+ * Show the progress of the uploads others make to the folder
+ */
+function setupEvents() {
+  if (!window.WebSocket) return;
+  const ws = new WebSocket(assetsUrl.replace(/^http/, "ws") + "events");
+  const dir = DATA.href.replace(/\/$/, "");
+  ws.addEventListener("message", message => {
+    const event = JSON.parse(message.data);
+    if (event.type !== "progress" || !event.total) return;
+    const slash = event.path.lastIndexOf("/");
+    if (event.path.slice(0, slash) !== dir) return;
+    const name = event.path.slice(slash + 1);
+    const index = DATA.paths.findIndex(v => v && v.name === name);
+    const $size = index >= 0 ? document.querySelector(`#addPath${index} .cell-size`) : null;
+    if ($size) {
+      $size.textContent = `${Math.floor(event.received * 100 / event.total)}%`;
+    }
+  });
 }
 
 /**
@@ -393,13 +660,13 @@ function addPath(file, index) {
   }
   if (DATA.allow_delete) {
     if (DATA.allow_upload) {
-      actionMove = `<div onclick="movePath(${index})" class="action-btn" id="moveBtn${index}" title="Move to new path">${ICONS.move}</div>`;
+      actionMove = `<div class="action-btn" id="moveBtn${index}" title="Move to new path">${ICONS.move}</div>`;
       if (!isDir) {
         actionEdit = `<a class="action-btn" title="Edit file" target="_blank" href="${url}?edit">${ICONS.edit}</a>`;
       }
     }
     actionDelete = `
-    <div onclick="deletePath(${index})" class="action-btn" id="deleteBtn${index}" title="Delete">${ICONS.delete}</div>`;
+    <div class="action-btn" id="deleteBtn${index}" title="Delete">${ICONS.delete}</div>`;
   }
   if (!actionEdit && !isDir) {
     actionView = `<a class="action-btn" title="View file" target="_blank" href="${url}?view">${ICONS.view}</a>`;
@@ -425,6 +692,8 @@ function addPath(file, index) {
   <td class="cell-size">${getSizeHTML(file.size)}</td>
   ${actionCell}
 </tr>`)
+  document.getElementById(`moveBtn${index}`)?.addEventListener("click", () => movePath(index));
+  document.getElementById(`deleteBtn${index}`)?.addEventListener("click", () => deletePath(index));
 }
 
 function setupDropzone() {
@@ -455,10 +724,20 @@ function setupAuth() {
   if (DATA.user) {
     $userBtn.classList.remove("hidden");
     $userName.textContent = DATA.user;
+    if (DATA.auth_form) {
+      $userBtn.title = "Logout";
+      $userBtn.addEventListener("click", () => {
+        location.href = baseUrl() + "?logout";
+      });
+    }
   } else {
     const $loginBtn = document.querySelector(".login-btn");
     $loginBtn.classList.remove("hidden");
     $loginBtn.addEventListener("click", async () => {
+      if (DATA.auth_form) {
+        location.href = baseUrl() + "?login";
+        return;
+      }
       try {
         await checkAuth()
         location.reload();
@@ -554,7 +833,10 @@ async function setupEditorPage() {
     const $notEditable = document.querySelector(".not-editable");
     const url = baseUrl();
     const ext = extName(baseName(url));
-    if (IFRAME_FORMATS.find(v => v === ext)) {
+    if (DATA.media_type === "video" || DATA.media_type === "audio") {
+      const duration = DATA.duration ? `<div class="media-duration">${formatDuration(DATA.duration)}</div>` : "";
+      $notEditable.insertAdjacentHTML("afterend", `<div class="media-player"><${DATA.media_type} src="${url}" controls preload="metadata"></${DATA.media_type}>${duration}</div>`)
+    } else if (IFRAME_FORMATS.find(v => v === ext)) {
       $notEditable.insertAdjacentHTML("afterend", `<iframe src="${url}" sandbox width="100%" height="${window.innerHeight - 100}px"></iframe>`)
     } else {
       $notEditable.classList.remove("hidden");
@@ -563,10 +845,17 @@ async function setupEditorPage() {
     return;
   }
 
+  if (DATA.highlighted) {
+    $editor.insertAdjacentHTML("afterend", `<div class="highlighted" title="${encodedStr(DATA.language)}">${DATA.highlighted}</div>`);
+    return;
+  }
+
   $editor.classList.remove("hidden");
   try {
-    const res = await fetch(baseUrl());
+    const res = await fetch(baseUrl(), { cache: "no-store" });
     await assertResOK(res);
+    // A compressed response carries the weak form of the file's ETag
+    editorETag = res.headers.get("etag")?.replace(/^W\//, "") || null;
     const encoding = getEncoding(res.headers.get("content-type"));
     if (encoding === "utf-8") {
       $editor.value = await res.text();
@@ -601,16 +890,31 @@ async function deletePath(index) {
 }
 
 async function doDeletePath(name, url, cb) {
-  if (!confirm(`Delete \`${name}\`?`)) return;
+  let entries = 0;
+  if (DATA.confirm_rm_dir) {
+    try {
+      const res = await fetch(url + "?stat");
+      await assertResOK(res);
+      entries = (await res.json()).entries || 0;
+    } catch (err) {
+      alert(`Cannot delete \`${name}\`, ${err.message}`);
+      return;
+    }
+  }
+  const message = entries > 0
+    ? `Delete \`${name}\` and the ${entries} entries in it?`
+    : `Delete \`${name}\`?`;
+  if (!confirm(message)) return;
   try {
     await checkAuth();
+    if (entries > 0) url += `?confirm=${entries}`;
     const res = await fetch(url, {
       method: "DELETE",
     });
     await assertResOK(res);
     cb();
   } catch (err) {
-    alert(`Cannot delete \`${file.name}\`, ${err.message}`);
+    alert(`Cannot delete \`${name}\`, ${err.message}`);
   }
 }
 
@@ -671,10 +975,15 @@ async function doMovePath(fileUrl) {
  */
 async function saveChange() {
   try {
-    await fetch(baseUrl(), {
+    const res = await fetch(baseUrl(), {
       method: "PUT",
       body: $editor.value,
+      headers: editorETag ? { "If-Match": editorETag } : {},
     });
+    if (res.status === 412) {
+      throw new Error("the file was changed since it was opened, reload it to see the changes");
+    }
+    await assertResOK(res);
     location.reload();
   } catch (err) {
     alert(`Failed to save file, ${err.message}`);
@@ -686,6 +995,10 @@ async function checkAuth() {
   const res = await fetch(baseUrl(), {
     method: "WRITEABLE",
   });
+  if (res.status === 401 && DATA.auth_form) {
+    location.href = baseUrl() + "?login";
+    throw new Error("Login required");
+  }
   await assertResOK(res);
   document.querySelector(".login-btn").classList.add("hidden");
   $userBtn.classList.remove("hidden");
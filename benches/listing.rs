@@ -0,0 +1,86 @@
+//! Times listing a folder of symlinks, which are each checked to stay in the root.
+//!
+//! Run with `cargo bench --bench listing`.
+
+use assert_fs::TempDir;
+use port_check::free_local_port;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const ENTRIES: usize = 5000;
+const ROUNDS: u32 = 20;
+
+fn main() {
+    let tmpdir = TempDir::new().unwrap();
+    let root = tmpdir.path();
+    // Deep enough that resolving the whole path of every entry shows
+    let files = root.join("a/b/c/d/e/files");
+    std::fs::create_dir_all(&files).unwrap();
+    std::fs::create_dir(root.join("links")).unwrap();
+    for i in 0..ENTRIES {
+        let name = format!("{i}.txt");
+        std::fs::write(files.join(&name), "").unwrap();
+        symlink(
+            format!("../a/b/c/d/e/files/{name}"),
+            root.join("links").join(&name),
+        );
+    }
+
+    for args in [&[] as &[&str], &["--metadata-cache", "60s"]] {
+        let port = free_local_port().unwrap();
+        let server = Server(
+            Command::new(env!("CARGO_BIN_EXE_dufs"))
+                .arg(root)
+                .arg("-p")
+                .arg(port.to_string())
+                .args(args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap(),
+        );
+        while !port_check::is_port_reachable(format!("localhost:{port}")) {
+            sleep(Duration::from_millis(50));
+        }
+        let url = format!("http://localhost:{port}/links/?json");
+        let client = reqwest::blocking::Client::new();
+        let list = || {
+            let text = client.get(&url).send().unwrap().text().unwrap();
+            assert!(text.contains(&format!("{}.txt", ENTRIES - 1)));
+        };
+        let start = Instant::now();
+        list();
+        let first = start.elapsed();
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            list();
+        }
+        println!(
+            "{:<24} first {:>8.1?}, then {:>8.1?} per listing of {ENTRIES} symlinks",
+            format!("{args:?}"),
+            first,
+            start.elapsed() / ROUNDS,
+        );
+        drop(server);
+    }
+}
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: String, path: std::path::PathBuf) {
+    std::os::unix::fs::symlink(target, path).unwrap();
+}
+
+#[cfg(windows)]
+fn symlink(target: String, path: std::path::PathBuf) {
+    std::os::windows::fs::symlink_file(target.replace('/', "\\"), path).unwrap();
+}
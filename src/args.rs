@@ -1,16 +1,35 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::builder::PossibleValuesParser;
-use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap::parser::{ValueSource, ValuesRef};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueHint};
 use clap_complete::{generate, Generator, Shell};
+use headers::{HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use ipnet::IpNet;
 use serde::{Deserialize, Deserializer};
 use smart_default::SmartDefault;
+use std::any::Any;
+use std::collections::HashSet;
 use std::env;
+use std::ffi::OsString;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::auth::AccessControl;
+use crate::auth::{AccessControl, AuthProxy, JwtAuth, LockoutPolicy};
 use crate::http_logger::HttpLogger;
-use crate::utils::encode_uri;
+use crate::rate_limit::RateLimit;
+use crate::rewrite::RewriteRules;
+use crate::s3::S3Target;
+use crate::utils::{encode_uri, glob, parse_duration, parse_ip_net, parse_size};
+
+/// The headers set by `--security-headers`
+pub const SECURITY_HEADERS: [&str; 4] = [
+    "content-security-policy",
+    "x-content-type-options",
+    "referrer-policy",
+    "strict-transport-security",
+];
 
 pub fn build_cli() -> Command {
     let app = Command::new(env!("CARGO_CRATE_NAME"))
@@ -20,13 +39,179 @@ pub fn build_cli() -> Command {
             env!("CARGO_PKG_DESCRIPTION"),
             " - ",
             env!("CARGO_PKG_REPOSITORY")
+        ));
+    let app = add_serve_args(app)
+        .arg(
+            Arg::new("completions")
+                .long("completions")
+                .value_name("shell")
+                .value_parser(value_parser!(Shell))
+                .hide(true)
+                .help("Print shell completion script for <shell>"),
+        )
+        .args_conflicts_with_subcommands(true)
+        .disable_help_subcommand(true)
+        .subcommand(add_serve_args(
+            Command::new("serve").about("Serve the files, the default without a subcommand"),
+        ))
+        .subcommand(
+            Command::new("hash")
+                .about("Hash a password read from stdin for use in auth rules")
+                .arg(
+                    Arg::new("algorithm")
+                        .short('m')
+                        .long("algorithm")
+                        .value_parser(PossibleValuesParser::new(["argon2", "bcrypt", "sha-512"]))
+                        .default_value("argon2")
+                        .help("Select hash algorithm"),
+                ),
+        )
+        .subcommand(
+            Command::new("gen-config")
+                .about("Print a config file with every option, commented out"),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print the completion script of a shell")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(value_parser!(Shell))
+                        .help("The shell to complete in"),
+                ),
+        )
+        .subcommand(Command::new("manpage").about("Print the man page, in roff"))
+        .subcommand(add_serve_args(
+            Command::new("check").about("Check the options, auth rules and TLS files, then exit"),
+        ))
+        .subcommand(add_client_args(
+            Command::new("upload")
+                .about("Upload files and folders to a folder of a remote dufs")
+                .arg(
+                    Arg::new("paths")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::AnyPath)
+                        .help("Files and folders to upload"),
+                )
+                .arg(
+                    Arg::new("url")
+                        .required(true)
+                        .help("URL of the folder to upload to"),
+                ),
         ))
+        .subcommand(add_client_args(
+            Command::new("download")
+                .about("Download a file or a folder from a remote dufs")
+                .arg(
+                    Arg::new("url")
+                        .required(true)
+                        .help("URL of the file or folder"),
+                )
+                .arg(
+                    Arg::new("dest")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::AnyPath)
+                        .help("Where to save it [default: .]"),
+                ),
+        ))
+        .subcommand(add_client_args(
+            Command::new("sync")
+                .about("Make a remote folder the same as a local one, or the other way around")
+                .arg(
+                    Arg::new("src")
+                        .required(true)
+                        .help("Folder to copy from, a path or a URL"),
+                )
+                .arg(
+                    Arg::new("dest")
+                        .required(true)
+                        .help("Folder to copy to, a URL or a path"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .long("delete")
+                        .action(ArgAction::SetTrue)
+                        .help("Delete what's in <dest> but not in <src>"),
+                ),
+        ));
+    #[cfg(windows)]
+    let app = app.subcommand(service_command());
+    app
+}
+
+/// The `service` subcommand, to run dufs from the Windows service manager
+#[cfg(windows)]
+fn service_command() -> Command {
+    let serve_args = || {
+        Arg::new("args")
+            .num_args(0..)
+            .trailing_var_arg(true)
+            .allow_hyphen_values(true)
+            .value_parser(value_parser!(OsString))
+    };
+    Command::new("service")
+        .about("Install or uninstall the Windows service of dufs")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("install")
+                .about(
+                    "Install the service, which serves with the options from the current directory",
+                )
+                .arg(serve_args().help("Options of the `serve` subcommand")),
+        )
+        .subcommand(Command::new("uninstall").about("Stop and uninstall the service"))
+        .subcommand(
+            Command::new("run")
+                .hide(true)
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(serve_args()),
+        )
+}
+
+/// Options of the `upload`, `download` and `sync` subcommands
+fn add_client_args(app: Command) -> Command {
+    app.arg(
+        Arg::new("user")
+            .short('u')
+            .long("user")
+            .value_name("user:pass")
+            .help("Credentials to sign in with, also taken from the URL"),
+    )
+    .arg(
+        Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_name("num")
+            .value_parser(value_parser!(usize))
+            .default_value("4")
+            .help("How many files to transfer at once"),
+    )
+    .arg(
+        Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .action(ArgAction::SetTrue)
+            .help("Don't show the progress"),
+    )
+}
+
+/// Options of the `serve` and `check` subcommands, also accepted without a subcommand
+fn add_serve_args(app: Command) -> Command {
+    let app = app
         .arg(
             Arg::new("serve-path")
                 .env("DUFS_SERVE_PATH")
 				.hide_env(true)
                 .value_parser(value_parser!(PathBuf))
-                .help("Specific path to serve [default: .]"),
+                .value_hint(ValueHint::AnyPath)
+                .help("Specific path to serve, or s3://bucket/prefix [default: .]"),
         )
         .arg(
             Arg::new("config")
@@ -35,6 +220,7 @@ pub fn build_cli() -> Command {
                 .short('c')
                 .long("config")
                 .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
                 .help("Specify configuration file"),
         )
         .arg(
@@ -55,9 +241,19 @@ pub fn build_cli() -> Command {
                 .short('p')
                 .long("port")
                 .value_parser(value_parser!(u16))
-                .help("Specify port to listen on [default: 5000]")
+                .help("Specify port to listen on, 0 for any free one [default: 5000]")
                 .value_name("port"),
         )
+        .arg(
+            Arg::new("urls-file")
+                .env("DUFS_URLS_FILE")
+                .hide_env(true)
+                .long("urls-file")
+                .value_name("path")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
+                .help("Write the URLs listened on to the file as JSON, `-` for stdout"),
+        )
         .arg(
             Arg::new("path-prefix")
                 .env("DUFS_PATH_PREFIX")
@@ -66,6 +262,16 @@ pub fn build_cli() -> Command {
                 .value_name("path")
                 .help("Specify a path prefix"),
         )
+        .arg(
+            Arg::new("mount")
+                .env("DUFS_MOUNT")
+                .hide_env(true)
+                .long("mount")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Serve another directory under a name in the root, e.g. /media=/srv/media")
+                .value_name("name=path"),
+        )
         .arg(
             Arg::new("hidden")
                 .env("DUFS_HIDDEN")
@@ -76,6 +282,34 @@ pub fn build_cli() -> Command {
                 .help("Hide paths from directory listings, e.g. tmp,*.log,*.lock")
                 .value_name("value"),
         )
+        .arg(
+            Arg::new("exclude")
+                .env("DUFS_EXCLUDE")
+                .hide_env(true)
+                .long("exclude")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Refuse access to paths as if they didn't exist, e.g. .env,secrets/")
+                .value_name("glob"),
+        )
+        .arg(
+            Arg::new("redirect")
+                .env("DUFS_REDIRECT")
+                .hide_env(true)
+                .long("redirect")
+                .action(ArgAction::Append)
+                .help("Redirect the paths matching a pattern, e.g. '/old/*=/new/$1' or '/go=302:https://example.com/'")
+                .value_name("pattern=target"),
+        )
+        .arg(
+            Arg::new("rewrite")
+                .env("DUFS_REWRITE")
+                .hide_env(true)
+                .long("rewrite")
+                .action(ArgAction::Append)
+                .help("Serve the paths matching a pattern as if another was requested, e.g. '/u/*=/users/$1.html'")
+                .value_name("pattern=target"),
+        )
         .arg(
             Arg::new("posix-hidden")
                 .env("DUFS_POSIX_HIDDEN")
@@ -90,7 +324,15 @@ pub fn build_cli() -> Command {
 				.hide_env(true)
                 .long("dir-size")
                 .action(ArgAction::SetTrue)
-                .help("Show sizes of folders in directory listing (synchronous; needs fast disk; doesn't consider auth during recursion)")
+                .help("Show sizes and file counts of folders in directory listing (doesn't consider auth during recursion)")
+        )
+        .arg(
+            Arg::new("default-sort")
+                .env("DUFS_DEFAULT_SORT")
+                .hide_env(true)
+                .long("default-sort")
+                .value_name("field")
+                .help("Sort listings by name, mtime, size or ext unless asked otherwise, e.g. mtime:desc [default: name]"),
         )
         .arg(
             Arg::new("auth")
@@ -102,17 +344,166 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::Append)
                 .value_name("rules"),
         )
+        .arg(
+            Arg::new("jwt-secret")
+                .env("DUFS_JWT_SECRET")
+                .hide_env(true)
+                .long("jwt-secret")
+                .value_name("secret")
+                .help("Accept bearer JWTs signed with the HMAC secret"),
+        )
+        .arg(
+            Arg::new("jwt-jwks")
+                .env("DUFS_JWT_JWKS")
+                .hide_env(true)
+                .long("jwt-jwks")
+                .value_name("url-or-path")
+                .help("Accept bearer JWTs signed with keys from the JWKS"),
+        )
+        .arg(
+            Arg::new("jwt-audience")
+                .env("DUFS_JWT_AUDIENCE")
+                .hide_env(true)
+                .long("jwt-audience")
+                .value_name("aud")
+                .help("Require bearer JWTs to be issued for the audience"),
+        )
+        .arg(
+            Arg::new("auth-proxy-header")
+                .env("DUFS_AUTH_PROXY_HEADER")
+                .hide_env(true)
+                .long("auth-proxy-header")
+                .value_name("name")
+                .help("Trust the user name in the header set by an authenticating proxy"),
+        )
+        .arg(
+            Arg::new("auth-proxy-trusted")
+                .env("DUFS_AUTH_PROXY_TRUSTED")
+                .hide_env(true)
+                .long("auth-proxy-trusted")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("ips")
+                .help("IPs or CIDRs of the authenticating proxy [default: 127.0.0.1,::1]"),
+        )
         .arg(
             Arg::new("auth-method")
-                .hide(true)
                 .env("DUFS_AUTH_METHOD")
 				.hide_env(true)
                 .long("auth-method")
-                .help("Select auth method")
-                .value_parser(PossibleValuesParser::new(["basic", "digest"]))
-                .default_value("digest")
+                .help("Select auth method, `form` serves a login page to browsers [default: digest]")
+                .value_parser(PossibleValuesParser::new(["basic", "digest", "form"]))
                 .value_name("value"),
         )
+        .arg(
+            Arg::new("auth-lockout")
+                .env("DUFS_AUTH_LOCKOUT")
+                .hide_env(true)
+                .long("auth-lockout")
+                .value_name("policy")
+                .help("Lock out IPs after failed auth attempts, e.g. 10/1m"),
+        )
+        .arg(
+            Arg::new("rate-limit")
+                .env("DUFS_RATE_LIMIT")
+                .hide_env(true)
+                .long("rate-limit")
+                .value_name("rate")
+                .help("Limit the requests of each user, or IP for anonymous ones, e.g. 10r/s"),
+        )
+        .arg(
+            Arg::new("max-concurrency")
+                .env("DUFS_MAX_CONCURRENCY")
+                .hide_env(true)
+                .long("max-concurrency")
+                .value_name("num")
+                .value_parser(value_parser!(usize))
+                .help("Answer 503 to the requests past this many handled at once"),
+        )
+        .arg(
+            Arg::new("metrics")
+                .env("DUFS_METRICS")
+                .hide_env(true)
+                .long("metrics")
+                .action(ArgAction::SetTrue)
+                .help("Serve the load of the server as Prometheus metrics at /__dufs__/metrics"),
+        )
+        .arg(
+            Arg::new("proxy-protocol")
+                .env("DUFS_PROXY_PROTOCOL")
+                .hide_env(true)
+                .long("proxy-protocol")
+                .action(ArgAction::SetTrue)
+                .help("Expect a PROXY protocol header on connections, sent by a TCP load balancer"),
+        )
+        .arg(
+            Arg::new("trusted-proxies")
+                .env("DUFS_TRUSTED_PROXIES")
+                .hide_env(true)
+                .long("trusted-proxies")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("cidrs")
+                .help("Take the client IP from X-Forwarded-For/X-Real-IP of these reverse proxies"),
+        )
+        .arg(
+            Arg::new("read-timeout")
+                .env("DUFS_READ_TIMEOUT")
+                .hide_env(true)
+                .long("read-timeout")
+                .value_name("duration")
+                .help("Drop connections that send nothing of a request for this long, e.g. 60s"),
+        )
+        .arg(
+            Arg::new("write-timeout")
+                .env("DUFS_WRITE_TIMEOUT")
+                .hide_env(true)
+                .long("write-timeout")
+                .value_name("duration")
+                .help("Drop connections that take nothing of a response for this long, e.g. 60s"),
+        )
+        .arg(
+            Arg::new("user")
+                .env("DUFS_USER")
+                .hide_env(true)
+                .long("user")
+                .value_name("user")
+                .help("Switch to this user once the ports are bound, when started as root"),
+        )
+        .arg(
+            Arg::new("group")
+                .env("DUFS_GROUP")
+                .hide_env(true)
+                .long("group")
+                .value_name("group")
+                .help("Switch to this group once the ports are bound [default: the user's]"),
+        )
+        .arg(
+            Arg::new("chroot")
+                .env("DUFS_CHROOT")
+                .hide_env(true)
+                .long("chroot")
+                .action(ArgAction::SetTrue)
+                .help("Confine dufs to the serve path once the ports are bound"),
+        )
+        .arg(
+            Arg::new("daemon")
+                .env("DUFS_DAEMON")
+                .hide_env(true)
+                .long("daemon")
+                .action(ArgAction::SetTrue)
+                .help("Run in the background once the ports are bound"),
+        )
+        .arg(
+            Arg::new("pid-file")
+                .env("DUFS_PID_FILE")
+                .hide_env(true)
+                .long("pid-file")
+                .value_name("path")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
+                .help("Write the process ID to the file while running"),
+        )
         .arg(
             Arg::new("allow-all")
                 .env("DUFS_ALLOW_ALL")
@@ -138,6 +529,32 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Allow delete files/folders"),
         )
+        .arg(
+            Arg::new("trash-dir")
+                .env("DUFS_TRASH_DIR")
+                .hide_env(true)
+                .long("trash-dir")
+                .value_name("path")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+                .help("Move deleted files/folders into this folder instead of removing them"),
+        )
+        .arg(
+            Arg::new("trash-retention")
+                .env("DUFS_TRASH_RETENTION")
+                .hide_env(true)
+                .long("trash-retention")
+                .value_name("duration")
+                .help("Purge deleted files/folders from the trash after this long [default: 30d]"),
+        )
+        .arg(
+            Arg::new("confirm-rm-dir")
+                .env("DUFS_CONFIRM_RM_DIR")
+                .hide_env(true)
+                .long("confirm-rm-dir")
+                .action(ArgAction::SetTrue)
+                .help("Only delete non-empty folders given ?confirm=<entries> from their ?stat"),
+        )
         .arg(
             Arg::new("allow-search")
                 .env("DUFS_ALLOW_SEARCH")
@@ -146,13 +563,130 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Allow search files/folders"),
         )
+        .arg(
+            Arg::new("search-index")
+                .env("DUFS_SEARCH_INDEX")
+                .hide_env(true)
+                .long("search-index")
+                .action(ArgAction::SetTrue)
+                .help("Keep an index of file names in memory for faster searches"),
+        )
+        .arg(
+            Arg::new("search-max-results")
+                .env("DUFS_SEARCH_MAX_RESULTS")
+                .hide_env(true)
+                .long("search-max-results")
+                .value_name("num")
+                .value_parser(value_parser!(usize))
+                .help("Stop a search once it has found this many results [default: 1000]"),
+        )
+        .arg(
+            Arg::new("search-timeout")
+                .env("DUFS_SEARCH_TIMEOUT")
+                .hide_env(true)
+                .long("search-timeout")
+                .value_name("duration")
+                .help("Stop a search once it has run this long [default: 10s]"),
+        )
+        .arg(
+            Arg::new("max-upload-size")
+                .env("DUFS_MAX_UPLOAD_SIZE")
+                .hide_env(true)
+                .long("max-upload-size")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Limit the size of uploaded files, e.g. 100M,/public:10M")
+                .value_name("size"),
+        )
+        .arg(
+            Arg::new("max-body-size")
+                .env("DUFS_MAX_BODY_SIZE")
+                .hide_env(true)
+                .long("max-body-size")
+                .help("Reject requests whose body is larger than this, e.g. 1G")
+                .value_name("size"),
+        )
+        .arg(
+            Arg::new("upload-allow-ext")
+                .env("DUFS_UPLOAD_ALLOW_EXT")
+                .hide_env(true)
+                .long("upload-allow-ext")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Only allow uploading files of these types, e.g. jpg,png")
+                .value_name("ext"),
+        )
+        .arg(
+            Arg::new("upload-deny-ext")
+                .env("DUFS_UPLOAD_DENY_EXT")
+                .hide_env(true)
+                .long("upload-deny-ext")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Refuse uploading files of these types, e.g. exe,elf")
+                .value_name("ext"),
+        )
+        .arg(
+            Arg::new("quota")
+                .env("DUFS_QUOTA")
+                .hide_env(true)
+                .long("quota")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Limit the disk usage of the root or folders, e.g. 10G,/public:1G")
+                .value_name("size"),
+        )
+        .arg(
+            Arg::new("min-free-space")
+                .env("DUFS_MIN_FREE_SPACE")
+                .hide_env(true)
+                .long("min-free-space")
+                .help("Reject uploads that would leave less free disk space than this, e.g. 5G")
+                .value_name("size"),
+        )
+        .arg(
+            Arg::new("hash-xattr")
+                .env("DUFS_HASH_XATTR")
+                .hide_env(true)
+                .long("hash-xattr")
+                .action(ArgAction::SetTrue)
+                .help("Cache the hashes of files in their extended attributes"),
+        )
+        .arg(
+            Arg::new("metadata-cache")
+                .env("DUFS_METADATA_CACHE")
+                .hide_env(true)
+                .long("metadata-cache")
+                .value_name("duration")
+                .help("Cache the metadata of files for listings and PROPFIND this long, e.g. 5s"),
+        )
+        .arg(
+            Arg::new("preload")
+                .env("DUFS_PRELOAD")
+                .hide_env(true)
+                .long("preload")
+                .value_name("size")
+                .help("Serve the files and listings from memory, keeping up to this much, e.g. 64M"),
+        )
+        .arg(
+            Arg::new("buffer-size")
+                .env("DUFS_BUFFER_SIZE")
+                .hide_env(true)
+                .long("buffer-size")
+                .value_name("size")
+                .help("Read files in chunks of this size when sending them [default: 64K]"),
+        )
         .arg(
             Arg::new("allow-symlink")
                 .env("DUFS_ALLOW_SYMLINK")
 				.hide_env(true)
                 .long("allow-symlink")
-                .action(ArgAction::SetTrue)
-                .help("Allow symlink to files/folders outside root directory"),
+                .action(ArgAction::Append)
+                .num_args(0..=1)
+                .default_missing_value("**")
+                .value_delimiter(',')
+                .value_name("paths")
+                .help("Allow symlinks to files/folders outside the root directory, everywhere or at the paths, e.g. /public/**"),
         )
         .arg(
             Arg::new("allow-archive")
@@ -160,7 +694,23 @@ pub fn build_cli() -> Command {
 				.hide_env(true)
                 .long("allow-archive")
                 .action(ArgAction::SetTrue)
-                .help("Allow zip archive generation"),
+                .help("Allow zip/tar archive generation"),
+        )
+        .arg(
+            Arg::new("no-zip64")
+                .env("DUFS_NO_ZIP64")
+                .hide_env(true)
+                .long("no-zip64")
+                .action(ArgAction::SetTrue)
+                .help("Refuse zip archives over 4GB or 65535 entries rather than use Zip64"),
+        )
+        .arg(
+            Arg::new("archive-progress")
+                .env("DUFS_ARCHIVE_PROGRESS")
+                .hide_env(true)
+                .long("archive-progress")
+                .action(ArgAction::SetTrue)
+                .help("Size up folders before zipping them, to report the progress of the download"),
         )
         .arg(
             Arg::new("enable-cors")
@@ -170,6 +720,93 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Enable CORS, sets `Access-Control-Allow-Origin: *`"),
         )
+        .arg(
+            Arg::new("enable-compression")
+                .env("DUFS_ENABLE_COMPRESSION")
+                .hide_env(true)
+                .long("enable-compression")
+                .action(ArgAction::SetTrue)
+                .help("Compress text responses, such as listings, with gzip or brotli"),
+        )
+        .arg(
+            Arg::new("security-headers")
+                .env("DUFS_SECURITY_HEADERS")
+                .hide_env(true)
+                .long("security-headers")
+                .action(ArgAction::SetTrue)
+                .help("Set Content-Security-Policy on pages, and nosniff, Referrer-Policy and HSTS on all responses"),
+        )
+        .arg(
+            Arg::new("security-headers-skip")
+                .env("DUFS_SECURITY_HEADERS_SKIP")
+                .hide_env(true)
+                .long("security-headers-skip")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(SECURITY_HEADERS))
+                .help("Leave out some of the security headers, e.g. strict-transport-security")
+                .value_name("header"),
+        )
+        .arg(
+            Arg::new("header")
+                .env("DUFS_HEADER")
+                .hide_env(true)
+                .long("header")
+                .action(ArgAction::Append)
+                .help("Add a header to responses, e.g. 'X-Frame-Options: DENY' or '/*.pdf:Content-Disposition: attachment'")
+                .value_name("header"),
+        )
+        .arg(
+            Arg::new("cache-control")
+                .env("DUFS_CACHE_CONTROL")
+                .hide_env(true)
+                .long("cache-control")
+                .action(ArgAction::Append)
+                .help("Set Cache-Control of files whose names match the glob, e.g. '*.jpg=max-age=86400'")
+                .value_name("glob=value"),
+        )
+        .arg(
+            Arg::new("cache-control-default")
+                .env("DUFS_CACHE_CONTROL_DEFAULT")
+                .hide_env(true)
+                .long("cache-control-default")
+                .help("Set Cache-Control of files that match no --cache-control glob")
+                .value_name("value"),
+        )
+        .arg(
+            Arg::new("dav-infinite-depth")
+                .env("DUFS_DAV_INFINITE_DEPTH")
+                .hide_env(true)
+                .long("dav-infinite-depth")
+                .action(ArgAction::SetTrue)
+                .help("Allow webdav `Depth: infinity` PROPFIND and infinite sync-collection"),
+        )
+        .arg(
+            Arg::new("dlna")
+                .env("DUFS_DLNA")
+                .hide_env(true)
+                .long("dlna")
+                .action(ArgAction::SetTrue)
+                .help("Advertise the media files to DLNA/UPnP players, such as smart TVs, on the LAN"),
+        )
+        .arg(
+            Arg::new("ftp-port")
+                .env("DUFS_FTP_PORT")
+                .hide_env(true)
+                .long("ftp-port")
+                .value_parser(value_parser!(u16))
+                .help("Also serve the files read-only over FTP on this port, with FTPS if TLS is enabled")
+                .value_name("port"),
+        )
+        .arg(
+            Arg::new("tftp-port")
+                .env("DUFS_TFTP_PORT")
+                .hide_env(true)
+                .long("tftp-port")
+                .value_parser(value_parser!(u16))
+                .help("Also serve the files read-only over TFTP on this UDP port, e.g. for PXE boot")
+                .value_name("port"),
+        )
         .arg(
             Arg::new("render-index")
                 .env("DUFS_RENDER_INDEX")
@@ -194,6 +831,50 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Serve SPA(Single Page Application) from `./index.html`"),
         )
+        .arg(
+            Arg::new("spa-root")
+                .env("DUFS_SPA_ROOT")
+                .hide_env(true)
+                .long("spa-root")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Serve a SPA from each of these folders rather than from the root, e.g. /app,/admin")
+                .value_name("paths"),
+        )
+        .arg(
+            Arg::new("spa-fallback")
+                .env("DUFS_SPA_FALLBACK")
+                .hide_env(true)
+                .long("spa-fallback")
+                .help("Serve this file of the SPA folder for the routes of the app [default: index.html]")
+                .value_name("name"),
+        )
+        .arg(
+            Arg::new("try-files")
+                .env("DUFS_TRY_FILES")
+                .hide_env(true)
+                .long("try-files")
+                .help("Serve a GET from the first of these paths that exists, or the last one, e.g. '$uri $uri/index.html /index.html'")
+                .value_name("paths"),
+        )
+        .arg(
+            Arg::new("index-name")
+                .env("DUFS_INDEX_NAME")
+                .hide_env(true)
+                .long("index-name")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Serve the first of these files that exists as the index of a folder [default: index.html]")
+                .value_name("names"),
+        )
+        .arg(
+            Arg::new("readme-name")
+                .env("DUFS_README_NAME")
+                .hide_env(true)
+                .long("readme-name")
+                .value_name("name")
+                .help("Show this markdown file below directory listings, empty for none [default: README.md]"),
+        )
         .arg(
             Arg::new("assets")
                 .env("DUFS_ASSETS")
@@ -201,22 +882,113 @@ pub fn build_cli() -> Command {
                 .long("assets")
                 .help("Set the path to the assets directory for overriding the built-in assets")
                 .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
                 .value_name("path")
         )
         .arg(
-            Arg::new("log-format")
-                .env("DUFS_LOG_FORMAT")
+            Arg::new("site-name")
+                .env("DUFS_SITE_NAME")
                 .hide_env(true)
-                .long("log-format")
-                .value_name("format")
-                .help("Customize http log format"),
+                .long("site-name")
+                .value_name("name")
+                .help("Show this name in the page titles [default: Dufs]"),
         )
         .arg(
-            Arg::new("completions")
-                .long("completions")
-                .value_name("shell")
-                .value_parser(value_parser!(Shell))
-                .help("Print shell completion script for <shell>"),
+            Arg::new("logo-url")
+                .env("DUFS_LOGO_URL")
+                .hide_env(true)
+                .long("logo-url")
+                .value_name("url")
+                .help("Show this image in place of the home icon"),
+        )
+        .arg(
+            Arg::new("header-html")
+                .env("DUFS_HEADER_HTML")
+                .hide_env(true)
+                .long("header-html")
+                .value_name("html")
+                .help("Show this HTML above the pages"),
+        )
+        .arg(
+            Arg::new("footer-html")
+                .env("DUFS_FOOTER_HTML")
+                .hide_env(true)
+                .long("footer-html")
+                .value_name("html")
+                .help("Show this HTML below the pages"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .env("DUFS_LOG_FORMAT")
+                .hide_env(true)
+                .long("log-format")
+                .value_name("format")
+                .help("Customize http log format"),
+        )
+        .arg(
+            Arg::new("hook-upload")
+                .env("DUFS_HOOK_UPLOAD")
+                .hide_env(true)
+                .long("hook-upload")
+                .value_name("cmd")
+                .help("Run a command after a file is uploaded"),
+        )
+        .arg(
+            Arg::new("hook-delete")
+                .env("DUFS_HOOK_DELETE")
+                .hide_env(true)
+                .long("hook-delete")
+                .value_name("cmd")
+                .help("Run a command after a file/folder is deleted"),
+        )
+        .arg(
+            Arg::new("hook-mkdir")
+                .env("DUFS_HOOK_MKDIR")
+                .hide_env(true)
+                .long("hook-mkdir")
+                .value_name("cmd")
+                .help("Run a command after a folder is created"),
+        )
+        .arg(
+            Arg::new("hook-move")
+                .env("DUFS_HOOK_MOVE")
+                .hide_env(true)
+                .long("hook-move")
+                .value_name("cmd")
+                .help("Run a command after a file/folder is moved"),
+        )
+        .arg(
+            Arg::new("hook-timeout")
+                .env("DUFS_HOOK_TIMEOUT")
+                .hide_env(true)
+                .long("hook-timeout")
+                .value_name("duration")
+                .help("Kill hook commands running longer than this [default: 60s]"),
+        )
+        .arg(
+            Arg::new("hook-concurrency")
+                .env("DUFS_HOOK_CONCURRENCY")
+                .hide_env(true)
+                .long("hook-concurrency")
+                .value_name("num")
+                .value_parser(value_parser!(usize))
+                .help("Run at most this many hook commands at once [default: 4]"),
+        )
+        .arg(
+            Arg::new("open")
+                .env("DUFS_OPEN")
+                .hide_env(true)
+                .long("open")
+                .action(ArgAction::SetTrue)
+                .help("Open the served URL in the browser on startup"),
+        )
+        .arg(
+            Arg::new("qrcode")
+                .env("DUFS_QRCODE")
+                .hide_env(true)
+                .long("qrcode")
+                .action(ArgAction::SetTrue)
+                .help("Print a QR code of the served URLs other devices can reach on startup"),
         );
 
     #[cfg(feature = "tls")]
@@ -228,6 +1000,7 @@ pub fn build_cli() -> Command {
                 .long("tls-cert")
                 .value_name("path")
                 .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
                 .help("Path to an SSL/TLS certificate to serve with HTTPS"),
         )
         .arg(
@@ -237,17 +1010,149 @@ pub fn build_cli() -> Command {
                 .long("tls-key")
                 .value_name("path")
                 .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
                 .help("Path to the SSL/TLS certificate's private key"),
+        )
+        .arg(
+            Arg::new("acme-domain")
+                .env("DUFS_ACME_DOMAIN")
+                .hide_env(true)
+                .long("acme-domain")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("domains")
+                .help(
+                    "Obtain a certificate for the domains from Let's Encrypt and serve with HTTPS",
+                ),
+        )
+        .arg(
+            Arg::new("acme-cache-dir")
+                .env("DUFS_ACME_CACHE_DIR")
+                .hide_env(true)
+                .long("acme-cache-dir")
+                .value_name("path")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+                .help("Store the ACME account and certificates in the directory"),
+        )
+        .arg(
+            Arg::new("tls-min-version")
+                .env("DUFS_TLS_MIN_VERSION")
+                .hide_env(true)
+                .long("tls-min-version")
+                .value_name("version")
+                .value_parser(["1.2", "1.3"])
+                .help("The lowest TLS version to accept [default: 1.2]"),
+        )
+        .arg(
+            Arg::new("tls-ciphers")
+                .env("DUFS_TLS_CIPHERS")
+                .hide_env(true)
+                .long("tls-ciphers")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("suites")
+                .help("Only accept the TLS cipher suites, e.g. TLS13_AES_256_GCM_SHA384 [default: all safe ones]"),
+        )
+        .arg(
+            Arg::new("http-redirect-port")
+                .env("DUFS_HTTP_REDIRECT_PORT")
+                .hide_env(true)
+                .long("http-redirect-port")
+                .value_parser(value_parser!(u16))
+                .help("Also listen for plain HTTP on this port and redirect it to HTTPS, e.g. 80")
+                .value_name("port"),
+        );
+
+    #[cfg(feature = "otlp")]
+    let app = app.arg(
+        Arg::new("otlp-endpoint")
+            .env("DUFS_OTLP_ENDPOINT")
+            .hide_env(true)
+            .long("otlp-endpoint")
+            .value_name("url")
+            .help("Export traces to an OpenTelemetry collector over OTLP/HTTP"),
+    );
+
+    #[cfg(feature = "plugins")]
+    let app = app.arg(
+        Arg::new("plugin")
+            .env("DUFS_PLUGIN")
+            .hide_env(true)
+            .long("plugin")
+            .action(ArgAction::Append)
+            .value_delimiter(',')
+            .value_name("path")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath)
+            .help("Load a WebAssembly plugin that hooks into the requests"),
+    );
+
+    #[cfg(feature = "sftp")]
+    let app = app
+        .arg(
+            Arg::new("sftp-port")
+                .env("DUFS_SFTP_PORT")
+                .hide_env(true)
+                .long("sftp-port")
+                .value_name("port")
+                .value_parser(value_parser!(u16))
+                .help("Also serve the files over SFTP on this port, with the same accounts"),
+        )
+        .arg(
+            Arg::new("sftp-host-key")
+                .env("DUFS_SFTP_HOST_KEY")
+                .hide_env(true)
+                .long("sftp-host-key")
+                .value_name("path")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath)
+                .help("Path to the OpenSSH private key of the SFTP server, one is made per run if unset"),
         );
 
     app
 }
 
+/// A config file listing every option with its help, commented out
+pub fn gen_config() -> String {
+    let cmd = build_cli();
+    let mut output = format!(
+        "# Config file of {}, see `{} --help` for details\n",
+        env!("CARGO_CRATE_NAME"),
+        env!("CARGO_CRATE_NAME")
+    );
+    for arg in cmd.get_arguments() {
+        let key = arg.get_id().as_str();
+        if matches!(key, "config" | "completions" | "help" | "version") {
+            continue;
+        }
+        let value = arg
+            .get_value_names()
+            .and_then(|v| v.first())
+            .map(|v| format!("<{v}>"))
+            .unwrap_or_else(|| format!("<{key}>"));
+        output.push('\n');
+        if let Some(help) = arg.get_help() {
+            output.push_str(&format!("# {help}\n"));
+        }
+        match arg.get_action() {
+            ArgAction::SetTrue => output.push_str(&format!("# {key}: true\n")),
+            ArgAction::Append => output.push_str(&format!("# {key}:\n#   - {value}\n")),
+            _ => output.push_str(&format!("# {key}: {value}\n")),
+        }
+    }
+    output
+}
+
 pub fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
     generate(gen, cmd, cmd.get_name().to_string(), &mut std::io::stdout());
 }
 
-#[derive(Debug, Deserialize, SmartDefault, PartialEq)]
+pub fn print_manpage(cmd: Command) -> std::io::Result<()> {
+    clap_mangen::Man::new(cmd).render(&mut std::io::stdout())
+}
+
+#[derive(Debug, Clone, Deserialize, SmartDefault, PartialEq)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Args {
@@ -262,66 +1167,248 @@ pub struct Args {
     #[serde(default = "default_port")]
     #[default(default_port())]
     pub port: u16,
+    pub urls_file: Option<PathBuf>,
     #[serde(skip)]
     pub path_is_file: bool,
+    /// The bucket served when the serve path is an `s3://` URL
+    #[serde(skip)]
+    pub s3_target: Option<S3Target>,
     pub path_prefix: String,
     #[serde(skip)]
     pub uri_prefix: String,
+    #[serde(rename = "mount")]
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub mounts: Vec<String>,
+    #[serde(skip)]
+    pub mount_paths: Vec<(String, PathBuf)>,
     #[serde(deserialize_with = "deserialize_string_or_vec")]
     pub hidden: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub exclude: Vec<String>,
+    #[serde(rename = "redirect")]
+    #[serde(deserialize_with = "deserialize_redirects")]
+    pub redirects: RewriteRules,
+    #[serde(rename = "rewrite")]
+    #[serde(deserialize_with = "deserialize_rewrites")]
+    pub rewrites: RewriteRules,
     pub posix_hidden: bool,
     pub dir_size: bool,
+    #[serde(deserialize_with = "deserialize_default_sort")]
+    pub default_sort: DefaultSort,
     #[serde(deserialize_with = "deserialize_access_control")]
     pub auth: AccessControl,
+    pub jwt_secret: Option<String>,
+    pub jwt_jwks: Option<String>,
+    pub jwt_audience: Option<String>,
+    pub auth_proxy_header: Option<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub auth_proxy_trusted: Vec<String>,
+    pub auth_method: AuthMethod,
+    #[serde(deserialize_with = "deserialize_auth_lockout")]
+    pub auth_lockout: Option<LockoutPolicy>,
+    #[serde(deserialize_with = "deserialize_rate_limit")]
+    pub rate_limit: Option<RateLimit>,
+    pub max_concurrency: Option<usize>,
+    pub metrics: bool,
+    pub proxy_protocol: bool,
+    #[serde(deserialize_with = "deserialize_trusted_proxies")]
+    pub trusted_proxies: TrustedProxies,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub read_timeout: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub write_timeout: Option<Duration>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub chroot: bool,
+    pub daemon: bool,
+    pub pid_file: Option<PathBuf>,
+    /// Where the serve path is outside the chroot, once in it
+    #[serde(skip)]
+    pub chrooted_from: Option<PathBuf>,
     pub allow_all: bool,
     pub allow_upload: bool,
     pub allow_delete: bool,
+    pub trash_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    #[default(Duration::from_secs(30 * 86400))]
+    pub trash_retention: Duration,
+    pub confirm_rm_dir: bool,
     pub allow_search: bool,
-    pub allow_symlink: bool,
+    pub search_index: bool,
+    #[default(1000)]
+    pub search_max_results: usize,
+    #[serde(deserialize_with = "deserialize_duration")]
+    #[default(Duration::from_secs(10))]
+    pub search_timeout: Duration,
+    pub hash_xattr: bool,
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub metadata_cache: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub preload: Option<u64>,
+    #[serde(deserialize_with = "deserialize_buffer_size")]
+    #[default(65536)]
+    pub buffer_size: usize,
+    #[serde(deserialize_with = "deserialize_symlink_rules")]
+    pub allow_symlink: SymlinkRules,
     pub allow_archive: bool,
+    pub no_zip64: bool,
+    pub archive_progress: bool,
+    #[serde(deserialize_with = "deserialize_upload_limits")]
+    pub max_upload_size: UploadLimits,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_body_size: Option<u64>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub upload_allow_ext: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub upload_deny_ext: Vec<String>,
+    #[serde(deserialize_with = "deserialize_upload_limits")]
+    pub quota: UploadLimits,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub min_free_space: Option<u64>,
+    pub dav_infinite_depth: bool,
+    pub dlna: bool,
+    pub ftp_port: Option<u16>,
+    pub tftp_port: Option<u16>,
     pub render_index: bool,
     pub render_spa: bool,
+    #[serde(rename = "spa-root")]
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub spa_roots: Vec<String>,
+    #[default("index.html".to_string())]
+    pub spa_fallback: String,
+    #[serde(deserialize_with = "deserialize_try_files")]
+    pub try_files: Option<TryFiles>,
     pub render_try_index: bool,
+    #[serde(rename = "index-name")]
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    #[default(vec!["index.html".to_string()])]
+    pub index_names: Vec<String>,
+    #[default("README.md".to_string())]
+    pub readme_name: String,
     pub enable_cors: bool,
+    pub enable_compression: bool,
+    pub security_headers: bool,
+    #[serde(deserialize_with = "deserialize_security_headers_skip")]
+    pub security_headers_skip: Vec<String>,
+    #[serde(rename = "header")]
+    #[serde(deserialize_with = "deserialize_response_headers")]
+    pub headers: ResponseHeaders,
+    #[serde(deserialize_with = "deserialize_cache_control")]
+    pub cache_control: CacheControlRules,
+    #[serde(deserialize_with = "deserialize_header_value")]
+    pub cache_control_default: Option<HeaderValue>,
     pub assets: Option<PathBuf>,
+    #[default("Dufs".to_string())]
+    pub site_name: String,
+    pub logo_url: Option<String>,
+    pub header_html: Option<String>,
+    pub footer_html: Option<String>,
     #[serde(deserialize_with = "deserialize_log_http")]
     #[serde(rename = "log-format")]
     pub http_logger: HttpLogger,
+    pub hook_upload: Option<String>,
+    pub hook_delete: Option<String>,
+    pub hook_mkdir: Option<String>,
+    pub hook_move: Option<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    #[default(Duration::from_secs(60))]
+    pub hook_timeout: Duration,
+    #[default(4)]
+    pub hook_concurrency: usize,
+    pub open: bool,
+    pub qrcode: bool,
     pub tls_cert: Option<PathBuf>,
     pub tls_key: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub acme_domain: Vec<String>,
+    pub acme_cache_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_tls_version")]
+    pub tls_min_version: Option<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub tls_ciphers: Vec<String>,
+    pub http_redirect_port: Option<u16>,
+    pub otlp_endpoint: Option<String>,
+    pub plugin: Vec<PathBuf>,
+    pub sftp_port: Option<u16>,
+    pub sftp_host_key: Option<PathBuf>,
 }
 
 impl Args {
+    /// Parse the arguments as given to the `dufs` binary, the first being the program name,
+    /// e.g. `["dufs", "/srv", "--allow-upload"]`
+    pub fn parse_from<I, T>(itr: I) -> Result<Args>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        Self::parse(build_cli().try_get_matches_from(itr)?)
+    }
+
     /// Parse command-line arguments.
     ///
     /// If a parsing error occurred, exit the process and print out informative
     /// error message to user.
+    /// Options are taken from environment variables, then the config file, then the command line,
+    /// each overriding the former.
     pub fn parse(matches: ArgMatches) -> Result<Args> {
         let mut args = Self::default();
+        let mut file_keys = HashSet::new();
 
         if let Some(config_path) = matches.get_one::<PathBuf>("config") {
             let contents = std::fs::read_to_string(config_path)
                 .with_context(|| format!("Failed to read config at {}", config_path.display()))?;
             args = serde_yaml::from_str(&contents)
                 .with_context(|| format!("Failed to load config at {}", config_path.display()))?;
+            if let Ok(serde_yaml::Value::Mapping(map)) = serde_yaml::from_str(&contents) {
+                file_keys = map
+                    .keys()
+                    .filter_map(|v| v.as_str().map(|v| v.to_string()))
+                    .collect();
+            }
         }
+        let matches = Matches {
+            inner: matches,
+            file_keys,
+        };
 
         if let Some(path) = matches.get_one::<PathBuf>("serve-path") {
             args.serve_path = path.clone()
         }
 
-        args.serve_path = Self::sanitize_path(args.serve_path)?;
+        args.s3_target = S3Target::parse(&args.serve_path.to_string_lossy())?;
+        if args.s3_target.is_none() {
+            args.serve_path = Self::sanitize_path(args.serve_path)?;
+        }
 
         if let Some(port) = matches.get_one::<u16>("port") {
             args.port = *port
         }
+        if let Some(path) = matches.get_one::<PathBuf>("urls-file") {
+            args.urls_file = Some(path.clone());
+        }
+        if let Some(path) = args.urls_file.take() {
+            args.urls_file = match path.as_os_str() == "-" {
+                true => Some(path),
+                false => Some(Self::sanitize_file_path(&path)?),
+            };
+        }
 
         if let Some(addrs) = matches.get_many::<String>("bind") {
             let addrs: Vec<_> = addrs.map(|v| v.as_str()).collect();
             args.addrs = BindAddr::parse_addrs(&addrs)?;
         }
 
-        args.path_is_file = args.serve_path.metadata()?.is_file();
+        if args.s3_target.is_none() {
+            args.path_is_file = args.serve_path.metadata()?.is_file();
+        }
+
+        if let Some(mounts) = matches.get_many::<String>("mount") {
+            args.mounts = mounts.cloned().collect();
+        }
+        args.mount_paths = Self::parse_mounts(&args.mounts)?;
+        if args.path_is_file && !args.mount_paths.is_empty() {
+            bail!("Cannot mount directories when serving a single file");
+        }
         if let Some(path_prefix) = matches.get_one::<String>("path-prefix") {
             args.path_prefix = path_prefix.clone();
         }
@@ -344,18 +1431,156 @@ impl Args {
                 .collect();
         }
 
+        if let Some(exclude) = matches.get_many::<String>("exclude") {
+            args.exclude = exclude.cloned().collect();
+        } else {
+            args.exclude = std::mem::take(&mut args.exclude)
+                .into_iter()
+                .flat_map(|v| v.split(',').map(|v| v.to_string()).collect::<Vec<String>>())
+                .collect();
+        }
+
+        if let Some(rules) = matches.get_many::<String>("redirect") {
+            let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
+            args.redirects = RewriteRules::redirects(&rules)?;
+        }
+        if let Some(rules) = matches.get_many::<String>("rewrite") {
+            let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
+            args.rewrites = RewriteRules::rewrites(&rules)?;
+        }
+
         args.posix_hidden = matches.get_flag("posix-hidden");
         args.dir_size = matches.get_flag("dir-size");
+        if let Some(sort) = matches.get_one::<String>("default-sort") {
+            args.default_sort = DefaultSort::parse(sort)?;
+        }
 
         if !args.enable_cors {
             args.enable_cors = matches.get_flag("enable-cors");
         }
 
+        if !args.enable_compression {
+            args.enable_compression = matches.get_flag("enable-compression");
+        }
+
+        if !args.security_headers {
+            args.security_headers = matches.get_flag("security-headers");
+        }
+        if let Some(skip) = matches.get_many::<String>("security-headers-skip") {
+            args.security_headers_skip = skip.cloned().collect();
+        }
+
+        if let Some(headers) = matches.get_many::<String>("header") {
+            let headers: Vec<_> = headers.map(|v| v.as_str()).collect();
+            args.headers = ResponseHeaders::new(&headers)?;
+        }
+
+        if let Some(rules) = matches.get_many::<String>("cache-control") {
+            let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
+            args.cache_control = CacheControlRules::new(&rules)?;
+        }
+        if let Some(value) = matches.get_one::<String>("cache-control-default") {
+            args.cache_control_default = Some(
+                HeaderValue::try_from(value.as_str())
+                    .with_context(|| format!("Invalid cache control `{value}`"))?,
+            );
+        }
+
         if let Some(rules) = matches.get_many::<String>("auth") {
             let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
             args.auth = AccessControl::new(&rules)?;
         }
 
+        if let Some(secret) = matches.get_one::<String>("jwt-secret") {
+            args.jwt_secret = Some(secret.clone());
+        }
+        if let Some(jwks) = matches.get_one::<String>("jwt-jwks") {
+            args.jwt_jwks = Some(jwks.clone());
+        }
+        if let Some(audience) = matches.get_one::<String>("jwt-audience") {
+            args.jwt_audience = Some(audience.clone());
+        }
+        if args.jwt_secret.is_some() || args.jwt_jwks.is_some() {
+            let jwt = JwtAuth::new(
+                args.jwt_secret.as_deref(),
+                args.jwt_jwks.as_deref(),
+                args.jwt_audience.as_deref(),
+            )?;
+            args.auth.set_jwt(jwt);
+        }
+
+        if let Some(method) = matches.get_one::<String>("auth-method") {
+            args.auth_method = match method.as_str() {
+                "basic" => AuthMethod::Basic,
+                "form" => AuthMethod::Form,
+                _ => AuthMethod::Digest,
+            };
+        }
+
+        if let Some(policy) = matches.get_one::<String>("auth-lockout") {
+            args.auth_lockout = LockoutPolicy::parse(policy)?;
+        }
+        if let Some(rate) = matches.get_one::<String>("rate-limit") {
+            args.rate_limit = Some(RateLimit::parse(rate)?);
+        }
+        if let Some(max) = matches.get_one::<usize>("max-concurrency") {
+            args.max_concurrency = Some(*max);
+        }
+        if !args.metrics {
+            args.metrics = matches.get_flag("metrics");
+        }
+
+        if let Some(header) = matches.get_one::<String>("auth-proxy-header") {
+            args.auth_proxy_header = Some(header.clone());
+        }
+        if let Some(trusted) = matches.get_many::<String>("auth-proxy-trusted") {
+            args.auth_proxy_trusted = trusted.cloned().collect();
+        }
+        if let Some(header) = args.auth_proxy_header.as_deref() {
+            let mut trusted: Vec<&str> = args
+                .auth_proxy_trusted
+                .iter()
+                .flat_map(|v| v.split(','))
+                .collect();
+            if trusted.is_empty() {
+                trusted = vec!["127.0.0.1", "::1"];
+            }
+            args.auth.set_proxy(AuthProxy::new(header, &trusted)?);
+        }
+
+        if !args.proxy_protocol {
+            args.proxy_protocol = matches.get_flag("proxy-protocol");
+        }
+
+        if let Some(proxies) = matches.get_many::<String>("trusted-proxies") {
+            let proxies: Vec<_> = proxies.map(|v| v.as_str()).collect();
+            args.trusted_proxies = TrustedProxies::new(&proxies)?;
+        }
+        if let Some(timeout) = matches.get_one::<String>("read-timeout") {
+            args.read_timeout = Some(parse_duration(timeout)?);
+        }
+        if let Some(timeout) = matches.get_one::<String>("write-timeout") {
+            args.write_timeout = Some(parse_duration(timeout)?);
+        }
+        if let Some(user) = matches.get_one::<String>("user") {
+            args.user = Some(user.clone());
+        }
+        if let Some(group) = matches.get_one::<String>("group") {
+            args.group = Some(group.clone());
+        }
+        if !args.chroot {
+            args.chroot = matches.get_flag("chroot");
+        }
+        if !args.daemon {
+            args.daemon = matches.get_flag("daemon");
+        }
+        if let Some(path) = matches.get_one::<PathBuf>("pid-file") {
+            args.pid_file = Some(path.clone());
+        }
+        if let Some(path) = &args.pid_file {
+            args.pid_file = Some(Self::sanitize_file_path(path)?);
+        }
+
         if !args.allow_all {
             args.allow_all = matches.get_flag("allow-all");
         }
@@ -368,15 +1593,92 @@ impl Args {
         if !args.allow_delete {
             args.allow_delete = allow_all || matches.get_flag("allow-delete");
         }
+        if let Some(trash_dir) = matches.get_one::<PathBuf>("trash-dir") {
+            args.trash_dir = Some(trash_dir.clone());
+        }
+        if let Some(trash_dir) = &args.trash_dir {
+            args.trash_dir = Some(Self::sanitize_trash_dir(trash_dir, &args)?);
+        }
+        if let Some(retention) = matches.get_one::<String>("trash-retention") {
+            args.trash_retention = parse_duration(retention)?;
+        }
+        if !args.confirm_rm_dir {
+            args.confirm_rm_dir = matches.get_flag("confirm-rm-dir");
+        }
         if !args.allow_search {
             args.allow_search = allow_all || matches.get_flag("allow-search");
         }
-        if !args.allow_symlink {
-            args.allow_symlink = allow_all || matches.get_flag("allow-symlink");
+        if !args.search_index {
+            args.search_index = matches.get_flag("search-index");
+        }
+        if let Some(max_results) = matches.get_one::<usize>("search-max-results") {
+            args.search_max_results = *max_results;
+        }
+        if let Some(timeout) = matches.get_one::<String>("search-timeout") {
+            args.search_timeout = parse_duration(timeout)?;
+        }
+        if !args.hash_xattr {
+            args.hash_xattr = matches.get_flag("hash-xattr");
+        }
+        if let Some(ttl) = matches.get_one::<String>("metadata-cache") {
+            args.metadata_cache = Some(parse_duration(ttl)?);
+        }
+        if let Some(size) = matches.get_one::<String>("preload") {
+            args.preload = Some(parse_size(size)?);
+        }
+        if let Some(size) = matches.get_one::<String>("buffer-size") {
+            args.buffer_size = parse_buffer_size(size)?;
+        }
+        if allow_all {
+            args.allow_symlink = SymlinkRules::all();
+        } else if let Some(rules) = matches.get_many::<String>("allow-symlink") {
+            let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
+            args.allow_symlink = SymlinkRules::new(&rules)?;
         }
         if !args.allow_archive {
             args.allow_archive = allow_all || matches.get_flag("allow-archive");
         }
+        if !args.no_zip64 {
+            args.no_zip64 = matches.get_flag("no-zip64");
+        }
+        if !args.archive_progress {
+            args.archive_progress = matches.get_flag("archive-progress");
+        }
+        if let Some(limits) = matches.get_many::<String>("max-upload-size") {
+            let limits: Vec<_> = limits.map(|v| v.as_str()).collect();
+            args.max_upload_size = UploadLimits::new(&limits)?;
+        }
+        if let Some(size) = matches.get_one::<String>("max-body-size") {
+            args.max_body_size = Some(parse_size(size)?);
+        }
+        if let Some(exts) = matches.get_many::<String>("upload-allow-ext") {
+            args.upload_allow_ext = exts.cloned().collect();
+        }
+        if let Some(exts) = matches.get_many::<String>("upload-deny-ext") {
+            args.upload_deny_ext = exts.cloned().collect();
+        }
+        if let Some(quota) = matches.get_many::<String>("quota") {
+            let quota: Vec<_> = quota.map(|v| v.as_str()).collect();
+            args.quota = UploadLimits::new(&quota)?;
+        }
+        if let Some(size) = matches.get_one::<String>("min-free-space") {
+            args.min_free_space = Some(parse_size(size)?);
+        }
+        if cfg!(not(unix)) && args.min_free_space.is_some() {
+            bail!("`--min-free-space` is only supported on Unix");
+        }
+        if !args.dav_infinite_depth {
+            args.dav_infinite_depth = matches.get_flag("dav-infinite-depth");
+        }
+        if !args.dlna {
+            args.dlna = matches.get_flag("dlna");
+        }
+        if let Some(port) = matches.get_one::<u16>("ftp-port") {
+            args.ftp_port = Some(*port);
+        }
+        if let Some(port) = matches.get_one::<u16>("tftp-port") {
+            args.tftp_port = Some(*port);
+        }
         if !args.render_index {
             args.render_index = matches.get_flag("render-index");
         }
@@ -388,11 +1690,87 @@ impl Args {
         if !args.render_spa {
             args.render_spa = matches.get_flag("render-spa");
         }
+        if let Some(roots) = matches.get_many::<String>("spa-root") {
+            args.spa_roots = roots.cloned().collect();
+        }
+        args.spa_roots = std::mem::take(&mut args.spa_roots)
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().trim_matches('/').to_string())
+            .collect();
+        if !args.spa_roots.is_empty() {
+            args.render_spa = true;
+        }
+        if let Some(name) = matches.get_one::<String>("spa-fallback") {
+            args.spa_fallback = name.clone();
+        }
+        if let Some(value) = matches.get_one::<String>("try-files") {
+            args.try_files = Some(TryFiles::parse(value)?);
+        }
+        if args.spa_fallback.is_empty()
+            || args.spa_fallback.contains(['/', '\\'])
+            || args.spa_fallback == ".."
+        {
+            bail!(
+                "Invalid SPA fallback `{}`, it must be a file name like index.html",
+                args.spa_fallback
+            );
+        }
+
+        if let Some(names) = matches.get_many::<String>("index-name") {
+            args.index_names = names.cloned().collect();
+        }
+        args.index_names = std::mem::take(&mut args.index_names)
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        if args.index_names.is_empty() {
+            args.index_names = vec!["index.html".to_string()];
+        }
+        if let Some(name) = args
+            .index_names
+            .iter()
+            .find(|v| v.contains(['/', '\\']) || *v == "..")
+        {
+            bail!("Invalid index name `{name}`, it must be a file name like index.html");
+        }
+
+        if let Some(readme_name) = matches.get_one::<String>("readme-name") {
+            args.readme_name = readme_name.clone();
+        }
+        if args.readme_name.contains('/') {
+            bail!("`--readme-name` must be a file name, not a path");
+        }
 
         if let Some(log_format) = matches.get_one::<String>("log-format") {
             args.http_logger = log_format.parse()?;
         }
 
+        for (name, hook) in [
+            ("hook-upload", &mut args.hook_upload),
+            ("hook-delete", &mut args.hook_delete),
+            ("hook-mkdir", &mut args.hook_mkdir),
+            ("hook-move", &mut args.hook_move),
+        ] {
+            if let Some(command) = matches.get_one::<String>(name) {
+                *hook = Some(command.clone());
+            }
+        }
+        if let Some(timeout) = matches.get_one::<String>("hook-timeout") {
+            args.hook_timeout = parse_duration(timeout)?;
+        }
+        if let Some(concurrency) = matches.get_one::<usize>("hook-concurrency") {
+            args.hook_concurrency = *concurrency;
+        }
+        if !args.open {
+            args.open = matches.get_flag("open");
+        }
+        if !args.qrcode {
+            args.qrcode = matches.get_flag("qrcode");
+        }
+
         if let Some(assets_path) = matches.get_one::<PathBuf>("assets") {
             args.assets = Some(assets_path.clone());
         }
@@ -401,6 +1779,19 @@ impl Args {
             args.assets = Some(Args::sanitize_assets_path(assets_path)?);
         }
 
+        if let Some(site_name) = matches.get_one::<String>("site-name") {
+            args.site_name = site_name.clone();
+        }
+        for (name, value) in [
+            ("logo-url", &mut args.logo_url),
+            ("header-html", &mut args.header_html),
+            ("footer-html", &mut args.footer_html),
+        ] {
+            if let Some(v) = matches.get_one::<String>(name) {
+                *value = Some(v.clone());
+            }
+        }
+
         #[cfg(feature = "tls")]
         {
             if let Some(tls_cert) = matches.get_one::<PathBuf>("tls-cert") {
@@ -417,43 +1808,344 @@ impl Args {
                 (_, Some(_)) => bail!("No tls-cert set"),
                 (None, None) => {}
             }
+
+            if let Some(domains) = matches.get_many::<String>("acme-domain") {
+                args.acme_domain = domains.cloned().collect();
+            } else {
+                args.acme_domain = args
+                    .acme_domain
+                    .iter()
+                    .flat_map(|v| v.split(','))
+                    .map(|v| v.trim().to_string())
+                    .collect();
+            }
+            args.acme_domain.retain(|v| !v.is_empty());
+            if let Some(dir) = matches.get_one::<PathBuf>("acme-cache-dir") {
+                args.acme_cache_dir = Some(dir.clone());
+            }
+            if !args.acme_domain.is_empty() && args.tls_cert.is_some() {
+                bail!("Cannot use both acme-domain and tls-cert");
+            }
+            if let Some(dir) = &args.acme_cache_dir {
+                std::fs::create_dir_all(dir).with_context(|| {
+                    format!("Failed to create acme cache dir at {}", dir.display())
+                })?;
+                let dir = Self::sanitize_path(dir)?;
+                if dir.starts_with(&args.serve_path) {
+                    bail!("The acme cache dir must not be inside the serve path");
+                }
+                args.acme_cache_dir = Some(dir);
+            }
+            if let Some(version) = matches.get_one::<String>("tls-min-version") {
+                args.tls_min_version = Some(version.clone());
+            }
+            if let Some(ciphers) = matches.get_many::<String>("tls-ciphers") {
+                args.tls_ciphers = ciphers.cloned().collect();
+            } else {
+                args.tls_ciphers = args
+                    .tls_ciphers
+                    .iter()
+                    .flat_map(|v| v.split(','))
+                    .map(|v| v.trim().to_string())
+                    .collect();
+            }
+            args.tls_ciphers.retain(|v| !v.is_empty());
+            crate::tls::config_builder(args.tls_min_version.as_deref(), &args.tls_ciphers)?;
+            if let Some(port) = matches.get_one::<u16>("http-redirect-port") {
+                args.http_redirect_port = Some(*port);
+            }
+            if args.http_redirect_port.is_some() && !args.tls() {
+                bail!("Cannot use http-redirect-port without tls-cert or acme-domain");
+            }
         }
         #[cfg(not(feature = "tls"))]
         {
             args.tls_cert = None;
             args.tls_key = None;
+            args.acme_domain = vec![];
+            args.acme_cache_dir = None;
+            args.tls_min_version = None;
+            args.tls_ciphers = vec![];
+            args.http_redirect_port = None;
         }
 
-        Ok(args)
-    }
-
-    fn sanitize_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
-        let path = path.as_ref();
-        if !path.exists() {
-            bail!("Path `{}` doesn't exist", path.display());
+        #[cfg(feature = "otlp")]
+        if let Some(endpoint) = matches.get_one::<String>("otlp-endpoint") {
+            args.otlp_endpoint = Some(endpoint.clone());
+        }
+        #[cfg(not(feature = "otlp"))]
+        {
+            args.otlp_endpoint = None;
         }
 
-        env::current_dir()
-            .and_then(|mut p| {
-                p.push(path); // If path is absolute, it replaces the current path.
-                std::fs::canonicalize(p)
-            })
-            .with_context(|| format!("Failed to access path `{}`", path.display()))
-    }
-
-    fn sanitize_assets_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
-        let path = Self::sanitize_path(path)?;
-        if !path.join("index.html").exists() {
-            bail!("Path `{}` doesn't contains index.html", path.display());
+        #[cfg(feature = "plugins")]
+        if let Some(plugins) = matches.get_many::<PathBuf>("plugin") {
+            args.plugin = plugins.cloned().collect();
+        }
+        #[cfg(not(feature = "plugins"))]
+        {
+            args.plugin = vec![];
         }
-        Ok(path)
-    }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub enum BindAddr {
-    Address(IpAddr),
-    Path(PathBuf),
+        #[cfg(feature = "sftp")]
+        {
+            if let Some(port) = matches.get_one::<u16>("sftp-port") {
+                args.sftp_port = Some(*port);
+            }
+            if let Some(path) = matches.get_one::<PathBuf>("sftp-host-key") {
+                args.sftp_host_key = Some(path.clone());
+            }
+            if let Some(path) = &args.sftp_host_key {
+                let path = Self::sanitize_path(path)?;
+                if path.starts_with(&args.serve_path) {
+                    bail!("The sftp host key must not be inside the serve path");
+                }
+                args.sftp_host_key = Some(path);
+            }
+        }
+        #[cfg(not(feature = "sftp"))]
+        {
+            args.sftp_port = None;
+            args.sftp_host_key = None;
+        }
+
+        if args.s3_target.is_some() {
+            let local_only = [
+                ("mount", !args.mount_paths.is_empty()),
+                ("chroot", args.chroot),
+                ("trash-dir", args.trash_dir.is_some()),
+                ("search-index", args.search_index),
+                ("preload", args.preload.is_some()),
+                ("quota", args.quota.rules().next().is_some()),
+                ("min-free-space", args.min_free_space.is_some()),
+                ("confirm-rm-dir", args.confirm_rm_dir),
+                ("try-files", args.try_files.is_some()),
+                ("dlna", args.dlna),
+                ("ftp-port", args.ftp_port.is_some()),
+                ("tftp-port", args.tftp_port.is_some()),
+                ("sftp-port", args.sftp_port.is_some()),
+            ];
+            if let Some((name, _)) = local_only.iter().find(|(_, used)| *used) {
+                bail!("Cannot use `--{name}` when serving object storage");
+            }
+            // Both go through the whole tree, which takes listing every key of the bucket.
+            // `--allow-all` allows all that object storage supports.
+            let unsupported = [
+                ("allow-search", args.allow_search),
+                ("allow-archive", args.allow_archive),
+            ];
+            match unsupported.iter().find(|(_, used)| *used) {
+                Some((name, _)) if !allow_all => {
+                    bail!("`--{name}` is not supported for s3:// targets")
+                }
+                _ => {}
+            }
+            args.allow_search = false;
+            args.allow_archive = false;
+        }
+
+        if cfg!(not(unix)) && (args.user.is_some() || args.group.is_some() || args.chroot) {
+            bail!("`--user`, `--group` and `--chroot` are only supported on Unix");
+        }
+        if cfg!(not(unix)) && (args.daemon || args.pid_file.is_some()) {
+            bail!("`--daemon` and `--pid-file` are only supported on Unix, see `dufs service` on Windows");
+        }
+        if args.chroot {
+            if args.path_is_file {
+                bail!("Cannot use `--chroot` when serving a single file");
+            }
+            if !args.acme_domain.is_empty() {
+                bail!("Cannot use both `--chroot` and acme-domain");
+            }
+            if args.trash_dir.is_some() {
+                bail!(
+                    "Cannot use both `--chroot` and `--trash-dir`, which is outside the serve path"
+                );
+            }
+            let outside = args
+                .mount_paths
+                .iter()
+                .map(|(_, v)| v)
+                .chain(&args.assets)
+                .chain(&args.plugin)
+                .find(|v| !v.starts_with(&args.serve_path));
+            if let Some(path) = outside {
+                bail!(
+                    "With `--chroot`, `{}` must be inside the serve path",
+                    path.display()
+                );
+            }
+        }
+
+        Ok(args)
+    }
+
+    /// The args as seen from inside a chroot to the serve path, where it is `/`
+    pub fn chrooted(&self) -> Self {
+        let inside = |path: &PathBuf| match path.strip_prefix(&self.serve_path) {
+            Ok(v) => Path::new("/").join(v),
+            Err(_) => path.clone(),
+        };
+        let mut args = self.clone();
+        args.serve_path = PathBuf::from("/");
+        args.mount_paths = self
+            .mount_paths
+            .iter()
+            .map(|(name, path)| (name.clone(), inside(path)))
+            .collect();
+        args.assets = self.assets.as_ref().map(inside);
+        args.plugin = self.plugin.iter().map(inside).collect();
+        args.chrooted_from = Some(self.serve_path.clone());
+        args
+    }
+
+    /// Whether the server speaks HTTPS
+    pub fn tls(&self) -> bool {
+        self.tls_cert.is_some() || !self.acme_domain.is_empty()
+    }
+
+    /// Where the assets and the endpoints of dufs itself are served, which changes with
+    /// the version so browsers don't keep stale assets
+    pub fn assets_prefix(&self) -> String {
+        format!("{}__dufs_v{}_", self.uri_prefix, env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Whether the security header is to be set, one of [`SECURITY_HEADERS`]
+    pub fn security_header(&self, name: &str) -> bool {
+        self.security_headers && !self.security_headers_skip.iter().any(|v| v == name)
+    }
+
+    /// Makes the path of a file dufs creates absolute, its folder has to exist
+    fn sanitize_file_path(path: &Path) -> Result<PathBuf> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid file path `{}`", path.display()))?;
+        let dir = Self::sanitize_path(
+            path.parent()
+                .filter(|v| !v.as_os_str().is_empty())
+                .unwrap_or(Path::new(".")),
+        )?;
+        Ok(dir.join(name))
+    }
+
+    fn sanitize_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        if !path.exists() {
+            bail!("Path `{}` doesn't exist", path.display());
+        }
+
+        env::current_dir()
+            .and_then(|mut p| {
+                p.push(path); // If path is absolute, it replaces the current path.
+                std::fs::canonicalize(p)
+            })
+            .with_context(|| format!("Failed to access path `{}`", path.display()))
+    }
+
+    fn parse_mounts(mounts: &[String]) -> Result<Vec<(String, PathBuf)>> {
+        let mut output: Vec<(String, PathBuf)> = vec![];
+        for mount in mounts {
+            let (name, path) = match mount.split_once('=') {
+                Some((name, path)) => (name.trim_matches('/'), path),
+                None => bail!("Invalid mount `{mount}`, expect name=path"),
+            };
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                bail!("Invalid mount `{mount}`, the name must be a single path segment");
+            }
+            if output.iter().any(|(v, _)| v == name) {
+                bail!("Duplicate mount `{name}`");
+            }
+            let path = Self::sanitize_path(path)?;
+            if !path.is_dir() {
+                bail!("Mount path `{}` is not a directory", path.display());
+            }
+            output.push((name.to_string(), path));
+        }
+        Ok(output)
+    }
+
+    fn sanitize_trash_dir(path: &Path, args: &Args) -> Result<PathBuf> {
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create trash dir `{}`", path.display()))?;
+        let path = Self::sanitize_path(path)?;
+        let roots =
+            std::iter::once(&args.serve_path).chain(args.mount_paths.iter().map(|(_, v)| v));
+        for root in roots {
+            if path.starts_with(root) || root.starts_with(&path) {
+                bail!(
+                    "Trash dir `{}` must be outside of the served paths",
+                    path.display()
+                );
+            }
+        }
+        Ok(path)
+    }
+
+    fn sanitize_assets_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+        let path = Self::sanitize_path(path)?;
+        if !path.join("index.html").exists() {
+            bail!("Path `{}` doesn't contains index.html", path.display());
+        }
+        Ok(path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    Basic,
+    #[default]
+    Digest,
+    /// Login page and session cookie for browsers, basic/digest for the others
+    Form,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    Name,
+    Mtime,
+    Size,
+    /// By extension, then by name
+    Ext,
+}
+
+impl SortBy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(Self::Name),
+            "mtime" => Some(Self::Mtime),
+            "size" => Some(Self::Size),
+            "ext" => Some(Self::Ext),
+            _ => None,
+        }
+    }
+}
+
+/// How listings are sorted when they don't ask, such as `mtime:desc`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultSort {
+    pub by: SortBy,
+    pub desc: bool,
+}
+
+impl DefaultSort {
+    pub fn parse(value: &str) -> Result<Self> {
+        let (by, order) = value.split_once(':').unwrap_or((value, "asc"));
+        let by = SortBy::parse(by).ok_or_else(|| anyhow!("Invalid sort `{value}`"))?;
+        let desc = match order {
+            "asc" => false,
+            "desc" => true,
+            _ => bail!("Invalid sort `{value}`"),
+        };
+        Ok(Self { by, desc })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BindAddr {
+    Address(IpAddr),
+    Path(PathBuf),
 }
 
 impl BindAddr {
@@ -481,6 +2173,350 @@ impl BindAddr {
     }
 }
 
+/// Size limits, a global one and optional per-path ones, for uploads or quotas
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UploadLimits {
+    global: Option<u64>,
+    paths: Vec<(String, u64)>,
+}
+
+impl UploadLimits {
+    pub fn new(rules: &[&str]) -> Result<Self> {
+        let mut limits = Self::default();
+        for rule in rules {
+            match rule.rsplit_once(':') {
+                Some((path, size)) => {
+                    let path = path.trim_matches('/').to_string();
+                    limits.paths.push((path, parse_size(size)?));
+                }
+                None => limits.global = Some(parse_size(rule)?),
+            }
+        }
+        // The most specific path wins
+        limits
+            .paths
+            .sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+        Ok(limits)
+    }
+
+    pub fn get(&self, path: &str) -> Option<u64> {
+        let path = path.trim_matches('/');
+        self.paths
+            .iter()
+            .find(|(prefix, _)| {
+                prefix.is_empty()
+                    || path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .map(|v| v.starts_with('/'))
+                        .unwrap_or_default()
+            })
+            .map(|(_, size)| *size)
+            .or(self.global)
+    }
+
+    /// Every limit with its path, the global one at the empty path
+    pub fn rules(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.global
+            .map(|v| ("", v))
+            .into_iter()
+            .chain(self.paths.iter().map(|(path, size)| (path.as_str(), *size)))
+    }
+}
+
+/// Headers added to responses, to all of them or to those whose path matches a glob
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ResponseHeaders {
+    headers: Vec<(Option<String>, HeaderName, HeaderValue)>,
+}
+
+impl ResponseHeaders {
+    pub fn new(rules: &[&str]) -> Result<Self> {
+        let mut headers = vec![];
+        for rule in rules {
+            // A rule scoped to paths starts with the glob, e.g. `/*.pdf:Content-Disposition: attachment`
+            let (glob, header) = match rule.starts_with('/') {
+                true => match rule.split_once(':') {
+                    Some((glob, header)) => (Some(glob.to_string()), header),
+                    None => bail!("Invalid header `{rule}`"),
+                },
+                false => (None, *rule),
+            };
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid header `{rule}`"))?;
+            let name = HeaderName::try_from(name.trim())
+                .with_context(|| format!("Invalid header name in `{rule}`"))?;
+            let value = HeaderValue::try_from(value.trim())
+                .with_context(|| format!("Invalid header value in `{rule}`"))?;
+            headers.push((glob, name, value));
+        }
+        Ok(Self { headers })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Sets the headers that apply to the path, the later rules overriding the former
+    pub fn apply(&self, path: &str, res_headers: &mut HeaderMap<HeaderValue>) {
+        for (pattern, name, value) in &self.headers {
+            if pattern.as_ref().map(|v| glob(v, path)).unwrap_or(true) {
+                res_headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Cache-Control values of files, by globs of their names
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheControlRules {
+    rules: Vec<(String, HeaderValue)>,
+}
+
+impl CacheControlRules {
+    pub fn new(rules: &[&str]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let (pattern, value) = rule
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Invalid cache control `{rule}`"))?;
+                let value = HeaderValue::try_from(value.trim())
+                    .with_context(|| format!("Invalid cache control `{rule}`"))?;
+                Ok((pattern.trim().to_string(), value))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The value of the first rule matching the file name
+    pub fn get(&self, name: &str) -> Option<&HeaderValue> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| glob(pattern, name))
+            .map(|(_, value)| value)
+    }
+}
+
+/// The paths a GET is served from by `--try-files`, e.g. `$uri $uri/index.html /index.html`.
+///
+/// The candidates are tried in order, with `$uri` standing for the requested path, and the
+/// first that exists is served, a file or a folder if it ends with `/`. If none does, the
+/// last item is served, or its status answered if it's one like `=404`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryFiles {
+    candidates: Vec<String>,
+    fallback: TryFilesFallback,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TryFilesFallback {
+    Path(String),
+    Status(StatusCode),
+}
+
+impl TryFiles {
+    pub fn parse(value: &str) -> Result<Self> {
+        let err =
+            || anyhow!("Invalid try files `{value}`, e.g. '$uri $uri/index.html /index.html'");
+        let mut items: Vec<&str> = value.split_whitespace().collect();
+        let fallback = items.pop().ok_or_else(err)?;
+        let is_path = |v: &str| v.starts_with('/') || v.starts_with("$uri");
+        if items.is_empty() || !items.iter().all(|v| is_path(v)) {
+            return Err(err());
+        }
+        let fallback = match fallback.strip_prefix('=') {
+            Some(code) => {
+                let code = code.parse::<u16>().map_err(|_| err())?;
+                match StatusCode::from_u16(code) {
+                    Ok(code) if code.as_u16() >= 400 => TryFilesFallback::Status(code),
+                    _ => return Err(err()),
+                }
+            }
+            None if is_path(fallback) => TryFilesFallback::Path(fallback.to_string()),
+            None => return Err(err()),
+        };
+        Ok(Self {
+            candidates: items.into_iter().map(|v| v.to_string()).collect(),
+            fallback,
+        })
+    }
+
+    /// The candidates for the requested path, the relative one of the request
+    pub fn candidates<'a>(&'a self, relative_path: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.candidates
+            .iter()
+            .map(move |v| expand_uri(v, relative_path))
+    }
+
+    pub fn fallback(&self, relative_path: &str) -> TryFilesFallback {
+        match &self.fallback {
+            TryFilesFallback::Path(v) => TryFilesFallback::Path(expand_uri(v, relative_path)),
+            TryFilesFallback::Status(v) => TryFilesFallback::Status(*v),
+        }
+    }
+}
+
+/// The candidate with `$uri` replaced, as a relative path, keeping the trailing slash of a
+/// folder
+fn expand_uri(candidate: &str, relative_path: &str) -> String {
+    let path = candidate.replace("$uri", &format!("/{relative_path}"));
+    let mut output = String::new();
+    for part in path.split('/').filter(|v| !v.is_empty()) {
+        if !output.is_empty() {
+            output.push('/');
+        }
+        output.push_str(part);
+    }
+    if path.ends_with('/') && !output.is_empty() {
+        output.push('/');
+    }
+    output
+}
+
+/// Where symlinks may lead out of the served folders, by `--allow-symlink`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SymlinkRules {
+    all: bool,
+    patterns: Vec<String>,
+}
+
+impl SymlinkRules {
+    pub fn new(rules: &[&str]) -> Result<Self> {
+        let mut output = Self::default();
+        for rule in rules {
+            // `true` and `false` are what the flag took from `DUFS_ALLOW_SYMLINK` before
+            let pattern = rule.trim().trim_start_matches('/');
+            match pattern {
+                "" | "**" | "true" => output.all = true,
+                "false" => {}
+                _ => {
+                    ::glob::Pattern::new(pattern)
+                        .with_context(|| format!("Invalid symlink path `{rule}`"))?;
+                    output.patterns.push(pattern.to_string());
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    pub fn all() -> Self {
+        Self {
+            all: true,
+            patterns: vec![],
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.all
+    }
+
+    /// Whether a symlink at the relative path, or at one of its parents, may lead out
+    pub fn allows(&self, path: &str) -> bool {
+        if self.all {
+            return true;
+        }
+        let mut prefix_len = 0;
+        path.split('/').any(|name| {
+            prefix_len += name.len();
+            let prefix = &path[..prefix_len];
+            prefix_len += 1;
+            self.patterns.iter().any(|v| glob(v, prefix))
+        })
+    }
+}
+
+/// Reverse proxies whose forwarded headers tell the client IP
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TrustedProxies {
+    proxies: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: &[&str]) -> Result<Self> {
+        let proxies = proxies
+            .iter()
+            .map(|v| parse_ip_net(v.trim()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { proxies })
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.proxies.iter().any(|v| v.contains(ip))
+    }
+
+    /// The client IP of a request from `peer`.
+    ///
+    /// `X-Forwarded-For` is walked from the right, as the proxies append to it, and the
+    /// first untrusted hop is the client. Entries left of it may be forged.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap<HeaderValue>) -> IpAddr {
+        if !self.contains(&peer) {
+            return peer;
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim())
+            .collect();
+        if forwarded.is_empty() {
+            return headers
+                .get("x-real-ip")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(peer);
+        }
+        let mut client = peer;
+        for item in forwarded.iter().rev() {
+            match item.parse::<IpAddr>() {
+                Ok(ip) => {
+                    client = ip;
+                    if !self.contains(&ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+/// Matched args, without values of environment variables which the config file overrides
+struct Matches {
+    inner: ArgMatches,
+    file_keys: HashSet<String>,
+}
+
+impl Matches {
+    fn overridden(&self, id: &str) -> bool {
+        self.file_keys.contains(id) && self.inner.value_source(id) == Some(ValueSource::EnvVariable)
+    }
+
+    fn get_one<T: Any + Clone + Send + Sync + 'static>(&self, id: &str) -> Option<&T> {
+        if self.overridden(id) {
+            return None;
+        }
+        self.inner.get_one(id)
+    }
+
+    fn get_many<T: Any + Clone + Send + Sync + 'static>(
+        &self,
+        id: &str,
+    ) -> Option<ValuesRef<'_, T>> {
+        if self.overridden(id) {
+            return None;
+        }
+        self.inner.get_many(id)
+    }
+
+    fn get_flag(&self, id: &str) -> bool {
+        !self.overridden(id) && self.inner.get_flag(id)
+    }
+}
+
 fn deserialize_bind_addrs<'de, D>(deserializer: D) -> Result<Vec<BindAddr>, D::Error>
 where
     D: Deserializer<'de>,
@@ -553,6 +2589,210 @@ where
     AccessControl::new(&rules).map_err(serde::de::Error::custom)
 }
 
+fn deserialize_auth_lockout<'de, D>(deserializer: D) -> Result<Option<LockoutPolicy>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    LockoutPolicy::parse(&value).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_rate_limit<'de, D>(deserializer: D) -> Result<Option<RateLimit>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    RateLimit::parse(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_trusted_proxies<'de, D>(deserializer: D) -> Result<TrustedProxies, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let proxies = deserialize_string_or_vec(deserializer)?;
+    let proxies: Vec<&str> = proxies.iter().flat_map(|v| v.split(',')).collect();
+    TrustedProxies::new(&proxies).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_response_headers<'de, D>(deserializer: D) -> Result<ResponseHeaders, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = deserialize_string_or_vec(deserializer)?;
+    let rules: Vec<&str> = rules.iter().map(|v| v.as_str()).collect();
+    ResponseHeaders::new(&rules).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_security_headers_skip<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let names: Vec<String> = deserialize_string_or_vec(deserializer)?
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_lowercase())
+        .collect();
+    if let Some(name) = names
+        .iter()
+        .find(|v| !SECURITY_HEADERS.contains(&v.as_str()))
+    {
+        return Err(serde::de::Error::custom(format!(
+            "Unknown security header `{name}`"
+        )));
+    }
+    Ok(names)
+}
+
+fn deserialize_cache_control<'de, D>(deserializer: D) -> Result<CacheControlRules, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = deserialize_string_or_vec(deserializer)?;
+    let rules: Vec<&str> = rules.iter().map(|v| v.as_str()).collect();
+    CacheControlRules::new(&rules).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_try_files<'de, D>(deserializer: D) -> Result<Option<TryFiles>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    TryFiles::parse(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_redirects<'de, D>(deserializer: D) -> Result<RewriteRules, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = deserialize_string_or_vec(deserializer)?;
+    let rules: Vec<&str> = rules.iter().map(|v| v.as_str()).collect();
+    RewriteRules::redirects(&rules).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_rewrites<'de, D>(deserializer: D) -> Result<RewriteRules, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = deserialize_string_or_vec(deserializer)?;
+    let rules: Vec<&str> = rules.iter().map(|v| v.as_str()).collect();
+    RewriteRules::rewrites(&rules).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_symlink_rules<'de, D>(deserializer: D) -> Result<SymlinkRules, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Flag(bool),
+        Rules(#[serde(deserialize_with = "deserialize_string_or_vec")] Vec<String>),
+    }
+    match Value::deserialize(deserializer)? {
+        Value::Flag(all) => Ok(SymlinkRules {
+            all,
+            patterns: vec![],
+        }),
+        Value::Rules(rules) => {
+            let rules: Vec<&str> = rules.iter().map(|v| v.as_str()).collect();
+            SymlinkRules::new(&rules).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+fn deserialize_header_value<'de, D>(deserializer: D) -> Result<Option<HeaderValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    HeaderValue::try_from(value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_upload_limits<'de, D>(deserializer: D) -> Result<UploadLimits, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let rules = deserialize_string_or_vec(deserializer)?;
+    let rules: Vec<&str> = rules.iter().flat_map(|v| v.split(',')).collect();
+    UploadLimits::new(&rules).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    parse_size(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_buffer_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    parse_buffer_size(&value).map_err(serde::de::Error::custom)
+}
+
+fn parse_buffer_size(value: &str) -> Result<usize> {
+    let size = parse_size(value)?;
+    if !(4 << 10..=16 << 20).contains(&size) {
+        bail!("Invalid buffer size `{value}`, it must be between 4K and 16M");
+    }
+    Ok(size as usize)
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    parse_duration(&value).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    parse_duration(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_default_sort<'de, D>(deserializer: D) -> Result<DefaultSort, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: String = Deserialize::deserialize(deserializer)?;
+    DefaultSort::parse(&value).map_err(serde::de::Error::custom)
+}
+
+/// A TLS version, which YAML takes for a number unless quoted
+fn deserialize_tls_version<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Version {
+        Name(String),
+        Number(f64),
+    }
+    let value: Option<Version> = Deserialize::deserialize(deserializer)?;
+    Ok(value.map(|v| match v {
+        Version::Name(v) => v,
+        Version::Number(v) => v.to_string(),
+    }))
+}
+
 fn deserialize_log_http<'de, D>(deserializer: D) -> Result<HttpLogger, D::Error>
 where
     D: Deserializer<'de>,
@@ -619,6 +2859,166 @@ mod tests {
         assert_eq!(args.hidden, ["tmp", "*.log", "*.lock"]);
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_chroot() {
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        tmpdir.child("root/media").create_dir_all().unwrap();
+        tmpdir.child("media").create_dir_all().unwrap();
+        let root = tmpdir.child("root").to_string_lossy().to_string();
+        let parse = |mount: &str| {
+            let mount = format!("/media={}", tmpdir.child(mount).to_string_lossy());
+            Args::parse_from(["", &root, "--chroot", "--mount", &mount])
+        };
+        let err = parse("media").unwrap_err().to_string();
+        assert!(err.contains("must be inside the serve path"), "{err}");
+        let args = parse("root/media").unwrap().chrooted();
+        assert_eq!(args.serve_path, Path::new("/"));
+        assert_eq!(
+            args.mount_paths,
+            [("media".to_string(), PathBuf::from("/media"))]
+        );
+        let trash = tmpdir.child("trash").to_string_lossy().to_string();
+        assert!(Args::parse_from(["", &root, "--chroot", "--trash-dir", &trash]).is_err());
+    }
+
+    #[test]
+    fn test_upload_limits() {
+        let limits = UploadLimits::new(&["100M", "/dir1:10K", "/dir1/dir2:1K"]).unwrap();
+        assert_eq!(limits.get(""), Some(100 * 1024 * 1024));
+        assert_eq!(limits.get("dir1"), Some(10 * 1024));
+        assert_eq!(limits.get("dir1/file"), Some(10 * 1024));
+        assert_eq!(limits.get("dir1/dir2/file"), Some(1024));
+        assert_eq!(limits.get("dir10/file"), Some(100 * 1024 * 1024));
+        assert_eq!(UploadLimits::new(&["/dir1:1K"]).unwrap().get("file"), None);
+        assert!(UploadLimits::new(&["/dir1:abc"]).is_err());
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let headers = ResponseHeaders::new(&[
+            "X-Frame-Options: DENY",
+            "/*.pdf:Content-Disposition: attachment",
+            "/dir1/*:X-Frame-Options: SAMEORIGIN",
+        ])
+        .unwrap();
+        let get = |path: &str| {
+            let mut res_headers = HeaderMap::new();
+            headers.apply(path, &mut res_headers);
+            let mut output: Vec<_> = res_headers
+                .iter()
+                .map(|(k, v)| format!("{k}: {}", v.to_str().unwrap()))
+                .collect();
+            output.sort();
+            output
+        };
+        assert_eq!(get("/index.html"), ["x-frame-options: DENY"]);
+        assert_eq!(
+            get("/a/b.pdf"),
+            ["content-disposition: attachment", "x-frame-options: DENY"]
+        );
+        assert_eq!(get("/dir1/file"), ["x-frame-options: SAMEORIGIN"]);
+        assert!(ResponseHeaders::new(&["X-Invalid"]).is_err());
+        assert!(ResponseHeaders::new(&["/*.pdf"]).is_err());
+        assert!(ResponseHeaders::new(&["Bad Name: value"]).is_err());
+    }
+
+    #[test]
+    fn test_cache_control_rules() {
+        let rules =
+            CacheControlRules::new(&["*.jpg=max-age=86400", "*=public, max-age=60"]).unwrap();
+        assert_eq!(rules.get("a.jpg").unwrap(), "max-age=86400");
+        assert_eq!(rules.get("a.txt").unwrap(), "public, max-age=60");
+        assert_eq!(
+            CacheControlRules::new(&["*.jpg=no-store"])
+                .unwrap()
+                .get("a.txt"),
+            None
+        );
+        assert!(CacheControlRules::new(&["no-store"]).is_err());
+    }
+
+    #[test]
+    fn test_try_files() {
+        let try_files = TryFiles::parse("$uri $uri/index.html $uri/ /index.html").unwrap();
+        assert_eq!(
+            try_files.candidates("docs").collect::<Vec<_>>(),
+            ["docs", "docs/index.html", "docs/"]
+        );
+        assert_eq!(
+            try_files.candidates("").collect::<Vec<_>>(),
+            ["", "index.html", ""]
+        );
+        assert_eq!(
+            try_files.fallback("docs"),
+            TryFilesFallback::Path("index.html".into())
+        );
+        let try_files = TryFiles::parse("$uri =404").unwrap();
+        assert_eq!(
+            try_files.fallback("docs"),
+            TryFilesFallback::Status(StatusCode::NOT_FOUND)
+        );
+        assert!(TryFiles::parse("$uri").is_err());
+        assert!(TryFiles::parse("index.html $uri").is_err());
+        assert!(TryFiles::parse("$uri =200").is_err());
+        assert!(TryFiles::parse("$uri =abc").is_err());
+    }
+
+    #[test]
+    fn test_symlink_rules() {
+        let rules = SymlinkRules::new(&["/public/**", "/media"]).unwrap();
+        assert!(rules.allows("public/movies"));
+        assert!(rules.allows("public/movies/a.mkv"));
+        assert!(rules.allows("media/a.mkv"));
+        assert!(!rules.allows("media2"));
+        assert!(!rules.allows("private/public"));
+        assert!(SymlinkRules::new(&["**"]).unwrap().allows("private"));
+        assert!(SymlinkRules::new(&["true"]).unwrap().is_all());
+        assert!(!SymlinkRules::new(&["false"]).unwrap().allows("public"));
+        assert!(SymlinkRules::new(&["/public/***"]).is_err());
+    }
+
+    #[test]
+    fn test_default_sort() {
+        assert_eq!(DefaultSort::parse("name").unwrap(), DefaultSort::default());
+        assert_eq!(
+            DefaultSort::parse("mtime:desc").unwrap(),
+            DefaultSort {
+                by: SortBy::Mtime,
+                desc: true
+            }
+        );
+        assert!(DefaultSort::parse("date").is_err());
+        assert!(DefaultSort::parse("ext:down").is_err());
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let proxies = TrustedProxies::new(&["127.0.0.1", "10.0.0.0/8"]).unwrap();
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let client_ip = |name: &'static str, value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+            proxies.client_ip(peer, &headers).to_string()
+        };
+        assert_eq!(client_ip("x-forwarded-for", "1.2.3.4"), "1.2.3.4");
+        assert_eq!(
+            client_ip("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2"),
+            "1.2.3.4"
+        );
+        assert_eq!(
+            client_ip("x-forwarded-for", "10.0.0.3, 10.0.0.2"),
+            "10.0.0.3"
+        );
+        assert_eq!(client_ip("x-forwarded-for", "invalid"), "127.0.0.1");
+        assert_eq!(client_ip("x-real-ip", "1.2.3.4"), "1.2.3.4");
+        assert_eq!(client_ip("x-other", "1.2.3.4"), "127.0.0.1");
+        let headers = HeaderMap::new();
+        let untrusted: IpAddr = "192.168.0.1".parse().unwrap();
+        assert_eq!(proxies.client_ip(untrusted, &headers), untrusted);
+        assert!(TrustedProxies::new(&["invalid"]).is_err());
+    }
+
     #[test]
     fn test_args_from_empty_config_file() {
         let tmpdir = assert_fs::TempDir::new().unwrap();
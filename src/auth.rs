@@ -1,20 +1,44 @@
 use anyhow::{anyhow, bail, Result};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordVerifier};
 use base64::{engine::general_purpose, Engine as _};
-use headers::HeaderValue;
-use hyper::Method;
+use headers::{HeaderMap, HeaderValue};
+use hyper::{
+    header::{AUTHORIZATION, COOKIE},
+    Method,
+};
 use indexmap::IndexMap;
+use ipnet::IpNet;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
 use lazy_static::lazy_static;
+use linked_hash_map::LinkedHashMap;
 use md5::Context;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{
+    borrow::Cow,
     collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
     path::{Path, PathBuf},
+    sync::Mutex,
 };
 use uuid::Uuid;
 
-use crate::{args::Args, utils::unix_now};
+use crate::{
+    args::{Args, AuthMethod},
+    utils::{parse_duration, parse_ip_net, unix_now},
+};
 
 const REALM: &str = "DUFS";
 const DIGEST_AUTH_TIMEOUT: u32 = 604800; // 7 days
+const CHECKED_PASSWORDS_CAPACITY: usize = 1024;
+pub const SESSION_TIMEOUT: u64 = 604800; // 7 days
+pub const SESSION_COOKIE: &str = "dufs_session";
+const MAX_LOCKOUT: u64 = 86400;
+/// Failures are forgotten after a day without new ones
+const FAILURES_TTL: u64 = 86400;
+/// Clients tracked at most, the least recently failing ones are forgotten first
+const LOCKOUT_CAPACITY: usize = 10_000;
 
 lazy_static! {
     static ref NONCESTARTHASH: Context = {
@@ -23,6 +47,9 @@ lazy_static! {
         h.consume(std::process::id().to_be_bytes());
         h
     };
+    /// Hashing is deliberately slow, remember the credentials that passed, from the least
+    /// recently used. Failures aren't, guessing passwords mustn't push out the valid ones.
+    static ref CHECKED_PASSWORDS: Mutex<LinkedHashMap<Vec<u8>, ()>> = Default::default();
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,6 +57,8 @@ pub struct AccessControl {
     use_hashed_password: bool,
     users: IndexMap<String, (String, AccessPaths)>,
     anony: Option<AccessPaths>,
+    jwt: Option<JwtAuth>,
+    proxy: Option<AuthProxy>,
 }
 
 impl Default for AccessControl {
@@ -38,6 +67,8 @@ impl Default for AccessControl {
             use_hashed_password: false,
             anony: Some(AccessPaths::new(AccessPerm::ReadWrite)),
             users: IndexMap::new(),
+            jwt: None,
+            proxy: None,
         }
     }
 }
@@ -66,6 +97,7 @@ impl AccessControl {
                 let (path, perm) = match item.split_once(':') {
                     None => (item, AccessPerm::ReadOnly),
                     Some((path, "rw")) => (path, AccessPerm::ReadWrite),
+                    Some((path, "wo")) => (path, AccessPerm::UploadOnly),
                     _ => return Err(create_err(rule)),
                 };
                 if account.is_empty() {
@@ -79,7 +111,7 @@ impl AccessControl {
                 if user.is_empty() || pass.is_empty() {
                     return Err(create_err(rule));
                 }
-                if pass.starts_with("$6$") {
+                if is_hashed_password(pass) {
                     use_hashed_password = true;
                 }
                 users.insert(user.to_string(), (pass.to_string(), access_paths));
@@ -96,46 +128,450 @@ impl AccessControl {
             use_hashed_password,
             users,
             anony,
+            jwt: None,
+            proxy: None,
         })
     }
 
+    /// Accept bearer tokens besides the auth rules
+    pub fn set_jwt(&mut self, jwt: JwtAuth) {
+        if *self == Self::default() {
+            // Without any rules, only the token holders have access
+            self.anony = None;
+        }
+        self.jwt = Some(jwt);
+    }
+
+    /// Trust the user name in a header set by an authenticating proxy
+    pub fn set_proxy(&mut self, proxy: AuthProxy) {
+        if *self == Self::default() {
+            self.anony = None;
+        }
+        self.proxy = Some(proxy);
+    }
+
+    pub fn proxy(&self) -> Option<&AuthProxy> {
+        self.proxy.as_ref()
+    }
+
     pub fn exist(&self) -> bool {
-        !self.users.is_empty()
+        !self.users.is_empty() || self.jwt.is_some() || self.proxy.is_some()
+    }
+
+    pub fn use_jwt(&self) -> bool {
+        self.jwt.is_some()
     }
 
+    /// The proxy header must have been removed from requests of untrusted peers
     pub fn guard(
         &self,
         path: &str,
         method: &Method,
-        authorization: Option<&HeaderValue>,
+        headers: &HeaderMap<HeaderValue>,
     ) -> (Option<String>, Option<AccessPaths>) {
-        if let Some(authorization) = authorization {
+        let (user, paths) = self.authorize(method, headers);
+        guard_paths(user, paths, path, method)
+    }
+
+    /// Guard a user already authenticated by other means
+    pub fn guard_user(
+        &self,
+        path: &str,
+        method: &Method,
+        user: &str,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        guard_paths(Some(user.to_string()), self.user_paths(user), path, method)
+    }
+
+    /// The user of the request and all the paths it may access, for `guard` to find the
+    /// requested one in. `(None, None)` if the credentials are wrong.
+    pub fn authorize(
+        &self,
+        method: &Method,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> (Option<String>, Option<Cow<'_, AccessPaths>>) {
+        if let Some(user) = self
+            .proxy
+            .as_ref()
+            .and_then(|v| headers.get(v.header.as_str()))
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+        {
+            return (Some(user.to_string()), self.user_paths(user));
+        }
+        if let Some(authorization) = headers.get(AUTHORIZATION) {
+            if let (Some(jwt), Some(token)) = (
+                self.jwt.as_ref(),
+                strip_prefix(authorization.as_bytes(), b"Bearer "),
+            ) {
+                return match std::str::from_utf8(token).ok().and_then(|v| jwt.verify(v)) {
+                    Some(claims) => {
+                        let paths = self.jwt_access_paths(&claims);
+                        (claims.sub, Some(Cow::Owned(paths)))
+                    }
+                    None => (None, None),
+                };
+            }
             if let Some(user) = get_auth_user(authorization) {
                 if let Some((pass, paths)) = self.users.get(&user) {
-                    if method == Method::OPTIONS {
-                        return (Some(user), Some(AccessPaths::new(AccessPerm::ReadOnly)));
-                    }
                     if check_auth(authorization, method.as_str(), &user, pass).is_some() {
-                        return (Some(user), paths.find(path, !is_readonly_method(method)));
+                        return (Some(user), Some(Cow::Borrowed(paths)));
                     } else {
                         return (None, None);
                     }
                 }
             }
         }
+        (None, self.anony.as_ref().map(Cow::Borrowed))
+    }
+
+    /// The paths of a user already authenticated by other means, those of anonymous users
+    /// for the ones without rules
+    pub fn user_paths(&self, user: &str) -> Option<Cow<'_, AccessPaths>> {
+        match self.users.get(user) {
+            Some((_, paths)) => Some(Cow::Borrowed(paths)),
+            None => self.anony.as_ref().map(Cow::Borrowed),
+        }
+    }
+
+    pub async fn check_password(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some((pass, _)) if is_hashed_password(pass) => {
+                check_hashed_password(password, pass).await
+            }
+            Some((pass, _)) => pass == password,
+            None => false,
+        }
+    }
+
+    /// Checks the hashed password of the basic auth credentials on a blocking thread, for
+    /// `guard` to find the outcome without hashing on the async workers
+    pub async fn precheck(&self, headers: &HeaderMap<HeaderValue>) {
+        let Some(value) = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| strip_prefix(v.as_bytes(), b"Basic "))
+        else {
+            return;
+        };
+        let Some(value) = general_purpose::STANDARD.decode(value).ok() else {
+            return;
+        };
+        let Some((user, password)) = std::str::from_utf8(&value)
+            .ok()
+            .and_then(|v| v.split_once(':'))
+        else {
+            return;
+        };
+        if let Some((pass, _)) = self.users.get(user) {
+            if is_hashed_password(pass) {
+                check_hashed_password(password, pass).await;
+            }
+        }
+    }
+
+    /// Permissions of the `sub` user from the auth rules, extended by `scope` entries
+    /// like `dufs:/dir1:rw` or `dufs:/dir2`
+    fn jwt_access_paths(&self, claims: &JwtClaims) -> AccessPaths {
+        let mut paths = match claims.sub.as_ref().and_then(|v| self.users.get(v)) {
+            Some((_, paths)) => paths.clone(),
+            None => self.anony.clone().unwrap_or_default(),
+        };
+        for item in claims
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+        {
+            let item = match item.strip_prefix("dufs:") {
+                Some(v) => v,
+                None => continue,
+            };
+            if let Some(path) = item.strip_suffix(":rw") {
+                paths.add(path, AccessPerm::ReadWrite);
+            } else if let Some(path) = item.strip_suffix(":wo") {
+                paths.add(path, AccessPerm::UploadOnly);
+            } else {
+                paths.add(item, AccessPerm::ReadOnly);
+            }
+        }
+        paths
+    }
+}
+
+/// Sessions of users logged in with the login form
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, (String, u64)>>,
+}
+
+impl SessionStore {
+    /// Returns the session id
+    pub fn create(&self, user: &str) -> Result<String> {
+        let now = unix_now()?.as_secs();
+        let id = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, (_, expires)| *expires > now);
+        sessions.insert(id.clone(), (user.to_string(), now + SESSION_TIMEOUT));
+        Ok(id)
+    }
+
+    /// The user of the session cookie in the request
+    pub fn user(&self, headers: &HeaderMap<HeaderValue>) -> Option<String> {
+        let id = get_session_id(headers)?;
+        let now = unix_now().ok()?.as_secs();
+        let sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some((user, expires)) if *expires > now => Some(user.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&self, headers: &HeaderMap<HeaderValue>) {
+        if let Some(id) = get_session_id(headers) {
+            self.sessions.lock().unwrap().remove(id);
+        }
+    }
+}
+
+fn get_session_id(headers: &HeaderMap<HeaderValue>) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|v| {
+            let (name, value) = v.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then_some(value)
+        })
+}
+
+/// Lock out clients after too many failed auth attempts, `<attempts>/<duration>` such as `10/1m`.
+///
+/// Each failure beyond `attempts` doubles the lockout, up to a day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    attempts: u32,
+    duration: u64,
+}
+
+impl LockoutPolicy {
+    /// Returns `None` for `off`
+    pub fn parse(value: &str) -> Result<Option<Self>> {
+        if value == "off" {
+            return Ok(None);
+        }
+        let err = || anyhow!("Invalid auth lockout `{value}`");
+        let (attempts, duration) = value.split_once('/').ok_or_else(err)?;
+        let attempts: u32 = attempts.trim().parse().map_err(|_| err())?;
+        let duration = parse_duration(duration).map_err(|_| err())?.as_secs();
+        if attempts == 0 || duration == 0 {
+            return Err(err());
+        }
+        Ok(Some(Self { attempts, duration }))
+    }
+
+    /// Lockout in seconds after `failures` consecutive failures
+    fn lockout(&self, failures: u32) -> u64 {
+        if failures < self.attempts {
+            return 0;
+        }
+        let exp = (failures - self.attempts).min(32);
+        self.duration.saturating_mul(1 << exp).min(MAX_LOCKOUT)
+    }
+}
+
+/// Failed auth attempts per client IP, IPv6 clients are grouped by /64 since they usually
+/// get a whole one
+#[derive(Debug, Default)]
+pub struct AuthLockout {
+    policy: Option<LockoutPolicy>,
+    /// Consecutive failures and the time of the last one, from the least recent
+    failures: Mutex<LinkedHashMap<IpAddr, (u32, u64)>>,
+}
+
+impl AuthLockout {
+    pub fn new(policy: Option<LockoutPolicy>) -> Self {
+        Self {
+            policy,
+            failures: Default::default(),
+        }
+    }
+
+    /// Seconds left until the client may try again
+    pub fn locked(&self, ip: Option<IpAddr>) -> Option<u64> {
+        let (policy, ip) = (self.policy.as_ref()?, ip?);
+        let now = unix_now().ok()?.as_secs();
+        let failures = self.failures.lock().unwrap();
+        let (count, last) = failures.get(&lockout_key(ip))?;
+        let until = last + policy.lockout(*count);
+        (until > now).then(|| until - now)
+    }
+
+    pub fn fail(&self, ip: Option<IpAddr>) {
+        let (policy, ip) = match (self.policy.as_ref(), ip) {
+            (Some(policy), Some(ip)) => (policy, ip),
+            _ => return,
+        };
+        let now = match unix_now() {
+            Ok(v) => v.as_secs(),
+            Err(_) => return,
+        };
+        let key = lockout_key(ip);
+        let mut failures = self.failures.lock().unwrap();
+        if failures.get_refresh(&key).is_none() {
+            // Lockouts don't outlast the TTL, so the expired entries are the least recent ones
+            while let Some((_, (_, last))) = failures.front() {
+                if *last + FAILURES_TTL > now && failures.len() < LOCKOUT_CAPACITY {
+                    break;
+                }
+                failures.pop_front();
+            }
+            failures.insert(key, (0, 0));
+        }
+        let (count, last) = failures.get_mut(&key).unwrap();
+        if *last + FAILURES_TTL <= now {
+            *count = 0;
+        }
+        *count += 1;
+        *last = now;
+        let lockout = policy.lockout(*count);
+        if lockout > 0 {
+            warn!("Too many failed auth attempts from {ip}, locked out for {lockout}s");
+        }
+    }
 
-        if method == Method::OPTIONS {
-            return (None, Some(AccessPaths::new(AccessPerm::ReadOnly)));
+    pub fn succeed(&self, ip: Option<IpAddr>) {
+        if let (Some(_), Some(ip)) = (self.policy.as_ref(), ip) {
+            self.failures.lock().unwrap().remove(&lockout_key(ip));
         }
+    }
+}
+
+fn lockout_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & (u128::MAX << 64))),
+        },
+        ip => ip,
+    }
+}
+
+/// Authenticating reverse proxy, e.g. Authelia or oauth2-proxy
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthProxy {
+    header: String,
+    trusted: Vec<IpNet>,
+}
 
-        if let Some(paths) = self.anony.as_ref() {
-            return (None, paths.find(path, !is_readonly_method(method)));
+impl AuthProxy {
+    pub fn new(header: &str, trusted: &[&str]) -> Result<Self> {
+        let trusted = trusted
+            .iter()
+            .map(|v| parse_ip_net(v))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            header: header.to_ascii_lowercase(),
+            trusted,
+        })
+    }
+
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Unix socket peers, which have no address, are trusted
+    pub fn is_trusted(&self, addr: Option<IpAddr>) -> bool {
+        match addr {
+            Some(addr) => self.trusted.iter().any(|v| v.contains(&addr)),
+            None => true,
         }
+    }
+}
+
+/// Verify `Authorization: Bearer <jwt>` against an HMAC secret or a JWKS
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtAuth {
+    keys: JwtKeys,
+    audience: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum JwtKeys {
+    Secret(String),
+    Jwks(JwkSet),
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtClaims {
+    sub: Option<String>,
+    scope: Option<String>,
+}
 
-        (None, None)
+impl JwtAuth {
+    pub fn new(secret: Option<&str>, jwks: Option<&str>, audience: Option<&str>) -> Result<Self> {
+        let keys = match (secret, jwks) {
+            (Some(secret), None) => JwtKeys::Secret(secret.to_string()),
+            (None, Some(jwks)) => JwtKeys::Jwks(load_jwks(jwks)?),
+            (Some(_), Some(_)) => bail!("Only one of jwt-secret and jwt-jwks can be set"),
+            (None, None) => bail!("No jwt-secret or jwt-jwks set"),
+        };
+        Ok(Self {
+            keys,
+            audience: audience.map(|v| v.to_string()),
+        })
+    }
+
+    fn verify(&self, token: &str) -> Option<JwtClaims> {
+        let token = token.trim();
+        let header = decode_header(token).ok()?;
+        let key = match &self.keys {
+            JwtKeys::Secret(secret) => {
+                if !matches!(
+                    header.alg,
+                    Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+                ) {
+                    return None;
+                }
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            JwtKeys::Jwks(jwks) => {
+                let jwk = match header.kid.as_deref() {
+                    Some(kid) => jwks.find(kid)?,
+                    None if jwks.keys.len() == 1 => &jwks.keys[0],
+                    None => return None,
+                };
+                DecodingKey::from_jwk(jwk).ok()?
+            }
+        };
+        // The key family is checked against the algorithm, so an HMAC token can't
+        // be verified with a public key
+        let mut validation = Validation::new(header.alg);
+        match self.audience.as_ref() {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        decode::<JwtClaims>(token, &key, &validation)
+            .ok()
+            .map(|v| v.claims)
     }
 }
 
+fn load_jwks(value: &str) -> Result<JwkSet> {
+    let jwks = if value.starts_with("http://") || value.starts_with("https://") {
+        ureq::get(value)
+            .call()
+            .map_err(|e| anyhow!("Failed to fetch jwks from `{value}`, {e}"))?
+            .into_json()
+            .map_err(|e| anyhow!("Invalid jwks from `{value}`, {e}"))?
+    } else {
+        let contents = std::fs::read_to_string(value)
+            .map_err(|e| anyhow!("Failed to read jwks at `{value}`, {e}"))?;
+        serde_json::from_str(&contents).map_err(|e| anyhow!("Invalid jwks at `{value}`, {e}"))?
+    };
+    Ok(jwks)
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct AccessPaths {
     perm: AccessPerm,
@@ -187,7 +623,8 @@ impl AccessPaths {
             .filter(|v| !v.is_empty())
             .collect();
         let target = self.find_impl(&parts, self.perm)?;
-        if writable && !target.perm().readwrite() {
+        // The server limits upload-only paths to creating new entries
+        if writable && !target.perm().readwrite() && !target.perm().uploadonly() {
             return None;
         }
         Some(target)
@@ -220,6 +657,9 @@ impl AccessPaths {
     }
 
     pub fn leaf_paths(&self, base: &Path) -> Vec<PathBuf> {
+        if self.perm().uploadonly() {
+            return vec![];
+        }
         if !self.perm().indexonly() {
             return vec![base.to_path_buf()];
         }
@@ -233,7 +673,7 @@ impl AccessPaths {
             let base = base.join(name);
             if child.perm().indexonly() {
                 child.leaf_paths_impl(output, &base);
-            } else {
+            } else if !child.perm().uploadonly() {
                 output.push(base)
             }
         }
@@ -244,6 +684,8 @@ impl AccessPaths {
 pub enum AccessPerm {
     #[default]
     IndexOnly,
+    /// Create new files and folders, but neither list nor read them
+    UploadOnly,
     ReadOnly,
     ReadWrite,
 }
@@ -256,10 +698,29 @@ impl AccessPerm {
     pub fn indexonly(&self) -> bool {
         self == &AccessPerm::IndexOnly
     }
+
+    pub fn uploadonly(&self) -> bool {
+        self == &AccessPerm::UploadOnly
+    }
+}
+
+/// Narrows the paths `authorize` found down to the requested one, any user may ask for the
+/// allowed methods
+pub fn guard_paths(
+    user: Option<String>,
+    paths: Option<Cow<'_, AccessPaths>>,
+    path: &str,
+    method: &Method,
+) -> (Option<String>, Option<AccessPaths>) {
+    if method == Method::OPTIONS {
+        return (user, Some(AccessPaths::new(AccessPerm::ReadOnly)));
+    }
+    let paths = paths.and_then(|v| v.find(path, !is_readonly_method(method)));
+    (user, paths)
 }
 
 pub fn www_authenticate(args: &Args) -> Result<HeaderValue> {
-    let value = if args.auth.use_hashed_password {
+    let value = if args.auth.use_hashed_password || args.auth_method == AuthMethod::Basic {
         format!("Basic realm=\"{}\"", REALM)
     } else {
         let nonce = create_nonce()?;
@@ -299,8 +760,8 @@ pub fn check_auth(
             return None;
         }
 
-        if auth_pass.starts_with("$6$") {
-            if let Ok(()) = sha_crypt::sha512_check(parts[1], auth_pass) {
+        if is_hashed_password(auth_pass) {
+            if verify_hashed_password(parts[1], auth_pass) {
                 return Some(());
             }
         } else if parts[1] == auth_pass {
@@ -382,6 +843,85 @@ pub fn check_auth(
     }
 }
 
+fn is_hashed_password(pass: &str) -> bool {
+    ["$6$", "$2a$", "$2b$", "$2y$", "$argon2"]
+        .iter()
+        .any(|v| pass.starts_with(v))
+}
+
+/// Checks the password against the hash on a blocking thread, as bcrypt and argon2 take a
+/// while on purpose
+async fn check_hashed_password(password: &str, hash: &str) -> bool {
+    if checked_password(password, hash) {
+        return true;
+    }
+    let (password, hash) = (password.to_string(), hash.to_string());
+    tokio::task::spawn_blocking(move || verify_hashed_password(&password, &hash))
+        .await
+        .unwrap_or_default()
+}
+
+fn password_key(password: &str, hash: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(hash);
+    hasher.update([0]);
+    hasher.update(password);
+    hasher.finalize().to_vec()
+}
+
+fn checked_password(password: &str, hash: &str) -> bool {
+    let key = password_key(password, hash);
+    CHECKED_PASSWORDS
+        .lock()
+        .unwrap()
+        .get_refresh(&key)
+        .is_some()
+}
+
+fn verify_hashed_password(password: &str, hash: &str) -> bool {
+    if checked_password(password, hash) {
+        return true;
+    }
+    let verified = if hash.starts_with("$6$") {
+        sha_crypt::sha512_check(password, hash).is_ok()
+    } else if hash.starts_with("$argon2") {
+        match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => false,
+        }
+    } else {
+        bcrypt::verify(password, hash).unwrap_or_default()
+    };
+    if verified {
+        let mut cache = CHECKED_PASSWORDS.lock().unwrap();
+        if cache.len() >= CHECKED_PASSWORDS_CAPACITY {
+            cache.pop_front();
+        }
+        cache.insert(password_key(password, hash), ());
+    }
+    verified
+}
+
+/// Hash a password for use in auth rules
+pub fn hash_password(password: &str, algorithm: &str) -> Result<String> {
+    let output = match algorithm {
+        "argon2" => {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map_err(|e| anyhow!("Failed to hash password, {e}"))?
+                .to_string()
+        }
+        "bcrypt" => bcrypt::hash(password, bcrypt::DEFAULT_COST)?,
+        "sha-512" => sha_crypt::sha512_simple(password, &Default::default())
+            .map_err(|e| anyhow!("Failed to hash password, {e:?}"))?,
+        _ => bail!("Unknown hash algorithm `{algorithm}`"),
+    };
+    Ok(output)
+}
+
 /// Check if a nonce is still valid.
 /// Return an error if it was never valid
 fn validate_nonce(nonce: &[u8]) -> Result<bool> {
@@ -517,6 +1057,43 @@ fn compact_split_rules(rules: &[&str]) -> Vec<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_auth_lockout() {
+        assert_eq!(LockoutPolicy::parse("off").unwrap(), None);
+        assert!(LockoutPolicy::parse("5").is_err());
+        assert!(LockoutPolicy::parse("0/1m").is_err());
+        let policy = LockoutPolicy::parse("2/1m").unwrap().unwrap();
+        assert_eq!(policy.lockout(1), 0);
+        assert_eq!(policy.lockout(2), 60);
+        assert_eq!(policy.lockout(4), 240);
+        assert_eq!(policy.lockout(100), MAX_LOCKOUT);
+
+        let lockout = AuthLockout::new(Some(policy));
+        let ip: Option<IpAddr> = Some("10.0.0.1".parse().unwrap());
+        lockout.fail(ip);
+        assert_eq!(lockout.locked(ip), None);
+        lockout.fail(ip);
+        assert!(lockout.locked(ip).is_some());
+        assert_eq!(lockout.locked(Some("10.0.0.2".parse().unwrap())), None);
+        lockout.succeed(ip);
+        assert_eq!(lockout.locked(ip), None);
+
+        let ip: Option<IpAddr> = Some("2001:db8::1".parse().unwrap());
+        lockout.fail(ip);
+        lockout.fail(Some("2001:db8::2".parse().unwrap()));
+        assert!(lockout.locked(ip).is_some());
+        assert_eq!(
+            lockout.locked(Some("2001:db8:0:1::1".parse().unwrap())),
+            None
+        );
+
+        for i in 0..LOCKOUT_CAPACITY as u32 {
+            lockout.fail(Some(IpAddr::from((0x0b00_0000 + i).to_be_bytes())));
+        }
+        assert_eq!(lockout.failures.lock().unwrap().len(), LOCKOUT_CAPACITY);
+        assert_eq!(lockout.locked(ip), None);
+    }
+
     #[test]
     fn test_split_account_paths() {
         assert_eq!(
@@ -581,6 +1158,40 @@ mod tests {
         );
         assert_eq!(paths.find("dir2", true), None);
         assert!(paths.find("dir1/file", true).is_some());
+        paths.add("/dir3", AccessPerm::UploadOnly);
+        assert_eq!(paths.leaf_paths(Path::new("/tmp")).len(), 4);
+        assert_eq!(
+            paths.find("dir3/file", true).map(|v| v.perm()),
+            Some(AccessPerm::UploadOnly)
+        );
+    }
+
+    #[test]
+    fn test_auth_proxy() {
+        let proxy = AuthProxy::new("X-Remote-User", &["127.0.0.1", "10.0.0.0/8"]).unwrap();
+        assert_eq!(proxy.header(), "x-remote-user");
+        assert!(proxy.is_trusted(Some("127.0.0.1".parse().unwrap())));
+        assert!(proxy.is_trusted(Some("10.1.2.3".parse().unwrap())));
+        assert!(!proxy.is_trusted(Some("192.168.1.1".parse().unwrap())));
+        assert!(proxy.is_trusted(None));
+        assert!(AuthProxy::new("X-Remote-User", &["invalid"]).is_err());
+    }
+
+    #[test]
+    fn test_hashed_password() {
+        for algorithm in ["argon2", "sha-512"] {
+            let hash = hash_password("pass", algorithm).unwrap();
+            assert!(is_hashed_password(&hash));
+            assert!(verify_hashed_password("pass", &hash));
+            assert!(!verify_hashed_password("wrong", &hash));
+            // Only the valid one is remembered, to be found without hashing again
+            assert!(checked_password("pass", &hash));
+            assert!(!checked_password("wrong", &hash));
+        }
+        let hash = "$2y$04$jT4SXfPy0CAEo0p5YQLl8OCXEwAMOaxkgzD/V3tYak8SLMLqVE9D2";
+        assert!(is_hashed_password(hash));
+        assert!(verify_hashed_password("pass", hash));
+        assert!(!verify_hashed_password("wrong", hash));
     }
 
     #[test]
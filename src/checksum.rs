@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use headers::{HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+const BUF_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgo {
+    Md5,
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl ChecksumAlgo {
+    /// Algorithm names as registered for `Repr-Digest`
+    pub fn from_digest_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "md5" => Some(Self::Md5),
+            "sha-256" => Some(Self::Sha256),
+            "sha-512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Algorithm names as given in query strings, e.g. `sha256`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "md5" => Some(Self::Md5),
+            "sha256" => Some(Self::Sha256),
+            "sha512" => Some(Self::Sha512),
+            "blake3" => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            Self::Md5 => Hasher::Md5(md5::Context::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Sha512 => Hasher::Sha512(Sha512::new()),
+            Self::Blake3 => Hasher::Blake3(Box::default()),
+        }
+    }
+}
+
+pub enum Hasher {
+    Md5(md5::Context),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(h) => h.consume(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Md5(h) => h.compute().0.to_vec(),
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+            Self::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// The digest of the file, in lowercase hex.
+///
+/// With `cache`, digests are kept in extended attributes of the file along with its
+/// mtime and size, and reused until either changes.
+pub async fn file_checksum(path: &Path, algo: ChecksumAlgo, cache: bool) -> Result<String> {
+    let stamp = file_stamp(&fs::metadata(path).await?);
+    if cache {
+        if let Some(hash) = cached_checksum(path, algo, &stamp) {
+            return Ok(hash);
+        }
+    }
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = algo.hasher();
+    let mut buf = vec![0; BUF_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|v| format!("{v:02x}"))
+        .collect();
+    // Not if it changed while it was read
+    if cache && file_stamp(&fs::metadata(path).await?) == stamp {
+        store_checksum(path, algo, &stamp, &hash);
+    }
+    Ok(hash)
+}
+
+/// The digest of the file if it's cached in its extended attributes and still current
+pub async fn cached_file_checksum(path: &Path, algo: ChecksumAlgo) -> Option<String> {
+    let stamp = file_stamp(&fs::metadata(path).await.ok()?);
+    cached_checksum(path, algo, &stamp)
+}
+
+fn file_stamp(meta: &Metadata) -> String {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+        .map(|v| v.as_nanos())
+        .unwrap_or_default();
+    format!("{mtime}:{}", meta.len())
+}
+
+#[cfg(unix)]
+fn xattr_name(algo: ChecksumAlgo) -> String {
+    format!("user.dufs.{}", algo.name())
+}
+
+#[cfg(unix)]
+fn cached_checksum(path: &Path, algo: ChecksumAlgo, stamp: &str) -> Option<String> {
+    let value = xattr::get(path, xattr_name(algo)).ok()??;
+    let value = String::from_utf8(value).ok()?;
+    let (cached_stamp, hash) = value.rsplit_once(':')?;
+    (cached_stamp == stamp).then(|| hash.to_string())
+}
+
+#[cfg(unix)]
+fn store_checksum(path: &Path, algo: ChecksumAlgo, stamp: &str, hash: &str) {
+    let value = format!("{stamp}:{hash}");
+    if let Err(err) = xattr::set(path, xattr_name(algo), value.as_bytes()) {
+        debug!("Failed to cache the hash of {}, {err}", path.display());
+    }
+}
+
+#[cfg(not(unix))]
+fn cached_checksum(_path: &Path, _algo: ChecksumAlgo, _stamp: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn store_checksum(_path: &Path, _algo: ChecksumAlgo, _stamp: &str, _hash: &str) {}
+
+/// Verify the uploaded body against `Content-MD5` and `Repr-Digest` request headers
+pub struct DigestVerifier {
+    items: Vec<(Vec<u8>, Hasher)>,
+}
+
+impl DigestVerifier {
+    /// Returns `None` if the request doesn't carry any supported digest.
+    ///
+    /// `Repr-Digest` describes the whole representation, so it's only honored
+    /// when `full_body` is true.
+    pub fn from_headers(headers: &HeaderMap<HeaderValue>, full_body: bool) -> Result<Option<Self>> {
+        let mut items = vec![];
+        if let Some(value) = headers.get("content-md5") {
+            let value = decode_base64(value.to_str()?.trim())
+                .ok_or_else(|| anyhow!("Invalid Content-MD5"))?;
+            items.push((value, ChecksumAlgo::Md5.hasher()));
+        }
+        if full_body {
+            if let Some(value) = headers.get("repr-digest") {
+                for (algo, value) in parse_digest_fields(value.to_str()?)? {
+                    items.push((value, algo.hasher()));
+                }
+            }
+        }
+        if items.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Self { items }))
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, hasher) in self.items.iter_mut() {
+            hasher.update(data);
+        }
+    }
+
+    pub fn verify(self) -> bool {
+        self.items
+            .into_iter()
+            .all(|(expect, hasher)| hasher.finalize() == expect)
+    }
+}
+
+/// Parse a `Digest-Fields` dictionary, e.g. `sha-256=:base64:, sha-512=:base64:`
+///
+/// Unknown algorithms are ignored.
+fn parse_digest_fields(value: &str) -> Result<Vec<(ChecksumAlgo, Vec<u8>)>> {
+    let mut output = vec![];
+    for item in value.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let (name, value) = item
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid digest `{item}`"))?;
+        let value = value
+            .trim()
+            .strip_prefix(':')
+            .and_then(|v| v.strip_suffix(':'))
+            .and_then(decode_base64)
+            .ok_or_else(|| anyhow!("Invalid digest `{item}`"))?;
+        if let Some(algo) = ChecksumAlgo::from_digest_name(name.trim()) {
+            output.push((algo, value));
+        }
+    }
+    Ok(output)
+}
+
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    general_purpose::STANDARD.decode(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_digest_fields() {
+        assert_eq!(
+            parse_digest_fields("sha-256=:AAEC:, unknown=:AA==:").unwrap(),
+            vec![(ChecksumAlgo::Sha256, vec![0, 1, 2])]
+        );
+        assert!(parse_digest_fields("sha-256=AAEC").is_err());
+        assert!(parse_digest_fields("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,580 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use clap::ArgMatches;
+use fast_rsync::Signature;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+use crate::delta;
+use crate::utils::{decode_uri, encode_uri, get_file_name};
+
+/// How often the progress line is redrawn
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+/// Files the remote has an older copy of are sent as a delta from this size, below it
+/// the round trip for the signature isn't worth it
+const DELTA_MIN_SIZE: u64 = 1 << 20;
+
+/// A file or folder of a tree, by its path from the root of the tree
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    is_dir: bool,
+    size: u64,
+    /// Milliseconds since the epoch
+    mtime: u64,
+}
+
+type Tree = BTreeMap<String, Entry>;
+
+/// An entry of a `?json` listing
+#[derive(Debug, Deserialize)]
+struct ListItem {
+    path_type: String,
+    name: String,
+    mtime: u64,
+    size: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Listing {
+    paths: Vec<ListItem>,
+}
+
+/// Uploads files and folders into the remote folder
+pub fn upload(matches: &ArgMatches) -> Result<()> {
+    let client = Client::new(matches)?;
+    let url = dir_url(matches.get_one::<String>("url").unwrap());
+    let mut dirs = vec![];
+    let mut files = vec![];
+    for path in matches.get_many::<PathBuf>("paths").unwrap() {
+        let meta =
+            fs::metadata(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+        let name = get_file_name(&fs::canonicalize(path)?).to_string();
+        if meta.is_dir() {
+            dirs.push(name.clone());
+            for (rel, entry) in local_tree(path)? {
+                let rel = format!("{name}/{rel}");
+                match entry.is_dir {
+                    true => dirs.push(rel),
+                    false => {
+                        client.add_total(entry.size);
+                        files.push((path.join(&rel[name.len() + 1..]), rel, entry.size));
+                    }
+                }
+            }
+        } else {
+            client.add_total(meta.len());
+            files.push((path.clone(), name, meta.len()));
+        }
+    }
+    for dir in dirs {
+        client.mkdir(&join_url(&url, &dir))?;
+    }
+    client.run(files, |(path, rel, size)| {
+        client.upload(&path, &join_url(&url, &rel), size)
+    })
+}
+
+/// Downloads the remote file, or the remote folder with all it holds
+pub fn download(matches: &ArgMatches) -> Result<()> {
+    let client = Client::new(matches)?;
+    let url = matches.get_one::<String>("url").unwrap();
+    let dest = matches
+        .get_one::<PathBuf>("dest")
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from("."));
+    let name = url_name(url);
+    match client.stat(url)? {
+        None => bail!("Not found `{url}`"),
+        Some(entry) if !entry.is_dir => {
+            let dest = match dest.is_dir() {
+                true => dest.join(&name),
+                false => dest,
+            };
+            client.add_total(entry.size);
+            client.run(
+                vec![(url.to_string(), dest, entry.mtime)],
+                |(url, dest, mtime)| client.download(&url, &dest, mtime),
+            )
+        }
+        Some(_) => {
+            let url = dir_url(url);
+            let dest = match name.is_empty() {
+                true => dest,
+                false => dest.join(&name),
+            };
+            fs::create_dir_all(&dest)?;
+            let remote = client.list(&url)?;
+            pull(&client, &url, &dest, &remote, &Tree::new())
+        }
+    }
+}
+
+/// Makes the destination folder the same as the source folder, one of them being remote
+pub fn sync(matches: &ArgMatches) -> Result<()> {
+    let client = Client::new(matches)?;
+    let src = matches.get_one::<String>("src").unwrap();
+    let dest = matches.get_one::<String>("dest").unwrap();
+    let delete = matches.get_flag("delete");
+    match (is_url(src), is_url(dest)) {
+        (false, true) => {
+            let local = local_tree(Path::new(src))?;
+            let url = dir_url(dest);
+            let remote = match client.stat(&url)? {
+                Some(entry) if entry.is_dir => client.list(&url)?,
+                Some(_) => bail!("Not a folder `{dest}`"),
+                None => {
+                    client.mkdir(&url)?;
+                    Tree::new()
+                }
+            };
+            if delete {
+                for rel in extraneous(&local, &remote) {
+                    client.delete(&join_url(&url, &rel))?;
+                }
+            }
+            push(&client, Path::new(src), &url, &local, &remote)
+        }
+        (true, false) => {
+            let url = dir_url(src);
+            if !client.stat(&url)?.is_some_and(|v| v.is_dir) {
+                bail!("Not a folder `{src}`");
+            }
+            let remote = client.list(&url)?;
+            let dest = Path::new(dest);
+            fs::create_dir_all(dest)?;
+            let local = local_tree(dest)?;
+            if delete {
+                for rel in extraneous(&remote, &local) {
+                    let path = dest.join(&rel);
+                    match local[&rel].is_dir {
+                        true => fs::remove_dir_all(&path)?,
+                        false => fs::remove_file(&path)?,
+                    }
+                }
+            }
+            pull(&client, &url, dest, &remote, &local)
+        }
+        _ => bail!("One of <src> and <dest> must be a URL, the other a path"),
+    }
+}
+
+/// Uploads what's missing or changed in the remote tree
+fn push(client: &Client, root: &Path, url: &str, local: &Tree, remote: &Tree) -> Result<()> {
+    let mut files = vec![];
+    for (rel, entry) in local {
+        let other = remote.get(rel);
+        if entry.is_dir {
+            if other.is_none() {
+                client.mkdir(&join_url(url, rel))?;
+            }
+        } else if other.is_none_or(|v| v.is_dir || v.size != entry.size || v.mtime < entry.mtime) {
+            client.add_total(entry.size);
+            let delta = other.is_some_and(|v| !v.is_dir) && entry.size >= DELTA_MIN_SIZE;
+            files.push((root.join(rel), join_url(url, rel), entry.size, delta));
+        }
+    }
+    client.run(files, |(path, url, size, delta)| {
+        if delta && client.upload_delta(&path, &url, size)? {
+            return Ok(());
+        }
+        client.upload(&path, &url, size)
+    })
+}
+
+/// Downloads what's missing or changed in the local tree
+fn pull(client: &Client, url: &str, root: &Path, remote: &Tree, local: &Tree) -> Result<()> {
+    let mut files = vec![];
+    for (rel, entry) in remote {
+        let other = local.get(rel);
+        if entry.is_dir {
+            fs::create_dir_all(root.join(rel))?;
+        } else if other.is_none_or(|v| v.is_dir || v.size != entry.size || v.mtime < entry.mtime) {
+            client.add_total(entry.size);
+            files.push((join_url(url, rel), root.join(rel), entry.mtime));
+        }
+    }
+    client.run(files, |(url, path, mtime)| {
+        client.download(&url, &path, mtime)
+    })
+}
+
+/// The entries of `dest` missing from `src`, leaving out the ones in folders that are
+/// themselves left out
+fn extraneous(src: &Tree, dest: &Tree) -> Vec<String> {
+    dest.keys()
+        .filter(|rel| !src.contains_key(*rel))
+        .filter(|rel| match rel.rsplit_once('/') {
+            Some((parent, _)) => src.contains_key(parent),
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// The entries under the local folder
+fn local_tree(root: &Path) -> Result<Tree> {
+    let mut tree = Tree::new();
+    for entry in WalkDir::new(root).min_depth(1).follow_links(true) {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let rel = entry.path().strip_prefix(root)?;
+        let rel: Vec<_> = rel.iter().map(|v| v.to_string_lossy()).collect();
+        tree.insert(
+            rel.join("/"),
+            Entry {
+                is_dir: meta.is_dir(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                mtime: to_millis(meta.modified()?),
+            },
+        );
+    }
+    Ok(tree)
+}
+
+struct Client {
+    agent: ureq::Agent,
+    auth: Option<String>,
+    jobs: usize,
+    progress: Progress,
+}
+
+impl Client {
+    fn new(matches: &ArgMatches) -> Result<Self> {
+        let auth = matches
+            .get_one::<String>("user")
+            .map(|v| format!("Basic {}", general_purpose::STANDARD.encode(v)));
+        Ok(Self {
+            agent: ureq::AgentBuilder::new().build(),
+            auth,
+            jobs: *matches.get_one::<usize>("jobs").unwrap(),
+            progress: Progress::new(!matches.get_flag("quiet") && io::stderr().is_terminal()),
+        })
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let req = self.agent.request(method, url);
+        match &self.auth {
+            Some(auth) => req.set("Authorization", auth),
+            None => req,
+        }
+    }
+
+    /// The remote file or folder, `None` if it doesn't exist
+    fn stat(&self, url: &str) -> Result<Option<Entry>> {
+        let url = format!("{}?stat", url.trim_end_matches('/'));
+        match self.request("GET", &url).call() {
+            Ok(res) => {
+                let stat: serde_json::Value = res.into_json()?;
+                Ok(Some(Entry {
+                    is_dir: stat["type"] == "dir",
+                    size: stat["size"].as_u64().unwrap_or_default(),
+                    mtime: stat["mtime"].as_u64().unwrap_or_default(),
+                }))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(request_error(err, &url)),
+        }
+    }
+
+    /// The entries under the remote folder
+    fn list(&self, url: &str) -> Result<Tree> {
+        let listing: Listing = self
+            .request("GET", &format!("{url}?json&recursive"))
+            .call()
+            .map_err(|err| request_error(err, url))?
+            .into_json()?;
+        Ok(listing
+            .paths
+            .into_iter()
+            .map(|item| {
+                let is_dir = item.path_type.ends_with("Dir");
+                let entry = Entry {
+                    is_dir,
+                    size: if is_dir {
+                        0
+                    } else {
+                        item.size.unwrap_or_default()
+                    },
+                    mtime: item.mtime,
+                };
+                (item.name, entry)
+            })
+            .collect())
+    }
+
+    fn mkdir(&self, url: &str) -> Result<()> {
+        match self.request("MKCOL", url).call() {
+            Ok(_) | Err(ureq::Error::Status(405, _)) => Ok(()),
+            Err(err) => Err(request_error(err, url)),
+        }
+    }
+
+    fn delete(&self, url: &str) -> Result<()> {
+        self.request("DELETE", url)
+            .call()
+            .map_err(|err| request_error(err, url))?;
+        Ok(())
+    }
+
+    fn upload(&self, path: &Path, url: &str, size: u64) -> Result<()> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open `{}`", path.display()))?;
+        self.request("PUT", url)
+            .set("Content-Length", &size.to_string())
+            .send(self.progress.reader(file))
+            .map_err(|err| request_error(err, url))?;
+        self.progress.file_done();
+        Ok(())
+    }
+
+    /// Uploads what changed in the file against the signature of the copy the remote has.
+    /// Returns false if the server doesn't take deltas or the patched copy didn't come out
+    /// right, for the file to be uploaded whole.
+    fn upload_delta(&self, path: &Path, url: &str, size: u64) -> Result<bool> {
+        let signature = match self.request("GET", &format!("{url}?signature")).call() {
+            Ok(res) => {
+                let mut buf = vec![];
+                res.into_reader().read_to_end(&mut buf)?;
+                buf
+            }
+            Err(ureq::Error::Status(..)) => return Ok(false),
+            Err(err) => return Err(request_error(err, url)),
+        };
+        // Older servers send the file itself
+        let signature = match Signature::deserialize(signature) {
+            Ok(v) => v,
+            Err(_) => return Ok(false),
+        };
+        let tmp_path = std::env::temp_dir().join(format!("dufs-{}.delta", uuid::Uuid::new_v4()));
+        let ret = (|| {
+            let mut file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&tmp_path)?;
+            let hash = delta::diff(&signature, path, &mut io::BufWriter::new(&mut file))
+                .with_context(|| format!("Failed to diff `{}`", path.display()))?;
+            let len = file.stream_position()?;
+            file.rewind()?;
+            self.progress
+                .bytes
+                .fetch_sub(size.saturating_sub(len), Ordering::Relaxed);
+            let digest = format!("sha-256=:{}:", general_purpose::STANDARD.encode(hash));
+            let url = format!("{url}?delta");
+            match self
+                .request("PUT", &url)
+                .set("Content-Length", &len.to_string())
+                .set("Repr-Digest", &digest)
+                .send(self.progress.reader(file))
+            {
+                Ok(_) => Ok(true),
+                Err(ureq::Error::Status(422, _)) => {
+                    // The delta sent counts as done, the file is sent on top of it
+                    self.progress.bytes.fetch_add(size, Ordering::Relaxed);
+                    Ok(false)
+                }
+                Err(err) => Err(request_error(err, &url)),
+            }
+        })();
+        let _ = fs::remove_file(&tmp_path);
+        if ret.as_ref().is_ok_and(|v| *v) {
+            self.progress.file_done();
+        }
+        ret
+    }
+
+    /// Saves the remote file, with the mtime it has there
+    fn download(&self, url: &str, path: &Path, mtime: u64) -> Result<()> {
+        let res = self
+            .request("GET", url)
+            .call()
+            .map_err(|err| request_error(err, url))?;
+        let mut file =
+            File::create(path).with_context(|| format!("Failed to create `{}`", path.display()))?;
+        io::copy(&mut self.progress.reader(res.into_reader()), &mut file)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_millis(mtime))?;
+        self.progress.file_done();
+        Ok(())
+    }
+
+    fn add_total(&self, size: u64) {
+        self.progress.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Runs the transfers `jobs` at a time, going on past failures
+    fn run<T: Send>(&self, jobs: Vec<T>, transfer: impl Fn(T) -> Result<()> + Sync) -> Result<()> {
+        self.progress
+            .files
+            .fetch_add(jobs.len() as u64, Ordering::Relaxed);
+        let queue = Mutex::new(jobs.into_iter());
+        let errors = Mutex::new(vec![]);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            let workers: Vec<_> = (0..self.jobs.max(1))
+                .map(|_| {
+                    s.spawn(|| loop {
+                        let job = queue.lock().unwrap().next();
+                        match job {
+                            Some(job) => {
+                                if let Err(err) = transfer(job) {
+                                    errors.lock().unwrap().push(err);
+                                }
+                            }
+                            None => break,
+                        }
+                    })
+                })
+                .collect();
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    self.progress.draw(false);
+                    std::thread::sleep(PROGRESS_INTERVAL);
+                }
+            });
+            for worker in workers {
+                let _ = worker.join();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        self.progress.draw(true);
+        let errors = errors.into_inner().unwrap();
+        for err in &errors {
+            eprintln!("{err}");
+        }
+        match errors.len() {
+            0 => Ok(()),
+            n => bail!("{n} transfers failed"),
+        }
+    }
+}
+
+/// Bytes and files transferred so far, drawn as a line on stderr
+struct Progress {
+    enabled: bool,
+    files: AtomicU64,
+    files_done: AtomicU64,
+    bytes: AtomicU64,
+    bytes_done: AtomicU64,
+}
+
+impl Progress {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            files: Default::default(),
+            files_done: Default::default(),
+            bytes: Default::default(),
+            bytes_done: Default::default(),
+        }
+    }
+
+    fn reader<R: Read>(&self, inner: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            inner,
+            progress: self,
+        }
+    }
+
+    fn file_done(&self) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn draw(&self, last: bool) {
+        if !self.enabled {
+            return;
+        }
+        let files = self.files.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let bytes_done = self.bytes_done.load(Ordering::Relaxed);
+        let percent = match bytes {
+            0 => 100,
+            _ => bytes_done * 100 / bytes,
+        };
+        eprint!(
+            "\r{}/{files} files, {} of {} ({percent}%)\x1b[K{}",
+            self.files_done.load(Ordering::Relaxed),
+            format_size(bytes_done),
+            format_size(bytes),
+            if last { "\n" } else { "" },
+        );
+    }
+}
+
+struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress
+            .bytes_done
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+fn request_error(err: ureq::Error, url: &str) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(code, res) => {
+            let text = res.status_text().to_string();
+            anyhow!("Failed to request `{url}`, {code} {text}")
+        }
+        err => anyhow!("Failed to request `{url}`, {err}"),
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    value.starts_with("http://") || value.starts_with("https://")
+}
+
+fn dir_url(url: &str) -> String {
+    match url.ends_with('/') {
+        true => url.to_string(),
+        false => format!("{url}/"),
+    }
+}
+
+fn join_url(dir_url: &str, rel: &str) -> String {
+    format!("{dir_url}{}", encode_uri(rel))
+}
+
+/// The decoded last segment of the URL's path, empty for the root
+fn url_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = path.split_once("://").map(|(_, v)| v).unwrap_or(path);
+    let name = match path.trim_end_matches('/').rsplit_once('/') {
+        Some((_, name)) => name,
+        None => "",
+    };
+    decode_uri(name).map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Sizes in B, KiB, MiB and so on, with a decimal past KiB
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{size} {}", UNITS[0]),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
@@ -0,0 +1,136 @@
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder};
+use async_compression::Level;
+use futures::TryStreamExt;
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, VARY,
+};
+use hyper::{Body, HeaderMap, StatusCode};
+use std::io;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::server::Response;
+
+/// Smaller responses aren't worth compressing
+const COMPRESS_MIN_SIZE: u64 = 1024;
+/// Larger responses are mostly big files, compressing them on the fly would hog the CPU
+const COMPRESS_MAX_SIZE: u64 = 16777216; // 16M
+/// Brotli is far too slow for on the fly compression at its default quality
+const BROTLI_QUALITY: u32 = 4;
+
+const COMPRESSIBLE_TYPES: [&str; 5] = [
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+    "text/",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// The encoding the client accepts with the highest weight, brotli winning ties
+    pub fn negotiate(headers: &HeaderMap<HeaderValue>) -> Option<Self> {
+        let accepted: Vec<(String, f32)> = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|item| {
+                let mut parts = item.split(';');
+                let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+                let weight = parts
+                    .find_map(|v| v.trim().strip_prefix("q="))
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(1.0);
+                (name, weight)
+            })
+            .collect();
+        let weight = |names: &[&str]| {
+            accepted
+                .iter()
+                .find(|(name, _)| names.contains(&name.as_str()))
+                .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+                .map(|(_, weight)| *weight)
+                .unwrap_or_default()
+        };
+        let (brotli, gzip) = (weight(&["br"]), weight(&["gzip", "x-gzip"]));
+        if brotli > 0.0 && brotli >= gzip {
+            Some(Self::Brotli)
+        } else if gzip > 0.0 {
+            Some(Self::Gzip)
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+}
+
+/// Compresses the body of the response on the fly if it's text of a reasonable size
+pub fn compress(res: &mut Response, encoding: Option<Encoding>) {
+    if !is_compressible(res) {
+        return;
+    }
+    res.headers_mut()
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let encoding = match encoding {
+        Some(v) => v,
+        None => return,
+    };
+    let body = std::mem::take(res.body_mut());
+    let reader = StreamReader::new(body.map_err(io::Error::other));
+    *res.body_mut() = match encoding {
+        Encoding::Brotli => Body::wrap_stream(ReaderStream::new(BrotliEncoder::with_quality(
+            reader,
+            Level::Precise(BROTLI_QUALITY),
+        ))),
+        Encoding::Gzip => Body::wrap_stream(ReaderStream::new(GzipEncoder::new(reader))),
+    };
+    let headers = res.headers_mut();
+    headers.remove(CONTENT_LENGTH);
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    // The compressed body isn't byte for byte the same as the file anymore
+    if let Some(etag) = headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            if let Ok(etag) = HeaderValue::from_str(&format!("W/{etag}")) {
+                headers.insert(ETAG, etag);
+            }
+        }
+    }
+}
+
+fn is_compressible(res: &Response) -> bool {
+    let headers = res.headers();
+    if !res.status().is_success()
+        || res.status() == StatusCode::PARTIAL_CONTENT
+        || headers.contains_key(CONTENT_ENCODING)
+        || headers.contains_key(CONTENT_RANGE)
+    {
+        return false;
+    }
+    let size: Option<u64> = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    if !size.is_some_and(|v| (COMPRESS_MIN_SIZE..=COMPRESS_MAX_SIZE).contains(&v)) {
+        return false;
+    }
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            let v = v.to_ascii_lowercase();
+            COMPRESSIBLE_TYPES.iter().any(|t| v.starts_with(t))
+        })
+        .unwrap_or_default()
+}
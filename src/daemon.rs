@@ -0,0 +1,106 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// Detaches dufs from the terminal, by forking twice with a new session in between.
+///
+/// Has to be called before any thread is started. The process started from the terminal
+/// waits for the daemon to be ready, so it exits with an error when the start up fails.
+pub fn daemonize() -> Result<Daemon> {
+    let (mut reader, writer) = UnixStream::pair().context("Failed to create the daemon pipe")?;
+    if fork()? != 0 {
+        drop(writer);
+        let mut buf = [0; 1];
+        let code = match reader.read(&mut buf) {
+            Ok(1) => 0,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+    drop(reader);
+    if unsafe { libc::setsid() } == -1 {
+        return Err(std::io::Error::last_os_error()).context("Failed to start a new session");
+    }
+    // The session leader exits, so the daemon can't get a controlling terminal again
+    if fork()? != 0 {
+        unsafe { libc::_exit(0) };
+    }
+    Ok(Daemon { ready: writer })
+}
+
+fn fork() -> Result<libc::pid_t> {
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()).context("Failed to fork"),
+        pid => Ok(pid),
+    }
+}
+
+pub struct Daemon {
+    ready: UnixStream,
+}
+
+impl Daemon {
+    /// Lets the process started from the terminal exit, and lets go of the terminal.
+    /// Output that is redirected somewhere else than a terminal is kept.
+    pub fn ready(mut self) -> Result<()> {
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        let mut fds = vec![libc::STDIN_FILENO];
+        if std::io::stdout().is_terminal() {
+            fds.push(libc::STDOUT_FILENO);
+        }
+        if std::io::stderr().is_terminal() {
+            fds.push(libc::STDERR_FILENO);
+        }
+        for fd in fds {
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to detach from the terminal");
+            }
+        }
+        self.ready.write_all(&[1])?;
+        Ok(())
+    }
+}
+
+/// Holds the process ID in the file while dufs runs
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(pid) = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|v| v.trim().parse::<libc::pid_t>().ok())
+        {
+            let alive = pid > 0
+                && (unsafe { libc::kill(pid, 0) } == 0
+                    || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM));
+            if alive {
+                bail!(
+                    "dufs is already running with pid {pid}, as in `{}`",
+                    path.display()
+                );
+            }
+        }
+        let mut file = File::create(path)
+            .with_context(|| format!("Failed to create pid file `{}`", path.display()))?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Goes away with the process, unless dufs no longer can, after a chroot or user switch
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,198 @@
+//! The rsync algorithm, in the formats of librsync: a signature of the blocks of a file,
+//! then a delta of the new file against it, made of the blocks to copy and the bytes to add.
+
+use anyhow::{bail, Result};
+use fast_rsync::{Signature, SignatureOptions};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, SeekFrom, Write};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+const DELTA_MAGIC: u32 = 0x72730236;
+const OP_END: u8 = 0;
+const OP_LITERAL_64: u8 = 0x40;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_LITERAL_N8: u8 = 0x44;
+const OP_COPY_N1_N1: u8 = 0x45;
+const OP_COPY_N8_N8: u8 = 0x54;
+/// The bytes of the magic, the block size and the hash size ahead of the blocks of a signature
+const SIGNATURE_HEADER_LEN: usize = 12;
+/// MD4 hashes are kept whole, a block mistaken for another would corrupt the file
+const STRONG_HASH_LEN: u32 = 16;
+/// Blocks hashed at once, so a large file isn't read into memory
+const SIGNATURE_BATCH: usize = 256;
+const BUF_SIZE: usize = 65536;
+/// Bytes of a file diffed at once, blocks that straddle two of them aren't matched
+const DIFF_CHUNK: u64 = 64 << 20;
+
+pub const MIN_BLOCK_SIZE: u32 = 256;
+pub const MAX_BLOCK_SIZE: u32 = 1 << 20;
+
+/// The block size for a file of `len` bytes, about its square root as librsync picks it, so
+/// the signature grows slower than the file
+pub fn block_size(len: u64) -> u32 {
+    (len.isqrt() as u32)
+        .next_power_of_two()
+        .clamp(2048, MAX_BLOCK_SIZE)
+}
+
+/// The MD4 signature of the file
+pub fn signature(path: &Path, block_size: u32) -> Result<Vec<u8>> {
+    let options = SignatureOptions {
+        block_size,
+        crypto_hash_size: STRONG_HASH_LEN,
+    };
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; block_size as usize * SIGNATURE_BATCH];
+    let mut output = Signature::calculate(&[], options).into_serialized();
+    loop {
+        let mut len = 0;
+        while len < buf.len() {
+            match file.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if len == 0 {
+            break;
+        }
+        // Batches hold whole blocks, so their signatures add up to the one of the file
+        let batch = Signature::calculate(&buf[..len], options);
+        output.extend_from_slice(&batch.serialized()[SIGNATURE_HEADER_LEN..]);
+        if len < buf.len() {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// Writes the delta of the file against the signature of the other copy, a chunk at a time
+/// so the file isn't read into memory whole. Returns the SHA-256 of the file, to check the
+/// patched copy against since MD4 blocks can be forged.
+pub fn diff<W: Write>(signature: &Signature, path: &Path, out: &mut W) -> Result<[u8; 32]> {
+    let signature = signature.index();
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![];
+    let mut chunk_delta = vec![];
+    out.write_all(&DELTA_MAGIC.to_be_bytes())?;
+    loop {
+        buf.clear();
+        (&mut file).take(DIFF_CHUNK).read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+        hasher.update(&buf);
+        chunk_delta.clear();
+        fast_rsync::diff(&signature, &buf, &mut chunk_delta)?;
+        // The deltas of the chunks, without their magic and end, add up to the one of the file
+        out.write_all(&chunk_delta[4..chunk_delta.len() - 1])?;
+    }
+    out.write_all(&[OP_END])?;
+    out.flush()?;
+    Ok(hasher.finalize().into())
+}
+
+/// A delta that can't be applied, for being malformed or copying past the base file
+#[derive(Debug)]
+pub struct InvalidDelta(String);
+
+impl std::fmt::Display for InvalidDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid delta, {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDelta {}
+
+/// Writes the base file patched by the delta, as the delta comes in, returning the bytes
+/// written. Fails with `TooLarge` once they are more than `limit`.
+pub async fn apply<B, D, W>(
+    base: &mut B,
+    delta: &mut D,
+    out: &mut W,
+    limit: Option<u64>,
+) -> Result<u64>
+where
+    B: AsyncRead + AsyncSeek + Unpin,
+    D: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let base_len = base.seek(SeekFrom::End(0)).await?;
+    if read_int(delta, 4).await? != DELTA_MAGIC as u64 {
+        bail!(InvalidDelta("not a librsync delta".into()));
+    }
+    let mut buf = vec![0; BUF_SIZE];
+    let mut written: u64 = 0;
+    loop {
+        let op = read_int(delta, 1).await? as u8;
+        let (is_copy, len) = match op {
+            OP_END => break,
+            1..=OP_LITERAL_64 => (false, op as u64),
+            OP_LITERAL_N1..=OP_LITERAL_N8 => {
+                (false, read_int(delta, 1 << (op - OP_LITERAL_N1)).await?)
+            }
+            OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+                let mode = op - OP_COPY_N1_N1;
+                let offset = read_int(delta, 1 << (mode / 4)).await?;
+                let len = read_int(delta, 1 << (mode % 4)).await?;
+                if offset.checked_add(len).is_none_or(|end| end > base_len) {
+                    bail!(InvalidDelta(format!(
+                        "copies {len} bytes at {offset}, past the {base_len} bytes of the file"
+                    )));
+                }
+                base.seek(SeekFrom::Start(offset)).await?;
+                (true, len)
+            }
+            _ => bail!(InvalidDelta(format!("unknown command {op:#x}"))),
+        };
+        written = written.saturating_add(len);
+        if limit.is_some_and(|v| written > v) {
+            bail!(TooLarge);
+        }
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(buf.len() as u64) as usize;
+            match is_copy {
+                true => base.read_exact(&mut buf[..n]).await.map(|_| ())?,
+                false => read_exact(delta, &mut buf[..n]).await?,
+            }
+            out.write_all(&buf[..n]).await?;
+            left -= n as u64;
+        }
+    }
+    if delta.read(&mut buf[..1]).await? > 0 {
+        bail!(InvalidDelta("data past its end".into()));
+    }
+    out.flush().await?;
+    Ok(written)
+}
+
+/// The patched file would be larger than the upload limit
+#[derive(Debug)]
+pub struct TooLarge;
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Payload Too Large")
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+async fn read_int<R: AsyncRead + Unpin>(reader: &mut R, len: usize) -> Result<u64> {
+    let mut buf = [0; 8];
+    read_exact(reader, &mut buf[8 - len..]).await?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+async fn read_exact<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    match reader.read_exact(buf).await {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+            bail!(InvalidDelta("it ends early".into()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
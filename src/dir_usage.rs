@@ -0,0 +1,94 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::server::walk_visible;
+
+/// Folders are walked again after this long, to catch changes made outside the server
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// How much the files under a folder add up to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub size: u64,
+    pub files: u64,
+    pub dirs: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.size += other.size;
+        self.files += other.files;
+        self.dirs += other.dirs;
+    }
+}
+
+/// The recursive sizes of folders, walked in the background and kept for a while.
+///
+/// Hidden entries are left out, like from listings. Writes through the server clear
+/// the cache.
+pub struct DirUsage {
+    hidden: Vec<String>,
+    posix_hidden: bool,
+    running: Arc<AtomicBool>,
+    cache: Mutex<HashMap<PathBuf, (Usage, Instant)>>,
+}
+
+impl DirUsage {
+    pub fn new(hidden: Vec<String>, posix_hidden: bool, running: Arc<AtomicBool>) -> Self {
+        Self {
+            hidden,
+            posix_hidden,
+            running,
+            cache: Default::default(),
+        }
+    }
+
+    /// The usage of the folders, added up
+    pub async fn usage(&self, dirs: &[PathBuf]) -> Result<Usage> {
+        let mut total = Usage::default();
+        for dir in dirs {
+            total.add(&self.dir_usage(dir.clone()).await?);
+        }
+        Ok(total)
+    }
+
+    async fn dir_usage(&self, dir: PathBuf) -> Result<Usage> {
+        if let Some((usage, walked_at)) = self.cache.lock().unwrap().get(&dir) {
+            if walked_at.elapsed() < CACHE_TTL {
+                return Ok(*usage);
+            }
+        }
+        let hidden = self.hidden.clone();
+        let posix_hidden = self.posix_hidden;
+        let running = self.running.clone();
+        let root = dir.clone();
+        let usage = tokio::task::spawn_blocking(move || {
+            let mut usage = Usage::default();
+            walk_visible(&[root], &hidden, posix_hidden, &running, |path, is_dir| {
+                if is_dir {
+                    usage.dirs += 1;
+                } else if let Ok(meta) = std::fs::symlink_metadata(path) {
+                    usage.files += 1;
+                    usage.size += meta.len();
+                }
+                true
+            });
+            usage
+        })
+        .await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(dir, (usage, Instant::now()));
+        Ok(usage)
+    }
+
+    pub fn invalidate(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
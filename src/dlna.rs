@@ -0,0 +1,464 @@
+use anyhow::Result;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::args::{Args, BindAddr};
+
+const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// Seconds the players may remember the server for
+const MAX_AGE: u64 = 1800;
+/// The server is announced again well before the players forget it
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(MAX_AGE / 2);
+
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+pub const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+pub const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+
+pub const CONTENT_DIRECTORY_SCPD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+  <specVersion><major>1</major><minor>0</minor></specVersion>
+  <actionList>
+    <action>
+      <name>Browse</name>
+      <argumentList>
+        <argument><name>ObjectID</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_ObjectID</relatedStateVariable></argument>
+        <argument><name>BrowseFlag</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_BrowseFlag</relatedStateVariable></argument>
+        <argument><name>Filter</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Filter</relatedStateVariable></argument>
+        <argument><name>StartingIndex</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Index</relatedStateVariable></argument>
+        <argument><name>RequestedCount</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>
+        <argument><name>SortCriteria</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_SortCriteria</relatedStateVariable></argument>
+        <argument><name>Result</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Result</relatedStateVariable></argument>
+        <argument><name>NumberReturned</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>
+        <argument><name>TotalMatches</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>
+        <argument><name>UpdateID</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_UpdateID</relatedStateVariable></argument>
+      </argumentList>
+    </action>
+    <action>
+      <name>GetSearchCapabilities</name>
+      <argumentList>
+        <argument><name>SearchCaps</name><direction>out</direction><relatedStateVariable>SearchCapabilities</relatedStateVariable></argument>
+      </argumentList>
+    </action>
+    <action>
+      <name>GetSortCapabilities</name>
+      <argumentList>
+        <argument><name>SortCaps</name><direction>out</direction><relatedStateVariable>SortCapabilities</relatedStateVariable></argument>
+      </argumentList>
+    </action>
+    <action>
+      <name>GetSystemUpdateID</name>
+      <argumentList>
+        <argument><name>Id</name><direction>out</direction><relatedStateVariable>SystemUpdateID</relatedStateVariable></argument>
+      </argumentList>
+    </action>
+  </actionList>
+  <serviceStateTable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_ObjectID</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_Result</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="no">
+      <name>A_ARG_TYPE_BrowseFlag</name><dataType>string</dataType>
+      <allowedValueList><allowedValue>BrowseMetadata</allowedValue><allowedValue>BrowseDirectChildren</allowedValue></allowedValueList>
+    </stateVariable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_Filter</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_SortCriteria</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_Index</name><dataType>ui4</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_Count</name><dataType>ui4</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>A_ARG_TYPE_UpdateID</name><dataType>ui4</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>SearchCapabilities</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="no"><name>SortCapabilities</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="yes"><name>SystemUpdateID</name><dataType>ui4</dataType></stateVariable>
+  </serviceStateTable>
+</scpd>
+"#;
+
+pub const CONNECTION_MANAGER_SCPD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+  <specVersion><major>1</major><minor>0</minor></specVersion>
+  <actionList>
+    <action>
+      <name>GetProtocolInfo</name>
+      <argumentList>
+        <argument><name>Source</name><direction>out</direction><relatedStateVariable>SourceProtocolInfo</relatedStateVariable></argument>
+        <argument><name>Sink</name><direction>out</direction><relatedStateVariable>SinkProtocolInfo</relatedStateVariable></argument>
+      </argumentList>
+    </action>
+    <action>
+      <name>GetCurrentConnectionIDs</name>
+      <argumentList>
+        <argument><name>ConnectionIDs</name><direction>out</direction><relatedStateVariable>CurrentConnectionIDs</relatedStateVariable></argument>
+      </argumentList>
+    </action>
+  </actionList>
+  <serviceStateTable>
+    <stateVariable sendEvents="yes"><name>SourceProtocolInfo</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="yes"><name>SinkProtocolInfo</name><dataType>string</dataType></stateVariable>
+    <stateVariable sendEvents="yes"><name>CurrentConnectionIDs</name><dataType>string</dataType></stateVariable>
+  </serviceStateTable>
+</scpd>
+"#;
+
+/// The id of the device, which stays the same across restarts so players don't list the
+/// server twice
+pub fn device_uuid(args: &Args) -> Uuid {
+    let seed = format!("{}:{}", served_path(args).display(), args.port);
+    uuid::Builder::from_md5_bytes(md5::compute(seed).0).into_uuid()
+}
+
+/// The serve path as the players know it, from outside any chroot
+fn served_path(args: &Args) -> &Path {
+    args.chrooted_from.as_deref().unwrap_or(&args.serve_path)
+}
+
+/// The UPnP device description of the server, which points the players to its services
+pub fn description(args: &Args) -> String {
+    let name = served_path(args)
+        .file_name()
+        .and_then(|v| v.to_str())
+        .unwrap_or("/");
+    let prefix = format!("{}dlna/", args.assets_prefix());
+    let service = |kind: &str, name: &str| {
+        format!(
+            r#"      <service>
+        <serviceType>{kind}</serviceType>
+        <serviceId>urn:upnp-org:serviceId:{name}</serviceId>
+        <SCPDURL>{prefix}{name}.xml</SCPDURL>
+        <controlURL>{prefix}control/{name}</controlURL>
+        <eventSubURL>{prefix}event/{name}</eventSubURL>
+      </service>
+"#
+        )
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <specVersion><major>1</major><minor>0</minor></specVersion>
+  <device>
+    <deviceType>{DEVICE_TYPE}</deviceType>
+    <friendlyName>{}</friendlyName>
+    <manufacturer>dufs</manufacturer>
+    <modelName>dufs</modelName>
+    <modelNumber>{}</modelNumber>
+    <UDN>uuid:{}</UDN>
+    <serviceList>
+{}{}    </serviceList>
+  </device>
+</root>
+"#,
+        escape_str_pcdata(&format!("{}: {name}", args.site_name)),
+        env!("CARGO_PKG_VERSION"),
+        device_uuid(args),
+        service(CONTENT_DIRECTORY, "ContentDirectory"),
+        service(CONNECTION_MANAGER, "ConnectionManager"),
+    )
+}
+
+/// The arguments of a SOAP action, by name
+pub fn parse_action(body: &[u8]) -> Result<HashMap<String, String>> {
+    let mut args = HashMap::new();
+    let mut depth = 0;
+    let mut name = None;
+    let mut text = String::new();
+    // Envelope > Body > action > arguments
+    for event in EventReader::new(body) {
+        match event? {
+            XmlEvent::StartElement { name: v, .. } => {
+                depth += 1;
+                if depth == 4 {
+                    name = Some(v.local_name);
+                    text.clear();
+                }
+            }
+            XmlEvent::Characters(v) | XmlEvent::CData(v) if name.is_some() => {
+                text.push_str(&v);
+            }
+            XmlEvent::EndElement { .. } => {
+                if let Some(name) = name.take() {
+                    args.insert(name, std::mem::take(&mut text));
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(args)
+}
+
+pub fn soap_response(service: &str, action: &str, args: &[(&str, String)]) -> String {
+    let args: String = args
+        .iter()
+        .map(|(name, value)| format!("<{name}>{}</{name}>", escape_str_pcdata(value)))
+        .collect();
+    soap_envelope(&format!(
+        r#"<u:{action}Response xmlns:u="{service}">{args}</u:{action}Response>"#
+    ))
+}
+
+/// The error the players get for an action, with the UPnP error code
+pub fn soap_fault(code: u16, description: &str) -> String {
+    soap_envelope(&format!(
+        r#"<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{code}</errorCode><errorDescription>{description}</errorDescription></UPnPError></detail></s:Fault>"#
+    ))
+}
+
+fn soap_envelope(body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body>{body}</s:Body></s:Envelope>"#
+    )
+}
+
+pub fn didl(objects: &str) -> String {
+    format!(
+        r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">{objects}</DIDL-Lite>"#
+    )
+}
+
+pub fn didl_container(id: &str, title: &str) -> String {
+    format!(
+        r#"<container id="{}" parentID="{}" restricted="1"><dc:title>{}</dc:title><upnp:class>object.container.storageFolder</upnp:class></container>"#,
+        escape_str_attribute(id),
+        escape_str_attribute(parent_id(id)),
+        escape_str_pcdata(title)
+    )
+}
+
+/// An image, a video or an audio, which the player gets at the url
+pub fn didl_item(id: &str, title: &str, media_type: &str, url: &str, size: Option<u64>) -> String {
+    let class = match media_type {
+        "image" => "object.item.imageItem.photo",
+        "video" => "object.item.videoItem",
+        _ => "object.item.audioItem.musicTrack",
+    };
+    let mime = mime_guess::from_path(title).first_or_octet_stream();
+    let size = size.map(|v| format!(r#" size="{v}""#)).unwrap_or_default();
+    format!(
+        r#"<item id="{}" parentID="{}" restricted="1"><dc:title>{}</dc:title><upnp:class>{class}</upnp:class><res protocolInfo="http-get:*:{mime}:*"{size}>{}</res></item>"#,
+        escape_str_attribute(id),
+        escape_str_attribute(parent_id(id)),
+        escape_str_pcdata(title),
+        escape_str_pcdata(url)
+    )
+}
+
+/// Objects are identified by their path from the root, the root itself being `0`
+fn parent_id(id: &str) -> &str {
+    match id {
+        "0" => "-1",
+        _ => id.rsplit_once('/').map(|(v, _)| v).unwrap_or("0"),
+    }
+}
+
+/// Binds the SSDP port and joins its multicast group on the interfaces the server
+/// listens on
+pub fn bind_ssdp(args: &Args) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    let any = args
+        .addrs
+        .iter()
+        .any(|v| matches!(v, BindAddr::Address(IpAddr::V4(ip)) if ip.is_unspecified()));
+    let interfaces: Vec<Ipv4Addr> = if any {
+        if_addrs::get_if_addrs()?
+            .into_iter()
+            .filter_map(|v| match v.ip() {
+                IpAddr::V4(ip) if !ip.is_loopback() => Some(ip),
+                _ => None,
+            })
+            .collect()
+    } else {
+        args.addrs
+            .iter()
+            .filter_map(|v| match v {
+                BindAddr::Address(IpAddr::V4(ip)) if !ip.is_loopback() => Some(*ip),
+                _ => None,
+            })
+            .collect()
+    };
+    for interface in interfaces {
+        if let Err(err) = socket.join_multicast_v4(&SSDP_ADDR, &interface) {
+            warn!("Failed to join SSDP multicast on {interface}, {err}");
+        }
+    }
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Answers the searches of the players and announces the server to them every so often
+pub async fn advertise(args: Arc<Args>, socket: UdpSocket) {
+    let uuid = device_uuid(&args);
+    let group = SocketAddr::from((SSDP_ADDR, SSDP_PORT));
+    let mut interval = tokio::time::interval(NOTIFY_INTERVAL);
+    let mut buf = [0; 2048];
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let location = match location(&args, group) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                for target in targets(&uuid) {
+                    let message = notify_message(&location, &uuid, &target);
+                    if let Err(err) = socket.send_to(message.as_bytes(), group).await {
+                        warn!("Failed to announce DLNA server, {err}");
+                        break;
+                    }
+                }
+            }
+            ret = socket.recv_from(&mut buf) => {
+                let (len, peer) = match ret {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                let search = match search_target(&buf[..len]) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let location = match location(&args, peer) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                for target in targets(&uuid).into_iter().filter(|v| search == "ssdp:all" || *v == search) {
+                    let message = search_response(&location, &uuid, &target);
+                    let _ = socket.send_to(message.as_bytes(), peer).await;
+                }
+            }
+        }
+    }
+}
+
+/// What the server is announced as: a root device, itself, a media server and its
+/// services
+fn targets(uuid: &Uuid) -> Vec<String> {
+    vec![
+        "upnp:rootdevice".to_string(),
+        format!("uuid:{uuid}"),
+        DEVICE_TYPE.to_string(),
+        CONTENT_DIRECTORY.to_string(),
+        CONNECTION_MANAGER.to_string(),
+    ]
+}
+
+fn usn(uuid: &Uuid, target: &str) -> String {
+    if target.starts_with("uuid:") {
+        target.to_string()
+    } else {
+        format!("uuid:{uuid}::{target}")
+    }
+}
+
+fn notify_message(location: &str, uuid: &Uuid, target: &str) -> String {
+    format!(
+        "NOTIFY * HTTP/1.1\r\nHOST: {SSDP_ADDR}:{SSDP_PORT}\r\nCACHE-CONTROL: max-age={MAX_AGE}\r\nLOCATION: {location}\r\nNT: {target}\r\nNTS: ssdp:alive\r\nSERVER: {}\r\nUSN: {}\r\n\r\n",
+        server_header(),
+        usn(uuid, target)
+    )
+}
+
+fn search_response(location: &str, uuid: &Uuid, target: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age={MAX_AGE}\r\nEXT:\r\nLOCATION: {location}\r\nSERVER: {}\r\nST: {target}\r\nUSN: {}\r\n\r\n",
+        server_header(),
+        usn(uuid, target)
+    )
+}
+
+fn server_header() -> String {
+    format!(
+        "{}/1.0 UPnP/1.0 dufs/{}",
+        std::env::consts::OS,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// The search target of an SSDP `M-SEARCH` request
+fn search_target(message: &[u8]) -> Option<String> {
+    let message = std::str::from_utf8(message).ok()?;
+    let mut lines = message.lines();
+    if !lines.next()?.starts_with("M-SEARCH ") {
+        return None;
+    }
+    lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("st")
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// The URL of the device description on the address the peer reaches this host by, if
+/// the server listens there
+fn location(args: &Args, peer: SocketAddr) -> Option<String> {
+    let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    let served = args.addrs.iter().any(|v| match v {
+        BindAddr::Address(v) => *v == ip || (v.is_ipv4() && v.is_unspecified()),
+        BindAddr::Path(_) => false,
+    });
+    if !served {
+        return None;
+    }
+    let scheme = match args.tls() {
+        true => "https",
+        false => "http",
+    };
+    Some(format!(
+        "{scheme}://{ip}:{}{}dlna/description.xml",
+        args.port,
+        args.assets_prefix()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_target() {
+        let message = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nst: urn:schemas-upnp-org:device:MediaServer:1\r\n\r\n";
+        assert_eq!(
+            search_target(message).as_deref(),
+            Some("urn:schemas-upnp-org:device:MediaServer:1")
+        );
+        let message = b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\n\r\n";
+        assert_eq!(search_target(message), None);
+    }
+
+    #[test]
+    fn test_parse_action() {
+        let body = br#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+  <s:Body>
+    <u:Browse xmlns:u="urn:schemas-upnp-org:service:ContentDirectory:1">
+      <ObjectID>dir1/a &amp; b</ObjectID>
+      <BrowseFlag>BrowseDirectChildren</BrowseFlag>
+      <Filter>*</Filter>
+      <StartingIndex>0</StartingIndex>
+      <RequestedCount>10</RequestedCount>
+      <SortCriteria></SortCriteria>
+    </u:Browse>
+  </s:Body>
+</s:Envelope>"#;
+        let args = parse_action(body).unwrap();
+        assert_eq!(args["ObjectID"], "dir1/a & b");
+        assert_eq!(args["RequestedCount"], "10");
+        assert_eq!(args["SortCriteria"], "");
+    }
+
+    #[test]
+    fn test_parent_id() {
+        assert_eq!(parent_id("0"), "-1");
+        assert_eq!(parent_id("dir1"), "0");
+        assert_eq!(parent_id("dir1/sub/a.mp4"), "dir1/sub");
+    }
+}
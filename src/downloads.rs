@@ -0,0 +1,148 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// How long a download id may be, they are picked by the clients
+const MAX_ID_LEN: usize = 64;
+
+/// How far an archive download has come
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Progress {
+    /// Bytes of the files read into the archive so far
+    pub sent: u64,
+    /// Bytes of the files in all, as they were sized up before zipping
+    pub total: u64,
+    pub done: bool,
+}
+
+/// The progress of the archive downloads under way, by their download id.
+///
+/// The web UI picks the id and follows it before the download has started, so either side
+/// may come first, and the entry goes once neither is left.
+#[derive(Default)]
+pub struct Downloads {
+    inner: Mutex<HashMap<String, Arc<watch::Sender<Progress>>>>,
+}
+
+impl Downloads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts reporting the progress of the download, until the tracker is dropped
+    pub fn start(self: &Arc<Self>, id: &str, total: u64) -> Tracker {
+        let tx = self.sender(id);
+        tx.send_replace(Progress {
+            sent: 0,
+            total,
+            done: false,
+        });
+        Tracker {
+            downloads: self.clone(),
+            id: id.to_string(),
+            tx,
+            sent: 0,
+        }
+    }
+
+    /// Follows the download, whether or not it has started
+    pub fn watch(&self, id: &str) -> watch::Receiver<Progress> {
+        self.sender(id).subscribe()
+    }
+
+    /// Lets go of the entry if nobody follows or reports the download anymore
+    pub fn release(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(tx) = inner.get(id) {
+            if Arc::strong_count(tx) == 1 && tx.receiver_count() == 0 {
+                inner.remove(id);
+            }
+        }
+    }
+
+    fn sender(&self, id: &str) -> Arc<watch::Sender<Progress>> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_insert_with(|| Arc::new(watch::channel(Progress::default()).0))
+            .clone()
+    }
+}
+
+/// Reports the bytes read into an archive, and that it is done once dropped
+pub struct Tracker {
+    downloads: Arc<Downloads>,
+    id: String,
+    tx: Arc<watch::Sender<Progress>>,
+    sent: u64,
+}
+
+impl Tracker {
+    pub fn add(&mut self, len: u64) {
+        self.sent += len;
+        let sent = self.sent;
+        self.tx.send_modify(|v| v.sent = sent);
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.tx.send_modify(|v| v.done = true);
+        let mut inner = self.downloads.inner.lock().unwrap();
+        // A later download may have taken the id over
+        if inner
+            .get(&self.id)
+            .is_some_and(|v| Arc::ptr_eq(v, &self.tx))
+        {
+            inner.remove(&self.id);
+        }
+    }
+}
+
+/// Whether a client may pick this as a download id
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downloads() {
+        let downloads = Arc::new(Downloads::new());
+        let rx = downloads.watch("abc");
+        let mut tracker = downloads.start("abc", 10);
+        tracker.add(4);
+        assert_eq!(
+            *rx.borrow(),
+            Progress {
+                sent: 4,
+                total: 10,
+                done: false
+            }
+        );
+        drop(tracker);
+        assert!(rx.borrow().done);
+        assert!(downloads.inner.lock().unwrap().is_empty());
+
+        let rx = downloads.watch("def");
+        drop(rx);
+        downloads.release("def");
+        assert!(downloads.inner.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_valid_id() {
+        assert!(is_valid_id("3f2a-b_9"));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("../x"));
+        assert!(!is_valid_id(&"a".repeat(65)));
+    }
+}
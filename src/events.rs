@@ -0,0 +1,85 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Events a slow subscriber may fall behind by before it misses some
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Upload,
+    Mkdir,
+    Delete,
+    Move,
+    Copy,
+    /// An upload is under way
+    Progress,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsEvent {
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// From the root, starting with `/`
+    pub path: String,
+    /// Where it was moved or copied to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dest: Option<String>,
+    /// Bytes of the upload received so far
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received: Option<u64>,
+    /// Bytes of the upload in all, if the client told
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+}
+
+impl FsEvent {
+    pub fn new(kind: EventKind, path: String) -> Self {
+        Self {
+            kind,
+            path,
+            dest: None,
+            received: None,
+            total: None,
+        }
+    }
+
+    /// The paths the event is about
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.path.as_str()).chain(self.dest.as_deref())
+    }
+}
+
+/// Changes made through the server, for the web UI to follow them live.
+///
+/// The handlers that change files publish to it, the WebSocket connections subscribe.
+pub struct EventBus {
+    tx: broadcast::Sender<FsEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        Self { tx }
+    }
+
+    pub fn publish(&self, event: FsEvent) {
+        // Nobody listening isn't an error
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FsEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Whether anybody is listening, so events that are costly to make can be skipped
+    pub fn has_subscribers(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+}
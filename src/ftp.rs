@@ -0,0 +1,516 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::fs::{self, File};
+use tokio::io::{
+    self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+    BufReader, SeekFrom,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+#[cfg(feature = "tls")]
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
+use crate::server::{PathItem, Server};
+use crate::utils::resolve_relative;
+
+/// Sessions sending no command for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long a client has to open the data connection it asked for
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_LINE: u64 = 4096;
+/// Listings show the time of the files changed this recently, the year of the others
+const RECENT: Duration = Duration::from_secs(180 * 24 * 3600);
+
+const FEATURES: &str = "211-Features:\r\n EPSV\r\n MDTM\r\n MLST type*;size*;modify*;\r\n PASV\r\n REST STREAM\r\n SIZE\r\n UTF8\r\n";
+#[cfg(feature = "tls")]
+const TLS_FEATURES: &str = " AUTH TLS\r\n PBSZ\r\n PROT\r\n";
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+type Control = BufReader<Box<dyn Io>>;
+type Reply = (u16, String);
+
+/// A read-only FTP frontend to the server, with FTPS when TLS is enabled
+pub struct FtpServer {
+    server: Arc<RwLock<Arc<Server>>>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+}
+
+impl FtpServer {
+    pub fn new(server: Arc<RwLock<Arc<Server>>>) -> Self {
+        Self {
+            server,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some(TlsAcceptor::from(config));
+        self
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let local_ip = match stream.local_addr() {
+                Ok(v) => v.ip(),
+                Err(_) => continue,
+            };
+            let session = Session {
+                ftp: self.clone(),
+                peer_ip: peer.ip(),
+                local_ip,
+                user_name: None,
+                user: None,
+                logged_in: false,
+                cwd: String::new(),
+                data: None,
+                rest: 0,
+                protect: false,
+                secure: false,
+            };
+            tokio::spawn(async move {
+                if let Err(err) = session.run(Box::new(stream)).await {
+                    debug!("FTP session of {peer} ended, {err}");
+                }
+            });
+        }
+    }
+}
+
+/// Where the next transfer goes
+enum DataChannel {
+    /// The client connects to the listener, after `PASV` or `EPSV`
+    Passive(TcpListener),
+    /// The server connects to the client, after `PORT` or `EPRT`
+    Active(SocketAddr),
+}
+
+struct Session {
+    ftp: Arc<FtpServer>,
+    peer_ip: IpAddr,
+    local_ip: IpAddr,
+    /// Given by `USER`, awaiting `PASS`
+    user_name: Option<String>,
+    /// `None` for anonymous users
+    user: Option<String>,
+    logged_in: bool,
+    /// The current folder, relative to the root
+    cwd: String,
+    data: Option<DataChannel>,
+    /// Where the next `RETR` starts
+    rest: u64,
+    /// Whether the data connections are encrypted, with `PROT P`
+    protect: bool,
+    /// Whether the control connection is encrypted, after `AUTH TLS`
+    secure: bool,
+}
+
+impl Session {
+    async fn run(mut self, stream: Box<dyn Io>) -> Result<()> {
+        let mut control: Control = BufReader::new(stream);
+        reply(&mut control, (220, "Dufs ready".to_string())).await?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let mut reader = (&mut control).take(MAX_LINE);
+            if timeout(IDLE_TIMEOUT, reader.read_line(&mut line)).await?? == 0 {
+                return Ok(());
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+            let command = command.to_ascii_uppercase();
+            match command.as_str() {
+                "QUIT" => {
+                    reply(&mut control, (221, "Bye".to_string())).await?;
+                    return Ok(());
+                }
+                #[cfg(feature = "tls")]
+                "AUTH" if arg.eq_ignore_ascii_case("TLS") || arg.eq_ignore_ascii_case("SSL") => {
+                    let acceptor = match &self.ftp.tls {
+                        Some(v) if !self.secure => v.clone(),
+                        _ => {
+                            reply(&mut control, (534, "TLS not available".to_string())).await?;
+                            continue;
+                        }
+                    };
+                    reply(&mut control, (234, "Proceed with TLS".to_string())).await?;
+                    let stream = acceptor.accept(control.into_inner()).await?;
+                    control = BufReader::new(Box::new(stream));
+                    self.secure = true;
+                }
+                _ => {
+                    let ret = self.command(&command, arg, &mut control).await?;
+                    reply(&mut control, ret).await?;
+                }
+            }
+        }
+    }
+
+    fn server(&self) -> Arc<Server> {
+        self.ftp.server.read().unwrap().clone()
+    }
+
+    async fn command(&mut self, command: &str, arg: &str, control: &mut Control) -> Result<Reply> {
+        let ret = match command {
+            "USER" => {
+                self.user_name = Some(arg.to_string());
+                self.logged_in = false;
+                (331, "Password required".to_string())
+            }
+            "PASS" => {
+                let user = match self.user_name.take() {
+                    Some(v) if v == "anonymous" || v == "ftp" => None,
+                    Some(v) => Some(v),
+                    None => return Ok((503, "Login with USER first".to_string())),
+                };
+                if self
+                    .server()
+                    .login(user.as_deref(), arg, Some(self.peer_ip))
+                    .await
+                {
+                    self.user = user;
+                    self.logged_in = true;
+                    (230, "Logged in".to_string())
+                } else {
+                    (530, "Login incorrect".to_string())
+                }
+            }
+            "SYST" => (215, "UNIX Type: L8".to_string()),
+            "FEAT" => {
+                control.get_mut().write_all(FEATURES.as_bytes()).await?;
+                #[cfg(feature = "tls")]
+                if self.ftp.tls.is_some() {
+                    control.get_mut().write_all(TLS_FEATURES.as_bytes()).await?;
+                }
+                (211, "End".to_string())
+            }
+            "OPTS" if arg.eq_ignore_ascii_case("UTF8 ON") => {
+                (200, "Always in UTF8 mode".to_string())
+            }
+            "NOOP" => (200, "OK".to_string()),
+            "PBSZ" => (200, "PBSZ=0".to_string()),
+            "PROT" => match arg.to_ascii_uppercase().as_str() {
+                "C" => {
+                    self.protect = false;
+                    (200, "Protection level set to Clear".to_string())
+                }
+                "P" if self.secure => {
+                    self.protect = true;
+                    (200, "Protection level set to Private".to_string())
+                }
+                _ => (536, "Protection level not supported".to_string()),
+            },
+            _ if !self.logged_in => (530, "Please login with USER and PASS".to_string()),
+            "PWD" | "XPWD" => (
+                257,
+                format!(
+                    "\"/{}\" is the current directory",
+                    self.cwd.replace('"', "\"\"")
+                ),
+            ),
+            "CWD" | "XCWD" => self.change_dir(arg).await,
+            "CDUP" | "XCUP" => self.change_dir("..").await,
+            "TYPE" => match arg.to_ascii_uppercase().as_str() {
+                "A" | "A N" | "I" | "L 8" => (200, format!("Type set to {arg}")),
+                _ => (504, "Type not supported".to_string()),
+            },
+            "MODE" if arg.eq_ignore_ascii_case("S") => (200, "Mode set to S".to_string()),
+            "STRU" if arg.eq_ignore_ascii_case("F") => (200, "Structure set to F".to_string()),
+            "PASV" => match self.local_ip {
+                IpAddr::V4(ip) => {
+                    let port = self.listen().await?;
+                    let [a, b, c, d] = ip.octets();
+                    (
+                        227,
+                        format!(
+                            "Entering Passive Mode ({a},{b},{c},{d},{},{})",
+                            port >> 8,
+                            port & 0xff
+                        ),
+                    )
+                }
+                IpAddr::V6(_) => (522, "Use EPSV over IPv6".to_string()),
+            },
+            "EPSV" => {
+                let port = self.listen().await?;
+                (229, format!("Entering Extended Passive Mode (|||{port}|)"))
+            }
+            "PORT" | "EPRT" => {
+                let addr = match command {
+                    "PORT" => parse_port(arg),
+                    _ => parse_eprt(arg),
+                };
+                match addr {
+                    // Connecting elsewhere would let clients use the server to reach others
+                    Some(addr) if addr.ip() == self.peer_ip => {
+                        self.data = Some(DataChannel::Active(addr));
+                        (200, "Command okay".to_string())
+                    }
+                    _ => (501, "Invalid address".to_string()),
+                }
+            }
+            "REST" => match arg.parse() {
+                Ok(offset) => {
+                    self.rest = offset;
+                    (350, format!("Restarting at {offset}"))
+                }
+                Err(_) => (501, "Invalid offset".to_string()),
+            },
+            "LIST" | "NLST" | "MLSD" => self.list(command, arg, control).await?,
+            "MLST" => {
+                let path = resolve_relative(&self.cwd, arg);
+                match self.stat(&path).await {
+                    Some((is_dir, size, mtime)) => {
+                        let facts = facts(&format!("/{path}"), is_dir, size, mtime);
+                        let text = format!("250-Listing /{path}\r\n {facts}\r\n");
+                        control.get_mut().write_all(text.as_bytes()).await?;
+                        (250, "End".to_string())
+                    }
+                    None => (550, "No such file or directory".to_string()),
+                }
+            }
+            "SIZE" | "MDTM" => {
+                let path = resolve_relative(&self.cwd, arg);
+                match self.stat(&path).await {
+                    Some((false, size, _)) if command == "SIZE" => (213, size.to_string()),
+                    Some((false, _, mtime)) => (213, format_time(mtime, "%Y%m%d%H%M%S")),
+                    _ => (550, "No such file".to_string()),
+                }
+            }
+            "RETR" => self.retrieve(arg, control).await?,
+            "ABOR" => (226, "No transfer to abort".to_string()),
+            "STOR" | "STOU" | "APPE" | "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR"
+            | "RNTO" | "SITE" => (
+                550,
+                "Permission denied, the server is read-only".to_string(),
+            ),
+            _ => (502, "Command not implemented".to_string()),
+        };
+        Ok(ret)
+    }
+
+    async fn change_dir(&mut self, arg: &str) -> Reply {
+        let path = resolve_relative(&self.cwd, arg);
+        match self.server().lookup(&path, self.user.as_deref()).await {
+            Some((_, true, _)) => {
+                self.cwd = path;
+                (250, "Directory changed".to_string())
+            }
+            _ => (550, "No such directory".to_string()),
+        }
+    }
+
+    /// Whether the file or folder is a folder, its size and when it was modified
+    async fn stat(&self, path: &str) -> Option<(bool, u64, SystemTime)> {
+        let (path, is_dir, _) = self.server().lookup(path, self.user.as_deref()).await?;
+        let meta = fs::metadata(path).await.ok()?;
+        Some((is_dir, meta.len(), meta.modified().ok()?))
+    }
+
+    async fn list(&mut self, command: &str, arg: &str, control: &mut Control) -> Result<Reply> {
+        // Clients often pass `ls` options, such as `LIST -la`
+        let arg = match arg.starts_with('-') {
+            true => arg.split_once(' ').map(|(_, v)| v).unwrap_or_default(),
+            false => arg,
+        };
+        let path = resolve_relative(&self.cwd, arg);
+        let server = self.server();
+        let (file_path, is_dir, access_paths) =
+            match server.lookup(&path, self.user.as_deref()).await {
+                Some(v) => v,
+                None => return Ok((550, "No such file or directory".to_string())),
+            };
+        let output = if is_dir {
+            let items = server.entries(&file_path, &access_paths).await?;
+            let line = |item: &PathItem| {
+                let mtime = SystemTime::UNIX_EPOCH + Duration::from_millis(item.mtime);
+                let size = item.size.unwrap_or_default();
+                match command {
+                    "LIST" => list_line(item.base_name(), item.is_dir(), size, mtime),
+                    "MLSD" => facts(item.base_name(), item.is_dir(), size, mtime),
+                    _ => item.base_name().to_string(),
+                }
+            };
+            items.iter().map(|v| format!("{}\r\n", line(v))).collect()
+        } else if command == "MLSD" {
+            return Ok((501, "Not a directory".to_string()));
+        } else {
+            let meta = fs::metadata(&file_path).await?;
+            let name = file_path
+                .file_name()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default();
+            match command {
+                "LIST" => {
+                    let mtime = meta.modified()?;
+                    format!("{}\r\n", list_line(&name, false, meta.len(), mtime))
+                }
+                _ => format!("{name}\r\n"),
+            }
+        };
+        self.transfer(control, output.as_bytes()).await
+    }
+
+    async fn retrieve(&mut self, arg: &str, control: &mut Control) -> Result<Reply> {
+        let offset = std::mem::take(&mut self.rest);
+        let path = resolve_relative(&self.cwd, arg);
+        let file_path = match self.server().lookup(&path, self.user.as_deref()).await {
+            Some((v, false, _)) => v,
+            _ => return Ok((550, "No such file".to_string())),
+        };
+        let mut file = File::open(&file_path).await?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset)).await?;
+        }
+        self.transfer(control, file).await
+    }
+
+    /// Opens a data listener for the client to connect to, on the address the client
+    /// reached the server by
+    async fn listen(&mut self) -> Result<u16> {
+        let listener = TcpListener::bind((self.local_ip, 0)).await?;
+        let port = listener.local_addr()?.port();
+        self.data = Some(DataChannel::Passive(listener));
+        Ok(port)
+    }
+
+    /// Sends the data over the data connection the client asked for
+    async fn transfer(
+        &mut self,
+        control: &mut Control,
+        mut reader: impl AsyncRead + Unpin,
+    ) -> Result<Reply> {
+        let data = match self.data.take() {
+            Some(v) => v,
+            None => return Ok((425, "Use PASV or PORT first".to_string())),
+        };
+        reply(control, (150, "Opening data connection".to_string())).await?;
+        let stream = match data {
+            DataChannel::Passive(listener) => {
+                match timeout(DATA_TIMEOUT, listener.accept()).await {
+                    // Anyone else connecting could be stealing the data
+                    Ok(Ok((stream, peer))) if peer.ip() == self.peer_ip => stream,
+                    _ => return Ok((425, "Can't open data connection".to_string())),
+                }
+            }
+            DataChannel::Active(addr) => {
+                match timeout(DATA_TIMEOUT, TcpStream::connect(addr)).await {
+                    Ok(Ok(stream)) => stream,
+                    _ => return Ok((425, "Can't open data connection".to_string())),
+                }
+            }
+        };
+        let mut stream: Box<dyn Io> = Box::new(stream);
+        #[cfg(feature = "tls")]
+        if self.protect {
+            if let Some(acceptor) = &self.ftp.tls {
+                stream = Box::new(acceptor.accept(stream).await?);
+            }
+        }
+        let ret = match io::copy(&mut reader, &mut stream).await {
+            Ok(_) => stream.shutdown().await,
+            Err(err) => Err(err),
+        };
+        match ret {
+            Ok(()) => Ok((226, "Transfer complete".to_string())),
+            Err(_) => Ok((426, "Connection closed, transfer aborted".to_string())),
+        }
+    }
+}
+
+async fn reply(control: &mut Control, (code, text): Reply) -> Result<()> {
+    let stream = control.get_mut();
+    stream
+        .write_all(format!("{code} {text}\r\n").as_bytes())
+        .await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// A line of `ls -l`
+fn list_line(name: &str, is_dir: bool, size: u64, mtime: SystemTime) -> String {
+    let mode = match is_dir {
+        true => "drwxr-xr-x",
+        false => "-rw-r--r--",
+    };
+    let recent = SystemTime::now()
+        .duration_since(mtime)
+        .is_ok_and(|v| v < RECENT);
+    let time = match recent {
+        true => format_time(mtime, "%b %e %H:%M"),
+        false => format_time(mtime, "%b %e  %Y"),
+    };
+    format!("{mode} 1 dufs dufs {size:>12} {time} {name}")
+}
+
+/// The facts of `MLST` and `MLSD`
+fn facts(name: &str, is_dir: bool, size: u64, mtime: SystemTime) -> String {
+    let kind = match is_dir {
+        true => "dir",
+        false => "file",
+    };
+    let modify = format_time(mtime, "%Y%m%d%H%M%S");
+    format!("type={kind};size={size};modify={modify}; {name}")
+}
+
+fn format_time(time: SystemTime, format: &str) -> String {
+    DateTime::<Utc>::from(time).format(format).to_string()
+}
+
+/// The address of `PORT h1,h2,h3,h4,p1,p2`
+fn parse_port(arg: &str) -> Option<SocketAddr> {
+    let numbers: Vec<u8> = arg
+        .split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [a, b, c, d, p1, p2] => Some(SocketAddr::from((
+            [a, b, c, d],
+            u16::from(p1) << 8 | u16::from(p2),
+        ))),
+        _ => None,
+    }
+}
+
+/// The address of `EPRT |1|h.h.h.h|port|` or `EPRT |2|h::h|port|`
+fn parse_eprt(arg: &str) -> Option<SocketAddr> {
+    let delimiter = arg.chars().next()?;
+    let parts: Vec<&str> = arg.split(delimiter).collect();
+    match parts[..] {
+        ["", "1" | "2", ip, port, ""] => {
+            Some(SocketAddr::new(ip.parse().ok()?, port.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port() {
+        assert_eq!(
+            parse_port("127,0,0,1,4,1"),
+            Some("127.0.0.1:1025".parse().unwrap())
+        );
+        assert_eq!(parse_port("127,0,0,1,4"), None);
+        assert_eq!(
+            parse_eprt("|2|::1|5282|"),
+            Some("[::1]:5282".parse().unwrap())
+        );
+        assert_eq!(parse_eprt("|1|127.0.0.1|x|"), None);
+    }
+}
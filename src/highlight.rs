@@ -0,0 +1,34 @@
+use lazy_static::lazy_static;
+use std::path::Path;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME: Theme = ThemeSet::load_defaults()
+        .themes
+        .remove("InspiredGitHub")
+        .unwrap_or_default();
+}
+
+/// The language of the text file, going by its name, or else its first line such as a shebang
+pub fn detect_language(path: &Path, first_line: &str) -> Option<String> {
+    find_syntax(path, first_line).map(|v| v.name.clone())
+}
+
+/// The text as HTML with inline styles, None if its language isn't known
+pub fn highlight(path: &Path, text: &str) -> Option<String> {
+    let syntax = find_syntax(path, text.lines().next().unwrap_or_default())?;
+    highlighted_html_for_string(text, &SYNTAX_SET, syntax, &THEME).ok()
+}
+
+fn find_syntax(path: &Path, first_line: &str) -> Option<&'static SyntaxReference> {
+    let name = path.file_name()?.to_str()?;
+    let extension = path.extension().and_then(|v| v.to_str()).unwrap_or(name);
+    SYNTAX_SET
+        .find_syntax_by_extension(extension)
+        .or_else(|| SYNTAX_SET.find_syntax_by_extension(name))
+        .or_else(|| SYNTAX_SET.find_syntax_by_first_line(first_line))
+        .filter(|v| v.name != "Plain Text")
+}
@@ -0,0 +1,122 @@
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+use crate::args::Args;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Upload,
+    Delete,
+    Mkdir,
+    Move,
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Upload => "upload",
+            HookEvent::Delete => "delete",
+            HookEvent::Mkdir => "mkdir",
+            HookEvent::Move => "move",
+        }
+    }
+}
+
+/// Commands run in the background after changes made through the server.
+///
+/// The event is described to the command in `DUFS_EVENT_*` environment variables.
+pub struct Hooks {
+    commands: Vec<(HookEvent, String)>,
+    timeout: Duration,
+    permits: Arc<Semaphore>,
+}
+
+impl Hooks {
+    pub fn new(args: &Args) -> Self {
+        let commands = [
+            (HookEvent::Upload, &args.hook_upload),
+            (HookEvent::Delete, &args.hook_delete),
+            (HookEvent::Mkdir, &args.hook_mkdir),
+            (HookEvent::Move, &args.hook_move),
+        ]
+        .into_iter()
+        .filter_map(|(event, command)| Some((event, command.clone()?)))
+        .collect();
+        Self {
+            commands,
+            timeout: args.hook_timeout,
+            permits: Arc::new(Semaphore::new(args.hook_concurrency.max(1))),
+        }
+    }
+
+    /// Spawns the command of the event, which waits its turn if too many are running
+    pub fn run(&self, event: HookEvent, path: &Path, dest: Option<&Path>, user: Option<&str>) {
+        let command = match self.commands.iter().find(|(v, _)| *v == event) {
+            Some((_, command)) => command.clone(),
+            None => return,
+        };
+        let timeout = self.timeout;
+        let permits = self.permits.clone();
+        let path = path.to_path_buf();
+        let dest = dest.map(|v| v.to_path_buf());
+        let user = user.map(|v| v.to_string());
+        tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            if let Err(err) = exec(event, &command, timeout, path, dest, user).await {
+                warn!("Hook for {} failed, {err}", event.name());
+            }
+        });
+    }
+}
+
+async fn exec(
+    event: HookEvent,
+    command: &str,
+    timeout: Duration,
+    path: PathBuf,
+    dest: Option<PathBuf>,
+    user: Option<String>,
+) -> Result<()> {
+    let target = dest.as_ref().unwrap_or(&path);
+    let size = match tokio::fs::metadata(target).await {
+        Ok(meta) if meta.is_file() => Some(meta.len()),
+        _ => None,
+    };
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command)
+        .env("DUFS_EVENT", event.name())
+        .env("DUFS_EVENT_PATH", &path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    if let Some(dest) = &dest {
+        cmd.env("DUFS_EVENT_DEST", dest);
+    }
+    if let Some(user) = &user {
+        cmd.env("DUFS_EVENT_USER", user);
+    }
+    if let Some(size) = size {
+        cmd.env("DUFS_EVENT_SIZE", size.to_string());
+    }
+    let mut child = cmd.spawn()?;
+    let status = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => status?,
+        Err(_) => bail!("timed out after {}s", timeout.as_secs()),
+    };
+    if !status.success() {
+        bail!("{status}");
+    }
+    Ok(())
+}
@@ -1,6 +1,17 @@
-use std::{collections::HashMap, str::FromStr};
+use futures::TryStreamExt;
+use hyper::{body::HttpBody, header::CONTENT_LENGTH, Body};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    time::Instant,
+};
 
-use crate::{auth::get_auth_user, server::Request};
+use crate::{
+    auth::get_auth_user,
+    server::{Request, Response},
+};
 
 pub const DEFAULT_LOG_FORMAT: &str = r#"$remote_addr "$request" $status"#;
 
@@ -50,6 +61,44 @@ impl HttpLogger {
         }
         data
     }
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.elements
+            .iter()
+            .any(|v| matches!(v, LogElement::Variable(v) if v == name))
+    }
+
+    /// Logs the response, once its body is sent if the format needs `$bytes_sent` or `$duration_ms`
+    pub fn log_response(
+        &self,
+        mut data: HashMap<String, String>,
+        metrics: HttpMetrics,
+        res: &mut Response,
+    ) {
+        let size = res.body().size_hint().exact();
+        let wait_body = self.has_variable("bytes_sent") || self.has_variable("duration_ms");
+        if !wait_body || size == Some(0) {
+            metrics.insert(&mut data, size.unwrap_or_default());
+            self.log(&data, None);
+            return;
+        }
+        if let Some(size) = size {
+            // A wrapped body has no size, keep the response from being chunked
+            if !res.headers().contains_key(CONTENT_LENGTH) {
+                res.headers_mut().insert(CONTENT_LENGTH, size.into());
+            }
+        }
+        let mut log = BodyLog {
+            logger: self.clone(),
+            data,
+            metrics,
+            sent: 0,
+        };
+        let body = std::mem::take(res.body_mut());
+        *res.body_mut() = Body::wrap_stream(body.inspect_ok(move |chunk| {
+            log.add(chunk.len());
+        }));
+    }
+
     pub fn log(&self, data: &HashMap<String, String>, err: Option<String>) {
         if self.elements.is_empty() {
             return;
@@ -70,6 +119,64 @@ impl HttpLogger {
     }
 }
 
+/// Measures a request for `$duration_ms`, `$bytes_received` and `$bytes_sent`
+pub struct HttpMetrics {
+    start: Instant,
+    received: Arc<AtomicU64>,
+}
+
+impl HttpMetrics {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            received: Default::default(),
+        }
+    }
+
+    /// Counts the bytes of the request body while it's read
+    pub fn count_request(&self, req: Request) -> Request {
+        let received = self.received.clone();
+        let (parts, body) = req.into_parts();
+        let body = Body::wrap_stream(body.inspect_ok(move |chunk| {
+            received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }));
+        Request::from_parts(parts, body)
+    }
+
+    pub fn insert(&self, data: &mut HashMap<String, String>, sent: u64) {
+        data.insert(
+            "duration_ms".to_string(),
+            self.start.elapsed().as_millis().to_string(),
+        );
+        data.insert(
+            "bytes_received".to_string(),
+            self.received.load(Ordering::Relaxed).to_string(),
+        );
+        data.insert("bytes_sent".to_string(), sent.to_string());
+    }
+}
+
+/// Logs when the response body is done, or dropped as the client went away
+struct BodyLog {
+    logger: HttpLogger,
+    data: HashMap<String, String>,
+    metrics: HttpMetrics,
+    sent: u64,
+}
+
+impl BodyLog {
+    fn add(&mut self, len: usize) {
+        self.sent += len as u64;
+    }
+}
+
+impl Drop for BodyLog {
+    fn drop(&mut self) {
+        self.metrics.insert(&mut self.data, self.sent);
+        self.logger.log(&self.data, None);
+    }
+}
+
 impl FromStr for HttpLogger {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -0,0 +1,54 @@
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+
+/// Tells the work done in the background for a request whether to go on.
+///
+/// It stops once the server stops, or once its `JobGuard` is dropped, that is when the
+/// client is gone: the guard is held by the request while it's handled, then by the body
+/// of the response.
+#[derive(Clone)]
+pub struct Job {
+    running: Arc<AtomicBool>,
+    abandoned: Arc<AtomicBool>,
+}
+
+/// Abandons the job once dropped
+pub struct JobGuard(Arc<AtomicBool>);
+
+impl Job {
+    pub fn new(running: Arc<AtomicBool>) -> (Self, JobGuard) {
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let guard = JobGuard(abandoned.clone());
+        (Self { running, abandoned }, guard)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(atomic::Ordering::SeqCst)
+            && !self.abandoned.load(atomic::Ordering::SeqCst)
+    }
+}
+
+impl JobGuard {
+    /// Keeps the job going for as long as the stream isn't dropped
+    pub fn attach<S>(self, stream: S) -> impl Stream<Item = S::Item> + Send + 'static
+    where
+        S: Stream + Send + 'static,
+        S::Item: Send,
+    {
+        let mut stream = stream.boxed();
+        stream! {
+            let _guard = self;
+            while let Some(item) = stream.next().await {
+                yield item;
+            }
+        }
+    }
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Maximum number of changes kept in memory
+const CAPACITY: usize = 10_000;
+
+const TOKEN_PREFIX: &str = "urn:dufs:sync:";
+
+/// Remembers paths deleted, moved or copied through the server.
+///
+/// Modification times reveal new and updated entries, but neither deleted entries
+/// nor moved ones, whose mtime is preserved. The journal fills that gap for
+/// sync-collection reports.
+pub struct ChangeJournal {
+    boot: u64,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    entries: VecDeque<(u64, PathBuf)>,
+    /// Changes before this time are no longer known
+    since: u64,
+}
+
+impl ChangeJournal {
+    pub fn new() -> Self {
+        let boot = now();
+        Self {
+            boot,
+            inner: Mutex::new(Inner {
+                entries: VecDeque::new(),
+                since: boot,
+            }),
+        }
+    }
+
+    pub fn record(&self, path: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() >= CAPACITY {
+            if let Some((time, _)) = inner.entries.pop_front() {
+                inner.since = time + 1;
+            }
+        }
+        inner.entries.push_back((now(), path.to_path_buf()));
+    }
+
+    /// A sync token for the current state
+    pub fn token(&self) -> String {
+        format!("{TOKEN_PREFIX}{}-{}", self.boot, now())
+    }
+
+    /// The time encoded in `token` and the paths recorded since then.
+    ///
+    /// Returns `None` if the token wasn't issued by this server instance or is
+    /// too old for the journal to tell.
+    pub fn changes(&self, token: &str) -> Option<(u64, Vec<PathBuf>)> {
+        let (boot, time) = token.trim().strip_prefix(TOKEN_PREFIX)?.split_once('-')?;
+        let (boot, time): (u64, u64) = (boot.parse().ok()?, time.parse().ok()?);
+        let inner = self.inner.lock().unwrap();
+        if boot != self.boot || time < inner.since || time > now() {
+            return None;
+        }
+        let paths = inner
+            .entries
+            .iter()
+            .filter(|(v, _)| *v >= time)
+            .map(|(_, path)| path.clone())
+            .collect();
+        Some((time, paths))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_journal() {
+        let journal = ChangeJournal::new();
+        let token = journal.token();
+        journal.record(Path::new("/tmp/a"));
+        let (_, paths) = journal.changes(&token).unwrap();
+        assert_eq!(paths, vec![PathBuf::from("/tmp/a")]);
+        assert!(journal.changes("urn:dufs:sync:0-0").is_none());
+        assert!(journal.changes("invalid").is_none());
+        assert!(journal
+            .changes(&format!("{TOKEN_PREFIX}{}-{}", journal.boot, u64::MAX))
+            .is_none());
+    }
+}
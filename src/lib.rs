@@ -0,0 +1,458 @@
+//! Dufs as a library, to serve files from other programs.
+//!
+//! [`serve`] binds the addresses of the [`Args`] and serves the files like the `dufs`
+//! binary does. To mount dufs in a hyper service of your own instead, create a
+//! [`Server`] and hand it the requests with [`Server::call`].
+
+pub mod args;
+mod auth;
+mod checksum;
+pub mod client;
+mod compress;
+#[cfg(unix)]
+mod daemon;
+mod delta;
+mod dir_usage;
+mod dlna;
+mod downloads;
+pub mod events;
+mod ftp;
+mod highlight;
+mod hooks;
+mod http_logger;
+mod job;
+mod journal;
+mod load;
+pub mod logger;
+mod media;
+mod meta_cache;
+pub mod middleware;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod preload;
+#[cfg(unix)]
+mod privileges;
+mod proxy_protocol;
+mod quota;
+mod rate_limit;
+mod rewrite;
+mod s3;
+mod search_index;
+pub mod server;
+#[cfg(windows)]
+pub mod service;
+#[cfg(feature = "sftp")]
+mod sftp;
+mod streamer;
+mod tftp;
+mod timeout;
+#[cfg(feature = "tls")]
+mod tls;
+mod torrent;
+mod trash;
+#[cfg(unix)]
+mod unix;
+mod upload_types;
+mod utils;
+
+#[macro_use]
+extern crate log;
+
+pub use crate::args::Args;
+pub use crate::auth::hash_password;
+#[cfg(unix)]
+pub use crate::daemon::{daemonize, Daemon, PidFile};
+pub use crate::middleware::Middleware;
+pub use crate::server::{Request, Response, Server};
+
+use crate::args::BindAddr;
+use crate::ftp::FtpServer;
+use crate::proxy_protocol::{ProxyAcceptor, ProxyStream};
+#[cfg(feature = "sftp")]
+use crate::sftp::SftpServer;
+use crate::tftp::TftpServer;
+use crate::timeout::{TimeoutAcceptor, TimeoutStream};
+#[cfg(feature = "tls")]
+use crate::tls::{
+    acme_server_config, cert_server_config, config_builder, load_certified_key, TlsAcceptor,
+    TlsStream,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::future::join_all;
+use hyper::server::conn::AddrIncoming;
+use hyper::server::Builder;
+use hyper::service::{make_service_fn, service_fn};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr, TcpListener as StdTcpListener, UdpSocket as StdUdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+
+/// Serves the files on the addresses of the args until ctrl-c
+pub async fn serve(args: Args) -> Result<()> {
+    serve_until(args, shutdown_signal()).await
+}
+
+/// Serves the files on the addresses of the args until the shutdown future completes
+pub async fn serve_until(mut args: Args, shutdown: impl Future<Output = ()>) -> Result<()> {
+    let listeners = bind(&mut args)?;
+    let args = Arc::new(args);
+    let running = Arc::new(AtomicBool::new(true));
+    let server = Arc::new(RwLock::new(Arc::new(Server::init(
+        args.clone(),
+        running.clone(),
+    )?)));
+    let handles = listen(args.clone(), server.clone(), listeners)?;
+    drop_privileges(&args, &server)?;
+    tokio::spawn(purge_trash(server));
+    tokio::select! {
+        ret = join_all(handles) => {
+            for r in ret {
+                if let Err(e) = r {
+                    error!("{}", e);
+                }
+            }
+        },
+        _ = shutdown => {
+            running.store(false, Ordering::SeqCst);
+        },
+    }
+    Ok(())
+}
+
+/// Completes on ctrl-c, or on SIGTERM on Unix
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install SIGTERM signal handler");
+        tokio::select! {
+            ret = tokio::signal::ctrl_c() => ret.expect("Failed to install CTRL+C signal handler"),
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler")
+}
+
+/// Purges the trash of the current config every so often
+pub async fn purge_trash(server: Arc<RwLock<Arc<Server>>>) {
+    loop {
+        let trash = server.read().unwrap().trash();
+        let interval = match trash {
+            Some(trash) => {
+                if let Err(err) = trash.purge().await {
+                    warn!("Failed to purge trash, {err}");
+                }
+                trash.purge_interval()
+            }
+            None => Duration::from_secs(3600),
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Switches to the user and group of the args, and into the chroot with the current server
+/// swapped for one that serves from inside it, once the addresses are bound
+pub fn drop_privileges(args: &Args, server: &RwLock<Arc<Server>>) -> Result<()> {
+    if args.user.is_none() && args.group.is_none() && !args.chroot {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    privileges::drop_privileges(
+        args.user.as_deref(),
+        args.group.as_deref(),
+        args.chroot.then_some(args.serve_path.as_path()),
+    )?;
+    if args.chroot {
+        let current = server.read().unwrap().clone();
+        let new_server = current.reload(Arc::new(args.chrooted()))?;
+        *server.write().unwrap() = Arc::new(new_server);
+    }
+    Ok(())
+}
+
+/// Fails on what would keep the server from starting, without binding any address
+pub fn check(args: Args) -> Result<()> {
+    #[cfg(feature = "tls")]
+    if let (Some(cert_file), Some(key_file)) = (&args.tls_cert, &args.tls_key) {
+        load_certified_key(cert_file, key_file)?;
+    }
+    Server::init(Arc::new(args), Arc::new(AtomicBool::new(true)))?;
+    Ok(())
+}
+
+/// Binds the HTTP ports of the addresses of the args, in their order. With `--port 0`, the port
+/// the OS picks for the first address is used for the others too, and set in the args.
+pub fn bind(args: &mut Args) -> Result<Vec<TcpListener>> {
+    let ips: Vec<IpAddr> = args
+        .addrs
+        .iter()
+        .filter_map(|v| match v {
+            BindAddr::Address(ip) => Some(*ip),
+            BindAddr::Path(_) => None,
+        })
+        .collect();
+    let bind_ip = |ip: IpAddr, port: u16| {
+        create_listener(SocketAddr::new(ip, port))
+            .with_context(|| format!("Failed to bind `{ip}:{port}`"))
+    };
+    if args.port != 0 || ips.is_empty() {
+        return ips.into_iter().map(|ip| bind_ip(ip, args.port)).collect();
+    }
+    // The port picked for the first address can be in use on the others, then try another
+    'retry: for _ in 0..10 {
+        let mut listeners = vec![];
+        let mut port = 0;
+        for ip in ips.iter() {
+            match create_listener(SocketAddr::new(*ip, port)) {
+                Ok(listener) => {
+                    port = listener.local_addr()?.port();
+                    listeners.push(listener);
+                }
+                Err(err) if is_addr_in_use(&err) && port != 0 => continue 'retry,
+                Err(err) => return Err(err.context(format!("Failed to bind `{ip}:{port}`"))),
+            }
+        }
+        args.port = port;
+        return Ok(listeners);
+    }
+    bail!("Failed to find a port free on all the addresses")
+}
+
+fn is_addr_in_use(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|v| v.kind() == std::io::ErrorKind::AddrInUse)
+}
+
+/// Serves the requests on the listeners from `bind` and the other ports of the args with the
+/// current server, which can be swapped to reload the config
+pub fn listen(
+    args: Arc<Args>,
+    server: Arc<RwLock<Arc<Server>>>,
+    listeners: Vec<TcpListener>,
+) -> Result<Vec<JoinHandle<Result<(), hyper::Error>>>> {
+    let mut listeners = listeners.into_iter();
+    let mut handles = vec![];
+    #[cfg(feature = "tls")]
+    let tls_config = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert_file), Some(key_file)) => Some(cert_server_config(
+            config_builder(args.tls_min_version.as_deref(), &args.tls_ciphers)?,
+            cert_file,
+            key_file,
+        )?),
+        _ if !args.acme_domain.is_empty() => Some(acme_server_config(
+            config_builder(args.tls_min_version.as_deref(), &args.tls_ciphers)?,
+            &args.acme_domain,
+            args.acme_cache_dir.as_deref(),
+        )),
+        _ => None,
+    };
+    if args.dlna {
+        let socket = dlna::bind_ssdp(&args).context("Failed to bind SSDP for DLNA")?;
+        tokio::spawn(dlna::advertise(args.clone(), socket));
+    }
+    if let Some(ftp_port) = args.ftp_port {
+        let ftp = FtpServer::new(server.clone());
+        #[cfg(feature = "tls")]
+        let ftp = match tls_config.clone() {
+            Some(config) => ftp.with_tls(config),
+            None => ftp,
+        };
+        let ftp = Arc::new(ftp);
+        for bind_addr in args.addrs.iter() {
+            if let BindAddr::Address(ip) = bind_addr {
+                let listener = create_listener(SocketAddr::new(*ip, ftp_port))
+                    .with_context(|| format!("Failed to bind `{ip}:{ftp_port}`"))?;
+                tokio::spawn(ftp.clone().serve(listener));
+            }
+        }
+    }
+    #[cfg(feature = "sftp")]
+    if let Some(sftp_port) = args.sftp_port {
+        let sftp = Arc::new(SftpServer::new(
+            server.clone(),
+            args.sftp_host_key.as_deref(),
+        )?);
+        for bind_addr in args.addrs.iter() {
+            if let BindAddr::Address(ip) = bind_addr {
+                let listener = create_listener(SocketAddr::new(*ip, sftp_port))
+                    .with_context(|| format!("Failed to bind `{ip}:{sftp_port}`"))?;
+                tokio::spawn(sftp.clone().serve(listener));
+            }
+        }
+    }
+    if let Some(tftp_port) = args.tftp_port {
+        let tftp = Arc::new(TftpServer::new(server.clone()));
+        for bind_addr in args.addrs.iter() {
+            if let BindAddr::Address(ip) = bind_addr {
+                let socket = create_udp_socket(SocketAddr::new(*ip, tftp_port))
+                    .with_context(|| format!("Failed to bind `{ip}:{tftp_port}`"))?;
+                tokio::spawn(tftp.clone().serve(socket));
+            }
+        }
+    }
+    #[cfg(feature = "tls")]
+    if let Some(redirect_port) = args.http_redirect_port {
+        for bind_addr in args.addrs.iter() {
+            if let BindAddr::Address(ip) = bind_addr {
+                let incoming = create_addr_incoming(SocketAddr::new(*ip, redirect_port))
+                    .with_context(|| format!("Failed to bind `{ip}:{redirect_port}`"))?;
+                let incoming = ProxyAcceptor::new(incoming, args.proxy_protocol);
+                let https_port = args.port;
+                let new_service = make_service_fn(move |_| async move {
+                    Ok::<_, hyper::Error>(service_fn(move |req: Request| async move {
+                        Ok::<_, hyper::Error>(redirect_to_https(&req, https_port))
+                    }))
+                });
+                let server = tokio::spawn(server_builder(incoming, &args).serve(new_service));
+                handles.push(server);
+            }
+        }
+    }
+    for bind_addr in args.addrs.iter() {
+        let server = server.clone();
+        let serve_func = move |remote_addr: Option<SocketAddr>| {
+            let server = server.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request| {
+                    // Taken per request, so a reload applies to open connections too
+                    let inner = server.read().unwrap().clone();
+                    inner.call(req, remote_addr)
+                }))
+            }
+        };
+        match bind_addr {
+            BindAddr::Address(ip) => {
+                let listener = listeners
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing the listener of `{ip}`"))?;
+                let incoming = AddrIncoming::from_listener(listener)?;
+                let incoming = ProxyAcceptor::new(incoming, args.proxy_protocol);
+
+                #[cfg(feature = "tls")]
+                if let Some(config) = tls_config.clone() {
+                    let accepter = TlsAcceptor::new(config, incoming);
+                    let new_service = make_service_fn(move |socket: &TimeoutStream<TlsStream>| {
+                        let remote_addr = socket.get_ref().remote_addr();
+                        serve_func(Some(remote_addr))
+                    });
+                    let server = tokio::spawn(server_builder(accepter, &args).serve(new_service));
+                    handles.push(server);
+                    continue;
+                }
+                let new_service = make_service_fn(move |socket: &TimeoutStream<ProxyStream>| {
+                    let remote_addr = socket.get_ref().remote_addr();
+                    serve_func(Some(remote_addr))
+                });
+                let server = tokio::spawn(server_builder(incoming, &args).serve(new_service));
+                handles.push(server);
+            }
+            BindAddr::Path(path) => {
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                #[cfg(unix)]
+                {
+                    let listener = tokio::net::UnixListener::bind(path)
+                        .with_context(|| format!("Failed to bind `{}`", path.display()))?;
+                    let acceptor = unix::UnixAcceptor::from_listener(listener);
+                    let new_service = make_service_fn(move |_| serve_func(None));
+                    let server = tokio::spawn(server_builder(acceptor, &args).serve(new_service));
+                    handles.push(server);
+                }
+            }
+        }
+    }
+    Ok(handles)
+}
+
+/// Redirects a plain HTTP request to the same host, path and query over HTTPS
+#[cfg(feature = "tls")]
+fn redirect_to_https(req: &Request, https_port: u16) -> Response {
+    use hyper::header::{HOST, LOCATION};
+    use hyper::http::uri::Authority;
+    use hyper::{Body, StatusCode};
+
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<Authority>().ok());
+    let mut res = Response::default();
+    let host = match host {
+        Some(v) => v,
+        None => {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            *res.body_mut() = Body::from("Missing Host header");
+            return res;
+        }
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|v| v.as_str())
+        .unwrap_or("/");
+    let location = match https_port {
+        443 => format!("https://{}{path}", host.host()),
+        _ => format!("https://{}:{https_port}{path}", host.host()),
+    };
+    *res.status_mut() = StatusCode::MOVED_PERMANENTLY;
+    match location.parse() {
+        Ok(v) => {
+            res.headers_mut().insert(LOCATION, v);
+        }
+        Err(_) => *res.status_mut() = StatusCode::BAD_REQUEST,
+    }
+    res
+}
+
+/// A server for the connections of the acceptor, which times them out as the args say
+fn server_builder<A>(acceptor: A, args: &Args) -> Builder<TimeoutAcceptor<A>> {
+    let acceptor = TimeoutAcceptor::new(acceptor, args.read_timeout, args.write_timeout);
+    let builder = hyper::Server::builder(acceptor);
+    match args.read_timeout {
+        Some(timeout) => builder.http1_header_read_timeout(timeout),
+        None => builder,
+    }
+}
+
+#[cfg(feature = "tls")]
+fn create_addr_incoming(addr: SocketAddr) -> Result<AddrIncoming> {
+    let incoming = AddrIncoming::from_listener(create_listener(addr)?)?;
+    Ok(incoming)
+}
+
+fn create_listener(addr: SocketAddr) -> Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024 /* Default backlog */)?;
+    let std_listener = StdTcpListener::from(socket);
+    std_listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(std_listener)?)
+}
+
+fn create_udp_socket(addr: SocketAddr) -> Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    let std_socket = StdUdpSocket::from(socket);
+    std_socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(std_socket)?)
+}
@@ -0,0 +1,163 @@
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, StatusCode};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::middleware::{Context, Middleware};
+use crate::server::{Request, Response};
+
+/// The requests being handled, limited to `--max-concurrency`, and the ones turned away for
+/// the load. Kept across config reloads.
+#[derive(Debug, Default)]
+pub struct Load {
+    in_flight: AtomicUsize,
+    /// How long handling a request takes, a moving average in microseconds
+    avg_micros: AtomicU64,
+    rejected_concurrency: AtomicU64,
+    rejected_rate_limit: AtomicU64,
+}
+
+/// Counts the request as handled until dropped
+pub struct LoadPermit {
+    load: Arc<Load>,
+    start: Instant,
+}
+
+impl Load {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the request in, or returns the seconds to wait for one to be done if `max`
+    /// requests are already being handled
+    pub fn enter(self: &Arc<Self>, max: Option<usize>) -> Result<LoadPermit, u64> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if max.is_some_and(|max| in_flight >= max.max(1)) {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.rejected_concurrency.fetch_add(1, Ordering::Relaxed);
+            let avg = self.avg_micros.load(Ordering::Relaxed);
+            return Err(avg.div_ceil(1_000_000).max(1));
+        }
+        Ok(LoadPermit {
+            load: self.clone(),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn reject_rate_limit(&self) {
+        self.rejected_rate_limit.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The load in the Prometheus text format
+    pub fn metrics(&self, max: Option<usize>) -> String {
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+        let avg = self.avg_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let mut output = String::new();
+        metric(
+            &mut output,
+            "dufs_requests_in_flight",
+            "gauge",
+            "Requests being handled",
+            &[("", in_flight.to_string())],
+        );
+        if let Some(max) = max {
+            let saturation = in_flight as f64 / max as f64;
+            metric(
+                &mut output,
+                "dufs_saturation",
+                "gauge",
+                "Requests being handled, out of --max-concurrency",
+                &[("", saturation.to_string())],
+            );
+        }
+        metric(
+            &mut output,
+            "dufs_request_duration_seconds_avg",
+            "gauge",
+            "Moving average of the time taken to handle a request",
+            &[("", avg.to_string())],
+        );
+        metric(
+            &mut output,
+            "dufs_requests_rejected_total",
+            "counter",
+            "Requests turned away, for the load or for the rate limit",
+            &[
+                (
+                    "{reason=\"concurrency\"}",
+                    self.rejected_concurrency
+                        .load(Ordering::Relaxed)
+                        .to_string(),
+                ),
+                (
+                    "{reason=\"rate_limit\"}",
+                    self.rejected_rate_limit.load(Ordering::Relaxed).to_string(),
+                ),
+            ],
+        );
+        output
+    }
+}
+
+impl Drop for LoadPermit {
+    fn drop(&mut self) {
+        self.load.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let micros = self.start.elapsed().as_micros() as u64;
+        let _ = self
+            .load
+            .avg_micros
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                Some(match avg {
+                    0 => micros,
+                    _ => avg - avg / 8 + micros / 8,
+                })
+            });
+    }
+}
+
+/// Serves the load at `__dufs__/metrics` with `--metrics`, ahead of the limits so it can be
+/// scraped when the server is at them
+pub struct Metrics {
+    load: Arc<Load>,
+    path: String,
+    max_concurrency: Option<usize>,
+}
+
+impl Metrics {
+    pub fn new(load: Arc<Load>, uri_prefix: &str, max_concurrency: Option<usize>) -> Self {
+        Self {
+            load,
+            path: format!("{uri_prefix}__dufs__/metrics"),
+            max_concurrency,
+        }
+    }
+}
+
+impl Middleware for Metrics {
+    fn request(&self, req: &mut Request, _ctx: &mut Context) -> Option<Response> {
+        if req.uri().path() != self.path {
+            return None;
+        }
+        let mut res = Response::default();
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Some(res);
+        }
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+        );
+        if req.method() == Method::GET {
+            *res.body_mut() = Body::from(self.load.metrics(self.max_concurrency));
+        }
+        Some(res)
+    }
+}
+
+fn metric(output: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, String)]) {
+    output.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+    for (labels, value) in samples {
+        output.push_str(&format!("{name}{labels} {value}\n"));
+    }
+}
@@ -1,41 +1,27 @@
-mod args;
-mod auth;
-mod http_logger;
-mod logger;
-mod server;
-mod streamer;
-#[cfg(feature = "tls")]
-mod tls;
-#[cfg(unix)]
-mod unix;
-mod utils;
-
 #[macro_use]
 extern crate log;
 
-use crate::args::{build_cli, print_completions, Args};
-use crate::server::{Request, Server};
-#[cfg(feature = "tls")]
-use crate::tls::{load_certs, load_private_key, TlsAcceptor, TlsStream};
+use dufs::args::{build_cli, gen_config, print_completions, print_manpage, Args, BindAddr};
+use dufs::{
+    bind, check, client, drop_privileges, hash_password, listen, logger, purge_trash,
+    shutdown_signal, Server,
+};
 
 use anyhow::{anyhow, Context, Result};
-use std::net::{IpAddr, SocketAddr, TcpListener as StdTcpListener};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use std::io::IsTerminal;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-use args::BindAddr;
+use clap::ArgMatches;
 use clap_complete::Shell;
 use futures::future::join_all;
-use tokio::net::TcpListener;
-use tokio::task::JoinHandle;
 
-use hyper::server::conn::{AddrIncoming, AddrStream};
-use hyper::service::{make_service_fn, service_fn};
-#[cfg(feature = "tls")]
-use rustls::ServerConfig;
-
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     logger::init().map_err(|e| anyhow!("Failed to init logger, {e}"))?;
     let cmd = build_cli();
     let matches = cmd.get_matches();
@@ -44,11 +30,99 @@ async fn main() -> Result<()> {
         print_completions(*generator, &mut cmd);
         return Ok(());
     }
-    let args = Args::parse(matches)?;
+    let matches = match matches.subcommand() {
+        Some(("hash", matches)) => {
+            let algorithm = matches.get_one::<String>("algorithm").unwrap();
+            let password = read_password()?;
+            println!("{}", hash_password(&password, algorithm)?);
+            return Ok(());
+        }
+        Some(("gen-config", _)) => {
+            print!("{}", gen_config());
+            return Ok(());
+        }
+        Some(("completions", matches)) => {
+            let shell = matches.get_one::<Shell>("shell").unwrap();
+            print_completions(*shell, &mut build_cli());
+            return Ok(());
+        }
+        Some(("manpage", _)) => {
+            print_manpage(build_cli())?;
+            return Ok(());
+        }
+        Some(("check", matches)) => {
+            let args = Args::parse(matches.clone())?;
+            runtime()?.block_on(async { check(args) })?;
+            println!("OK");
+            return Ok(());
+        }
+        #[cfg(windows)]
+        Some(("service", matches)) => return dufs::service::command(matches),
+        Some(("upload", matches)) => return client::upload(matches),
+        Some(("download", matches)) => return client::download(matches),
+        Some(("sync", matches)) => return client::sync(matches),
+        Some(("serve", matches)) => matches.clone(),
+        _ => matches,
+    };
+    let args = Args::parse(matches.clone())?;
+    // Forks before the runtime starts any threads
+    #[cfg(unix)]
+    let daemon = match args.daemon {
+        true => Some(dufs::daemonize()?),
+        false => None,
+    };
+    #[cfg(unix)]
+    let _pid_file = args
+        .pid_file
+        .as_deref()
+        .map(dufs::PidFile::create)
+        .transpose()?;
+    let ready = move || {
+        #[cfg(unix)]
+        if let Some(daemon) = daemon {
+            daemon.ready()?;
+        }
+        Ok(())
+    };
+    runtime()?.block_on(serve(matches, args, ready))
+}
+
+fn runtime() -> Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .with_context(|| "Failed to start the runtime")
+}
+
+/// Serves until shutdown, `ready` is called once dufs has started
+async fn serve(
+    #[cfg_attr(not(unix), allow(unused_variables))] matches: ArgMatches,
+    mut args: Args,
+    ready: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let listeners = bind(&mut args)?;
     let args = Arc::new(args);
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &args.otlp_endpoint {
+        dufs::otlp::init(endpoint)?;
+    }
     let running = Arc::new(AtomicBool::new(true));
-    let handles = serve(args.clone(), running.clone())?;
-    print_listening(args)?;
+    let server = Arc::new(RwLock::new(Arc::new(Server::init(
+        args.clone(),
+        running.clone(),
+    )?)));
+    let handles = listen(args.clone(), server.clone(), listeners)?;
+    let addrs = listening_addrs(&args)?;
+    // Written before dropping privileges, which can take the folder of the file away
+    if let Some(path) = &args.urls_file {
+        write_urls(&args, &addrs, path)?;
+    }
+    drop_privileges(&args, &server)?;
+    tokio::spawn(purge_trash(server.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(matches, args.clone(), server));
+    print_listening(&args, &addrs)?;
+    ready()?;
 
     tokio::select! {
         ret = join_all(handles) => {
@@ -57,106 +131,103 @@ async fn main() -> Result<()> {
                     error!("{}", e);
                 }
             }
-            Ok(())
         },
         _ = shutdown_signal() => {
             running.store(false, Ordering::SeqCst);
-            Ok(())
         },
     }
+    #[cfg(feature = "otlp")]
+    dufs::otlp::shutdown().await;
+    Ok(())
 }
 
-fn serve(
-    args: Arc<Args>,
-    running: Arc<AtomicBool>,
-) -> Result<Vec<JoinHandle<Result<(), hyper::Error>>>> {
-    let inner = Arc::new(Server::init(args.clone(), running)?);
-    let mut handles = vec![];
-    let port = args.port;
-    for bind_addr in args.addrs.iter() {
-        let inner = inner.clone();
-        let serve_func = move |remote_addr: Option<SocketAddr>| {
-            let inner = inner.clone();
-            async move {
-                Ok::<_, hyper::Error>(service_fn(move |req: Request| {
-                    let inner = inner.clone();
-                    inner.call(req, remote_addr)
-                }))
-            }
-        };
-        match bind_addr {
-            BindAddr::Address(ip) => {
-                let incoming = create_addr_incoming(SocketAddr::new(*ip, port))
-                    .with_context(|| format!("Failed to bind `{ip}:{port}`"))?;
-
-                match (&args.tls_cert, &args.tls_key) {
-                    #[cfg(feature = "tls")]
-                    (Some(cert_file), Some(key_file)) => {
-                        let certs = load_certs(cert_file)?;
-                        let key = load_private_key(key_file)?;
-                        let config = ServerConfig::builder()
-                            .with_safe_defaults()
-                            .with_no_client_auth()
-                            .with_single_cert(certs.clone(), key.clone())?;
-                        let config = Arc::new(config);
-                        let accepter = TlsAcceptor::new(config.clone(), incoming);
-                        let new_service = make_service_fn(move |socket: &TlsStream| {
-                            let remote_addr = socket.remote_addr();
-                            serve_func(Some(remote_addr))
-                        });
-                        let server =
-                            tokio::spawn(hyper::Server::builder(accepter).serve(new_service));
-                        handles.push(server);
-                    }
-                    (None, None) => {
-                        let new_service = make_service_fn(move |socket: &AddrStream| {
-                            let remote_addr = socket.remote_addr();
-                            serve_func(Some(remote_addr))
-                        });
-                        let server =
-                            tokio::spawn(hyper::Server::builder(incoming).serve(new_service));
-                        handles.push(server);
-                    }
-                    _ => {
-                        unreachable!()
-                    }
-                };
-            }
-            BindAddr::Path(path) => {
-                if path.exists() {
-                    std::fs::remove_file(path)?;
-                }
-                #[cfg(unix)]
-                {
-                    let listener = tokio::net::UnixListener::bind(path)
-                        .with_context(|| format!("Failed to bind `{}`", path.display()))?;
-                    let acceptor = unix::UnixAcceptor::from_listener(listener);
-                    let new_service = make_service_fn(move |_| serve_func(None));
-                    let server = tokio::spawn(hyper::Server::builder(acceptor).serve(new_service));
-                    handles.push(server);
-                }
-            }
+/// Reloads the config on SIGHUP, listeners and tracing are kept as they are
+#[cfg(unix)]
+async fn reload_on_hangup(matches: ArgMatches, args: Arc<Args>, server: Arc<RwLock<Arc<Server>>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("Failed to listen for SIGHUP, {err}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match reload(&matches, &args, &server) {
+            Ok(()) => info!("Reloaded config"),
+            Err(err) => error!("Failed to reload config, {err}"),
         }
     }
-    Ok(handles)
 }
 
-fn create_addr_incoming(addr: SocketAddr) -> Result<AddrIncoming> {
-    use socket2::{Domain, Protocol, Socket, Type};
-    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
-    if addr.is_ipv6() {
-        socket.set_only_v6(true)?;
+#[cfg(unix)]
+fn reload(matches: &ArgMatches, args: &Args, server: &RwLock<Arc<Server>>) -> Result<()> {
+    if args.chroot {
+        return Err(anyhow!(
+            "The config can't be reloaded with `--chroot`, restart dufs instead"
+        ));
+    }
+    let mut new_args = Args::parse(matches.clone())?;
+    let restart_needed = new_args.addrs != args.addrs
+        // Port 0 is the port picked at start
+        || (new_args.port != args.port && new_args.port != 0)
+        || new_args.urls_file != args.urls_file
+        || new_args.proxy_protocol != args.proxy_protocol
+        || new_args.read_timeout != args.read_timeout
+        || new_args.write_timeout != args.write_timeout
+        || new_args.user != args.user
+        || new_args.group != args.group
+        || new_args.chroot != args.chroot
+        || new_args.daemon != args.daemon
+        || new_args.pid_file != args.pid_file
+        || new_args.dlna != args.dlna
+        || new_args.ftp_port != args.ftp_port
+        || new_args.tftp_port != args.tftp_port
+        || new_args.tls_cert != args.tls_cert
+        || new_args.tls_key != args.tls_key
+        || new_args.tls_min_version != args.tls_min_version
+        || new_args.tls_ciphers != args.tls_ciphers
+        || new_args.http_redirect_port != args.http_redirect_port
+        || new_args.acme_domain != args.acme_domain
+        || new_args.acme_cache_dir != args.acme_cache_dir
+        || new_args.otlp_endpoint != args.otlp_endpoint
+        || new_args.sftp_port != args.sftp_port
+        || new_args.sftp_host_key != args.sftp_host_key;
+    if restart_needed {
+        warn!("Changes to bind, port, urls-file, proxy-protocol, timeouts, user, group, chroot, daemon, pid-file, dlna, ftp, tftp, tls, otlp and sftp options need a restart");
     }
-    socket.set_reuse_address(true)?;
-    socket.bind(&addr.into())?;
-    socket.listen(1024 /* Default backlog */)?;
-    let std_listener = StdTcpListener::from(socket);
-    std_listener.set_nonblocking(true)?;
-    let incoming = AddrIncoming::from_listener(TcpListener::from_std(std_listener)?)?;
-    Ok(incoming)
+    new_args.addrs = args.addrs.clone();
+    new_args.port = args.port;
+    new_args.urls_file = args.urls_file.clone();
+    new_args.proxy_protocol = args.proxy_protocol;
+    new_args.read_timeout = args.read_timeout;
+    new_args.write_timeout = args.write_timeout;
+    new_args.user = args.user.clone();
+    new_args.group = args.group.clone();
+    new_args.chroot = args.chroot;
+    new_args.daemon = args.daemon;
+    new_args.pid_file = args.pid_file.clone();
+    new_args.dlna = args.dlna;
+    new_args.ftp_port = args.ftp_port;
+    new_args.tftp_port = args.tftp_port;
+    new_args.tls_cert = args.tls_cert.clone();
+    new_args.tls_key = args.tls_key.clone();
+    new_args.tls_min_version = args.tls_min_version.clone();
+    new_args.tls_ciphers = args.tls_ciphers.clone();
+    new_args.http_redirect_port = args.http_redirect_port;
+    new_args.acme_domain = args.acme_domain.clone();
+    new_args.acme_cache_dir = args.acme_cache_dir.clone();
+    new_args.otlp_endpoint = args.otlp_endpoint.clone();
+    new_args.sftp_port = args.sftp_port;
+    new_args.sftp_host_key = args.sftp_host_key.clone();
+    let current = server.read().unwrap().clone();
+    let new_server = current.reload(Arc::new(new_args))?;
+    *server.write().unwrap() = Arc::new(new_server);
+    Ok(())
 }
 
-fn print_listening(args: Arc<Args>) -> Result<()> {
+/// The addresses listened on, with the unspecified ones replaced by those of the interfaces
+fn listening_addrs(args: &Args) -> Result<Vec<BindAddr>> {
     let mut bind_addrs = vec![];
     let (mut ipv4, mut ipv6) = (false, false);
     for bind_addr in args.addrs.iter() {
@@ -189,28 +260,55 @@ fn print_listening(args: Arc<Args>) -> Result<()> {
         }
     }
     bind_addrs.sort_unstable();
-    let urls = bind_addrs
-        .into_iter()
+    Ok(bind_addrs)
+}
+
+fn url(args: &Args, addr: &IpAddr) -> String {
+    let addr = match addr {
+        IpAddr::V4(_) => format!("{}:{}", addr, args.port),
+        IpAddr::V6(_) => format!("[{}]:{}", addr, args.port),
+    };
+    let protocol = if args.tls() { "https" } else { "http" };
+    format!("{}://{}{}", protocol, addr, args.uri_prefix)
+}
+
+fn urls(args: &Args, bind_addrs: &[BindAddr]) -> Vec<String> {
+    bind_addrs
+        .iter()
         .map(|bind_addr| match bind_addr {
-            BindAddr::Address(addr) => {
-                let addr = match addr {
-                    IpAddr::V4(_) => format!("{}:{}", addr, args.port),
-                    IpAddr::V6(_) => format!("[{}]:{}", addr, args.port),
-                };
-                let protocol = if args.tls_cert.is_some() {
-                    "https"
-                } else {
-                    "http"
-                };
-                format!("{}://{}{}", protocol, addr, args.uri_prefix)
-            }
+            BindAddr::Address(addr) => url(args, addr),
             BindAddr::Path(path) => path.display().to_string(),
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
+/// Writes the port and the URLs as JSON for scripts, replacing the file at once so a partly
+/// written one is never read
+fn write_urls(args: &Args, bind_addrs: &[BindAddr], path: &Path) -> Result<()> {
+    let output = serde_json::json!({
+        "port": args.port,
+        "urls": urls(args, bind_addrs),
+    });
+    if path.as_os_str() == "-" {
+        println!("{output}");
+        return Ok(());
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, format!("{output}\n"))
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .with_context(|| format!("Failed to write urls file `{}`", path.display()))
+}
+
+fn print_listening(args: &Args, bind_addrs: &[BindAddr]) -> Result<()> {
+    // With the urls file on stdout, the JSON is all that's printed of them
+    let urls = match args.urls_file.as_deref() == Some(Path::new("-")) {
+        true => vec![],
+        false => urls(args, bind_addrs),
+    };
     if urls.len() == 1 {
         println!("Listening on {}", urls[0]);
-    } else {
+    } else if !urls.is_empty() {
         let info = urls
             .iter()
             .map(|v| format!("  {v}"))
@@ -219,11 +317,72 @@ fn print_listening(args: Arc<Args>) -> Result<()> {
         println!("Listening on:\n{info}\n");
     }
 
+    let ips: Vec<&IpAddr> = bind_addrs
+        .iter()
+        .filter_map(|v| match v {
+            BindAddr::Address(ip) => Some(ip),
+            BindAddr::Path(_) => None,
+        })
+        .collect();
+    if args.qrcode {
+        // Loopback and link-local addresses can't be reached from a phone
+        let reachable = |ip: &&&IpAddr| match ip {
+            IpAddr::V4(v) => !v.is_loopback() && !v.is_link_local(),
+            IpAddr::V6(v) => !v.is_loopback() && !v.is_unicast_link_local(),
+        };
+        for ip in ips.iter().filter(reachable) {
+            let url = url(args, ip);
+            let code = QrCode::new(&url)?
+                .render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build();
+            println!("{url}\n{code}\n");
+        }
+    }
+    if args.open {
+        let ip = ips.iter().find(|v| v.is_loopback()).or(ips.first());
+        if let Some(ip) = ip {
+            if let Err(err) = open_browser(&url(args, ip)) {
+                warn!("Failed to open the browser, {err}");
+            }
+        }
+    }
+
     Ok(())
 }
 
-async fn shutdown_signal() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler")
+/// Opens the URL in the default browser of the system
+fn open_browser(url: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    let mut child = command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn read_password() -> Result<String> {
+    let password = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password("Password: ")?
+    } else {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        password.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        return Err(anyhow!("Empty password"));
+    }
+    Ok(password)
 }
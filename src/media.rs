@@ -0,0 +1,126 @@
+use anyhow::{bail, Result};
+use image::ImageOutputFormat;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Images larger than this aren't thumbnailed, decoding them would take too much memory
+const THUMBNAIL_MAX_SIZE: u64 = 67108864; // 64M
+const THUMBNAIL_QUALITY: u8 = 80;
+
+/// Whether the file is an `image`, a `video` or an `audio`, going by its name
+pub fn media_type(name: &str) -> Option<&'static str> {
+    let mime = mime_guess::from_path(name).first()?;
+    match mime.type_() {
+        mime_guess::mime::IMAGE => Some("image"),
+        mime_guess::mime::VIDEO => Some("video"),
+        mime_guess::mime::AUDIO => Some("audio"),
+        _ => None,
+    }
+}
+
+/// Scales the image down to fit in a `size` square, returns it encoded along with its
+/// content type. Images with transparency are encoded as png, the others as jpeg.
+pub fn thumbnail(path: &Path, size: u32) -> Result<(Vec<u8>, &'static str)> {
+    if std::fs::metadata(path)?.len() > THUMBNAIL_MAX_SIZE {
+        bail!("Image too large");
+    }
+    let image = image::open(path)?.thumbnail(size, size);
+    let mut output = Cursor::new(vec![]);
+    if image.color().has_alpha() {
+        image.write_to(&mut output, ImageOutputFormat::Png)?;
+        Ok((output.into_inner(), "image/png"))
+    } else {
+        image
+            .into_rgb8()
+            .write_to(&mut output, ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY))?;
+        Ok((output.into_inner(), "image/jpeg"))
+    }
+}
+
+/// The duration in seconds of an mp4/mov or wav file, read from its headers
+pub fn probe_duration(path: &Path) -> Option<f64> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    match ext.as_str() {
+        "mp4" | "m4a" | "m4v" | "mov" | "3gp" => mp4_duration(&mut file, len),
+        "wav" => wav_duration(&mut file, len),
+        _ => None,
+    }
+}
+
+/// Reads the duration from the `mvhd` box inside the `moov` box
+fn mp4_duration(file: &mut File, len: u64) -> Option<f64> {
+    let (start, end) = find_mp4_box(file, 0, len, b"moov")?;
+    let (start, _) = find_mp4_box(file, start, end, b"mvhd")?;
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let version = read_bytes::<4>(file)?[0];
+    let (timescale, duration) = if version == 1 {
+        read_bytes::<16>(file)?;
+        let timescale = u32::from_be_bytes(read_bytes(file)?);
+        (timescale, u64::from_be_bytes(read_bytes(file)?))
+    } else {
+        read_bytes::<8>(file)?;
+        let timescale = u32::from_be_bytes(read_bytes(file)?);
+        (timescale, u32::from_be_bytes(read_bytes(file)?) as u64)
+    };
+    (timescale > 0).then(|| duration as f64 / timescale as f64)
+}
+
+/// Finds the box of the kind between `start` and `end`, returns where its content starts and ends
+fn find_mp4_box(file: &mut File, start: u64, end: u64, kind: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut pos = start;
+    while pos + 8 <= end {
+        file.seek(SeekFrom::Start(pos)).ok()?;
+        let header = read_bytes::<8>(file)?;
+        let (header_size, size) = match u32::from_be_bytes(header[0..4].try_into().ok()?) {
+            1 => (16, u64::from_be_bytes(read_bytes(file)?)),
+            0 => (8, end - pos),
+            v => (8, v as u64),
+        };
+        if size < header_size {
+            return None;
+        }
+        if &header[4..8] == kind {
+            return Some((pos + header_size, pos.saturating_add(size).min(end)));
+        }
+        pos = pos.saturating_add(size);
+    }
+    None
+}
+
+/// Divides the size of the `data` chunk by the byte rate of the `fmt ` chunk
+fn wav_duration(file: &mut File, len: u64) -> Option<f64> {
+    let header = read_bytes::<12>(file)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut pos = 12;
+    let mut byte_rate = None;
+    while pos + 8 <= len {
+        file.seek(SeekFrom::Start(pos)).ok()?;
+        let chunk = read_bytes::<8>(file)?;
+        let size = u32::from_le_bytes(chunk[4..8].try_into().ok()?) as u64;
+        match &chunk[0..4] {
+            b"fmt " => {
+                let fmt = read_bytes::<12>(file)?;
+                byte_rate = Some(u32::from_le_bytes(fmt[8..12].try_into().ok()?));
+            }
+            b"data" => {
+                let byte_rate = byte_rate.filter(|v| *v > 0)?;
+                return Some(size.min(len - pos - 8) as f64 / byte_rate as f64);
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size
+        pos += 8 + size + size % 2;
+    }
+    None
+}
+
+fn read_bytes<const N: usize>(file: &mut File) -> Option<[u8; N]> {
+    let mut buf = [0; N];
+    file.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
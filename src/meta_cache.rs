@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+/// Entries are dropped all at once past this many, rather than tracking which are least used
+const MAX_ENTRIES: usize = 100_000;
+
+/// The metadata of files and folders, kept for a short while.
+///
+/// Sync clients sweep the same folders with PROPFIND over and over, and every entry
+/// costs a `metadata` and a `symlink_metadata` call. Any write through the server
+/// clears the cache, changes made outside of it show up once the entries expire.
+///
+/// Paths resolved to check symlinks stay in the served folders are kept too, along with
+/// their folders, so the symlinks of a listing don't each take resolving their whole path.
+pub struct MetaCache {
+    ttl: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Bumped by every write, entries looked up before it are stale
+    generation: u64,
+    entries: HashMap<PathBuf, Entry>,
+    real_paths: HashMap<PathBuf, RealPath>,
+}
+
+struct RealPath {
+    generation: u64,
+    cached_at: Instant,
+    path: PathBuf,
+}
+
+struct Entry {
+    generation: u64,
+    cached_at: Instant,
+    meta: Metadata,
+    is_symlink: bool,
+}
+
+impl MetaCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Default::default(),
+        }
+    }
+
+    /// The metadata of the path, following symlinks, and whether it's a symlink itself
+    pub async fn metadata(&self, path: &Path) -> io::Result<(Metadata, bool)> {
+        let generation = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(entry) = inner.entries.get(path) {
+                if entry.generation == inner.generation && entry.cached_at.elapsed() < self.ttl {
+                    return Ok((entry.meta.clone(), entry.is_symlink));
+                }
+            }
+            inner.generation
+        };
+        let (meta, meta2) = tokio::join!(fs::metadata(path), fs::symlink_metadata(path));
+        let (meta, is_symlink) = (meta?, meta2?.is_symlink());
+        let mut inner = self.inner.lock().unwrap();
+        // A write happened meanwhile, what was read may be from before or after it
+        if generation == inner.generation {
+            if inner.entries.len() >= MAX_ENTRIES {
+                inner.entries.clear();
+            }
+            inner.entries.insert(
+                path.to_path_buf(),
+                Entry {
+                    generation,
+                    cached_at: Instant::now(),
+                    meta: meta.clone(),
+                    is_symlink,
+                },
+            );
+        }
+        Ok((meta, is_symlink))
+    }
+
+    /// Forgets everything, after a write
+    pub fn invalidate(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.real_paths.clear();
+    }
+
+    /// The path with every symlink resolved, like `fs::canonicalize`
+    pub async fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if let Ok(real_path) = cached_real_path(&self.inner, self.ttl, path) {
+            return Ok(real_path);
+        }
+        let (inner, ttl, path) = (self.inner.clone(), self.ttl, path.to_path_buf());
+        tokio::task::spawn_blocking(move || real_path(&inner, ttl, &path, 0))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Resolves the last name of the path in its resolved folder, so the folders shared by
+/// the symlinks of a listing, and by their targets, are resolved once
+fn real_path(
+    inner: &Mutex<Inner>,
+    ttl: Duration,
+    path: &Path,
+    depth: usize,
+) -> io::Result<PathBuf> {
+    // As many as Linux follows
+    const MAX_SYMLINKS: usize = 40;
+    let generation = match cached_real_path(inner, ttl, path) {
+        Ok(real_path) => return Ok(real_path),
+        Err(generation) => generation,
+    };
+    let real_path = match (path.parent(), path.file_name()) {
+        // The `..` of symlink targets can't be joined to verbatim paths on Windows
+        (Some(dir), Some(name)) if cfg!(unix) && !dir.as_os_str().is_empty() => {
+            let joined = real_path(inner, ttl, dir, depth)?.join(name);
+            if std::fs::symlink_metadata(&joined)?.is_symlink() {
+                if depth >= MAX_SYMLINKS {
+                    return Err(io::Error::other("Too many levels of symbolic links"));
+                }
+                // An absolute target replaces the folder
+                let target = joined.parent().unwrap().join(std::fs::read_link(&joined)?);
+                real_path(inner, ttl, &target, depth + 1)?
+            } else {
+                joined
+            }
+        }
+        _ => std::fs::canonicalize(path)?,
+    };
+    let mut inner = inner.lock().unwrap();
+    // A write happened meanwhile, what was resolved may be from before or after it
+    if generation == inner.generation {
+        if inner.real_paths.len() >= MAX_ENTRIES {
+            inner.real_paths.clear();
+        }
+        inner.real_paths.insert(
+            path.to_path_buf(),
+            RealPath {
+                generation,
+                cached_at: Instant::now(),
+                path: real_path.clone(),
+            },
+        );
+    }
+    Ok(real_path)
+}
+
+/// The resolved path if cached, or the generation to cache it with
+fn cached_real_path(inner: &Mutex<Inner>, ttl: Duration, path: &Path) -> Result<PathBuf, u64> {
+    let inner = inner.lock().unwrap();
+    match inner.real_paths.get(path) {
+        Some(entry) if entry.generation == inner.generation && entry.cached_at.elapsed() < ttl => {
+            Ok(entry.path.clone())
+        }
+        _ => Err(inner.generation),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[tokio::test]
+    async fn test_canonicalize() {
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        let root = tmpdir.path();
+        std::fs::create_dir_all(root.join("files/sub")).unwrap();
+        std::fs::write(root.join("files/a.txt"), "a").unwrap();
+        std::fs::create_dir(root.join("links")).unwrap();
+        symlink("../files/a.txt", root.join("links/relative")).unwrap();
+        symlink(root.join("files/a.txt"), root.join("links/absolute")).unwrap();
+        symlink("relative", root.join("links/chain")).unwrap();
+        symlink("../files", root.join("links/dir")).unwrap();
+        symlink("loop", root.join("links/loop")).unwrap();
+
+        let cache = MetaCache::new(Duration::from_secs(60));
+        for name in [
+            "relative",
+            "absolute",
+            "chain",
+            "dir",
+            "dir/sub",
+            "dir/a.txt",
+        ] {
+            let path = root.join("links").join(name);
+            assert_eq!(
+                cache.canonicalize(&path).await.unwrap(),
+                std::fs::canonicalize(&path).unwrap()
+            );
+        }
+        assert!(cache.canonicalize(&root.join("links/loop")).await.is_err());
+        assert!(cache
+            .canonicalize(&root.join("links/missing"))
+            .await
+            .is_err());
+    }
+}
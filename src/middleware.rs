@@ -0,0 +1,130 @@
+use headers::{AccessControlAllowCredentials, AccessControlAllowOrigin, HeaderMapExt};
+use hyper::header::HeaderValue;
+use hyper::http::Extensions;
+use hyper::Uri;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::events::FsEvent;
+use crate::http_logger::{HttpLogger, HttpMetrics};
+use crate::server::{Request, Response};
+
+/// Hooks into the handling of every request, to add auth schemes, rewrite requests or
+/// log them without touching the server.
+///
+/// The `request` hooks run in the order the middleware are registered and the `response`
+/// hooks in the reverse order, so the first one registered sees the request first and
+/// the response last. Every hook does nothing by default.
+pub trait Middleware: Send + Sync {
+    /// Before the request is handled. A response returned answers the request instead of
+    /// the server, the `response` hooks still run.
+    fn request(&self, _req: &mut Request, _ctx: &mut Context) -> Option<Response> {
+        None
+    }
+
+    /// Once the response is ready, a 500 if handling the request failed
+    fn response(&self, _res: &mut Response, _ctx: &mut Context) {}
+
+    /// After a file or folder was changed through the server
+    fn event(&self, _event: &FsEvent) {}
+}
+
+/// The request as the middleware see it, from the request to the response
+pub struct Context {
+    pub uri: Uri,
+    /// The address of the client, past the trusted proxies
+    pub remote_addr: Option<IpAddr>,
+    /// Why handling the request failed
+    pub error: Option<String>,
+    /// What the middleware keep from the request to the response, by type
+    pub extensions: Extensions,
+}
+
+impl Context {
+    pub fn new(uri: Uri, remote_addr: Option<IpAddr>) -> Self {
+        Self {
+            uri,
+            remote_addr,
+            error: None,
+            extensions: Extensions::new(),
+        }
+    }
+}
+
+/// Allows requests from any origin, with `--enable-cors`
+pub struct Cors;
+
+impl Middleware for Cors {
+    fn response(&self, res: &mut Response, _ctx: &mut Context) {
+        res.headers_mut()
+            .typed_insert(AccessControlAllowOrigin::ANY);
+        res.headers_mut()
+            .typed_insert(AccessControlAllowCredentials);
+        res.headers_mut().insert(
+            "Access-Control-Allow-Methods",
+            HeaderValue::from_static("*"),
+        );
+        res.headers_mut().insert(
+            "Access-Control-Allow-Headers",
+            HeaderValue::from_static("Authorization,*"),
+        );
+        res.headers_mut().insert(
+            "Access-Control-Expose-Headers",
+            HeaderValue::from_static("Authorization,*"),
+        );
+    }
+}
+
+/// Logs the requests in the `--log-format`, leaving out the successful ones for assets
+pub struct HttpLog {
+    logger: HttpLogger,
+    assets_prefix: String,
+}
+
+/// What's logged of the request, kept until the response
+struct LogState {
+    data: HashMap<String, String>,
+    metrics: HttpMetrics,
+}
+
+impl HttpLog {
+    pub fn new(logger: HttpLogger, assets_prefix: String) -> Self {
+        Self {
+            logger,
+            assets_prefix,
+        }
+    }
+}
+
+impl Middleware for HttpLog {
+    fn request(&self, req: &mut Request, ctx: &mut Context) -> Option<Response> {
+        let metrics = HttpMetrics::new();
+        if self.logger.has_variable("bytes_received") {
+            *req = metrics.count_request(std::mem::take(req));
+        }
+        let mut data = self.logger.data(req);
+        if let Some(ip) = ctx.remote_addr {
+            data.insert("remote_addr".to_string(), ip.to_string());
+        }
+        ctx.extensions.insert(LogState { data, metrics });
+        None
+    }
+
+    fn response(&self, res: &mut Response, ctx: &mut Context) {
+        let LogState { mut data, metrics } = match ctx.extensions.remove::<LogState>() {
+            Some(v) => v,
+            None => return,
+        };
+        data.insert("status".to_string(), res.status().as_u16().to_string());
+        match &ctx.error {
+            Some(err) => {
+                metrics.insert(&mut data, 0);
+                self.logger.log(&data, Some(err.clone()));
+            }
+            None if !ctx.uri.path().starts_with(&self.assets_prefix) => {
+                self.logger.log_response(data, metrics, res);
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+use hyper::HeaderMap;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing::{Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Exports spans to an OTLP/HTTP collector, e.g. `http://localhost:4318`
+pub fn init(endpoint: &str) -> Result<()> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(endpoint.trim_end_matches('/'));
+    let config = trace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        env!("CARGO_CRATE_NAME"),
+    )]));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(config)
+        .install_batch(runtime::Tokio)
+        .map_err(|e| anyhow!("Failed to init otlp exporter, {e}"))?;
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    // Leave out the spans of dependencies, such as hyper's for parsing headers
+    let filter = Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::TRACE);
+    tracing_subscriber::registry()
+        .with(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(filter),
+        )
+        .try_init()
+        .map_err(|e| anyhow!("Failed to init tracing, {e}"))?;
+    Ok(())
+}
+
+/// Continues the trace of the caller given with the `traceparent` header
+pub fn set_parent(span: &Span, headers: &HeaderMap) {
+    let context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    span.set_parent(context);
+}
+
+/// Exports the remaining spans
+pub async fn shutdown() {
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|v| v.as_str()).collect()
+    }
+}
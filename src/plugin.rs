@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Context as _, Result};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use wasmtime::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::events::FsEvent;
+use crate::middleware::{Context, Middleware};
+use crate::server::{Request, Response};
+use crate::utils::{decode_uri, get_file_name};
+
+/// Fuel a plugin may burn in one call, so a plugin stuck in a loop can't hang requests
+const CALL_FUEL: u64 = 100_000_000;
+
+/// A WebAssembly module loaded with `--plugin`, consulted on every request.
+///
+/// The module exports its `memory`, an `alloc(len) -> ptr` for the host to pass it
+/// JSON, and any of `on_request`, `on_response` and `on_event`, which take the pointer
+/// and length of the JSON. `on_request` and `on_response` return the pointer and length
+/// of their JSON reply packed as `ptr << 32 | len`, or 0 for no reply. The host provides
+/// `dufs.log(ptr, len)` to log a message.
+pub struct Plugin {
+    name: String,
+    inner: Mutex<Instance>,
+}
+
+struct Instance {
+    store: Store<String>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    on_request: Option<TypedFunc<(i32, i32), i64>>,
+    on_response: Option<TypedFunc<(i32, i32), i64>>,
+    on_event: Option<TypedFunc<(i32, i32), ()>>,
+}
+
+/// What a plugin replies to `on_request` and `on_response`
+#[derive(Debug, Default, Deserialize)]
+struct Reply {
+    /// Answers the request with this status instead of the server, in `on_request`
+    status: Option<u16>,
+    #[serde(default)]
+    body: String,
+    /// Headers to add to the response
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+/// Headers the plugins asked for in `on_request`, added to the response
+#[derive(Debug, Default)]
+struct Annotations(Vec<(String, String)>);
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = get_file_name(path).to_string();
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)
+            .with_context(|| format!("Failed to load plugin `{}`", path.display()))?;
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap(
+            "dufs",
+            "log",
+            |mut caller: Caller<'_, String>, ptr: i32, len: i32| {
+                if let Some(Extern::Memory(memory)) = caller.get_export("memory") {
+                    let message = read(memory.data(&caller), ptr, len)
+                        .map(|v| String::from_utf8_lossy(v).to_string());
+                    if let Some(message) = message {
+                        info!("Plugin {}: {}", caller.data(), message);
+                    }
+                }
+            },
+        )?;
+        let mut store = Store::new(&engine, name.clone());
+        store.set_fuel(CALL_FUEL)?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .with_context(|| format!("Failed to start plugin `{name}`"))?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("Plugin `{name}` exports no `memory`"))?;
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .with_context(|| format!("Plugin `{name}` exports no `alloc`"))?;
+        let on_request = instance.get_typed_func(&mut store, "on_request").ok();
+        let on_response = instance.get_typed_func(&mut store, "on_response").ok();
+        let on_event = instance.get_typed_func(&mut store, "on_event").ok();
+        Ok(Self {
+            name,
+            inner: Mutex::new(Instance {
+                store,
+                memory,
+                alloc,
+                on_request,
+                on_response,
+                on_event,
+            }),
+        })
+    }
+
+    fn reply(&self, reply: Option<Vec<u8>>) -> Result<Reply> {
+        match reply {
+            Some(reply) => serde_json::from_slice(&reply)
+                .with_context(|| format!("Invalid reply of plugin `{}`", self.name)),
+            None => Ok(Reply::default()),
+        }
+    }
+
+    fn on_request(&self, req: &Request, ctx: &Context) -> Result<Reply> {
+        let mut inner = self.inner.lock().unwrap();
+        let func = match inner.on_request.clone() {
+            Some(v) => v,
+            None => return Ok(Reply::default()),
+        };
+        let headers: HashMap<&str, &str> = req
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?)))
+            .collect();
+        let input = serde_json::json!({
+            "method": req.method().as_str(),
+            "path": decode_uri(req.uri().path()).unwrap_or_default(),
+            "query": req.uri().query().unwrap_or_default(),
+            "headers": headers,
+            "remote_addr": ctx.remote_addr.map(|v| v.to_string()),
+        });
+        let reply = inner.call(func, &input)?;
+        self.reply(reply)
+    }
+
+    fn on_response(&self, res: &Response, ctx: &Context) -> Result<Reply> {
+        let mut inner = self.inner.lock().unwrap();
+        let func = match inner.on_response.clone() {
+            Some(v) => v,
+            None => return Ok(Reply::default()),
+        };
+        let input = serde_json::json!({
+            "path": decode_uri(ctx.uri.path()).unwrap_or_default(),
+            "status": res.status().as_u16(),
+        });
+        let reply = inner.call(func, &input)?;
+        self.reply(reply)
+    }
+}
+
+impl Instance {
+    /// Hands the JSON to the hook, returning the reply it made
+    fn call(
+        &mut self,
+        func: TypedFunc<(i32, i32), i64>,
+        input: &serde_json::Value,
+    ) -> Result<Option<Vec<u8>>> {
+        let (ptr, len) = self.write(input)?;
+        let ret = func.call(&mut self.store, (ptr, len))?;
+        if ret == 0 {
+            return Ok(None);
+        }
+        let (ptr, len) = ((ret >> 32) as i32, ret as i32);
+        let reply = read(self.memory.data(&self.store), ptr, len)
+            .ok_or_else(|| anyhow!("Reply out of the memory of the plugin"))?;
+        Ok(Some(reply.to_vec()))
+    }
+
+    fn write(&mut self, input: &serde_json::Value) -> Result<(i32, i32)> {
+        self.store.set_fuel(CALL_FUEL)?;
+        let input = serde_json::to_vec(input)?;
+        let len = i32::try_from(input.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, &input)?;
+        Ok((ptr, len))
+    }
+}
+
+impl Middleware for Plugin {
+    fn request(&self, req: &mut Request, ctx: &mut Context) -> Option<Response> {
+        let reply = match self.on_request(req, ctx) {
+            Ok(v) => v,
+            Err(err) => {
+                ctx.error = Some(format!("Plugin `{}` failed, {err:#}", self.name));
+                let mut res = Response::default();
+                *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return Some(res);
+            }
+        };
+        if !reply.headers.is_empty() {
+            let annotations = match ctx.extensions.get_mut::<Annotations>() {
+                Some(v) => v,
+                None => {
+                    ctx.extensions.insert(Annotations::default());
+                    ctx.extensions.get_mut::<Annotations>().unwrap()
+                }
+            };
+            annotations.0.extend(reply.headers);
+        }
+        let status = StatusCode::from_u16(reply.status?).ok()?;
+        let mut res = Response::new(Body::from(reply.body));
+        *res.status_mut() = status;
+        Some(res)
+    }
+
+    fn response(&self, res: &mut Response, ctx: &mut Context) {
+        if let Some(Annotations(headers)) = ctx.extensions.remove::<Annotations>() {
+            add_headers(res, headers);
+        }
+        match self.on_response(res, ctx) {
+            Ok(reply) => add_headers(res, reply.headers),
+            Err(err) => error!("Plugin `{}` failed, {err:#}", self.name),
+        }
+    }
+
+    fn event(&self, event: &FsEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        let func = match inner.on_event.clone() {
+            Some(v) => v,
+            None => return,
+        };
+        let ret = serde_json::to_value(event)
+            .map_err(anyhow::Error::from)
+            .and_then(|input| inner.write(&input))
+            .and_then(|args| func.call(&mut inner.store, args));
+        if let Err(err) = ret {
+            error!("Plugin `{}` failed, {err:#}", self.name);
+        }
+    }
+}
+
+fn add_headers(res: &mut Response, headers: impl IntoIterator<Item = (String, String)>) {
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            res.headers_mut().append(name, value);
+        }
+    }
+}
+
+fn read(data: &[u8], ptr: i32, len: i32) -> Option<&[u8]> {
+    let start = ptr as u32 as usize;
+    data.get(start..start.checked_add(len as u32 as usize)?)
+}
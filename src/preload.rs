@@ -0,0 +1,319 @@
+use anyhow::{anyhow, Result};
+use hyper::body::Bytes;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::fs::{File, Metadata};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+
+use crate::server::{is_hidden, walk_visible};
+use crate::utils::get_file_name;
+
+/// The served files and folders kept in memory, along with their metadata.
+///
+/// The tree is read at startup, then kept up to date by a filesystem watcher. The bytes of
+/// the files are kept until they add up to `max_size`, the files past it are read from disk.
+pub struct Preload {
+    files: Arc<Files>,
+    _watchers: Vec<RecommendedWatcher>,
+}
+
+/// A preloaded file or folder
+#[derive(Clone)]
+pub struct Entry {
+    /// The metadata of the entry, or of where it leads for a symlink
+    pub meta: Metadata,
+    pub is_symlink: bool,
+    /// The bytes of a file, unless they didn't fit
+    pub data: Option<Bytes>,
+}
+
+struct Files {
+    roots: Vec<PathBuf>,
+    hidden: Vec<String>,
+    posix_hidden: bool,
+    running: Arc<AtomicBool>,
+    max_size: u64,
+    inner: RwLock<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Sorted so that a folder is followed by its entries. Symlinked folders aren't followed.
+    entries: BTreeMap<PathBuf, Entry>,
+    /// The bytes of all the files kept
+    size: u64,
+}
+
+impl Preload {
+    pub fn new(
+        roots: Vec<PathBuf>,
+        hidden: Vec<String>,
+        posix_hidden: bool,
+        running: Arc<AtomicBool>,
+        max_size: u64,
+    ) -> Result<Self> {
+        let files = Arc::new(Files {
+            roots,
+            hidden,
+            posix_hidden,
+            running,
+            max_size,
+            inner: Default::default(),
+        });
+        // Watch before reading, so the changes made meanwhile aren't missed
+        let mut watchers = vec![];
+        for root in &files.roots {
+            let watched = files.clone();
+            let mut watcher =
+                notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                    Ok(event) => watched.apply(event),
+                    Err(err) => warn!("Preload watcher failed, {err}"),
+                })?;
+            watcher
+                .watch(root, RecursiveMode::Recursive)
+                .map_err(|e| anyhow!("Failed to watch `{}` for preload, {e}", root.display()))?;
+            watchers.push(watcher);
+        }
+        let skipped = files.reload();
+        if skipped > 0 {
+            warn!("{skipped} files don't fit in the preload size, they are read from disk");
+        }
+        Ok(Self {
+            files,
+            _watchers: watchers,
+        })
+    }
+
+    /// The entry at `path`, None if it's not preloaded, for being hidden or out of reach
+    pub fn get(&self, path: &Path) -> Option<Entry> {
+        self.files.inner.read().unwrap().entries.get(path).cloned()
+    }
+
+    /// The paths of the entries of the folder, None if it's not preloaded
+    pub fn children(&self, dir: &Path) -> Option<Vec<PathBuf>> {
+        let inner = self.files.inner.read().unwrap();
+        if !inner.entries.get(dir)?.is_plain_dir() {
+            return None;
+        }
+        let paths = inner
+            .entries
+            .range(dir.to_path_buf()..)
+            .skip(1)
+            .take_while(|(path, _)| path.starts_with(dir))
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .map(|(path, _)| path.clone())
+            .collect();
+        Some(paths)
+    }
+
+    /// Takes the entry at `path`, and the ones inside it, as they are now on disk, right
+    /// away rather than once the watcher gets to it
+    pub fn refresh(&self, path: &Path) {
+        self.files.refresh(path);
+    }
+}
+
+impl Entry {
+    fn is_plain_dir(&self) -> bool {
+        !self.is_symlink && self.meta.is_dir()
+    }
+}
+
+impl Files {
+    /// Reads the whole tree again, returns how many files were read from disk for not fitting
+    fn reload(&self) -> usize {
+        *self.inner.write().unwrap() = Default::default();
+        let mut skipped = 0;
+        for root in &self.roots {
+            if !self.insert(root) {
+                skipped += 1;
+            }
+            walk_visible(
+                std::slice::from_ref(root),
+                &self.hidden,
+                self.posix_hidden,
+                &self.running,
+                |path, _| {
+                    if !self.insert(path) {
+                        skipped += 1;
+                    }
+                    true
+                },
+            );
+        }
+        skipped
+    }
+
+    fn apply(&self, event: Event) {
+        if event.need_rescan() {
+            self.reload();
+            return;
+        }
+        match event.kind {
+            EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_)) => {
+                for path in &event.paths {
+                    self.update(path);
+                }
+            }
+            EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(_) => {
+                for path in &event.paths {
+                    self.refresh(path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes the entry at `path` as it is now on disk, if it's kept
+    fn update(&self, path: &Path) {
+        if self.inner.read().unwrap().entries.contains_key(path) {
+            self.insert(path);
+        }
+    }
+
+    fn refresh(&self, path: &Path) {
+        if self.roots.iter().any(|root| root == path) {
+            self.insert(path);
+            return;
+        }
+        if !self.roots.iter().any(|root| path.starts_with(root)) {
+            return;
+        }
+        self.remove(path);
+        if let Some(parent) = path.parent() {
+            self.update(parent);
+        }
+        let meta = match std::fs::symlink_metadata(path) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        // Only the entries of plain folders are kept, this leaves out the ones that are
+        // hidden or inside a symlink
+        let in_plain_dir = {
+            let inner = self.inner.read().unwrap();
+            path.parent()
+                .and_then(|v| inner.entries.get(v))
+                .is_some_and(|v| v.is_plain_dir())
+        };
+        if !in_plain_dir
+            || is_hidden(
+                &self.hidden,
+                self.posix_hidden,
+                get_file_name(path),
+                meta.is_dir(),
+            )
+        {
+            return;
+        }
+        if !self.insert(path) {
+            debug!(
+                "`{}` doesn't fit in the preload size, it's read from disk",
+                path.display()
+            );
+        }
+        if meta.is_dir() {
+            walk_visible(
+                &[path.to_path_buf()],
+                &self.hidden,
+                self.posix_hidden,
+                &self.running,
+                |path, _| {
+                    self.insert(path);
+                    true
+                },
+            );
+        }
+    }
+
+    fn remove(&self, path: &Path) {
+        let mut inner = self.inner.write().unwrap();
+        let removed: Vec<PathBuf> = inner
+            .entries
+            .range(path.to_path_buf()..)
+            .take_while(|(v, _)| v.starts_with(path))
+            .map(|(v, _)| v.clone())
+            .collect();
+        for v in removed {
+            if let Some(data) = inner.entries.remove(&v).and_then(|v| v.data) {
+                inner.size -= data.len() as u64;
+            }
+        }
+    }
+
+    /// Keeps the entry at `path`, returns false if it's a file whose bytes didn't fit
+    fn insert(&self, path: &Path) -> bool {
+        let link_meta = match std::fs::symlink_metadata(path) {
+            Ok(v) => v,
+            Err(_) => {
+                self.remove(path);
+                return true;
+            }
+        };
+        let is_symlink = link_meta.is_symlink();
+        let (meta, data) = if is_symlink {
+            match std::fs::metadata(path) {
+                Ok(v) => (v, None),
+                Err(_) => {
+                    self.remove(path);
+                    return true;
+                }
+            }
+        } else if link_meta.is_file() && self.fits(path, link_meta.len()) {
+            match read_file(path) {
+                Some((meta, data)) => (meta, Some(data)),
+                None => (link_meta, None),
+            }
+        } else {
+            (link_meta, None)
+        };
+        let is_file = !is_symlink && meta.is_file();
+        let mut inner = self.inner.write().unwrap();
+        let old_size = inner
+            .entries
+            .get(path)
+            .and_then(|v| v.data.as_ref())
+            .map(|v| v.len() as u64)
+            .unwrap_or_default();
+        let size = inner.size - old_size;
+        let data = data.filter(|v| size + v.len() as u64 <= self.max_size);
+        inner.size = size + data.as_ref().map(|v| v.len() as u64).unwrap_or_default();
+        let fits = !is_file || data.is_some();
+        inner.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                meta,
+                is_symlink,
+                data,
+            },
+        );
+        fits
+    }
+
+    /// Whether a file of `len` bytes fits, in place of the one at `path`
+    fn fits(&self, path: &Path, len: u64) -> bool {
+        let inner = self.inner.read().unwrap();
+        let old_size = inner
+            .entries
+            .get(path)
+            .and_then(|v| v.data.as_ref())
+            .map(|v| v.len() as u64)
+            .unwrap_or_default();
+        inner.size - old_size + len <= self.max_size
+    }
+}
+
+/// The metadata and the bytes of the file, None if it changed while being read
+fn read_file(path: &Path) -> Option<(Metadata, Bytes)> {
+    let mut file = File::open(path).ok()?;
+    let mut data = vec![];
+    file.read_to_end(&mut data).ok()?;
+    let meta = file.metadata().ok()?;
+    if meta.len() != data.len() as u64 {
+        return None;
+    }
+    Some((meta, data.into()))
+}
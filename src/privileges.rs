@@ -0,0 +1,93 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::path::Path;
+
+/// Switches to the user and group, and confines the process to the root, so dufs started as
+/// root to bind low ports doesn't keep root once they are bound.
+///
+/// The user and group are looked up before the chroot, which hides the databases they are
+/// in. The group defaults to the one of the user, and the other groups of a user given by
+/// name are kept.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>, root: Option<&Path>) -> Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        bail!("Need dufs to be started as root to switch users or chroot");
+    }
+    let user = user.map(find_user).transpose()?;
+    let gid = match (group, &user) {
+        (Some(group), _) => Some(find_group(group)?),
+        (None, Some(User { gid: Some(gid), .. })) => Some(*gid),
+        (None, Some(_)) => bail!("The user isn't in the user database, give its `--group`"),
+        (None, None) => None,
+    };
+    if let Some(root) = root {
+        std::os::unix::fs::chroot(root)
+            .with_context(|| format!("Failed to chroot to `{}`", root.display()))?;
+        std::env::set_current_dir("/")?;
+    }
+    if let Some(gid) = gid {
+        let ret = match user.as_ref().and_then(|v| v.name.as_ref()) {
+            Some(name) => unsafe { libc::initgroups(name.as_ptr(), gid as _) },
+            None => unsafe { libc::setgroups(1, &gid) },
+        };
+        check(ret).context("Failed to set the supplementary groups")?;
+        check(unsafe { libc::setgid(gid) }).context("Failed to switch group")?;
+    }
+    if let Some(user) = user {
+        check(unsafe { libc::setuid(user.uid) }).context("Failed to switch user")?;
+    }
+    Ok(())
+}
+
+struct User {
+    /// Set when the user is in the user database, for its groups
+    name: Option<CString>,
+    uid: libc::uid_t,
+    gid: Option<libc::gid_t>,
+}
+
+/// Looks up a user by name, or by id
+fn find_user(user: &str) -> Result<User> {
+    let name = CString::new(user)?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if let Some(passwd) = unsafe { passwd.as_ref() } {
+        return Ok(User {
+            name: Some(unsafe { CStr::from_ptr(passwd.pw_name) }.into()),
+            uid: passwd.pw_uid,
+            gid: Some(passwd.pw_gid),
+        });
+    }
+    let uid: libc::uid_t = user.parse().map_err(|_| anyhow!("Unknown user `{user}`"))?;
+    let passwd = unsafe { libc::getpwuid(uid) };
+    match unsafe { passwd.as_ref() } {
+        Some(passwd) => Ok(User {
+            name: Some(unsafe { CStr::from_ptr(passwd.pw_name) }.into()),
+            uid,
+            gid: Some(passwd.pw_gid),
+        }),
+        None => Ok(User {
+            name: None,
+            uid,
+            gid: None,
+        }),
+    }
+}
+
+/// Looks up a group by name, or by id
+fn find_group(group: &str) -> Result<libc::gid_t> {
+    let name = CString::new(group)?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    match unsafe { entry.as_ref() } {
+        Some(entry) => Ok(entry.gr_gid),
+        None => group
+            .parse()
+            .map_err(|_| anyhow!("Unknown group `{group}`")),
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
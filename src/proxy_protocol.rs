@@ -0,0 +1,222 @@
+use futures::future::BoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest v1 header, `PROXY TCP6 <ip> <ip> <port> <port>\r\n`
+const V1_MAX_LEN: usize = 107;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts connections, which start with a HAProxy PROXY protocol header if enabled.
+///
+/// Headers are read concurrently, so a slow client doesn't hold up the others.
+pub struct ProxyAcceptor {
+    incoming: AddrIncoming,
+    enabled: bool,
+    pending: FuturesUnordered<BoxFuture<'static, io::Result<ProxyStream>>>,
+}
+
+impl ProxyAcceptor {
+    pub fn new(incoming: AddrIncoming, enabled: bool) -> Self {
+        Self {
+            incoming,
+            enabled,
+            pending: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl Accept for ProxyAcceptor {
+    type Conn = ProxyStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let pin = self.get_mut();
+        loop {
+            match Pin::new(&mut pin.incoming).poll_accept(cx) {
+                Poll::Ready(Some(Ok(stream))) => {
+                    if !pin.enabled {
+                        let remote_addr = stream.remote_addr();
+                        return Poll::Ready(Some(Ok(ProxyStream {
+                            stream,
+                            remote_addr,
+                        })));
+                    }
+                    pin.pending.push(Box::pin(async move {
+                        tokio::time::timeout(HEADER_TIMEOUT, read_header(stream))
+                            .await
+                            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timeout"))?
+                    }));
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            match pin.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(stream))) => return Poll::Ready(Some(Ok(stream))),
+                // Drop the connection, an error would stop the server
+                Poll::Ready(Some(Err(err))) => debug!("Invalid PROXY protocol header, {err}"),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Connection whose remote address is the client's one from the PROXY protocol header
+pub struct ProxyStream {
+    stream: AddrStream,
+    remote_addr: SocketAddr,
+}
+
+impl ProxyStream {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// Reads the header without consuming anything after it
+async fn read_header(mut stream: AddrStream) -> io::Result<ProxyStream> {
+    // Both a v2 header and the shortest v1 one, `PROXY UNKNOWN\r\n`, are at least 15 bytes
+    let mut buf = vec![0; 15];
+    stream.read_exact(&mut buf).await?;
+    let addr = if buf.starts_with(V2_SIGNATURE) {
+        let mut rest = [0; 1];
+        stream.read_exact(&mut rest).await?;
+        buf.extend_from_slice(&rest);
+        let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        let mut addrs = vec![0; len];
+        stream.read_exact(&mut addrs).await?;
+        parse_v2(buf[12], buf[13], &addrs)
+    } else if buf.starts_with(b"PROXY ") {
+        while !buf.ends_with(b"\r\n") {
+            if buf.len() >= V1_MAX_LEN {
+                return Err(invalid_header());
+            }
+            buf.push(stream.read_u8().await?);
+        }
+        parse_v1(&buf)
+    } else {
+        return Err(invalid_header());
+    }
+    .ok_or_else(invalid_header)?;
+    let remote_addr = addr.unwrap_or_else(|| stream.remote_addr());
+    Ok(ProxyStream {
+        stream,
+        remote_addr,
+    })
+}
+
+/// Returns `Some(None)` for a valid header without the client address
+fn parse_v1(header: &[u8]) -> Option<Option<SocketAddr>> {
+    let header = std::str::from_utf8(header).ok()?.strip_suffix("\r\n")?;
+    let parts: Vec<&str> = header.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Some(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, sport, _dport] => {
+            let ip: IpAddr = src.parse().ok()?;
+            Some(Some(SocketAddr::new(ip, sport.parse().ok()?)))
+        }
+        _ => None,
+    }
+}
+
+fn parse_v2(ver_cmd: u8, family: u8, addrs: &[u8]) -> Option<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 {
+        return None;
+    }
+    match ver_cmd & 0x0f {
+        // LOCAL, e.g. health checks of the proxy itself
+        0 => return Some(None),
+        1 => {}
+        _ => return None,
+    }
+    match family >> 4 {
+        1 if addrs.len() >= 12 => {
+            let ip: [u8; 4] = addrs[0..4].try_into().ok()?;
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Some(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        2 if addrs.len() >= 36 => {
+            let ip: [u8; 16] = addrs[0..16].try_into().ok()?;
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Some(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        1 | 2 => None,
+        _ => Some(None),
+    }
+}
+
+fn invalid_header() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1() {
+        assert_eq!(
+            parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n"),
+            Some(Some("192.168.0.1:56324".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_v1(b"PROXY TCP6 ::1 ::1 56324 443\r\n"),
+            Some(Some("[::1]:56324".parse().unwrap()))
+        );
+        assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n"), Some(None));
+        assert_eq!(parse_v1(b"PROXY TCP4 invalid\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let addrs = [10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0, 80];
+        assert_eq!(
+            parse_v2(0x21, 0x11, &addrs),
+            Some(Some("10.0.0.1:8080".parse().unwrap()))
+        );
+        assert_eq!(parse_v2(0x20, 0x00, &[]), Some(None));
+        assert_eq!(parse_v2(0x21, 0x11, &addrs[..4]), None);
+        assert_eq!(parse_v2(0x11, 0x11, &addrs), None);
+    }
+}
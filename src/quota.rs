@@ -0,0 +1,82 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Usage is scanned again after this long, to catch changes made outside the server
+const RESCAN_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Disk usage limits of folders.
+///
+/// The usage of a folder is scanned the first time it's needed, then kept up to date
+/// by uploads. Deletes, moves and copies have it scanned again.
+#[derive(Default)]
+pub struct Quotas {
+    quotas: Vec<Quota>,
+}
+
+struct Quota {
+    root: PathBuf,
+    limit: u64,
+    usage: Mutex<Option<(u64, Instant)>>,
+}
+
+impl Quotas {
+    pub fn new(limits: Vec<(PathBuf, u64)>) -> Self {
+        let quotas = limits
+            .into_iter()
+            .map(|(root, limit)| Quota {
+                root,
+                limit,
+                usage: Mutex::new(None),
+            })
+            .collect();
+        Self { quotas }
+    }
+
+    /// Whether writing `incoming` more bytes at `path` would exceed a quota
+    pub async fn exceeded(&self, path: &Path, incoming: u64) -> Result<bool> {
+        for quota in self.quotas.iter().filter(|v| path.starts_with(&v.root)) {
+            if quota.usage().await?.saturating_add(incoming) > quota.limit {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Accounts for a file at `path` that grew, or shrank, by `delta` bytes
+    pub fn add(&self, path: &Path, delta: i64) {
+        for quota in self.quotas.iter().filter(|v| path.starts_with(&v.root)) {
+            if let Some((usage, _)) = quota.usage.lock().unwrap().as_mut() {
+                *usage = usage.saturating_add_signed(delta);
+            }
+        }
+    }
+
+    /// Has the usage of the quotas covering `path` scanned again
+    pub fn invalidate(&self, path: &Path) {
+        for quota in self.quotas.iter().filter(|v| path.starts_with(&v.root)) {
+            *quota.usage.lock().unwrap() = None;
+        }
+    }
+}
+
+impl Quota {
+    async fn usage(&self) -> Result<u64> {
+        if let Some((usage, scanned_at)) = *self.usage.lock().unwrap() {
+            if scanned_at.elapsed() < RESCAN_INTERVAL {
+                return Ok(usage);
+            }
+        }
+        let root = self.root.clone();
+        let usage = match tokio::task::spawn_blocking(move || fs_extra::dir::get_size(root)).await?
+        {
+            Ok(usage) => usage,
+            // Not created yet
+            Err(_) if !self.root.exists() => 0,
+            Err(err) => return Err(err.into()),
+        };
+        *self.usage.lock().unwrap() = Some((usage, Instant::now()));
+        Ok(usage)
+    }
+}
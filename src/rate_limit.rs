@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::utils::parse_duration;
+
+/// Past this many clients, the ones whose buckets have filled up again are forgotten
+const CLIENTS_CAPACITY: usize = 10_000;
+
+/// How many requests a client may make, `<requests>r/<duration>` such as `10r/s` or `600r/5m`.
+///
+/// Each client has a bucket of `requests` tokens, which refills at that rate, so it may
+/// also send them all at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    requests: u32,
+    secs: u64,
+}
+
+impl RateLimit {
+    pub fn parse(value: &str) -> Result<Self> {
+        let err = || anyhow!("Invalid rate limit `{value}`, e.g. 10r/s");
+        let (requests, per) = value.split_once("r/").ok_or_else(err)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| err())?;
+        let per = per.trim();
+        let per = match per.starts_with(|c: char| c.is_ascii_digit()) {
+            true => parse_duration(per),
+            false => parse_duration(&format!("1{per}")),
+        };
+        let secs = per.map_err(|_| err())?.as_secs();
+        if requests == 0 || secs == 0 {
+            return Err(err());
+        }
+        Ok(Self { requests, secs })
+    }
+
+    /// Tokens added per second
+    fn rate(&self) -> f64 {
+        self.requests as f64 / self.secs as f64
+    }
+}
+
+/// Who a request counts against, the user if it's known, otherwise the IP
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Client {
+    User(String),
+    Ip(IpAddr),
+}
+
+impl Client {
+    pub fn new(user: Option<&str>, ip: Option<IpAddr>) -> Option<Self> {
+        match (user, ip) {
+            (Some(user), _) => Some(Self::User(user.to_string())),
+            (None, Some(ip)) => Some(Self::Ip(ip)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The token buckets of the clients
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    /// Tokens left and when they were counted
+    buckets: Mutex<HashMap<Client, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Takes a token for the request, or returns the seconds until one is left
+    pub fn acquire(&self, client: Client) -> Result<(), u64> {
+        let capacity = self.limit.requests as f64;
+        let rate = self.limit.rate();
+        let now = Instant::now();
+        let refill = |tokens: f64, last: Instant| {
+            (tokens + now.duration_since(last).as_secs_f64() * rate).min(capacity)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= CLIENTS_CAPACITY && !buckets.contains_key(&client) {
+            buckets.retain(|_, (tokens, last)| refill(*tokens, *last) < capacity);
+        }
+        let (tokens, last) = buckets.entry(client).or_insert((capacity, now));
+        *tokens = refill(*tokens, *last);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) / rate).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        assert_eq!(
+            RateLimit::parse("10r/s").unwrap(),
+            RateLimit {
+                requests: 10,
+                secs: 1
+            }
+        );
+        assert_eq!(
+            RateLimit::parse("600r/5m").unwrap(),
+            RateLimit {
+                requests: 600,
+                secs: 300
+            }
+        );
+        assert!(RateLimit::parse("10").is_err());
+        assert!(RateLimit::parse("0r/s").is_err());
+        assert!(RateLimit::parse("10r/0s").is_err());
+        assert!(RateLimit::parse("10r/x").is_err());
+
+        let limiter = RateLimiter::new(RateLimit::parse("2r/m").unwrap());
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(limiter.acquire(Client::Ip(ip)).is_ok());
+        assert!(limiter.acquire(Client::Ip(ip)).is_ok());
+        let retry_after = limiter.acquire(Client::Ip(ip)).unwrap_err();
+        assert!((29..=30).contains(&retry_after));
+        assert!(limiter.acquire(Client::User("admin".into())).is_ok());
+        assert!(limiter
+            .acquire(Client::Ip("10.0.0.2".parse().unwrap()))
+            .is_ok());
+    }
+}
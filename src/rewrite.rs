@@ -0,0 +1,214 @@
+use anyhow::{anyhow, bail, Result};
+use hyper::header::{HeaderValue, LOCATION};
+use hyper::{StatusCode, Uri};
+
+use crate::middleware::{Context, Middleware};
+use crate::server::{Request, Response};
+
+/// Rules mapping request paths to others, `<pattern>=<target>` such as `/old/*=/new/$1`.
+///
+/// Each `*` of the pattern matches any run of characters, slashes included, and `$1` to
+/// `$9` in the target are what they matched, in order. Paths are matched as they are sent,
+/// still percent-encoded, and the first rule matching wins.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RewriteRules {
+    rules: Vec<RewriteRule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RewriteRule {
+    pattern: String,
+    target: String,
+    /// Of the redirect, 301 unless the target starts with another, e.g. `302:/new`
+    status: StatusCode,
+}
+
+impl RewriteRules {
+    /// Rules answering with a redirect to the target, a path or a URL
+    pub fn redirects(rules: &[&str]) -> Result<Self> {
+        Self::new(rules, true)
+    }
+
+    /// Rules handling the request as if it were for the target path
+    pub fn rewrites(rules: &[&str]) -> Result<Self> {
+        Self::new(rules, false)
+    }
+
+    fn new(rules: &[&str], redirect: bool) -> Result<Self> {
+        let kind = if redirect { "redirect" } else { "rewrite" };
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let err = || anyhow!("Invalid {kind} `{rule}`, e.g. /old/*=/new/$1");
+                let (pattern, target) = rule.split_once('=').ok_or_else(err)?;
+                let (pattern, mut target) = (pattern.trim(), target.trim());
+                let mut status = StatusCode::MOVED_PERMANENTLY;
+                if let Some((code, rest)) = target.split_once(':') {
+                    if let Ok(code) = code.parse::<u16>() {
+                        if !redirect || !matches!(code, 301 | 302 | 303 | 307 | 308) {
+                            return Err(err());
+                        }
+                        status = StatusCode::from_u16(code)?;
+                        target = rest;
+                    }
+                }
+                if !pattern.starts_with('/') || target.is_empty() {
+                    return Err(err());
+                }
+                if !redirect && !target.starts_with('/') {
+                    return Err(err());
+                }
+                let stars = pattern.matches('*').count();
+                let mut chars = target.chars();
+                while let Some(c) = chars.next() {
+                    if c == '$' {
+                        match chars.next().and_then(|v| v.to_digit(10)) {
+                            Some(n) if (1..=stars as u32).contains(&n) => {}
+                            _ => bail!(
+                                "Invalid {kind} `{rule}`, `$` must be followed by the number of a `*`"
+                            ),
+                        }
+                    }
+                }
+                Ok(RewriteRule {
+                    pattern: pattern.to_string(),
+                    target: target.to_string(),
+                    status,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The target of the first rule matching the path, and the status to redirect with
+    fn apply(&self, path: &str) -> Option<(String, StatusCode)> {
+        self.rules.iter().find_map(|rule| {
+            let captures = captures(&rule.pattern, path)?;
+            let mut target = String::new();
+            let mut chars = rule.target.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '$' => {
+                        let n = chars.next()?.to_digit(10)? as usize;
+                        target.push_str(captures.get(n - 1)?);
+                    }
+                    c => target.push(c),
+                }
+            }
+            Some((target, rule.status))
+        })
+    }
+}
+
+/// Answers the `--redirect` rules and rewrites the paths of the `--rewrite` ones, before
+/// the paths are resolved, so auth and everything else see the target
+pub struct Rewrite {
+    redirects: RewriteRules,
+    rewrites: RewriteRules,
+}
+
+impl Rewrite {
+    pub fn new(redirects: RewriteRules, rewrites: RewriteRules) -> Self {
+        Self {
+            redirects,
+            rewrites,
+        }
+    }
+}
+
+impl Middleware for Rewrite {
+    fn request(&self, req: &mut Request, _ctx: &mut Context) -> Option<Response> {
+        let path = req.uri().path();
+        let query = req.uri().query();
+        if let Some((target, status)) = self.redirects.apply(path) {
+            let mut res = Response::default();
+            match HeaderValue::from_str(&with_query(target, query)) {
+                Ok(location) => {
+                    *res.status_mut() = status;
+                    res.headers_mut().insert(LOCATION, location);
+                }
+                Err(_) => *res.status_mut() = StatusCode::BAD_REQUEST,
+            }
+            return Some(res);
+        }
+        if let Some((target, _)) = self.rewrites.apply(path) {
+            match with_query(target, query).parse::<Uri>() {
+                Ok(uri) => *req.uri_mut() = uri,
+                Err(_) => {
+                    let mut res = Response::default();
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    return Some(res);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The target with the query of the request, after the one of the target if it has one
+fn with_query(target: String, query: Option<&str>) -> String {
+    match query {
+        Some(query) if target.contains('?') => format!("{target}&{query}"),
+        Some(query) => format!("{target}?{query}"),
+        None => target,
+    }
+}
+
+/// What the `*` of the pattern match in the path, if it matches, the earlier ones as little
+/// as they can
+fn captures<'a>(pattern: &str, path: &'a str) -> Option<Vec<&'a str>> {
+    match pattern.split_once('*') {
+        None => (pattern == path).then(Vec::new),
+        Some((head, rest)) => {
+            let path = path.strip_prefix(head)?;
+            path.char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(path.len()))
+                .find_map(|i| {
+                    let mut output = captures(rest, &path[i..])?;
+                    output.insert(0, &path[..i]);
+                    Some(output)
+                })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_rules() {
+        let rules = RewriteRules::redirects(&[
+            "/old/*=/new/$1",
+            "/go/*/*=302:https://example.com/$2/$1",
+            "/about=/about/",
+        ])
+        .unwrap();
+        assert_eq!(
+            rules.apply("/old/a/b.txt"),
+            Some(("/new/a/b.txt".into(), StatusCode::MOVED_PERMANENTLY))
+        );
+        assert_eq!(
+            rules.apply("/go/x/y/z"),
+            Some(("https://example.com/y/z/x".into(), StatusCode::FOUND))
+        );
+        assert!(rules.apply("/about").is_some());
+        assert!(rules.apply("/about/").is_none());
+        assert!(rules.apply("/older/a").is_none());
+
+        let rules = RewriteRules::rewrites(&["/u/*=/users/$1/profile.html"]).unwrap();
+        assert_eq!(rules.apply("/u/jo").unwrap().0, "/users/jo/profile.html");
+
+        assert!(RewriteRules::redirects(&["/old"]).is_err());
+        assert!(RewriteRules::redirects(&["old/*=/new/$1"]).is_err());
+        assert!(RewriteRules::redirects(&["/old/*=/new/$2"]).is_err());
+        assert!(RewriteRules::redirects(&["/old=200:/new"]).is_err());
+        assert!(RewriteRules::rewrites(&["/old=302:/new"]).is_err());
+        assert!(RewriteRules::rewrites(&["/old=https://example.com/"]).is_err());
+    }
+}
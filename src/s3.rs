@@ -0,0 +1,813 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::io::Read;
+use std::sync::Arc;
+use xml::escape::escape_str_pcdata;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Uploads are sent in parts of this size, and S3 takes up to `MAX_PARTS` of them
+const PART_SIZE: usize = 16777216; // 16M
+const MAX_PARTS: usize = 10000;
+/// The bodies sent aren't hashed, TLS already guards them
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// The bucket and the prefix of the keys of an `s3://bucket/prefix` serve path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Target {
+    pub bucket: String,
+    /// Without leading or trailing slashes, empty for the whole bucket
+    pub prefix: String,
+}
+
+impl S3Target {
+    /// Returns `None` for a path that isn't an `s3://` URL
+    pub fn parse(value: &str) -> Result<Option<Self>> {
+        let value = match value.strip_prefix("s3://") {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let (bucket, prefix) = value.split_once('/').unwrap_or((value, ""));
+        if bucket.is_empty() {
+            bail!("Invalid object storage `s3://{value}`, expect s3://bucket/prefix");
+        }
+        Ok(Some(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        }))
+    }
+}
+
+/// A file of the bucket, or a folder, which is what the keys sharing a prefix up to a `/`
+/// make up. Folders have no size or mtime of their own.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Milliseconds since the epoch
+    pub mtime: u64,
+    pub etag: Option<String>,
+}
+
+/// A bucket of AWS S3, or of another server speaking its API like MinIO, with requests
+/// signed by AWS Signature Version 4. The requests are blocking, they are made on the
+/// blocking threads.
+pub struct Bucket {
+    agent: ureq::Agent,
+    /// `scheme://host[:port]` the requests are sent to
+    endpoint: String,
+    host: String,
+    /// `/bucket` when the bucket is in the path rather than in the host
+    bucket_path: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl Bucket {
+    /// The bucket of the target, with the credentials and region of the standard AWS
+    /// environment variables. `AWS_ENDPOINT_URL` points to a server other than AWS, whose
+    /// buckets are then addressed in the path.
+    pub fn new(target: &S3Target) -> Result<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let (access_key, secret_key) =
+            match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+                (Some(access_key), Some(secret_key)) => (access_key, secret_key),
+                _ => bail!(
+                    "Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY to serve `s3://{}`",
+                    target.bucket
+                ),
+            };
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let bucket = target.bucket.clone();
+        let (endpoint, bucket_path) = match var("AWS_ENDPOINT_URL_S3").or(var("AWS_ENDPOINT_URL")) {
+            Some(endpoint) => (endpoint, format!("/{bucket}")),
+            // Dots in the name would break the certificate of the host
+            None if bucket.contains('.') => (
+                format!("https://s3.{region}.amazonaws.com"),
+                format!("/{bucket}"),
+            ),
+            None => (
+                format!("https://{bucket}.s3.{region}.amazonaws.com"),
+                String::new(),
+            ),
+        };
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let host = match endpoint.split_once("://") {
+            Some(("http", host)) => host.strip_suffix(":80").unwrap_or(host),
+            Some(("https", host)) => host.strip_suffix(":443").unwrap_or(host),
+            _ => bail!("Invalid endpoint `{endpoint}`, expect http(s)://host[:port]"),
+        };
+        if host.is_empty() || host.contains('/') {
+            bail!("Invalid endpoint `{endpoint}`, expect http(s)://host[:port]");
+        }
+        Ok(Self {
+            agent: ureq::AgentBuilder::new().build(),
+            host: host.to_string(),
+            endpoint,
+            bucket_path,
+            bucket,
+            prefix: target.prefix.clone(),
+            region,
+            access_key,
+            secret_key,
+            session_token: var("AWS_SESSION_TOKEN"),
+        })
+    }
+
+    /// The file or folder at the path relative to the prefix, `None` if there's neither
+    pub async fn stat(self: &Arc<Self>, path: &str) -> Result<Option<Entry>> {
+        let path = path.to_string();
+        self.run(move |bucket| {
+            if path.is_empty() {
+                return Ok(Some(Entry {
+                    is_dir: true,
+                    ..Default::default()
+                }));
+            }
+            if let Some(mut entry) = bucket.head(&path)? {
+                entry.name = path;
+                return Ok(Some(entry));
+            }
+            let page = bucket.list_page(&bucket.dir_prefix(&path), false, None, Some(1))?;
+            Ok(
+                (!page.objects.is_empty() || !page.prefixes.is_empty()).then(|| Entry {
+                    name: path,
+                    is_dir: true,
+                    ..Default::default()
+                }),
+            )
+        })
+        .await
+    }
+
+    /// The files and folders in the folder, by their names
+    pub async fn list(self: &Arc<Self>, dir: &str) -> Result<Vec<Entry>> {
+        let prefix = self.dir_prefix(dir);
+        self.run(move |bucket| {
+            let mut entries = vec![];
+            let mut token = None;
+            loop {
+                let page = bucket.list_page(&prefix, true, token.as_deref(), None)?;
+                for mut object in page.objects {
+                    object.name = object.name[prefix.len()..].to_string();
+                    // The marker of an empty folder
+                    if !object.name.is_empty() {
+                        entries.push(object);
+                    }
+                }
+                for name in page.prefixes {
+                    entries.push(Entry {
+                        name: name[prefix.len()..].trim_end_matches('/').to_string(),
+                        is_dir: true,
+                        ..Default::default()
+                    });
+                }
+                token = match page.next {
+                    Some(v) => Some(v),
+                    None => break,
+                };
+            }
+            Ok(entries)
+        })
+        .await
+    }
+
+    /// The keys in the folder and in its subfolders, relative to it. The markers of the
+    /// folders end in `/`, the one of the folder itself is empty.
+    pub async fn walk(self: &Arc<Self>, dir: &str) -> Result<Vec<String>> {
+        let prefix = self.dir_prefix(dir);
+        self.run(move |bucket| {
+            let mut names = vec![];
+            let mut token = None;
+            loop {
+                let page = bucket.list_page(&prefix, false, token.as_deref(), None)?;
+                names.extend(
+                    page.objects
+                        .into_iter()
+                        .map(|v| v.name[prefix.len()..].to_string()),
+                );
+                token = match page.next {
+                    Some(v) => Some(v),
+                    None => break,
+                };
+            }
+            Ok(names)
+        })
+        .await
+    }
+
+    /// Reads the file, or the inclusive range of its bytes
+    pub async fn get(
+        self: &Arc<Self>,
+        path: &str,
+        range: Option<(u64, u64)>,
+    ) -> Result<Box<dyn Read + Send + Sync>> {
+        let key = self.key(path);
+        self.run(move |bucket| {
+            // The body is taken as stored, ureq would decompress it otherwise
+            let query = [("response-content-encoding", "identity")];
+            let mut req = bucket.request("GET", &key, &query, &[]);
+            if let Some((start, end)) = range {
+                req = req.set("Range", &format!("bytes={start}-{end}"));
+            }
+            let res = req.call().map_err(|err| request_error(err, "GET", &key))?;
+            Ok(res.into_reader())
+        })
+        .await
+    }
+
+    /// Writes the file with the body as it comes, in a multipart upload once it's larger
+    /// than a part. Returns the size of the file.
+    pub async fn put(
+        self: &Arc<Self>,
+        path: &str,
+        body: impl Stream<Item = Result<Bytes>> + Unpin,
+    ) -> Result<u64> {
+        let mut upload = Upload {
+            key: self.key(path),
+            content_type: mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+            id: None,
+            etags: vec![],
+        };
+        let ret = self.send_upload(&mut upload, body).await;
+        if ret.is_err() {
+            if let Some(id) = upload.id {
+                let key = upload.key;
+                let _ = self
+                    .run(move |bucket| {
+                        bucket
+                            .request("DELETE", &key, &[("uploadId", &id)], &[])
+                            .call()?;
+                        Ok(())
+                    })
+                    .await;
+            }
+        }
+        ret
+    }
+
+    async fn send_upload(
+        self: &Arc<Self>,
+        upload: &mut Upload,
+        mut body: impl Stream<Item = Result<Bytes>> + Unpin,
+    ) -> Result<u64> {
+        let mut size = 0;
+        let mut buf = vec![];
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            buf.extend_from_slice(&chunk);
+            while buf.len() >= PART_SIZE {
+                let rest = buf.split_off(PART_SIZE);
+                let part = std::mem::replace(&mut buf, rest);
+                self.send_part(upload, part).await?;
+            }
+        }
+        if upload.id.is_none() {
+            let (key, content_type) = (upload.key.clone(), upload.content_type.clone());
+            self.run(move |bucket| {
+                bucket
+                    .request("PUT", &key, &[], &[("content-type", &content_type)])
+                    .send_bytes(&buf)
+                    .map_err(|err| request_error(err, "PUT", &key))?;
+                Ok(())
+            })
+            .await?;
+            return Ok(size);
+        }
+        if !buf.is_empty() {
+            self.send_part(upload, buf).await?;
+        }
+        let parts: String = upload
+            .etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    i + 1,
+                    escape_str_pcdata(etag)
+                )
+            })
+            .collect();
+        let body = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+        let (key, id) = (upload.key.clone(), upload.id.clone().unwrap_or_default());
+        self.run(move |bucket| {
+            let res = bucket
+                .request("POST", &key, &[("uploadId", &id)], &[])
+                .send_string(&body)
+                .map_err(|err| request_error(err, "POST", &key))?;
+            // Failures to complete can come with a 200 status
+            let text = res.into_string()?;
+            if xml_text(text.as_bytes(), "Error", "Code")?.is_some() {
+                bail!("Failed to complete the upload of `{key}`, {text}");
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(size)
+    }
+
+    /// Sends the next part of the upload, starting the upload with the first one
+    async fn send_part(self: &Arc<Self>, upload: &mut Upload, part: Vec<u8>) -> Result<()> {
+        if upload.etags.len() >= MAX_PARTS {
+            bail!("Failed to upload `{}`, it's too large", upload.key);
+        }
+        let (key, content_type) = (upload.key.clone(), upload.content_type.clone());
+        let id = match upload.id.clone() {
+            Some(v) => v,
+            None => {
+                let id = self
+                    .run(move |bucket| {
+                        let text = bucket
+                            .request(
+                                "POST",
+                                &key,
+                                &[("uploads", "")],
+                                &[("content-type", &content_type)],
+                            )
+                            .call()
+                            .map_err(|err| request_error(err, "POST", &key))?
+                            .into_string()?;
+                        xml_text(text.as_bytes(), "InitiateMultipartUploadResult", "UploadId")?
+                            .ok_or_else(|| anyhow!("Failed to start the upload of `{key}`"))
+                    })
+                    .await?;
+                upload.id = Some(id.clone());
+                id
+            }
+        };
+        let key = upload.key.clone();
+        let number = (upload.etags.len() + 1).to_string();
+        let etag = self
+            .run(move |bucket| {
+                let res = bucket
+                    .request(
+                        "PUT",
+                        &key,
+                        &[("partNumber", &number), ("uploadId", &id)],
+                        &[],
+                    )
+                    .send_bytes(&part)
+                    .map_err(|err| request_error(err, "PUT", &key))?;
+                res.header("etag")
+                    .map(|v| v.to_string())
+                    .ok_or_else(|| anyhow!("Failed to upload a part of `{key}`"))
+            })
+            .await?;
+        upload.etags.push(etag);
+        Ok(())
+    }
+
+    /// Makes the folder, with an empty object ending in `/` as S3 consoles do
+    pub async fn mkdir(self: &Arc<Self>, path: &str) -> Result<()> {
+        let key = self.dir_prefix(path);
+        self.run(move |bucket| {
+            bucket
+                .request("PUT", &key, &[], &[])
+                .send_bytes(&[])
+                .map_err(|err| request_error(err, "PUT", &key))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Deletes the file, or the folder with all it holds
+    pub async fn delete(self: &Arc<Self>, path: &str, is_dir: bool) -> Result<()> {
+        let keys = match is_dir {
+            true => {
+                let prefix = self.dir_prefix(path);
+                self.walk(path)
+                    .await?
+                    .into_iter()
+                    .map(|v| format!("{prefix}{v}"))
+                    .collect()
+            }
+            false => vec![self.key(path)],
+        };
+        self.run(move |bucket| {
+            for key in keys {
+                bucket
+                    .request("DELETE", &key, &[], &[])
+                    .call()
+                    .map_err(|err| request_error(err, "DELETE", &key))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Copies the file, or the files of the folder at the paths relative to it, to `dest`
+    /// on the server
+    pub async fn copy(
+        self: &Arc<Self>,
+        src: &str,
+        dest: &str,
+        is_dir: bool,
+        names: Vec<String>,
+    ) -> Result<()> {
+        let (src, dest) = match is_dir {
+            true => (self.dir_prefix(src), self.dir_prefix(dest)),
+            false => (self.key(src), self.key(dest)),
+        };
+        self.run(move |bucket| {
+            for name in names {
+                let (src_key, dest_key) = (format!("{src}{name}"), format!("{dest}{name}"));
+                let source = format!("/{}/{}", bucket.bucket, uri_encode(&src_key, false));
+                bucket
+                    .request("PUT", &dest_key, &[], &[("x-amz-copy-source", &source)])
+                    .call()
+                    .map_err(|err| request_error(err, "PUT", &dest_key))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn run<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&Self) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let bucket = self.clone();
+        tokio::task::spawn_blocking(move || f(&bucket)).await?
+    }
+
+    fn head(&self, path: &str) -> Result<Option<Entry>> {
+        let key = self.key(path);
+        let res = match self.request("HEAD", &key, &[], &[]).call() {
+            Ok(v) => v,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(err) => return Err(request_error(err, "HEAD", &key)),
+        };
+        let mtime = res
+            .header("last-modified")
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .map(|v| v.timestamp_millis() as u64)
+            .unwrap_or_default();
+        Ok(Some(Entry {
+            name: String::new(),
+            is_dir: false,
+            size: res
+                .header("content-length")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            mtime,
+            etag: res.header("etag").map(|v| v.to_string()),
+        }))
+    }
+
+    /// A page of the keys starting with the prefix, with the ones sharing a prefix up to
+    /// the next `/` rolled up when `delimit` is set. The names are the keys.
+    fn list_page(
+        &self,
+        prefix: &str,
+        delimit: bool,
+        token: Option<&str>,
+        max_keys: Option<usize>,
+    ) -> Result<ListPage> {
+        let max_keys = max_keys.map(|v| v.to_string());
+        let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+        if delimit {
+            query.push(("delimiter", "/"));
+        }
+        if let Some(token) = token {
+            query.push(("continuation-token", token));
+        }
+        if let Some(max_keys) = max_keys.as_deref() {
+            query.push(("max-keys", max_keys));
+        }
+        let text = self
+            .request("GET", "", &query, &[])
+            .call()
+            .map_err(|err| request_error(err, "GET", prefix))?
+            .into_string()?;
+        ListPage::parse(text.as_bytes())
+    }
+
+    /// The key of the path relative to the prefix
+    fn key(&self, path: &str) -> String {
+        join_key(&self.prefix, path)
+    }
+
+    /// The prefix of the keys in the folder
+    fn dir_prefix(&self, path: &str) -> String {
+        let key = self.key(path);
+        match key.is_empty() {
+            true => key,
+            false => format!("{key}/"),
+        }
+    }
+
+    /// A request for the key signed with the query and the `x-amz-*` and `content-type`
+    /// headers
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+    ) -> ureq::Request {
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let path = format!("{}/{}", self.bucket_path, uri_encode(key, false));
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        let mut headers: Vec<(&str, &str)> = headers.to_vec();
+        headers.extend([
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD),
+            ("x-amz-date", &amz_date),
+        ]);
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        headers.sort();
+        let canonical_headers: String = headers.iter().map(|(k, v)| format!("{k}:{v}\n")).collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{UNSIGNED_PAYLOAD}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, date, &self.region, "s3");
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key
+        );
+        let url = match query.is_empty() {
+            true => format!("{}{path}", self.endpoint),
+            false => format!("{}{path}?{query}", self.endpoint),
+        };
+        let mut req = self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &authorization);
+        for (name, value) in headers {
+            req = req.set(name, value);
+        }
+        req
+    }
+}
+
+/// A multipart upload, once it's started
+struct Upload {
+    key: String,
+    content_type: String,
+    id: Option<String>,
+    /// Of the parts sent, in their order
+    etags: Vec<String>,
+}
+
+/// A page of a `ListObjectsV2` response
+#[derive(Debug, Default, PartialEq)]
+struct ListPage {
+    objects: Vec<Entry>,
+    prefixes: Vec<String>,
+    /// The token of the next page, if there's one
+    next: Option<String>,
+}
+
+impl ListPage {
+    fn parse(body: &[u8]) -> Result<Self> {
+        let mut page = Self::default();
+        let mut object = Entry::default();
+        let mut truncated = false;
+        let mut names: Vec<String> = vec![];
+        let mut text = String::new();
+        for event in EventReader::new(body) {
+            match event? {
+                XmlEvent::StartElement { name, .. } => {
+                    names.push(name.local_name);
+                    text.clear();
+                }
+                XmlEvent::Characters(v) => text.push_str(&v),
+                XmlEvent::EndElement { .. } => {
+                    let name = names.pop().unwrap_or_default();
+                    let parent = names.last().map(|v| v.as_str()).unwrap_or_default();
+                    let text = std::mem::take(&mut text);
+                    match (parent, name.as_str()) {
+                        ("Contents", "Key") => object.name = text,
+                        ("Contents", "Size") => object.size = text.trim().parse()?,
+                        ("Contents", "LastModified") => {
+                            object.mtime = DateTime::parse_from_rfc3339(text.trim())
+                                .map(|v| v.timestamp_millis() as u64)
+                                .unwrap_or_default()
+                        }
+                        ("Contents", "ETag") => object.etag = Some(text),
+                        ("ListBucketResult", "Contents") => {
+                            page.objects.push(std::mem::take(&mut object))
+                        }
+                        ("CommonPrefixes", "Prefix") => page.prefixes.push(text),
+                        ("ListBucketResult", "IsTruncated") => truncated = text.trim() == "true",
+                        ("ListBucketResult", "NextContinuationToken") => page.next = Some(text),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !truncated {
+            page.next = None;
+        }
+        Ok(page)
+    }
+}
+
+/// The text of the element named `name` right under the root element `root`, `None` if
+/// the root is another element or the element isn't there
+fn xml_text(body: &[u8], root: &str, name: &str) -> Result<Option<String>> {
+    let mut depth = 0;
+    let mut in_root = false;
+    let mut output = None;
+    let mut text = String::new();
+    for event in EventReader::new(body) {
+        match event? {
+            XmlEvent::StartElement { name: v, .. } => {
+                depth += 1;
+                text.clear();
+                if depth == 1 {
+                    in_root = v.local_name == root;
+                }
+            }
+            XmlEvent::Characters(v) => text.push_str(&v),
+            XmlEvent::EndElement { name: v } => {
+                if depth == 2 && in_root && v.local_name == name {
+                    output = Some(std::mem::take(&mut text));
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(output)
+}
+
+fn join_key(prefix: &str, path: &str) -> String {
+    match (prefix.is_empty(), path.is_empty()) {
+        (true, _) => path.to_string(),
+        (false, true) => prefix.to_string(),
+        (false, false) => format!("{prefix}/{path}"),
+    }
+}
+
+/// Percent-encodes all but the unreserved characters, and `/` unless `slash` is set,
+/// as the signature expects
+fn uri_encode(value: &str, slash: bool) -> String {
+    value
+        .bytes()
+        .map(|v| match v {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (v as char).to_string()
+            }
+            b'/' if !slash => "/".to_string(),
+            v => format!("%{v:02X}"),
+        })
+        .collect()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{secret_key}").as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|v| v ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|v| v ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|v| format!("{v:02x}")).collect()
+}
+
+fn request_error(err: ureq::Error, method: &str, key: &str) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(code, res) => {
+            let text = res.status_text().to_string();
+            anyhow!("Failed to {method} `{key}` of the bucket, {code} {text}")
+        }
+        err => anyhow!("Failed to {method} `{key}` of the bucket, {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_s3_target() {
+        assert_eq!(S3Target::parse("/srv/www").unwrap(), None);
+        assert_eq!(
+            S3Target::parse("s3://bucket/a/b/").unwrap(),
+            Some(S3Target {
+                bucket: "bucket".into(),
+                prefix: "a/b".into()
+            })
+        );
+        assert_eq!(S3Target::parse("s3://bucket").unwrap().unwrap().prefix, "");
+        assert!(S3Target::parse("s3:///prefix").is_err());
+    }
+
+    #[test]
+    fn test_signing() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // The example of the AWS docs on deriving the signing key
+        assert_eq!(
+            hex(&signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(uri_encode("a b/ü~", false), "a%20b/%C3%BC~");
+        assert_eq!(uri_encode("a/b", true), "a%2Fb");
+    }
+
+    #[test]
+    fn test_list_page() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>p/</Prefix>
+  <IsTruncated>true</IsTruncated>
+  <Contents>
+    <Key>p/a.txt</Key>
+    <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+    <ETag>&quot;fba9dede5f27731c9771645a39863328&quot;</ETag>
+    <Size>434234</Size>
+  </Contents>
+  <CommonPrefixes><Prefix>p/dir/</Prefix></CommonPrefixes>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+</ListBucketResult>"#;
+        assert_eq!(
+            ListPage::parse(body).unwrap(),
+            ListPage {
+                objects: vec![Entry {
+                    name: "p/a.txt".into(),
+                    is_dir: false,
+                    size: 434234,
+                    mtime: 1255369830000,
+                    etag: Some(r#""fba9dede5f27731c9771645a39863328""#.into()),
+                }],
+                prefixes: vec!["p/dir/".into()],
+                next: Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=".into()),
+            }
+        );
+        assert_eq!(
+            xml_text(
+                b"<Error><Code>InternalError</Code></Error>",
+                "Error",
+                "Code"
+            )
+            .unwrap()
+            .as_deref(),
+            Some("InternalError")
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::server::{is_hidden, walk_visible};
+use crate::utils::get_file_name;
+
+/// The names of the served files and folders, kept in memory.
+///
+/// The index is built in the background at startup, then kept up to date by a
+/// filesystem watcher, so searches don't have to walk the disk.
+pub struct SearchIndex {
+    entries: Arc<Entries>,
+    _watchers: Vec<RecommendedWatcher>,
+}
+
+struct Entries {
+    roots: Vec<PathBuf>,
+    hidden: Vec<String>,
+    posix_hidden: bool,
+    running: Arc<AtomicBool>,
+    /// The paths and their lowercased names, sorted so that a folder is followed by its entries
+    names: RwLock<BTreeMap<PathBuf, String>>,
+    ready: AtomicBool,
+}
+
+impl SearchIndex {
+    pub fn new(
+        roots: Vec<PathBuf>,
+        hidden: Vec<String>,
+        posix_hidden: bool,
+        running: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let entries = Arc::new(Entries {
+            roots,
+            hidden,
+            posix_hidden,
+            running,
+            names: Default::default(),
+            ready: AtomicBool::new(false),
+        });
+        // Watch before walking, so the changes made meanwhile aren't missed
+        let mut watchers = vec![];
+        for root in &entries.roots {
+            let watched = entries.clone();
+            let mut watcher =
+                notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                    Ok(event) => watched.apply(event),
+                    Err(err) => warn!("Search index watcher failed, {err}"),
+                })?;
+            watcher.watch(root, RecursiveMode::Recursive).map_err(|e| {
+                anyhow!(
+                    "Failed to watch `{}` for the search index, {e}",
+                    root.display()
+                )
+            })?;
+            watchers.push(watcher);
+        }
+        let building = entries.clone();
+        std::thread::spawn(move || building.rebuild());
+        Ok(Self {
+            entries,
+            _watchers: watchers,
+        })
+    }
+
+    /// The entries in `dirs` whose names contain `search`, lowercased, along with whether
+    /// there were more than `max_results`. None until the index is built.
+    pub fn search(
+        &self,
+        dirs: &[PathBuf],
+        search: &str,
+        max_results: usize,
+    ) -> Option<(Vec<PathBuf>, bool)> {
+        if !self.entries.ready.load(Ordering::SeqCst) {
+            return None;
+        }
+        let names = self.entries.names.read().unwrap();
+        let mut paths = vec![];
+        for dir in dirs {
+            let entries = names
+                .range(dir.to_path_buf()..)
+                .take_while(|(path, _)| path.starts_with(dir))
+                .filter(|(path, _)| *path != dir);
+            for (path, name) in entries {
+                if name.contains(search) {
+                    if paths.len() >= max_results {
+                        return Some((paths, true));
+                    }
+                    paths.push(path.clone());
+                }
+            }
+        }
+        Some((paths, false))
+    }
+}
+
+impl Entries {
+    fn rebuild(&self) {
+        self.ready.store(false, Ordering::SeqCst);
+        self.names.write().unwrap().clear();
+        walk_visible(
+            &self.roots,
+            &self.hidden,
+            self.posix_hidden,
+            &self.running,
+            |path, _| {
+                self.insert(path);
+                true
+            },
+        );
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    fn apply(&self, event: Event) {
+        if event.need_rescan() {
+            return self.rebuild();
+        }
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+        ) {
+            for path in &event.paths {
+                self.refresh(path);
+            }
+        }
+    }
+
+    /// Takes the entry at `path`, and the ones inside it, as they are now on disk
+    fn refresh(&self, path: &Path) {
+        let root = match self.roots.iter().find(|root| path.starts_with(root)) {
+            Some(root) if root != path => root,
+            _ => return,
+        };
+        {
+            let mut names = self.names.write().unwrap();
+            let removed: Vec<PathBuf> = names
+                .range(path.to_path_buf()..)
+                .take_while(|(v, _)| v.starts_with(path))
+                .map(|(v, _)| v.clone())
+                .collect();
+            for v in removed {
+                names.remove(&v);
+            }
+        }
+        let is_dir = match std::fs::symlink_metadata(path) {
+            Ok(meta) => meta.is_dir(),
+            Err(_) => return,
+        };
+        if self.is_hidden_path(root, path, is_dir) {
+            return;
+        }
+        self.insert(path);
+        if is_dir {
+            walk_visible(
+                &[path.to_path_buf()],
+                &self.hidden,
+                self.posix_hidden,
+                &self.running,
+                |path, _| {
+                    self.insert(path);
+                    true
+                },
+            );
+        }
+    }
+
+    /// Whether the entry, or one of the folders it's in, is hidden
+    fn is_hidden_path(&self, root: &Path, path: &Path, is_dir: bool) -> bool {
+        let names: Vec<_> = match path.strip_prefix(root) {
+            Ok(v) => v.iter().filter_map(|v| v.to_str()).collect(),
+            Err(_) => return true,
+        };
+        names.iter().enumerate().any(|(i, name)| {
+            let is_dir = is_dir || i + 1 < names.len();
+            is_hidden(&self.hidden, self.posix_hidden, name, is_dir)
+        })
+    }
+
+    fn insert(&self, path: &Path) {
+        let name = get_file_name(path).to_lowercase();
+        self.names.write().unwrap().insert(path.to_path_buf(), name);
+    }
+}
@@ -1,70 +1,226 @@
 #![allow(clippy::too_many_arguments)]
 
-use crate::auth::{www_authenticate, AccessPaths, AccessPerm};
-use crate::streamer::Streamer;
+use crate::args::{AuthMethod, DefaultSort, SortBy, TryFiles, TryFilesFallback};
+use crate::auth::{
+    guard_paths, www_authenticate, AccessPaths, AccessPerm, AuthLockout, SessionStore,
+    SESSION_COOKIE, SESSION_TIMEOUT,
+};
+use crate::checksum::{cached_file_checksum, file_checksum, ChecksumAlgo, DigestVerifier};
+use crate::compress::{compress, Encoding};
+use crate::delta;
+use crate::dir_usage::DirUsage;
+use crate::dlna;
+use crate::downloads::{is_valid_id as is_valid_download_id, Downloads, Tracker};
+use crate::events::{EventBus, EventKind as FsEventKind, FsEvent};
+use crate::highlight::{detect_language, highlight};
+use crate::hooks::{HookEvent, Hooks};
+use crate::job::Job;
+use crate::journal::ChangeJournal;
+use crate::load::{Load, Metrics};
+use crate::media::{media_type, probe_duration, thumbnail};
+use crate::meta_cache::MetaCache;
+use crate::middleware::{Context, Cors, HttpLog, Middleware};
+use crate::preload::Preload;
+use crate::quota::Quotas;
+use crate::rate_limit::{Client, RateLimiter};
+use crate::rewrite::Rewrite;
+use crate::s3::{Bucket, Entry};
+use crate::search_index::SearchIndex;
+use crate::streamer::{FileStreamer, Streamer};
+use crate::torrent::Torrents;
+use crate::trash::{move_entry, Trash};
+use crate::upload_types::{UploadTypes, SNIFF_LEN};
+#[cfg(unix)]
+use crate::utils::available_space;
 use crate::utils::{
-    decode_uri, encode_uri, get_file_mtime_and_mode, get_file_name, glob, try_get_file_name,
+    archive_entry_path, decode_uri, encode_uri, get_file_mtime_and_mode, get_file_name, glob,
+    mtime_and_mode, render_markdown, render_template, try_get_file_name,
 };
 use crate::Args;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use walkdir::WalkDir;
-use xml::escape::escape_str_pcdata;
+use xml::escape::{escape_str_attribute, escape_str_pcdata};
+use xml::reader::{EventReader, XmlEvent};
 
+use async_compression::tokio::bufread::GzipDecoder;
+use async_compression::tokio::write::GzipEncoder;
+use async_stream::stream;
+use async_zip::base::read::stream::ZipFileReader as ZipStreamReader;
 use async_zip::tokio::write::ZipFileWriter;
 use async_zip::{Compression, ZipDateTime, ZipEntryBuilder};
-use chrono::{LocalResult, TimeZone, Utc};
-use fs_extra::dir::get_size;
-use futures::TryStreamExt;
+use chrono::{LocalResult, SecondsFormat, TimeZone, Utc};
+use futures::{SinkExt, StreamExt, TryStreamExt};
 use headers::{
-    AcceptRanges, AccessControlAllowCredentials, AccessControlAllowOrigin, CacheControl,
-    ContentLength, ContentType, ETag, HeaderMap, HeaderMapExt, IfModifiedSince, IfNoneMatch,
-    IfRange, LastModified, Range,
+    AcceptRanges, CacheControl, ContentLength, ContentType, ETag, HeaderMap, HeaderMapExt, IfMatch,
+    IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince, LastModified, Range,
 };
+use hyper::body::Bytes;
 use hyper::header::{
-    HeaderValue, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
-    RANGE, WWW_AUTHENTICATE,
+    HeaderValue, ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONNECTION, CONTENT_DISPOSITION,
+    CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE, RETRY_AFTER, SET_COOKIE, UPGRADE,
+    WWW_AUTHENTICATE,
 };
 use hyper::{Body, Method, StatusCode, Uri};
-use serde::Serialize;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs::Metadata;
 use std::io::SeekFrom;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{self, AtomicBool};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::{fs, io};
-use tokio_util::compat::FuturesAsyncWriteCompatExt;
-use tokio_util::io::StreamReader;
+use tokio_tar::{Archive as TarArchive, Builder as TarBuilder};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Message, Role};
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
+use tokio_util::io::{InspectWriter, StreamReader};
+use tracing::{field, info_span, instrument, Instrument};
 use uuid::Uuid;
 
 pub type Request = hyper::Request<Body>;
 pub type Response = hyper::Response<Body>;
 
 const INDEX_HTML: &str = include_str!("../assets/index.html");
+const LOGIN_HTML: &str = include_str!("../assets/login.html");
 const INDEX_CSS: &str = include_str!("../assets/index.css");
 const INDEX_JS: &str = include_str!("../assets/index.js");
 const FAVICON_ICO: &[u8] = include_bytes!("../assets/favicon.ico");
-const INDEX_NAME: &str = "index.html";
 const BUF_SIZE: usize = 65536;
+const ZIP_MAX_ENTRIES: usize = 65535;
+const ZIP_MAX_SIZE: u64 = 4294967295;
+/// Requests with more ranges are served the whole file
+const MAX_RANGES: usize = 64;
+/// Filesystem timestamps are coarser than the clock behind sync tokens
+const SYNC_MTIME_SLACK: u64 = 1000;
 const TEXT_MAX_SIZE: u64 = 4194304; // 4M
+const SEARCH_SNIPPET_LEN: usize = 200;
+const WATCH_KEEP_ALIVE: Duration = Duration::from_secs(15);
+const README_MAX_SIZE: u64 = 1048576; // 1M
+const HIGHLIGHT_MAX_SIZE: u64 = 524288; // 512K
+const THUMBNAIL_SIZE: u32 = 256;
+const THUMBNAIL_MIN_SIZE: u32 = 16;
+const THUMBNAIL_MAX_SIZE: u32 = 1024;
+const LISTING_HASH_MAX_SIZE: u64 = 16777216; // 16M
+/// Bytes hashed at most for the checksums of a listing, the files past it are left out
+const LISTING_HASH_MAX_TOTAL: u64 = 268435456; // 256M
+const LOGIN_BODY_MAX_SIZE: usize = 4096; // 4K
+/// How often the progress of an upload is published
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// How many entries a streamed listing sends at a time
+const STREAM_BATCH_SIZE: usize = 256;
+const API_PREFIX: &str = "__dufs__/api/v1/";
 
 pub struct Server {
     args: Arc<Args>,
     assets_prefix: String,
+    api_prefix: String,
     html: Cow<'static, str>,
     single_file_req_paths: Vec<String>,
+    /// Hidden and excluded names, both left out of listings, searches and archives
+    hidden: Vec<String>,
     running: Arc<AtomicBool>,
+    journal: Arc<ChangeJournal>,
+    events: Arc<EventBus>,
+    sessions: Arc<SessionStore>,
+    lockout: Arc<AuthLockout>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    load: Arc<Load>,
+    downloads: Arc<Downloads>,
+    torrents: Arc<Torrents>,
+    hooks: Hooks,
+    /// The middleware of the features built in, before the ones registered
+    builtin_middlewares: Vec<Arc<dyn Middleware>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    trash: Option<Arc<Trash>>,
+    quotas: Quotas,
+    upload_types: UploadTypes,
+    search_index: Option<SearchIndex>,
+    meta_cache: Option<MetaCache>,
+    preload: Option<Preload>,
+    dir_usage: DirUsage,
+    /// Where the files are when the serve path is object storage
+    bucket: Option<Arc<Bucket>>,
 }
 
 impl Server {
+    /// A server for the args, whose background work stops once `running` is cleared
     pub fn init(args: Arc<Args>, running: Arc<AtomicBool>) -> Result<Self> {
-        let assets_prefix = format!("{}__dufs_v{}_", args.uri_prefix, env!("CARGO_PKG_VERSION"));
+        let lockout = Arc::new(AuthLockout::new(args.auth_lockout));
+        Self::build(
+            args,
+            running,
+            Arc::new(ChangeJournal::new()),
+            Arc::new(EventBus::new()),
+            Default::default(),
+            lockout,
+            Arc::new(Load::new()),
+        )
+    }
+
+    pub fn trash(&self) -> Option<Arc<Trash>> {
+        self.trash.clone()
+    }
+
+    /// A server with new args, which keeps the journal, events, sessions, lockouts, rate
+    /// limits, load, downloads, torrents and middleware of this one
+    pub fn reload(&self, args: Arc<Args>) -> Result<Self> {
+        let lockout = if args.auth_lockout == self.args.auth_lockout {
+            self.lockout.clone()
+        } else {
+            Arc::new(AuthLockout::new(args.auth_lockout))
+        };
+        let mut server = Self::build(
+            args,
+            self.running.clone(),
+            self.journal.clone(),
+            self.events.clone(),
+            self.sessions.clone(),
+            lockout,
+            self.load.clone(),
+        )?;
+        server.middlewares = self.middlewares.clone();
+        server.downloads = self.downloads.clone();
+        server.torrents = self.torrents.clone();
+        if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+            if server.args.rate_limit == Some(rate_limiter.limit()) {
+                server.rate_limiter = Some(rate_limiter.clone());
+            }
+        }
+        Ok(server)
+    }
+
+    /// Registers the middleware, after the ones registered before
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    fn all_middlewares(&self) -> impl DoubleEndedIterator<Item = &Arc<dyn Middleware>> {
+        self.builtin_middlewares.iter().chain(&self.middlewares)
+    }
+
+    fn build(
+        args: Arc<Args>,
+        running: Arc<AtomicBool>,
+        journal: Arc<ChangeJournal>,
+        events: Arc<EventBus>,
+        sessions: Arc<SessionStore>,
+        lockout: Arc<AuthLockout>,
+        load: Arc<Load>,
+    ) -> Result<Self> {
+        let assets_prefix = args.assets_prefix();
+        let api_prefix = format!("{}{API_PREFIX}", args.uri_prefix);
         let single_file_req_paths = if args.path_is_file {
             vec![
                 args.uri_prefix.to_string(),
@@ -78,65 +234,370 @@ impl Server {
         } else {
             vec![]
         };
+        let hooks = Hooks::new(&args);
+        let mut builtin_middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(HttpLog::new(
+            args.http_logger.clone(),
+            assets_prefix.clone(),
+        ))];
+        if args.metrics {
+            builtin_middlewares.push(Arc::new(Metrics::new(
+                load.clone(),
+                &args.uri_prefix,
+                args.max_concurrency,
+            )));
+        }
+        if args.enable_cors {
+            builtin_middlewares.push(Arc::new(Cors));
+        }
+        if !args.redirects.is_empty() || !args.rewrites.is_empty() {
+            builtin_middlewares.push(Arc::new(Rewrite::new(
+                args.redirects.clone(),
+                args.rewrites.clone(),
+            )));
+        }
+        #[cfg(feature = "plugins")]
+        for path in args.plugin.iter() {
+            builtin_middlewares.push(Arc::new(crate::plugin::Plugin::load(path)?));
+        }
+        let trash = args
+            .trash_dir
+            .as_ref()
+            .map(|dir| Arc::new(Trash::new(dir, args.trash_retention)));
+        let hidden: Vec<String> = args.hidden.iter().chain(&args.exclude).cloned().collect();
+        let meta_cache = args.metadata_cache.map(MetaCache::new);
+        let rate_limiter = args.rate_limit.map(|v| Arc::new(RateLimiter::new(v)));
+        let dir_usage = DirUsage::new(hidden.clone(), args.posix_hidden, running.clone());
+        let upload_types = UploadTypes::new(&args.upload_allow_ext, &args.upload_deny_ext);
+        let bucket = match args.s3_target.as_ref() {
+            Some(target) => Some(Arc::new(Bucket::new(target)?)),
+            None => None,
+        };
         let html = match args.assets.as_ref() {
             Some(path) => Cow::Owned(std::fs::read_to_string(path.join("index.html"))?),
             None => Cow::Borrowed(INDEX_HTML),
         };
-        Ok(Self {
+        let mut server = Self {
             args,
             running,
             single_file_req_paths,
+            hidden,
             assets_prefix,
+            api_prefix,
             html,
-        })
+            journal,
+            events,
+            sessions,
+            lockout,
+            rate_limiter,
+            load,
+            downloads: Arc::new(Downloads::new()),
+            torrents: Arc::new(Torrents::new()),
+            hooks,
+            builtin_middlewares,
+            middlewares: vec![],
+            trash,
+            quotas: Quotas::default(),
+            upload_types,
+            search_index: None,
+            meta_cache,
+            preload: None,
+            dir_usage,
+            bucket,
+        };
+        let quotas = server
+            .args
+            .quota
+            .rules()
+            .filter_map(|(path, limit)| Some((server.join_path(path)?, limit)))
+            .collect();
+        server.quotas = Quotas::new(quotas);
+        let roots: Vec<PathBuf> = std::iter::once(server.args.serve_path.clone())
+            .chain(server.args.mount_paths.iter().map(|(_, v)| v.clone()))
+            .collect();
+        if server.args.search_index && server.args.allow_search && !server.args.path_is_file {
+            server.search_index = Some(SearchIndex::new(
+                roots.clone(),
+                server.hidden.clone(),
+                server.args.posix_hidden,
+                server.running.clone(),
+            )?);
+        }
+        if let Some(max_size) = server.args.preload {
+            server.preload = Some(Preload::new(
+                roots,
+                server.hidden.clone(),
+                server.args.posix_hidden,
+                server.running.clone(),
+                max_size,
+            )?);
+        }
+        Ok(server)
     }
 
+    /// Handles a request, from the client at `addr` if it's known
     pub async fn call(
         self: Arc<Self>,
-        req: Request,
+        mut req: Request,
         addr: Option<SocketAddr>,
     ) -> Result<Response, hyper::Error> {
-        let uri = req.uri().clone();
-        let assets_prefix = &self.assets_prefix;
-        let enable_cors = self.args.enable_cors;
-        let mut http_log_data = self.args.http_logger.data(&req);
-        if let Some(addr) = addr {
-            http_log_data.insert("remote_addr".to_string(), addr.ip().to_string());
+        if let Some(proxy) = self.args.auth.proxy() {
+            if !proxy.is_trusted(addr.map(|v| v.ip())) {
+                req.headers_mut().remove(proxy.header());
+            }
         }
+        let uri = req.uri().clone();
+        let ip = addr.map(|v| self.args.trusted_proxies.client_ip(v.ip(), req.headers()));
+        let if_none_match = match req.method() == Method::GET || req.method() == Method::HEAD {
+            true => req.headers().typed_get::<IfNoneMatch>(),
+            false => None,
+        };
+        let encoding = match self.args.enable_compression && req.method() != Method::HEAD {
+            true => Encoding::negotiate(req.headers()),
+            false => None,
+        };
+        let span = info_span!(
+            "handle",
+            http.request.method = %req.method(),
+            url.path = uri.path(),
+            http.response.status_code = field::Empty,
+        );
+        #[cfg(feature = "otlp")]
+        crate::otlp::set_parent(&span, req.headers());
 
-        let mut res = match self.clone().handle(req).await {
-            Ok(res) => {
-                http_log_data.insert("status".to_string(), res.status().as_u16().to_string());
-                if !uri.path().starts_with(assets_prefix) {
-                    self.args.http_logger.log(&http_log_data, None);
-                }
-                res
+        let mut ctx = Context::new(uri.clone(), ip);
+        let answered = self.guard_body(&mut req).or_else(|| {
+            self.all_middlewares()
+                .find_map(|middleware| middleware.request(&mut req, &mut ctx))
+        });
+        // Held while the request is handled, not while the body of the response is sent
+        let mut permit = None;
+        let answered = answered.or_else(|| match self.load.enter(self.args.max_concurrency) {
+            Ok(v) => {
+                permit = Some(v);
+                None
             }
-            Err(err) => {
+            Err(retry_after) => {
                 let mut res = Response::default();
-                let status = StatusCode::INTERNAL_SERVER_ERROR;
-                *res.status_mut() = status;
-                http_log_data.insert("status".to_string(), status.as_u16().to_string());
-                self.args
-                    .http_logger
-                    .log(&http_log_data, Some(err.to_string()));
-                res
+                status_service_unavailable(&mut res, retry_after);
+                Some(res)
+            }
+        });
+        let mut res = match answered {
+            Some(res) => res,
+            None => match self.clone().handle(req, ip).instrument(span.clone()).await {
+                Ok(mut res) => {
+                    if let Some(if_none_match) = if_none_match {
+                        not_modified(&mut res, &if_none_match);
+                    }
+                    if self.args.enable_compression {
+                        compress(&mut res, encoding);
+                    }
+                    res
+                }
+                Err(err) => {
+                    let mut res = Response::default();
+                    match BodyError::find(&err) {
+                        Some(err) => err.respond(&mut res),
+                        None => {
+                            ctx.error = Some(err.to_string());
+                            *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        }
+                    }
+                    res
+                }
+            },
+        };
+        drop(permit);
+        span.record("http.response.status_code", res.status().as_u16());
+        for middleware in self.all_middlewares().rev() {
+            middleware.response(&mut res, &mut ctx);
+        }
+        self.add_security_headers(&mut res);
+        if !self.args.headers.is_empty() {
+            let path = match uri.path().strip_prefix(&self.args.uri_prefix) {
+                Some(v) => format!("/{v}"),
+                None => uri.path().to_string(),
+            };
+            let path = decode_uri(&path).unwrap_or_default();
+            self.args.headers.apply(&path, res.headers_mut());
+        }
+        Ok(res)
+    }
+
+    /// Answers 413 if the body of the request is declared larger than `--max-body-size`,
+    /// otherwise has it fail with a `BodyError` once it grows larger while read, or nothing
+    /// of it comes for `--read-timeout`
+    fn guard_body(&self, req: &mut Request) -> Option<Response> {
+        let declared = req.headers().typed_get::<ContentLength>().map(|v| v.0);
+        if let (Some(limit), Some(len)) = (self.args.max_body_size, declared) {
+            if len > limit {
+                let mut res = Response::default();
+                status_payload_too_large(&mut res);
+                return Some(res);
+            }
+        }
+        // Hyper stops reading at the declared length already
+        let limit = self.args.max_body_size.filter(|_| declared.is_none());
+        let timeout = self.args.read_timeout;
+        if (limit.is_none() && timeout.is_none())
+            || hyper::body::HttpBody::is_end_stream(req.body())
+        {
+            return None;
+        }
+        let mut body = std::mem::take(req.body_mut());
+        let body = stream! {
+            let mut received = 0;
+            loop {
+                let chunk = match timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, body.next()).await {
+                        Ok(v) => v,
+                        Err(_) => {
+                            yield Err(BodyError::TimedOut.into());
+                            break;
+                        }
+                    },
+                    None => body.next().await,
+                };
+                let chunk = match chunk {
+                    Some(Ok(v)) => v,
+                    Some(Err(err)) => {
+                        yield Err(err.into());
+                        break;
+                    }
+                    None => break,
+                };
+                received += chunk.len() as u64;
+                if limit.is_some_and(|v| received > v) {
+                    yield Err(BodyError::TooLarge.into());
+                    break;
+                }
+                yield Ok::<_, Box<dyn std::error::Error + Send + Sync>>(chunk);
             }
         };
+        *req.body_mut() = Body::wrap_stream(body);
+        None
+    }
 
-        if enable_cors {
-            add_cors(&mut res);
+    pub async fn handle(self: Arc<Self>, req: Request, ip: Option<IpAddr>) -> Result<Response> {
+        let writes = !matches!(
+            req.method().as_str(),
+            "GET" | "HEAD" | "OPTIONS" | "PROPFIND"
+        );
+        let res = self.clone().route(req, ip).await;
+        if writes {
+            if let Some(meta_cache) = &self.meta_cache {
+                meta_cache.invalidate();
+            }
+            self.dir_usage.invalidate();
+        }
+        res
+    }
+
+    async fn route(self: Arc<Self>, mut req: Request, ip: Option<IpAddr>) -> Result<Response> {
+        let route = match req.uri().path().strip_prefix(&self.api_prefix) {
+            Some(v) => v.to_string(),
+            None => return self.handle_path(req, ip, None).await,
+        };
+        let mut res = Response::default();
+        if let Some(op) = self.route_api(&route, &mut req, &mut res) {
+            res = self.handle_path(req, ip, Some(op)).await?;
         }
+        api_error_body(&mut res).await?;
         Ok(res)
     }
 
-    pub async fn handle(self: Arc<Self>, req: Request) -> Result<Response> {
+    /// Rewrites a request of the JSON API into a request of the path it's about.
+    /// The method is kept, for auth to check it as sent.
+    fn route_api(&self, route: &str, req: &mut Request, res: &mut Response) -> Option<ApiOp> {
+        let (name, target) = route.split_once('/').unwrap_or((route, ""));
+        let op = match ApiOp::parse(name) {
+            Some(v) => v,
+            None => {
+                status_not_found(res);
+                return None;
+            }
+        };
+        if *req.method() != op.method() {
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return None;
+        }
+        let query = req.uri().query().unwrap_or_default().to_string();
+        let uri = match query.is_empty() {
+            true => format!("{}{target}", self.args.uri_prefix),
+            false => format!("{}{target}?{query}", self.args.uri_prefix),
+        };
+        *req.uri_mut() = match uri.parse() {
+            Ok(v) => v,
+            Err(_) => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return None;
+            }
+        };
+        if matches!(op, ApiOp::Move | ApiOp::Copy) {
+            let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let dest = query_params.get("to").map(|v| {
+                format!(
+                    "{}{}",
+                    self.args.uri_prefix,
+                    encode_uri(v.trim_matches('/'))
+                )
+            });
+            match dest.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                Some(dest) => req.headers_mut().insert("destination", dest),
+                None => {
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    *res.body_mut() = Body::from("Missing `to`");
+                    return None;
+                }
+            };
+            let overwrite = query_params.get("overwrite").map(|v| v != "false");
+            req.headers_mut().insert(
+                "overwrite",
+                HeaderValue::from_static(if overwrite == Some(false) { "F" } else { "T" }),
+            );
+        }
+        Some(op)
+    }
+
+    async fn handle_path(
+        self: Arc<Self>,
+        req: Request,
+        ip: Option<IpAddr>,
+        api: Option<ApiOp>,
+    ) -> Result<Response> {
         let mut res = Response::default();
 
         let req_path = req.uri().path();
         let headers = req.headers();
         let method = req.method().clone();
 
+        if method == Method::GET && req_path.strip_prefix(&self.assets_prefix) == Some("events") {
+            self.handle_events(req, &mut res).await?;
+            return Ok(res);
+        }
+
+        if let Some(id) = req_path
+            .strip_prefix(&self.assets_prefix)
+            .and_then(|v| v.strip_prefix("downloads/"))
+            .filter(|_| method == Method::GET && self.args.archive_progress)
+        {
+            let id = id.to_string();
+            self.handle_download_progress(&id, &mut res)?;
+            return Ok(res);
+        }
+
+        if let Some(name) = req_path
+            .strip_prefix(&self.assets_prefix)
+            .and_then(|v| v.strip_prefix("dlna/"))
+            .filter(|_| self.args.dlna)
+        {
+            let name = name.to_string();
+            self.handle_dlna(&name, req, &mut res).await?;
+            return Ok(res);
+        }
+
         if method == Method::GET && self.handle_assets(req_path, headers, &mut res).await? {
             return Ok(res);
         }
@@ -150,11 +611,87 @@ impl Server {
             }
         };
 
-        let guard = self.args.auth.guard(&relative_path, &method, authorization);
+        let query = req.uri().query().unwrap_or_default();
+        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        if self.args.auth_method == AuthMethod::Form {
+            if query_params.contains_key("login") {
+                let location = self.same_origin_location(req_path, &relative_path);
+                if method == Method::POST {
+                    match read_body_limited(req.into_body(), LOGIN_BODY_MAX_SIZE).await? {
+                        Some(body) => self.handle_login(&location, &body, ip, &mut res).await?,
+                        None => status_payload_too_large(&mut res),
+                    }
+                } else {
+                    self.handle_login_page(None, &mut res).await?;
+                }
+                return Ok(res);
+            } else if query_params.contains_key("logout") {
+                let location = self.same_origin_location(req_path, &relative_path);
+                self.handle_logout(&location, headers, &mut res)?;
+                return Ok(res);
+            }
+        }
+
+        if authorization.is_some() {
+            if let Some(retry_after) = self.lockout.locked(ip) {
+                status_too_many_requests(&mut res, retry_after);
+                return Ok(res);
+            }
+            self.args.auth.precheck(headers).await;
+        }
+
+        // The path a GET is served from is picked once the requested path is authorized, so
+        // nothing is told about what exists where the client can't go, then it's authorized
+        // in turn
+        let relative_path = match self.args.try_files.as_ref() {
+            Some(try_files)
+                if api.is_none()
+                    && (method == Method::GET || method == Method::HEAD)
+                    && self
+                        .guard_request(&relative_path, &method, &req)
+                        .1
+                        .is_some() =>
+            {
+                match self.try_files(try_files, &relative_path).await {
+                    Ok(v) => v,
+                    Err(status) => {
+                        match status {
+                            StatusCode::NOT_FOUND => status_not_found(&mut res),
+                            status => *res.status_mut() = status,
+                        }
+                        return Ok(res);
+                    }
+                }
+            }
+            _ => relative_path,
+        };
+
+        let guard = self.guard_request(&relative_path, &method, &req);
+
+        if let Some(rate_limiter) = self.rate_limiter.as_ref() {
+            if let Some(client) = Client::new(guard.0.as_deref(), ip) {
+                if let Err(retry_after) = rate_limiter.acquire(client) {
+                    self.load.reject_rate_limit();
+                    status_too_many_requests(&mut res, retry_after);
+                    return Ok(res);
+                }
+            }
+        }
+
+        if authorization.is_some() {
+            match guard {
+                (None, None) => self.lockout.fail(ip),
+                (Some(_), _) => self.lockout.succeed(ip),
+                _ => {}
+            }
+        }
 
         let (user, access_paths) = match guard {
             (None, None) => {
-                self.auth_reject(&mut res)?;
+                self.auth_reject(&method, headers, &mut res).await?;
                 return Ok(res);
             }
             (Some(_), None) => {
@@ -164,15 +701,29 @@ impl Server {
             (x, Some(y)) => (x, y),
         };
 
-        let query = req.uri().query().unwrap_or_default();
-        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
-
         if method.as_str() == "WRITEABLE" {
             return Ok(res);
         }
 
+        // The writes of the JSON API are carried out as their webdav counterparts
+        let method = api.and_then(|v| v.write_method()).unwrap_or(method);
+
+        if let Some(bucket) = self.bucket.clone() {
+            self.handle_bucket(
+                &bucket,
+                &relative_path,
+                method,
+                api,
+                &query_params,
+                user,
+                access_paths,
+                req,
+                &mut res,
+            )
+            .await?;
+            return Ok(res);
+        }
+
         let head_only = method == Method::HEAD;
 
         if self.args.path_is_file {
@@ -198,11 +749,20 @@ impl Server {
 
         let path = path.as_path();
 
-        let (is_miss, is_dir, is_file, size) = match fs::metadata(path).await.ok() {
+        let meta = match self.preload.as_ref().and_then(|v| v.get(path)) {
+            Some(entry) => Some(entry.meta),
+            None => fs::metadata(path).await.ok(),
+        };
+        let (is_miss, is_dir, is_file, size) = match &meta {
             Some(meta) => (false, meta.is_dir(), meta.is_file(), meta.len()),
             None => (true, false, false, 0),
         };
 
+        if self.is_excluded(&relative_path, is_dir) {
+            status_not_found(&mut res);
+            return Ok(res);
+        }
+
         let allow_upload = self.args.allow_upload;
         let allow_delete = self.args.allow_delete;
         let allow_search = self.args.allow_search;
@@ -211,22 +771,122 @@ impl Server {
         let render_spa = self.args.render_spa;
         let render_try_index = self.args.render_try_index;
 
-        if !self.args.allow_symlink && !is_miss && !self.is_root_contained(path).await {
+        if !is_miss && !self.is_symlink_allowed(path).await {
             status_not_found(&mut res);
             return Ok(res);
         }
 
+        if api == Some(ApiOp::Diff) {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            self.handle_api_diff(
+                &relative_path,
+                is_dir,
+                is_miss,
+                &query_params,
+                access_paths,
+                &body,
+                &mut res,
+            )
+            .await?;
+            return Ok(res);
+        }
+
+        if let Some(op) = api.filter(|v| v.write_method().is_none()) {
+            self.handle_api_read(
+                op,
+                path,
+                &relative_path,
+                is_dir,
+                is_miss,
+                &query_params,
+                access_paths,
+                &mut res,
+            )
+            .await?;
+            return Ok(res);
+        }
+
+        // Dropbox, new entries can be created but nothing is listed or read
+        if access_paths.perm().uploadonly() {
+            let is_create = is_miss && (method == Method::PUT || method.as_str() == "MKCOL");
+            if !is_create {
+                if (method == Method::GET || method == Method::HEAD)
+                    && (is_dir || (is_miss && req_path.ends_with('/')))
+                {
+                    self.send_index(
+                        path,
+                        vec![],
+                        None,
+                        is_dir,
+                        &query_params,
+                        head_only,
+                        user,
+                        access_paths,
+                        &mut res,
+                    )
+                    .await?;
+                } else if user.is_none() {
+                    self.auth_reject(&method, headers, &mut res).await?;
+                } else {
+                    status_forbid(&mut res);
+                }
+                return Ok(res);
+            }
+        }
+
+        if relative_path.is_empty()
+            && (query_params.contains_key("trash") || query_params.contains_key("restore"))
+        {
+            match &self.trash {
+                Some(trash) if allow_delete && access_paths.perm().readwrite() => {
+                    self.handle_trash(trash, &method, &query_params, &mut res)
+                        .await?
+                }
+                Some(_) => status_forbid(&mut res),
+                None => status_not_found(&mut res),
+            }
+            return Ok(res);
+        }
+
+        if matches!(method.as_str(), "PUT" | "DELETE" | "MOVE")
+            && !write_preconditions_pass(headers, meta.as_ref())
+        {
+            *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+            return Ok(res);
+        }
+
         match method {
             Method::GET | Method::HEAD => {
-                if is_dir {
-                    if render_try_index {
+                if (is_dir || is_file) && query_params.contains_key("stat") {
+                    self.handle_stat(path, is_dir, head_only, &mut res).await?;
+                } else if is_dir {
+                    if query_params.contains_key("watch") {
+                        self.clone()
+                            .handle_watch_dir(path, head_only, access_paths, &mut res)
+                            .await?;
+                    } else if render_try_index {
                         if allow_archive && query_params.contains_key("zip") {
                             if !allow_archive {
                                 status_not_found(&mut res);
                                 return Ok(res);
                             }
-                            self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                                .await?;
+                            self.handle_zip_dir(
+                                path,
+                                head_only,
+                                query_params.get("download-id"),
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                        } else if allow_archive && is_tar_query(&query_params) {
+                            self.handle_tar_dir(
+                                path,
+                                query_params.contains_key("tar.gz"),
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
                         } else if allow_search && query_params.contains_key("q") {
                             self.handle_search_dir(
                                 path,
@@ -249,6 +909,9 @@ impl Server {
                             )
                             .await?;
                         }
+                    } else if render_spa && !render_index && self.index_file(path).await.is_none() {
+                        self.handle_render_spa(&relative_path, headers, head_only, &mut res)
+                            .await?;
                     } else if render_index || render_spa {
                         self.handle_render_index(
                             path,
@@ -265,8 +928,27 @@ impl Server {
                             status_not_found(&mut res);
                             return Ok(res);
                         }
-                        self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                            .await?;
+                        self.handle_zip_dir(
+                            path,
+                            head_only,
+                            query_params.get("download-id"),
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if is_tar_query(&query_params) {
+                        if !allow_archive {
+                            status_not_found(&mut res);
+                            return Ok(res);
+                        }
+                        self.handle_tar_dir(
+                            path,
+                            query_params.contains_key("tar.gz"),
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
                     } else if allow_search && query_params.contains_key("q") {
                         self.handle_search_dir(
                             path,
@@ -277,6 +959,29 @@ impl Server {
                             &mut res,
                         )
                         .await?;
+                    } else if query_params.contains_key("metalink") {
+                        self.handle_metalink(
+                            path,
+                            true,
+                            headers,
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if query_params.contains_key("du") {
+                        self.handle_du(path, &relative_path, head_only, access_paths, &mut res)
+                            .await?;
+                    } else if is_stream_query(&query_params) {
+                        self.clone()
+                            .handle_stream_dir(
+                                path,
+                                &query_params,
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
                     } else {
                         self.handle_ls_dir(
                             path,
@@ -291,17 +996,63 @@ impl Server {
                     }
                 } else if is_file {
                     if query_params.contains_key("edit") {
-                        self.handle_deal_file(path, DataKind::Edit, head_only, user, &mut res)
-                            .await?;
+                        self.handle_deal_file(
+                            path,
+                            DataKind::Edit,
+                            &query_params,
+                            head_only,
+                            user,
+                            &mut res,
+                        )
+                        .await?;
                     } else if query_params.contains_key("view") {
-                        self.handle_deal_file(path, DataKind::View, head_only, user, &mut res)
+                        self.handle_deal_file(
+                            path,
+                            DataKind::View,
+                            &query_params,
+                            head_only,
+                            user,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if query_params.contains_key("metalink") {
+                        self.handle_metalink(
+                            path,
+                            false,
+                            headers,
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if query_params.contains_key("torrent") {
+                        self.handle_torrent(path, headers, head_only, &mut res)
+                            .await?;
+                    } else if query_params.contains_key("hash") {
+                        self.handle_hash_file(path, &query_params, head_only, &mut res)
+                            .await?;
+                    } else if query_params.contains_key("signature") {
+                        self.handle_signature(path, &query_params, head_only, &mut res)
                             .await?;
+                    } else if query_params.contains_key("thumb") {
+                        self.handle_thumbnail(path, &query_params, headers, head_only, &mut res)
+                            .await?;
+                    } else if query_params.contains_key("gallery") {
+                        self.handle_gallery_item(
+                            path,
+                            &relative_path,
+                            &query_params,
+                            headers,
+                            head_only,
+                            &mut res,
+                        )
+                        .await?;
                     } else {
                         self.handle_send_file(path, headers, head_only, &mut res)
                             .await?;
                     }
                 } else if render_spa {
-                    self.handle_render_spa(path, headers, head_only, &mut res)
+                    self.handle_render_spa(&relative_path, headers, head_only, &mut res)
                         .await?;
                 } else if allow_upload && req_path.ends_with('/') {
                     self.handle_ls_dir(
@@ -321,24 +1072,113 @@ impl Server {
             Method::OPTIONS => {
                 set_webdav_headers(&mut res);
             }
+            Method::PUT if query_params.contains_key("unzip") => {
+                if !allow_upload || is_dir {
+                    status_forbid(&mut res);
+                } else {
+                    self.clone()
+                        .handle_extract(&relative_path, ip, req, &mut res)
+                        .await?;
+                }
+            }
+            Method::PUT if query_params.contains_key("delta") => {
+                if !allow_upload || !allow_delete || is_dir {
+                    status_forbid(&mut res);
+                } else if is_miss {
+                    status_not_found(&mut res);
+                } else if self.short_of_space(path, size).await? {
+                    status_insufficient_storage(&mut res);
+                } else {
+                    let limit = self.args.max_upload_size.get(&relative_path);
+                    self.handle_delta(path, size, limit, req, &mut res).await?;
+                    if res.status().is_success() {
+                        self.publish(FsEventKind::Upload, path, None);
+                        self.hooks
+                            .run(HookEvent::Upload, path, None, user.as_deref());
+                    }
+                }
+            }
             Method::PUT => {
-                if !allow_upload || (!allow_delete && is_file && size > 0) {
+                let range = match headers.get(CONTENT_RANGE).map(parse_content_range) {
+                    Some(Some(UploadRange::Bytes(start, end))) => Some((start, end)),
+                    Some(Some(UploadRange::Unsatisfied(total))) => {
+                        let size = if is_file { size } else { 0 };
+                        let has_body = headers
+                            .typed_get::<ContentLength>()
+                            .is_some_and(|v| v.0 > 0);
+                        if !allow_upload {
+                            status_forbid(&mut res);
+                        } else if has_body {
+                            *res.status_mut() = StatusCode::BAD_REQUEST;
+                        } else if size == total {
+                            status_no_content(&mut res);
+                        } else {
+                            *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                            res.headers_mut()
+                                .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                        }
+                        return Ok(res);
+                    }
+                    Some(None) => {
+                        *res.status_mut() = StatusCode::BAD_REQUEST;
+                        return Ok(res);
+                    }
+                    None => None,
+                };
+                let offset = range.map(|v| v.0);
+                let growth = upload_growth(headers, offset, is_file, size);
+                if !allow_upload || (!allow_delete && is_file && size > offset.unwrap_or(0)) {
                     status_forbid(&mut res);
+                } else if offset.map(|v| v > size).unwrap_or_default() {
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    res.headers_mut()
+                        .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                } else if self.quotas.exceeded(path, growth).await?
+                    || self.short_of_space(path, growth).await?
+                {
+                    status_insufficient_storage(&mut res);
                 } else {
-                    self.handle_upload(path, req, &mut res).await?;
+                    let limit = self.args.max_upload_size.get(&relative_path);
+                    self.handle_upload(path, range, is_file, limit, req, &mut res)
+                        .await?;
+                    if res.status().is_success() {
+                        let old_size = if is_file { size } else { 0 };
+                        let new_size = fs::metadata(path).await?.len();
+                        self.quotas.add(path, new_size as i64 - old_size as i64);
+                        self.publish(FsEventKind::Upload, path, None);
+                        self.hooks
+                            .run(HookEvent::Upload, path, None, user.as_deref());
+                    }
                 }
             }
             Method::DELETE => {
-                if !allow_delete {
+                if !allow_delete || self.is_mount(path) {
                     status_forbid(&mut res);
-                } else if !is_miss {
-                    self.handle_delete(path, is_dir, &mut res).await?
-                } else {
+                } else if is_miss {
                     status_not_found(&mut res);
+                } else if let Some((status, message)) =
+                    self.unconfirmed_rm_dir(path, is_dir, &query_params).await?
+                {
+                    *res.status_mut() = status;
+                    *res.body_mut() = message.into();
+                } else {
+                    self.handle_delete(path, &relative_path, is_dir, &mut res)
+                        .await?;
+                    self.hooks
+                        .run(HookEvent::Delete, path, None, user.as_deref());
                 }
             }
             method => match method.as_str() {
                 "PROPFIND" => {
+                    let depth = headers.get("depth").cloned();
+                    let body = hyper::body::to_bytes(req.into_body()).await?;
+                    let propfind = match PropFind::parse(&body) {
+                        Ok(v) => v,
+                        Err(_) => {
+                            *res.status_mut() = StatusCode::BAD_REQUEST;
+                            return Ok(res);
+                        }
+                    };
                     if is_dir {
                         let access_paths = if access_paths.perm().indexonly() {
                             // see https://github.com/sigoden/dufs/issues/229
@@ -346,10 +1186,44 @@ impl Server {
                         } else {
                             access_paths
                         };
-                        self.handle_propfind_dir(path, headers, access_paths, &mut res)
+                        self.clone()
+                            .handle_propfind_dir(
+                                path,
+                                depth.as_ref(),
+                                propfind,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
+                    } else if is_file {
+                        self.handle_propfind_file(path, &propfind, &mut res).await?;
+                    } else {
+                        status_not_found(&mut res);
+                    }
+                }
+                "REPORT" => {
+                    let body = hyper::body::to_bytes(req.into_body()).await?;
+                    let sync = match SyncCollection::parse(&body) {
+                        Ok(Some(v)) => v,
+                        Ok(None) => {
+                            res_dav_error(&mut res, "supported-report");
+                            return Ok(res);
+                        }
+                        Err(_) => {
+                            *res.status_mut() = StatusCode::BAD_REQUEST;
+                            return Ok(res);
+                        }
+                    };
+                    if is_dir {
+                        let access_paths = if access_paths.perm().indexonly() {
+                            AccessPaths::new(AccessPerm::ReadOnly)
+                        } else {
+                            access_paths
+                        };
+                        self.handle_sync_collection(path, sync, access_paths, &mut res)
                             .await?;
                     } else if is_file {
-                        self.handle_propfind_file(path, &mut res).await?;
+                        res_dav_error(&mut res, "supported-report");
                     } else {
                         status_not_found(&mut res);
                     }
@@ -367,8 +1241,12 @@ impl Server {
                     } else if !is_miss {
                         *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
                         *res.body_mut() = Body::from("Already exists");
+                    } else if self.quotas.exceeded(path, 0).await? {
+                        status_insufficient_storage(&mut res);
                     } else {
                         self.handle_mkcol(path, &mut res).await?;
+                        self.hooks
+                            .run(HookEvent::Mkdir, path, None, user.as_deref());
                     }
                 }
                 "COPY" => {
@@ -377,16 +1255,25 @@ impl Server {
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_copy(path, &req, &mut res).await?
+                        self.handle_copy(path, &access_paths, &req, &mut res)
+                            .await?
                     }
                 }
                 "MOVE" => {
-                    if !allow_upload || !allow_delete {
+                    if !allow_upload || !allow_delete || self.is_mount(path) {
                         status_forbid(&mut res);
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_move(path, &req, &mut res).await?
+                        self.handle_move(path, &req, &mut res).await?;
+                        if res.status().is_success() {
+                            let dest = self
+                                .extract_destination_header(req.headers())
+                                .and_then(|v| self.resolve_path(&v))
+                                .and_then(|v| self.join_path(&v));
+                            self.hooks
+                                .run(HookEvent::Move, path, dest.as_deref(), user.as_deref());
+                        }
                     }
                 }
                 "LOCK" => {
@@ -412,78 +1299,1340 @@ impl Server {
         Ok(res)
     }
 
-    async fn handle_upload(&self, path: &Path, mut req: Request, res: &mut Response) -> Result<()> {
-        ensure_path_parent(path).await?;
-
-        let mut file = match fs::File::create(&path).await {
-            Ok(v) => v,
-            Err(_) => {
+    /// Handles a request when the serve path is object storage. The files are listed,
+    /// read and written through WebDAV and the web UI, the features that need a local
+    /// filesystem aren't there.
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_bucket(
+        &self,
+        bucket: &Arc<Bucket>,
+        relative_path: &str,
+        method: Method,
+        api: Option<ApiOp>,
+        query_params: &HashMap<String, String>,
+        user: Option<String>,
+        access_paths: AccessPaths,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let head_only = method == Method::HEAD;
+        let req_path = req.uri().path().to_string();
+        let path = match self.join_path(relative_path) {
+            Some(v) => v,
+            None => {
                 status_forbid(res);
                 return Ok(());
             }
         };
+        let path = path.as_path();
 
-        let body_with_io_error = req
-            .body_mut()
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+        if api.is_some_and(|v| v.write_method().is_none()) {
+            status_not_implemented(res);
+            return Ok(());
+        }
 
-        let body_reader = StreamReader::new(body_with_io_error);
+        let entry = bucket.stat(relative_path).await?;
+        let (is_miss, is_dir, is_file) = match &entry {
+            Some(entry) => (false, entry.is_dir, !entry.is_dir),
+            None => (true, false, false),
+        };
 
-        futures::pin_mut!(body_reader);
+        if self.is_excluded(relative_path, is_dir) {
+            status_not_found(res);
+            return Ok(());
+        }
 
-        let ret = io::copy(&mut body_reader, &mut file).await;
-        if ret.is_err() {
-            tokio::fs::remove_file(&path).await?;
+        let allow_upload = self.args.allow_upload;
+        let allow_delete = self.args.allow_delete;
 
-            ret?;
+        // Dropbox, new entries can be created but nothing is listed or read
+        if access_paths.perm().uploadonly() {
+            let is_create = is_miss && (method == Method::PUT || method.as_str() == "MKCOL");
+            if !is_create {
+                if (method == Method::GET || method == Method::HEAD)
+                    && (is_dir || (is_miss && req_path.ends_with('/')))
+                {
+                    self.send_index(
+                        path,
+                        vec![],
+                        None,
+                        is_dir,
+                        query_params,
+                        head_only,
+                        user,
+                        access_paths,
+                        res,
+                    )
+                    .await?;
+                } else if user.is_none() {
+                    self.auth_reject(&method, req.headers(), res).await?;
+                } else {
+                    status_forbid(res);
+                }
+                return Ok(());
+            }
         }
 
-        *res.status_mut() = StatusCode::CREATED;
+        match method {
+            Method::GET | Method::HEAD => {
+                if is_dir {
+                    if query_params.contains_key("zip") || is_tar_query(query_params) {
+                        status_not_found(res);
+                    } else if ["stat", "watch", "metalink", "du", "stream"]
+                        .iter()
+                        .any(|v| query_params.contains_key(*v))
+                    {
+                        status_not_implemented(res);
+                    } else {
+                        let paths = self
+                            .list_bucket_dir(bucket, relative_path, &access_paths)
+                            .await?;
+                        self.send_index(
+                            path,
+                            paths,
+                            None,
+                            true,
+                            query_params,
+                            head_only,
+                            user,
+                            access_paths,
+                            res,
+                        )
+                        .await?;
+                    }
+                } else if let Some(entry) = entry.filter(|_| is_file) {
+                    if [
+                        "stat",
+                        "edit",
+                        "view",
+                        "metalink",
+                        "torrent",
+                        "hash",
+                        "signature",
+                        "thumb",
+                        "gallery",
+                    ]
+                    .iter()
+                    .any(|v| query_params.contains_key(*v))
+                    {
+                        status_not_implemented(res);
+                    } else {
+                        self.handle_bucket_file(
+                            bucket,
+                            relative_path,
+                            &entry,
+                            req.headers(),
+                            head_only,
+                            res,
+                        )
+                        .await?;
+                    }
+                } else if allow_upload && req_path.ends_with('/') {
+                    self.send_index(
+                        path,
+                        vec![],
+                        None,
+                        false,
+                        query_params,
+                        head_only,
+                        user,
+                        access_paths,
+                        res,
+                    )
+                    .await?;
+                } else {
+                    status_not_found(res);
+                }
+            }
+            Method::OPTIONS => {
+                set_webdav_headers(res);
+            }
+            Method::PUT => {
+                if query_params.contains_key("unzip")
+                    || query_params.contains_key("delta")
+                    || req.headers().contains_key(CONTENT_RANGE)
+                {
+                    status_not_implemented(res);
+                } else if !allow_upload || is_dir || (!allow_delete && is_file) {
+                    status_forbid(res);
+                } else {
+                    self.handle_bucket_upload(bucket, relative_path, path, req, res)
+                        .await?;
+                    if res.status().is_success() {
+                        self.publish(FsEventKind::Upload, path, None);
+                        self.hooks
+                            .run(HookEvent::Upload, path, None, user.as_deref());
+                    }
+                }
+            }
+            Method::DELETE => {
+                if !allow_delete || relative_path.is_empty() {
+                    status_forbid(res);
+                } else if is_miss {
+                    status_not_found(res);
+                } else {
+                    bucket.delete(relative_path, is_dir).await?;
+                    self.publish(FsEventKind::Delete, path, None);
+                    status_no_content(res);
+                    self.hooks
+                        .run(HookEvent::Delete, path, None, user.as_deref());
+                }
+            }
+            method => match method.as_str() {
+                "PROPFIND" => {
+                    let depth = match req.headers().get("depth").map(|v| v.to_str()) {
+                        None | Some(Ok("1")) => 1,
+                        Some(Ok("0")) => 0,
+                        Some(Ok(v)) if v.trim().eq_ignore_ascii_case("infinity") => {
+                            res_dav_error(res, "propfind-finite-depth");
+                            return Ok(());
+                        }
+                        _ => {
+                            *res.status_mut() = StatusCode::BAD_REQUEST;
+                            return Ok(());
+                        }
+                    };
+                    let body = hyper::body::to_bytes(req.into_body()).await?;
+                    let propfind = match PropFind::parse(&body) {
+                        Ok(v) => v,
+                        Err(_) => {
+                            *res.status_mut() = StatusCode::BAD_REQUEST;
+                            return Ok(());
+                        }
+                    };
+                    let entry = match entry {
+                        Some(v) => v,
+                        None => {
+                            status_not_found(res);
+                            return Ok(());
+                        }
+                    };
+                    let mut paths = vec![bucket_pathitem(relative_path.to_string(), &entry)];
+                    if is_dir && depth != 0 {
+                        // see https://github.com/sigoden/dufs/issues/229
+                        let access_paths = if access_paths.perm().indexonly() {
+                            AccessPaths::new(AccessPerm::ReadOnly)
+                        } else {
+                            access_paths
+                        };
+                        let children = self
+                            .list_bucket_dir(bucket, relative_path, &access_paths)
+                            .await?;
+                        paths.extend(children.into_iter().map(|mut v| {
+                            v.name = normalize_path(Path::new(relative_path).join(&v.name));
+                            v
+                        }));
+                    }
+                    let output = paths
+                        .iter()
+                        .map(|v| v.to_dav_xml(self.args.uri_prefix.as_str(), &propfind))
+                        .collect::<String>();
+                    res_multistatus(res, &output);
+                }
+                "PROPPATCH" => {
+                    if is_file {
+                        self.handle_proppatch(&req_path, res).await?;
+                    } else {
+                        status_not_found(res);
+                    }
+                }
+                "MKCOL" => {
+                    if !allow_upload {
+                        status_forbid(res);
+                    } else if !is_miss {
+                        *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                        *res.body_mut() = Body::from("Already exists");
+                    } else {
+                        bucket.mkdir(relative_path).await?;
+                        self.publish(FsEventKind::Mkdir, path, None);
+                        *res.status_mut() = StatusCode::CREATED;
+                        self.hooks
+                            .run(HookEvent::Mkdir, path, None, user.as_deref());
+                    }
+                }
+                "COPY" | "MOVE" => {
+                    let is_move = method.as_str() == "MOVE";
+                    if !allow_upload || (is_move && !allow_delete) {
+                        status_forbid(res);
+                    } else if let Some(entry) = entry {
+                        let dest = self
+                            .handle_bucket_copy(
+                                bucket,
+                                relative_path,
+                                &entry,
+                                is_move,
+                                &access_paths,
+                                &req,
+                                res,
+                            )
+                            .await?;
+                        if let (Some(dest), true) = (dest, is_move) {
+                            self.hooks
+                                .run(HookEvent::Move, path, Some(&dest), user.as_deref());
+                        }
+                    } else {
+                        status_not_found(res);
+                    }
+                }
+                "LOCK" => {
+                    // Fake lock
+                    if is_file {
+                        let has_auth = req.headers().contains_key(AUTHORIZATION);
+                        self.handle_lock(&req_path, has_auth, res).await?;
+                    } else {
+                        status_not_found(res);
+                    }
+                }
+                "UNLOCK" => {
+                    // Fake unlock
+                    if is_miss {
+                        status_not_found(res);
+                    }
+                }
+                _ => {
+                    *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                }
+            },
+        }
         Ok(())
     }
 
-    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
-        match is_dir {
-            true => fs::remove_dir_all(path).await?,
-            false => fs::remove_file(path).await?,
+    /// Sends the file of the bucket, a single range of it is read with a ranged request
+    async fn handle_bucket_file(
+        &self,
+        bucket: &Arc<Bucket>,
+        relative_path: &str,
+        entry: &Entry,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let name = relative_path.rsplit('/').next().unwrap_or_default();
+        let cache_control = self
+            .args
+            .cache_control
+            .get(name)
+            .or(self.args.cache_control_default.as_ref());
+        if let Some(value) = cache_control {
+            res.headers_mut().insert(CACHE_CONTROL, value.clone());
+        }
+        let last_modified =
+            LastModified::from(SystemTime::UNIX_EPOCH + Duration::from_millis(entry.mtime));
+        let mut use_range = false;
+        match entry.etag.as_ref().and_then(|v| v.parse::<ETag>().ok()) {
+            Some(etag) => {
+                use_range = match set_cache_headers(headers, etag, last_modified, res) {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
+            }
+            None => res.headers_mut().typed_insert(last_modified),
         }
 
-        status_no_content(res);
+        let size = entry.size;
+        let ranges = if use_range {
+            parse_ranges(headers, size)
+        } else {
+            None
+        };
+
+        let content_type = mime_guess::from_path(name)
+            .first_or_octet_stream()
+            .to_string();
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+        set_content_disposition(res, true, name)?;
+        res.headers_mut().typed_insert(AcceptRanges::bytes());
+
+        let range = match ranges.as_deref() {
+            Some([]) => {
+                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.headers_mut()
+                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                return Ok(());
+            }
+            Some([(start, end)]) => {
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                res.headers_mut().insert(
+                    CONTENT_RANGE,
+                    format!("bytes {start}-{end}/{size}").parse()?,
+                );
+                Some((*start, *end))
+            }
+            // Several ranges would take a request each, the whole file is sent instead
+            _ => None,
+        };
+        let length = range.map(|(start, end)| end - start + 1).unwrap_or(size);
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, format!("{length}").parse()?);
+        if head_only || length == 0 {
+            return Ok(());
+        }
+        let reader = bucket.get(relative_path, range).await?;
+        *res.body_mut() = Body::wrap_stream(
+            FileStreamer::new(reader, self.args.buffer_size).into_stream_sized(length),
+        );
         Ok(())
     }
 
-    async fn handle_ls_dir(
+    /// Writes the body of the PUT to the bucket as it comes, checking it like `handle_upload`
+    async fn handle_bucket_upload(
         &self,
+        bucket: &Arc<Bucket>,
+        relative_path: &str,
         path: &Path,
-        exist: bool,
-        query_params: &HashMap<String, String>,
-        head_only: bool,
-        user: Option<String>,
-        access_paths: AccessPaths,
+        req: Request,
         res: &mut Response,
     ) -> Result<()> {
-        let mut paths = vec![];
-        if exist {
-            paths = match self.list_dir(path, path, access_paths.clone()).await {
-                Ok(paths) => paths,
-                Err(_) => {
-                    status_forbid(res);
-                    return Ok(());
-                }
+        let limit = self.args.max_upload_size.get(relative_path);
+        if let (Some(limit), Some(ContentLength(len))) =
+            (limit, req.headers().typed_get::<ContentLength>())
+        {
+            if len > limit {
+                status_payload_too_large(res);
+                return Ok(());
             }
-        };
-        self.send_index(
-            path,
-            paths,
-            exist,
+        }
+
+        if !self.upload_types.allows_name(get_file_name(path)) {
+            status_unsupported_type(res, path);
+            return Ok(());
+        }
+
+        let mut body = req.into_body();
+
+        // The type of the file is told by its first bytes, before anything is written
+        let mut head = vec![];
+        if self.upload_types.is_restricted() {
+            while (head.len() as u64) < SNIFF_LEN {
+                match body.next().await {
+                    Some(chunk) => head.extend_from_slice(&chunk?),
+                    None => break,
+                }
+            }
+            if !self
+                .upload_types
+                .allows_content(&head[..head.len().min(SNIFF_LEN as usize)])
+            {
+                status_unsupported_type(res, path);
+                return Ok(());
+            }
+        }
+
+        let mut received = 0;
+        let body = futures::stream::iter([Ok(Bytes::from(head))])
+            .chain(body.map_err(anyhow::Error::from))
+            .map(move |chunk| {
+                let chunk = chunk?;
+                received += chunk.len() as u64;
+                if limit.is_some_and(|v| received > v) {
+                    return Err(BodyError::TooLarge.into());
+                }
+                Ok(chunk)
+            });
+        bucket.put(relative_path, body).await?;
+        *res.status_mut() = StatusCode::CREATED;
+        Ok(())
+    }
+
+    /// Copies or moves the file or folder of the bucket to the destination of the request,
+    /// object by object, and returns the destination once it's done. A copy leaves out what
+    /// the user doesn't see of the folder, like `copy_dir_all`.
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_bucket_copy(
+        &self,
+        bucket: &Arc<Bucket>,
+        relative_path: &str,
+        entry: &Entry,
+        is_move: bool,
+        access_paths: &AccessPaths,
+        req: &Request,
+        res: &mut Response,
+    ) -> Result<Option<PathBuf>> {
+        let (dest_name, upload_only) = match self.extract_dest_name(req, res) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let overwrite = match req.headers().get("overwrite").map(|v| v.as_bytes()) {
+            None | Some(b"T") | Some(b"t") => true,
+            Some(b"F") | Some(b"f") => false,
+            _ => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(None);
+            }
+        };
+        let (src, dest) = (Path::new(relative_path), Path::new(&dest_name));
+        // Into itself, or over a folder it's in
+        if relative_path.is_empty() || dest.starts_with(src) || src.starts_with(dest) {
+            status_forbid(res);
+            return Ok(None);
+        }
+        let dest_path = match self.join_path(&dest_name) {
+            Some(v) => v,
+            None => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(None);
+            }
+        };
+
+        if !entry.is_dir && self.upload_types.is_restricted() {
+            let mut head = vec![];
+            if entry.size > 0 {
+                let end = entry.size.min(SNIFF_LEN) - 1;
+                let mut reader = bucket.get(relative_path, Some((0, end))).await?;
+                head = tokio::task::spawn_blocking(move || {
+                    reader.read_to_end(&mut head).map(|_| head)
+                })
+                .await??;
+            }
+            if !self.upload_types.allows(get_file_name(&dest_path), &head) {
+                status_unsupported_type(res, &dest_path);
+                return Ok(None);
+            }
+        } else if !entry.is_dir && !self.upload_types.allows_name(get_file_name(&dest_path)) {
+            status_unsupported_type(res, &dest_path);
+            return Ok(None);
+        }
+
+        let existing = bucket.stat(&dest_name).await?;
+        let overwritten = existing.is_some();
+        if overwritten && (upload_only || !overwrite) {
+            match upload_only {
+                // A dropbox takes new entries, it doesn't let the ones there be replaced
+                true => status_forbid(res),
+                false => *res.status_mut() = StatusCode::PRECONDITION_FAILED,
+            }
+            return Ok(None);
+        }
+
+        let names = match entry.is_dir {
+            true => {
+                let leaves = access_paths.leaf_paths(Path::new(""));
+                let posix_hidden = self.args.posix_hidden;
+                bucket
+                    .walk(relative_path)
+                    .await?
+                    .into_iter()
+                    .filter(|name| {
+                        if is_move {
+                            return true;
+                        }
+                        let parts: Vec<&str> = name.trim_end_matches('/').split('/').collect();
+                        let hidden = parts.iter().enumerate().any(|(i, part)| {
+                            let is_dir = i + 1 < parts.len() || name.ends_with('/');
+                            is_hidden(&self.hidden, posix_hidden, part, is_dir)
+                        });
+                        !hidden && leaves.iter().any(|v| Path::new(name).starts_with(v))
+                    })
+                    .collect()
+            }
+            false => vec![String::new()],
+        };
+
+        // The destination is replaced, not merged
+        if let Some(existing) = existing {
+            bucket.delete(&dest_name, existing.is_dir).await?;
+        }
+        if entry.is_dir {
+            bucket.mkdir(&dest_name).await?;
+        }
+        bucket
+            .copy(relative_path, &dest_name, entry.is_dir, names)
+            .await?;
+        let src_path = self.args.serve_path.join(relative_path);
+        if is_move {
+            bucket.delete(relative_path, entry.is_dir).await?;
+            self.publish(FsEventKind::Move, &src_path, Some(&dest_path));
+        } else {
+            self.publish(FsEventKind::Copy, &src_path, Some(&dest_path));
+        }
+        status_copied(res, overwritten);
+        Ok(Some(dest_path))
+    }
+
+    /// The entries of the folder of the bucket the user sees, named relative to it
+    async fn list_bucket_dir(
+        &self,
+        bucket: &Arc<Bucket>,
+        dir: &str,
+        access_paths: &AccessPaths,
+    ) -> Result<Vec<PathItem>> {
+        let children = access_paths.child_paths();
+        let indexonly = access_paths.perm().indexonly();
+        let paths = bucket
+            .list(dir)
+            .await?
+            .into_iter()
+            .filter(|v| !indexonly || children.iter().any(|name| **name == v.name))
+            .filter(|v| !is_hidden(&self.hidden, self.args.posix_hidden, &v.name, v.is_dir))
+            .map(|v| bucket_pathitem(v.name.clone(), &v))
+            .collect();
+        Ok(paths)
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_upload(
+        &self,
+        path: &Path,
+        range: Option<(u64, u64)>,
+        exist: bool,
+        limit: Option<u64>,
+        mut req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let offset = range.map(|(start, _)| start);
+        // A partial upload must be as long as its range
+        let range_len = range.map(|(start, end)| end - start + 1);
+        if let (Some(range_len), Some(ContentLength(len))) =
+            (range_len, req.headers().typed_get::<ContentLength>())
+        {
+            if len != range_len {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                *res.body_mut() = Body::from("Content-Range doesn't match the body length");
+                return Ok(());
+            }
+        }
+
+        let verifier = match DigestVerifier::from_headers(req.headers(), offset.is_none()) {
+            Ok(v) => v,
+            Err(_) => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(());
+            }
+        };
+
+        // Bytes the body may contain without the file exceeding the limit
+        let remaining = limit.map(|v| v.saturating_sub(offset.unwrap_or(0)));
+        if let (Some(remaining), Some(ContentLength(len))) =
+            (remaining, req.headers().typed_get::<ContentLength>())
+        {
+            if len > remaining {
+                status_payload_too_large(res);
+                return Ok(());
+            }
+        }
+
+        if !self.upload_types.allows_name(get_file_name(path)) {
+            status_unsupported_type(res, path);
+            return Ok(());
+        }
+
+        let progress = self.upload_progress(path, offset, req.headers());
+        let body_with_io_error = req
+            .body_mut()
+            .inspect_ok(|chunk| {
+                if let Some(progress) = progress.as_ref() {
+                    progress(chunk.len() as u64);
+                }
+            })
+            .map_err(io::Error::other);
+
+        let body_reader = StreamReader::new(body_with_io_error);
+
+        // Read one byte past the limit to tell if the body is too large
+        let read_limit = remaining.into_iter().chain(range_len).min();
+        let body_reader = body_reader.take(read_limit.map(|v| v + 1).unwrap_or(u64::MAX));
+
+        futures::pin_mut!(body_reader);
+
+        // The type of the file is told by its first bytes, before anything is written
+        let mut head = vec![];
+        if self.upload_types.is_restricted() && offset.unwrap_or_default() == 0 {
+            (&mut body_reader)
+                .take(SNIFF_LEN)
+                .read_to_end(&mut head)
+                .await?;
+            if !self.upload_types.allows_content(&head) {
+                status_unsupported_type(res, path);
+                return Ok(());
+            }
+        }
+        let mut body_reader = std::io::Cursor::new(head).chain(body_reader);
+
+        ensure_path_parent(path).await?;
+
+        if fs::metadata(path).await.is_ok_and(|v| v.is_dir()) {
+            status_forbid(res);
+            return Ok(());
+        }
+
+        // The body is written next to the file and only put in place once it's all there,
+        // so a failed upload leaves the file as it was
+        let tmp_path = path.with_file_name(format!(
+            ".{}.{}.dufs-upload",
+            get_file_name(path),
+            Uuid::new_v4().simple()
+        ));
+        let mut tmp = match fs::File::create(&tmp_path).await {
+            Ok(v) => v,
+            Err(_) => {
+                status_forbid(res);
+                return Ok(());
+            }
+        };
+
+        let ret = match verifier {
+            Some(mut verifier) => copy_with_digest(&mut body_reader, &mut tmp, &mut verifier)
+                .await
+                .map(|n| (n, verifier.verify())),
+            None => io::copy(&mut body_reader, &mut tmp)
+                .await
+                .map(|n| (n, true)),
+        };
+        let ret = match ret {
+            Ok((n, _)) if remaining.map(|v| n > v).unwrap_or_default() => {
+                Some((StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large"))
+            }
+            Ok((n, _)) if range_len.is_some_and(|v| n != v) => Some((
+                StatusCode::BAD_REQUEST,
+                "Content-Range doesn't match the body length",
+            )),
+            Ok((_, false)) => Some((StatusCode::UNPROCESSABLE_ENTITY, "Digest mismatch")),
+            Ok(_) => None,
+            Err(err) => {
+                drop(tmp);
+                let _ = fs::remove_file(&tmp_path).await;
+                return Err(err.into());
+            }
+        };
+        drop(tmp);
+        if let Some((status, message)) = ret {
+            fs::remove_file(&tmp_path).await?;
+            *res.status_mut() = status;
+            *res.body_mut() = Body::from(message);
+            return Ok(());
+        }
+
+        let ret = match offset {
+            // Partial upload, keep existing data and write from the given offset
+            Some(offset) if exist => {
+                async {
+                    let mut file = fs::OpenOptions::new().write(true).open(path).await?;
+                    file.seek(SeekFrom::Start(offset)).await?;
+                    io::copy(&mut fs::File::open(&tmp_path).await?, &mut file).await?;
+                    file.flush().await?;
+                    fs::remove_file(&tmp_path).await?;
+                    Ok(())
+                }
+                .await
+            }
+            _ => {
+                async {
+                    if exist {
+                        fs::set_permissions(&tmp_path, fs::metadata(path).await?.permissions())
+                            .await?;
+                    }
+                    self.replace_dest(&tmp_path, path, exist).await
+                }
+                .await
+            }
+        };
+        if let Err(err) = ret {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(err);
+        }
+
+        if offset.is_some() && exist {
+            status_no_content(res);
+        } else {
+            *res.status_mut() = StatusCode::CREATED;
+        }
+        Ok(())
+    }
+
+    /// Patches the file with the rsync delta uploaded with `PUT /file?delta`, made against
+    /// its signature. The result is written next to it and renamed over it, so the file is
+    /// never seen half patched.
+    async fn handle_delta(
+        &self,
+        path: &Path,
+        size: u64,
+        limit: Option<u64>,
+        mut req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        // The digests are of the patched file, not of the delta
+        let mut digest_headers = HeaderMap::new();
+        if let Some(value) = req.headers().get("repr-digest") {
+            digest_headers.insert("repr-digest", value.clone());
+        }
+        let mut verifier = match DigestVerifier::from_headers(&digest_headers, true) {
+            Ok(v) => v,
+            Err(_) => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(());
+            }
+        };
+
+        let mut base = fs::File::open(path).await?;
+        let tmp_path = path.with_file_name(format!(
+            ".{}.{}.dufs-delta",
+            get_file_name(path),
+            Uuid::new_v4().simple()
+        ));
+        let mut tmp = fs::File::create(&tmp_path).await?;
+        let body = req.body_mut().map_err(io::Error::other);
+        let mut delta_reader = StreamReader::new(body);
+        let mut writer = InspectWriter::new(&mut tmp, |chunk| {
+            if let Some(verifier) = verifier.as_mut() {
+                verifier.update(chunk);
+            }
+        });
+        let ret = delta::apply(&mut base, &mut delta_reader, &mut writer, limit).await;
+        let ret = match ret {
+            Ok(new_size) => {
+                let mut head = vec![];
+                if self.upload_types.is_restricted() {
+                    tmp.seek(SeekFrom::Start(0)).await?;
+                    (&mut tmp).take(SNIFF_LEN).read_to_end(&mut head).await?;
+                }
+                if verifier.map(|v| v.verify()) == Some(false) {
+                    Err((
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "Digest mismatch".to_string(),
+                    ))
+                } else if self.upload_types.is_restricted()
+                    && !self.upload_types.allows_content(&head)
+                {
+                    Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, String::new()))
+                } else if new_size > size && self.quotas.exceeded(path, new_size - size).await? {
+                    Err((StatusCode::INSUFFICIENT_STORAGE, String::new()))
+                } else {
+                    Ok(new_size)
+                }
+            }
+            Err(err) => match err.downcast::<delta::InvalidDelta>() {
+                Ok(err) => Err((StatusCode::BAD_REQUEST, err.to_string())),
+                Err(err) if err.is::<delta::TooLarge>() => Err((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "Payload Too Large".to_string(),
+                )),
+                Err(err) => {
+                    drop(tmp);
+                    let _ = fs::remove_file(&tmp_path).await;
+                    return Err(err);
+                }
+            },
+        };
+        drop(tmp);
+        match ret {
+            Ok(new_size) => {
+                fs::set_permissions(&tmp_path, fs::metadata(path).await?.permissions()).await?;
+                fs::rename(&tmp_path, path).await?;
+                self.quotas.add(path, new_size as i64 - size as i64);
+                status_no_content(res);
+            }
+            Err((status, message)) => {
+                fs::remove_file(&tmp_path).await?;
+                if status == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                    status_unsupported_type(res, path);
+                } else {
+                    *res.status_mut() = status;
+                    *res.body_mut() = Body::from(message);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts the zip or tar uploaded with `PUT /dir/archive.zip?unzip` into `/dir` as it
+    /// comes in. Each entry is written as the request it maps to, so the same permissions,
+    /// limits, hooks and events apply as to uploading the files one by one.
+    #[instrument(skip_all)]
+    async fn handle_extract(
+        self: Arc<Self>,
+        rel_path: &str,
+        ip: Option<IpAddr>,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let granted = match self.authorize_request(req.method(), &req) {
+            (user, Some(paths)) => Granted(user, Arc::new(paths.into_owned())),
+            _ => {
+                status_forbid(res);
+                return Ok(());
+            }
+        };
+        let (dir, name) = rel_path.rsplit_once('/').unwrap_or(("", rel_path));
+        let name = name.to_ascii_lowercase();
+        let body = req.into_body().map_err(io::Error::other);
+        let reader = StreamReader::new(body);
+        let ret = if name.ends_with(".zip") {
+            self.extract_zip(dir, reader, &granted, ip).await
+        } else if name.ends_with(".tar") {
+            self.extract_tar(dir, reader, &granted, ip).await
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            self.extract_tar(dir, GzipDecoder::new(reader), &granted, ip)
+                .await
+        } else {
+            *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            *res.body_mut() =
+                Body::from("Only .zip, .tar, .tar.gz and .tgz archives can be extracted");
+            return Ok(());
+        };
+        match ret {
+            Ok(None) => *res.status_mut() = StatusCode::CREATED,
+            Ok(Some((status, message))) => {
+                *res.status_mut() = status;
+                *res.body_mut() = Body::from(message);
+            }
+            Err(err) => match BodyError::find(&err) {
+                Some(body_err) => body_err.respond(res),
+                None => {
+                    *res.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+                    *res.body_mut() = Body::from(format!("Invalid archive, {err}"));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Extracts the entries of a zip as they come, returning the status and message of the
+    /// first that can't be extracted
+    async fn extract_zip<R: AsyncBufRead + Unpin>(
+        self: &Arc<Self>,
+        dir: &str,
+        reader: R,
+        granted: &Granted,
+        ip: Option<IpAddr>,
+    ) -> Result<Option<(StatusCode, String)>> {
+        let mut zip = ZipStreamReader::with_tokio(reader);
+        while let Some(mut entry) = zip.next_with_entry().await? {
+            let (name, is_dir, size) = {
+                let entry = entry.reader().entry();
+                let name = entry.filename().as_str()?.to_string();
+                // Sizes are left out of the local headers of entries with data descriptors
+                let size = Some(entry.uncompressed_size()).filter(|v| *v > 0);
+                (name, entry.dir()?, size)
+            };
+            let mut reader = entry.reader_mut().compat();
+            let ret = self
+                .extract_entry(dir, &name, is_dir, size, &mut reader, granted, ip)
+                .await?;
+            if ret.is_some() {
+                return Ok(ret);
+            }
+            zip = entry.skip().await?;
+        }
+        Ok(None)
+    }
+
+    /// Extracts the files and folders of a tar as they come, leaving out links and special
+    /// files, returning the status and message of the first that can't be extracted
+    async fn extract_tar<R: AsyncRead + Unpin>(
+        self: &Arc<Self>,
+        dir: &str,
+        reader: R,
+        granted: &Granted,
+        ip: Option<IpAddr>,
+    ) -> Result<Option<(StatusCode, String)>> {
+        let mut archive = TarArchive::new(reader);
+        let mut entries = archive.entries()?;
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+            if !entry_type.is_file() && !entry_type.is_dir() {
+                continue;
+            }
+            let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+            let size = entry.header().size().ok();
+            let ret = self
+                .extract_entry(
+                    dir,
+                    &name,
+                    entry_type.is_dir(),
+                    size,
+                    &mut entry,
+                    granted,
+                    ip,
+                )
+                .await?;
+            if ret.is_some() {
+                return Ok(ret);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Writes an entry of an archive under `dir`, with a `PUT` or a `MKCOL`. `None` if it
+    /// was written or is a folder already there, else the status and message why not.
+    async fn extract_entry<R: AsyncRead + Unpin>(
+        self: &Arc<Self>,
+        dir: &str,
+        name: &str,
+        is_dir: bool,
+        size: Option<u64>,
+        reader: &mut R,
+        granted: &Granted,
+        ip: Option<IpAddr>,
+    ) -> Result<Option<(StatusCode, String)>> {
+        let rel_path = match archive_entry_path(name) {
+            Some(v) if v.is_empty() => return Ok(None),
+            Some(v) if dir.is_empty() => v,
+            Some(v) => format!("{dir}/{v}"),
+            None => {
+                let message = format!("Refused to extract `{name}`, it is outside the folder");
+                return Ok(Some((StatusCode::BAD_REQUEST, message)));
+            }
+        };
+        let mut req = Request::default();
+        *req.uri_mut() = self.uri(&rel_path).parse()?;
+        req.extensions_mut().insert(granted.clone());
+        let user = granted.0.as_deref();
+        let status = if is_dir {
+            let exists = match self.join_path(&rel_path) {
+                Some(path) => fs::metadata(path).await.is_ok_and(|v| v.is_dir()),
+                None => false,
+            };
+            if exists {
+                return Ok(None);
+            }
+            *req.method_mut() = Method::from_bytes(b"MKCOL")?;
+            self.clone().change(req, user, ip).await?
+        } else {
+            *req.method_mut() = Method::PUT;
+            if let Some(size) = size {
+                req.headers_mut().typed_insert(ContentLength(size));
+            }
+            let (mut sender, body) = Body::channel();
+            *req.body_mut() = body;
+            let pump = async move {
+                let mut buf = vec![0; BUF_SIZE];
+                loop {
+                    let len = match reader.read(&mut buf).await {
+                        Ok(0) => return Ok(()),
+                        Ok(len) => len,
+                        Err(err) => {
+                            // Fails the upload rather than leave a truncated file
+                            sender.abort();
+                            return Err(err);
+                        }
+                    };
+                    // Refused, the rest of the entry is skipped
+                    if sender.send_data(buf[..len].to_vec().into()).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            };
+            let (pumped, status) = tokio::join!(pump, self.clone().change(req, user, ip));
+            pumped?;
+            status?
+        };
+        if status.is_success() {
+            return Ok(None);
+        }
+        let reason = status.canonical_reason().unwrap_or_default();
+        Ok(Some((
+            status,
+            format!("Failed to extract `{name}`, {reason}"),
+        )))
+    }
+
+    /// With `--confirm-rm-dir`, refuses to delete a non-empty folder unless `confirm` is the
+    /// number of entries under it, as `?stat` reports it
+    async fn unconfirmed_rm_dir(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        query_params: &HashMap<String, String>,
+    ) -> Result<Option<(StatusCode, String)>> {
+        if !is_dir || !self.args.confirm_rm_dir {
+            return Ok(None);
+        }
+        let entries = count_entries(path).await?;
+        let name = get_file_name(path);
+        match query_params.get("confirm") {
+            _ if entries == 0 => Ok(None),
+            None => Ok(Some((
+                StatusCode::PRECONDITION_REQUIRED,
+                format!("`{name}` isn't empty, delete it with ?confirm=<entries> from its ?stat"),
+            ))),
+            Some(v) if *v != entries.to_string() => Ok(Some((
+                StatusCode::PRECONDITION_FAILED,
+                format!("`{name}` has changed, it holds {entries} entries now"),
+            ))),
+            Some(_) => Ok(None),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_delete(
+        &self,
+        path: &Path,
+        rel_path: &str,
+        is_dir: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        match (&self.trash, is_dir) {
+            (Some(trash), _) => trash.put(path, rel_path).await?,
+            (None, true) => fs::remove_dir_all(path).await?,
+            (None, false) => fs::remove_file(path).await?,
+        }
+        self.journal.record(path);
+        self.quotas.invalidate(path);
+        self.publish(FsEventKind::Delete, path, None);
+
+        status_no_content(res);
+        Ok(())
+    }
+
+    /// Lists the trash with `GET /?trash`, restores an entry with `POST /?restore=<id>`
+    #[instrument(skip_all)]
+    async fn handle_trash(
+        &self,
+        trash: &Trash,
+        method: &Method,
+        query_params: &HashMap<String, String>,
+        res: &mut Response,
+    ) -> Result<()> {
+        if *method == Method::GET && query_params.contains_key("trash") {
+            let output = serde_json::to_string_pretty(&trash.list().await?)?;
+            res.headers_mut()
+                .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+            res.headers_mut()
+                .typed_insert(ContentLength(output.len() as u64));
+            *res.body_mut() = output.into();
+            return Ok(());
+        }
+        let id = match query_params.get("restore") {
+            Some(id) if *method == Method::POST => id,
+            _ => {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                return Ok(());
+            }
+        };
+        let (file, rel_path) = match trash.take(id).await {
+            Some(v) => v,
+            None => {
+                status_not_found(res);
+                return Ok(());
+            }
+        };
+        let dest = match self.join_path(&rel_path) {
+            Some(v) if !self.is_mount(&v) && !self.is_excluded(&rel_path, true) => v,
+            _ => {
+                status_forbid(res);
+                return Ok(());
+            }
+        };
+        if fs::symlink_metadata(&dest).await.is_ok() {
+            *res.status_mut() = StatusCode::CONFLICT;
+            return Ok(());
+        }
+        ensure_path_parent(&dest).await?;
+        move_entry(&file, &dest).await?;
+        trash.forget(id).await?;
+        self.journal.record(&dest);
+        *res.status_mut() = StatusCode::CREATED;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_ls_dir(
+        &self,
+        path: &Path,
+        exist: bool,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        user: Option<String>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut paths = vec![];
+        let mut readme = None;
+        let depth = list_depth(query_params);
+        if exist && depth > 1 {
+            paths = match self.list_tree(path, access_paths.clone(), depth).await {
+                Ok(paths) => paths,
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            };
+        } else if exist && self.can_list_page(path, query_params, &access_paths) {
+            let (offset, limit) = search_page(query_params);
+            let (_, desc, _) = list_sort(query_params, &self.args.default_sort);
+            let filter = query_params.get("filter").map(|v| v.as_str());
+            let (page, total) = match self.list_dir_page(path, filter, offset, limit, desc).await {
+                Ok(v) => v,
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            };
+            paths = page;
+            res.headers_mut().insert("x-total-count", total.into());
+        } else if exist {
+            paths = match self.list_dir(path, path, access_paths.clone()).await {
+                Ok(paths) => paths,
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            };
+            if !access_paths.perm().indexonly() {
+                readme = self.render_readme(path).await;
+            }
+        };
+        if let Some(filter) = query_params.get("filter") {
+            paths.retain(|v| glob(filter, v.base_name()));
+        }
+        if exist && is_list_paged(query_params) && !res.headers().contains_key("x-total-count") {
+            let (offset, limit) = search_page(query_params);
+            sort_paths(&mut paths, query_params, &self.args.default_sort);
+            res.headers_mut()
+                .insert("x-total-count", paths.len().into());
+            paths = paths.into_iter().skip(offset).take(limit).collect();
+        }
+        self.send_index(
+            path,
+            paths,
+            readme,
+            exist,
             query_params,
             head_only,
             user,
             access_paths,
             res,
         )
+        .await
+    }
+
+    /// Sends the recursive size and file count of the folder as json, leaving out hidden
+    /// entries and the folders the user can't read
+    #[instrument(skip_all)]
+    async fn handle_du(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let usage = self.dir_usage.usage(&access_paths.leaf_paths(path)).await?;
+        let output = serde_json::json!({
+            "path": format!("/{relative_path}"),
+            "size": usage.size,
+            "files": usage.files,
+            "dirs": usage.dirs,
+        });
+        let output = serde_json::to_string_pretty(&output)?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Streams the entries of the folder as they're read, unsorted, with `stream` along
+    /// with `json` for JSON lines or `simple` for names
+    #[instrument(skip_all)]
+    async fn handle_stream_dir(
+        self: Arc<Self>,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let json = query_params.contains_key("json");
+        let content_type = if json {
+            "application/x-ndjson; charset=utf-8"
+        } else {
+            "text/plain; charset=utf-8"
+        };
+        let filter = query_params.get("filter").cloned();
+        let dir = path.to_path_buf();
+        let mut rd = match access_paths.perm().indexonly() {
+            true => None,
+            false => match fs::read_dir(&dir).await {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            },
+        };
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let format = |paths: &mut Vec<PathItem>| {
+                let output: String = paths
+                    .drain(..)
+                    .filter(|v| filter.as_ref().is_none_or(|f| glob(f, v.base_name())))
+                    .map(|v| match (json, v.is_dir()) {
+                        (true, _) => format!("{}\n", serde_json::to_string(&v).unwrap_or_default()),
+                        (false, true) => format!("{}/\n", v.name),
+                        (false, false) => format!("{}\n", v.name),
+                    })
+                    .collect();
+                output
+            };
+            let mut paths = vec![];
+            match rd.as_mut() {
+                Some(rd) => {
+                    let is_root = dir == self.args.serve_path;
+                    while let Ok(Some(entry)) = rd.next_entry().await {
+                        let entry_path = entry.path();
+                        if is_root && self.is_shadowed(&entry_path) {
+                            continue;
+                        }
+                        self.add_pathitem(&mut paths, &dir, &entry_path).await;
+                        if paths.len() >= STREAM_BATCH_SIZE {
+                            // Stops once the client is gone
+                            if sender.send_data(format(&mut paths).into()).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    if is_root {
+                        for (_, root) in self.args.mount_paths.iter() {
+                            self.add_pathitem(&mut paths, &dir, root).await;
+                        }
+                    }
+                }
+                None => {
+                    if let Ok(v) = self.list_dir(&dir, &dir, access_paths).await {
+                        paths = v;
+                    }
+                }
+            }
+            if !paths.is_empty() {
+                let _ = sender.send_data(format(&mut paths).into()).await;
+            }
+        });
+        *res.body_mut() = body;
+        Ok(())
+    }
+
+    /// The readme of the folder as HTML, if it has one that isn't hidden or too large
+    async fn render_readme(&self, path: &Path) -> Option<String> {
+        let name = &self.args.readme_name;
+        if name.is_empty() || is_hidden(&self.hidden, self.args.posix_hidden, name, false) {
+            return None;
+        }
+        let readme_path = path.join(name);
+        let meta = fs::metadata(&readme_path).await.ok()?;
+        if !meta.is_file() || meta.len() > README_MAX_SIZE {
+            return None;
+        }
+        let text = fs::read_to_string(&readme_path).await.ok()?;
+        Some(render_markdown(&text))
     }
 
+    #[instrument(skip_all)]
     async fn handle_search_dir(
         &self,
         path: &Path,
@@ -498,60 +2647,28 @@ impl Server {
             .get("q")
             .ok_or_else(|| anyhow!("invalid q"))?
             .to_lowercase();
+        if query_params.get("content").map(|v| v != "false") == Some(true) {
+            return self.handle_search_content(
+                path,
+                search,
+                query_params,
+                head_only,
+                access_paths,
+                res,
+            );
+        }
         if !search.is_empty() {
-            let path_buf = path.to_path_buf();
-            let hidden = Arc::new(self.args.hidden.to_vec());
-            let hidden = hidden.clone();
-            let posix_hidden = self.args.posix_hidden;
-            let running = self.running.clone();
-            let access_paths = access_paths.clone();
-            let search_paths = tokio::task::spawn_blocking(move || {
-                let mut paths: Vec<PathBuf> = vec![];
-                for dir in access_paths.leaf_paths(&path_buf) {
-                    let mut it = WalkDir::new(&dir).into_iter();
-                    it.next();
-                    while let Some(Ok(entry)) = it.next() {
-                        if !running.load(atomic::Ordering::SeqCst) {
-                            break;
-                        }
-                        let entry_path = entry.path();
-                        let base_name = get_file_name(entry_path);
-                        let file_type = entry.file_type();
-                        let mut is_dir_type: bool = file_type.is_dir();
-                        if file_type.is_symlink() {
-                            match std::fs::symlink_metadata(entry_path) {
-                                Ok(meta) => {
-                                    is_dir_type = meta.is_dir();
-                                }
-                                Err(_) => {
-                                    continue;
-                                }
-                            }
-                        }
-                        if is_hidden(&hidden, posix_hidden, base_name, is_dir_type) {
-                            if file_type.is_dir() {
-                                it.skip_current_dir();
-                            }
-                            continue;
-                        }
-                        if !base_name.to_lowercase().contains(&search) {
-                            continue;
-                        }
-                        paths.push(entry_path.to_path_buf());
-                    }
-                }
-                paths
-            })
-            .await?;
-            for search_path in search_paths.into_iter() {
-                if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
-                    paths.push(item);
-                }
+            let truncated;
+            (paths, truncated) = self.search_names(path, &search, &access_paths).await?;
+            if truncated {
+                res.headers_mut()
+                    .insert("x-search-truncated", HeaderValue::from_static("true"));
             }
         }
         self.send_index(
             path,
             paths,
+            None,
             true,
             query_params,
             head_only,
@@ -559,15 +2676,416 @@ impl Server {
             access_paths,
             res,
         )
+        .await
+    }
+
+    /// The entries under the folder whose names contain `search`, lowercased, along with
+    /// whether the search stopped early
+    async fn search_names(
+        &self,
+        path: &Path,
+        search: &str,
+        access_paths: &AccessPaths,
+    ) -> Result<(Vec<PathItem>, bool)> {
+        let dirs = access_paths.leaf_paths(path);
+        let hidden = self.hidden.to_vec();
+        let posix_hidden = self.args.posix_hidden;
+        let running = self.running.clone();
+        // The walk stops if the request is dropped before it's done
+        let (job, _guard) = Job::new(running.clone());
+        let max_results = self.args.search_max_results;
+        let deadline = Instant::now() + self.args.search_timeout;
+        let indexed = self
+            .search_index
+            .as_ref()
+            .and_then(|index| index.search(&dirs, search, max_results));
+        let (search_paths, truncated) = match indexed {
+            Some(v) => v,
+            None => {
+                let search = search.to_string();
+                tokio::task::spawn_blocking(move || {
+                    let mut paths: Vec<PathBuf> = vec![];
+                    let mut truncated = false;
+                    walk_visible(&dirs, &hidden, posix_hidden, &running, |entry_path, _| {
+                        if !job.is_running() {
+                            return false;
+                        }
+                        if paths.len() >= max_results || Instant::now() >= deadline {
+                            truncated = true;
+                            return false;
+                        }
+                        if get_file_name(entry_path).to_lowercase().contains(&search) {
+                            paths.push(entry_path.to_path_buf());
+                        }
+                        true
+                    });
+                    (paths, truncated)
+                })
+                .await?
+            }
+        };
+        let mut paths = vec![];
+        for search_path in search_paths.into_iter() {
+            if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
+                paths.push(item);
+            }
+        }
+        Ok((paths, truncated))
+    }
+
+    /// Answers the reads of the JSON API: `list`, `stat`, `search` and `hash`
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip_all)]
+    async fn handle_api_read(
+        &self,
+        op: ApiOp,
+        path: &Path,
+        relative_path: &str,
+        is_dir: bool,
+        is_miss: bool,
+        query_params: &HashMap<String, String>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        if access_paths.perm().uploadonly() {
+            status_forbid(res);
+            return Ok(());
+        }
+        if is_miss {
+            status_not_found(res);
+            return Ok(());
+        }
+        let output = match op {
+            ApiOp::Stat => {
+                let base_path = match relative_path.is_empty() {
+                    true => path,
+                    false => path.parent().unwrap_or(path),
+                };
+                let item = match self.to_pathitem(path, base_path).await? {
+                    Some(v) => v,
+                    None => {
+                        status_not_found(res);
+                        return Ok(());
+                    }
+                };
+                let dir = relative_path.rsplit_once('/').map(|v| v.0).unwrap_or("");
+                serde_json::to_value(ApiEntry::new(item, dir))?
+            }
+            ApiOp::List => {
+                if !is_dir {
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    *res.body_mut() = Body::from("Not a folder");
+                    return Ok(());
+                }
+                let mut paths = match self.list_dir(path, path, access_paths).await {
+                    Ok(v) => v,
+                    Err(_) => {
+                        status_forbid(res);
+                        return Ok(());
+                    }
+                };
+                paths.sort_by(|v1, v2| v1.sort_by_name(v2));
+                let entries: Vec<_> = paths
+                    .into_iter()
+                    .map(|v| ApiEntry::new(v, relative_path))
+                    .collect();
+                serde_json::json!({ "path": format!("/{relative_path}"), "entries": entries })
+            }
+            ApiOp::Search => {
+                let search = query_params
+                    .get("q")
+                    .map(|v| v.to_lowercase())
+                    .unwrap_or_default();
+                if !self.args.allow_search || !is_dir {
+                    status_forbid(res);
+                    return Ok(());
+                }
+                if search.is_empty() {
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    *res.body_mut() = Body::from("Missing `q`");
+                    return Ok(());
+                }
+                let (mut paths, truncated) =
+                    self.search_names(path, &search, &access_paths).await?;
+                paths.sort_by(|v1, v2| v1.sort_by_name(v2));
+                let (offset, limit) = search_page(query_params);
+                let entries: Vec<_> = paths
+                    .into_iter()
+                    .skip(offset)
+                    .take(limit)
+                    .map(|v| ApiEntry::new(v, relative_path))
+                    .collect();
+                serde_json::json!({
+                    "path": format!("/{relative_path}"),
+                    "entries": entries,
+                    "truncated": truncated,
+                })
+            }
+            ApiOp::Hash => {
+                let algo = match query_params.get("algo") {
+                    Some(v) => ChecksumAlgo::from_name(v),
+                    None => Some(ChecksumAlgo::Sha256),
+                };
+                let algo = match algo {
+                    Some(v) if !is_dir => v,
+                    _ => {
+                        *res.status_mut() = StatusCode::BAD_REQUEST;
+                        return Ok(());
+                    }
+                };
+                let hash = file_checksum(path, algo, self.args.hash_xattr).await?;
+                serde_json::json!({
+                    "path": format!("/{relative_path}"),
+                    "algo": algo.name(),
+                    "hash": hash,
+                })
+            }
+            ApiOp::Diff | ApiOp::Mkdir | ApiOp::Delete | ApiOp::Move | ApiOp::Copy => {
+                unreachable!()
+            }
+        };
+        let output = serde_json::to_string_pretty(&output)?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Tells which files of the manifest, a list of paths relative to the folder along with
+    /// their sizes and hashes, are missing or different on the server, for a client to
+    /// upload only those
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(skip_all)]
+    async fn handle_api_diff(
+        &self,
+        relative_path: &str,
+        is_dir: bool,
+        is_miss: bool,
+        query_params: &HashMap<String, String>,
+        access_paths: AccessPaths,
+        body: &[u8],
+        res: &mut Response,
+    ) -> Result<()> {
+        if !self.args.allow_upload || access_paths.perm().uploadonly() {
+            status_forbid(res);
+            return Ok(());
+        }
+        // A folder yet to be made has all the files missing
+        if !is_dir && !is_miss {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            *res.body_mut() = Body::from("Not a folder");
+            return Ok(());
+        }
+        let algo = match query_params.get("algo") {
+            Some(v) => ChecksumAlgo::from_name(v),
+            None => Some(ChecksumAlgo::Sha256),
+        };
+        let algo = match algo {
+            Some(v) => v,
+            None => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(());
+            }
+        };
+        let manifest: Vec<ManifestEntry> = match serde_json::from_slice(body) {
+            Ok(v) => v,
+            Err(err) => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                *res.body_mut() = Body::from(format!("Invalid manifest, {err}"));
+                return Ok(());
+            }
+        };
+        let (mut missing, mut changed, mut skipped) = (vec![], vec![], vec![]);
+        for entry in manifest {
+            let name = entry.path.trim_start_matches('/');
+            let file_path = name
+                .split('/')
+                .all(|v| !matches!(v, "" | "." | ".."))
+                .then(|| match relative_path.is_empty() {
+                    true => name.to_string(),
+                    false => format!("{relative_path}/{name}"),
+                })
+                .filter(|v| !self.is_excluded(v, false))
+                .and_then(|v| self.join_path(&v));
+            let file_path = match file_path {
+                Some(v) if self.is_symlink_allowed(&v).await => v,
+                _ => {
+                    skipped.push(entry.path);
+                    continue;
+                }
+            };
+            let meta = match fs::metadata(&file_path).await {
+                Ok(v) => v,
+                Err(_) => {
+                    missing.push(entry.path);
+                    continue;
+                }
+            };
+            let same = meta.is_file()
+                && meta.len() == entry.size
+                && match entry.hash.as_ref() {
+                    Some(hash) => file_checksum(&file_path, algo, self.args.hash_xattr)
+                        .await?
+                        .eq_ignore_ascii_case(hash),
+                    None => true,
+                };
+            if !same {
+                changed.push(entry.path);
+            }
+        }
+        let output = serde_json::json!({
+            "path": format!("/{relative_path}"),
+            "algo": algo.name(),
+            "missing": missing,
+            "changed": changed,
+            "skipped": skipped,
+        });
+        let output = serde_json::to_string_pretty(&output)?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Streams the lines of the text files that contain the search, as `name:line:text`,
+    /// or as JSON lines with `json`
+    fn handle_search_content(
+        &self,
+        path: &Path,
+        search: String,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let json = query_params.contains_key("json");
+        let content_type = if json {
+            "application/x-ndjson; charset=utf-8"
+        } else {
+            "text/plain; charset=utf-8"
+        };
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        if head_only || search.is_empty() {
+            return Ok(());
+        }
+        let dirs = access_paths.leaf_paths(path);
+        let base = path.to_path_buf();
+        let hidden = self.hidden.to_vec();
+        let posix_hidden = self.args.posix_hidden;
+        let running = self.running.clone();
+        let (job, guard) = Job::new(running.clone());
+        let max_results = self.args.search_max_results;
+        let deadline = Instant::now() + self.args.search_timeout;
+        let (offset, limit) = search_page(query_params);
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(64);
+        tokio::task::spawn_blocking(move || {
+            let max_results = max_results.min(offset.saturating_add(limit));
+            let mut found = 0;
+            walk_visible(
+                &dirs,
+                &hidden,
+                posix_hidden,
+                &running,
+                |entry_path, is_dir| {
+                    if !job.is_running() || found >= max_results || Instant::now() >= deadline {
+                        return false;
+                    }
+                    if is_dir {
+                        return true;
+                    }
+                    let name = match entry_path.strip_prefix(&base) {
+                        Ok(v) => normalize_path(v),
+                        Err(_) => return true,
+                    };
+                    let mut output = String::new();
+                    for (number, line) in grep_file(entry_path, &search) {
+                        found += 1;
+                        if found <= offset {
+                            continue;
+                        }
+                        if found > max_results {
+                            break;
+                        }
+                        if json {
+                            let value =
+                                serde_json::json!({"name": name, "line": number, "text": line});
+                            output.push_str(&format!("{value}\n"));
+                        } else {
+                            output.push_str(&format!("{name}:{number}:{line}\n"));
+                        }
+                    }
+                    output.is_empty() || tx.blocking_send(output).is_ok()
+                },
+            );
+        });
+        let lines = stream! {
+            while let Some(output) = rx.recv().await {
+                yield Ok::<_, std::io::Error>(output);
+            }
+        };
+        // Stops the walk once the client is gone, even while it finds nothing to send
+        *res.body_mut() = Body::wrap_stream(guard.attach(lines));
+        Ok(())
     }
 
+    #[instrument(skip_all)]
     async fn handle_zip_dir(
         &self,
         path: &Path,
         head_only: bool,
+        download_id: Option<&String>,
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
+        let hidden = self.hidden.clone();
+        let posix_hidden = self.args.posix_hidden;
+        let (job, guard) = Job::new(self.running.clone());
+        // Without Zip64 the limits, and the progress, are known only once the folder is walked
+        let zip_paths = if self.args.no_zip64 || self.args.archive_progress {
+            let zip_paths = archive_paths(
+                path,
+                access_paths.clone(),
+                &hidden,
+                job.clone(),
+                posix_hidden,
+            )
+            .await?;
+            if self.args.no_zip64 {
+                if let Some(reason) = zip64_needed(path, &zip_paths).await {
+                    *res.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+                    *res.body_mut() =
+                        Body::from(format!("The zip archive would need Zip64, {reason}"));
+                    return Ok(());
+                }
+            }
+            Some(zip_paths)
+        } else {
+            None
+        };
+        let progress = match zip_paths.as_ref() {
+            Some(zip_paths) if self.args.archive_progress => {
+                let id = match download_id {
+                    Some(id) if is_valid_download_id(id) => id.clone(),
+                    Some(_) => {
+                        *res.status_mut() = StatusCode::BAD_REQUEST;
+                        *res.body_mut() = Body::from("Invalid download id");
+                        return Ok(());
+                    }
+                    None => Uuid::new_v4().to_string(),
+                };
+                let total = archive_size(zip_paths).await;
+                res.headers_mut().insert("x-archive-size", total.into());
+                res.headers_mut()
+                    .insert("x-download-id", HeaderValue::from_str(&id)?);
+                Some((id, total))
+            }
+            _ => None,
+        };
         let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
         let filename = try_get_file_name(path)?;
         set_content_disposition(res, false, &format!("{}.zip", filename))?;
@@ -576,29 +3094,140 @@ impl Server {
         if head_only {
             return Ok(());
         }
+        let tracker = progress.map(|(id, total)| self.downloads.start(&id, total));
+        let path = path.to_owned();
+        let no_zip64 = self.args.no_zip64;
+        // Deflating takes a core, on a thread of its own it doesn't hold up other requests.
+        // Entries can't be deflated in parallel, async_zip only writes the data it deflates.
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(async move {
+                let ret = match zip_paths {
+                    Some(zip_paths) => Ok(zip_paths),
+                    None => {
+                        archive_paths(&path, access_paths, &hidden, job.clone(), posix_hidden).await
+                    }
+                };
+                let ret = match ret {
+                    Ok(zip_paths) => {
+                        zip_dir(&mut writer, &path, zip_paths, no_zip64, tracker, &job).await
+                    }
+                    Err(e) => Err(e),
+                };
+                // Writing fails once the client is gone, that's no error
+                if let Err(e) = ret {
+                    if job.is_running() {
+                        error!("Failed to zip {}, {}", path.display(), e);
+                    }
+                }
+            })
+        });
+        let reader = Streamer::new(reader, BUF_SIZE);
+        *res.body_mut() = Body::wrap_stream(guard.attach(reader.into_stream()));
+        Ok(())
+    }
+
+    /// Streams the progress of an archive download as Server-Sent Events, from before it
+    /// starts until it is done
+    fn handle_download_progress(&self, id: &str, res: &mut Response) -> Result<()> {
+        if !is_valid_download_id(id) {
+            status_not_found(res);
+            return Ok(());
+        }
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        let downloads = self.downloads.clone();
+        let id = id.to_string();
+        let mut rx = downloads.watch(&id);
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let mut keep_alive = tokio::time::interval(WATCH_KEEP_ALIVE);
+            loop {
+                let output = tokio::select! {
+                    ret = rx.changed() => {
+                        let progress = *rx.borrow_and_update();
+                        let kind = if progress.done || ret.is_err() { "done" } else { "progress" };
+                        let data = serde_json::to_string(&progress).unwrap_or_default();
+                        format!("event: {kind}\ndata: {data}\n\n")
+                    }
+                    _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+                };
+                let done = output.starts_with("event: done");
+                if sender.send_data(output.into()).await.is_err() || done {
+                    break;
+                }
+                // Coalesces the updates of the chunks read meanwhile
+                tokio::time::sleep(PROGRESS_INTERVAL).await;
+            }
+            drop(rx);
+            downloads.release(&id);
+        });
+        *res.body_mut() = body;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_tar_dir(
+        &self,
+        path: &Path,
+        gzip: bool,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let (writer, reader) = tokio::io::duplex(BUF_SIZE);
+        let filename = try_get_file_name(path)?;
+        let (ext, content_type) = if gzip {
+            ("tar.gz", "application/gzip")
+        } else {
+            ("tar", "application/x-tar")
+        };
+        set_content_disposition(res, false, &format!("{}.{}", filename, ext))?;
+        res.headers_mut()
+            .insert("content-type", HeaderValue::from_static(content_type));
+        if head_only {
+            return Ok(());
+        }
         let path = path.to_owned();
-        let hidden = self.args.hidden.clone();
+        let hidden = self.hidden.clone();
         let posix_hidden = self.args.posix_hidden;
-        let running = self.running.clone();
+        let (job, guard) = Job::new(self.running.clone());
         tokio::spawn(async move {
-            if let Err(e) = zip_dir(
-                &mut writer,
-                &path,
-                access_paths,
-                &hidden,
-                running,
-                posix_hidden,
-            )
-            .await
-            {
-                error!("Failed to zip {}, {}", path.display(), e);
+            let ret = if gzip {
+                tar_dir(
+                    GzipEncoder::new(writer),
+                    &path,
+                    access_paths,
+                    &hidden,
+                    job.clone(),
+                    posix_hidden,
+                )
+                .await
+            } else {
+                tar_dir(
+                    writer,
+                    &path,
+                    access_paths,
+                    &hidden,
+                    job.clone(),
+                    posix_hidden,
+                )
+                .await
+            };
+            if let Err(e) = ret {
+                if job.is_running() {
+                    error!("Failed to tar {}, {}", path.display(), e);
+                }
             }
         });
         let reader = Streamer::new(reader, BUF_SIZE);
-        *res.body_mut() = Body::wrap_stream(reader.into_stream());
+        *res.body_mut() = Body::wrap_stream(guard.attach(reader.into_stream()));
         Ok(())
     }
 
+    #[instrument(skip_all)]
     async fn handle_render_index(
         &self,
         path: &Path,
@@ -609,13 +3238,7 @@ impl Server {
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let index_path = path.join(INDEX_NAME);
-        if fs::metadata(&index_path)
-            .await
-            .ok()
-            .map(|v| v.is_file())
-            .unwrap_or_default()
-        {
+        if let Some(index_path) = self.index_file(path).await {
             self.handle_send_file(&index_path, headers, head_only, res)
                 .await?;
         } else if self.args.render_try_index {
@@ -627,21 +3250,86 @@ impl Server {
         Ok(())
     }
 
+    /// The first of the `--index-name` files that exists in the folder
+    async fn index_file(&self, path: &Path) -> Option<PathBuf> {
+        for name in &self.args.index_names {
+            let index_path = path.join(name);
+            if file_exists(&index_path).await {
+                return Some(index_path);
+            }
+        }
+        None
+    }
+
+    /// Serves the fallback of the SPA folder the missing path is in, for the app to route
+    /// it. Pages get it whatever the path, such as `/user/1.5`, other requests only if the
+    /// path has no extension, so a missing script or image is still a 404.
     async fn handle_render_spa(
         &self,
-        path: &Path,
+        relative_path: &str,
         headers: &HeaderMap<HeaderValue>,
         head_only: bool,
         res: &mut Response,
     ) -> Result<()> {
-        if path.extension().is_none() {
-            let path = self.args.serve_path.join(INDEX_NAME);
-            self.handle_send_file(&path, headers, head_only, res)
-                .await?;
-        } else {
-            status_not_found(res)
+        let is_page = headers
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/html"));
+        let has_ext = Path::new(relative_path.trim_end_matches('/'))
+            .extension()
+            .is_some();
+        let root = self.spa_root(relative_path);
+        match root.and_then(|root| self.join_path(root)) {
+            Some(root) if is_page || !has_ext => {
+                let path = root.join(&self.args.spa_fallback);
+                self.handle_send_file(&path, headers, head_only, res)
+                    .await?;
+            }
+            _ => status_not_found(res),
+        }
+        Ok(())
+    }
+
+    /// The first of the `--try-files` candidates for the path that exists, otherwise the
+    /// fallback path or the status to answer
+    async fn try_files(
+        &self,
+        try_files: &TryFiles,
+        relative_path: &str,
+    ) -> std::result::Result<String, StatusCode> {
+        for candidate in try_files.candidates(relative_path) {
+            let is_dir = candidate.ends_with('/') || candidate.is_empty();
+            let candidate = candidate.trim_end_matches('/');
+            let meta = match self.join_path(candidate) {
+                Some(path) => fs::metadata(path).await.ok(),
+                None => None,
+            };
+            if meta.is_some_and(|v| v.is_dir() == is_dir) {
+                return Ok(candidate.to_string());
+            }
         }
-        Ok(())
+        match try_files.fallback(relative_path) {
+            TryFilesFallback::Path(v) => Ok(v.trim_end_matches('/').to_string()),
+            TryFilesFallback::Status(status) => Err(status),
+        }
+    }
+
+    /// The innermost of the `--spa-root` folders the path is in, the root without any
+    fn spa_root<'a>(&'a self, relative_path: &str) -> Option<&'a str> {
+        if self.args.spa_roots.is_empty() {
+            return Some("");
+        }
+        self.args
+            .spa_roots
+            .iter()
+            .filter(|root| {
+                root.is_empty()
+                    || relative_path
+                        .strip_prefix(root.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|root| root.len())
+            .map(|v| v.as_str())
     }
 
     async fn handle_assets(
@@ -695,6 +3383,7 @@ impl Server {
         }
     }
 
+    #[instrument(skip_all)]
     async fn handle_send_file(
         &self,
         path: &Path,
@@ -702,67 +3391,80 @@ impl Server {
         head_only: bool,
         res: &mut Response,
     ) -> Result<()> {
-        let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
-        let (mut file, meta) = (file?, meta?);
+        let preloaded = self
+            .preload
+            .as_ref()
+            .and_then(|v| v.get(path))
+            .and_then(|v| Some((v.meta, v.data?)));
+        let (source, meta) = match preloaded {
+            Some((meta, data)) => (FileSource::Memory(data), meta),
+            None => {
+                let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
+                (FileSource::Disk(file?), meta?)
+            }
+        };
+        let cache_control = self
+            .args
+            .cache_control
+            .get(get_file_name(path))
+            .or(self.args.cache_control_default.as_ref());
+        if let Some(value) = cache_control {
+            res.headers_mut().insert(CACHE_CONTROL, value.clone());
+        }
         let mut use_range = true;
         if let Some((etag, last_modified)) = extract_cache_headers(&meta) {
-            let cached = {
-                if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
-                    !if_none_match.precondition_passes(&etag)
-                } else if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
-                    !if_modified_since.is_modified(last_modified.into())
-                } else {
-                    false
-                }
+            use_range = match set_cache_headers(headers, etag, last_modified, res) {
+                Some(v) => v,
+                None => return Ok(()),
             };
-            if cached {
-                *res.status_mut() = StatusCode::NOT_MODIFIED;
-                return Ok(());
-            }
-
-            res.headers_mut().typed_insert(last_modified);
-            res.headers_mut().typed_insert(etag.clone());
-
-            if headers.typed_get::<Range>().is_some() {
-                use_range = headers
-                    .typed_get::<IfRange>()
-                    .map(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified)))
-                    // Always be fresh if there is no validators
-                    .unwrap_or(true);
-            } else {
-                use_range = false;
-            }
         }
 
-        let range = if use_range {
-            parse_range(headers)
+        let size = meta.len();
+        let ranges = if use_range {
+            parse_ranges(headers, size)
         } else {
             None
         };
 
-        res.headers_mut().insert(
-            CONTENT_TYPE,
-            HeaderValue::from_str(&get_content_type(path).await?)?,
-        );
+        let content_type = match &source {
+            FileSource::Memory(data) => sniff_content_type(path, &data[..data.len().min(1024)]),
+            FileSource::Disk(_) => get_content_type(path).await?,
+        };
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
 
         let filename = try_get_file_name(path)?;
         set_content_disposition(res, true, filename)?;
 
         res.headers_mut().typed_insert(AcceptRanges::bytes());
 
-        let size = meta.len();
-
-        if let Some(range) = range {
-            if range
-                .end
-                .map_or_else(|| range.start < size, |v| v >= range.start)
-                && file.seek(SeekFrom::Start(range.start)).await.is_ok()
-            {
-                let end = range.end.unwrap_or(size - 1).min(size - 1);
-                let part_size = end - range.start + 1;
-                let reader = Streamer::new(file, BUF_SIZE);
+        match ranges.as_deref() {
+            None => {
+                res.headers_mut()
+                    .insert(CONTENT_LENGTH, format!("{size}").parse()?);
+                if head_only {
+                    return Ok(());
+                }
+                *res.body_mut() = match source {
+                    FileSource::Memory(data) => Body::from(data),
+                    // Hyper writes the body to the connection itself, so the file can't be
+                    // handed to sendfile. Over loopback chunks larger than 64K were measured
+                    // to be no faster, it's the copies rather than the reads that cost.
+                    FileSource::Disk(file) => Body::wrap_stream(
+                        FileStreamer::new(file.into_std().await, self.args.buffer_size)
+                            .into_stream(),
+                    ),
+                };
+            }
+            Some([]) => {
+                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.headers_mut()
+                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+            }
+            Some([(start, end)]) => {
+                let part_size = end - start + 1;
                 *res.status_mut() = StatusCode::PARTIAL_CONTENT;
-                let content_range = format!("bytes {}-{}/{}", range.start, end, size);
+                let content_range = format!("bytes {start}-{end}/{size}");
                 res.headers_mut()
                     .insert(CONTENT_RANGE, content_range.parse()?);
                 res.headers_mut()
@@ -770,41 +3472,464 @@ impl Server {
                 if head_only {
                     return Ok(());
                 }
-                *res.body_mut() = Body::wrap_stream(reader.into_stream_sized(part_size));
-            } else {
-                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                *res.body_mut() = match source {
+                    FileSource::Memory(data) => {
+                        Body::from(data.slice(*start as usize..=*end as usize))
+                    }
+                    FileSource::Disk(mut file) => {
+                        file.seek(SeekFrom::Start(*start)).await?;
+                        let reader =
+                            FileStreamer::new(file.into_std().await, self.args.buffer_size);
+                        Body::wrap_stream(reader.into_stream_sized(part_size))
+                    }
+                };
+            }
+            Some(ranges) => {
+                let boundary = Uuid::new_v4().simple().to_string();
+                let parts: Vec<_> = ranges
+                    .iter()
+                    .map(|(start, end)| {
+                        let head = format!(
+                            "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {start}-{end}/{size}\r\n\r\n"
+                        );
+                        (head.into_bytes(), *start, *end)
+                    })
+                    .collect();
+                let tail = format!("\r\n--{boundary}--\r\n").into_bytes();
+                let length = parts
+                    .iter()
+                    .map(|(head, start, end)| head.len() as u64 + end - start + 1)
+                    .sum::<u64>()
+                    + tail.len() as u64;
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                res.headers_mut().insert(
+                    CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={boundary}").parse()?,
+                );
                 res.headers_mut()
-                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                    .insert(CONTENT_LENGTH, format!("{length}").parse()?);
+                if head_only {
+                    return Ok(());
+                }
+                if let FileSource::Memory(data) = source {
+                    let chunks = parts.into_iter().flat_map(move |(head, start, end)| {
+                        [Bytes::from(head), data.slice(start as usize..=end as usize)]
+                    });
+                    let chunks = chunks.chain(std::iter::once(Bytes::from(tail)));
+                    *res.body_mut() =
+                        Body::wrap_stream(futures::stream::iter(chunks.map(Ok::<_, io::Error>)));
+                    return Ok(());
+                }
+                // Every part reads the file with a handle of its own, opened once it's reached
+                let path = path.to_path_buf();
+                let buf_size = self.args.buffer_size;
+                let stream = futures::stream::iter(parts)
+                    .then(move |(head, start, end)| {
+                        let path = path.clone();
+                        async move {
+                            let mut file = fs::File::open(&path).await?;
+                            file.seek(SeekFrom::Start(start)).await?;
+                            let reader = FileStreamer::new(file.into_std().await, buf_size);
+                            let head = futures::stream::once(async move { Ok(head) });
+                            Ok::<_, io::Error>(
+                                head.chain(reader.into_stream_sized(end - start + 1)),
+                            )
+                        }
+                    })
+                    .try_flatten()
+                    .chain(futures::stream::once(async move { Ok(tail) }));
+                *res.body_mut() = Body::wrap_stream(stream);
             }
-        } else {
-            res.headers_mut()
-                .insert(CONTENT_LENGTH, format!("{size}").parse()?);
-            if head_only {
+        }
+        Ok(())
+    }
+
+    /// Sends the metadata of the file or folder as json, for scripts that would rather not
+    /// go through PROPFIND
+    #[instrument(skip_all)]
+    async fn handle_stat(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let meta = fs::metadata(path).await?;
+        let (_, mode) = get_file_mtime_and_mode(path).await?;
+        let symlink_target = match fs::symlink_metadata(path).await?.is_symlink() {
+            true => Some(fs::read_link(path).await?.to_string_lossy().to_string()),
+            false => None,
+        };
+        let content_type = match is_dir {
+            true => None,
+            false => Some(
+                mime_guess::from_path(path)
+                    .first_or_octet_stream()
+                    .to_string(),
+            ),
+        };
+        let mut output = serde_json::json!({
+            "name": get_file_name(path),
+            "type": if is_dir { "dir" } else { "file" },
+            "size": if is_dir { None } else { Some(meta.len()) },
+            "mtime": to_timestamp(&meta.modified()?),
+            "mode": format!("{:o}", mode & 0o7777),
+            "symlink": symlink_target.is_some(),
+            "symlink_target": symlink_target,
+            "content_type": content_type,
+        });
+        if is_dir && self.args.confirm_rm_dir {
+            output["entries"] = count_entries(path).await?.into();
+        }
+        let output = serde_json::to_string_pretty(&output)?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Sends the digest of the file with `hash=<algo>`, as `sha256sum` and the like print it
+    #[instrument(skip_all)]
+    async fn handle_hash_file(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let algo = match hash_algo(query_params) {
+            Some(v) => v,
+            None => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                *res.body_mut() = Body::from("Unknown hash algorithm");
                 return Ok(());
             }
-            let reader = Streamer::new(file, BUF_SIZE);
-            *res.body_mut() = Body::wrap_stream(reader.into_stream());
+        };
+        let hash = file_checksum(path, algo, self.args.hash_xattr).await?;
+        let output = format!("{hash}  {}\n", get_file_name(path));
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::TEXT_PLAIN_UTF_8));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Sends a torrent of the file with its URL as the web seed, for clients to fetch its
+    /// pieces from the server and share them with each other
+    #[instrument(skip_all)]
+    async fn handle_torrent(
+        &self,
+        path: &Path,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let url = format!(
+            "{}{}{}",
+            self.base_url(headers),
+            self.args.uri_prefix,
+            encode_uri(&self.relative_name(path, &self.args.serve_path)?)
+        );
+        let meta = fs::metadata(path).await?;
+        let name = try_get_file_name(path)?.to_string();
+        let torrents = self.torrents.clone();
+        let file_path = path.to_path_buf();
+        let output =
+            tokio::task::spawn_blocking(move || torrents.torrent(&file_path, &meta, &name, &url))
+                .await??;
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-bittorrent"),
+        );
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        let filename = try_get_file_name(path)?;
+        set_content_disposition(res, false, &format!("{filename}.torrent"))?;
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Sends the rsync signature of the file, the hashes of its blocks that a client diffs
+    /// its copy against to upload only what changed with `?delta`
+    async fn handle_signature(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let block_size = match query_params.get("block_size") {
+            Some(value) => match value.parse::<u32>() {
+                Ok(v) if (delta::MIN_BLOCK_SIZE..=delta::MAX_BLOCK_SIZE).contains(&v) => v,
+                _ => {
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    *res.body_mut() = Body::from(format!(
+                        "Invalid block size, between {} and {}",
+                        delta::MIN_BLOCK_SIZE,
+                        delta::MAX_BLOCK_SIZE
+                    ));
+                    return Ok(());
+                }
+            },
+            None => delta::block_size(fs::metadata(path).await?.len()),
+        };
+        let path = path.to_path_buf();
+        let output =
+            tokio::task::spawn_blocking(move || delta::signature(&path, block_size)).await??;
+        res.headers_mut().typed_insert(ContentType::from(
+            mime_guess::mime::APPLICATION_OCTET_STREAM,
+        ));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Sends a Metalink 4 document of the file, or of the files under the folder, with
+    /// their sizes, sha-256 digests and URLs for download managers to verify and resume
+    #[instrument(skip_all)]
+    async fn handle_metalink(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let algo = ChecksumAlgo::Sha256;
+        let files = if is_dir {
+            let mut paths = self.list_tree(path, access_paths, usize::MAX).await?;
+            paths.retain(|v| !v.is_dir());
+            self.add_checksums(path, &mut paths, algo).await;
+            paths
+                .into_iter()
+                .map(|item| {
+                    let hash = item.checksum.and_then(|mut v| v.remove(algo.name()));
+                    (path.join(&item.name), item.name, item.size, hash)
+                })
+                .collect()
+        } else {
+            let size = fs::metadata(path).await?.len();
+            let hash = file_checksum(path, algo, self.args.hash_xattr).await?;
+            let name = get_file_name(path).to_string();
+            vec![(path.to_path_buf(), name, Some(size), Some(hash))]
+        };
+        let base_url = self.base_url(headers);
+        let mut output = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <generator>dufs/{}</generator>
+"#,
+            env!("CARGO_PKG_VERSION")
+        );
+        for (file_path, name, size, hash) in files {
+            let url = format!(
+                "{base_url}{}{}",
+                self.args.uri_prefix,
+                encode_uri(&self.relative_name(&file_path, &self.args.serve_path)?)
+            );
+            output.push_str(&format!(
+                "  <file name=\"{}\">\n",
+                escape_str_attribute(&name)
+            ));
+            if let Some(size) = size {
+                output.push_str(&format!("    <size>{size}</size>\n"));
+            }
+            if let Some(hash) = hash {
+                output.push_str(&format!("    <hash type=\"sha-256\">{hash}</hash>\n"));
+            }
+            output.push_str(&format!(
+                "    <url>{}</url>\n  </file>\n",
+                escape_str_pcdata(&url)
+            ));
+        }
+        output.push_str("</metalink>\n");
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/metalink4+xml"),
+        );
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        let filename = try_get_file_name(path)?;
+        set_content_disposition(res, false, &format!("{filename}.meta4"))?;
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// The scheme and host the request was made to, empty if the client left out `Host`
+    fn base_url(&self, headers: &HeaderMap<HeaderValue>) -> String {
+        let scheme = match self.args.tls() {
+            true => "https",
+            false => "http",
+        };
+        headers
+            .get(hyper::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .map(|host| format!("{scheme}://{host}"))
+            .unwrap_or_default()
+    }
+
+    /// Sends the image scaled down to `thumb` pixels, 256 by default
+    #[instrument(skip_all)]
+    async fn handle_thumbnail(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        if media_type(get_file_name(path)) != Some("image") {
+            *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            return Ok(());
+        }
+        let size = query_params
+            .get("thumb")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(THUMBNAIL_SIZE)
+            .clamp(THUMBNAIL_MIN_SIZE, THUMBNAIL_MAX_SIZE);
+        let meta = fs::metadata(path).await?;
+        if let Some((_, last_modified)) = extract_cache_headers(&meta) {
+            if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
+                if !if_modified_since.is_modified(last_modified.into()) {
+                    *res.status_mut() = StatusCode::NOT_MODIFIED;
+                    return Ok(());
+                }
+            }
+            res.headers_mut().typed_insert(last_modified);
+        }
+        let image_path = path.to_path_buf();
+        let (output, content_type) =
+            match tokio::task::spawn_blocking(move || thumbnail(&image_path, size)).await? {
+                Ok(v) => v,
+                Err(_) => {
+                    *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                    return Ok(());
+                }
+            };
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Sends the media files before and after this one in its folder, for paging through
+    /// them in the gallery
+    #[instrument(skip_all)]
+    async fn handle_gallery_item(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let name = get_file_name(path);
+        let media_type = media_type(name);
+        let parent_path = relative_path.rsplit_once('/').map(|(v, _)| v).unwrap_or("");
+        let mut paths = match (
+            path.parent(),
+            self.guard(parent_path, &Method::GET, headers),
+        ) {
+            (Some(parent), (_, Some(access_paths))) => self
+                .list_dir(parent, parent, access_paths)
+                .await
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+        paths.retain(|v| !v.is_dir() && v.media_type.is_some());
+        sort_paths(&mut paths, query_params, &self.args.default_sort);
+        let index = paths.iter().position(|v| v.name == name);
+        let neighbor = |offset: isize| {
+            let index = index?.checked_add_signed(offset)?;
+            paths.get(index).map(|v| v.name.clone())
+        };
+        let output = serde_json::to_string_pretty(&serde_json::json!({
+            "name": name,
+            "media_type": media_type,
+            "prev": neighbor(-1),
+            "next": neighbor(1),
+        }))?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        if head_only {
+            return Ok(());
         }
+        *res.body_mut() = output.into();
         Ok(())
     }
 
+    #[instrument(skip_all)]
     async fn handle_deal_file(
         &self,
         path: &Path,
         kind: DataKind,
+        query_params: &HashMap<String, String>,
         head_only: bool,
         user: Option<String>,
         res: &mut Response,
     ) -> Result<()> {
         let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
         let (file, meta) = (file?, meta?);
-        let href = format!(
-            "/{}",
-            normalize_path(path.strip_prefix(&self.args.serve_path)?)
-        );
+        let href = format!("/{}", self.relative_name(path, &self.args.serve_path)?);
         let mut buffer: Vec<u8> = vec![];
         file.take(1024).read_to_end(&mut buffer).await?;
         let editable = meta.len() <= TEXT_MAX_SIZE && content_inspector::inspect(&buffer).is_text();
+        let mut language = None;
+        let mut highlighted = None;
+        if editable {
+            let first_line = String::from_utf8_lossy(&buffer);
+            language = detect_language(path, first_line.lines().next().unwrap_or_default());
+            if kind == DataKind::View && language.is_some() && meta.len() <= HIGHLIGHT_MAX_SIZE {
+                let path = path.to_path_buf();
+                highlighted = tokio::task::spawn_blocking(move || {
+                    let text = std::fs::read_to_string(&path).ok()?;
+                    highlight(&path, &text)
+                })
+                .await?;
+            }
+        }
+        let media_type = media_type(get_file_name(path));
+        let duration = match media_type {
+            Some("video" | "audio") => {
+                let path = path.to_path_buf();
+                tokio::task::spawn_blocking(move || probe_duration(&path)).await?
+            }
+            _ => None,
+        };
         let data = EditData {
             href,
             kind,
@@ -812,40 +3937,185 @@ impl Server {
             allow_upload: self.args.allow_upload,
             allow_delete: self.args.allow_delete,
             auth: self.args.auth.exist(),
+            auth_form: self.args.auth_method == AuthMethod::Form,
             user,
             editable,
+            language,
+            highlighted,
+            media_type,
+            duration,
+        };
+        let output = if query_params.contains_key("json") {
+            res.headers_mut()
+                .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+            serde_json::to_string_pretty(&data)?
+        } else {
+            res.headers_mut()
+                .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
+            self.render_html(
+                &self.html,
+                &[("INDEX_DATA", &serde_json::to_string(&data)?)],
+                res,
+            )
         };
         res.headers_mut()
-            .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
-        let output = self
-            .html
-            .replace("__ASSETS_PREFIX__", &self.assets_prefix)
-            .replace("__INDEX_DATA__", &serde_json::to_string(&data)?);
+            .typed_insert(ContentLength(output.len() as u64));
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Streams the changes of the entries of the folder as Server-Sent Events
+    #[instrument(skip_all)]
+    async fn handle_watch_dir(
+        self: Arc<Self>,
+        path: &Path,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                let _ = tx.send(event);
+            }
+        })?;
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
         res.headers_mut()
-            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+            .typed_insert(CacheControl::new().with_no_cache());
         if head_only {
             return Ok(());
         }
-        *res.body_mut() = output.into();
+        let (mut sender, body) = Body::channel();
+        let dir = path.to_path_buf();
+        tokio::spawn(async move {
+            // Stops watching when the client is gone
+            let _watcher = watcher;
+            let mut keep_alive = tokio::time::interval(WATCH_KEEP_ALIVE);
+            loop {
+                let output = tokio::select! {
+                    event = rx.recv() => match event {
+                        Some(event) => self.watch_events(&dir, event, &access_paths).await,
+                        None => break,
+                    },
+                    _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+                };
+                if output.is_empty() {
+                    continue;
+                }
+                if sender.send_data(output.into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        *res.body_mut() = body;
         Ok(())
     }
 
+    /// The Server-Sent Events of the visible entries of the folder in the event
+    async fn watch_events(&self, dir: &Path, event: Event, access_paths: &AccessPaths) -> String {
+        let changes: Vec<(&str, &PathBuf)> = match (&event.kind, event.paths.as_slice()) {
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) => {
+                vec![("delete", from), ("create", to)]
+            }
+            (EventKind::Modify(ModifyKind::Name(RenameMode::From)), paths)
+            | (EventKind::Remove(_), paths) => paths.iter().map(|v| ("delete", v)).collect(),
+            (EventKind::Modify(ModifyKind::Name(RenameMode::To)), paths)
+            | (EventKind::Create(_), paths) => paths.iter().map(|v| ("create", v)).collect(),
+            (EventKind::Modify(_), paths) => paths.iter().map(|v| ("modify", v)).collect(),
+            _ => vec![],
+        };
+        let mut output = String::new();
+        for (kind, path) in changes {
+            if path.parent() != Some(dir) {
+                continue;
+            }
+            let name = get_file_name(path);
+            if access_paths.find(name, false).is_none() {
+                continue;
+            }
+            let relative_path = match self.relative_name(path, &self.args.serve_path) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let data = if kind == "delete" {
+                // Whether it was a folder isn't known anymore, so go with the stricter check
+                if is_hidden(&self.hidden, self.args.posix_hidden, name, true)
+                    || self.is_excluded(&relative_path, true)
+                {
+                    continue;
+                }
+                serde_json::json!({ "name": name }).to_string()
+            } else {
+                let item = match self.to_pathitem(path.as_path(), dir).await {
+                    Ok(Some(item)) => item,
+                    _ => continue,
+                };
+                if is_hidden(&self.hidden, self.args.posix_hidden, name, item.is_dir())
+                    || self.is_excluded(&relative_path, item.is_dir())
+                {
+                    continue;
+                }
+                match serde_json::to_string(&item) {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                }
+            };
+            output.push_str(&format!("event: {kind}\ndata: {data}\n\n"));
+        }
+        output
+    }
+
+    #[instrument(skip_all)]
     async fn handle_propfind_dir(
-        &self,
+        self: Arc<Self>,
         path: &Path,
-        headers: &HeaderMap<HeaderValue>,
+        depth: Option<&HeaderValue>,
+        propfind: PropFind,
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let depth: u32 = match headers.get("depth") {
-            Some(v) => match v.to_str().ok().and_then(|v| v.parse().ok()) {
-                Some(v) => v,
+        let depth: Option<u32> = match depth.map(|v| v.to_str().map(|v| v.trim())) {
+            None => Some(1),
+            Some(Ok(v)) if v.eq_ignore_ascii_case("infinity") => None,
+            Some(v) => match v.ok().and_then(|v| v.parse().ok()) {
+                Some(v) => Some(v),
                 None => {
                     *res.status_mut() = StatusCode::BAD_REQUEST;
                     return Ok(());
                 }
             },
-            None => 1,
+        };
+        let depth = match depth {
+            Some(v) => v,
+            None => {
+                if !self.args.dav_infinite_depth {
+                    res_dav_error(res, "propfind-finite-depth");
+                    return Ok(());
+                }
+                let (mut sender, body) = Body::channel();
+                let path = path.to_path_buf();
+                tokio::spawn(async move {
+                    if let Err(e) = self
+                        .propfind_recursive(&mut sender, &path, &propfind, access_paths)
+                        .await
+                    {
+                        sender.abort();
+                        error!("Failed to propfind {}, {}", path.display(), e);
+                    }
+                });
+                *res.status_mut() = StatusCode::MULTI_STATUS;
+                res.headers_mut().insert(
+                    "content-type",
+                    HeaderValue::from_static("application/xml; charset=utf-8"),
+                );
+                *res.body_mut() = body;
+                return Ok(());
+            }
         };
         let mut paths = match self.to_pathitem(path, &self.args.serve_path).await? {
             Some(v) => vec![v],
@@ -865,7 +4135,7 @@ impl Server {
         }
         let output = paths
             .iter()
-            .map(|v| v.to_dav_xml(self.args.uri_prefix.as_str()))
+            .map(|v| v.to_dav_xml(self.args.uri_prefix.as_str(), &propfind))
             .fold(String::new(), |mut acc, v| {
                 acc.push_str(&v);
                 acc
@@ -874,22 +4144,652 @@ impl Server {
         Ok(())
     }
 
-    async fn handle_propfind_file(&self, path: &Path, res: &mut Response) -> Result<()> {
-        if let Some(pathitem) = self.to_pathitem(path, &self.args.serve_path).await? {
-            res_multistatus(res, &pathitem.to_dav_xml(self.args.uri_prefix.as_str()));
+    /// Stream the multistatus of the whole tree, one response per path
+    async fn propfind_recursive(
+        &self,
+        sender: &mut hyper::body::Sender,
+        path: &Path,
+        propfind: &PropFind,
+        access_paths: AccessPaths,
+    ) -> Result<()> {
+        let uri_prefix = self.args.uri_prefix.as_str();
+        let serve_path = self.args.serve_path.as_path();
+        sender
+            .send_data(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:multistatus xmlns:D="DAV:">
+"#
+                .into(),
+            )
+            .await?;
+        if let Some(item) = self.to_pathitem(path, serve_path).await? {
+            sender
+                .send_data(item.to_dav_xml(uri_prefix, propfind).into())
+                .await?;
+        }
+        let mut dirs = VecDeque::from([(path.to_path_buf(), access_paths)]);
+        while let Some((dir, access_paths)) = dirs.pop_front() {
+            if !self.running.load(atomic::Ordering::SeqCst) {
+                break;
+            }
+            let paths = match self.list_dir(&dir, serve_path, access_paths.clone()).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for item in paths {
+                // Symlinked dirs are not followed to avoid loops
+                if item.path_type == PathType::Dir {
+                    let child = self.child_path(&dir, item.base_name());
+                    if let Some(access_paths) = access_paths.find(item.base_name(), false) {
+                        dirs.push_back((child, access_paths));
+                    }
+                }
+                sender
+                    .send_data(item.to_dav_xml(uri_prefix, propfind).into())
+                    .await?;
+            }
+        }
+        sender.send_data("\n</D:multistatus>".into()).await?;
+        Ok(())
+    }
+
+    /// Report the members changed since the given sync token, see RFC 6578
+    #[instrument(skip_all)]
+    async fn handle_sync_collection(
+        &self,
+        path: &Path,
+        sync: SyncCollection,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        if sync.infinite && !self.args.dav_infinite_depth {
+            res_dav_error(res, "sync-traversal-supported");
+            return Ok(());
+        }
+        // Taken before walking so that concurrent changes are reported next time
+        let token = self.journal.token();
+        let (since, journal) = match sync.token.as_deref() {
+            None => (None, vec![]),
+            Some(v) => match self.journal.changes(v) {
+                Some((since, journal)) => (Some(since), journal),
+                None => {
+                    res_dav_error(res, "valid-sync-token");
+                    return Ok(());
+                }
+            },
+        };
+        let uri_prefix = self.args.uri_prefix.as_str();
+        let serve_path = self.args.serve_path.as_path();
+        let mut output = String::new();
+        let mut dirs = VecDeque::from([(path.to_path_buf(), access_paths.clone())]);
+        while let Some((dir, access_paths)) = dirs.pop_front() {
+            if !self.running.load(atomic::Ordering::SeqCst) {
+                break;
+            }
+            let paths = match self.list_dir(&dir, serve_path, access_paths.clone()).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            for item in paths {
+                let child = self.child_path(&dir, item.base_name());
+                let changed = match since {
+                    None => true,
+                    Some(since) => {
+                        item.mtime + SYNC_MTIME_SLACK >= since
+                            || journal.iter().any(|v| child.starts_with(v))
+                    }
+                };
+                if changed {
+                    output.push_str(&item.to_dav_xml(uri_prefix, &sync.propfind));
+                }
+                if sync.infinite && item.path_type == PathType::Dir {
+                    if let Some(access_paths) = access_paths.find(item.base_name(), false) {
+                        dirs.push_back((child, access_paths));
+                    }
+                }
+            }
+        }
+        let mut removed: Vec<_> = journal
+            .iter()
+            .filter(|v| match v.strip_prefix(path) {
+                Ok(rel) => rel.components().count() == 1 || (sync.infinite && v != &path),
+                Err(_) => false,
+            })
+            .collect();
+        removed.sort();
+        removed.dedup();
+        for removed_path in removed {
+            if fs::symlink_metadata(removed_path).await.is_ok()
+                || is_hidden(
+                    &self.hidden,
+                    self.args.posix_hidden,
+                    get_file_name(removed_path),
+                    false,
+                )
+            {
+                continue;
+            }
+            let rel_path = normalize_path(removed_path.strip_prefix(path)?);
+            if access_paths.find(&rel_path, false).is_none() {
+                continue;
+            }
+            let name = self.relative_name(removed_path, serve_path)?;
+            let href = encode_uri(&format!("{uri_prefix}{name}"));
+            output.push_str(&format!(
+                r#"<D:response>
+<D:href>{href}</D:href>
+<D:status>HTTP/1.1 404 Not Found</D:status>
+</D:response>
+"#
+            ));
+        }
+        output.push_str(&format!(
+            "<D:sync-token>{}</D:sync-token>",
+            escape_str_pcdata(&token)
+        ));
+        res_multistatus(res, &output);
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_propfind_file(
+        &self,
+        path: &Path,
+        propfind: &PropFind,
+        res: &mut Response,
+    ) -> Result<()> {
+        if let Some(pathitem) = self.to_pathitem(path, &self.args.serve_path).await? {
+            res_multistatus(
+                res,
+                &pathitem.to_dav_xml(self.args.uri_prefix.as_str(), propfind),
+            );
+        } else {
+            status_not_found(res);
+        }
+        Ok(())
+    }
+
+    /// Tells the subscribers to the events the file was changed
+    fn publish(&self, kind: FsEventKind, path: &Path, dest: Option<&Path>) {
+        // Preloaded files are up to date once the request is answered
+        if let Some(preload) = &self.preload {
+            preload.refresh(path);
+            if let Some(dest) = dest {
+                preload.refresh(dest);
+            }
+        }
+        if !self.wants_events() {
+            return;
+        }
+        let href = |path: &Path| {
+            self.relative_name(path, &self.args.serve_path)
+                .ok()
+                .map(|v| format!("/{v}"))
+        };
+        if let Some(name) = href(path) {
+            let mut event = FsEvent::new(kind, name);
+            event.dest = dest.and_then(href);
+            let middlewares: Vec<_> = self.all_middlewares().cloned().collect();
+            emit(&self.events, &middlewares, event);
+        }
+    }
+
+    /// Whether anybody follows the events, the WebSockets or the middleware
+    fn wants_events(&self) -> bool {
+        self.events.has_subscribers() || !self.middlewares.is_empty()
+    }
+
+    /// A callback for the chunks of an upload that publishes its progress, at most every
+    /// `PROGRESS_INTERVAL`
+    fn upload_progress(
+        &self,
+        path: &Path,
+        offset: Option<u64>,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Option<impl Fn(u64)> {
+        if !self.wants_events() {
+            return None;
+        }
+        let name = format!("/{}", self.relative_name(path, &self.args.serve_path).ok()?);
+        let offset = offset.unwrap_or_default();
+        let total = headers.typed_get::<ContentLength>().map(|v| offset + v.0);
+        let events = self.events.clone();
+        let middlewares: Vec<_> = self.all_middlewares().cloned().collect();
+        let state = std::sync::Mutex::new((offset, Instant::now()));
+        Some(move |len: u64| {
+            let mut state = state.lock().unwrap();
+            state.0 += len;
+            if state.1.elapsed() >= PROGRESS_INTERVAL {
+                state.1 = Instant::now();
+                let mut event = FsEvent::new(FsEventKind::Progress, name.clone());
+                event.received = Some(state.0);
+                event.total = total;
+                emit(&events, &middlewares, event);
+            }
+        })
+    }
+
+    /// Pushes the events of the folders the user can read over a WebSocket
+    async fn handle_events(self: Arc<Self>, req: Request, res: &mut Response) -> Result<()> {
+        let headers = req.headers();
+        let access_paths = match self.guard("", &Method::GET, headers) {
+            (_, Some(v)) if !v.perm().uploadonly() => v,
+            (None, _) => return self.auth_reject(&Method::GET, headers, res).await,
+            (Some(_), _) => {
+                status_forbid(res);
+                return Ok(());
+            }
+        };
+        let upgrade = headers
+            .get(UPGRADE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+        let key = match headers.get("sec-websocket-key") {
+            Some(key) if upgrade => derive_accept_key(key.as_bytes()),
+            _ => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                *res.body_mut() = Body::from("Expected a WebSocket upgrade");
+                return Ok(());
+            }
+        };
+        *res.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        res.headers_mut()
+            .insert(UPGRADE, HeaderValue::from_static("websocket"));
+        res.headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("upgrade"));
+        res.headers_mut()
+            .insert("sec-websocket-accept", HeaderValue::from_str(&key)?);
+        let mut rx = self.events.subscribe();
+        tokio::spawn(async move {
+            let upgraded = match hyper::upgrade::on(req).await {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+            let (mut sink, mut stream) = ws.split();
+            loop {
+                tokio::select! {
+                    event = rx.recv() => match event {
+                        Ok(event) if self.can_see_event(&event, &access_paths) => {
+                            let text = serde_json::to_string(&event).unwrap_or_default();
+                            if sink.send(Message::Text(text)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => {}
+                    },
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Whether the user may read all the paths of the event
+    fn can_see_event(&self, event: &FsEvent, access_paths: &AccessPaths) -> bool {
+        event.paths().all(|path| {
+            let path = path.trim_start_matches('/');
+            !self.is_hidden_path(path, false)
+                && !self.is_excluded(path, false)
+                && access_paths
+                    .find(path, false)
+                    .is_some_and(|v| !v.perm().uploadonly())
+        })
+    }
+
+    /// The options the server runs with
+    #[cfg(feature = "sftp")]
+    pub(crate) fn args(&self) -> &Args {
+        &self.args
+    }
+
+    /// Whether the path relative to the root is at or in a name hidden by `--hidden`
+    pub(crate) fn is_hidden_path(&self, relative_path: &str, is_dir: bool) -> bool {
+        let names: Vec<&str> = relative_path.split('/').collect();
+        names.iter().enumerate().any(|(i, name)| {
+            is_hidden(
+                &self.hidden,
+                self.args.posix_hidden,
+                name,
+                is_dir || i + 1 < names.len(),
+            )
+        })
+    }
+
+    /// Whether the user may log in with the password, for the frontends besides HTTP.
+    /// Anonymous users log in without one, if the auth rules let them in anywhere.
+    /// Failed logins count towards the lockout of the ip.
+    pub(crate) async fn login(&self, user: Option<&str>, pass: &str, ip: Option<IpAddr>) -> bool {
+        if self.lockout.locked(ip).is_some() {
+            return false;
+        }
+        match user {
+            Some(user) if self.args.auth.check_password(user, pass).await => {
+                self.lockout.succeed(ip);
+                true
+            }
+            Some(_) => {
+                self.lockout.fail(ip);
+                false
+            }
+            None => self.guard("", &Method::GET, &HeaderMap::new()).1.is_some(),
+        }
+    }
+
+    /// The file or folder at the path relative to the root, whether it's a folder and
+    /// what's readable in it, if the user may read it, for the frontends besides HTTP
+    pub(crate) async fn lookup(
+        &self,
+        relative_path: &str,
+        user: Option<&str>,
+    ) -> Option<(PathBuf, bool, AccessPaths)> {
+        let (_, access_paths) = match user {
+            Some(user) => self.args.auth.guard_user(relative_path, &Method::GET, user),
+            None => self.guard(relative_path, &Method::GET, &HeaderMap::new()),
+        };
+        let access_paths = access_paths.filter(|v| !v.perm().uploadonly())?;
+        let path = self.join_path(relative_path)?;
+        let is_dir = fs::metadata(&path).await.ok()?.is_dir();
+        if self.is_excluded(relative_path, is_dir) || !self.is_symlink_allowed(&path).await {
+            return None;
+        }
+        Some((path, is_dir, access_paths))
+    }
+
+    /// The entries of a folder found by `lookup` that may be read, sorted by name and
+    /// named relative to the root
+    pub(crate) async fn entries(
+        &self,
+        path: &Path,
+        access_paths: &AccessPaths,
+    ) -> Result<Vec<PathItem>> {
+        let mut items = self
+            .list_dir(path, &self.args.serve_path, access_paths.clone())
+            .await?;
+        items.retain(|item| {
+            !item.is_dir()
+                || access_paths
+                    .find(item.base_name(), false)
+                    .is_some_and(|v| !v.perm().uploadonly())
+        });
+        items.sort_by(|a, b| a.sort_by_name(b));
+        Ok(items)
+    }
+
+    /// The URI of the path relative to the root, for the requests given to `change`
+    pub(crate) fn uri(&self, relative_path: &str) -> String {
+        format!("{}{}", self.args.uri_prefix, encode_uri(relative_path))
+    }
+
+    /// Carries out a change of a frontend besides HTTP, or of an archive being extracted, as
+    /// the request it maps to, so the same permissions, limits, hooks and events apply,
+    /// returning the status of the response. `user` is the user logged in, anonymous if none.
+    pub(crate) async fn change(
+        self: Arc<Self>,
+        mut req: Request,
+        user: Option<&str>,
+        ip: Option<IpAddr>,
+    ) -> Result<StatusCode> {
+        req.extensions_mut()
+            .insert(LoggedIn(user.map(|v| v.to_string())));
+        // Boxed, as extracting an archive makes these requests while handling one
+        let res = Box::pin(self.handle(req, ip)).await?;
+        Ok(res.status())
+    }
+
+    /// The UPnP descriptions and services of `--dlna`, used by the players without
+    /// authenticating
+    async fn handle_dlna(&self, name: &str, req: Request, res: &mut Response) -> Result<()> {
+        let output = match (req.method().as_str(), name) {
+            ("GET", "description.xml") => dlna::description(&self.args),
+            ("GET", "ContentDirectory.xml") => dlna::CONTENT_DIRECTORY_SCPD.to_string(),
+            ("GET", "ConnectionManager.xml") => dlna::CONNECTION_MANAGER_SCPD.to_string(),
+            ("POST", "control/ContentDirectory" | "control/ConnectionManager") => {
+                let service = name.trim_start_matches("control/");
+                let action = req
+                    .headers()
+                    .get("soapaction")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim_matches('"').split_once('#'))
+                    .map(|(_, v)| v.to_string())
+                    .unwrap_or_default();
+                let headers = req.headers().clone();
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let params = dlna::parse_action(&body).unwrap_or_default();
+                match self
+                    .dlna_action(service, &action, &params, &headers)
+                    .await?
+                {
+                    Ok(v) => v,
+                    Err((code, description)) => {
+                        *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        dlna::soap_fault(code, description)
+                    }
+                }
+            }
+            // Changes aren't evented, the players see them when they browse again
+            ("SUBSCRIBE", _) if name.starts_with("event/") => {
+                let sid = format!("uuid:{}", Uuid::new_v4());
+                res.headers_mut()
+                    .insert("SID", HeaderValue::from_str(&sid)?);
+                res.headers_mut()
+                    .insert("TIMEOUT", HeaderValue::from_static("Second-1800"));
+                return Ok(());
+            }
+            ("UNSUBSCRIBE", _) if name.starts_with("event/") => return Ok(()),
+            _ => {
+                status_not_found(res);
+                return Ok(());
+            }
+        };
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/xml; charset=utf-8"),
+        );
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// The SOAP reply to the action, or the UPnP error code and description
+    async fn dlna_action(
+        &self,
+        service: &str,
+        action: &str,
+        params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Result<String, (u16, &'static str)>> {
+        let (service, args) = match (service, action) {
+            ("ContentDirectory", "Browse") => match self.dlna_browse(params, headers).await? {
+                Some((result, returned, total)) => (
+                    dlna::CONTENT_DIRECTORY,
+                    vec![
+                        ("Result", result),
+                        ("NumberReturned", returned.to_string()),
+                        ("TotalMatches", total.to_string()),
+                        ("UpdateID", "0".to_string()),
+                    ],
+                ),
+                None => return Ok(Err((701, "No such object"))),
+            },
+            ("ContentDirectory", "GetSearchCapabilities") => {
+                (dlna::CONTENT_DIRECTORY, vec![("SearchCaps", String::new())])
+            }
+            ("ContentDirectory", "GetSortCapabilities") => {
+                (dlna::CONTENT_DIRECTORY, vec![("SortCaps", String::new())])
+            }
+            ("ContentDirectory", "GetSystemUpdateID") => {
+                (dlna::CONTENT_DIRECTORY, vec![("Id", "0".to_string())])
+            }
+            ("ConnectionManager", "GetProtocolInfo") => (
+                dlna::CONNECTION_MANAGER,
+                vec![
+                    ("Source", "http-get:*:*:*".to_string()),
+                    ("Sink", String::new()),
+                ],
+            ),
+            ("ConnectionManager", "GetCurrentConnectionIDs") => (
+                dlna::CONNECTION_MANAGER,
+                vec![("ConnectionIDs", "0".to_string())],
+            ),
+            _ => return Ok(Err((401, "Invalid Action"))),
+        };
+        Ok(Ok(dlna::soap_response(service, action, &args)))
+    }
+
+    /// The DIDL-Lite of the folders and media files in a folder, or of a single object,
+    /// along with how many were returned and how many there are. Objects are identified
+    /// by their path and only what anonymous users may read is browsed.
+    async fn dlna_browse(
+        &self,
+        params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Option<(String, usize, usize)>> {
+        let id = params.get("ObjectID").map(|v| v.as_str()).unwrap_or("0");
+        let relative_path = match id {
+            "0" => "",
+            _ if id.split('/').any(|v| v.is_empty() || v == "." || v == "..") => return Ok(None),
+            _ => id,
+        };
+        let (path, is_dir, access_paths) = match self.lookup(relative_path, None).await {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let base_url = format!("{}{}", self.base_url(headers), self.args.uri_prefix);
+        let to_didl = |item: &PathItem| match item.media_type {
+            _ if item.is_dir() => dlna::didl_container(&item.name, item.base_name()),
+            Some(media_type) => dlna::didl_item(
+                &item.name,
+                item.base_name(),
+                media_type,
+                &format!("{base_url}{}", encode_uri(&item.name)),
+                item.size,
+            ),
+            None => String::new(),
+        };
+        if params.get("BrowseFlag").map(|v| v.as_str()) == Some("BrowseMetadata") {
+            let output = match id {
+                "0" => dlna::didl_container("0", &self.args.site_name),
+                _ => match self.to_pathitem(&path, &self.args.serve_path).await? {
+                    Some(item) if item.is_dir() || item.media_type.is_some() => to_didl(&item),
+                    _ => return Ok(None),
+                },
+            };
+            return Ok(Some((dlna::didl(&output), 1, 1)));
+        }
+        if !is_dir {
+            return Ok(None);
+        }
+        let mut items = self.entries(&path, &access_paths).await?;
+        items.retain(|item| item.is_dir() || item.media_type.is_some());
+        let total = items.len();
+        let offset = params
+            .get("StartingIndex")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let limit = match params.get("RequestedCount").and_then(|v| v.parse().ok()) {
+            Some(0) | None => usize::MAX,
+            Some(v) => v,
+        };
+        let page: Vec<String> = items.iter().skip(offset).take(limit).map(to_didl).collect();
+        Ok(Some((dlna::didl(&page.concat()), page.len(), total)))
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_mkcol(&self, path: &Path, res: &mut Response) -> Result<()> {
+        fs::create_dir_all(path).await?;
+        self.publish(FsEventKind::Mkdir, path, None);
+        *res.status_mut() = StatusCode::CREATED;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_copy(
+        &self,
+        path: &Path,
+        access_paths: &AccessPaths,
+        req: &Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let dest = match self.extract_dest(req, res) {
+            Some(dest) => dest,
+            None => {
+                return Ok(());
+            }
+        };
+
+        if !self.file_type_allowed(path, &dest).await? {
+            status_unsupported_type(res, &dest);
+            return Ok(());
+        }
+
+        let meta = fs::symlink_metadata(path).await?;
+        let recursive = match req.headers().get("depth").map(|v| v.to_str()) {
+            None => true,
+            Some(Ok(v)) if v.eq_ignore_ascii_case("infinity") => true,
+            Some(Ok("0")) if meta.is_dir() => false,
+            _ => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(());
+            }
+        };
+
+        let incoming = match (meta.is_dir(), recursive) {
+            (true, true) => entry_size(path).await?,
+            (true, false) => 0,
+            (false, _) => meta.len(),
+        };
+        if self.quotas.exceeded(&dest, incoming).await? {
+            status_insufficient_storage(res);
+            return Ok(());
+        }
+
+        let overwritten = match prepare_dest(path, &dest, req.headers(), res).await? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        ensure_path_parent(&dest).await?;
+
+        // Copied next to the destination, which is replaced once the copy is complete
+        let staged = staging_path(&dest, "copy");
+        let ret = if !meta.is_dir() {
+            fs::copy(path, &staged).await.map_err(Into::into)
+        } else if recursive {
+            self.copy_dir_all(path, &staged, access_paths).await
         } else {
-            status_not_found(res);
+            fs::create_dir(&staged).await.map(|_| 0).map_err(Into::into)
+        };
+        let copied = match ret {
+            Ok(v) => v,
+            Err(err) => {
+                let _ = remove_entry(&staged).await;
+                return Err(err);
+            }
+        };
+        let replaced = match overwritten {
+            true => entry_size(&dest).await?,
+            false => 0,
+        };
+        if let Err(err) = self.replace_dest(&staged, &dest, overwritten).await {
+            let _ = remove_entry(&staged).await;
+            return Err(err);
         }
-        Ok(())
-    }
 
-    async fn handle_mkcol(&self, path: &Path, res: &mut Response) -> Result<()> {
-        fs::create_dir_all(path).await?;
-        *res.status_mut() = StatusCode::CREATED;
+        self.journal.record(&dest);
+        self.quotas.add(&dest, copied as i64 - replaced as i64);
+        self.publish(FsEventKind::Copy, path, Some(&dest));
+        status_copied(res, overwritten);
         Ok(())
     }
 
-    async fn handle_copy(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
+    #[instrument(skip_all)]
+    async fn handle_move(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
         let dest = match self.extract_dest(req, res) {
             Some(dest) => dest,
             None => {
@@ -897,36 +4797,115 @@ impl Server {
             }
         };
 
-        let meta = fs::symlink_metadata(path).await?;
-        if meta.is_dir() {
-            status_forbid(res);
+        if !self.file_type_allowed(path, &dest).await? {
+            status_unsupported_type(res, &dest);
             return Ok(());
         }
 
+        let overwritten = match prepare_dest(path, &dest, req.headers(), res).await? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
         ensure_path_parent(&dest).await?;
 
-        fs::copy(path, &dest).await?;
+        self.replace_dest(path, &dest, overwritten).await?;
+        self.journal.record(path);
+        self.journal.record(&dest);
+        self.quotas.invalidate(path);
+        self.quotas.invalidate(&dest);
+        self.publish(FsEventKind::Move, path, Some(&dest));
 
-        status_no_content(res);
+        status_copied(res, overwritten);
         Ok(())
     }
 
-    async fn handle_move(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
-        let dest = match self.extract_dest(req, res) {
-            Some(dest) => dest,
-            None => {
-                return Ok(());
+    /// Copy a directory with what the user sees of it, like in listings: the folders out of
+    /// reach of the access paths and the hidden and excluded entries are left out, as well
+    /// as symlinks. Returns the bytes copied.
+    async fn copy_dir_all(
+        &self,
+        src: &Path,
+        dest: &Path,
+        access_paths: &AccessPaths,
+    ) -> Result<u64> {
+        let leaves = access_paths.leaf_paths(src);
+        let hidden = self.hidden.clone();
+        let posix_hidden = self.args.posix_hidden;
+        let src = src.to_path_buf();
+        let dest = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<u64> {
+            std::fs::create_dir_all(&dest)?;
+            // Collect first, so the walk never sees what is being copied
+            let mut entries = vec![];
+            for leaf in leaves {
+                let mut it = WalkDir::new(&leaf).into_iter();
+                while let Some(entry) = it.next() {
+                    let entry = entry?;
+                    let name = get_file_name(entry.path());
+                    let is_dir = entry.file_type().is_dir();
+                    if entry.depth() > 0 && is_hidden(&hidden, posix_hidden, name, is_dir) {
+                        if is_dir {
+                            it.skip_current_dir();
+                        }
+                        continue;
+                    }
+                    entries.push(entry);
+                }
             }
-        };
-
-        ensure_path_parent(&dest).await?;
+            let mut copied = 0;
+            for entry in entries {
+                let target = dest.join(entry.path().strip_prefix(&src)?);
+                let file_type = entry.file_type();
+                if file_type.is_dir() {
+                    std::fs::create_dir_all(&target)?;
+                } else if file_type.is_file() {
+                    copied += std::fs::copy(entry.path(), &target)?;
+                }
+            }
+            Ok(copied)
+        })
+        .await?
+    }
 
-        fs::rename(path, &dest).await?;
+    /// Renames `src` to `dest`, or copies it over and removes it when they're on different
+    /// filesystems, like those of two mounts. The destination is replaced, not merged, and
+    /// only once the move succeeded, it's put back otherwise.
+    async fn replace_dest(&self, src: &Path, dest: &Path, overwritten: bool) -> Result<()> {
+        if !overwritten {
+            move_entry(src, dest).await?;
+            return Ok(());
+        }
+        let old = staging_path(dest, "old");
+        fs::rename(dest, &old).await?;
+        if let Err(err) = move_entry(src, dest).await {
+            let _ = fs::rename(&old, dest).await;
+            return Err(err.into());
+        }
+        match &self.trash {
+            Some(trash) => {
+                let rel_path = self.relative_name(dest, &self.args.serve_path)?;
+                trash.put(&old, &rel_path).await
+            }
+            None => remove_entry(&old).await,
+        }
+    }
 
-        status_no_content(res);
-        Ok(())
+    /// Whether a file may be moved or copied to `dest`, as if it were uploaded there
+    async fn file_type_allowed(&self, path: &Path, dest: &Path) -> Result<bool> {
+        if !self.upload_types.is_restricted() || fs::metadata(path).await?.is_dir() {
+            return Ok(true);
+        }
+        let mut head = vec![];
+        fs::File::open(path)
+            .await?
+            .take(SNIFF_LEN)
+            .read_to_end(&mut head)
+            .await?;
+        Ok(self.upload_types.allows(get_file_name(dest), &head))
     }
 
+    #[instrument(skip_all)]
     async fn handle_lock(&self, req_path: &str, auth: bool, res: &mut Response) -> Result<()> {
         let token = if auth {
             format!("opaquelocktoken:{}", Uuid::new_v4())
@@ -951,6 +4930,7 @@ impl Server {
         Ok(())
     }
 
+    #[instrument(skip_all)]
     async fn handle_proppatch(&self, req_path: &str, res: &mut Response) -> Result<()> {
         let output = format!(
             r#"<D:response>
@@ -967,10 +4947,11 @@ impl Server {
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn send_index(
+    async fn send_index(
         &self,
         path: &Path,
         mut paths: Vec<PathItem>,
+        readme: Option<String>,
         exist: bool,
         query_params: &HashMap<String, String>,
         head_only: bool,
@@ -978,23 +4959,30 @@ impl Server {
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        if let Some(sort) = query_params.get("sort") {
-            if sort == "name" {
-                paths.sort_by(|v1, v2| v1.sort_by_name(v2))
-            } else if sort == "mtime" {
-                paths.sort_by(|v1, v2| v1.sort_by_mtime(v2))
-            } else if sort == "size" {
-                paths.sort_by(|v1, v2| v1.sort_by_size(v2))
-            }
-            if query_params
-                .get("order")
-                .map(|v| v == "desc")
-                .unwrap_or_default()
-            {
-                paths.reverse()
-            }
-        } else {
-            paths.sort_by(|v1, v2| v1.sort_by_name(v2))
+        let gallery = query_params.contains_key("gallery");
+        if gallery {
+            paths.retain(|v| v.is_dir() || v.media_type.is_some());
+        }
+        sort_paths(&mut paths, query_params, &self.args.default_sort);
+        if query_params.contains_key("q") {
+            let (offset, limit) = search_page(query_params);
+            paths = paths.into_iter().skip(offset).take(limit).collect();
+        }
+        if query_params.contains_key("json") && query_params.contains_key("hash") {
+            let algo = match hash_algo(query_params) {
+                Some(v) => v,
+                None => {
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    *res.body_mut() = Body::from("Unknown hash algorithm");
+                    return Ok(());
+                }
+            };
+            self.add_checksums(path, &mut paths, algo).await;
+        }
+        if query_params.contains_key("json")
+            && query_params.get("meta").map(|v| v.as_str()) == Some("full")
+        {
+            add_full_meta(&mut paths);
         }
         if query_params.contains_key("simple") {
             let output = paths
@@ -1011,44 +4999,102 @@ impl Server {
             res.headers_mut()
                 .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
             res.headers_mut()
-                .typed_insert(ContentLength(output.as_bytes().len() as u64));
+                .typed_insert(ContentLength(output.len() as u64));
+            res.headers_mut()
+                .typed_insert(digest_etag(&[b"simple", output.as_bytes()]));
             *res.body_mut() = output.into();
             if head_only {
                 return Ok(());
             }
             return Ok(());
         }
-        let href = format!(
-            "/{}",
-            normalize_path(path.strip_prefix(&self.args.serve_path)?)
-        );
+        if let Some(sep) = table_separator(query_params) {
+            let mut output = ["name", "type", "size", "mtime"].join(sep) + "\r\n";
+            for item in paths {
+                let mtime = match Utc.timestamp_millis_opt(item.mtime as i64) {
+                    LocalResult::Single(v) => v.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    _ => String::new(),
+                };
+                let row = [
+                    table_field(&item.name, sep),
+                    if item.is_dir() { "dir" } else { "file" }.to_string(),
+                    item.size.map(|v| v.to_string()).unwrap_or_default(),
+                    mtime,
+                ];
+                output.push_str(&row.join(sep));
+                output.push_str("\r\n");
+            }
+            let mime = if sep == "," {
+                "text/csv; charset=utf-8"
+            } else {
+                "text/tab-separated-values; charset=utf-8"
+            };
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(mime));
+            res.headers_mut()
+                .typed_insert(ContentLength(output.len() as u64));
+            res.headers_mut()
+                .typed_insert(digest_etag(&[sep.as_bytes(), output.as_bytes()]));
+            if !head_only {
+                *res.body_mut() = output.into();
+            }
+            return Ok(());
+        }
+        let href = format!("/{}", self.relative_name(path, &self.args.serve_path)?);
         let readwrite = access_paths.perm().readwrite();
+        let upload_only = access_paths.perm().uploadonly();
         let data = IndexData {
-            kind: DataKind::Index,
+            kind: if gallery {
+                DataKind::Gallery
+            } else {
+                DataKind::Index
+            },
             href,
             uri_prefix: self.args.uri_prefix.clone(),
-            allow_upload: self.args.allow_upload && readwrite,
+            allow_upload: self.args.allow_upload && (readwrite || upload_only),
             allow_delete: self.args.allow_delete && readwrite,
             allow_search: self.args.allow_search,
             allow_archive: self.args.allow_archive,
+            archive_progress: self.args.archive_progress,
+            confirm_rm_dir: self.args.confirm_rm_dir,
             dir_exists: exist,
+            upload_only,
             auth: self.args.auth.exist(),
+            auth_form: self.args.auth_method == AuthMethod::Form,
             user,
             paths,
+            readme,
         };
-        let output = if query_params.contains_key("json") {
+        let (output, etag) = if query_params.contains_key("json") {
             res.headers_mut()
                 .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
-            serde_json::to_string_pretty(&data)?
+            let output = match query_params.get("fields") {
+                Some(fields) => {
+                    let fields: Vec<&str> = fields.split(',').map(|v| v.trim()).collect();
+                    let mut data = serde_json::to_value(&data)?;
+                    if let Some(paths) = data["paths"].as_array_mut() {
+                        for item in paths.iter_mut().filter_map(|v| v.as_object_mut()) {
+                            item.retain(|k, _| fields.contains(&k.as_str()));
+                        }
+                    }
+                    serde_json::to_string_pretty(&data)?
+                }
+                None => serde_json::to_string_pretty(&data)?,
+            };
+            let etag = digest_etag(&[b"json", output.as_bytes()]);
+            (output, etag)
         } else {
             res.headers_mut()
                 .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
-            self.html
-                .replace("__ASSETS_PREFIX__", &self.assets_prefix)
-                .replace("__INDEX_DATA__", &serde_json::to_string(&data)?)
+            // The page itself differs every time by its nonce, so the etag covers what it shows
+            let index_data = serde_json::to_string(&data)?;
+            let etag = digest_etag(&[b"html", self.html.as_bytes(), index_data.as_bytes()]);
+            let output = self.render_html(&self.html, &[("INDEX_DATA", &index_data)], res);
+            (output, etag)
         };
         res.headers_mut()
-            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+            .typed_insert(ContentLength(output.len() as u64));
+        res.headers_mut().typed_insert(etag);
         res.headers_mut()
             .typed_insert(CacheControl::new().with_no_cache());
         res.headers_mut().insert(
@@ -1062,24 +5108,350 @@ impl Server {
         Ok(())
     }
 
-    fn auth_reject(&self, res: &mut Response) -> Result<()> {
+    /// Fills in the digests of the listed files, hashing only the ones small enough while
+    /// the bytes hashed stay within a budget, and leaving the others to the xattr cache
+    async fn add_checksums(&self, path: &Path, paths: &mut [PathItem], algo: ChecksumAlgo) {
+        let mut budget = LISTING_HASH_MAX_TOTAL;
+        for item in paths.iter_mut().filter(|v| !v.is_dir()) {
+            let file_path = path.join(&item.name);
+            let size = item.size.unwrap_or_default();
+            let hash = if size <= LISTING_HASH_MAX_SIZE && size <= budget {
+                budget -= size;
+                file_checksum(&file_path, algo, self.args.hash_xattr)
+                    .await
+                    .ok()
+            } else if self.args.hash_xattr {
+                cached_file_checksum(&file_path, algo).await
+            } else {
+                None
+            };
+            if let Some(hash) = hash {
+                item.checksum = Some(BTreeMap::from([(algo.name(), hash)]));
+            }
+        }
+    }
+
+    fn guard(
+        &self,
+        path: &str,
+        method: &Method,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        let (user, paths) = self.authorize(method, headers);
+        guard_paths(user, paths, path, method)
+    }
+
+    /// The user and all the paths they may access, for `guard` to find the requested one in
+    fn authorize(
+        &self,
+        method: &Method,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> (Option<String>, Option<Cow<'_, AccessPaths>>) {
+        if self.args.auth_method == AuthMethod::Form {
+            if let Some(user) = self.sessions.user(headers) {
+                let paths = self.args.auth.user_paths(&user);
+                return (Some(user), paths);
+            }
+        }
+        self.args.auth.authorize(method, headers)
+    }
+
+    /// Like `guard`, but going by the user a frontend besides HTTP logged in, if the
+    /// request was made by one with `change`
+    fn guard_request(
+        &self,
+        path: &str,
+        method: &Method,
+        req: &Request,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        let (user, paths) = self.authorize_request(method, req);
+        guard_paths(user, paths, path, method)
+    }
+
+    /// Like `authorize`, but going by what the requests made with `change` carry
+    fn authorize_request<'a>(
+        &'a self,
+        method: &Method,
+        req: &'a Request,
+    ) -> (Option<String>, Option<Cow<'a, AccessPaths>>) {
+        if let Some(Granted(user, paths)) = req.extensions().get::<Granted>() {
+            return (user.clone(), Some(Cow::Borrowed(paths)));
+        }
+        match req.extensions().get::<LoggedIn>() {
+            Some(LoggedIn(Some(user))) => (Some(user.clone()), self.args.auth.user_paths(user)),
+            Some(LoggedIn(None)) => self.authorize(method, &HeaderMap::new()),
+            None => self.authorize(method, req.headers()),
+        }
+    }
+
+    /// Where to send the browser back to after logging in or out, a path of this server
+    /// whatever the request path looks like, e.g. `//evil.com`
+    fn same_origin_location(&self, req_path: &str, relative_path: &str) -> String {
+        let mut location = format!("{}{}", self.args.uri_prefix, encode_uri(relative_path));
+        if req_path.ends_with('/') && !relative_path.is_empty() {
+            location.push('/');
+        }
+        location
+    }
+
+    #[instrument(skip_all)]
+    async fn handle_login(
+        &self,
+        location: &str,
+        body: &[u8],
+        ip: Option<IpAddr>,
+        res: &mut Response,
+    ) -> Result<()> {
+        if let Some(retry_after) = self.lockout.locked(ip) {
+            self.handle_login_page(Some("Too many failed attempts, try again later"), res)
+                .await?;
+            status_too_many_requests(res, retry_after);
+            return Ok(());
+        }
+        let form: HashMap<String, String> = form_urlencoded::parse(body)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let user = form.get("username").map(|v| v.as_str()).unwrap_or_default();
+        let password = form.get("password").map(|v| v.as_str()).unwrap_or_default();
+        if !self.args.auth.check_password(user, password).await {
+            self.lockout.fail(ip);
+            self.handle_login_page(Some("Invalid username or password"), res)
+                .await?;
+            *res.status_mut() = StatusCode::UNAUTHORIZED;
+            return Ok(());
+        }
+        self.lockout.succeed(ip);
+        let id = self.sessions.create(user)?;
+        let secure = if self.args.tls() { "; Secure" } else { "" };
+        res.headers_mut().insert(
+            SET_COOKIE,
+            format!(
+                "{SESSION_COOKIE}={id}; Path={}; Max-Age={SESSION_TIMEOUT}; HttpOnly; SameSite=Lax{secure}",
+                self.args.uri_prefix
+            )
+            .parse()?,
+        );
+        *res.status_mut() = StatusCode::SEE_OTHER;
+        res.headers_mut().insert(LOCATION, location.parse()?);
+        Ok(())
+    }
+
+    fn handle_logout(
+        &self,
+        location: &str,
+        headers: &HeaderMap<HeaderValue>,
+        res: &mut Response,
+    ) -> Result<()> {
+        self.sessions.remove(headers);
+        res.headers_mut().insert(
+            SET_COOKIE,
+            format!(
+                "{SESSION_COOKIE}=; Path={}; Max-Age=0; HttpOnly; SameSite=Lax",
+                self.args.uri_prefix
+            )
+            .parse()?,
+        );
+        *res.status_mut() = StatusCode::SEE_OTHER;
+        res.headers_mut().insert(LOCATION, location.parse()?);
+        Ok(())
+    }
+
+    async fn handle_login_page(&self, error: Option<&str>, res: &mut Response) -> Result<()> {
+        let output = self.login_page(error, res).await?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.len() as u64));
+        *res.body_mut() = output.into();
+        Ok(())
+    }
+
+    /// Use `login.html` of the custom assets if there is one
+    async fn login_page(&self, error: Option<&str>, res: &mut Response) -> Result<String> {
+        let html = match self.args.assets.as_ref() {
+            Some(path) => fs::read_to_string(path.join("login.html"))
+                .await
+                .unwrap_or_else(|_| LOGIN_HTML.to_string()),
+            None => LOGIN_HTML.to_string(),
+        };
+        let error = error
+            .map(|v| format!(r#"<p class="error">{v}</p>"#))
+            .unwrap_or_default();
+        Ok(self.render_html(&html, &[("LOGIN_ERROR", &error)], res))
+    }
+
+    /// Fills the placeholders of a page, the ones of the branding options and the given ones.
+    /// Sets the Content-Security-Policy of the page, whose inline scripts carry `__CSP_NONCE__`.
+    fn render_html(&self, template: &str, vars: &[(&str, &str)], res: &mut Response) -> String {
+        let nonce = Uuid::new_v4().simple().to_string();
+        if self.args.security_header("content-security-policy") {
+            let csp = format!(
+                "default-src 'self'; script-src 'self' 'nonce-{nonce}'; style-src 'self' 'unsafe-inline'; \
+                img-src * data: blob:; media-src 'self' blob:; object-src 'none'; base-uri 'self'; \
+                form-action 'self'; frame-ancestors 'self'"
+            );
+            res.headers_mut().insert(
+                "content-security-policy",
+                HeaderValue::from_str(&csp).unwrap(),
+            );
+        }
+        let site_name = escape_str_pcdata(&self.args.site_name);
+        let logo_url = escape_str_attribute(self.args.logo_url.as_deref().unwrap_or_default());
+        let mut all_vars = vec![
+            ("ASSETS_PREFIX", self.assets_prefix.as_str()),
+            ("CSP_NONCE", &nonce),
+            ("SITE_NAME", &site_name),
+            ("LOGO_URL", &logo_url),
+            (
+                "HEADER_HTML",
+                self.args.header_html.as_deref().unwrap_or_default(),
+            ),
+            (
+                "FOOTER_HTML",
+                self.args.footer_html.as_deref().unwrap_or_default(),
+            ),
+        ];
+        all_vars.extend_from_slice(vars);
+        render_template(template, &all_vars)
+    }
+
+    fn add_security_headers(&self, res: &mut Response) {
+        if self.args.security_header("x-content-type-options") {
+            res.headers_mut().insert(
+                "x-content-type-options",
+                HeaderValue::from_static("nosniff"),
+            );
+        }
+        if self.args.security_header("referrer-policy") {
+            res.headers_mut().insert(
+                "referrer-policy",
+                HeaderValue::from_static("strict-origin-when-cross-origin"),
+            );
+        }
+        if self.args.tls() && self.args.security_header("strict-transport-security") {
+            res.headers_mut().insert(
+                "strict-transport-security",
+                HeaderValue::from_static("max-age=31536000"),
+            );
+        }
+    }
+
+    async fn auth_reject(
+        &self,
+        method: &Method,
+        headers: &HeaderMap<HeaderValue>,
+        res: &mut Response,
+    ) -> Result<()> {
+        // Browsers get the login form instead of a basic auth popup
+        if self.args.auth_method == AuthMethod::Form {
+            if method.as_str() == "WRITEABLE" {
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                return Ok(());
+            }
+            if (method == Method::GET || method == Method::HEAD)
+                && headers
+                    .get("accept")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.contains("text/html"))
+                    .unwrap_or_default()
+            {
+                self.handle_login_page(None, res).await?;
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                return Ok(());
+            }
+        }
         set_webdav_headers(res);
         res.headers_mut()
             .append(WWW_AUTHENTICATE, www_authenticate(&self.args)?);
+        if self.args.auth.use_jwt() {
+            res.headers_mut().append(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer realm=\"DUFS\""),
+            );
+        }
         // set 401 to make the browser pop up the login box
         *res.status_mut() = StatusCode::UNAUTHORIZED;
         Ok(())
     }
 
+    /// Whether the path is in the served folders, or out of them by a symlink allowed
+    /// by `--allow-symlink`
+    async fn is_symlink_allowed(&self, path: &Path) -> bool {
+        let rules = &self.args.allow_symlink;
+        rules.is_all()
+            || self
+                .relative_path_of(path)
+                .is_some_and(|v| rules.allows(&v))
+            || self.is_root_contained(path).await
+    }
+
+    /// The relative request path of a path in the served folders, the reverse of `join_path`
+    fn relative_path_of(&self, path: &Path) -> Option<String> {
+        let (name, rest) = self
+            .args
+            .mount_paths
+            .iter()
+            .find_map(|(name, root)| Some((name.as_str(), path.strip_prefix(root).ok()?)))
+            .or_else(|| Some(("", path.strip_prefix(&self.args.serve_path).ok()?)))?;
+        let rest = rest
+            .components()
+            .map(|v| v.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Some(match (name, rest.as_str()) {
+            ("", _) => rest,
+            (_, "") => name.to_string(),
+            _ => format!("{name}/{rest}"),
+        })
+    }
+
     async fn is_root_contained(&self, path: &Path) -> bool {
-        fs::canonicalize(path)
-            .await
+        // Symlinks aren't followed when preloading, so the plain entries are inside a root
+        if let Some(entry) = self.preload.as_ref().and_then(|v| v.get(path)) {
+            if !entry.is_symlink {
+                return true;
+            }
+        }
+        let real_path = match &self.meta_cache {
+            Some(meta_cache) => meta_cache.canonicalize(path).await,
+            None => fs::canonicalize(path).await,
+        };
+        real_path
             .ok()
-            .map(|v| v.starts_with(&self.args.serve_path))
+            .map(|v| {
+                v.starts_with(&self.args.serve_path)
+                    || self
+                        .args
+                        .mount_paths
+                        .iter()
+                        .any(|(_, root)| v.starts_with(root))
+            })
             .unwrap_or_default()
     }
 
     fn extract_dest(&self, req: &Request, res: &mut Response) -> Option<PathBuf> {
+        let (relative_path, upload_only) = self.extract_dest_name(req, res)?;
+
+        let dest = match self.join_path(&relative_path) {
+            Some(dest) => dest,
+            None => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return None;
+            }
+        };
+
+        // A dropbox takes new entries, it doesn't let the ones there be replaced
+        if self.is_mount(&dest) || (upload_only && dest.symlink_metadata().is_ok()) {
+            status_forbid(res);
+            return None;
+        }
+
+        Some(dest)
+    }
+
+    /// The relative path of the destination of a COPY or MOVE the user may write to, and
+    /// whether it's in a dropbox
+    fn extract_dest_name(&self, req: &Request, res: &mut Response) -> Option<(String, bool)> {
         let headers = req.headers();
         let dest_path = match self.extract_destination_header(headers) {
             Some(dest) => dest,
@@ -1097,29 +5469,21 @@ impl Server {
             }
         };
 
-        let authorization = headers.get(AUTHORIZATION);
-        let guard = self
-            .args
-            .auth
-            .guard(&relative_path, req.method(), authorization);
+        // Whether it ends up a folder isn't known yet, so go with the stricter check
+        if self.is_excluded(&relative_path, true) {
+            status_not_found(res);
+            return None;
+        }
 
-        match guard {
-            (_, Some(_)) => {}
+        let upload_only = match self.guard_request(&relative_path, req.method(), req) {
+            (_, Some(access_paths)) => access_paths.perm().uploadonly(),
             _ => {
                 status_forbid(res);
                 return None;
             }
         };
 
-        let dest = match self.join_path(&relative_path) {
-            Some(dest) => dest,
-            None => {
-                *res.status_mut() = StatusCode::BAD_REQUEST;
-                return None;
-            }
-        };
-
-        Some(dest)
+        Some((relative_path, upload_only))
     }
 
     fn extract_destination_header(&self, headers: &HeaderMap<HeaderValue>) -> Option<String> {
@@ -1143,12 +5507,91 @@ impl Server {
         if path.is_empty() {
             return Some(self.args.serve_path.clone());
         }
-        let path = if cfg!(windows) {
-            path.replace('/', "\\")
-        } else {
-            path.to_string()
-        };
-        Some(self.args.serve_path.join(path))
+        let (root, path) = match self.find_mount(path) {
+            Some((root, rest)) => (root, rest),
+            None => (&self.args.serve_path, path),
+        };
+        if path.is_empty() {
+            return Some(root.clone());
+        }
+        let path = if cfg!(windows) {
+            path.replace('/', "\\")
+        } else {
+            path.to_string()
+        };
+        Some(root.join(path))
+    }
+
+    /// The mount a relative request path is in, with the path left inside it
+    fn find_mount<'a>(&'a self, path: &'a str) -> Option<(&'a PathBuf, &'a str)> {
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        self.args
+            .mount_paths
+            .iter()
+            .find(|(v, _)| v == name)
+            .map(|(_, root)| (root, rest))
+    }
+
+    /// Whether the relative path is in or at a name matched by `--exclude`
+    fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        if self.args.exclude.is_empty() || path.is_empty() {
+            return false;
+        }
+        let names: Vec<&str> = path.split('/').collect();
+        names.iter().enumerate().any(|(i, name)| {
+            let is_dir_type = is_dir || i + 1 < names.len();
+            is_hidden(&self.args.exclude, false, name, is_dir_type)
+        })
+    }
+
+    /// Whether writing `incoming` more bytes at `path` would leave less free space
+    /// than `--min-free-space`
+    async fn short_of_space(&self, path: &Path, incoming: u64) -> Result<bool> {
+        let min_free_space = match self.args.min_free_space {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        #[cfg(unix)]
+        {
+            let path = path.to_path_buf();
+            let free = tokio::task::spawn_blocking(move || available_space(&path)).await??;
+            Ok(free.saturating_sub(incoming) < min_free_space)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (path, incoming, min_free_space);
+            Ok(false)
+        }
+    }
+
+    fn is_mount(&self, path: &Path) -> bool {
+        self.args.mount_paths.iter().any(|(_, root)| root == path)
+    }
+
+    /// Mounts are listed in the root as if they were directories in it
+    fn child_path(&self, dir: &Path, name: &str) -> PathBuf {
+        if dir == self.args.serve_path {
+            if let Some((_, root)) = self.args.mount_paths.iter().find(|(v, _)| v == name) {
+                return root.clone();
+            }
+        }
+        dir.join(name)
+    }
+
+    /// The name of the path relative to `base_path`, going through the mounts
+    /// if `base_path` is the serve path
+    fn relative_name(&self, path: &Path, base_path: &Path) -> Result<String> {
+        if base_path == self.args.serve_path {
+            for (name, root) in self.args.mount_paths.iter() {
+                if let Ok(rest) = path.strip_prefix(root) {
+                    if rest.as_os_str().is_empty() {
+                        return Ok(name.clone());
+                    }
+                    return Ok(normalize_path(Path::new(name).join(rest)));
+                }
+            }
+        }
+        Ok(normalize_path(path.strip_prefix(base_path)?))
     }
 
     async fn list_dir(
@@ -1158,28 +5601,171 @@ impl Server {
         access_paths: AccessPaths,
     ) -> Result<Vec<PathItem>> {
         let mut paths: Vec<PathItem> = vec![];
+        let is_root = entry_path == self.args.serve_path;
         if access_paths.perm().indexonly() {
             for name in access_paths.child_paths() {
-                let entry_path = entry_path.join(name);
+                let entry_path = self.child_path(entry_path, name);
                 self.add_pathitem(&mut paths, base_path, &entry_path).await;
             }
         } else {
-            let mut rd = fs::read_dir(entry_path).await?;
-            while let Ok(Some(entry)) = rd.next_entry().await {
-                let entry_path = entry.path();
+            let children = match self.preload.as_ref().and_then(|v| v.children(entry_path)) {
+                Some(v) => v,
+                None => {
+                    let mut children = vec![];
+                    let mut rd = fs::read_dir(entry_path).await?;
+                    while let Ok(Some(entry)) = rd.next_entry().await {
+                        children.push(entry.path());
+                    }
+                    children
+                }
+            };
+            for entry_path in children {
+                if is_root && self.is_shadowed(&entry_path) {
+                    continue;
+                }
                 self.add_pathitem(&mut paths, base_path, &entry_path).await;
             }
+            if is_root {
+                for (_, root) in self.args.mount_paths.iter() {
+                    self.add_pathitem(&mut paths, base_path, root).await;
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Whether a page of the listing can be had without looking up every entry, which
+    /// takes it being sorted by name, folders first, and a plain folder
+    fn can_list_page(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        access_paths: &AccessPaths,
+    ) -> bool {
+        is_list_paged(query_params)
+            && list_depth(query_params) == 1
+            && matches!(
+                list_sort(query_params, &self.args.default_sort),
+                (SortBy::Name, _, None)
+            )
+            && !query_params.contains_key("gallery")
+            && !access_paths.perm().indexonly()
+            && (path != self.args.serve_path || self.args.mount_paths.is_empty())
+    }
+
+    /// A page of the entries of the folder sorted by name, along with how many entries
+    /// there are. Entries are sorted by their name and type, so only the ones on the page
+    /// are looked up.
+    async fn list_dir_page(
+        &self,
+        path: &Path,
+        filter: Option<&str>,
+        offset: usize,
+        limit: usize,
+        desc: bool,
+    ) -> Result<(Vec<PathItem>, usize)> {
+        let mut entries: Vec<(PathType, String)> = vec![];
+        let mut rd = fs::read_dir(path).await?;
+        while let Ok(Some(entry)) = rd.next_entry().await {
+            let name = match entry.file_name().into_string() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if filter.is_some_and(|v| !glob(v, &name)) {
+                continue;
+            }
+            let file_type = match entry.file_type().await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let path_type = if file_type.is_symlink() {
+                let entry_path = entry.path();
+                if !self.is_symlink_allowed(&entry_path).await {
+                    continue;
+                }
+                match fs::metadata(&entry_path).await {
+                    Ok(meta) if meta.is_dir() => PathType::SymlinkDir,
+                    Ok(_) => PathType::SymlinkFile,
+                    Err(_) => continue,
+                }
+            } else if file_type.is_dir() {
+                PathType::Dir
+            } else {
+                PathType::File
+            };
+            let is_dir = matches!(path_type, PathType::Dir | PathType::SymlinkDir);
+            if is_hidden(&self.hidden, self.args.posix_hidden, &name, is_dir) {
+                continue;
+            }
+            entries.push((path_type, name));
+        }
+        entries.sort_by(|(type1, name1), (type2, name2)| match type1.cmp(type2) {
+            Ordering::Equal => {
+                alphanumeric_sort::compare_str(name1.to_lowercase(), name2.to_lowercase())
+            }
+            v => v,
+        });
+        if desc {
+            entries.reverse();
+        }
+        let total = entries.len();
+        let mut paths = vec![];
+        for (_, name) in entries.into_iter().skip(offset).take(limit) {
+            if let Ok(Some(item)) = self.to_pathitem(path.join(name), path.to_path_buf()).await {
+                paths.push(item);
+            }
+        }
+        Ok((paths, total))
+    }
+
+    /// The entries of the folder and of its subfolders down to `depth` levels, named
+    /// relative to the folder. Symlinked folders aren't followed.
+    async fn list_tree(
+        &self,
+        path: &Path,
+        access_paths: AccessPaths,
+        depth: usize,
+    ) -> Result<Vec<PathItem>> {
+        let mut paths = vec![];
+        let mut dirs = VecDeque::from([(path.to_path_buf(), access_paths, 1)]);
+        while let Some((dir, access_paths, level)) = dirs.pop_front() {
+            if !self.running.load(atomic::Ordering::SeqCst) {
+                break;
+            }
+            let items = match self.list_dir(&dir, path, access_paths.clone()).await {
+                Ok(v) => v,
+                Err(err) if level == 1 => return Err(err),
+                Err(_) => continue,
+            };
+            for item in items {
+                if item.path_type == PathType::Dir && level < depth {
+                    let child_access = access_paths
+                        .find(item.base_name(), false)
+                        .filter(|v| !v.perm().uploadonly());
+                    if let Some(child_access) = child_access {
+                        dirs.push_back((path.join(&item.name), child_access, level + 1));
+                    }
+                }
+                paths.push(item);
+            }
         }
         Ok(paths)
     }
 
+    /// Whether the entry of the root is shadowed by a mount of the same name
+    fn is_shadowed(&self, entry_path: &Path) -> bool {
+        self.args
+            .mount_paths
+            .iter()
+            .any(|(v, _)| entry_path.file_name() == Some(v.as_ref()))
+    }
+
     async fn add_pathitem(&self, paths: &mut Vec<PathItem>, base_path: &Path, entry_path: &Path) {
-        let base_name = get_file_name(entry_path);
         if let Ok(Some(item)) = self.to_pathitem(entry_path, base_path).await {
             if is_hidden(
-                &self.args.hidden,
+                &self.hidden,
                 self.args.posix_hidden,
-                base_name,
+                item.base_name(),
                 item.is_dir(),
             ) {
                 return;
@@ -1190,10 +5776,18 @@ impl Server {
 
     async fn to_pathitem<P: AsRef<Path>>(&self, path: P, base_path: P) -> Result<Option<PathItem>> {
         let path = path.as_ref();
-        let (meta, meta2) = tokio::join!(fs::metadata(&path), fs::symlink_metadata(&path));
-        let (meta, meta2) = (meta?, meta2?);
-        let is_symlink = meta2.is_symlink();
-        if !self.args.allow_symlink && is_symlink && !self.is_root_contained(path).await {
+        let (meta, is_symlink) = match self.preload.as_ref().and_then(|v| v.get(path)) {
+            Some(entry) => (entry.meta, entry.is_symlink),
+            None => match &self.meta_cache {
+                Some(meta_cache) => meta_cache.metadata(path).await?,
+                None => {
+                    let (meta, meta2) =
+                        tokio::join!(fs::metadata(&path), fs::symlink_metadata(&path));
+                    (meta?, meta2?.is_symlink())
+                }
+            },
+        };
+        if is_symlink && !self.is_symlink_allowed(path).await {
             return Ok(None);
         }
         let is_dir = meta.is_dir();
@@ -1204,30 +5798,94 @@ impl Server {
             (false, false) => PathType::File,
         };
         let mtime = to_timestamp(&meta.modified()?);
-        let size = match path_type {
-            PathType::Dir | PathType::SymlinkDir => {
-                if self.args.dir_size {
-                    Some(get_size(fs::canonicalize(Path::new(path)).await?)?) // this ignores authorizations and filters, and just does a blind sum
-                } else {
-                    None
-                }
+        let ctime = meta.created().map(|v| to_timestamp(&v)).unwrap_or(mtime);
+        let (size, files) = match path_type {
+            // This doesn't consider the permissions of the subfolders
+            PathType::Dir | PathType::SymlinkDir if self.args.dir_size => {
+                let dir = fs::canonicalize(path).await?;
+                let usage = self.dir_usage.usage(&[dir]).await?;
+                (Some(usage.size), Some(usage.files))
             }
-            PathType::File | PathType::SymlinkFile => Some(meta.len()),
+            PathType::Dir | PathType::SymlinkDir => (None, None),
+            PathType::File | PathType::SymlinkFile => (Some(meta.len()), None),
+        };
+        let name = self.relative_name(path, base_path.as_ref())?;
+        let media_type = if is_dir { None } else { media_type(&name) };
+        #[cfg(unix)]
+        let (mode, uid) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(meta.mode() & 0o7777), Some(meta.uid()))
         };
-        let rel_path = path.strip_prefix(base_path)?;
-        let name = normalize_path(rel_path);
+        #[cfg(not(unix))]
+        let (mode, uid) = (None, None);
         Ok(Some(PathItem {
             path_type,
             name,
             mtime,
+            ctime,
             size,
+            files,
+            media_type,
+            checksum: None,
+            mode,
+            uid,
+            full_meta: None,
         }))
     }
 }
 
+/// The entry of the bucket as a path of the listings
+fn bucket_pathitem(name: String, entry: &Entry) -> PathItem {
+    let (path_type, size, media_type) = match entry.is_dir {
+        true => (PathType::Dir, None, None),
+        false => (PathType::File, Some(entry.size), media_type(&name)),
+    };
+    PathItem {
+        path_type,
+        name,
+        mtime: entry.mtime,
+        ctime: entry.mtime,
+        size,
+        files: None,
+        media_type,
+        checksum: None,
+        mode: None,
+        uid: None,
+        full_meta: None,
+    }
+}
+
+/// The user a frontend besides HTTP logged in, on the requests it makes with `change`.
+/// Clients can't set extensions, so it can't be forged over HTTP.
+#[derive(Debug, Clone)]
+struct LoggedIn(Option<String>);
+
+/// The user and paths the client extracting an archive was granted, on the requests made for
+/// its entries, as a JWT grants ones the user name alone doesn't
+#[derive(Debug, Clone)]
+struct Granted(Option<String>, Arc<AccessPaths>);
+
+/// The `Content-Range` of a partial upload
+#[derive(Debug, PartialEq)]
+enum UploadRange {
+    /// The first and last byte the body is written to
+    Bytes(u64, u64),
+    /// `bytes */<size>` without a body, asking whether the file is that long, or else how
+    /// long it is to resume from there
+    Unsatisfied(u64),
+}
+
+/// Where the bytes of a file are sent from
+enum FileSource {
+    /// Preloaded
+    Memory(Bytes),
+    Disk(File),
+}
+
 #[derive(Debug, Serialize, PartialEq)]
 enum DataKind {
     Index,
+    Gallery,
     Edit,
     View,
 }
@@ -1241,10 +5899,16 @@ struct IndexData {
     allow_delete: bool,
     allow_search: bool,
     allow_archive: bool,
+    archive_progress: bool,
+    confirm_rm_dir: bool,
     dir_exists: bool,
+    upload_only: bool,
     auth: bool,
+    auth_form: bool,
     user: Option<String>,
     paths: Vec<PathItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    readme: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -1255,16 +5919,178 @@ struct EditData {
     allow_upload: bool,
     allow_delete: bool,
     auth: bool,
+    auth_form: bool,
     user: Option<String>,
     editable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    /// The file as HTML, with its syntax highlighted, for read-only views
+    #[serde(skip_serializing_if = "Option::is_none")]
+    highlighted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<&'static str>,
+    /// Seconds, of the videos and audios whose duration could be probed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
 }
 
-#[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
-struct PathItem {
-    path_type: PathType,
+/// The routes of the JSON API, under `__dufs__/api/v1/`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ApiOp {
+    List,
+    Stat,
+    Search,
+    Hash,
+    Diff,
+    Mkdir,
+    Delete,
+    Move,
+    Copy,
+}
+
+impl ApiOp {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "list" => Some(Self::List),
+            "stat" => Some(Self::Stat),
+            "search" => Some(Self::Search),
+            "hash" => Some(Self::Hash),
+            "diff" => Some(Self::Diff),
+            "mkdir" => Some(Self::Mkdir),
+            "delete" => Some(Self::Delete),
+            "move" => Some(Self::Move),
+            "copy" => Some(Self::Copy),
+            _ => None,
+        }
+    }
+
+    /// The method the route is requested with
+    fn method(&self) -> Method {
+        match self.write_method() {
+            Some(_) => Method::POST,
+            None if matches!(self, Self::Diff) => Method::POST,
+            None => Method::GET,
+        }
+    }
+
+    /// The webdav method a write is carried out as, None for reads
+    fn write_method(&self) -> Option<Method> {
+        match self {
+            Self::Mkdir => Method::from_bytes(b"MKCOL").ok(),
+            Self::Delete => Some(Method::DELETE),
+            Self::Move => Method::from_bytes(b"MOVE").ok(),
+            Self::Copy => Method::from_bytes(b"COPY").ok(),
+            Self::List | Self::Stat | Self::Search | Self::Hash | Self::Diff => None,
+        }
+    }
+}
+
+/// A file of the manifest posted to the `diff` route of the JSON API
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+    /// Relative to the folder
+    path: String,
+    size: u64,
+    /// Compared only when given, otherwise the sizes are
+    hash: Option<String>,
+}
+
+/// A file or folder as the JSON API describes it, independently of the UI's data
+#[derive(Debug, Serialize)]
+struct ApiEntry {
     name: String,
-    mtime: u64,
+    /// From the root, starting with `/`
+    path: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    symlink: bool,
+    /// In bytes, for files
     size: Option<u64>,
+    /// In milliseconds since the epoch
+    mtime: u64,
+}
+
+impl ApiEntry {
+    /// `dir` is the path, from the root, that the name of the item is relative to
+    fn new(item: PathItem, dir: &str) -> Self {
+        let path = match dir.is_empty() {
+            true => format!("/{}", item.name),
+            false => format!("/{dir}/{}", item.name),
+        };
+        let path = path.trim_end_matches('/').to_string();
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
+        let is_dir = item.is_dir();
+        Self {
+            name,
+            path: if path.is_empty() {
+                "/".to_string()
+            } else {
+                path
+            },
+            kind: if is_dir { "dir" } else { "file" },
+            symlink: matches!(item.path_type, PathType::SymlinkDir | PathType::SymlinkFile),
+            size: if is_dir { None } else { item.size },
+            mtime: item.mtime,
+        }
+    }
+}
+
+/// Errors of the JSON API are sent as `{"error": "..."}`
+async fn api_error_body(res: &mut Response) -> Result<()> {
+    let status = res.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return Ok(());
+    }
+    let body = hyper::body::to_bytes(std::mem::take(res.body_mut())).await?;
+    let message = match String::from_utf8_lossy(&body).trim() {
+        "" => status.canonical_reason().unwrap_or_default().to_string(),
+        v => v.to_string(),
+    };
+    let output = serde_json::json!({ "error": message }).to_string();
+    res.headers_mut()
+        .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+    res.headers_mut()
+        .typed_insert(ContentLength(output.len() as u64));
+    *res.body_mut() = output.into();
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) struct PathItem {
+    path_type: PathType,
+    pub(crate) name: String,
+    /// Milliseconds since the epoch
+    pub(crate) mtime: u64,
+    #[serde(skip)]
+    ctime: u64,
+    pub(crate) size: Option<u64>,
+    /// How many files a folder holds, with `--dir-size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<&'static str>,
+    /// The digest of the file named by its algorithm, with `?json&hash=<algo>`
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    checksum: Option<BTreeMap<&'static str, String>>,
+    /// The permission bits and owner id, where the OS has them
+    #[serde(skip)]
+    mode: Option<u32>,
+    #[serde(skip)]
+    uid: Option<u32>,
+    /// With `?json&meta=full`
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    full_meta: Option<FullMeta>,
+}
+
+#[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+struct FullMeta {
+    mtime_str: String,
+    /// In octal, like `0644`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    /// The name of the user, or their id when it has none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
 }
 
 impl PathItem {
@@ -1272,53 +6098,118 @@ impl PathItem {
         self.path_type == PathType::Dir || self.path_type == PathType::SymlinkDir
     }
 
-    pub fn to_dav_xml(&self, prefix: &str) -> String {
-        let mtime = match Utc.timestamp_millis_opt(self.mtime as i64) {
-            LocalResult::Single(v) => v.to_rfc2822(),
-            _ => String::new(),
-        };
+    fn to_dav_xml(&self, prefix: &str, propfind: &PropFind) -> String {
         let mut href = encode_uri(&format!("{}{}", prefix, &self.name));
         if self.is_dir() && !href.ends_with('/') {
             href.push('/');
         }
-        let displayname = escape_str_pcdata(self.base_name());
-        match self.path_type {
-            PathType::Dir | PathType::SymlinkDir => format!(
-                r#"<D:response>
+        let props = self.dav_props();
+        let propstats = match propfind {
+            PropFind::AllProp => {
+                let values: Vec<_> = props.iter().map(|(_, prop)| prop).collect();
+                dav_propstat(&values, "200 OK")
+            }
+            PropFind::PropName => {
+                let names: Vec<_> = props
+                    .iter()
+                    .map(|(name, _)| format!("<D:{name}/>"))
+                    .collect();
+                dav_propstat(&names, "200 OK")
+            }
+            PropFind::Prop(names) => {
+                let mut found = vec![];
+                let mut missing = vec![];
+                for (ns, name) in names {
+                    match props.iter().find(|(v, _)| ns == "DAV:" && v == name) {
+                        Some((_, prop)) => found.push(prop.clone()),
+                        None if ns == "DAV:" => missing.push(format!("<D:{name}/>")),
+                        None => missing
+                            .push(format!(r#"<{name} xmlns="{}"/>"#, escape_str_attribute(ns))),
+                    }
+                }
+                let mut output = String::new();
+                if !found.is_empty() || missing.is_empty() {
+                    output.push_str(&dav_propstat(&found, "200 OK"));
+                }
+                if !missing.is_empty() {
+                    output.push_str(&dav_propstat(&missing, "404 Not Found"));
+                }
+                output
+            }
+        };
+        format!(
+            r#"<D:response>
 <D:href>{href}</D:href>
-<D:propstat>
-<D:prop>
-<D:displayname>{displayname}</D:displayname>
-<D:getlastmodified>{mtime}</D:getlastmodified>
-<D:resourcetype><D:collection/></D:resourcetype>
-</D:prop>
-<D:status>HTTP/1.1 200 OK</D:status>
-</D:propstat>
-</D:response>"#
-            ),
-            PathType::File | PathType::SymlinkFile => format!(
-                r#"<D:response>
-<D:href>{}</D:href>
-<D:propstat>
-<D:prop>
-<D:displayname>{}</D:displayname>
-<D:getcontentlength>{}</D:getcontentlength>
-<D:getlastmodified>{}</D:getlastmodified>
-<D:resourcetype></D:resourcetype>
-</D:prop>
-<D:status>HTTP/1.1 200 OK</D:status>
-</D:propstat>
-</D:response>"#,
-                href,
-                displayname,
-                self.size.unwrap_or_default(),
-                mtime
-            ),
+{propstats}</D:response>"#
+        )
+    }
+
+    /// Live properties of the path, each is a pair of name and xml element
+    fn dav_props(&self) -> Vec<(&'static str, String)> {
+        let mtime = match Utc.timestamp_millis_opt(self.mtime as i64) {
+            LocalResult::Single(v) => v.to_rfc2822(),
+            _ => String::new(),
+        };
+        let ctime = match Utc.timestamp_millis_opt(self.ctime as i64) {
+            LocalResult::Single(v) => v.to_rfc3339_opts(SecondsFormat::Secs, true),
+            _ => String::new(),
+        };
+        let displayname = escape_str_pcdata(self.base_name());
+        let mut props = vec![(
+            "displayname",
+            format!("<D:displayname>{displayname}</D:displayname>"),
+        )];
+        if self.is_dir() {
+            props.extend([
+                (
+                    "getlastmodified",
+                    format!("<D:getlastmodified>{mtime}</D:getlastmodified>"),
+                ),
+                (
+                    "resourcetype",
+                    "<D:resourcetype><D:collection/></D:resourcetype>".to_string(),
+                ),
+            ]);
+        } else {
+            let size = self.size.unwrap_or_default();
+            let content_type = mime_guess::from_path(&self.name)
+                .first_or_octet_stream()
+                .to_string();
+            props.extend([
+                (
+                    "getcontentlength",
+                    format!("<D:getcontentlength>{size}</D:getcontentlength>"),
+                ),
+                (
+                    "getlastmodified",
+                    format!("<D:getlastmodified>{mtime}</D:getlastmodified>"),
+                ),
+                (
+                    "resourcetype",
+                    "<D:resourcetype></D:resourcetype>".to_string(),
+                ),
+                (
+                    "getetag",
+                    format!(
+                        "<D:getetag>{}</D:getetag>",
+                        escape_str_pcdata(&format!(r#""{}-{size}""#, self.mtime))
+                    ),
+                ),
+                (
+                    "getcontenttype",
+                    format!("<D:getcontenttype>{content_type}</D:getcontenttype>"),
+                ),
+            ]);
         }
+        props.push((
+            "creationdate",
+            format!("<D:creationdate>{ctime}</D:creationdate>"),
+        ));
+        props
     }
 
     pub fn base_name(&self) -> &str {
-        self.name.split('/').last().unwrap_or_default()
+        self.name.split('/').next_back().unwrap_or_default()
     }
 
     pub fn sort_by_name(&self, other: &Self) -> Ordering {
@@ -1330,71 +6221,332 @@ impl PathItem {
         }
     }
 
-    pub fn sort_by_mtime(&self, other: &Self) -> Ordering {
-        match self.path_type.cmp(&other.path_type) {
-            Ordering::Equal => self.mtime.cmp(&other.mtime),
-            v => v,
+    /// Compares the entries by the field alone, folders and files alike
+    fn compare(&self, other: &Self, by: SortBy) -> Ordering {
+        let by_name =
+            || alphanumeric_sort::compare_str(self.name.to_lowercase(), other.name.to_lowercase());
+        match by {
+            SortBy::Name => by_name(),
+            SortBy::Mtime => self.mtime.cmp(&other.mtime),
+            SortBy::Size if self.is_dir() && other.is_dir() => by_name(),
+            SortBy::Size => self.size.unwrap_or(0).cmp(&other.size.unwrap_or(0)),
+            SortBy::Ext => {
+                let ext = |v: &Self| match v.is_dir() {
+                    true => String::new(),
+                    false => v
+                        .base_name()
+                        .rsplit_once('.')
+                        .map(|(_, ext)| ext.to_lowercase())
+                        .unwrap_or_default(),
+                };
+                ext(self).cmp(&ext(other)).then_with(by_name)
+            }
         }
     }
+}
 
-    pub fn sort_by_size(&self, other: &Self) -> Ordering {
-        match self.path_type.cmp(&other.path_type) {
-            Ordering::Equal => {
-                if self.is_dir() {
-                    alphanumeric_sort::compare_str(
-                        self.name.to_lowercase(),
-                        other.name.to_lowercase(),
-                    )
-                } else {
-                    self.size.unwrap_or(0).cmp(&other.size.unwrap_or(0))
+/// What a PROPFIND request asks for
+#[derive(Debug, PartialEq)]
+enum PropFind {
+    AllProp,
+    PropName,
+    /// Pairs of namespace and name
+    Prop(Vec<(String, String)>),
+}
+
+impl PropFind {
+    fn parse(body: &[u8]) -> Result<Self> {
+        if body.iter().all(|v| v.is_ascii_whitespace()) {
+            return Ok(PropFind::AllProp);
+        }
+        let mut output = None;
+        let mut props = vec![];
+        let mut in_prop = false;
+        let mut depth = 0;
+        for event in EventReader::new(body) {
+            match event? {
+                XmlEvent::StartElement { name, .. } => {
+                    depth += 1;
+                    let is_dav = name.namespace.as_deref() == Some("DAV:");
+                    match (depth, is_dav, name.local_name.as_str()) {
+                        (1, true, "propfind") => {}
+                        (1, _, _) => bail!("Invalid propfind"),
+                        (2, true, "allprop") => output = Some(PropFind::AllProp),
+                        (2, true, "propname") => output = Some(PropFind::PropName),
+                        (2, true, "prop") => in_prop = true,
+                        (3, _, _) if in_prop => {
+                            props.push((name.namespace.unwrap_or_default(), name.local_name));
+                        }
+                        _ => {}
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    if depth == 2 && in_prop {
+                        in_prop = false;
+                        output = Some(PropFind::Prop(std::mem::take(&mut props)));
+                    }
+                    depth -= 1;
                 }
+                _ => {}
             }
-            v => v,
         }
+        output.ok_or_else(|| anyhow!("Invalid propfind"))
+    }
+}
+
+/// A sync-collection REPORT request
+#[derive(Debug, PartialEq)]
+struct SyncCollection {
+    /// `None` for the initial synchronization
+    token: Option<String>,
+    infinite: bool,
+    propfind: PropFind,
+}
+
+impl SyncCollection {
+    /// Returns `None` for any other kind of report
+    fn parse(body: &[u8]) -> Result<Option<Self>> {
+        let mut token = None;
+        let mut level = None;
+        let mut props = None;
+        let mut text = String::new();
+        let mut depth = 0;
+        for event in EventReader::new(body) {
+            match event? {
+                XmlEvent::StartElement { name, .. } => {
+                    depth += 1;
+                    text.clear();
+                    let is_dav = name.namespace.as_deref() == Some("DAV:");
+                    match (depth, is_dav, name.local_name.as_str()) {
+                        (1, true, "sync-collection") => {}
+                        (1, _, _) => return Ok(None),
+                        (2, true, "prop") => props = Some(vec![]),
+                        (3, _, _) => {
+                            if let Some(props) = props.as_mut() {
+                                props.push((name.namespace.unwrap_or_default(), name.local_name));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                XmlEvent::Characters(v) => text.push_str(&v),
+                XmlEvent::EndElement { name } => {
+                    if depth == 2 && name.namespace.as_deref() == Some("DAV:") {
+                        match name.local_name.as_str() {
+                            "sync-token" => token = Some(text.trim().to_string()),
+                            "sync-level" => level = Some(text.trim().to_string()),
+                            _ => {}
+                        }
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        let infinite = match level.as_deref() {
+            Some("1") => false,
+            Some("infinite") => true,
+            _ => bail!("Invalid sync-level"),
+        };
+        let props = props.ok_or_else(|| anyhow!("Missing prop"))?;
+        Ok(Some(Self {
+            token: token.filter(|v| !v.is_empty()),
+            infinite,
+            propfind: PropFind::Prop(props),
+        }))
+    }
+}
+
+#[derive(Debug, Serialize, Eq, PartialEq)]
+enum PathType {
+    Dir,
+    SymlinkDir,
+    File,
+    SymlinkFile,
+}
+
+impl Ord for PathType {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let to_value = |t: &Self| -> u8 {
+            if matches!(t, Self::Dir | Self::SymlinkDir) {
+                0
+            } else {
+                1
+            }
+        };
+        to_value(self).cmp(&to_value(other))
+    }
+}
+impl PartialOrd for PathType {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn to_timestamp(time: &SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn normalize_path<P: AsRef<Path>>(path: P) -> String {
+    let path = path.as_ref().to_str().unwrap_or_default();
+    if cfg!(windows) {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    }
+}
+
+async fn copy_with_digest<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    verifier: &mut DigestVerifier,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; BUF_SIZE];
+    let mut written = 0;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        verifier.update(&buf[..n]);
+        writer.write_all(&buf[..n]).await?;
+        written += n as u64;
+    }
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Check the destination of COPY/MOVE according to the `Overwrite` header.
+///
+/// Returns whether there's an existing destination to replace, or `None` if the request is
+/// rejected. Nothing is removed here, see `replace_dest`.
+async fn prepare_dest(
+    src: &Path,
+    dest: &Path,
+    headers: &HeaderMap<HeaderValue>,
+    res: &mut Response,
+) -> Result<Option<bool>> {
+    let overwrite = match headers.get("overwrite").map(|v| v.as_bytes()) {
+        None | Some(b"T") | Some(b"t") => true,
+        Some(b"F") | Some(b"f") => false,
+        _ => {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(None);
+        }
+    };
+    // Into itself, or over a folder it's in
+    if dest.starts_with(src) || src.starts_with(dest) {
+        status_forbid(res);
+        return Ok(None);
+    }
+    if fs::symlink_metadata(dest).await.is_err() {
+        return Ok(Some(false));
+    }
+    if !overwrite {
+        *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+        return Ok(None);
+    }
+    Ok(Some(true))
+}
+
+/// A hidden sibling of the path, to stage an entry at or set one aside
+fn staging_path(path: &Path, kind: &str) -> PathBuf {
+    path.with_file_name(format!(
+        ".{}.{}.dufs-{kind}",
+        get_file_name(path),
+        Uuid::new_v4().simple()
+    ))
+}
+
+async fn remove_entry(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await?;
+    } else {
+        fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// The body read into memory, or `None` if it's larger than the limit
+async fn read_body_limited(mut body: Body, limit: usize) -> Result<Option<Bytes>> {
+    let mut output = vec![];
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        if output.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        output.extend_from_slice(&chunk);
     }
+    Ok(Some(output.into()))
 }
 
-#[derive(Debug, Serialize, Eq, PartialEq)]
-enum PathType {
-    Dir,
-    SymlinkDir,
-    File,
-    SymlinkFile,
+/// The size of a file, or of all the files under a folder
+async fn entry_size(path: &Path) -> Result<u64> {
+    let path = path.to_path_buf();
+    Ok(tokio::task::spawn_blocking(move || fs_extra::dir::get_size(path)).await??)
 }
 
-impl Ord for PathType {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let to_value = |t: &Self| -> u8 {
-            if matches!(t, Self::Dir | Self::SymlinkDir) {
-                0
-            } else {
-                1
-            }
+/// Fills in the readable mtime, mode and owner of the listed paths, with `?json&meta=full`
+fn add_full_meta(paths: &mut [PathItem]) {
+    let mut owners: HashMap<u32, String> = HashMap::new();
+    for item in paths.iter_mut() {
+        let mtime_str = match Utc.timestamp_millis_opt(item.mtime as i64) {
+            LocalResult::Single(v) => v.to_rfc3339_opts(SecondsFormat::Millis, true),
+            _ => String::new(),
         };
-        to_value(self).cmp(&to_value(other))
-    }
-}
-impl PartialOrd for PathType {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+        let owner = item.uid.map(|uid| {
+            owners
+                .entry(uid)
+                .or_insert_with(|| {
+                    #[cfg(unix)]
+                    let name = crate::unix::user_name(uid);
+                    #[cfg(not(unix))]
+                    let name = None;
+                    name.unwrap_or_else(|| uid.to_string())
+                })
+                .clone()
+        });
+        item.full_meta = Some(FullMeta {
+            mtime_str,
+            mode: item.mode.map(|v| format!("{v:04o}")),
+            owner,
+        });
     }
 }
 
-fn to_timestamp(time: &SystemTime) -> u64 {
-    time.duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis() as u64
+/// The separator of the `?csv` or `?tsv` export of a listing
+fn table_separator(query_params: &HashMap<String, String>) -> Option<&'static str> {
+    if query_params.contains_key("csv") {
+        Some(",")
+    } else if query_params.contains_key("tsv") {
+        Some("\t")
+    } else {
+        None
+    }
 }
 
-fn normalize_path<P: AsRef<Path>>(path: P) -> String {
-    let path = path.as_ref().to_str().unwrap_or_default();
-    if cfg!(windows) {
-        path.replace('\\', "/")
+/// The value quoted if it holds the separator, a quote or a line break, as spreadsheets read it
+fn table_field(value: &str, sep: &str) -> String {
+    if value.contains(sep) || value.contains(['"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
     } else {
-        path.to_string()
+        value.to_string()
     }
 }
 
+async fn file_exists(path: &Path) -> bool {
+    fs::metadata(path)
+        .await
+        .map(|v| v.is_file())
+        .unwrap_or_default()
+}
+
 async fn ensure_path_parent(path: &Path) -> Result<()> {
     if let Some(parent) = path.parent() {
         if fs::symlink_metadata(parent).await.is_err() {
@@ -1404,23 +6556,48 @@ async fn ensure_path_parent(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn add_cors(res: &mut Response) {
-    res.headers_mut()
-        .typed_insert(AccessControlAllowOrigin::ANY);
-    res.headers_mut()
-        .typed_insert(AccessControlAllowCredentials);
-    res.headers_mut().insert(
-        "Access-Control-Allow-Methods",
-        HeaderValue::from_static("*"),
-    );
-    res.headers_mut().insert(
-        "Access-Control-Allow-Headers",
-        HeaderValue::from_static("Authorization,*"),
-    );
+/// Turns a successful response into 304 if the client has it already, going by its ETag.
+/// Files are answered 304 before they're opened, this is for the generated responses.
+fn not_modified(res: &mut Response, if_none_match: &IfNoneMatch) {
+    if res.status() != StatusCode::OK {
+        return;
+    }
+    if let Some(etag) = res.headers().typed_get::<ETag>() {
+        if !if_none_match.precondition_passes(&etag) {
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            *res.body_mut() = Body::empty();
+            res.headers_mut().remove(CONTENT_LENGTH);
+            res.headers_mut().remove(CONTENT_TYPE);
+        }
+    }
+}
+
+fn dav_propstat<T: AsRef<str>>(props: &[T], status: &str) -> String {
+    let props = props
+        .iter()
+        .map(|v| format!("{}\n", v.as_ref()))
+        .collect::<String>();
+    format!(
+        r#"<D:propstat>
+<D:prop>
+{props}</D:prop>
+<D:status>HTTP/1.1 {status}</D:status>
+</D:propstat>
+"#
+    )
+}
+
+/// Reject with a WebDAV precondition, e.g. `propfind-finite-depth`
+fn res_dav_error(res: &mut Response, condition: &str) {
+    *res.status_mut() = StatusCode::FORBIDDEN;
     res.headers_mut().insert(
-        "Access-Control-Expose-Headers",
-        HeaderValue::from_static("Authorization,*"),
+        "content-type",
+        HeaderValue::from_static("application/xml; charset=utf-8"),
     );
+    *res.body_mut() = Body::from(format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:error xmlns:D="DAV:"><D:{condition}/></D:error>"#
+    ));
 }
 
 fn res_multistatus(res: &mut Response, content: &str) {
@@ -1437,25 +6614,150 @@ fn res_multistatus(res: &mut Response, content: &str) {
     ));
 }
 
+/// Runs on a blocking thread, so the files are read with blocking calls
 async fn zip_dir<W: AsyncWrite + Unpin>(
     writer: &mut W,
     dir: &Path,
+    zip_paths: Vec<PathBuf>,
+    no_zip64: bool,
+    mut tracker: Option<Tracker>,
+    job: &Job,
+) -> Result<()> {
+    let mut writer = ZipFileWriter::with_tokio(writer);
+    if no_zip64 {
+        // Fails the archive if the files grew past the limits since they were sized up
+        writer = writer.force_no_zip64();
+    }
+    let mut buf = vec![0; BUF_SIZE];
+    for zip_path in zip_paths.into_iter() {
+        if !job.is_running() {
+            return Ok(());
+        }
+        let filename = match zip_path.strip_prefix(dir).ok().and_then(|v| v.to_str()) {
+            Some(v) => v,
+            None => continue,
+        };
+        let mut file = std::fs::File::open(&zip_path)?;
+        let meta = file.metadata()?;
+        let (datetime, mode) = mtime_and_mode(&meta)?;
+        // Deflating nothing would only cost setting up a compressor
+        let compression = match meta.len() {
+            0 => Compression::Stored,
+            _ => Compression::Deflate,
+        };
+        let builder = ZipEntryBuilder::new(filename.into(), compression)
+            .unix_permissions(mode)
+            .last_modification_date(ZipDateTime::from_chrono(&datetime));
+        let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
+        loop {
+            let n = std::io::Read::read(&mut file, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if let Some(tracker) = tracker.as_mut() {
+                tracker.add(n as u64);
+            }
+            file_writer.write_all(&buf[..n]).await?;
+        }
+        file_writer.into_inner().close().await?;
+    }
+    writer.close().await?;
+    Ok(())
+}
+
+/// The bytes of the files in all, those gone since they were listed count for nothing
+async fn archive_size(paths: &[PathBuf]) -> u64 {
+    let mut size = 0;
+    for path in paths {
+        if let Ok(meta) = tokio::fs::metadata(path).await {
+            size += meta.len();
+        }
+    }
+    size
+}
+
+/// Why a zip archive of the files can't do without Zip64, if it can't.
+///
+/// Sizes are bounded from above, as deflate can grow incompressible data a little.
+async fn zip64_needed(dir: &Path, zip_paths: &[PathBuf]) -> Option<String> {
+    if zip_paths.len() > ZIP_MAX_ENTRIES {
+        return Some(format!(
+            "it has {} entries, more than {ZIP_MAX_ENTRIES}",
+            zip_paths.len()
+        ));
+    }
+    let mut offset: u64 = 0;
+    let mut directory_size: u64 = 0;
+    for zip_path in zip_paths {
+        let name_len = match zip_path.strip_prefix(dir).ok().and_then(|v| v.to_str()) {
+            Some(v) => v.len() as u64,
+            None => continue,
+        };
+        let size = match tokio::fs::metadata(zip_path).await {
+            Ok(v) => v.len(),
+            Err(_) => continue,
+        };
+        if size > ZIP_MAX_SIZE {
+            let name = zip_path.strip_prefix(dir).unwrap_or(zip_path);
+            return Some(format!("`{}` is over 4GB", name.display()));
+        }
+        // Local header, data and data descriptor
+        offset += 30 + name_len + deflate_bound(size) + 16;
+        directory_size += 46 + name_len;
+    }
+    if offset + directory_size > ZIP_MAX_SIZE {
+        return Some("it would be over 4GB".to_string());
+    }
+    None
+}
+
+/// The most deflate can make of data this size, in stored blocks at worst
+fn deflate_bound(size: u64) -> u64 {
+    size + (size / 16383 + 1) * 5
+}
+
+async fn tar_dir<W: AsyncWrite + Unpin + Send + 'static>(
+    writer: W,
+    dir: &Path,
     access_paths: AccessPaths,
     hidden: &[String],
-    running: Arc<AtomicBool>,
+    job: Job,
     posix_hidden: bool,
 ) -> Result<()> {
-    let mut writer = ZipFileWriter::with_tokio(writer);
+    let mut builder = TarBuilder::new(writer);
+    let tar_paths = archive_paths(dir, access_paths, hidden, job.clone(), posix_hidden).await?;
+    for tar_path in tar_paths.into_iter() {
+        if !job.is_running() {
+            return Ok(());
+        }
+        let filename = match tar_path.strip_prefix(dir).ok() {
+            Some(v) => v,
+            None => continue,
+        };
+        // The header is built from the file's metadata, so mode and mtime are kept.
+        builder.append_path_with_name(&tar_path, filename).await?;
+    }
+    let mut writer = builder.into_inner().await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+async fn archive_paths(
+    dir: &Path,
+    access_paths: AccessPaths,
+    hidden: &[String],
+    job: Job,
+    posix_hidden: bool,
+) -> Result<Vec<PathBuf>> {
     let hidden = Arc::new(hidden.to_vec());
-    let hidden = hidden.clone();
-    let dir_clone = dir.to_path_buf();
-    let zip_paths = tokio::task::spawn_blocking(move || {
+    let dir = dir.to_path_buf();
+    let paths = tokio::task::spawn_blocking(move || {
         let mut paths: Vec<PathBuf> = vec![];
-        for dir in access_paths.leaf_paths(&dir_clone) {
+        for dir in access_paths.leaf_paths(&dir) {
             let mut it = WalkDir::new(&dir).into_iter();
             it.next();
             while let Some(Ok(entry)) = it.next() {
-                if !running.load(atomic::Ordering::SeqCst) {
+                if !job.is_running() {
                     break;
                 }
                 let entry_path = entry.path();
@@ -1490,22 +6792,65 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
         paths
     })
     .await?;
-    for zip_path in zip_paths.into_iter() {
-        let filename = match zip_path.strip_prefix(dir).ok().and_then(|v| v.to_str()) {
-            Some(v) => v,
-            None => continue,
+    Ok(paths)
+}
+
+/// The order asked for with `sort` and `order`, or the default one, and whether
+/// folders go first with `dirs_first`
+fn list_sort(
+    query_params: &HashMap<String, String>,
+    default_sort: &DefaultSort,
+) -> (SortBy, bool, Option<bool>) {
+    let by = query_params.get("sort").map(|v| SortBy::parse(v));
+    let desc = match query_params.get("order") {
+        Some(order) => order == "desc",
+        None => by.is_none() && default_sort.desc,
+    };
+    let by = match by {
+        Some(by) => by.unwrap_or_default(),
+        None => default_sort.by,
+    };
+    let dirs_first = query_params
+        .get("dirs_first")
+        .map(|v| v.is_empty() || v == "true" || v == "1");
+    (by, desc, dirs_first)
+}
+
+/// Sorts the entries by the `sort` and `order` params, by the default sort otherwise.
+///
+/// Folders come before files, or after them when descending, unless `dirs_first` says
+/// to keep them first or to mix them with the files.
+fn sort_paths(
+    paths: &mut [PathItem],
+    query_params: &HashMap<String, String>,
+    default_sort: &DefaultSort,
+) {
+    let (by, desc, dirs_first) = list_sort(query_params, default_sort);
+    paths.sort_by(|v1, v2| {
+        let order = match desc {
+            true => v1.compare(v2, by).reverse(),
+            false => v1.compare(v2, by),
         };
-        let (datetime, mode) = get_file_mtime_and_mode(&zip_path).await?;
-        let builder = ZipEntryBuilder::new(filename.into(), Compression::Deflate)
-            .unix_permissions(mode)
-            .last_modification_date(ZipDateTime::from_chrono(&datetime));
-        let mut file = File::open(&zip_path).await?;
-        let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
-        io::copy(&mut file, &mut file_writer).await?;
-        file_writer.into_inner().close().await?;
+        match dirs_first {
+            None if desc => v2.path_type.cmp(&v1.path_type).then(order),
+            Some(false) => order,
+            _ => v1.path_type.cmp(&v2.path_type).then(order),
+        }
+    });
+}
+
+/// A weak ETag of a generated response, from the digest of its parts
+fn digest_etag(parts: &[&[u8]]) -> ETag {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
     }
-    writer.close().await?;
-    Ok(())
+    let digest: String = hasher.finalize()[..16]
+        .iter()
+        .map(|v| format!("{v:02x}"))
+        .collect();
+    format!(r#"W/"{digest}""#).parse().unwrap()
 }
 
 fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
@@ -1517,34 +6862,124 @@ fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
     Some((etag, last_modified))
 }
 
-#[derive(Debug)]
-struct RangeValue {
-    start: u64,
-    end: Option<u64>,
-}
-
-fn parse_range(headers: &HeaderMap<HeaderValue>) -> Option<RangeValue> {
-    let range_hdr = headers.get(RANGE)?;
-    let hdr = range_hdr.to_str().ok()?;
-    let mut sp = hdr.splitn(2, '=');
-    let units = sp.next()?;
-    if units == "bytes" {
-        let range = sp.next()?;
-        let mut sp_range = range.splitn(2, '-');
-        let start: u64 = sp_range.next()?.parse().ok()?;
-        let end: Option<u64> = if let Some(end) = sp_range.next() {
-            if end.is_empty() {
-                None
-            } else {
-                Some(end.parse().ok()?)
-            }
+/// Whether the `If-Match` and `If-Unmodified-Since` preconditions of a write hold for
+/// the current state of the path, so that changes made meanwhile aren't overwritten
+/// Answers 304 if the copy the client has is fresh and returns `None`, otherwise sets the
+/// validators of the file and returns whether the `Range` of the request may be served
+fn set_cache_headers(
+    headers: &HeaderMap<HeaderValue>,
+    etag: ETag,
+    last_modified: LastModified,
+    res: &mut Response,
+) -> Option<bool> {
+    let cached = {
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            !if_none_match.precondition_passes(&etag)
+        } else if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
+            !if_modified_since.is_modified(last_modified.into())
         } else {
-            None
-        };
-        Some(RangeValue { start, end })
+            false
+        }
+    };
+    if cached {
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+        return None;
+    }
+
+    res.headers_mut().typed_insert(last_modified);
+    res.headers_mut().typed_insert(etag.clone());
+
+    if headers.typed_get::<Range>().is_some() {
+        Some(
+            headers
+                .typed_get::<IfRange>()
+                .map(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified)))
+                // Always be fresh if there is no validators
+                .unwrap_or(true),
+        )
     } else {
-        None
+        Some(false)
+    }
+}
+
+fn write_preconditions_pass(headers: &HeaderMap<HeaderValue>, meta: Option<&Metadata>) -> bool {
+    let validators = meta.and_then(extract_cache_headers);
+    if let Some(if_match) = headers.typed_get::<IfMatch>() {
+        return match (meta, validators) {
+            (Some(_), Some((etag, _))) => if_match.precondition_passes(&etag),
+            (Some(_), None) => if_match.is_any(),
+            (None, _) => false,
+        };
+    }
+    match (headers.typed_get::<IfUnmodifiedSince>(), validators) {
+        (Some(if_unmodified_since), Some((_, last_modified))) => {
+            if_unmodified_since.precondition_passes(last_modified.into())
+        }
+        _ => true,
+    }
+}
+
+/// Parse `Range: bytes=<ranges>` against the size of the file into inclusive bounds.
+///
+/// None if there is no valid header, so the whole file is sent; an empty list if none
+/// of the ranges is satisfiable.
+fn parse_ranges(headers: &HeaderMap<HeaderValue>, size: u64) -> Option<Vec<(u64, u64)>> {
+    let value = headers.get(RANGE)?.to_str().ok()?;
+    let specs = value.trim().strip_prefix("bytes=")?;
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+    let mut ranges = vec![];
+    for spec in specs {
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // The last bytes of the file
+            let len: u64 = end.parse().ok()?;
+            if len > 0 && size > 0 {
+                ranges.push((size - len.min(size), size - 1));
+            }
+            continue;
+        }
+        let start: u64 = start.parse().ok()?;
+        let end: Option<u64> = match end.is_empty() {
+            true => None,
+            false => Some(end.parse().ok()?),
+        };
+        if end.is_some_and(|end| end < start) {
+            return None;
+        }
+        if start < size {
+            ranges.push((start, end.unwrap_or(u64::MAX).min(size - 1)));
+        }
+    }
+    Some(ranges)
+}
+
+/// Parse `Content-Range: bytes <start>-<end>/<size>` of a partial upload, where the size may
+/// be `*`, or `bytes */<size>`
+fn parse_content_range(value: &HeaderValue) -> Option<UploadRange> {
+    let value = value.to_str().ok()?.strip_prefix("bytes ")?;
+    let (range, total) = value.split_once('/')?;
+    let total: Option<u64> = match total.trim() {
+        "*" => None,
+        v => Some(v.parse().ok()?),
+    };
+    if range.trim() == "*" {
+        return total.map(UploadRange::Unsatisfied);
     }
+    let (start, end) = range.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    if end < start || total.is_some_and(|v| end >= v) {
+        return None;
+    }
+    Some(UploadRange::Bytes(start, end))
 }
 
 fn status_forbid(res: &mut Response) {
@@ -1557,10 +6992,107 @@ fn status_not_found(res: &mut Response) {
     *res.body_mut() = Body::from("Not Found");
 }
 
+/// What a request body fails with once it's larger than `--max-body-size`, or nothing of
+/// it has come for `--read-timeout`
+#[derive(Debug)]
+enum BodyError {
+    TooLarge,
+    TimedOut,
+}
+
+impl BodyError {
+    /// The body error the error comes down to, if any
+    fn find(err: &anyhow::Error) -> Option<&Self> {
+        err.chain().find_map(|v| v.downcast_ref::<Self>())
+    }
+
+    fn respond(&self, res: &mut Response) {
+        match self {
+            Self::TooLarge => status_payload_too_large(res),
+            Self::TimedOut => {
+                *res.status_mut() = StatusCode::REQUEST_TIMEOUT;
+                *res.body_mut() = Body::from("Request Timeout");
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::TooLarge => f.write_str("Request body too large"),
+            Self::TimedOut => f.write_str("Request body timed out"),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+fn status_payload_too_large(res: &mut Response) {
+    *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    *res.body_mut() = Body::from("Payload Too Large");
+}
+
+/// What is asked of object storage that only works on a local filesystem
+fn status_not_implemented(res: &mut Response) {
+    *res.status_mut() = StatusCode::NOT_IMPLEMENTED;
+    *res.body_mut() = Body::from("Not supported on object storage");
+}
+
+fn status_too_many_requests(res: &mut Response, retry_after: u64) {
+    *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    *res.body_mut() = Body::from("Too Many Requests");
+}
+
+fn status_service_unavailable(res: &mut Response, retry_after: u64) {
+    *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    *res.body_mut() = Body::from("Service Unavailable");
+}
+
+fn status_unsupported_type(res: &mut Response, path: &Path) {
+    *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    *res.body_mut() = Body::from(format!(
+        "`{}` is not a type of file that can be uploaded",
+        get_file_name(path)
+    ));
+}
+
+fn status_insufficient_storage(res: &mut Response) {
+    *res.status_mut() = StatusCode::INSUFFICIENT_STORAGE;
+    *res.body_mut() = Body::from("Insufficient Storage");
+}
+
+/// How many bytes an upload adds to the disk usage, as far as the headers tell
+fn upload_growth(
+    headers: &HeaderMap<HeaderValue>,
+    offset: Option<u64>,
+    exist: bool,
+    size: u64,
+) -> u64 {
+    let len = headers
+        .typed_get::<ContentLength>()
+        .map(|v| v.0)
+        .unwrap_or_default();
+    let old_size = if exist { size } else { 0 };
+    (offset.unwrap_or(0) + len).saturating_sub(old_size)
+}
+
 fn status_no_content(res: &mut Response) {
     *res.status_mut() = StatusCode::NO_CONTENT;
 }
 
+fn status_copied(res: &mut Response, overwritten: bool) {
+    if overwritten {
+        status_no_content(res);
+    } else {
+        *res.status_mut() = StatusCode::CREATED;
+    }
+}
+
 fn set_content_disposition(res: &mut Response, inline: bool, filename: &str) -> Result<()> {
     let kind = if inline { "inline" } else { "attachment" };
     let value = if filename.is_ascii() {
@@ -1576,7 +7108,12 @@ fn set_content_disposition(res: &mut Response, inline: bool, filename: &str) ->
     Ok(())
 }
 
-fn is_hidden(hidden: &[String], posix_hidden: bool, file_name: &str, is_dir_type: bool) -> bool {
+pub fn is_hidden(
+    hidden: &[String],
+    posix_hidden: bool,
+    file_name: &str,
+    is_dir_type: bool,
+) -> bool {
     if posix_hidden && file_name.starts_with('.') {
         return true;
     }
@@ -1591,15 +7128,144 @@ fn is_hidden(hidden: &[String], posix_hidden: bool, file_name: &str, is_dir_type
     })
 }
 
+fn is_tar_query(query_params: &HashMap<String, String>) -> bool {
+    query_params.contains_key("tar") || query_params.contains_key("tar.gz")
+}
+
 fn set_webdav_headers(res: &mut Response) {
     res.headers_mut().insert(
         "Allow",
-        HeaderValue::from_static("GET,HEAD,PUT,OPTIONS,DELETE,PROPFIND,COPY,MOVE"),
+        HeaderValue::from_static("GET,HEAD,PUT,OPTIONS,DELETE,PROPFIND,COPY,MOVE,REPORT"),
     );
     res.headers_mut()
         .insert("DAV", HeaderValue::from_static("1,2"));
 }
 
+/// How many levels of folders a json listing goes down with `recursive` or `depth=<n>`
+fn list_depth(query_params: &HashMap<String, String>) -> usize {
+    if !query_params.contains_key("json") {
+        return 1;
+    }
+    match query_params.get("depth").and_then(|v| v.parse().ok()) {
+        Some(v) => v,
+        None if query_params.contains_key("recursive") => usize::MAX,
+        None => 1,
+    }
+}
+
+/// Hands the event to the middleware, then to the subscribers of the bus
+fn emit(events: &EventBus, middlewares: &[Arc<dyn Middleware>], event: FsEvent) {
+    for middleware in middlewares {
+        middleware.event(&event);
+    }
+    events.publish(event);
+}
+
+/// The algorithm asked for with `hash`, sha256 if it's left empty
+fn hash_algo(query_params: &HashMap<String, String>) -> Option<ChecksumAlgo> {
+    match query_params.get("hash").map(|v| v.as_str()) {
+        None | Some("") => Some(ChecksumAlgo::Sha256),
+        Some(v) => ChecksumAlgo::from_name(v),
+    }
+}
+
+fn is_stream_query(query_params: &HashMap<String, String>) -> bool {
+    query_params.contains_key("stream")
+        && (query_params.contains_key("json") || query_params.contains_key("simple"))
+}
+
+/// Whether a json listing is asked a page of its entries with `offset` or `limit`
+fn is_list_paged(query_params: &HashMap<String, String>) -> bool {
+    query_params.contains_key("json")
+        && !query_params.contains_key("q")
+        && (query_params.contains_key("offset") || query_params.contains_key("limit"))
+}
+
+/// The page of search results or listing entries asked for with `offset` and `limit`
+fn search_page(query_params: &HashMap<String, String>) -> (usize, usize) {
+    let param = |name: &str| query_params.get(name).and_then(|v| v.parse().ok());
+    (
+        param("offset").unwrap_or(0),
+        param("limit").unwrap_or(usize::MAX),
+    )
+}
+
+/// How many files, folders and links are under the folder, hidden ones included
+async fn count_entries(dir: &Path) -> Result<u64> {
+    let dir = dir.to_path_buf();
+    let count = tokio::task::spawn_blocking(move || {
+        WalkDir::new(dir).min_depth(1).into_iter().count() as u64
+    })
+    .await?;
+    Ok(count)
+}
+
+/// Walks the folders, skipping hidden entries, until `visit` returns false
+pub fn walk_visible(
+    dirs: &[PathBuf],
+    hidden: &[String],
+    posix_hidden: bool,
+    running: &AtomicBool,
+    mut visit: impl FnMut(&Path, bool) -> bool,
+) {
+    for dir in dirs {
+        let mut it = WalkDir::new(dir).into_iter();
+        it.next();
+        while let Some(Ok(entry)) = it.next() {
+            if !running.load(atomic::Ordering::SeqCst) {
+                return;
+            }
+            let entry_path = entry.path();
+            let base_name = get_file_name(entry_path);
+            let file_type = entry.file_type();
+            let mut is_dir_type: bool = file_type.is_dir();
+            if file_type.is_symlink() {
+                match std::fs::symlink_metadata(entry_path) {
+                    Ok(meta) => {
+                        is_dir_type = meta.is_dir();
+                    }
+                    Err(_) => {
+                        continue;
+                    }
+                }
+            }
+            if is_hidden(hidden, posix_hidden, base_name, is_dir_type) {
+                if file_type.is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+            if !visit(entry_path, is_dir_type) {
+                return;
+            }
+        }
+    }
+}
+
+/// The numbered lines of a text file that contain the lowercase search, cut to
+/// `SEARCH_SNIPPET_LEN` characters
+fn grep_file(path: &Path, search: &str) -> Vec<(usize, String)> {
+    let content = match std::fs::metadata(path) {
+        Ok(meta) if meta.is_file() && meta.len() <= TEXT_MAX_SIZE => std::fs::read(path),
+        _ => return vec![],
+    };
+    let content = match content {
+        Ok(v) if content_inspector::inspect(&v[..v.len().min(1024)]).is_text() => v,
+        _ => return vec![],
+    };
+    String::from_utf8_lossy(&content)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.to_lowercase().contains(search))
+        .map(|(i, line)| {
+            (
+                i + 1,
+                line.trim().chars().take(SEARCH_SNIPPET_LEN).collect(),
+            )
+        })
+        .collect()
+}
+
 async fn get_content_type(path: &Path) -> Result<String> {
     let mut buffer: Vec<u8> = vec![];
     fs::File::open(path)
@@ -1607,11 +7273,16 @@ async fn get_content_type(path: &Path) -> Result<String> {
         .take(1024)
         .read_to_end(&mut buffer)
         .await?;
+    Ok(sniff_content_type(path, &buffer))
+}
+
+/// The content type of the file, by its name and its first bytes
+fn sniff_content_type(path: &Path, buffer: &[u8]) -> String {
     let mime = mime_guess::from_path(path).first();
-    let is_text = content_inspector::inspect(&buffer).is_text();
-    let content_type = if is_text {
+    let is_text = content_inspector::inspect(buffer).is_text();
+    if is_text {
         let mut detector = chardetng::EncodingDetector::new();
-        detector.feed(&buffer, buffer.len() < 1024);
+        detector.feed(buffer, buffer.len() < 1024);
         let (enc, confident) = detector.guess_assess(None, true);
         let charset = if confident {
             format!("; charset={}", enc.name())
@@ -1627,6 +7298,5 @@ async fn get_content_type(path: &Path) -> Result<String> {
             Some(m) => m.to_string(),
             None => "application/octet-stream".into(),
         }
-    };
-    Ok(content_type)
+    }
 }
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ArgMatches;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::args::build_cli;
+use crate::{serve_until, Args};
+
+const SERVICE_NAME: &str = "dufs";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+/// The directory and the serve options of `service run`, for the service thread
+static SERVICE_ARGS: OnceLock<(PathBuf, Vec<OsString>)> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Runs the `service` subcommand
+pub fn command(matches: &ArgMatches) -> Result<()> {
+    let serve_args = |matches: &ArgMatches| -> Vec<OsString> {
+        matches
+            .get_many::<OsString>("args")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default()
+    };
+    match matches.subcommand() {
+        Some(("install", matches)) => install(serve_args(matches)),
+        Some(("uninstall", _)) => uninstall(),
+        Some(("run", matches)) => {
+            let dir = matches.get_one::<PathBuf>("dir").unwrap().clone();
+            let _ = SERVICE_ARGS.set((dir, serve_args(matches)));
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main).with_context(|| {
+                "Failed to connect to the service manager, `service run` is for it only"
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Parses the serve options as if given to dufs, relative to the current directory
+fn parse_args(args: &[OsString]) -> Result<Args> {
+    let matches = build_cli()
+        .try_get_matches_from(std::iter::once(OsString::from(SERVICE_NAME)).chain(args.to_vec()))?;
+    Args::parse(matches)
+}
+
+fn install(args: Vec<OsString>) -> Result<()> {
+    parse_args(&args)?;
+    let dir = std::env::current_dir()?;
+    let mut launch_arguments: Vec<OsString> =
+        vec!["service".into(), "run".into(), "--dir".into(), dir.into()];
+    launch_arguments.push("--".into());
+    launch_arguments.extend(args);
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .with_context(|| "Failed to connect to the service manager")?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: SERVICE_NAME.into(),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+        .with_context(|| "Failed to install the service")?;
+    service.set_description(env!("CARGO_PKG_DESCRIPTION"))?;
+    service
+        .start::<&str>(&[])
+        .with_context(|| "Failed to start the service")?;
+    println!("Installed and started the `{SERVICE_NAME}` service");
+    Ok(())
+}
+
+fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .with_context(|| "Failed to connect to the service manager")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .with_context(|| "Failed to open the service")?;
+    // Removed once it's stopped and no longer open
+    service.delete()?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    println!("Uninstalled the `{SERVICE_NAME}` service");
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        error!("Failed to run the service, {err}");
+    }
+}
+
+fn run_service() -> Result<()> {
+    let shutdown = Arc::new(Notify::new());
+    let handler = {
+        let shutdown = shutdown.clone();
+        move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                shutdown.notify_one();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+    let status_handle = service_control_handler::register(SERVICE_NAME, handler)?;
+    let set_status = |state, exit_code| {
+        status_handle.set_service_status(ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted: match state {
+                ServiceState::Running => {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                }
+                _ => ServiceControlAccept::empty(),
+            },
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+    set_status(ServiceState::Running, ServiceExitCode::Win32(0))?;
+
+    let ret = serve_service(shutdown);
+    let exit_code = match &ret {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+    set_status(ServiceState::Stopped, exit_code)?;
+    ret
+}
+
+fn serve_service(shutdown: Arc<Notify>) -> Result<()> {
+    let (dir, args) = SERVICE_ARGS
+        .get()
+        .ok_or_else(|| anyhow!("Missing the service options"))?;
+    std::env::set_current_dir(dir)
+        .with_context(|| format!("Failed to change directory to `{}`", dir.display()))?;
+    let args = parse_args(args)?;
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(serve_until(args, async move { shutdown.notified().await }))
+}
@@ -0,0 +1,669 @@
+use anyhow::{Context, Result};
+use hyper::body::{Bytes, Sender};
+use hyper::header::CONTENT_RANGE;
+use hyper::{Body, Method, Request, StatusCode as HttpStatus};
+use russh::keys::ssh_key::private::Ed25519Keypair;
+use russh::keys::{load_secret_key, PrivateKey};
+use russh::server::{Auth, ChannelOpenHandle, Config, Msg, Session};
+use russh::{Channel, ChannelId, MethodKind, MethodSet};
+use russh_sftp::protocol::{
+    Attrs, Data, File as Entry, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode,
+    Version,
+};
+use russh_sftp::server::StatusReply;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::UNIX_EPOCH;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::auth::AccessPaths;
+use crate::server::{PathItem, Server};
+use crate::utils::resolve_relative;
+
+/// The user name anonymous users log in with, with any password or none
+const ANONYMOUS: &str = "anonymous";
+/// The most bytes sent for one read, as the clients ask for about 32 KiB
+const MAX_READ: u32 = 256 * 1024;
+
+/// An SFTP frontend to the server, with the accounts and permissions of `--auth`
+pub struct SftpServer {
+    server: Arc<RwLock<Arc<Server>>>,
+    config: Arc<Config>,
+}
+
+impl SftpServer {
+    /// Uses the OpenSSH private key at `host_key`, or one made for this run
+    pub fn new(server: Arc<RwLock<Arc<Server>>>, host_key: Option<&Path>) -> Result<Self> {
+        let key = match host_key {
+            Some(path) => load_secret_key(path, None)
+                .with_context(|| format!("Failed to load sftp host key `{}`", path.display()))?,
+            None => random_key(),
+        };
+        let config = Config {
+            methods: MethodSet::from(&[MethodKind::None, MethodKind::Password][..]),
+            keys: vec![key],
+            ..Default::default()
+        };
+        Ok(Self {
+            server,
+            config: Arc::new(config),
+        })
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let connection = Connection {
+                server: self.server.clone(),
+                ip: peer.ip(),
+                user: None,
+                channels: HashMap::new(),
+            };
+            let config = self.config.clone();
+            tokio::spawn(async move {
+                let ret = match russh::server::run_stream(config, stream, connection).await {
+                    Ok(session) => session.await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = ret {
+                    debug!("SFTP session of {peer} ended, {err}");
+                }
+            });
+        }
+    }
+}
+
+/// An ed25519 key, seeded by the random bytes of two v4 uuids
+fn random_key() -> PrivateKey {
+    let mut seed = [0; 32];
+    seed[..16].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    seed[16..].copy_from_slice(uuid::Uuid::new_v4().as_bytes());
+    PrivateKey::from(Ed25519Keypair::from_seed(&seed))
+}
+
+/// An SSH connection, offering the SFTP subsystem once logged in
+struct Connection {
+    server: Arc<RwLock<Arc<Server>>>,
+    ip: IpAddr,
+    /// `None` for anonymous users
+    user: Option<String>,
+    /// The session channels opened, awaiting their subsystem request
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl russh::server::Handler for Connection {
+    type Error = anyhow::Error;
+
+    async fn auth_none(&mut self, user: &str) -> Result<Auth> {
+        self.auth_password(user, "").await
+    }
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth> {
+        // Anonymous users may give anything as the password, as with FTP
+        let user = Some(user).filter(|v| *v != ANONYMOUS);
+        let server = self.server.read().unwrap().clone();
+        if server.login(user, password, Some(self.ip)).await {
+            self.user = user.map(|v| v.to_string());
+            return Ok(Auth::Accept);
+        }
+        Ok(Auth::reject())
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<()> {
+        self.channels.insert(channel.id(), channel);
+        reply.accept().await;
+        Ok(())
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<()> {
+        match self.channels.remove(&channel_id) {
+            Some(channel) if name == "sftp" => {
+                session.channel_success(channel_id)?;
+                let sftp = Sftp {
+                    server: self.server.clone(),
+                    ip: self.ip,
+                    user: self.user.clone(),
+                    handles: HashMap::new(),
+                    next_handle: 0,
+                };
+                russh_sftp::server::run(channel.into_stream(), sftp).await;
+            }
+            _ => session.channel_failure(channel_id)?,
+        }
+        Ok(())
+    }
+
+    async fn channel_eof(&mut self, channel: ChannelId, session: &mut Session) -> Result<()> {
+        session.close(channel)?;
+        Ok(())
+    }
+}
+
+/// What a handle of the SFTP session refers to
+enum Open {
+    Dir {
+        path: PathBuf,
+        access_paths: AccessPaths,
+        /// Whether the entries were sent, all in one go
+        listed: bool,
+    },
+    File(File),
+    Upload(Upload),
+}
+
+/// A file being written, carried out as one upload with `PUT` so hooks and events see
+/// the whole file
+struct Upload {
+    /// The path relative to the root
+    path: String,
+    /// Whether the file is uploaded from the start, rather than resumed from where the
+    /// first write goes
+    whole: bool,
+    /// The body of the upload and the task carrying it out, from the first write on
+    body: Option<(Sender, JoinHandle<Result<HttpStatus>>)>,
+    /// Where the next write must go, as the upload is streamed
+    next: u64,
+}
+
+struct Sftp {
+    server: Arc<RwLock<Arc<Server>>>,
+    ip: IpAddr,
+    user: Option<String>,
+    handles: HashMap<String, Open>,
+    next_handle: u64,
+}
+
+impl Sftp {
+    fn server(&self) -> Arc<Server> {
+        self.server.read().unwrap().clone()
+    }
+
+    fn add_handle(&mut self, id: u32, open: Open) -> Handle {
+        self.next_handle += 1;
+        let handle = self.next_handle.to_string();
+        self.handles.insert(handle.clone(), open);
+        Handle { id, handle }
+    }
+
+    /// The file or folder, what's readable in it and its attributes, if the user may read it
+    async fn lookup(
+        &self,
+        path: &str,
+    ) -> Result<(PathBuf, AccessPaths, FileAttributes), StatusReply> {
+        let server = self.server();
+        let (path, _, access_paths) = server
+            .lookup(&resolve_relative("", path), self.user.as_deref())
+            .await
+            .ok_or(StatusCode::NoSuchFile)?;
+        let meta = fs::metadata(&path)
+            .await
+            .map_err(|_| StatusCode::NoSuchFile)?;
+        Ok((path, access_paths, metadata_attributes(&meta)))
+    }
+
+    /// Makes the change with the request, as the logged in user
+    async fn change(&self, req: Request<Body>) -> Result<(), StatusReply> {
+        let status = self
+            .server()
+            .change(req, self.user.as_deref(), Some(self.ip))
+            .await
+            .map_err(|err| StatusCode::Failure.with_message(err.to_string()))?;
+        status_of(status)
+    }
+
+    fn request(&self, method: &str, path: &str) -> hyper::http::request::Builder {
+        let uri = self.server().uri(&resolve_relative("", path));
+        Request::builder()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(uri)
+    }
+
+    /// Starts the upload at the first write
+    fn start_upload(&self, upload: &mut Upload, offset: u64) -> Result<(), StatusReply> {
+        let mut builder = self.request("PUT", &upload.path);
+        if !upload.whole {
+            // Only the start of the range counts, the length isn't known yet
+            builder = builder.header(CONTENT_RANGE, format!("bytes {offset}-{offset}/*"));
+        } else if offset != 0 {
+            return Err(unsequential());
+        }
+        let (sender, body) = Body::channel();
+        let req = builder.body(body).map_err(|_| StatusCode::BadMessage)?;
+        let server = self.server();
+        let user = self.user.clone();
+        let ip = self.ip;
+        let task = tokio::spawn(async move { server.change(req, user.as_deref(), Some(ip)).await });
+        upload.body = Some((sender, task));
+        upload.next = offset;
+        Ok(())
+    }
+}
+
+impl russh_sftp::server::Handler for Sftp {
+    type Error = StatusReply;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported.into()
+    }
+
+    async fn init(
+        &mut self,
+        _version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version, Self::Error> {
+        Ok(Version::new())
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let path = format!("/{}", resolve_relative("", &path));
+        Ok(Name {
+            id,
+            files: vec![Entry::dummy(path)],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let (_, _, attrs) = self.lookup(&path).await?;
+        Ok(Attrs { id, attrs })
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        self.stat(id, path).await
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let meta = match self.handles.get(&handle) {
+            Some(Open::Dir { path, .. }) => fs::metadata(path).await,
+            Some(Open::File(file)) => file.metadata().await,
+            Some(Open::Upload(upload)) => {
+                let path = upload.path.clone();
+                return self.stat(id, path).await;
+            }
+            None => return Err(StatusCode::Failure.with_message("Invalid handle")),
+        };
+        let meta = meta.map_err(|_| StatusCode::NoSuchFile)?;
+        Ok(Attrs {
+            id,
+            attrs: metadata_attributes(&meta),
+        })
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        _path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        // Clients set the times and modes after uploading, which are left as they are
+        Ok(ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        _handle: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        Ok(ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let (path, access_paths, attrs) = self.lookup(&path).await?;
+        if !attrs.is_dir() {
+            return Err(StatusCode::NoSuchFile.with_message("Not a directory"));
+        }
+        Ok(self.add_handle(
+            id,
+            Open::Dir {
+                path,
+                access_paths,
+                listed: false,
+            },
+        ))
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        let server = self.server();
+        let (path, access_paths) = match self.handles.get_mut(&handle) {
+            Some(Open::Dir { listed: true, .. }) => return Err(StatusCode::Eof.into()),
+            Some(Open::Dir {
+                path,
+                access_paths,
+                listed,
+            }) => {
+                *listed = true;
+                (path.clone(), access_paths.clone())
+            }
+            _ => return Err(StatusCode::Failure.with_message("Invalid handle")),
+        };
+        let items = server
+            .entries(&path, &access_paths)
+            .await
+            .map_err(|_| StatusCode::Failure)?;
+        let files = items.iter().map(entry).collect();
+        Ok(Name { id, files })
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        if !pflags.contains(OpenFlags::WRITE) {
+            let (path, _, attrs) = self.lookup(&filename).await?;
+            if attrs.is_dir() {
+                return Err(StatusCode::Failure.with_message("Is a directory"));
+            }
+            let file = File::open(&path)
+                .await
+                .map_err(|_| StatusCode::PermissionDenied)?;
+            return Ok(self.add_handle(id, Open::File(file)));
+        }
+
+        let server = self.server();
+        let path = resolve_relative("", &filename);
+        if !server.args().allow_upload {
+            return Err(StatusCode::PermissionDenied.into());
+        }
+        // Whether the user may write here at all, the rest is up to the upload
+        let req = self
+            .request("WRITEABLE", &path)
+            .body(Body::empty())
+            .map_err(|_| StatusCode::BadMessage)?;
+        self.change(req).await?;
+        let exists = server.lookup(&path, self.user.as_deref()).await.is_some();
+        if exists && pflags.contains(OpenFlags::EXCLUDE) {
+            return Err(StatusCode::Failure.with_message("File exists"));
+        }
+        let upload = Upload {
+            path,
+            whole: !exists || pflags.contains(OpenFlags::TRUNCATE),
+            body: None,
+            next: 0,
+        };
+        Ok(self.add_handle(id, Open::Upload(upload)))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let file = match self.handles.get_mut(&handle) {
+            Some(Open::File(file)) => file,
+            _ => return Err(StatusCode::Failure.with_message("Invalid handle")),
+        };
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|_| StatusCode::Failure)?;
+        let mut data = vec![];
+        file.take(len.min(MAX_READ) as u64)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|_| StatusCode::Failure)?;
+        if data.is_empty() {
+            return Err(StatusCode::Eof.into());
+        }
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let mut upload = match self.handles.remove(&handle) {
+            Some(Open::Upload(v)) => v,
+            Some(open) => {
+                self.handles.insert(handle, open);
+                return Err(StatusCode::Failure.with_message("Invalid handle"));
+            }
+            None => return Err(StatusCode::Failure.with_message("Invalid handle")),
+        };
+        let ret = self.write_upload(&mut upload, offset, data).await;
+        match ret {
+            // The upload is over once refused, so is the handle
+            Err(err) if upload.body.is_none() => Err(err),
+            ret => {
+                self.handles.insert(handle, Open::Upload(upload));
+                ret.map(|_| ok(id))
+            }
+        }
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        match self.handles.remove(&handle) {
+            Some(Open::Upload(mut upload)) => {
+                if upload.body.is_none() {
+                    if !upload.whole {
+                        return Ok(ok(id));
+                    }
+                    // Nothing was written, so the file is left empty
+                    self.start_upload(&mut upload, 0)?;
+                }
+                finish(upload).await?;
+                Ok(ok(id))
+            }
+            Some(_) => Ok(ok(id)),
+            None => Err(StatusCode::Failure.with_message("Invalid handle")),
+        }
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let req = self
+            .request("MKCOL", &path)
+            .body(Body::empty())
+            .map_err(|_| StatusCode::BadMessage)?;
+        self.change(req).await.map(|_| ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        let (dir, _, attrs) = self.lookup(&path).await?;
+        if !attrs.is_dir() {
+            return Err(StatusCode::Failure.with_message("Not a directory"));
+        }
+        let mut entries = fs::read_dir(&dir).await.map_err(|_| StatusCode::Failure)?;
+        if let Ok(Some(_)) = entries.next_entry().await {
+            return Err(StatusCode::Failure.with_message("Directory not empty"));
+        }
+        self.delete(id, &path).await
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        let (_, _, attrs) = self.lookup(&filename).await?;
+        if attrs.is_dir() {
+            return Err(StatusCode::Failure.with_message("Is a directory"));
+        }
+        self.delete(id, &filename).await
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        let dest = self.server().uri(&resolve_relative("", &newpath));
+        let req = self
+            .request("MOVE", &oldpath)
+            .header("Destination", dest)
+            .header("Overwrite", "F")
+            .body(Body::empty())
+            .map_err(|_| StatusCode::BadMessage)?;
+        self.change(req).await.map(|_| ok(id))
+    }
+}
+
+impl Sftp {
+    async fn write_upload(
+        &self,
+        upload: &mut Upload,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<(), StatusReply> {
+        if upload.body.is_none() {
+            self.start_upload(upload, offset)?;
+        }
+        if offset != upload.next {
+            return Err(unsequential());
+        }
+        upload.next += data.len() as u64;
+        let (sender, _) = upload.body.as_mut().unwrap();
+        if sender.send_data(Bytes::from(data)).await.is_err() {
+            // The upload was turned down before taking the whole body
+            let (_, task) = upload.body.take().unwrap();
+            let status = task
+                .await
+                .map_err(|_| StatusCode::Failure)?
+                .map_err(|err| StatusCode::Failure.with_message(err.to_string()))?;
+            status_of(status)?;
+            return Err(StatusCode::Failure.into());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: u32, path: &str) -> Result<Status, StatusReply> {
+        let req = self
+            .request("DELETE", path)
+            .body(Body::empty())
+            .map_err(|_| StatusCode::BadMessage)?;
+        self.change(req).await.map(|_| ok(id))
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // Left unfinished, e.g. by a lost connection, so the upload fails too
+        if let Some((sender, _)) = self.body.take() {
+            sender.abort();
+        }
+    }
+}
+
+/// Ends the body of the upload and waits for its outcome
+async fn finish(mut upload: Upload) -> Result<(), StatusReply> {
+    let (sender, task) = match upload.body.take() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    drop(sender);
+    let status = task
+        .await
+        .map_err(|_| StatusCode::Failure)?
+        .map_err(|err| StatusCode::Failure.with_message(err.to_string()))?;
+    status_of(status)
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn unsequential() -> StatusReply {
+    StatusCode::OpUnsupported.with_message("Only sequential writes are supported")
+}
+
+/// The SFTP status of the response to a change
+fn status_of(status: HttpStatus) -> Result<(), StatusReply> {
+    match status {
+        v if v.is_success() => Ok(()),
+        HttpStatus::UNAUTHORIZED | HttpStatus::FORBIDDEN => {
+            Err(StatusCode::PermissionDenied.into())
+        }
+        HttpStatus::NOT_FOUND => Err(StatusCode::NoSuchFile.into()),
+        HttpStatus::METHOD_NOT_ALLOWED | HttpStatus::PRECONDITION_FAILED => {
+            Err(StatusCode::Failure.with_message("File exists"))
+        }
+        v => Err(StatusCode::Failure.with_message(v.canonical_reason().unwrap_or("Failure"))),
+    }
+}
+
+fn attributes(is_dir: bool, size: u64, mtime: u64) -> FileAttributes {
+    let mtime = (mtime / 1000) as u32;
+    FileAttributes {
+        size: Some(if is_dir { 0 } else { size }),
+        permissions: Some(if is_dir { 0o40755 } else { 0o100644 }),
+        atime: Some(mtime),
+        mtime: Some(mtime),
+        ..Default::default()
+    }
+}
+
+fn metadata_attributes(meta: &Metadata) -> FileAttributes {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default();
+    attributes(meta.is_dir(), meta.len(), mtime)
+}
+
+fn entry(item: &PathItem) -> Entry {
+    let attrs = attributes(item.is_dir(), item.size.unwrap_or_default(), item.mtime);
+    Entry::new(item.base_name(), attrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_of() {
+        assert!(status_of(HttpStatus::CREATED).is_ok());
+        assert_eq!(
+            status_of(HttpStatus::FORBIDDEN).unwrap_err().status_code,
+            StatusCode::PermissionDenied
+        );
+        assert_eq!(
+            status_of(HttpStatus::INSUFFICIENT_STORAGE)
+                .unwrap_err()
+                .error_message
+                .as_deref(),
+            Some("Insufficient Storage")
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        let attrs = attributes(false, 16, 1_700_000_000_123);
+        assert_eq!(attrs.size, Some(16));
+        assert_eq!(attrs.mtime, Some(1_700_000_000));
+        assert!(attrs.is_regular());
+        assert!(attributes(true, 4096, 0).is_dir());
+    }
+}
@@ -1,6 +1,7 @@
 use async_stream::stream;
 use futures::{Stream, StreamExt};
-use std::io::Error;
+use std::fs::File;
+use std::io::{Error, Read};
 use std::pin::Pin;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
@@ -36,31 +37,65 @@ where
         };
         stream.boxed()
     }
-    // allow truncation as truncated remaining is always less than buf_size: usize
+}
+
+/// Streams a file, reading the next chunk on a blocking thread while the one before is
+/// being sent, so that reading from the disk overlaps writing to the network. Other
+/// blocking readers, like the bodies of object storage, are streamed the same way.
+pub struct FileStreamer<R = File>
+where
+    R: Read + Send + 'static,
+{
+    file: R,
+    buf_size: usize,
+}
+
+impl<R> FileStreamer<R>
+where
+    R: Read + Send + 'static,
+{
+    #[inline]
+    pub fn new(file: R, buf_size: usize) -> Self {
+        Self { file, buf_size }
+    }
+    pub fn into_stream(
+        self,
+    ) -> Pin<Box<impl ?Sized + Stream<Item = Result<Vec<u8>, Error>> + 'static>> {
+        self.into_stream_sized(u64::MAX)
+    }
     pub fn into_stream_sized(
-        mut self,
+        self,
         max_length: u64,
     ) -> Pin<Box<impl ?Sized + Stream<Item = Result<Vec<u8>, Error>> + 'static>> {
-        let stream = stream! {
-        let mut remaining = max_length;
-            loop {
-                if remaining == 0 {
-                    break;
-                }
-                let bs = if remaining >= self.buf_size as u64 {
-                    self.buf_size
+        let buf_size = self.buf_size;
+        let read = move |mut file: R, remaining: u64| {
+            tokio::task::spawn_blocking(move || {
+                // allow truncation as truncated remaining is always less than buf_size: usize
+                let bs = if remaining >= buf_size as u64 {
+                    buf_size
                 } else {
                     remaining as usize
                 };
                 let mut buf = vec![0; bs];
-                let r = self.reader.read(&mut buf).await?;
-                if r == 0 {
-                    break;
-                } else {
+                let ret = file.read(&mut buf).map(|r| {
                     buf.truncate(r);
-                    yield Ok(buf);
+                    buf
+                });
+                (file, ret)
+            })
+        };
+        let stream = stream! {
+            let mut remaining = max_length;
+            let mut next = read(self.file, remaining);
+            while remaining > 0 {
+                let (file, ret) = next.await?;
+                let buf = ret?;
+                if buf.is_empty() {
+                    break;
                 }
-                remaining -= r as u64;
+                remaining -= buf.len() as u64;
+                next = read(file, remaining);
+                yield Ok(buf);
             }
         };
         stream.boxed()
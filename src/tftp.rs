@@ -0,0 +1,233 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use crate::server::Server;
+use crate::utils::resolve_relative;
+
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+const ERR_NOT_FOUND: u16 = 1;
+const ERR_ACCESS: u16 = 2;
+const ERR_ILLEGAL: u16 = 4;
+
+const DEFAULT_BLKSIZE: usize = 512;
+const MAX_BLKSIZE: usize = 65464;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Times a block is sent again before the transfer is given up
+const RETRIES: usize = 5;
+
+/// A read or write request
+#[derive(Debug, PartialEq)]
+struct Request {
+    opcode: u16,
+    filename: String,
+    /// Lowercase names and their values, in the order given
+    options: Vec<(String, String)>,
+}
+
+/// A read-only TFTP frontend to the server, for what anonymous users may read
+pub struct TftpServer {
+    server: Arc<RwLock<Arc<Server>>>,
+}
+
+impl TftpServer {
+    pub fn new(server: Arc<RwLock<Arc<Server>>>) -> Self {
+        Self { server }
+    }
+
+    pub async fn serve(self: Arc<Self>, socket: UdpSocket) {
+        let local_ip = match socket.local_addr() {
+            Ok(v) => v.ip(),
+            Err(_) => return,
+        };
+        let mut buf = [0; 1024];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let request = buf[..len].to_vec();
+            let tftp = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = tftp.transfer(&request, local_ip, peer).await {
+                    debug!("TFTP transfer to {peer} failed, {err}");
+                }
+            });
+        }
+    }
+
+    /// Answers a request from a port of its own, as TFTP goes
+    async fn transfer(&self, request: &[u8], local_ip: IpAddr, peer: SocketAddr) -> Result<()> {
+        let socket = UdpSocket::bind((local_ip, 0)).await?;
+        socket.connect(peer).await?;
+        let Request {
+            filename, options, ..
+        } = match parse_request(request) {
+            Some(v) if v.opcode == OP_RRQ => v,
+            Some(v) if v.opcode == OP_WRQ => {
+                return send_error(&socket, ERR_ACCESS, "The server is read-only").await
+            }
+            _ => return send_error(&socket, ERR_ILLEGAL, "Illegal TFTP operation").await,
+        };
+        // Windows clients ask for paths like `boot\x64\wdsnbp.com`
+        let path = resolve_relative("", &filename.replace('\\', "/"));
+        let server = self.server.read().unwrap().clone();
+        let file_path = match server.lookup(&path, None).await {
+            Some((v, false, _)) if !server.is_hidden_path(&path, false) => v,
+            _ => return send_error(&socket, ERR_NOT_FOUND, "File not found").await,
+        };
+        let mut file = File::open(&file_path).await?;
+        let size = file.metadata().await?.len();
+
+        let mut blksize = DEFAULT_BLKSIZE;
+        let mut wait = DEFAULT_TIMEOUT;
+        let mut acked = vec![];
+        for (name, value) in options {
+            match name.as_str() {
+                "blksize" => {
+                    if let Ok(v) = value.parse::<usize>() {
+                        blksize = v.clamp(8, MAX_BLKSIZE);
+                        acked.push((name, blksize.to_string()));
+                    }
+                }
+                "timeout" => {
+                    if let Ok(v @ 1..=255) = value.parse::<u64>() {
+                        wait = Duration::from_secs(v);
+                        acked.push((name, value));
+                    }
+                }
+                "tsize" => acked.push((name, size.to_string())),
+                _ => {}
+            }
+        }
+        if !acked.is_empty() {
+            let mut packet = OP_OACK.to_be_bytes().to_vec();
+            for (name, value) in acked {
+                packet.extend_from_slice(name.as_bytes());
+                packet.push(0);
+                packet.extend_from_slice(value.as_bytes());
+                packet.push(0);
+            }
+            send_acked(&socket, &packet, 0, wait).await?;
+        }
+
+        let mut block: u16 = 0;
+        let mut data = vec![0; blksize];
+        loop {
+            let len = read_full(&mut file, &mut data).await?;
+            block = block.wrapping_add(1);
+            let mut packet = OP_DATA.to_be_bytes().to_vec();
+            packet.extend_from_slice(&block.to_be_bytes());
+            packet.extend_from_slice(&data[..len]);
+            send_acked(&socket, &packet, block, wait).await?;
+            // A short block ends the transfer
+            if len < blksize {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Sends the packet until the peer acknowledges the block
+async fn send_acked(socket: &UdpSocket, packet: &[u8], block: u16, wait: Duration) -> Result<()> {
+    let mut buf = [0; 516];
+    for _ in 0..RETRIES {
+        socket.send(packet).await?;
+        loop {
+            let len = match timeout(wait, socket.recv(&mut buf)).await {
+                Ok(ret) => ret?,
+                Err(_) => break,
+            };
+            match (opcode(&buf[..len]), buf.get(2..4)) {
+                (Some(OP_ACK), Some(v)) if u16::from_be_bytes([v[0], v[1]]) == block => {
+                    return Ok(())
+                }
+                (Some(OP_ERROR), _) => bail!("Aborted by the client"),
+                // A late ack of a block sent before
+                _ => {}
+            }
+        }
+    }
+    bail!("Timed out")
+}
+
+async fn send_error(socket: &UdpSocket, code: u16, message: &str) -> Result<()> {
+    let mut packet = OP_ERROR.to_be_bytes().to_vec();
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    socket.send(&packet).await?;
+    Ok(())
+}
+
+/// Reads until the buffer is full or the file ends
+async fn read_full(file: &mut File, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+fn opcode(packet: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes([*packet.first()?, *packet.get(1)?]))
+}
+
+fn parse_request(packet: &[u8]) -> Option<Request> {
+    let opcode = opcode(packet)?;
+    let mut fields = packet[2..]
+        .split(|v| *v == 0)
+        .map(|v| String::from_utf8_lossy(v).to_string());
+    let filename = fields.next()?;
+    let _mode = fields.next()?;
+    let mut options: HashMap<String, String> = HashMap::new();
+    let mut ordered = vec![];
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        let name = name.to_ascii_lowercase();
+        if !name.is_empty() && options.insert(name.clone(), value.clone()).is_none() {
+            ordered.push((name, value));
+        }
+    }
+    Some(Request {
+        opcode,
+        filename,
+        options: ordered,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let packet = b"\x00\x01pxelinux.0\x00octet\x00TSIZE\x000\x00blksize\x001456\x00";
+        assert_eq!(
+            parse_request(packet),
+            Some(Request {
+                opcode: OP_RRQ,
+                filename: "pxelinux.0".to_string(),
+                options: vec![
+                    ("tsize".to_string(), "0".to_string()),
+                    ("blksize".to_string(), "1456".to_string())
+                ],
+            })
+        );
+        assert_eq!(parse_request(b"\x00\x01name"), None);
+    }
+}
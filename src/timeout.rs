@@ -0,0 +1,164 @@
+use hyper::server::accept::Accept;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// Accepts connections that fail once they've sent nothing since they were opened for the
+/// read timeout, or their writes have been blocked for the write timeout, so a peer that
+/// went quiet doesn't hold on to a task or a response forever.
+///
+/// Reads are only timed until the first bytes come, hyper keeps a read pending while it
+/// sends a response to tell if the peer goes away. The headers and bodies of the requests
+/// are timed by hyper and the server.
+pub struct TimeoutAcceptor<A> {
+    inner: A,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl<A> TimeoutAcceptor<A> {
+    pub fn new(inner: A, read_timeout: Option<Duration>, write_timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            read_timeout,
+            write_timeout,
+        }
+    }
+}
+
+impl<A: Accept + Unpin> Accept for TimeoutAcceptor<A> {
+    type Conn = TimeoutStream<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let pin = self.get_mut();
+        let (read_timeout, write_timeout) = (pin.read_timeout, pin.write_timeout);
+        Pin::new(&mut pin.inner)
+            .poll_accept(cx)
+            .map_ok(|stream| TimeoutStream {
+                stream,
+                read_timeout,
+                write_timeout,
+                read_deadline: None,
+                write_deadline: None,
+            })
+    }
+}
+
+pub struct TimeoutStream<S> {
+    stream: S,
+    /// Cleared once the first bytes are read
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    /// When the write blocked since gives up
+    write_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> TimeoutStream<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Passes the result of a write through, or fails it once blocked for too long
+    fn timed_write<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match self.write_timeout.filter(|_| poll.is_pending()) {
+            Some(timeout) => poll_deadline(&mut self.write_deadline, timeout, cx, "write"),
+            None => {
+                self.write_deadline = None;
+                poll
+            }
+        }
+    }
+}
+
+/// Pending until the deadline, started on the first poll, has passed
+fn poll_deadline<T>(
+    deadline: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Duration,
+    cx: &mut Context<'_>,
+    kind: &str,
+) -> Poll<io::Result<T>> {
+    let sleep = deadline.get_or_insert_with(|| Box::pin(sleep(timeout)));
+    match sleep.as_mut().poll(cx) {
+        Poll::Ready(()) => {
+            *deadline = None;
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{kind} timed out"),
+            )))
+        }
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TimeoutStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let pin = self.get_mut();
+        let poll = Pin::new(&mut pin.stream).poll_read(cx, buf);
+        match pin.read_timeout {
+            Some(timeout) if poll.is_pending() => {
+                poll_deadline(&mut pin.read_deadline, timeout, cx, "read")
+            }
+            Some(_) => {
+                pin.read_timeout = None;
+                pin.read_deadline = None;
+                poll
+            }
+            None => poll,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let pin = self.get_mut();
+        let poll = Pin::new(&mut pin.stream).poll_write(cx, buf);
+        pin.timed_write(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let pin = self.get_mut();
+        let poll = Pin::new(&mut pin.stream).poll_write_vectored(cx, bufs);
+        pin.timed_write(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let pin = self.get_mut();
+        let poll = Pin::new(&mut pin.stream).poll_flush(cx);
+        pin.timed_write(cx, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let pin = self.get_mut();
+        let poll = Pin::new(&mut pin.stream).poll_shutdown(cx);
+        pin.timed_write(cx, poll)
+    }
+}
@@ -1,21 +1,29 @@
 use anyhow::{anyhow, bail, Context as AnyhowContext, Result};
 use core::task::{Context, Poll};
-use futures::ready;
+use futures::{ready, StreamExt};
 use hyper::server::accept::Accept;
-use hyper::server::conn::{AddrIncoming, AddrStream};
-use rustls::{Certificate, PrivateKey};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{
+    Certificate, ConfigBuilder, PrivateKey, SupportedCipherSuite, SupportedProtocolVersion,
+    WantsVerifier, ALL_CIPHER_SUITES,
+};
+use rustls_acme::{acme::ACME_TLS_ALPN_NAME, caches::DirCache, AcmeConfig};
 use std::future::Future;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 use std::{fs, io};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio_rustls::rustls::ServerConfig;
 
+use crate::proxy_protocol::{ProxyAcceptor, ProxyStream};
+
 enum State {
-    Handshaking(tokio_rustls::Accept<AddrStream>),
-    Streaming(tokio_rustls::server::TlsStream<AddrStream>),
+    Handshaking(tokio_rustls::Accept<ProxyStream>),
+    Streaming(tokio_rustls::server::TlsStream<ProxyStream>),
 }
 
 // tokio_rustls::server::TlsStream doesn't expose constructor methods,
@@ -27,7 +35,7 @@ pub struct TlsStream {
 }
 
 impl TlsStream {
-    fn new(stream: AddrStream, config: Arc<ServerConfig>) -> TlsStream {
+    fn new(stream: ProxyStream, config: Arc<ServerConfig>) -> TlsStream {
         let remote_addr = stream.remote_addr();
         let accept = tokio_rustls::TlsAcceptor::from(config).accept(stream);
         TlsStream {
@@ -98,11 +106,11 @@ impl AsyncWrite for TlsStream {
 
 pub struct TlsAcceptor {
     config: Arc<ServerConfig>,
-    incoming: AddrIncoming,
+    incoming: ProxyAcceptor,
 }
 
 impl TlsAcceptor {
-    pub fn new(config: Arc<ServerConfig>, incoming: AddrIncoming) -> TlsAcceptor {
+    pub fn new(config: Arc<ServerConfig>, incoming: ProxyAcceptor) -> TlsAcceptor {
         TlsAcceptor { config, incoming }
     }
 }
@@ -124,6 +132,147 @@ impl Accept for TlsAcceptor {
     }
 }
 
+/// The start of a server config that accepts the TLS versions from the minimum up and the
+/// cipher suites, by their names like `TLS13_AES_256_GCM_SHA384`, or the safe defaults of
+/// rustls for those not given. TLS 1.3 suites may also be given by their IANA names, like
+/// `TLS_AES_256_GCM_SHA384`.
+pub fn config_builder(
+    min_version: Option<&str>,
+    ciphers: &[String],
+) -> Result<ConfigBuilder<ServerConfig, WantsVerifier>> {
+    let versions: &[&'static SupportedProtocolVersion] = match min_version {
+        None | Some("1.2") => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        Some("1.3") => &[&rustls::version::TLS13],
+        Some(v) => bail!("Unsupported TLS version `{v}`, the lowest supported is 1.2"),
+    };
+    let suites = match ciphers.is_empty() {
+        true => ALL_CIPHER_SUITES.to_vec(),
+        false => ciphers
+            .iter()
+            .map(|v| find_cipher_suite(v).ok_or_else(|| anyhow!("Unknown TLS cipher suite `{v}`")))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    ServerConfig::builder()
+        .with_cipher_suites(&suites)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .map_err(|err| anyhow!("Invalid TLS ciphers for the TLS versions, {err}"))
+}
+
+fn find_cipher_suite(name: &str) -> Option<SupportedCipherSuite> {
+    ALL_CIPHER_SUITES.iter().copied().find(|suite| {
+        let suite_name = format!("{:?}", suite.suite());
+        suite_name.eq_ignore_ascii_case(name)
+            || suite_name
+                .strip_prefix("TLS13_")
+                .is_some_and(|v| format!("TLS_{v}").eq_ignore_ascii_case(name))
+    })
+}
+
+/// How often the certificate files are checked for changes
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Server config for `--tls-cert`/`--tls-key`, whose certificate is reloaded when
+/// the files change or on SIGHUP. Connections already established keep the old one.
+pub fn cert_server_config(
+    builder: ConfigBuilder<ServerConfig, WantsVerifier>,
+    cert_file: &Path,
+    key_file: &Path,
+) -> Result<Arc<ServerConfig>> {
+    let resolver = Arc::new(ReloadableCert::new(cert_file, key_file)?);
+    let config = builder
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    tokio::spawn(watch_cert(resolver));
+    Ok(Arc::new(config))
+}
+
+struct ReloadableCert {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    fn new(cert_file: &Path, key_file: &Path) -> Result<Self> {
+        let key = load_certified_key(cert_file, key_file)?;
+        Ok(Self {
+            cert_file: cert_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            key: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    fn reload(&self) {
+        match load_certified_key(&self.cert_file, &self.key_file) {
+            Ok(key) => {
+                *self.key.write().unwrap() = Arc::new(key);
+                info!("Reloaded TLS certificate `{}`", self.cert_file.display());
+            }
+            Err(err) => error!("Failed to reload TLS certificate, {err:#}"),
+        }
+    }
+
+    fn mtimes(&self) -> Option<(SystemTime, SystemTime)> {
+        let mtime = |path: &Path| fs::metadata(path).and_then(|v| v.modified()).ok();
+        Some((mtime(&self.cert_file)?, mtime(&self.key_file)?))
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+async fn watch_cert(cert: Arc<ReloadableCert>) {
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(v) => Some(v),
+        Err(err) => {
+            warn!("Failed to listen for SIGHUP, {err}");
+            None
+        }
+    };
+    let mut interval = tokio::time::interval(CERT_CHECK_INTERVAL);
+    let mut loaded = cert.mtimes();
+    let mut seen = loaded;
+    loop {
+        #[cfg(unix)]
+        let hangup_recv = async {
+            match hangup.as_mut() {
+                Some(v) => v.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup_recv = std::future::pending::<Option<()>>();
+        tokio::select! {
+            _ = hangup_recv => {
+                cert.reload();
+                loaded = cert.mtimes();
+                seen = loaded;
+            }
+            _ = interval.tick() => {
+                // Wait until the files stop changing, certbot doesn't write both at once
+                let mtimes = cert.mtimes();
+                if mtimes != loaded && mtimes == seen && mtimes.is_some() {
+                    cert.reload();
+                    loaded = mtimes;
+                }
+                seen = mtimes;
+            }
+        }
+    }
+}
+
+pub fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey> {
+    let certs = load_certs(cert_file)?;
+    let key = load_private_key(key_file)?;
+    let key = any_supported_type(&key).map_err(|_| anyhow!("Unsupported private key"))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
 // Load public certificate from file.
 pub fn load_certs<T: AsRef<Path>>(filename: T) -> Result<Vec<Certificate>> {
     // Open certificate file.
@@ -159,3 +308,29 @@ pub fn load_private_key<T: AsRef<Path>>(filename: T) -> Result<PrivateKey> {
 
     Ok(PrivateKey(keys))
 }
+
+/// Server config whose certificate is obtained and renewed from Let's Encrypt
+/// with the TLS-ALPN-01 challenge.
+pub fn acme_server_config(
+    builder: ConfigBuilder<ServerConfig, WantsVerifier>,
+    domains: &[String],
+    cache_dir: Option<&Path>,
+) -> Arc<ServerConfig> {
+    let mut state = AcmeConfig::new(domains)
+        .cache_option(cache_dir.map(|v| DirCache::new(v.to_path_buf())))
+        .directory_lets_encrypt(true)
+        .state();
+    let mut config = builder
+        .with_no_client_auth()
+        .with_cert_resolver(state.resolver());
+    config.alpn_protocols = vec![b"http/1.1".to_vec(), ACME_TLS_ALPN_NAME.to_vec()];
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => info!("ACME: {:?}", event),
+                Err(err) => error!("ACME: {:?}", err),
+            }
+        }
+    });
+    Arc::new(config)
+}
@@ -0,0 +1,152 @@
+//! `.torrent` files of the served files, with the server as their web seed (BEP 19), so
+//! downloads of large files can be shared between peers and still start from dufs.
+
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries are dropped all at once past this many, rather than tracking which are least used
+const MAX_ENTRIES: usize = 1024;
+const MIN_PIECE_LENGTH: u64 = 16 << 10;
+const MAX_PIECE_LENGTH: u64 = 16 << 20;
+/// Pieces aimed for, fewer when they'd be smaller than `MIN_PIECE_LENGTH`
+const TARGET_PIECES: u64 = 1500;
+
+/// The piece hashes of files, kept until the files change, since hashing a large file
+/// takes a while and a popular one has its torrent fetched again and again
+#[derive(Default)]
+pub struct Torrents {
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+/// The piece hashes of a file, with the mtime and size it was hashed at
+struct Entry {
+    mtime: Option<SystemTime>,
+    size: u64,
+    pieces: Vec<u8>,
+}
+
+impl Torrents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The torrent of the file, whose single web seed is `url`. Blocks while the file is
+    /// hashed.
+    pub fn torrent(&self, path: &Path, meta: &Metadata, name: &str, url: &str) -> Result<Vec<u8>> {
+        let size = meta.len();
+        let mtime = meta.modified().ok();
+        let piece_length = piece_length(size);
+        let cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(path)
+            .filter(|entry| entry.mtime == mtime && entry.size == size)
+            .map(|entry| entry.pieces.clone());
+        let pieces = match cached {
+            Some(v) => v,
+            None => {
+                let pieces = hash_pieces(path, piece_length)?;
+                let mut entries = self.entries.lock().unwrap();
+                if entries.len() >= MAX_ENTRIES {
+                    entries.clear();
+                }
+                let entry = Entry {
+                    mtime,
+                    size,
+                    pieces: pieces.clone(),
+                };
+                entries.insert(path.to_path_buf(), entry);
+                pieces
+            }
+        };
+        let creation_date = mtime
+            .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+            .map(|v| v.as_secs())
+            .unwrap_or_default();
+
+        // Keys of dictionaries go in sorted order
+        let mut output = vec![];
+        output.push(b'd');
+        bytes(&mut output, b"created by");
+        bytes(
+            &mut output,
+            format!("dufs/{}", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        bytes(&mut output, b"creation date");
+        int(&mut output, creation_date);
+        bytes(&mut output, b"info");
+        output.push(b'd');
+        bytes(&mut output, b"length");
+        int(&mut output, size);
+        bytes(&mut output, b"name");
+        bytes(&mut output, name.as_bytes());
+        bytes(&mut output, b"piece length");
+        int(&mut output, piece_length);
+        bytes(&mut output, b"pieces");
+        bytes(&mut output, &pieces);
+        output.push(b'e');
+        bytes(&mut output, b"url-list");
+        bytes(&mut output, url.as_bytes());
+        output.push(b'e');
+        Ok(output)
+    }
+}
+
+/// A power of two, so a file has about `TARGET_PIECES` pieces
+fn piece_length(size: u64) -> u64 {
+    (size / TARGET_PIECES)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+/// The SHA-1 of each piece of the file, one after the other
+fn hash_pieces(path: &Path, piece_length: u64) -> Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; piece_length as usize];
+    let mut pieces = vec![];
+    loop {
+        let mut len = 0;
+        while len < buf.len() {
+            match file.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if len == 0 {
+            break;
+        }
+        pieces.extend_from_slice(&Sha1::digest(&buf[..len]));
+        if len < buf.len() {
+            break;
+        }
+    }
+    Ok(pieces)
+}
+
+fn bytes(output: &mut Vec<u8>, value: &[u8]) {
+    output.extend_from_slice(format!("{}:", value.len()).as_bytes());
+    output.extend_from_slice(value);
+}
+
+fn int(output: &mut Vec<u8>, value: u64) {
+    output.extend_from_slice(format!("i{value}e").as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_piece_length() {
+        assert_eq!(piece_length(0), MIN_PIECE_LENGTH);
+        assert_eq!(piece_length(1 << 30), 1 << 20);
+        assert_eq!(piece_length(1 << 40), MAX_PIECE_LENGTH);
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use walkdir::WalkDir;
+
+use crate::utils::{decode_uri, encode_uri};
+
+/// Purges don't run more often than this, nor less often than hourly
+const MIN_PURGE_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Deleted files and folders, kept until the retention period is over.
+///
+/// The layout follows the freedesktop.org trash spec, the deleted entries are in
+/// `files/` and for each one a `.trashinfo` in `info/` tells where it came from.
+pub struct Trash {
+    dir: PathBuf,
+    retention: Duration,
+    seq: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrashItem {
+    pub id: String,
+    pub path: String,
+    pub deleted_at: String,
+}
+
+impl Trash {
+    pub fn new(dir: &Path, retention: Duration) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            retention,
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    pub fn purge_interval(&self) -> Duration {
+        self.retention.clamp(MIN_PURGE_INTERVAL, MAX_PURGE_INTERVAL)
+    }
+
+    /// Moves the entry at `path`, served at `rel_path`, into the trash
+    pub async fn put(&self, path: &Path, rel_path: &str) -> Result<()> {
+        fs::create_dir_all(self.dir.join("files")).await?;
+        fs::create_dir_all(self.dir.join("info")).await?;
+        let now = SystemTime::now();
+        let millis = now.duration_since(UNIX_EPOCH)?.as_millis();
+        let id = loop {
+            let id = format!("{millis}-{}", self.seq.fetch_add(1, Ordering::Relaxed));
+            if fs::symlink_metadata(self.info_path(&id)).await.is_err() {
+                break id;
+            }
+        };
+        let deleted_at = DateTime::<Local>::from(now).format("%Y-%m-%dT%H:%M:%S");
+        let info = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={deleted_at}\n",
+            encode_uri(rel_path)
+        );
+        fs::write(self.info_path(&id), info).await?;
+        if let Err(err) = move_entry(path, &self.dir.join("files").join(&id)).await {
+            let _ = fs::remove_file(self.info_path(&id)).await;
+            return Err(anyhow!(
+                "Failed to move `{}` to trash, {err}",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// The entries in the trash, the most recently deleted first
+    pub async fn list(&self) -> Result<Vec<TrashItem>> {
+        let mut items = vec![];
+        let mut rd = match fs::read_dir(self.dir.join("info")).await {
+            Ok(rd) => rd,
+            Err(_) => return Ok(items),
+        };
+        while let Some(entry) = rd.next_entry().await? {
+            let name = entry.file_name();
+            let id = match name.to_str().and_then(|v| v.strip_suffix(".trashinfo")) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            if let Some(item) = self.read_info(&id).await {
+                items.push(item);
+            }
+        }
+        items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(b.id.cmp(&a.id)));
+        Ok(items)
+    }
+
+    /// Takes an entry out of the trash, returns where it is kept and where it came from
+    pub async fn take(&self, id: &str) -> Option<(PathBuf, String)> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            return None;
+        }
+        let item = self.read_info(id).await?;
+        Some((self.dir.join("files").join(id), item.path))
+    }
+
+    /// Forgets an entry once it's been restored
+    pub async fn forget(&self, id: &str) -> Result<()> {
+        fs::remove_file(self.info_path(id)).await?;
+        Ok(())
+    }
+
+    /// Deletes for good the entries older than the retention period
+    pub async fn purge(&self) -> Result<()> {
+        let mut rd = match fs::read_dir(self.dir.join("info")).await {
+            Ok(rd) => rd,
+            Err(_) => return Ok(()),
+        };
+        while let Some(entry) = rd.next_entry().await? {
+            let name = entry.file_name();
+            let id = match name.to_str().and_then(|v| v.strip_suffix(".trashinfo")) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            let expired = entry
+                .metadata()
+                .await?
+                .modified()?
+                .elapsed()
+                .map(|v| v >= self.retention)
+                .unwrap_or_default();
+            if !expired {
+                continue;
+            }
+            let file = self.dir.join("files").join(&id);
+            match fs::symlink_metadata(&file).await {
+                Ok(meta) if meta.is_dir() => fs::remove_dir_all(&file).await?,
+                Ok(_) => fs::remove_file(&file).await?,
+                Err(_) => {}
+            }
+            fs::remove_file(entry.path()).await?;
+        }
+        Ok(())
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join("info").join(format!("{id}.trashinfo"))
+    }
+
+    async fn read_info(&self, id: &str) -> Option<TrashItem> {
+        let info = fs::read_to_string(self.info_path(id)).await.ok()?;
+        let mut path = None;
+        let mut deleted_at = None;
+        for line in info.lines() {
+            if let Some(v) = line.strip_prefix("Path=") {
+                path = Some(decode_uri(v)?.to_string());
+            } else if let Some(v) = line.strip_prefix("DeletionDate=") {
+                deleted_at = Some(v.to_string());
+            }
+        }
+        Some(TrashItem {
+            id: id.to_string(),
+            path: path?,
+            deleted_at: deleted_at.unwrap_or_default(),
+        })
+    }
+}
+
+/// Renames the entry, or copies it over and removes it if the trash is on another
+/// filesystem, like the one of a `--mount`
+pub async fn move_entry(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(src, dest).await {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {}
+        ret => return ret,
+    }
+    let (src, dest) = (src.to_path_buf(), dest.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = copy_entry(&src, &dest) {
+            let _ = remove_entry(&dest);
+            return Err(err);
+        }
+        remove_entry(&src)
+    })
+    .await?
+}
+
+fn copy_entry(src: &Path, dest: &Path) -> io::Result<()> {
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let target = match entry.path().strip_prefix(src) {
+            Ok(v) if v.as_os_str().is_empty() => dest.to_path_buf(),
+            Ok(v) => dest.join(v),
+            Err(_) => continue,
+        };
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            std::fs::create_dir(&target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_entry(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
@@ -1,6 +1,7 @@
 use hyper::server::accept::Accept;
 use tokio::net::UnixListener;
 
+use std::ffi::CStr;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
@@ -29,3 +30,21 @@ impl Accept for UnixAcceptor {
         }
     }
 }
+
+/// The name of the user with the id, if it's in the user database
+pub fn user_name(uid: libc::uid_t) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    loop {
+        let ret =
+            unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+        match ret {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if !result.is_null() => break,
+            _ => return None,
+        }
+    }
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
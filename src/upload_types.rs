@@ -0,0 +1,115 @@
+/// How many of the first bytes of a file are looked at to tell its type
+pub const SNIFF_LEN: u64 = 8192;
+
+/// The types of files that may be uploaded, by the extensions allowed or denied.
+///
+/// A file passes by its name, then by its first bytes: if they show a known type, like a
+/// png or an executable, one of the extensions of that type has to pass too, so a renamed
+/// file can't slip through.
+#[derive(Debug, Default)]
+pub struct UploadTypes {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl UploadTypes {
+    pub fn new(allow: &[String], deny: &[String]) -> Self {
+        Self {
+            allow: normalize(allow),
+            deny: normalize(deny),
+        }
+    }
+
+    /// Whether any type is refused, otherwise files aren't looked at
+    pub fn is_restricted(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    /// Whether a file of the name may be uploaded, as far as the name tells
+    pub fn allows_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let matches = |ext: &String| {
+            name.strip_suffix(ext.as_str())
+                .is_some_and(|v| v.len() > 1 && v.ends_with('.'))
+        };
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+
+    /// Whether a file starting with the bytes may be uploaded, as far as they tell
+    pub fn allows_content(&self, head: &[u8]) -> bool {
+        let kind = match infer::get(head) {
+            Some(v) => v,
+            None => return true,
+        };
+        let exts = mime_guess::get_mime_extensions_str(kind.mime_type()).unwrap_or_default();
+        std::iter::once(kind.extension())
+            .chain(exts.iter().copied())
+            .any(|ext| self.allows_ext(ext))
+    }
+
+    /// Whether a file of the name, starting with the bytes, may be uploaded
+    pub fn allows(&self, name: &str, head: &[u8]) -> bool {
+        self.allows_name(name) && self.allows_content(head)
+    }
+
+    fn allows_ext(&self, ext: &str) -> bool {
+        let ext = ext.to_lowercase();
+        (self.allow.is_empty() || self.allow.contains(&ext)) && !self.deny.contains(&ext)
+    }
+}
+
+/// Lowercase extensions without their leading dot, `.tar.gz` and `tar.gz` alike, from lists
+/// that may be comma-separated
+fn normalize(exts: &[String]) -> Vec<String> {
+    exts.iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().trim_start_matches('.').to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    /// The start of an executable, as long as it takes to be told apart
+    fn elf() -> Vec<u8> {
+        let mut head = b"\x7fELF\x02\x01\x01".to_vec();
+        head.resize(64, 0);
+        head
+    }
+
+    fn types(allow: &[&str], deny: &[&str]) -> UploadTypes {
+        let strings = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        UploadTypes::new(&strings(allow), &strings(deny))
+    }
+
+    #[test]
+    fn test_allows_name() {
+        let images = types(&["jpg, .PNG"], &[]);
+        assert!(images.allows_name("a.png"));
+        assert!(images.allows_name("A.JPG"));
+        assert!(!images.allows_name("a.exe"));
+        assert!(!images.allows_name("png"));
+        assert!(!images.allows_name(".png"));
+        let archives = types(&[], &["exe", "tar.gz"]);
+        assert!(archives.allows_name("a.tar"));
+        assert!(archives.allows_name("README"));
+        assert!(!archives.allows_name("a.tar.gz"));
+        assert!(!archives.allows_name("a.EXE"));
+        assert!(!types(&[], &[]).is_restricted());
+    }
+
+    #[test]
+    fn test_allows_content() {
+        let images = types(&["jpeg", "png"], &[]);
+        assert!(images.allows("a.png", PNG));
+        assert!(images.allows("a.png", b"plain text"));
+        assert!(!images.allows("a.png", &elf()));
+        let no_binaries = types(&[], &["elf", "exe"]);
+        assert!(no_binaries.allows("a.png", PNG));
+        assert!(!no_binaries.allows("a.png", &elf()));
+    }
+}
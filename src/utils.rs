@@ -1,11 +1,28 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Utc};
+use ipnet::IpNet;
 use std::{
     borrow::Cow,
+    net::IpAddr,
     path::Path,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+/// Free space available to unprivileged users on the filesystem of the path, or
+/// of its closest existing ancestor
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = path.ancestors().find(|v| v.exists()).unwrap_or(path);
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
 pub fn unix_now() -> Result<Duration> {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -17,7 +34,7 @@ pub fn encode_uri(v: &str) -> String {
     parts.join("/")
 }
 
-pub fn decode_uri(v: &str) -> Option<Cow<str>> {
+pub fn decode_uri(v: &str) -> Option<Cow<'_, str>> {
     percent_encoding::percent_decode(v.as_bytes())
         .decode_utf8()
         .ok()
@@ -29,17 +46,20 @@ pub fn get_file_name(path: &Path) -> &str {
         .unwrap_or_default()
 }
 
-#[cfg(unix)]
 pub async fn get_file_mtime_and_mode(path: &Path) -> Result<(DateTime<Utc>, u16)> {
-    use std::os::unix::prelude::MetadataExt;
     let meta = tokio::fs::metadata(path).await?;
+    mtime_and_mode(&meta)
+}
+
+#[cfg(unix)]
+pub fn mtime_and_mode(meta: &std::fs::Metadata) -> Result<(DateTime<Utc>, u16)> {
+    use std::os::unix::prelude::MetadataExt;
     let datetime: DateTime<Utc> = meta.modified()?.into();
     Ok((datetime, meta.mode() as u16))
 }
 
 #[cfg(not(unix))]
-pub async fn get_file_mtime_and_mode(path: &Path) -> Result<(DateTime<Utc>, u16)> {
-    let meta = tokio::fs::metadata(&path).await?;
+pub fn mtime_and_mode(meta: &std::fs::Metadata) -> Result<(DateTime<Utc>, u16)> {
     let datetime: DateTime<Utc> = meta.modified()?.into();
     Ok((datetime, 0o644))
 }
@@ -50,6 +70,95 @@ pub fn try_get_file_name(path: &Path) -> Result<&str> {
         .ok_or_else(|| anyhow!("Failed to get file name of `{}`", path.display()))
 }
 
+/// The path relative to the root that `path` names from the folder `base`, itself
+/// relative to the root. It can't go above the root.
+pub fn resolve_relative(base: &str, path: &str) -> String {
+    let mut names: Vec<&str> = match path.starts_with('/') {
+        true => vec![],
+        false => base.split('/').filter(|v| !v.is_empty()).collect(),
+    };
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            v => names.push(v),
+        }
+    }
+    names.join("/")
+}
+
+/// Where an entry of an archive goes, relative to the folder it is extracted in. `None` for
+/// the names that would escape it, like `../x`, `/etc/x` or `C:\x`.
+pub fn archive_entry_path(name: &str) -> Option<String> {
+    let name = name.replace('\\', "/");
+    let mut parts = name.split('/').peekable();
+    let is_drive = |v: &str| v.len() == 2 && v.ends_with(':');
+    if name.starts_with('/') || parts.peek().is_some_and(|v| is_drive(v)) {
+        return None;
+    }
+    let mut names = vec![];
+    for part in parts {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            v => names.push(v),
+        }
+    }
+    Some(names.join("/"))
+}
+
+/// Parse human readable size such as `512`, `10K`, `100M`, `2G`, suffixes are powers of 1024
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (num, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("Invalid size `{value}`"),
+    };
+    let num: u64 = num.parse().map_err(|_| anyhow!("Invalid size `{value}`"))?;
+    num.checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("Invalid size `{value}`"))
+}
+
+/// Parse human readable duration such as `30`, `30s`, `10m`, `2h`, `1d`, plain numbers are seconds
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (num, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => bail!("Invalid duration `{value}`"),
+    };
+    let num: u64 = num
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration `{value}`"))?;
+    num.checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| anyhow!("Invalid duration `{value}`"))
+}
+
+/// Parse a CIDR such as `10.0.0.0/8`, or a single IP
+pub fn parse_ip_net(value: &str) -> Result<IpNet> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow!("Invalid IP or CIDR `{value}`"))
+}
+
 pub fn glob(pattern: &str, target: &str) -> bool {
     let pat = match ::glob::Pattern::new(pattern) {
         Ok(pat) => pat,
@@ -58,6 +167,58 @@ pub fn glob(pattern: &str, target: &str) -> bool {
     pat.matches(target)
 }
 
+/// Fills the `__NAME__` placeholders of the template in a single pass, so the values
+/// are never taken for placeholders themselves. Unknown placeholders are left as is.
+pub fn render_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("__") {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let found = vars.iter().find(|(name, _)| {
+            rest[2..].starts_with(name) && rest[2 + name.len()..].starts_with("__")
+        });
+        match found {
+            Some((name, value)) => {
+                output.push_str(value);
+                rest = &rest[name.len() + 4..];
+            }
+            None => {
+                output.push('_');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Renders markdown as HTML, leaving out raw HTML and script links so the result is safe to embed
+pub fn render_markdown(text: &str) -> String {
+    use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+    fn safe_url(url: CowStr) -> CowStr {
+        let scheme = url.trim_start().to_ascii_lowercase();
+        if scheme.starts_with("javascript:") || scheme.starts_with("vbscript:") {
+            CowStr::Borrowed("")
+        } else {
+            url
+        }
+    }
+    let parser = Parser::new_ext(text, Options::all()).map(|event| match event {
+        Event::Html(v) => Event::Text(v),
+        Event::Start(Tag::Link(kind, url, title)) => {
+            Event::Start(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) => {
+            Event::Start(Tag::Image(kind, safe_url(url), title))
+        }
+        v => v,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    output
+}
+
 #[test]
 fn test_glob_key() {
     assert!(glob("", ""));
@@ -81,3 +242,76 @@ fn test_glob_key() {
     assert!(glob("*/", "abc/"));
     assert!(!glob("*/", "abc"));
 }
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("512").unwrap(), 512);
+    assert_eq!(parse_size("10K").unwrap(), 10240);
+    assert_eq!(parse_size("1kb").unwrap(), 1024);
+    assert_eq!(parse_size("100M").unwrap(), 100 * 1024 * 1024);
+    assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+    assert!(parse_size("").is_err());
+    assert!(parse_size("10X").is_err());
+    assert!(parse_size("M").is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+    assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+    assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+    assert_eq!(parse_duration("2H").unwrap(), Duration::from_secs(7200));
+    assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("1w").is_err());
+    assert!(parse_duration("m").is_err());
+}
+
+#[test]
+fn test_render_markdown() {
+    assert_eq!(
+        render_markdown("# Title\n\n*a*"),
+        "<h1>Title</h1>\n<p><em>a</em></p>\n"
+    );
+    assert_eq!(
+        render_markdown("<script>alert(1)</script>"),
+        "&lt;script&gt;alert(1)&lt;/script&gt;"
+    );
+    assert_eq!(
+        render_markdown("[a](javascript:alert(1))"),
+        "<p><a href=\"\">a</a></p>\n"
+    );
+}
+
+#[test]
+fn test_render_template() {
+    let vars = [("NAME", "__DATA__"), ("DATA", "{}")];
+    assert_eq!(render_template("<b>__NAME__</b>", &vars), "<b>__DATA__</b>");
+    assert_eq!(render_template("__DATA____NAME__", &vars), "{}__DATA__");
+    assert_eq!(render_template("___DATA__", &vars), "_{}");
+    assert_eq!(render_template("__OTHER__ _", &vars), "__OTHER__ _");
+}
+
+#[test]
+fn test_resolve_relative() {
+    assert_eq!(resolve_relative("", "dir1"), "dir1");
+    assert_eq!(resolve_relative("dir1", "sub/a.txt"), "dir1/sub/a.txt");
+    assert_eq!(resolve_relative("dir1/sub", ".."), "dir1");
+    assert_eq!(resolve_relative("dir1", "/dir2/./a.txt"), "dir2/a.txt");
+    assert_eq!(
+        resolve_relative("dir1", "../../../etc/passwd"),
+        "etc/passwd"
+    );
+    assert_eq!(resolve_relative("dir1", ""), "dir1");
+}
+
+#[test]
+fn test_archive_entry_path() {
+    assert_eq!(archive_entry_path("a/b.txt").as_deref(), Some("a/b.txt"));
+    assert_eq!(archive_entry_path("./a//b/").as_deref(), Some("a/b"));
+    assert_eq!(archive_entry_path("a\\b.txt").as_deref(), Some("a/b.txt"));
+    assert_eq!(archive_entry_path("./").as_deref(), Some(""));
+    assert_eq!(archive_entry_path("a/../../b"), None);
+    assert_eq!(archive_entry_path("/etc/passwd"), None);
+    assert_eq!(archive_entry_path("C:\\x"), None);
+}
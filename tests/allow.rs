@@ -27,6 +27,13 @@ fn default_not_allow_archive(server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn default_not_allow_tar(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?tar.gz", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
 #[rstest]
 fn default_not_exist_dir(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}404/", server.url()))?;
@@ -51,6 +58,24 @@ fn allow_upload_no_override(#[with(&["--allow-upload"])] server: TestServer) ->
     Ok(())
 }
 
+#[rstest]
+fn allow_upload_partial_append(
+    #[with(&["--allow-upload"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 0-2/*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 18-20/*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    Ok(())
+}
+
 #[rstest]
 fn allow_delete_no_override(#[with(&["--allow-delete"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}index.html", server.url());
@@ -0,0 +1,224 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use reqwest::blocking::Client;
+use rstest::rstest;
+use serde_json::Value;
+
+fn api_url(server: &TestServer, route: &str) -> String {
+    format!("{}__dufs__/api/v1/{route}", server.url())
+}
+
+#[rstest]
+fn api_list(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(api_url(&server, "list/dir1"))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["path"], "/dir1");
+    let entries = json["entries"].as_array().unwrap();
+    let names: Vec<_> = entries
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["index.html", "test.html", "test.txt", "😀.bin"]);
+    assert_eq!(entries[2]["path"], "/dir1/test.txt");
+    assert_eq!(entries[2]["type"], "file");
+    assert_eq!(entries[2]["size"], 21);
+    assert!(entries[2]["mtime"].as_u64().is_some());
+    let resp = reqwest::blocking::get(api_url(&server, "list"))?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["path"], "/");
+    let dir1 = json["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|v| v["name"] == "dir1")
+        .unwrap();
+    assert_eq!(dir1["path"], "/dir1");
+    assert_eq!(dir1["type"], "dir");
+    assert_eq!(dir1["size"], Value::Null);
+    Ok(())
+}
+
+#[rstest]
+fn api_stat(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(api_url(&server, "stat/dir1/test.html"))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["name"], "test.html");
+    assert_eq!(json["path"], "/dir1/test.html");
+    assert_eq!(json["type"], "file");
+    assert_eq!(json["symlink"], false);
+    let resp = reqwest::blocking::get(api_url(&server, "stat/dir1"))?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["path"], "/dir1");
+    assert_eq!(json["type"], "dir");
+    let resp = reqwest::blocking::get(api_url(&server, "stat/missing"))?;
+    assert_eq!(resp.status(), 404);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["error"], "Not Found");
+    Ok(())
+}
+
+#[rstest]
+fn api_search(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(api_url(&server, "search/dir1?q=test"))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    let paths: Vec<_> = json["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["/dir1/test.html", "/dir1/test.txt"]);
+    assert_eq!(json["truncated"], false);
+    let resp = reqwest::blocking::get(api_url(&server, "search/dir1"))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn api_hash(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(api_url(&server, "hash/index.html"))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["algo"], "sha256");
+    assert_eq!(
+        json["hash"],
+        "c8dd395e3202674b9512f7b7f956e0d96a8ba8f572e785b0d5413ab83766dbc4"
+    );
+    let resp = reqwest::blocking::get(api_url(&server, "hash/index.html?algo=md5"))?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["algo"], "md5");
+    let resp = reqwest::blocking::get(api_url(&server, "hash/index.html?algo=crc"))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn api_diff(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let manifest = serde_json::json!([
+        {
+            "path": "index.html",
+            "size": 18,
+            "hash": "C8DD395E3202674B9512F7B7F956E0D96A8BA8F572E785B0D5413AB83766DBC4"
+        },
+        {"path": "dir1/test.txt", "size": 21, "hash": "0000"},
+        {"path": "test.html", "size": 1},
+        {"path": "test.txt", "size": 16},
+        {"path": "dir1", "size": 0},
+        {"path": "new/new.txt", "size": 3},
+        {"path": "../index.html", "size": 18}
+    ]);
+    let resp = Client::new()
+        .post(api_url(&server, "diff"))
+        .body(manifest.to_string())
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["path"], "/");
+    assert_eq!(json["algo"], "sha256");
+    assert_eq!(json["missing"], serde_json::json!(["new/new.txt"]));
+    assert_eq!(
+        json["changed"],
+        serde_json::json!(["dir1/test.txt", "test.html", "dir1"])
+    );
+    assert_eq!(json["skipped"], serde_json::json!(["../index.html"]));
+
+    // A folder yet to be made misses everything
+    let resp = Client::new()
+        .post(api_url(&server, "diff/new"))
+        .body(r#"[{"path": "index.html", "size": 18}]"#)
+        .send()?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["missing"], serde_json::json!(["index.html"]));
+
+    let resp = Client::new()
+        .post(api_url(&server, "diff/index.html"))
+        .body(manifest.to_string())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = Client::new()
+        .post(api_url(&server, "diff"))
+        .body(r#"[{"path": "index.html"}]"#)
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let json: Value = resp.text()?.parse()?;
+    assert!(json["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Invalid manifest"));
+    Ok(())
+}
+
+#[rstest]
+fn api_writes(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let client = Client::new();
+    let resp = client.post(api_url(&server, "mkdir/new/sub")).send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = client
+        .post(api_url(&server, "copy/test.html?to=new/sub/copied.html"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = client
+        .post(api_url(
+            &server,
+            "move/new/sub/copied.html?to=/new/moved.html",
+        ))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = reqwest::blocking::get(format!("{}new/moved.html", server.url()))?;
+    assert_eq!(resp.text()?, "This is test.html");
+    let resp = client
+        .post(api_url(
+            &server,
+            "copy/test.txt?to=new/moved.html&overwrite=false",
+        ))
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = client.post(api_url(&server, "move/test.txt")).send()?;
+    assert_eq!(resp.status(), 400);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["error"], "Missing `to`");
+    let resp = client.post(api_url(&server, "delete/new")).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(api_url(&server, "stat/new"))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn api_not_allowed(server: TestServer) -> Result<(), Error> {
+    let client = Client::new();
+    let resp = client.post(api_url(&server, "mkdir/new")).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = client.get(api_url(&server, "mkdir/new")).send()?;
+    assert_eq!(resp.status(), 405);
+    let resp = client.post(api_url(&server, "diff")).body("[]").send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = client.get(api_url(&server, "unknown/new")).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn api_auth(
+    #[with(&["--auth", "user:pass@/:rw", "--auth", "@/dir1", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(api_url(&server, "list/dir1"))?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(api_url(&server, "list/dir2"))?;
+    assert_eq!(resp.status(), 401);
+    let resp = Client::new()
+        .post(api_url(&server, "mkdir/dir1/new"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = Client::new()
+        .post(api_url(&server, "mkdir/dir1/new"))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
@@ -123,3 +123,27 @@ fn assets_override(tmpdir: TempDir, port: u16) -> Result<(), Error> {
     child.kill()?;
     Ok(())
 }
+
+#[rstest]
+fn assets_branding(
+    #[with(&[
+        "--site-name",
+        "My <Files>",
+        "--logo-url",
+        "/logo.png?a&b",
+        "--header-html",
+        "<b>__INDEX_DATA__</b>",
+        "--footer-html",
+        "<p>Hosted by us</p>",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    let text = resp.text()?;
+    assert!(text.contains("<title>My &lt;Files>"));
+    assert!(text.contains(r#"data-logo="/logo.png?a&amp;b""#));
+    // Values aren't taken for placeholders
+    assert!(text.contains(r#"<div class="site-header"><b>__INDEX_DATA__</b></div>"#));
+    assert!(text.contains(r#"<div class="site-footer"><p>Hosted by us</p></div>"#));
+    Ok(())
+}
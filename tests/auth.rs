@@ -32,11 +32,15 @@ fn auth(#[case] server: TestServer, #[case] user: &str, #[case] pass: &str) -> R
 }
 
 const HASHED_PASSWORD_AUTH: &str =  "user:$6$gQxZwKyWn/ZmWEA2$4uV7KKMnSUnET2BtWTj/9T5.Jq3h/MdkOlnIl5hdlTxDZ4MZKmJ.kl6C.NL9xnNPqC4lVHC1vuI0E5cLpTJX81@/:rw"; // user:pass
+const BCRYPT_PASSWORD_AUTH: &str =
+    "user:$2y$04$jT4SXfPy0CAEo0p5YQLl8OCXEwAMOaxkgzD/V3tYak8SLMLqVE9D2@/:rw"; // user:pass
+const ARGON2_PASSWORD_AUTH: &str = "user:$argon2id$v=19$m=19456,t=2,p=1$y6ciTRcDsu+U689i7viqfQ$A5u5Bw6/h30sus1406J1XYBupHYOWSAjaIoufSYBpN0@/:rw"; // user:pass
 
 #[rstest]
-fn auth_hashed_password(
-    #[with(&["--auth", HASHED_PASSWORD_AUTH, "-A"])] server: TestServer,
-) -> Result<(), Error> {
+#[case(server(&["--auth", HASHED_PASSWORD_AUTH, "-A"]))]
+#[case(server(&["--auth", BCRYPT_PASSWORD_AUTH, "-A"]))]
+#[case(server(&["--auth", ARGON2_PASSWORD_AUTH, "-A"]))]
+fn auth_hashed_password(#[case] server: TestServer) -> Result<(), Error> {
     let url = format!("{}file1", server.url());
     let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
     assert_eq!(resp.status(), 401);
@@ -244,6 +248,28 @@ fn auth_partial_index(
     Ok(())
 }
 
+#[rstest]
+fn auth_partial_index_recursive(
+    #[with(&["--auth", "user:pass@/dir1:rw,/dir2:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}?json&recursive", server.url()))
+        .send_with_digest_auth("user", "pass")?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.text()?.parse()?;
+    let names: Vec<&str> = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap())
+        .collect();
+    assert!(names.contains(&"dir1/test.html"));
+    assert!(names.contains(&"dir2/test.html"));
+    assert!(!names
+        .iter()
+        .any(|v| v.starts_with("dir3") || *v == "test.html"));
+    Ok(())
+}
+
 #[rstest]
 fn no_auth_propfind_dir(
     #[with(&["--auth", "user:pass@/:rw", "--auth", "@/dir-assets", "-A"])] server: TestServer,
@@ -274,3 +300,378 @@ fn auth_data(
     assert_eq!(json["allow_upload"], serde_json::Value::Bool(true));
     Ok(())
 }
+
+#[rstest]
+fn auth_upload_only(
+    #[with(&["-a", "user:pass@/:rw", "-a", "@/dir1:wo", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url).body(b"def".to_vec()).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"MKCOL", format!("{}dir1/newdir", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = reqwest::blocking::get(format!("{}dir1/", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json = utils::retrieve_json(&resp.text()?).unwrap();
+    assert_eq!(json["paths"], serde_json::json!([]));
+    assert_eq!(json["allow_upload"], serde_json::Value::Bool(true));
+    assert_eq!(json["allow_delete"], serde_json::Value::Bool(false));
+
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"PROPFIND", &url).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"DELETE", &url).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"GET", &url).send_with_digest_auth("user", "pass")?;
+    assert_eq!(resp.text()?, "abc");
+    Ok(())
+}
+
+#[rstest]
+fn auth_upload_only_move_into(
+    #[with(&["-a", "user:pass@/:rw", "-a", "user2:pass2@/dir2:rw,/dir1:wo", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let copy = |dest: &str| -> Result<u16, Error> {
+        let resp = fetch!(b"COPY", format!("{}dir2/test.html", server.url()))
+            .header("Destination", format!("{}{dest}", server.url()))
+            .send_with_digest_auth("user2", "pass2")?;
+        Ok(resp.status().as_u16())
+    };
+    // Existing entries of the dropbox can't be replaced
+    assert_eq!(copy("dir1/test.html")?, 403);
+    assert_eq!(copy("dir1/new.html")?, 201);
+    let resp = fetch!(b"GET", format!("{}dir1/test.html", server.url()))
+        .send_with_digest_auth("user", "pass")?;
+    assert_eq!(resp.text()?, "This is dir1/test.html");
+    Ok(())
+}
+
+#[rstest]
+fn auth_upload_only_search(
+    #[with(&["-a", "user:pass@/:rw", "-a", "@/dir1:wo,/dir2", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q=test.html", server.url()))?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(!paths.is_empty());
+    assert!(paths.iter().all(|v| v.starts_with("dir2/")));
+    Ok(())
+}
+
+fn create_jwt(secret: &str, kid: Option<&str>, claims: serde_json::Value) -> String {
+    let header = jsonwebtoken::Header {
+        kid: kid.map(|v| v.to_string()),
+        ..Default::default()
+    };
+    let mut claims = claims;
+    if claims.get("exp").is_none() {
+        claims["exp"] = serde_json::json!(u32::MAX);
+    }
+    jsonwebtoken::encode(
+        &header,
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap()
+}
+
+#[rstest]
+fn auth_jwt(
+    #[with(&["--jwt-secret", "secret", "-a", "user:pass@/dir1:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 401);
+    let challenges: Vec<_> = resp.headers().get_all("www-authenticate").iter().collect();
+    assert!(challenges.iter().any(|v| *v == "Bearer realm=\"DUFS\""));
+    let token = create_jwt("secret", None, serde_json::json!({ "sub": "user" }));
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let token = create_jwt("wrong", None, serde_json::json!({ "sub": "user" }));
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let token = create_jwt(
+        "secret",
+        None,
+        serde_json::json!({ "sub": "user", "exp": 1 }),
+    );
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn auth_jwt_scope(
+    #[with(&["--jwt-secret", "secret", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let token = create_jwt(
+        "secret",
+        None,
+        serde_json::json!({ "sub": "other", "scope": "openid dufs:/dir1:rw dufs:/dir2" }),
+    );
+    let resp = fetch!(b"PUT", format!("{}dir1/file1", server.url()))
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{}dir2/file1", server.url()))
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}dir2/", server.url()))
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", format!("{}dir3/", server.url()))
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}dir2/", server.url())).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn auth_jwt_scope_extract(
+    #[with(&["--jwt-secret", "secret", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let token = create_jwt(
+        "secret",
+        None,
+        serde_json::json!({ "sub": "other", "scope": "dufs:/dir1 dufs:/new:rw" }),
+    );
+    let archive = fetch!(b"GET", format!("{}dir1?zip", server.url()))
+        .bearer_auth(&token)
+        .send()?
+        .bytes()?;
+    let resp = fetch!(b"PUT", format!("{}new/dir1.zip?unzip", server.url()))
+        .body(archive.clone())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("new/test.txt").is_file());
+    let resp = fetch!(b"PUT", format!("{}dir1/dir1.zip?unzip", server.url()))
+        .body(archive)
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_jwt_audience(
+    #[with(&["--jwt-secret", "secret", "--jwt-audience", "dufs", "-a", "@/"])] server: TestServer,
+) -> Result<(), Error> {
+    let token = create_jwt("secret", None, serde_json::json!({ "aud": "other" }));
+    let resp = fetch!(b"GET", server.url()).bearer_auth(&token).send()?;
+    assert_eq!(resp.status(), 401);
+    let token = create_jwt("secret", None, serde_json::json!({ "aud": "dufs" }));
+    let resp = fetch!(b"GET", server.url()).bearer_auth(&token).send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn auth_jwt_jwks(
+    #[with(&["--jwt-jwks", "tests/data/jwks.json", "-a", "user:pass@/:rw"])] server: TestServer,
+) -> Result<(), Error> {
+    let token = create_jwt("secret", Some("key1"), serde_json::json!({ "sub": "user" }));
+    let resp = fetch!(b"GET", server.url()).bearer_auth(&token).send()?;
+    assert_eq!(resp.status(), 200);
+    let token = create_jwt("secret", Some("key2"), serde_json::json!({ "sub": "user" }));
+    let resp = fetch!(b"GET", server.url()).bearer_auth(&token).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn auth_proxy(
+    #[with(&["--auth-proxy-header", "X-Remote-User", "-a", "user:pass@/dir1:rw", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .header("X-Remote-User", "user")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .header("X-Remote-User", "other")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_proxy_untrusted(
+    #[with(&["--auth-proxy-header", "X-Remote-User", "--auth-proxy-trusted", "10.0.0.0/8", "-a", "user:pass@/:rw"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url())
+        .header("X-Remote-User", "user")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn auth_form(
+    #[with(&["--auth-method", "form", "-a", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let resp = client
+        .get(server.url())
+        .header("accept", "text/html")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    assert!(!resp.headers().contains_key("www-authenticate"));
+    assert!(resp.text()?.contains("<form"));
+
+    let login_url = format!("{}?login", server.url());
+    let resp = client
+        .post(&login_url)
+        .form(&[("username", "user"), ("password", "wrong")])
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    assert!(resp.text()?.contains("Invalid username or password"));
+
+    let resp = client
+        .post(&login_url)
+        .form(&[("username", "user"), ("password", "pass")])
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    let cookie = resp.headers()["set-cookie"].to_str()?;
+    assert!(cookie.starts_with("dufs_session="));
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    let url = format!("{}file1", server.url());
+    let resp = client
+        .put(&url)
+        .header("cookie", &cookie)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = client
+        .get(format!("{}?logout", server.url()))
+        .header("cookie", &cookie)
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    let resp = client.get(&url).header("cookie", &cookie).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn auth_form_redirect(
+    #[with(&["--auth-method", "form", "-a", "user:pass@/:rw"])] server: TestServer,
+) -> Result<(), Error> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let resp = client
+        .post(format!("{}/evil.com/?login", server.url()))
+        .form(&[("username", "user"), ("password", "pass")])
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(resp.headers()["location"], "/evil.com/");
+    let resp = client
+        .get(format!("{}/evil.com?logout", server.url()))
+        .send()?;
+    assert_eq!(resp.headers()["location"], "/evil.com");
+    // The form is small, a larger body isn't read
+    let resp = client
+        .post(format!("{}?login", server.url()))
+        .body(vec![b'x'; 65536])
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    Ok(())
+}
+
+#[rstest]
+fn auth_form_basic_fallback(
+    #[with(&["--auth-method", "form", "-a", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().contains_key("www-authenticate"));
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .send_with_digest_auth("user", "pass")?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn auth_lockout(
+    #[with(&["--auth", "user:pass@/:rw", "--auth", "@/", "--auth-lockout", "2/1m", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    for _ in 0..2 {
+        let resp = fetch!(b"PUT", &url)
+            .body(b"abc".to_vec())
+            .send_with_digest_auth("user", "wrong")?;
+        assert_eq!(resp.status(), 401);
+    }
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .send_with_digest_auth("user", "pass")?;
+    assert_eq!(resp.status(), 429);
+    assert!(resp.headers().contains_key("retry-after"));
+    let resp = fetch!(b"GET", server.url()).send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn auth_lockout_reset(
+    #[with(&["--auth", "user:pass@/:rw", "--auth-lockout", "2/1m", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    for _ in 0..3 {
+        let resp = fetch!(b"PUT", &url)
+            .body(b"abc".to_vec())
+            .send_with_digest_auth("user", "wrong")?;
+        assert_eq!(resp.status(), 401);
+        let resp = fetch!(b"PUT", &url)
+            .body(b"abc".to_vec())
+            .send_with_digest_auth("user", "pass")?;
+        assert_eq!(resp.status(), 201);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn auth_lockout_off(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    for _ in 0..12 {
+        let resp = fetch!(b"PUT", &url)
+            .body(b"abc".to_vec())
+            .send_with_digest_auth("user", "wrong")?;
+        assert_eq!(resp.status(), 401);
+    }
+    Ok(())
+}
@@ -6,8 +6,10 @@ use assert_cmd::prelude::*;
 use assert_fs::fixture::TempDir;
 use regex::Regex;
 use rstest::rstest;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read};
 use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 #[rstest]
 #[case(&["-b", "20.205.243.166"])]
@@ -82,3 +84,67 @@ fn validate_printed_urls(tmpdir: TempDir, port: u16, #[case] args: &[&str]) -> R
 
     Ok(())
 }
+
+#[rstest]
+#[case(&["-b", "127.0.0.1"])]
+#[case(&["-b", "127.0.0.1", "-b", "::1"])]
+fn bind_ephemeral_port(tmpdir: TempDir, #[case] args: &[&str]) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg("0")
+        .arg("--urls-file")
+        .arg("-")
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.as_mut().expect("Failed to get stdout")).read_line(&mut line)?;
+    let output: serde_json::Value = serde_json::from_str(&line)?;
+    let port = output["port"].as_u64().unwrap();
+    assert_ne!(port, 0);
+    let urls = output["urls"].as_array().unwrap();
+    assert_eq!(urls.len(), args.len() / 2);
+    for url in urls {
+        let url = url.as_str().unwrap();
+        assert!(url.ends_with(&format!(":{port}/")));
+        reqwest::blocking::get(url)?.error_for_status()?;
+    }
+
+    child.kill()?;
+    child.wait()?;
+
+    Ok(())
+}
+
+#[rstest]
+fn urls_file(tmpdir: TempDir) -> Result<(), Error> {
+    let urls_file = tmpdir.path().join("urls.json");
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg("0")
+        .arg("-b")
+        .arg("127.0.0.1")
+        .arg("--urls-file")
+        .arg(&urls_file)
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    let start = Instant::now();
+    while !urls_file.exists() && start.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(50));
+    }
+    let output: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&urls_file)?)?;
+    let port = output["port"].as_u64().unwrap();
+    assert_eq!(
+        output["urls"],
+        serde_json::json!([format!("http://127.0.0.1:{port}/")])
+    );
+
+    child.kill()?;
+    child.wait()?;
+
+    Ok(())
+}
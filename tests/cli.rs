@@ -26,7 +26,161 @@ fn print_completions() -> Result<(), Error> {
             .arg(shell.to_string())
             .assert()
             .success();
+        assert_cmd::Command::cargo_bin("dufs")?
+            .arg("completions")
+            .arg(shell.to_string())
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("tls-cert"));
+    }
+
+    Ok(())
+}
+
+#[test]
+/// Print the man page and exit.
+fn print_manpage() -> Result<(), Error> {
+    assert_cmd::Command::cargo_bin("dufs")?
+        .arg("manpage")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(".TH dufs 1"))
+        .stdout(predicates::str::contains("allow\\-upload"));
+
+    Ok(())
+}
+
+#[test]
+/// Hash a password read from stdin and exit.
+fn hash_password() -> Result<(), Error> {
+    for (algorithm, prefix) in [("argon2", "$argon2id$"), ("sha-512", "$6$")] {
+        assert_cmd::Command::cargo_bin("dufs")?
+            .args(["hash", "-m", algorithm])
+            .write_stdin("pass\n")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with(prefix));
     }
 
     Ok(())
 }
+
+#[test]
+/// Print a config file with every option commented out and exit.
+fn gen_config() -> Result<(), Error> {
+    assert_cmd::Command::cargo_bin("dufs")?
+        .arg("gen-config")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("\n# allow-upload: true\n"))
+        .stdout(predicates::str::contains("\n# auth:\n#   - <rules>\n"));
+
+    Ok(())
+}
+
+#[test]
+/// Check the options and exit.
+fn check_options() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["check", "-a", "user:pass@/:rw", "-p", "1"])
+        .assert()
+        .success();
+    Command::cargo_bin("dufs")?
+        .args(["check", "-a", "user:pass@/:xx"])
+        .assert()
+        .failure();
+    Command::cargo_bin("dufs")?
+        .args(["check", "--tls-cert", "tests/data/cert.pem"])
+        .args(["--tls-key", "tests/data/cert.pem"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+/// Object storage is served with the credentials of the environment, and without the
+/// options that need a local filesystem.
+fn serve_s3_target() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["check", "s3://bucket/prefix"])
+        .env_remove("AWS_ACCESS_KEY_ID")
+        .env_remove("AWS_SECRET_ACCESS_KEY")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY",
+        ));
+
+    Command::cargo_bin("dufs")?
+        .args(["check", "s3://bucket/prefix", "--dlna"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Cannot use `--dlna` when serving object storage",
+        ));
+
+    Command::cargo_bin("dufs")?
+        .args(["check", "s3://bucket/prefix", "--allow-search"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "`--allow-search` is not supported for s3:// targets",
+        ));
+
+    Ok(())
+}
+
+#[test]
+/// The buffer size is kept within reason.
+fn invalid_buffer_size() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["--buffer-size", "32M"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("must be between 4K and 16M"));
+
+    Ok(())
+}
+
+#[test]
+/// An invalid rate limit is shown with an example.
+fn invalid_rate_limit() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["--rate-limit", "10/s"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid rate limit `10/s`, e.g. 10r/s",
+        ));
+
+    Ok(())
+}
+
+#[test]
+/// A `$` in the target of a redirect must refer to a `*` of the pattern.
+fn invalid_redirect() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["--redirect", "/old/*=/new/$2"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid redirect `/old/*=/new/$2`, `$` must be followed by the number of a `*`",
+        ));
+
+    Ok(())
+}
+
+#[test]
+/// The SPA fallback is a file name, not a path.
+fn invalid_spa_fallback() -> Result<(), Error> {
+    Command::cargo_bin("dufs")?
+        .args(["--render-spa", "--spa-fallback", "../index.html"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid SPA fallback `../index.html`, it must be a file name like index.html",
+        ));
+
+    Ok(())
+}
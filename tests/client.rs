@@ -0,0 +1,140 @@
+mod fixtures;
+
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use assert_fs::prelude::*;
+use fixtures::{server, tmpdir, Error, TestServer, BIN_FILE, FILES};
+use rstest::rstest;
+use std::process::Command;
+
+fn dufs(args: &[&str]) -> Result<Command, Error> {
+    let mut cmd = Command::cargo_bin("dufs")?;
+    cmd.args(args);
+    Ok(cmd)
+}
+
+#[rstest]
+fn client_upload(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let local = TempDir::new()?;
+    local.child("docs/a.txt").write_str("a")?;
+    local.child("docs/sub/b b.txt").write_str("b")?;
+    local.child("docs/empty").create_dir_all()?;
+    local.child("c.txt").write_str("c")?;
+    let url = format!("{}dir1/", server.url());
+    dufs(&["upload", "docs", "c.txt", &url])?
+        .current_dir(local.path())
+        .assert()
+        .success();
+    let dir = server.path().join("dir1");
+    assert_eq!(std::fs::read_to_string(dir.join("docs/a.txt"))?, "a");
+    assert_eq!(std::fs::read_to_string(dir.join("docs/sub/b b.txt"))?, "b");
+    assert!(dir.join("docs/empty").is_dir());
+    assert_eq!(std::fs::read_to_string(dir.join("c.txt"))?, "c");
+    Ok(())
+}
+
+#[rstest]
+fn client_upload_auth(
+    #[with(&["--auth", "user:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let local = TempDir::new()?;
+    local.child("a.txt").write_str("a")?;
+    let path = local.path().join("a.txt");
+    let path = path.to_str().unwrap();
+    dufs(&["upload", path, server.url().as_str()])?
+        .assert()
+        .failure();
+    dufs(&["upload", "-u", "user:pass", path, server.url().as_str()])?
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(server.path().join("a.txt"))?, "a");
+    Ok(())
+}
+
+#[rstest]
+fn client_download(server: TestServer) -> Result<(), Error> {
+    let local = TempDir::new()?;
+    let dest = local.path().to_str().unwrap();
+    dufs(&["download", &format!("{}dir1", server.url()), dest])?
+        .assert()
+        .success();
+    for file in FILES {
+        assert_eq!(
+            std::fs::read(local.path().join("dir1").join(file))?,
+            std::fs::read(server.path().join("dir1").join(file))?
+        );
+    }
+    dufs(&["download", &format!("{}{BIN_FILE}", server.url()), dest])?
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(local.path().join(BIN_FILE))?, b"bin\0\x00123");
+    dufs(&["download", &format!("{}missing", server.url()), dest])?
+        .assert()
+        .failure();
+    Ok(())
+}
+
+#[rstest]
+fn client_sync_push(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let local = tmpdir();
+    local.child("dir1/new.txt").write_str("new")?;
+    std::fs::remove_file(local.path().join("dir1/test.txt"))?;
+    std::fs::remove_dir_all(local.path().join("dir2"))?;
+    std::fs::write(server.path().join("dir3/test.txt"), "remote")?;
+    let url = server.url().to_string();
+    dufs(&["sync", local.path().to_str().unwrap(), &url, "--delete"])?
+        .assert()
+        .success();
+    let remote = server.path();
+    assert_eq!(std::fs::read_to_string(remote.join("dir1/new.txt"))?, "new");
+    assert!(!remote.join("dir1/test.txt").exists());
+    assert!(!remote.join("dir2").exists());
+    // A file that differs is uploaded again
+    assert_eq!(
+        std::fs::read_to_string(remote.join("dir3/test.txt"))?,
+        "This is dir3/test.txt"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn client_sync_push_delta(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let old: Vec<u8> = (0..3_000_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut new = old.clone();
+    new[1_000_000..1_000_005].copy_from_slice(b"hello");
+    new.truncate(2_500_000);
+    std::fs::write(server.path().join("dir1/big.bin"), &old)?;
+    let local = TempDir::new()?;
+    local.child("big.bin").write_binary(&new)?;
+    let url = format!("{}dir1/", server.url());
+    dufs(&["sync", local.path().to_str().unwrap(), &url])?
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(server.path().join("dir1/big.bin"))?, new);
+    Ok(())
+}
+
+#[rstest]
+fn client_sync_pull(server: TestServer) -> Result<(), Error> {
+    let local = TempDir::new()?;
+    local.child("extra.txt").write_str("extra")?;
+    local.child("dir1/test.txt").write_str("changed")?;
+    let dest = local.path().to_str().unwrap();
+    dufs(&["sync", &format!("{}dir1/", server.url()), dest])?
+        .assert()
+        .success();
+    assert!(local.path().join("extra.txt").exists());
+    dufs(&["sync", server.url().as_str(), dest, "--delete"])?
+        .assert()
+        .success();
+    assert!(!local.path().join("extra.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(local.path().join("dir1/test.txt"))?,
+        "This is dir1/test.txt"
+    );
+    assert_eq!(
+        std::fs::read_to_string(local.path().join("test.txt"))?,
+        "This is test.txt"
+    );
+    Ok(())
+}
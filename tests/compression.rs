@@ -0,0 +1,78 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use flate2::read::GzDecoder;
+use reqwest::blocking::Client;
+use rstest::rstest;
+use std::io::Read;
+
+#[rstest]
+fn compression_gzip(#[with(&["--enable-compression"])] server: TestServer) -> Result<(), Error> {
+    let plain = reqwest::blocking::get(format!("{}?json", server.url()))?.text()?;
+    assert!(plain.len() > 1024);
+    let resp = Client::new()
+        .get(format!("{}?json", server.url()))
+        .header("accept-encoding", "gzip, deflate")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+    assert_eq!(resp.headers().get("vary").unwrap(), "accept-encoding");
+    let mut output = String::new();
+    GzDecoder::new(&resp.bytes()?[..]).read_to_string(&mut output)?;
+    assert_eq!(output, plain);
+    Ok(())
+}
+
+#[rstest]
+#[case("br", Some("br"))]
+#[case("gzip;q=1, br;q=0.5", Some("gzip"))]
+#[case("*", Some("br"))]
+#[case("br;q=0, *;q=0.1", Some("gzip"))]
+#[case("identity", None)]
+#[case("", None)]
+fn compression_negotiate(
+    #[with(&["--enable-compression"])] server: TestServer,
+    #[case] accept_encoding: &str,
+    #[case] expected: Option<&str>,
+) -> Result<(), Error> {
+    let resp = Client::new()
+        .get(server.url())
+        .header("accept-encoding", accept_encoding)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()
+            .get("content-encoding")
+            .map(|v| v.to_str().unwrap()),
+        expected
+    );
+    Ok(())
+}
+
+#[rstest]
+fn compression_skips(#[with(&["--enable-compression"])] server: TestServer) -> Result<(), Error> {
+    // Too small
+    let resp = Client::new()
+        .get(format!("{}test.txt", server.url()))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    // Not text
+    let resp = Client::new()
+        .get(format!("{}{}", server.url(), fixtures::BIN_FILE))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn compression_disabled(server: TestServer) -> Result<(), Error> {
+    let resp = Client::new()
+        .get(server.url())
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    Ok(())
+}
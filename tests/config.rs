@@ -47,6 +47,77 @@ fn use_config_file(tmpdir: TempDir, port: u16) -> Result<(), Error> {
     Ok(())
 }
 
+/// The config file overrides environment variables, the command line overrides both.
+#[rstest]
+fn config_precedence(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let config_dir = TempDir::new()?;
+    let config_path = config_dir.path().join("config.yaml");
+    std::fs::write(&config_path, "allow-upload: false\nallow-delete: false\n")?;
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--config", &config_path.display().to_string()])
+        .arg("--allow-delete")
+        .env("DUFS_ALLOW_UPLOAD", "true")
+        .env("DUFS_ALLOW_SEARCH", "true")
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/file1"))
+        .body("Hello")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"DELETE", format!("http://localhost:{port}/test.txt")).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"GET", format!("http://localhost:{port}/?q=test&simple")).send()?;
+    assert!(resp.text()?.split('\n').any(|v| v == "test.html"));
+
+    child.kill()?;
+    Ok(())
+}
+
+/// Auth rules are reloaded from the config file on SIGHUP.
+#[cfg(unix)]
+#[rstest]
+fn reload_config_on_sighup(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let config_dir = TempDir::new()?;
+    let config_path = config_dir.path().join("config.yaml");
+    std::fs::write(&config_path, "auth:\n  - user1:pass1@/:rw\n")?;
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--config", &config_path.display().to_string()])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let url = format!("http://localhost:{port}/index.html");
+    let resp = fetch!(b"GET", &url).send_with_digest_auth("user2", "pass2")?;
+    assert_eq!(resp.status(), 401);
+
+    std::fs::write(
+        &config_path,
+        "auth:\n  - user1:pass1@/:rw\n  - user2:pass2@/\n",
+    )?;
+    Command::new("kill")
+        .args(["-HUP", &child.id().to_string()])
+        .status()?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let resp = fetch!(b"GET", &url).send_with_digest_auth("user2", "pass2")?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", &url).send_with_digest_auth("user1", "pass1")?;
+    assert_eq!(resp.status(), 200);
+
+    child.kill()?;
+    Ok(())
+}
+
 fn get_config_path() -> PathBuf {
     let mut path = std::env::current_dir().expect("Failed to get current directory");
     path.push("tests");
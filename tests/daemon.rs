@@ -0,0 +1,81 @@
+#![cfg(unix)]
+
+mod fixtures;
+mod utils;
+
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use fixtures::{port, tmpdir, Error};
+use rstest::rstest;
+use std::fs::File;
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// The process started from the terminal exits once the daemon is listening.
+#[rstest]
+fn daemon(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let pid_file = tmpdir.path().join("dufs.pid");
+    // Output redirected away from the terminal is kept, a pipe would stay open
+    let log_file = tmpdir.path().join("dufs.log");
+    let status = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--daemon")
+        .arg("--pid-file")
+        .arg(&pid_file)
+        .stdin(Stdio::null())
+        .stdout(File::create(&log_file)?)
+        .status()?;
+    assert!(status.success());
+    assert!(std::fs::read_to_string(&log_file)?.contains("Listening on"));
+
+    let pid = std::fs::read_to_string(&pid_file)?.trim().to_string();
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/index.html"))?;
+    assert_eq!(resp.status(), 200);
+
+    Command::new("kill").arg(&pid).status()?;
+    for _ in 0..20 {
+        if !pid_file.exists() {
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    assert!(!pid_file.exists());
+    Ok(())
+}
+
+/// The process started from the terminal exits with the error of the daemon.
+#[rstest]
+fn daemon_fails(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let output = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-b")
+        .arg("1.2.3.4")
+        .arg("--daemon")
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("Failed to bind"));
+    Ok(())
+}
+
+/// Another dufs can't start with the pid file of one that is running.
+#[rstest]
+fn pid_file_running(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let pid_file = tmpdir.path().join("dufs.pid");
+    std::fs::write(&pid_file, format!("{}\n", std::process::id()))?;
+    let output = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--pid-file")
+        .arg(&pid_file)
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("already running"));
+    Ok(())
+}
@@ -0,0 +1,112 @@
+mod fixtures;
+mod utils;
+
+use fast_rsync::Signature;
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+fn make_delta(signature: Vec<u8>, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let signature = Signature::deserialize(signature)?;
+    let mut delta = vec![];
+    fast_rsync::diff(&signature.index(), data, &mut delta)?;
+    Ok(delta)
+}
+
+fn sample(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[rstest]
+fn delta_upload(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let old = sample(100_000);
+    let resp = fetch!(b"PUT", &url).body(old.clone()).send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = reqwest::blocking::get(format!("{url}?signature&block_size=256"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "application/octet-stream");
+    let signature = resp.bytes()?.to_vec();
+
+    let mut new = old.clone();
+    new[50_000..50_010].copy_from_slice(b"0123456789");
+    new.extend_from_slice(b"appended");
+    let delta = make_delta(signature, &new)?;
+    assert!(delta.len() < 5_000);
+
+    let resp = fetch!(b"PUT", format!("{url}?delta")).body(delta).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.bytes()?.to_vec(), new);
+    Ok(())
+}
+
+#[rstest]
+fn delta_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", format!("{url}?delta"))
+        .body(b"not a delta".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+
+    // Copies a block past the end of the 18 bytes file
+    let delta = [0x72, 0x73, 0x02, 0x36, 0x45, 16, 8, 0];
+    let resp = fetch!(b"PUT", format!("{url}?delta"))
+        .body(delta.to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "This is index.html");
+    let resp = reqwest::blocking::get(format!("{url}?signature&block_size=1"))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn delta_digest_mismatch(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let signature = reqwest::blocking::get(format!("{url}?signature"))?.bytes()?;
+    let delta = make_delta(signature.to_vec(), b"This is index.html!")?;
+    let resp = fetch!(b"PUT", format!("{url}?delta"))
+        .header("repr-digest", "md5=:AAAAAAAAAAAAAAAAAAAAAA==:")
+        .body(delta)
+        .send()?;
+    assert_eq!(resp.status(), 422);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn delta_too_large(
+    #[with(&["-A", "--max-upload-size", "20"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let signature = reqwest::blocking::get(format!("{url}?signature"))?.bytes()?;
+    let delta = make_delta(signature.to_vec(), b"This is index.html, grown")?;
+    let resp = fetch!(b"PUT", format!("{url}?delta")).body(delta).send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn delta_not_allowed(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let signature = reqwest::blocking::get(format!("{url}?signature"))?.bytes()?;
+    let delta = make_delta(signature.to_vec(), b"This is index.html!")?;
+    let resp = fetch!(b"PUT", format!("{url}?delta")).body(delta).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn delta_missing_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}file1?delta", server.url()))
+        .body(vec![0x72, 0x73, 0x02, 0x36, 0])
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
@@ -0,0 +1,136 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+fn dlna_url(server: &TestServer, name: &str) -> String {
+    format!(
+        "{}__dufs_v{}_dlna/{name}",
+        server.url(),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn browse(server: &TestServer, id: &str, flag: &str) -> Result<(u16, String), Error> {
+    let body = format!(
+        r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <s:Body>
+    <u:Browse xmlns:u="urn:schemas-upnp-org:service:ContentDirectory:1">
+      <ObjectID>{id}</ObjectID>
+      <BrowseFlag>{flag}</BrowseFlag>
+      <Filter>*</Filter>
+      <StartingIndex>0</StartingIndex>
+      <RequestedCount>0</RequestedCount>
+      <SortCriteria></SortCriteria>
+    </u:Browse>
+  </s:Body>
+</s:Envelope>"#
+    );
+    let resp = reqwest::blocking::Client::new()
+        .post(dlna_url(server, "control/ContentDirectory"))
+        .header(
+            "SOAPAction",
+            "\"urn:schemas-upnp-org:service:ContentDirectory:1#Browse\"",
+        )
+        .body(body)
+        .send()?;
+    Ok((resp.status().as_u16(), resp.text()?))
+}
+
+#[rstest]
+fn dlna_description(#[with(&["--dlna"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(dlna_url(&server, "description.xml"))?;
+    assert_eq!(resp.status(), 200);
+    let body = resp.text()?;
+    assert!(body.contains("<deviceType>urn:schemas-upnp-org:device:MediaServer:1</deviceType>"));
+    assert!(body.contains("dlna/control/ContentDirectory</controlURL>"));
+    let resp = reqwest::blocking::get(dlna_url(&server, "ContentDirectory.xml"))?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.text()?.contains("<name>Browse</name>"));
+    Ok(())
+}
+
+#[rstest]
+fn dlna_disabled(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(dlna_url(&server, "description.xml"))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn dlna_browse(#[with(&["--dlna"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("song.mp3"), "mp3")?;
+    std::fs::write(server.path().join("dir1/movie one.mp4"), "mp4")?;
+    let (status, body) = browse(&server, "0", "BrowseDirectChildren")?;
+    assert_eq!(status, 200);
+    assert!(body.contains("&lt;container id=\"dir1\" parentID=\"0\""));
+    assert!(body.contains("&lt;dc:title>song.mp3&lt;/dc:title>"));
+    assert!(body.contains(&format!("{}song.mp3&lt;/res>", server.url())));
+    assert!(!body.contains("test.txt"));
+    let (status, body) = browse(&server, "dir1", "BrowseDirectChildren")?;
+    assert_eq!(status, 200);
+    assert!(body.contains("&lt;item id=\"dir1/movie one.mp4\" parentID=\"dir1\""));
+    assert!(body.contains("http-get:*:video/mp4:*"));
+    assert!(body.contains(&format!("{}dir1/movie%20one.mp4&lt;/res>", server.url())));
+    assert!(body.contains("<NumberReturned>1</NumberReturned>"));
+    let (status, body) = browse(&server, "dir1/movie one.mp4", "BrowseMetadata")?;
+    assert_eq!(status, 200);
+    assert!(body.contains("<TotalMatches>1</TotalMatches>"));
+    let (status, body) = browse(&server, "dir1/../..", "BrowseDirectChildren")?;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>701</errorCode>"));
+    Ok(())
+}
+
+#[rstest]
+fn dlna_browse_hidden(
+    #[with(&["--dlna", "--hidden", ".git"])] server: TestServer,
+) -> Result<(), Error> {
+    let (status, body) = browse(&server, "0", "BrowseDirectChildren")?;
+    assert_eq!(status, 200);
+    assert!(!body.contains(".git"));
+    Ok(())
+}
+
+#[rstest]
+fn dlna_browse_anonymous_only(
+    #[with(&["--dlna", "-a", "user:pass@/:rw", "-a", "@/dir1"])] server: TestServer,
+) -> Result<(), Error> {
+    let (status, body) = browse(&server, "0", "BrowseDirectChildren")?;
+    assert_eq!(status, 200);
+    assert!(body.contains("container id=\"dir1\""));
+    assert!(!body.contains("dir2"));
+    let (status, _) = browse(&server, "dir2", "BrowseDirectChildren")?;
+    assert_eq!(status, 500);
+    Ok(())
+}
+
+#[rstest]
+fn dlna_ssdp_search(
+    #[from(server)]
+    #[with(&["--dlna"])]
+    _server: TestServer,
+) -> Result<(), Error> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let search = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: urn:schemas-upnp-org:device:MediaServer:1\r\n\r\n";
+    socket.send_to(search.as_bytes(), "127.0.0.1:1900")?;
+    let mut buf = [0; 2048];
+    let (len, _) = socket.recv_from(&mut buf)?;
+    let reply = String::from_utf8_lossy(&buf[..len]);
+    assert!(reply.starts_with("HTTP/1.1 200 OK"));
+    assert!(reply.contains("ST: urn:schemas-upnp-org:device:MediaServer:1"));
+    // Another server of the tests may have answered, on a port of its own
+    let location = reply
+        .lines()
+        .find_map(|v| v.strip_prefix("LOCATION: "))
+        .unwrap();
+    assert!(location.starts_with("http://127.0.0.1:"));
+    let resp = reqwest::blocking::get(location)?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::net::TcpStream;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::stream::MaybeTlsStream;
+use tokio_tungstenite::tungstenite::{connect, Message, WebSocket};
+
+fn events_url(server: &TestServer) -> String {
+    format!(
+        "{}__dufs_v{}_events",
+        server.url().as_str().replacen("http", "ws", 1),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+fn subscribe(url: &str, auth: Option<&str>) -> WebSocket<MaybeTlsStream<TcpStream>> {
+    let mut req = url.into_client_request().unwrap();
+    if let Some(auth) = auth {
+        req.headers_mut()
+            .insert("authorization", format!("Basic {auth}").parse().unwrap());
+    }
+    let (ws, _) = connect(req).unwrap();
+    if let MaybeTlsStream::Plain(stream) = ws.get_ref() {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+    }
+    ws
+}
+
+/// The next event, as JSON
+fn next_event(ws: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> serde_json::Value {
+    loop {
+        if let Message::Text(text) = ws.read().expect("no event") {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+#[rstest]
+fn events(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let mut ws = subscribe(&events_url(&server), None);
+
+    let resp = fetch!(b"PUT", format!("{}dir1/new.txt", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        next_event(&mut ws),
+        serde_json::json!({"type": "upload", "path": "/dir1/new.txt"})
+    );
+
+    let resp = fetch!(b"MKCOL", format!("{}newdir", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        next_event(&mut ws),
+        serde_json::json!({"type": "mkdir", "path": "/newdir"})
+    );
+
+    let resp = fetch!(b"MOVE", format!("{}dir1/new.txt", server.url()))
+        .header("Destination", format!("{}newdir/new.txt", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        next_event(&mut ws),
+        serde_json::json!({"type": "move", "path": "/dir1/new.txt", "dest": "/newdir/new.txt"})
+    );
+
+    let resp = fetch!(b"DELETE", format!("{}newdir/new.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(
+        next_event(&mut ws),
+        serde_json::json!({"type": "delete", "path": "/newdir/new.txt"})
+    );
+    Ok(())
+}
+
+#[rstest]
+fn events_not_websocket(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(events_url(&server).replacen("ws", "http", 1))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn events_auth(
+    #[with(&["--auth", "user:pass@/:rw", "--auth", "user2:pass2@/dir1:rw", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = events_url(&server);
+    assert!(connect(url.as_str()).is_err());
+
+    // user2:pass2
+    let mut ws = subscribe(&url, Some("dXNlcjI6cGFzczI="));
+    for name in ["dir2/a.txt", "dir1/b.txt"] {
+        let resp = fetch!(b"PUT", format!("{}{name}", server.url()))
+            .body(b"abc".to_vec())
+            .basic_auth("user", Some("pass"))
+            .send()?;
+        assert_eq!(resp.status(), 201);
+    }
+    assert_eq!(next_event(&mut ws)["path"], "/dir1/b.txt");
+    Ok(())
+}
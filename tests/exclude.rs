@@ -0,0 +1,68 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[rstest]
+#[case(server(&["--exclude", "dir1/,index.html"]))]
+fn exclude_get(#[case] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 200);
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir2/"));
+    assert!(!paths.contains("dir1/"));
+    assert!(!paths.contains("index.html"));
+
+    for path in ["index.html", "dir1/", "dir1/test.txt", "dir2/index.html"] {
+        let resp = reqwest::blocking::get(format!("{}{}", server.url(), path))?;
+        assert_eq!(resp.status(), 404, "{path}");
+    }
+    let resp = reqwest::blocking::get(format!("{}dir2/test.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+#[case(server(&["-A", "--exclude", "*.secret"]))]
+fn exclude_write(#[case] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}new.secret", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = fetch!(b"MOVE", format!("{}test.txt", server.url()))
+        .header("Destination", format!("{}test.secret", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = fetch!(b"MKCOL", format!("{}dir.secret", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+#[case(server(&["-A", "--exclude", "test.html,dir1/"]))]
+fn exclude_propfind_search_zip(#[case] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", server.url()).send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/dir2/</D:href>"));
+    assert!(!body.contains("<D:href>/dir1/</D:href>"));
+    assert!(!body.contains("<D:href>/test.html</D:href>"));
+
+    let resp = fetch!(b"PROPFIND", format!("{}test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+
+    let resp = reqwest::blocking::get(format!("{}?q=test.html", server.url()))?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.iter().all(|v| !v.ends_with("test.html")));
+
+    let resp = reqwest::blocking::get(format!("{}?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    // Entry names are stored uncompressed
+    let body = resp.bytes()?;
+    let contains = |name: &[u8]| body.windows(name.len()).any(|v| v == name);
+    assert!(contains(b"dir2/test.txt"));
+    assert!(!contains(b"dir1/"));
+    assert!(!contains(b"test.html"));
+    Ok(())
+}
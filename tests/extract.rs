@@ -0,0 +1,148 @@
+mod fixtures;
+mod utils;
+
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use fixtures::{server, Error, TestServer};
+use futures::AsyncWriteExt;
+use rstest::rstest;
+use std::fs;
+
+/// A zip of the files, stored as they are
+fn make_zip(files: &[(&str, &str)]) -> Result<Vec<u8>, Error> {
+    futures::executor::block_on(async {
+        let mut writer = ZipFileWriter::new(vec![]);
+        for (name, data) in files {
+            let builder = ZipEntryBuilder::new(name.to_string().into(), Compression::Stored);
+            writer.write_entry_whole(builder, data.as_bytes()).await?;
+        }
+        Ok(writer.close().await?)
+    })
+}
+
+#[rstest]
+#[case("zip")]
+#[case("tar")]
+#[case("tar.gz")]
+fn extract_archive(#[with(&["-A"])] server: TestServer, #[case] ext: &str) -> Result<(), Error> {
+    let archive = reqwest::blocking::get(format!("{}dir1?{ext}", server.url()))?.bytes()?;
+    let url = format!("{}new/dir1.{ext}?unzip", server.url());
+    let resp = fetch!(b"PUT", &url).body(archive).send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(!server.path().join(format!("new/dir1.{ext}")).exists());
+    for entry in fs::read_dir(server.path().join("dir1"))? {
+        let entry = entry?;
+        let extracted = server.path().join("new").join(entry.file_name());
+        assert_eq!(fs::read(entry.path())?, fs::read(extracted)?);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn extract_folders(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let archive = make_zip(&[("a/", ""), ("a/b/c.txt", "c"), ("./d.txt", "d")])?;
+    let resp = fetch!(b"PUT", format!("{}dir1/x.zip?unzip", server.url()))
+        .body(archive)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("dir1/a").is_dir());
+    assert_eq!(
+        fs::read_to_string(server.path().join("dir1/a/b/c.txt"))?,
+        "c"
+    );
+    assert_eq!(fs::read_to_string(server.path().join("dir1/d.txt"))?, "d");
+    Ok(())
+}
+
+#[rstest]
+#[case("../evil.txt")]
+#[case("/evil.txt")]
+#[case("a/../../evil.txt")]
+fn extract_zip_slip(#[with(&["-A"])] server: TestServer, #[case] name: &str) -> Result<(), Error> {
+    let archive = make_zip(&[(name, "evil")])?;
+    let resp = fetch!(b"PUT", format!("{}dir1/x.zip?unzip", server.url()))
+        .body(archive)
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    assert_eq!(
+        resp.text()?,
+        format!("Refused to extract `{name}`, it is outside the folder")
+    );
+    assert!(!server.path().join("evil.txt").exists());
+    assert!(!server.path().join("dir1/evil.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_refused(
+    #[with(&["-a", "user:pass@/dir1:rw", "-a", "@/", "--allow-upload"])] server: TestServer,
+) -> Result<(), Error> {
+    let archive = make_zip(&[("a.txt", "a")])?;
+    let resp = fetch!(b"PUT", format!("{}x.zip?unzip", server.url()))
+        .body(archive.clone())
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"PUT", format!("{}dir1/x.zip?unzip", server.url()))
+        .body(archive)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("dir1/a.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_not_allowed(server: TestServer) -> Result<(), Error> {
+    let archive = make_zip(&[("a.txt", "a")])?;
+    let resp = fetch!(b"PUT", format!("{}x.zip?unzip", server.url()))
+        .body(archive)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!server.path().join("a.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_overwrite(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let archive = make_zip(&[("new.txt", "new"), ("test.txt", "changed")])?;
+    let resp = fetch!(b"PUT", format!("{}x.zip?unzip", server.url()))
+        .body(archive)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.text()?, "Failed to extract `test.txt`, Forbidden");
+    assert_eq!(fs::read_to_string(server.path().join("new.txt"))?, "new");
+    assert_ne!(
+        fs::read_to_string(server.path().join("test.txt"))?,
+        "changed"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn extract_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}x.rar?unzip", server.url()))
+        .body("data")
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    let resp = fetch!(b"PUT", format!("{}x.tar.gz?unzip", server.url()))
+        .body("not an archive")
+        .send()?;
+    assert_eq!(resp.status(), 422);
+    assert!(resp.text()?.starts_with("Invalid archive, "));
+
+    // The size of a stored entry is only known from its data descriptor, after its data
+    let archive = futures::executor::block_on(async {
+        let mut writer = ZipFileWriter::new(vec![]);
+        let builder = ZipEntryBuilder::new("a.txt".to_string().into(), Compression::Stored);
+        let mut entry = writer.write_entry_stream(builder).await?;
+        entry.write_all(b"a").await?;
+        entry.close().await?;
+        writer.close().await
+    })?;
+    let resp = fetch!(b"PUT", format!("{}x.zip?unzip", server.url()))
+        .body(archive)
+        .send()?;
+    assert_eq!(resp.status(), 422);
+    assert!(!server.path().join("a.txt").exists());
+    Ok(())
+}
@@ -0,0 +1,163 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{port, server, Error, TestServer};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Just enough of an FTP client, in passive mode
+struct Ftp {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Ftp {
+    fn connect(port: u16) -> Result<Self, Error> {
+        let stream = TcpStream::connect(("127.0.0.1", port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut ftp = Ftp {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        assert_eq!(ftp.reply()?.0, 220);
+        Ok(ftp)
+    }
+
+    fn login(port: u16, user: &str, pass: &str) -> Result<Self, Error> {
+        let mut ftp = Self::connect(port)?;
+        assert_eq!(ftp.cmd(&format!("USER {user}"))?.0, 331);
+        assert_eq!(ftp.cmd(&format!("PASS {pass}"))?.0, 230);
+        Ok(ftp)
+    }
+
+    fn reply(&mut self) -> Result<(u16, String), Error> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            text.push_str(&line);
+            // The last line of a reply is the code and a space
+            if line.len() > 3 && line.as_bytes()[3] == b' ' {
+                return Ok((line[..3].parse()?, text));
+            }
+        }
+    }
+
+    fn cmd(&mut self, line: &str) -> Result<(u16, String), Error> {
+        self.writer.write_all(format!("{line}\r\n").as_bytes())?;
+        self.reply()
+    }
+
+    /// Runs a command transferring data, returning its final reply and the data
+    fn transfer(&mut self, line: &str) -> Result<(u16, Vec<u8>), Error> {
+        let (code, text) = self.cmd("EPSV")?;
+        assert_eq!(code, 229);
+        let port: u16 = text.split('|').nth(3).unwrap().parse()?;
+        let mut data = TcpStream::connect(("127.0.0.1", port))?;
+        let (code, _) = self.cmd(line)?;
+        if code != 150 {
+            return Ok((code, vec![]));
+        }
+        let mut output = vec![];
+        data.read_to_end(&mut output)?;
+        Ok((self.reply()?.0, output))
+    }
+}
+
+fn ftp_server(args: &[&str]) -> (TestServer, u16) {
+    let ftp_port = port();
+    let ftp_port_arg = ftp_port.to_string();
+    let mut all_args = vec!["--ftp-port", ftp_port_arg.as_str()];
+    all_args.extend_from_slice(args);
+    (server(&all_args), ftp_port)
+}
+
+#[test]
+fn ftp_list_and_retrieve() -> Result<(), Error> {
+    let (_server, port) = ftp_server(&["--hidden", ".git"]);
+    let mut ftp = Ftp::login(port, "anonymous", "guest@")?;
+    assert_eq!(ftp.cmd("PWD")?.1, "257 \"/\" is the current directory\r\n");
+    let (code, output) = ftp.transfer("NLST")?;
+    assert_eq!(code, 226);
+    let names = String::from_utf8(output)?;
+    assert!(names.lines().any(|v| v == "dir1"));
+    assert!(names.lines().any(|v| v == "test.txt"));
+    assert!(!names.contains(".git"));
+    let (code, output) = ftp.transfer("LIST -la")?;
+    assert_eq!(code, 226);
+    let listing = String::from_utf8(output)?;
+    assert!(listing
+        .lines()
+        .any(|v| v.starts_with("drwxr-xr-x") && v.ends_with(" dir1")));
+    assert!(listing
+        .lines()
+        .any(|v| v.starts_with("-rw-r--r--") && v.contains(" 16 ") && v.ends_with(" test.txt")));
+    assert_eq!(
+        ftp.transfer("RETR test.txt")?,
+        (226, b"This is test.txt".to_vec())
+    );
+    assert_eq!(ftp.cmd("CWD dir1")?.0, 250);
+    assert_eq!(ftp.cmd("SIZE test.txt")?.1, "213 21\r\n");
+    assert_eq!(
+        ftp.transfer("RETR test.txt")?,
+        (226, b"This is dir1/test.txt".to_vec())
+    );
+    assert_eq!(ftp.cmd("REST 8")?.0, 350);
+    assert_eq!(
+        ftp.transfer("RETR /dir1/test.txt")?,
+        (226, b"dir1/test.txt".to_vec())
+    );
+    let (code, output) = ftp.transfer("MLSD")?;
+    assert_eq!(code, 226);
+    assert!(String::from_utf8(output)?.contains("type=file;size=21;modify="));
+    assert_eq!(ftp.cmd("CDUP")?.0, 250);
+    assert_eq!(ftp.cmd("CWD missing")?.0, 550);
+    assert_eq!(ftp.transfer("RETR missing.txt")?.0, 550);
+    assert_eq!(ftp.cmd("QUIT")?.0, 221);
+    Ok(())
+}
+
+#[test]
+fn ftp_read_only() -> Result<(), Error> {
+    let (_server, port) = ftp_server(&["-A"]);
+    let mut ftp = Ftp::login(port, "anonymous", "")?;
+    assert_eq!(ftp.cmd("STOR new.txt")?.0, 550);
+    assert_eq!(ftp.cmd("DELE test.txt")?.0, 550);
+    assert_eq!(ftp.cmd("MKD new")?.0, 550);
+    Ok(())
+}
+
+#[test]
+fn ftp_auth() -> Result<(), Error> {
+    let (_server, port) = ftp_server(&["-a", "user:pass@/:rw", "-a", "@/dir1"]);
+    let mut ftp = Ftp::connect(port)?;
+    assert_eq!(ftp.cmd("PWD")?.0, 530);
+    ftp.cmd("USER user")?;
+    assert_eq!(ftp.cmd("PASS wrong")?.0, 530);
+
+    let mut ftp = Ftp::login(port, "anonymous", "")?;
+    assert_eq!(ftp.transfer("RETR test.txt")?.0, 550);
+    assert_eq!(
+        ftp.transfer("RETR dir1/test.txt")?,
+        (226, b"This is dir1/test.txt".to_vec())
+    );
+    let (_, output) = ftp.transfer("NLST")?;
+    assert_eq!(String::from_utf8(output)?, "dir1\r\n");
+
+    let mut ftp = Ftp::login(port, "user", "pass")?;
+    assert_eq!(
+        ftp.transfer("RETR test.txt")?,
+        (226, b"This is test.txt".to_vec())
+    );
+    Ok(())
+}
+
+#[test]
+fn ftp_no_anonymous() -> Result<(), Error> {
+    let (_server, port) = ftp_server(&["-a", "user:pass@/:rw"]);
+    let mut ftp = Ftp::connect(port)?;
+    ftp.cmd("USER anonymous")?;
+    assert_eq!(ftp.cmd("PASS guest")?.0, 530);
+    Ok(())
+}
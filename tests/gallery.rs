@@ -0,0 +1,93 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+/// A gray 24-bit bmp image
+fn bmp(width: u32, height: u32) -> Vec<u8> {
+    let row_size = (width * 3).div_ceil(4) * 4;
+    let data_size = row_size * height;
+    let mut output = vec![];
+    output.extend(b"BM");
+    output.extend((54 + data_size).to_le_bytes());
+    output.extend([0; 4]);
+    output.extend(54u32.to_le_bytes());
+    output.extend(40u32.to_le_bytes());
+    output.extend(width.to_le_bytes());
+    output.extend(height.to_le_bytes());
+    output.extend(1u16.to_le_bytes());
+    output.extend(24u16.to_le_bytes());
+    output.extend([0; 4]);
+    output.extend(data_size.to_le_bytes());
+    output.extend([0; 16]);
+    output.extend(vec![128; data_size as usize]);
+    output
+}
+
+#[rstest]
+fn gallery_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("dir1/photo.bmp"), bmp(4, 4))?;
+    std::fs::write(server.path().join("dir1/clip.mp4"), "")?;
+
+    let resp = reqwest::blocking::get(format!("{}dir1/?json", server.url()))?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    let paths = json["paths"].as_array().unwrap();
+    let photo = paths.iter().find(|v| v["name"] == "photo.bmp").unwrap();
+    assert_eq!(photo["media_type"], "image");
+    let text = paths.iter().find(|v| v["name"] == "test.txt").unwrap();
+    assert!(text.get("media_type").is_none());
+
+    let resp = reqwest::blocking::get(format!("{}dir1/?gallery&json", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["kind"], "Gallery");
+    let names: Vec<_> = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["clip.mp4", "photo.bmp"]);
+
+    let resp = reqwest::blocking::get(format!("{}?gallery&simple", server.url()))?;
+    assert!(resp.text()?.lines().all(|v| v.ends_with('/')));
+    Ok(())
+}
+
+#[rstest]
+fn gallery_thumbnail(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("photo.bmp"), bmp(64, 32))?;
+    let resp = reqwest::blocking::get(format!("{}photo.bmp?thumb=16", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/jpeg");
+    assert!(resp.headers().contains_key("last-modified"));
+    assert!(resp.bytes()?.starts_with(&[0xff, 0xd8]));
+
+    let resp = reqwest::blocking::get(format!("{}test.txt?thumb", server.url()))?;
+    assert_eq!(resp.status(), 415);
+    std::fs::write(server.path().join("broken.png"), "not a png")?;
+    let resp = reqwest::blocking::get(format!("{}broken.png?thumb", server.url()))?;
+    assert_eq!(resp.status(), 415);
+    Ok(())
+}
+
+#[rstest]
+fn gallery_item(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    for name in ["a.bmp", "b.mp4", "c.bmp"] {
+        std::fs::write(server.path().join("dir1").join(name), bmp(1, 1))?;
+    }
+    let resp = reqwest::blocking::get(format!("{}dir1/b.mp4?gallery", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(
+        json,
+        serde_json::json!({"name": "b.mp4", "media_type": "video", "prev": "a.bmp", "next": "c.bmp"})
+    );
+
+    let resp = reqwest::blocking::get(format!("{}dir1/a.bmp?gallery", server.url()))?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["prev"], serde_json::Value::Null);
+    assert_eq!(json["next"], "b.mp4");
+    Ok(())
+}
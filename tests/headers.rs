@@ -0,0 +1,102 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[rstest]
+fn headers(
+    #[with(&[
+        "--header", "X-Frame-Options: DENY",
+        "--header", "/*.html:Content-Disposition: attachment",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.headers().get("x-frame-options").unwrap(), "DENY");
+    assert!(resp.headers().get("content-disposition").is_none());
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.headers().get("x-frame-options").unwrap(), "DENY");
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn headers_override(
+    #[with(&["--header", "Cache-Control: no-store"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.headers().get("cache-control").unwrap(), "no-store");
+    Ok(())
+}
+
+#[rstest]
+fn cache_control(
+    #[with(&[
+        "--cache-control", "*.html=max-age=86400",
+        "--cache-control-default", "no-cache",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(
+        resp.headers().get("cache-control").unwrap(),
+        "max-age=86400"
+    );
+    let resp = reqwest::blocking::get(format!("{}test.txt", server.url()))?;
+    assert_eq!(resp.headers().get("cache-control").unwrap(), "no-cache");
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let resp = reqwest::blocking::Client::new()
+        .get(format!("{}test.txt", server.url()))
+        .header("if-none-match", etag)
+        .send()?;
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.headers().get("cache-control").unwrap(), "no-cache");
+    Ok(())
+}
+
+#[rstest]
+fn security_headers(#[with(&["--security-headers"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(
+        resp.headers().get("x-content-type-options").unwrap(),
+        "nosniff"
+    );
+    assert!(resp.headers().get("referrer-policy").is_some());
+    assert!(resp.headers().get("strict-transport-security").is_none());
+    let csp = resp
+        .headers()
+        .get("content-security-policy")
+        .unwrap()
+        .to_str()?
+        .to_string();
+    let nonce = csp
+        .split("'nonce-")
+        .nth(1)
+        .and_then(|v| v.split('\'').next())
+        .unwrap();
+    assert!(resp
+        .text()?
+        .contains(&format!(r#"<script nonce="{nonce}">"#)));
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(
+        resp.headers().get("x-content-type-options").unwrap(),
+        "nosniff"
+    );
+    assert!(resp.headers().get("content-security-policy").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn security_headers_skip(
+    #[with(&["--security-headers", "--security-headers-skip", "content-security-policy"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    assert!(resp.headers().get("content-security-policy").is_none());
+    assert!(resp.headers().get("referrer-policy").is_some());
+    Ok(())
+}
@@ -0,0 +1,96 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::TempDir;
+use fixtures::{server, Error};
+use rstest::rstest;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Waits for the hook command to write its output
+fn wait_output(path: &Path) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if let Ok(output) = std::fs::read_to_string(path) {
+            if output.ends_with('\n') {
+                return output;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("hook didn't run");
+}
+
+fn hook_command(dir: &TempDir) -> String {
+    format!(
+        r#"echo "$DUFS_EVENT ${{DUFS_EVENT_PATH##*/}} ${{DUFS_EVENT_DEST##*/}} $DUFS_EVENT_USER $DUFS_EVENT_SIZE" > {}/out"#,
+        dir.path().display()
+    )
+}
+
+#[cfg(unix)]
+#[rstest]
+fn hook_upload() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let server = server(vec![
+        "-a".to_string(),
+        "user:pass@/:rw".to_string(),
+        "--allow-upload".to_string(),
+        "--hook-upload".to_string(),
+        hook_command(&dir),
+    ]);
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        wait_output(&dir.path().join("out")),
+        "upload file1  user 3\n"
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn hook_move_and_delete() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let server = server(vec![
+        "-A".to_string(),
+        "--hook-move".to_string(),
+        hook_command(&dir),
+        "--hook-delete".to_string(),
+        hook_command(&dir),
+    ]);
+    let dest = format!("{}moved.html", server.url());
+    let resp = fetch!(b"MOVE", format!("{}index.html", server.url()))
+        .header("Destination", &dest)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        wait_output(&dir.path().join("out")),
+        "move index.html moved.html  18\n"
+    );
+
+    std::fs::remove_file(dir.path().join("out"))?;
+    let resp = fetch!(b"DELETE", &dest).send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(
+        wait_output(&dir.path().join("out")),
+        "delete moved.html   \n"
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn hook_not_run_on_failure() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let server = server(vec!["--hook-delete".to_string(), hook_command(&dir)]);
+    let resp = fetch!(b"DELETE", format!("{}index.html", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(!dir.path().join("out").exists());
+    Ok(())
+}
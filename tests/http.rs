@@ -25,6 +25,37 @@ fn head_dir(server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_readme(server: TestServer) -> Result<(), Error> {
+    std::fs::write(
+        server.path().join("dir1/README.md"),
+        "# Hello\n\n<b>raw</b>",
+    )?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?json", server.url()))?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(
+        json["readme"],
+        "<h1>Hello</h1>\n<p>&lt;b&gt;raw&lt;/b&gt;</p>\n"
+    );
+
+    let resp = reqwest::blocking::get(format!("{}dir2/?json", server.url()))?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert!(json.get("readme").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_readme_name(
+    #[with(&["--readme-name", "NOTES.md"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("dir1/README.md"), "readme")?;
+    std::fs::write(server.path().join("dir1/NOTES.md"), "notes")?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?json", server.url()))?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["readme"], "<p>notes</p>\n");
+    Ok(())
+}
+
 #[rstest]
 fn get_dir_404(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}404/", server.url()))?;
@@ -51,6 +82,41 @@ fn get_dir_zip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_tar(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1?tar", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/x-tar"
+    );
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"dir1.tar\""
+    );
+    let body = resp.bytes()?;
+    assert_eq!(&body[257..262], b"ustar");
+    assert_eq!(body.len() % 512, 0);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_tar_gz(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1?tar.gz", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/gzip"
+    );
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"dir1.tar.gz\""
+    );
+    let body = resp.bytes()?;
+    assert_eq!(&body[0..2], b"\x1f\x8b");
+    Ok(())
+}
+
 #[rstest]
 fn get_dir_json(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
@@ -64,6 +130,210 @@ fn get_dir_json(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_json_recursive(
+    #[with(&["--hidden", "*.html"])] server: TestServer,
+) -> Result<(), Error> {
+    let names = |query: &str| -> Result<Vec<String>, Error> {
+        let resp = reqwest::blocking::get(format!("{}?json&{query}", server.url()))?;
+        assert_eq!(resp.status(), 200);
+        let json: Value = resp.text()?.parse()?;
+        Ok(json["paths"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["name"].as_str().unwrap().to_string())
+            .collect())
+    };
+    let names1 = names("recursive")?;
+    assert!(names1.contains(&"dir1".to_string()));
+    assert!(names1.contains(&"dir1/test.txt".to_string()));
+    assert!(!names1.iter().any(|v| v.ends_with(".html")));
+    let names2 = names("depth=1")?;
+    assert!(names2.contains(&"dir1".to_string()));
+    assert!(!names2.iter().any(|v| v.contains('/')));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_json_page(server: TestServer) -> Result<(), Error> {
+    let page = |query: &str| -> Result<(Vec<Value>, String), Error> {
+        let resp = reqwest::blocking::get(format!("{}?json&{query}", server.url()))?;
+        assert_eq!(resp.status(), 200);
+        let total = resp.headers()["x-total-count"].to_str()?.to_string();
+        let json: Value = resp.text()?.parse()?;
+        Ok((json["paths"].as_array().unwrap().clone(), total))
+    };
+    let (all, total) = page("offset=0")?;
+    assert_eq!(all.len().to_string(), total);
+    let (paths, _) = page("offset=1&limit=2")?;
+    assert_eq!(paths, all[1..3]);
+    let (paths, _) = page("offset=0&limit=1&order=desc")?;
+    assert_eq!(paths[0], all[all.len() - 1]);
+    let (paths, total) = page("limit=2&sort=size")?;
+    assert_eq!(paths.len(), 2);
+    assert_eq!(all.len().to_string(), total);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_json_fields(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json&fields=name,size", server.url()))?;
+    let json: Value = resp.text()?.parse()?;
+    for item in json["paths"].as_array().unwrap() {
+        let keys: Vec<&String> = item.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["name", "size"]);
+    }
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_stream(#[with(&["--hidden", "*.html"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json&stream", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()["content-type"],
+        "application/x-ndjson; charset=utf-8"
+    );
+    let items: Vec<Value> = resp
+        .text()?
+        .lines()
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()?;
+    assert!(items.iter().any(|v| v["name"] == "dir1"));
+    assert!(items.iter().any(|v| v["name"] == "test.txt"));
+    assert!(!items.iter().any(|v| v["name"] == "index.html"));
+
+    let resp = reqwest::blocking::get(format!("{}?simple&stream", server.url()))?;
+    let names: Vec<String> = resp.text()?.lines().map(|v| v.to_string()).collect();
+    assert!(names.contains(&"dir1/".to_string()));
+    assert!(names.contains(&"test.txt".to_string()));
+    assert_eq!(names.len(), items.len());
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_full_meta(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json&meta=full", server.url()))?;
+    let json: Value = resp.text()?.parse()?;
+    let paths = json["paths"].as_array().unwrap();
+    let item = paths.iter().find(|v| v["name"] == "test.txt").unwrap();
+    let mtime_str = item["mtime_str"].as_str().unwrap();
+    assert!(mtime_str.ends_with('Z') && mtime_str.contains('T'));
+    #[cfg(unix)]
+    {
+        assert!(item["mode"].as_str().unwrap().starts_with('0'));
+        assert!(item["owner"].is_string());
+    }
+    // Left out unless asked for
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = resp.text()?.parse()?;
+    assert!(json["paths"][0].get("mtime_str").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_csv(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("a, \"b\".txt"), "abc")?;
+    let resp = reqwest::blocking::get(format!("{}?csv&filter=*.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+    let text = resp.text()?;
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "name,type,size,mtime");
+    assert!(lines[1].starts_with("\"a, \"\"b\"\".txt\",file,3,"));
+    assert!(lines[1].ends_with('Z'));
+    let resp = reqwest::blocking::get(format!("{}?tsv&filter=dir1", server.url()))?;
+    assert_eq!(
+        resp.headers()["content-type"],
+        "text/tab-separated-values; charset=utf-8"
+    );
+    let text = resp.text()?;
+    assert!(text.lines().nth(1).unwrap().starts_with("dir1\tdir\t"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_metadata_cache(
+    #[with(&["--metadata-cache", "1h", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let size = || -> Result<Value, Error> {
+        let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+        let json: Value = resp.text()?.parse()?;
+        let paths = json["paths"].as_array().unwrap();
+        Ok(paths.iter().find(|v| v["name"] == "test.txt").unwrap()["size"].clone())
+    };
+    assert_eq!(size()?, 16);
+    // Changes made outside the server show up once the entries expire
+    std::fs::write(server.path().join("test.txt"), "changed")?;
+    assert_eq!(size()?, 16);
+    // Writes through the server clear the cache
+    let resp = fetch!(b"PUT", format!("{}new.txt", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(size()?, 7);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_filter(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?simple&filter=*.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "index.html\ntest.html\n");
+    let resp = reqwest::blocking::get(format!("{}?json&filter=test.*&limit=1", server.url()))?;
+    assert_eq!(resp.headers()["x-total-count"], "2");
+    let resp = reqwest::blocking::get(format!("{}?simple&stream&filter=dir?", server.url()))?;
+    let mut names: Vec<String> = resp.text()?.lines().map(|v| v.to_string()).collect();
+    names.sort();
+    assert_eq!(names, ["dir1/", "dir2/", "dir3/", "dir4/"]);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_json_hash(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json&hash=sha256", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    let paths = json["paths"].as_array().unwrap();
+    let item = |name: &str| paths.iter().find(|v| v["name"] == name).unwrap();
+    assert_eq!(
+        item("index.html")["sha256"],
+        "c8dd395e3202674b9512f7b7f956e0d96a8ba8f572e785b0d5413ab83766dbc4"
+    );
+    assert!(item("dir1")["sha256"].is_null());
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = resp.text()?.parse()?;
+    assert!(json["paths"][0].get("sha256").is_none());
+    let resp = reqwest::blocking::get(format!("{}?json&hash=crc32", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+#[case("")]
+#[case("?json")]
+#[case("?simple")]
+fn get_dir_etag(#[with(&["-A"])] server: TestServer, #[case] query: &str) -> Result<(), Error> {
+    let url = format!("{}{query}", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 200);
+    let etag = resp.headers().get("etag").unwrap().clone();
+    assert!(etag.to_str()?.starts_with("W/"));
+    let resp = fetch!(b"GET", &url)
+        .header("if-none-match", etag.clone())
+        .send()?;
+    assert_eq!(resp.status(), 304);
+    assert_eq!(resp.text()?, "");
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"GET", &url).header("if-none-match", etag).send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
 #[rstest]
 fn get_dir_simple(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}?simple", server.url()))?;
@@ -123,6 +393,59 @@ fn get_dir_search3(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_search_content(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(
+        server.path().join("dir1/notes.md"),
+        "first line\n  A Needle here  \nlast line\n",
+    )?;
+    let resp = reqwest::blocking::get(format!("{}?q=needle&content=true", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "dir1/notes.md:2:A Needle here\n");
+
+    let resp = reqwest::blocking::get(format!("{}?q=this%20is&content=true&json", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let text = resp.text()?;
+    let lines: Vec<serde_json::Value> = text.lines().map(|v| v.parse().unwrap()).collect();
+    assert!(lines.iter().any(|v| v["name"] == "dir1/test.txt"
+        && v["line"] == 1
+        && v["text"] == "This is dir1/test.txt"));
+    // Binary files are skipped
+    let resp = reqwest::blocking::get(format!("{}?q=bin&content=true", server.url()))?;
+    assert_eq!(resp.text()?, "");
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_page(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}?q=test.html&simple", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    let all: Vec<String> = resp.text()?.lines().map(|v| v.to_string()).collect();
+    assert!(all.len() > 2);
+    let resp = reqwest::blocking::get(format!("{url}&offset=1&limit=2"))?;
+    assert_eq!(resp.status(), 200);
+    let page: Vec<String> = resp.text()?.lines().map(|v| v.to_string()).collect();
+    assert_eq!(page, all[1..3]);
+
+    let resp = reqwest::blocking::get(format!("{}?q=test&content=true&limit=1", server.url()))?;
+    assert_eq!(resp.text()?.lines().count(), 1);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_max_results(
+    #[with(&["-A", "--search-max-results", "2"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q=test&simple", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-search-truncated").unwrap(), "true");
+    assert_eq!(resp.text()?.lines().count(), 2);
+
+    let resp = reqwest::blocking::get(format!("{}?q=needle-not-found&simple", server.url()))?;
+    assert!(resp.headers().get("x-search-truncated").is_none());
+    Ok(())
+}
+
 #[rstest]
 fn get_dir_search4(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}dir1?q=dir1&simple", server.url()))?;
@@ -222,6 +545,239 @@ fn get_file_edit_bin(server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_du(#[with(&["--hidden", "*.html"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}dir1/?du", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["path"], "/dir1");
+    assert_eq!(json["files"], 2);
+    assert_eq!(
+        json["size"],
+        "This is dir1/test.txt".len() + "bin\0\x00123".len()
+    );
+    assert_eq!(json["dirs"], 0);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_size(#[with(&["--dir-size"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = resp.text()?.parse()?;
+    let paths = json["paths"].as_array().unwrap();
+    let dir1 = paths.iter().find(|v| v["name"] == "dir1").unwrap();
+    assert_eq!(dir1["files"], 4);
+    assert!(dir1["size"].as_u64().unwrap() > 0);
+    Ok(())
+}
+
+#[rstest]
+fn get_stat(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html?stat", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["name"], "index.html");
+    assert_eq!(json["type"], "file");
+    assert_eq!(json["size"], 18);
+    assert_eq!(json["symlink"], false);
+    assert_eq!(json["content_type"], "text/html");
+    assert!(json["mtime"].as_u64().unwrap() > 0);
+    assert!(json["mode"].is_string());
+    let resp = fetch!(b"GET", format!("{}dir1/?stat", server.url())).send()?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["type"], "dir");
+    assert!(json["size"].is_null());
+    assert!(json["content_type"].is_null());
+    assert!(json["entries"].is_null());
+    let resp = fetch!(b"GET", format!("{}missing?stat", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn get_stat_symlink(#[with(&["--allow-symlink"])] server: TestServer) -> Result<(), Error> {
+    std::os::unix::fs::symlink("index.html", server.path().join("link.html"))?;
+    let resp = fetch!(b"GET", format!("{}link.html?stat", server.url())).send()?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["symlink"], true);
+    assert_eq!(json["symlink_target"], "index.html");
+    assert_eq!(json["size"], 18);
+    Ok(())
+}
+
+#[rstest]
+fn get_file_hash(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html?hash", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.text()?,
+        "c8dd395e3202674b9512f7b7f956e0d96a8ba8f572e785b0d5413ab83766dbc4  index.html\n"
+    );
+    let resp = fetch!(b"GET", format!("{}index.html?hash=md5", server.url())).send()?;
+    assert_eq!(
+        resp.text()?,
+        format!("{:x}  index.html\n", md5::compute("This is index.html"))
+    );
+    let resp = fetch!(b"GET", format!("{}index.html?hash=crc32", server.url())).send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_file_hash_xattr(#[with(&["--hash-xattr"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html?hash=blake3", server.url());
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(
+        resp.text()?,
+        format!(
+            "{}  index.html\n",
+            blake3::hash(b"This is index.html").to_hex()
+        )
+    );
+    // A changed file isn't served the cached hash
+    std::fs::write(server.path().join("index.html"), "changed")?;
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(
+        resp.text()?,
+        format!("{}  index.html\n", blake3::hash(b"changed").to_hex())
+    );
+    Ok(())
+}
+
+#[rstest]
+fn get_file_metalink(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html?metalink", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/metalink4+xml"
+    );
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"index.html.meta4\""
+    );
+    let body = resp.text()?;
+    assert!(body.contains(r#"<file name="index.html">"#));
+    assert!(body.contains("<size>18</size>"));
+    assert!(body.contains(
+        r#"<hash type="sha-256">c8dd395e3202674b9512f7b7f956e0d96a8ba8f572e785b0d5413ab83766dbc4</hash>"#
+    ));
+    assert!(body.contains(&format!("<url>{}index.html</url>", server.url())));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_metalink(server: TestServer) -> Result<(), Error> {
+    std::fs::create_dir(server.path().join("dir1/sub"))?;
+    std::fs::write(server.path().join("dir1/sub/a b.txt"), "abc")?;
+    let resp = fetch!(b"GET", format!("{}dir1/?metalink", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let body = resp.text()?;
+    assert!(body.contains(r#"<file name="test.txt">"#));
+    assert!(body.contains(&format!("<url>{}dir1/test.txt</url>", server.url())));
+    assert!(body.contains(r#"<file name="sub/a b.txt">"#));
+    assert!(body.contains(&format!("<url>{}dir1/sub/a%20b.txt</url>", server.url())));
+    assert!(!body.contains(r#"<file name="sub">"#));
+    Ok(())
+}
+
+#[rstest]
+fn get_file_torrent(server: TestServer) -> Result<(), Error> {
+    use sha1::Digest;
+    let resp = fetch!(b"GET", format!("{}index.html?torrent", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/x-bittorrent"
+    );
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"index.html.torrent\""
+    );
+    let body = resp.bytes()?.to_vec();
+    let mut info =
+        b"4:infod6:lengthi18e4:name10:index.html12:piece lengthi16384e6:pieces20:".to_vec();
+    info.extend_from_slice(&sha1::Sha1::digest(b"This is index.html"));
+    info.extend_from_slice(b"e");
+    assert!(body.windows(info.len()).any(|v| v == info));
+    let url = format!("{}index.html", server.url());
+    assert!(body.ends_with(format!("8:url-list{}:{url}e", url.len()).as_bytes()));
+    Ok(())
+}
+
+#[rstest]
+fn get_file_view_highlighted(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("main.rs"), "fn main() {}\n")?;
+    let resp = fetch!(b"GET", format!("{}main.rs?view&json", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["language"], "Rust");
+    let highlighted = json["highlighted"].as_str().unwrap();
+    assert!(highlighted.starts_with("<pre"));
+    assert!(highlighted.contains("main"));
+
+    // Editors get the plain text
+    let resp = fetch!(b"GET", format!("{}main.rs?edit&json", server.url())).send()?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["language"], "Rust");
+    assert!(json.get("highlighted").is_none());
+
+    std::fs::write(server.path().join("script"), "#!/bin/bash\necho hi\n")?;
+    let resp = fetch!(b"GET", format!("{}script?view&json", server.url())).send()?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert!(json["language"].as_str().unwrap().contains("bash"));
+
+    let resp = fetch!(b"GET", format!("{}test.txt?view&json", server.url())).send()?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert!(json.get("language").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn get_file_view_media(server: TestServer) -> Result<(), Error> {
+    let mut mp4 = vec![];
+    mp4.extend(16u32.to_be_bytes());
+    mp4.extend(b"ftypisom\0\0\0\0");
+    mp4.extend(32u32.to_be_bytes());
+    mp4.extend(b"moov");
+    mp4.extend(24u32.to_be_bytes());
+    mp4.extend(b"mvhd\0\0\0\0\0\0\0\0\0\0\0\0");
+    mp4.extend(1000u32.to_be_bytes());
+    mp4.extend(90500u32.to_be_bytes());
+    std::fs::write(server.path().join("clip.mp4"), mp4)?;
+    let resp = fetch!(b"GET", format!("{}clip.mp4?view&json", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["kind"], "View");
+    assert_eq!(json["media_type"], "video");
+    assert_eq!(json["duration"], 90.5);
+
+    let mut wav = vec![];
+    wav.extend(b"RIFF");
+    wav.extend((36u32 + 32000).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend([1, 0, 1, 0]);
+    wav.extend(8000u32.to_le_bytes());
+    wav.extend(16000u32.to_le_bytes());
+    wav.extend([2, 0, 16, 0]);
+    wav.extend(b"data");
+    wav.extend(32000u32.to_le_bytes());
+    wav.extend(vec![0; 32000]);
+    std::fs::write(server.path().join("sound.wav"), wav)?;
+    let resp = fetch!(b"GET", format!("{}sound.wav?view&json", server.url())).send()?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert_eq!(json["media_type"], "audio");
+    assert_eq!(json["duration"], 2.0);
+
+    let resp = fetch!(b"GET", format!("{}index.html?view&json", server.url())).send()?;
+    let json: serde_json::Value = resp.text()?.parse()?;
+    assert!(json.get("media_type").is_none());
+    assert!(json.get("duration").is_none());
+    Ok(())
+}
+
 #[rstest]
 fn head_file_404(server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"HEAD", format!("{}404", server.url())).send()?;
@@ -235,7 +791,7 @@ fn options_dir(server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.status(), 200);
     assert_eq!(
         resp.headers().get("allow").unwrap(),
-        "GET,HEAD,PUT,OPTIONS,DELETE,PROPFIND,COPY,MOVE"
+        "GET,HEAD,PUT,OPTIONS,DELETE,PROPFIND,COPY,MOVE,REPORT"
     );
     assert_eq!(resp.headers().get("dav").unwrap(), "1,2");
     Ok(())
@@ -251,6 +807,58 @@ fn put_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn put_file_if_match(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let etag = reqwest::blocking::get(&url)?
+        .headers()
+        .get("etag")
+        .unwrap()
+        .clone();
+    let resp = fetch!(b"PUT", &url)
+        .header("if-match", r#""0-0""#)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = fetch!(b"PUT", &url)
+        .header("if-match", etag.clone())
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    // Changed since
+    let resp = fetch!(b"PUT", &url)
+        .header("if-match", etag)
+        .body(b"abcd".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "abc");
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .header("if-match", "*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    Ok(())
+}
+
+#[rstest]
+fn delete_file_if_unmodified_since(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}test.html", server.url());
+    let resp = fetch!(b"DELETE", &url)
+        .header("if-unmodified-since", "Thu, 01 Jan 1970 00:00:00 GMT")
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let last_modified = reqwest::blocking::get(&url)?
+        .headers()
+        .get("last-modified")
+        .unwrap()
+        .clone();
+    let resp = fetch!(b"DELETE", &url)
+        .header("if-unmodified-since", last_modified)
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    Ok(())
+}
+
 #[rstest]
 fn put_file_create_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}xyz/file1", server.url());
@@ -269,6 +877,158 @@ fn put_file_conflict_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Erro
     Ok(())
 }
 
+#[rstest]
+fn put_file_content_md5(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-md5", "kAFQmDzST7DWlj99KOF/cg==")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url)
+        .header("content-md5", "kAFQmDzST7DWlj99KOF/cg==")
+        .body(b"abd".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 422);
+    // The file that was there is kept
+    let resp = reqwest::blocking::get(url)?;
+    assert_eq!(resp.text()?, "abc");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_repr_digest(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let digest = "sha-256=:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=:";
+    let resp = fetch!(b"PUT", &url)
+        .header("repr-digest", digest)
+        .body(b"abd".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 422);
+    let resp = fetch!(b"PUT", &url)
+        .header("repr-digest", digest)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url)
+        .header("repr-digest", "sha-256=invalid")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 18-20/21")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 0-3/21")
+        .body(b"That".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(url)?;
+    assert_eq!(resp.text()?, "That is index.htmlabc");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial_failed(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 0-2/*")
+        .header("content-md5", "kAFQmDzST7DWlj99KOF/cg==")
+        .body(b"abd".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 422);
+    let resp = reqwest::blocking::get(url)?;
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial_new(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 0-2/*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = reqwest::blocking::get(url)?;
+    assert_eq!(resp.text()?, "abc");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial_beyond(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 20-22/23")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 416);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */18");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial_length_mismatch(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 0-3/*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 0-2/2")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = reqwest::blocking::get(url)?;
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial_unsatisfied(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes */21")
+        .send()?;
+    assert_eq!(resp.status(), 416);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */18");
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes */18")
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes */18")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .header("content-range", "bytes */3")
+        .send()?;
+    assert_eq!(resp.status(), 416);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */0");
+    Ok(())
+}
+
+#[rstest]
+fn put_file_partial_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 3-0/*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
 #[rstest]
 fn delete_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}test.html", server.url());
@@ -286,6 +1046,35 @@ fn delete_file_404(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn delete_dir_confirm(
+    #[with(&["-A", "--confirm-rm-dir"])] server: TestServer,
+) -> Result<(), Error> {
+    let dir = server.path().join("tree");
+    std::fs::create_dir_all(dir.join("a"))?;
+    std::fs::write(dir.join("a/b.txt"), "b")?;
+    std::fs::write(dir.join(".hidden"), "")?;
+    let url = format!("{}tree", server.url());
+    let resp = fetch!(b"DELETE", &url).send()?;
+    assert_eq!(resp.status(), 428);
+    let resp = reqwest::blocking::get(format!("{url}?stat"))?;
+    let json: Value = resp.text()?.parse()?;
+    assert_eq!(json["entries"], 3);
+    let resp = fetch!(b"DELETE", format!("{url}?confirm=2")).send()?;
+    assert_eq!(resp.status(), 412);
+    assert_eq!(resp.text()?, "`tree` has changed, it holds 3 entries now");
+    let resp = fetch!(b"DELETE", format!("{url}?confirm=3")).send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!dir.exists());
+
+    std::fs::create_dir(server.path().join("empty"))?;
+    let resp = fetch!(b"DELETE", format!("{}empty", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"DELETE", format!("{}test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    Ok(())
+}
+
 #[rstest]
 fn get_file_content_type(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}content-types/bin.tar", server.url()))?;
@@ -7,7 +7,7 @@ use fixtures::{port, tmpdir, wait_for_port, Error};
 use assert_cmd::prelude::*;
 use assert_fs::fixture::TempDir;
 use rstest::rstest;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::process::{Command, Stdio};
 
 #[rstest]
@@ -78,3 +78,118 @@ fn no_log(tmpdir: TempDir, port: u16, #[case] args: &[&str]) -> Result<(), Error
     child.kill()?;
     Ok(())
 }
+
+#[rstest]
+#[case(b"PROXY TCP4 10.1.2.3 127.0.0.1 5555 80\r\n".to_vec())]
+#[case([
+    &b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c"[..],
+    &[10, 1, 2, 3, 127, 0, 0, 1, 0x15, 0xb3, 0, 80],
+].concat())]
+fn log_proxy_protocol(tmpdir: TempDir, port: u16, #[case] header: Vec<u8>) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--proxy-protocol", "--log-format", "$remote_addr"])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let stdout = child.stdout.as_mut().expect("Failed to get stdout");
+
+    let mut stream = std::net::TcpStream::connect(("localhost", port))?;
+    stream.write_all(&header)?;
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp)?;
+    assert!(resp.starts_with("HTTP/1.1 200"));
+
+    let mut buf = [0; 2048];
+    let buf_len = stdout.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+
+    assert!(output.lines().last().unwrap().ends_with(" 10.1.2.3"));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+#[case(&["--trusted-proxies", "127.0.0.1,::1"], "1.2.3.4")]
+#[case(&[], "127.0.0.1")]
+fn log_forwarded_for(
+    tmpdir: TempDir,
+    port: u16,
+    #[case] args: &[&str],
+    #[case] remote_addr: &str,
+) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--log-format", "$remote_addr"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let stdout = child.stdout.as_mut().expect("Failed to get stdout");
+
+    let resp = fetch!(b"GET", &format!("http://127.0.0.1:{port}"))
+        .header("X-Forwarded-For", "6.6.6.6, 1.2.3.4")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+
+    let mut buf = [0; 2048];
+    let buf_len = stdout.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+
+    assert!(output
+        .lines()
+        .last()
+        .unwrap()
+        .ends_with(&format!(" {remote_addr}")));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn log_bytes_and_duration(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args([
+            "-A",
+            "--log-format",
+            "$bytes_received $bytes_sent $duration_ms",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let stdout = child.stdout.as_mut().expect("Failed to get stdout");
+    let mut buf = [0; 2048];
+
+    let url = format!("http://localhost:{port}/file-new");
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    let buf_len = stdout.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+    assert!(output.lines().last().unwrap().contains(" 3 0 "));
+
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-length").unwrap(), "3");
+    assert_eq!(resp.text()?, "abc");
+    let buf_len = stdout.read(&mut buf)?;
+    let output = std::str::from_utf8(&buf[0..buf_len])?;
+    assert!(output.lines().last().unwrap().contains(" 0 3 "));
+
+    child.kill()?;
+    Ok(())
+}
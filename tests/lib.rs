@@ -0,0 +1,86 @@
+//! Embed the server as a library, without running the binary
+
+mod fixtures;
+
+use dufs::events::FsEvent;
+use dufs::middleware::{Context, Middleware};
+use dufs::{Request as DufsRequest, Response as DufsResponse};
+use fixtures::{tmpdir, Error};
+use hyper::body::to_bytes;
+use hyper::{Body, Request, StatusCode};
+use rstest::rstest;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+#[rstest]
+#[tokio::test]
+async fn lib_call(#[from(tmpdir)] tmpdir: assert_fs::TempDir) -> Result<(), Error> {
+    let args = dufs::Args::parse_from(["dufs", tmpdir.to_str().unwrap(), "-A"])?;
+    let server = Arc::new(dufs::Server::init(
+        Arc::new(args),
+        Arc::new(AtomicBool::new(true)),
+    )?);
+
+    let req = Request::get("/test.txt").body(Body::empty())?;
+    let res = server.clone().call(req, None).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_bytes(res.into_body()).await?, "This is test.txt");
+
+    let req = Request::put("/new.txt").body(Body::from("new"))?;
+    let res = server.call(req, None).await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(std::fs::read_to_string(tmpdir.join("new.txt"))?, "new");
+    Ok(())
+}
+
+/// Lets through the requests with the token only, and keeps the paths of the events
+#[derive(Default)]
+struct TokenAuth {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for TokenAuth {
+    fn request(&self, req: &mut DufsRequest, _ctx: &mut Context) -> Option<DufsResponse> {
+        if req.headers().get("x-token").is_some_and(|v| v == "secret") {
+            return None;
+        }
+        let mut res = DufsResponse::default();
+        *res.status_mut() = StatusCode::FORBIDDEN;
+        Some(res)
+    }
+
+    fn response(&self, res: &mut DufsResponse, _ctx: &mut Context) {
+        res.headers_mut()
+            .insert("x-middleware", "token".parse().unwrap());
+    }
+
+    fn event(&self, event: &FsEvent) {
+        self.events.lock().unwrap().push(event.path.clone());
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn lib_middleware(#[from(tmpdir)] tmpdir: assert_fs::TempDir) -> Result<(), Error> {
+    let args = dufs::Args::parse_from(["dufs", tmpdir.to_str().unwrap(), "-A"])?;
+    let middleware = TokenAuth::default();
+    let events = middleware.events.clone();
+    let server = Arc::new(
+        dufs::Server::init(Arc::new(args), Arc::new(AtomicBool::new(true)))?
+            .with_middleware(middleware),
+    );
+
+    let req = Request::get("/test.txt").body(Body::empty())?;
+    let res = server.clone().call(req, None).await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.headers().get("x-middleware").unwrap(), "token");
+
+    let req = Request::put("/dir1/new.txt")
+        .header("x-token", "secret")
+        .body(Body::from("new"))?;
+    let res = server.call(req, None).await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(res.headers().get("x-middleware").unwrap(), "token");
+    assert_eq!(*events.lock().unwrap(), ["/dir1/new.txt"]);
+    Ok(())
+}
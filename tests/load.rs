@@ -0,0 +1,60 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::io::Write;
+use std::net::TcpStream;
+
+#[rstest]
+fn max_concurrency(
+    #[with(&["--max-concurrency", "1", "--metrics", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    // An upload whose body is still coming is being handled
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port()))?;
+    stream.write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 503);
+    assert!(resp.headers()["retry-after"].to_str()?.parse::<u64>()? >= 1);
+
+    let metrics = reqwest::blocking::get(format!("{}__dufs__/metrics", server.url()))?.text()?;
+    assert!(metrics.contains("dufs_requests_in_flight 1\n"));
+    assert!(metrics.contains("dufs_saturation 1\n"));
+    assert!(metrics.contains("dufs_requests_rejected_total{reason=\"concurrency\"} 1\n"));
+
+    stream.write_all(b"defghij")?;
+    drop(stream);
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn metrics(
+    #[with(&["--metrics", "--rate-limit", "1r/m"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}__dufs__/metrics", server.url());
+    for _ in 0..2 {
+        reqwest::blocking::get(server.url())?;
+    }
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()?
+        .starts_with("text/plain"));
+    let metrics = resp.text()?;
+    assert!(metrics.contains("# TYPE dufs_requests_in_flight gauge\n"));
+    assert!(metrics.contains("dufs_requests_rejected_total{reason=\"rate_limit\"} 1\n"));
+    assert!(!metrics.contains("dufs_saturation"));
+    Ok(())
+}
+
+#[rstest]
+fn no_metrics(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}__dufs__/metrics", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
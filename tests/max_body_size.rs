@@ -0,0 +1,58 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[rstest]
+fn max_body_size(#[with(&["-A", "--max-body-size", "4"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abcde".to_vec()).send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = fetch!(b"PUT", &url).body(b"abcd".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn max_body_size_chunked(
+    #[with(&["-A", "--max-body-size", "4"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let body = reqwest::blocking::Body::new(std::io::Cursor::new(b"abcde".to_vec()));
+    let resp = fetch!(b"PUT", &url).body(body).send()?;
+    assert_eq!(resp.status(), 413);
+    assert!(!server.path().join("file1").exists());
+    let body = reqwest::blocking::Body::new(std::io::Cursor::new(b"abcd".to_vec()));
+    let resp = fetch!(b"PUT", &url).body(body).send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn max_body_size_any_method(
+    #[with(&["-A", "--max-body-size", "16"])] server: TestServer,
+) -> Result<(), Error> {
+    let propfind = r#"<?xml version="1.0"?><propfind xmlns="DAV:"><allprop/></propfind>"#;
+    let resp = fetch!(b"PROPFIND", server.url()).body(propfind).send()?;
+    assert_eq!(resp.status(), 413);
+    let body = reqwest::blocking::Body::new(std::io::Cursor::new(propfind));
+    let resp = fetch!(b"PROPFIND", server.url()).body(body).send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = fetch!(b"PROPFIND", server.url()).send()?;
+    assert_eq!(resp.status(), 207);
+    Ok(())
+}
+
+#[rstest]
+fn max_body_size_extract(
+    #[with(&["-A", "--max-body-size", "100"])] server: TestServer,
+) -> Result<(), Error> {
+    let archive = reqwest::blocking::get(format!("{}dir1?tar", server.url()))?.bytes()?;
+    let body = reqwest::blocking::Body::new(std::io::Cursor::new(archive));
+    let resp = fetch!(b"PUT", format!("{}new/dir1.tar?unzip", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[rstest]
+fn max_upload_size(
+    #[with(&["-A", "--max-upload-size", "4"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abcde".to_vec()).send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = fetch!(b"PUT", &url).body(b"abcd".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn max_upload_size_chunked(
+    #[with(&["-A", "--max-upload-size", "4"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let body = reqwest::blocking::Body::new(std::io::Cursor::new(b"abcde".to_vec()));
+    let resp = fetch!(b"PUT", &url).body(body).send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn max_upload_size_partial(
+    #[with(&["-A", "--max-upload-size", "20"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("content-range", "bytes 18-20/*")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn max_upload_size_per_path(
+    #[with(&["-A", "--max-upload-size", "100,/dir1:4"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}dir1/file1", server.url()))
+        .body(b"abcde".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = fetch!(b"PUT", format!("{}dir2/file1", server.url()))
+        .body(b"abcde".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
@@ -0,0 +1,17 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[cfg(unix)]
+#[rstest]
+#[case(server(&["-A", "--min-free-space", "1K"]), 201)]
+#[case(server(&["-A", "--min-free-space", "1000000T"]), 507)]
+fn min_free_space(#[case] server: TestServer, #[case] status: u16) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), status);
+    assert_eq!(server.path().join("file1").exists(), status == 201);
+    Ok(())
+}
@@ -0,0 +1,131 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::prelude::*;
+use assert_fs::TempDir;
+use fixtures::{server, Error};
+use rstest::rstest;
+use utils::retrieve_index_paths;
+
+fn media_dir() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    dir.child("song.mp3").write_str("This is song.mp3").unwrap();
+    dir.child("album/track.mp3")
+        .write_str("This is track.mp3")
+        .unwrap();
+    dir
+}
+
+#[rstest]
+fn mount_listed_in_root() -> Result<(), Error> {
+    let media = media_dir();
+    let server = server(vec![
+        "--mount".to_string(),
+        format!("/media={}", media.path().display()),
+    ]);
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 200);
+    let paths = retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("media/"));
+    assert!(paths.contains("index.html"));
+
+    let resp = reqwest::blocking::get(format!("{}media/", server.url()))?;
+    let paths = retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("song.mp3"));
+    assert!(paths.contains("album/"));
+
+    let resp = reqwest::blocking::get(format!("{}media/album/track.mp3", server.url()))?;
+    assert_eq!(resp.text()?, "This is track.mp3");
+    Ok(())
+}
+
+#[rstest]
+fn mount_upload_and_protect_root() -> Result<(), Error> {
+    let media = media_dir();
+    let server = server(vec![
+        "-A".to_string(),
+        "--mount".to_string(),
+        format!("/media={}", media.path().display()),
+    ]);
+    let resp = fetch!(b"PUT", format!("{}media/new.mp3", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(std::fs::read(media.path().join("new.mp3"))?, b"abc");
+
+    let resp = fetch!(b"DELETE", format!("{}media", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(media.path().exists());
+
+    let resp = fetch!(b"MOVE", format!("{}index.html", server.url()))
+        .header("Destination", format!("{}media", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = fetch!(b"MOVE", format!("{}media/song.mp3", server.url()))
+        .header("Destination", format!("{}song.mp3", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(!media.path().join("song.mp3").exists());
+    Ok(())
+}
+
+#[rstest]
+fn mount_propfind_href() -> Result<(), Error> {
+    let media = media_dir();
+    let server = server(vec![
+        "--mount".to_string(),
+        format!("/media={}", media.path().display()),
+    ]);
+    let resp = fetch!(b"PROPFIND", server.url()).send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/media/</D:href>"));
+
+    let resp = fetch!(b"PROPFIND", format!("{}media/song.mp3", server.url())).send()?;
+    assert_eq!(resp.status(), 207);
+    assert!(resp.text()?.contains("<D:href>/media/song.mp3</D:href>"));
+    Ok(())
+}
+
+#[rstest]
+fn mount_move_across_filesystems() -> Result<(), Error> {
+    // /dev/shm is another filesystem than the one of the temporary dirs, where it's there
+    let tmp = TempDir::new()?;
+    let shm = std::path::Path::new("/dev/shm");
+    let media = match shm.is_dir() {
+        true => shm.join(format!("dufs-mount-{}", std::process::id())),
+        false => tmp.path().join("media"),
+    };
+    std::fs::create_dir_all(media.join("album"))?;
+    std::fs::write(media.join("song.mp3"), "This is song.mp3")?;
+    std::fs::write(media.join("album/track.mp3"), "This is track.mp3")?;
+    let server = server(vec![
+        "-A".to_string(),
+        "--mount".to_string(),
+        format!("/media={}", media.display()),
+    ]);
+
+    let resp = fetch!(b"MOVE", format!("{}media/album", server.url()))
+        .header("Destination", format!("{}album", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(!media.join("album").exists());
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("album/track.mp3"))?,
+        "This is track.mp3"
+    );
+
+    let resp = fetch!(b"MOVE", format!("{}media/song.mp3", server.url()))
+        .header("Destination", format!("{}index.html", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!media.join("song.mp3").exists());
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("index.html"))?,
+        "This is song.mp3"
+    );
+
+    std::fs::remove_dir_all(&media)?;
+    Ok(())
+}
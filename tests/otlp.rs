@@ -0,0 +1,79 @@
+mod fixtures;
+mod utils;
+
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use fixtures::{port, tmpdir, wait_for_port, Error};
+use rstest::rstest;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[rstest]
+fn otlp_export(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let collector = TcpListener::bind("127.0.0.1:0")?;
+    collector.set_nonblocking(true)?;
+    let endpoint = format!("http://127.0.0.1:{}", collector.local_addr()?.port());
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--otlp-endpoint", &endpoint])
+        .env("OTEL_BSP_SCHEDULE_DELAY", "100")
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    wait_for_port(port);
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let resp = fetch!(b"GET", format!("http://localhost:{port}/index.html"))
+        .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut stream = loop {
+        match collector.accept() {
+            Ok((stream, _)) => break stream,
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Err(err) => return Err(err.into()),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut request = vec![];
+    let mut buf = [0; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut buf)?;
+        assert_ne!(n, 0);
+        request.extend_from_slice(&buf[..n]);
+        if let Some(i) = request.windows(4).position(|v| v == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+    let headers = String::from_utf8_lossy(&request[..header_end]).to_lowercase();
+    assert!(headers.starts_with("post /v1/traces "));
+    let content_length: usize = headers
+        .lines()
+        .find_map(|v| v.strip_prefix("content-length: "))
+        .unwrap()
+        .parse()?;
+    while request.len() < header_end + content_length {
+        let n = stream.read(&mut buf)?;
+        assert_ne!(n, 0);
+        request.extend_from_slice(&buf[..n]);
+    }
+    stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")?;
+
+    let trace_id: Vec<u8> = (0..trace_id.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&trace_id[i..i + 2], 16).unwrap())
+        .collect();
+    let body = &request[header_end..];
+    assert!(body.windows(trace_id.len()).any(|v| v == trace_id));
+    assert!(body.windows(6).any(|v| v == b"handle"));
+
+    child.kill()?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "plugins")]
+
+mod fixtures;
+mod utils;
+
+use assert_fs::fixture::TempDir;
+use assert_fs::prelude::*;
+use fixtures::{server, Error};
+
+/// Hands out the memory past the first KiB for the input, which is read before the next
+const ALLOC: &str = r#"
+  (memory (export "memory") 1)
+  (func (export "alloc") (param $len i32) (result i32)
+    (i32.const 1024))
+"#;
+
+fn plugin(dir: &TempDir, name: &str, body: &str) -> String {
+    let path = dir.child(name);
+    path.write_str(&format!("(module\n{body}\n{ALLOC})"))
+        .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn plugin_annotates() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let path = plugin(
+        &dir,
+        "annotate.wat",
+        r#"
+  (data (i32.const 0) "{\"headers\":{\"x-plugin\":\"request\"}}")
+  (data (i32.const 64) "{\"headers\":{\"x-plugin-status\":\"seen\"}}")
+  (func (export "on_request") (param i32 i32) (result i64)
+    (i64.const 34))
+  (func (export "on_response") (param i32 i32) (result i64)
+    (i64.const 274877906982))
+"#,
+    );
+    let server = server(&["--plugin", &path]);
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-plugin").unwrap(), "request");
+    assert_eq!(resp.headers().get("x-plugin-status").unwrap(), "seen");
+    Ok(())
+}
+
+#[test]
+fn plugin_vetoes() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let path = plugin(
+        &dir,
+        "veto.wat",
+        r#"
+  (import "dufs" "log" (func $log (param i32 i32)))
+  (data (i32.const 0) "{\"status\":403,\"body\":\"Vetoed by plugin\"}")
+  (func (export "on_request") (param $ptr i32) (param $len i32) (result i64)
+    (call $log (local.get $ptr) (local.get $len))
+    (i64.const 40))
+"#,
+    );
+    let server = server(&["--plugin", &path]);
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.text()?, "Vetoed by plugin");
+    Ok(())
+}
+
+#[test]
+fn plugin_runs_out_of_fuel() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let path = plugin(
+        &dir,
+        "loop.wat",
+        r#"
+  (func (export "on_request") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const 0))
+"#,
+    );
+    let server = server(&["--plugin", &path]);
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 500);
+    Ok(())
+}
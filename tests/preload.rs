@@ -0,0 +1,105 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer, DIR_GIT};
+use headers::HeaderValue;
+use rstest::rstest;
+use std::time::{Duration, Instant};
+
+/// Gets the file until its text is as expected, preloaded files are refreshed in the background
+fn wait_text(server: &TestServer, name: &str, expected: &str) -> Result<(), Error> {
+    let url = format!("{}{name}", server.url());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let text = reqwest::blocking::get(&url)?.text()?;
+        if text == expected {
+            return Ok(());
+        }
+        if Instant::now() > deadline {
+            panic!("`{name}` was {text:?}, expected {expected:?}");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[rstest]
+fn preload(
+    #[with(&["--preload", "1M", "--hidden", ".git"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/html; charset=UTF-8"
+    );
+    assert_eq!(resp.text()?, "This is index.html");
+
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=8-12"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 8-12/18"
+    );
+    assert_eq!(resp.text()?, "index");
+
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=0-3,8-12"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    let text = resp.text()?;
+    assert!(text.contains("Content-Range: bytes 0-3/18\r\n\r\nThis\r\n"));
+    assert!(text.contains("Content-Range: bytes 8-12/18\r\n\r\nindex\r\n"));
+
+    let resp = reqwest::blocking::get(format!("{}dir1/", server.url()))?;
+    assert_resp_paths!(resp);
+
+    // Hidden files aren't preloaded, they are still served from disk
+    let resp = reqwest::blocking::get(format!("{}{DIR_GIT}index.html", server.url()))?;
+    assert_eq!(resp.text()?, "This is .git/index.html");
+    Ok(())
+}
+
+#[rstest]
+fn preload_watch(#[with(&["--preload", "1M"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("index.html"), "changed")?;
+    wait_text(&server, "index.html", "changed")?;
+
+    std::fs::create_dir(server.path().join("new"))?;
+    std::fs::write(server.path().join("new/file.txt"), "new file")?;
+    wait_text(&server, "new/file.txt", "new file")?;
+
+    std::fs::remove_file(server.path().join("new/file.txt"))?;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while reqwest::blocking::get(format!("{}new/file.txt", server.url()))?.status() != 404 {
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+#[rstest]
+fn preload_upload(#[with(&["--preload", "1M", "-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}dir1/upload.txt", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"uploaded".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    // Served right away, without waiting for the watcher
+    assert_eq!(reqwest::blocking::get(&url)?.text()?, "uploaded");
+
+    let resp = fetch!(b"DELETE", &url).send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(reqwest::blocking::get(&url)?.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn preload_too_large(#[with(&["--preload", "20"])] server: TestServer) -> Result<(), Error> {
+    for name in ["index.html", "dir1/test.txt", "dir2/index.html"] {
+        let resp = reqwest::blocking::get(format!("{}{name}", server.url()))?;
+        assert_eq!(resp.text()?, format!("This is {name}"));
+    }
+    std::fs::write(server.path().join("test.txt"), "a file too large to fit")?;
+    wait_text(&server, "test.txt", "a file too large to fit")?;
+    Ok(())
+}
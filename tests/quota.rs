@@ -0,0 +1,79 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+fn put(server: &TestServer, path: &str, size: usize) -> Result<u16, Error> {
+    let resp = fetch!(b"PUT", format!("{}{}", server.url(), path))
+        .body(vec![b'x'; size])
+        .send()?;
+    Ok(resp.status().as_u16())
+}
+
+#[rstest]
+fn quota_upload(#[with(&["-A", "--quota", "/box:10"])] server: TestServer) -> Result<(), Error> {
+    assert_eq!(put(&server, "box/a", 5)?, 201);
+    assert_eq!(put(&server, "box/b", 6)?, 507);
+    assert!(!server.path().join("box/b").exists());
+    assert_eq!(put(&server, "box/b", 5)?, 201);
+    assert_eq!(put(&server, "box/c", 1)?, 507);
+    // Shrinking a file is fine, also with a full quota
+    assert_eq!(put(&server, "box/b", 3)?, 201);
+    assert_eq!(put(&server, "box/c", 2)?, 201);
+    assert_eq!(put(&server, "box/d", 1)?, 507);
+    // Outside of the quota
+    assert_eq!(put(&server, "other", 100)?, 201);
+    Ok(())
+}
+
+#[rstest]
+fn quota_after_delete(
+    #[with(&["-A", "--quota", "/box:10"])] server: TestServer,
+) -> Result<(), Error> {
+    assert_eq!(put(&server, "box/a", 10)?, 201);
+    assert_eq!(put(&server, "box/b", 1)?, 507);
+    let resp = fetch!(b"DELETE", format!("{}box/a", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(put(&server, "box/b", 10)?, 201);
+    Ok(())
+}
+
+#[rstest]
+fn quota_copy_and_mkcol(
+    #[with(&["-A", "--quota", "/box:10"])] server: TestServer,
+) -> Result<(), Error> {
+    assert_eq!(put(&server, "box/a", 6)?, 201);
+    let resp = fetch!(b"COPY", format!("{}box/a", server.url()))
+        .header("Destination", format!("{}box/b", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 507);
+    assert_eq!(put(&server, "box/b", 5)?, 507);
+    assert_eq!(put(&server, "box/b", 4)?, 201);
+    let resp = fetch!(b"MKCOL", format!("{}box/dir", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+    std::fs::write(server.path().join("box/c"), b"x")?;
+    // Changes made outside of the server are noticed after a delete
+    let resp = fetch!(b"DELETE", format!("{}box/dir", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"MKCOL", format!("{}box/dir", server.url())).send()?;
+    assert_eq!(resp.status(), 507);
+    Ok(())
+}
+
+#[rstest]
+fn quota_copy_dir(#[with(&["-A", "--quota", "/box:10"])] server: TestServer) -> Result<(), Error> {
+    assert_eq!(put(&server, "box/dir/a", 4)?, 201);
+    let copy = |dest: &str| -> Result<u16, Error> {
+        let resp = fetch!(b"COPY", format!("{}box/dir", server.url()))
+            .header("Destination", format!("{}{dest}", server.url()))
+            .send()?;
+        Ok(resp.status().as_u16())
+    };
+    assert_eq!(copy("box/dir2")?, 201);
+    // The copy is counted, so a third one doesn't fit
+    assert_eq!(copy("box/dir3")?, 507);
+    assert_eq!(put(&server, "box/b", 2)?, 201);
+    assert_eq!(put(&server, "box/c", 1)?, 507);
+    Ok(())
+}
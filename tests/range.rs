@@ -43,3 +43,75 @@ fn get_file_range_invalid(server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */18");
     Ok(())
 }
+
+#[rstest]
+fn get_file_range_suffix(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=-4"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 14-17/18"
+    );
+    assert_eq!(resp.text()?, "html");
+    Ok(())
+}
+
+#[rstest]
+fn get_file_range_multipart(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=0-3, 12-, 30-40"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    let content_type = resp.headers().get("content-type").unwrap().to_str()?;
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap()
+        .to_string();
+    let content_length: usize = resp
+        .headers()
+        .get("content-length")
+        .unwrap()
+        .to_str()?
+        .parse()?;
+    let body = resp.text()?;
+    assert_eq!(body.len(), content_length);
+    assert_eq!(
+        body,
+        format!(
+            "\r\n--{boundary}\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Range: bytes 0-3/18\r\n\r\nThis\
+            \r\n--{boundary}\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Range: bytes 12-17/18\r\n\r\nx.html\
+            \r\n--{boundary}--\r\n"
+        )
+    );
+    Ok(())
+}
+
+#[rstest]
+fn get_file_range_malformed(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("range", HeaderValue::from_static("bytes=0-3,5-2"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn get_file_range_buffer_size(
+    #[with(&["--buffer-size", "4K"])] server: TestServer,
+) -> Result<(), Error> {
+    let data: Vec<u8> = (0..100_000u32).map(|v| v as u8).collect();
+    std::fs::write(server.path().join("large.bin"), &data)?;
+    let resp = reqwest::blocking::get(format!("{}large.bin", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.bytes()?.as_ref(), data.as_slice());
+
+    let resp = fetch!(b"GET", format!("{}large.bin", server.url()))
+        .header("range", HeaderValue::from_static("bytes=5000-59999"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.bytes()?.as_ref(), &data[5000..60000]);
+    Ok(())
+}
@@ -0,0 +1,43 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+#[rstest]
+fn rate_limit(#[with(&["--rate-limit", "2r/m"])] server: TestServer) -> Result<(), Error> {
+    for _ in 0..2 {
+        let resp = reqwest::blocking::get(server.url())?;
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 429);
+    let retry_after: u64 = resp.headers()["retry-after"].to_str()?.parse()?;
+    assert!((1..=30).contains(&retry_after));
+    Ok(())
+}
+
+#[rstest]
+fn rate_limit_per_user(
+    #[with(&["--auth", "user:pass@/:rw", "--auth", "@/", "--rate-limit", "2r/m"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    for _ in 0..2 {
+        let resp = reqwest::blocking::get(server.url())?;
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = reqwest::blocking::get(server.url())?;
+    assert_eq!(resp.status(), 429);
+    // The requests of a user count against the user, not the IP
+    for _ in 0..2 {
+        let resp = fetch!(b"GET", server.url())
+            .basic_auth("user", Some("pass"))
+            .send()?;
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 429);
+    Ok(())
+}
@@ -19,6 +19,79 @@ fn render_index2(#[with(&["--render-index"])] server: TestServer) -> Result<(),
     Ok(())
 }
 
+#[rstest]
+fn render_index_names(
+    #[with(&["--render-index", "--index-name", "default.htm,test.html"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("dir2/default.htm"), "default")?;
+    let resp = reqwest::blocking::get(format!("{}dir1/", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir1/test.html");
+    let resp = reqwest::blocking::get(format!("{}dir2/", server.url()))?;
+    assert_eq!(resp.text()?, "default");
+    Ok(())
+}
+
+#[rstest]
+fn try_files(
+    #[with(&["--try-files", "$uri $uri/index.html $uri/ /dir1/index.html"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}test.txt", server.url()))?;
+    assert_eq!(resp.text()?, "This is test.txt");
+    let resp = reqwest::blocking::get(format!("{}dir2", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir2/index.html");
+    let resp = reqwest::blocking::get(format!("{}{DIR_NO_INDEX}?json", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.text()?.contains("test.txt"));
+    let resp = reqwest::blocking::get(format!("{}no/such/route", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir1/index.html");
+    Ok(())
+}
+
+#[rstest]
+fn try_files_status(
+    #[with(&["--try-files", "$uri/index.html =410", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir1/index.html");
+    let resp = reqwest::blocking::get(format!("{}test.txt", server.url()))?;
+    assert_eq!(resp.status(), 410);
+    // Other methods go to the path requested
+    let resp = fetch!(b"PUT", format!("{}test.txt", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn try_files_checks_auth_of_target(
+    #[with(&["--try-files", "$uri /dir1/index.html", "--auth", "user:pass@/dir1", "--auth", "@/dir2"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir2/missing", server.url()))?;
+    assert_eq!(resp.status(), 401);
+    let resp = reqwest::blocking::get(format!("{}dir2/test.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    // Whether a path exists isn't told before the request is authorized
+    let resp = reqwest::blocking::get(format!("{}dir1/missing", server.url()))?;
+    assert_eq!(resp.status(), 401);
+    let resp = reqwest::blocking::get(format!("{}dir1/test.txt", server.url()))?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn try_files_status_checks_auth(
+    #[with(&["--try-files", "$uri =404", "--auth", "user:pass@/:rw", "--auth", "@/dir2"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/missing", server.url()))?;
+    assert_eq!(resp.status(), 401);
+    let resp = reqwest::blocking::get(format!("{}dir2/missing", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
 #[rstest]
 fn render_try_index(#[with(&["--render-try-index"])] server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(server.url())?;
@@ -78,3 +151,38 @@ fn render_spa2(#[with(&["--render-spa"])] server: TestServer) -> Result<(), Erro
     assert_eq!(text, "This is index.html");
     Ok(())
 }
+
+#[rstest]
+fn render_spa_dotted_route(#[with(&["--render-spa"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}user/1.5", server.url());
+    let resp = fetch!(b"GET", &url).header("accept", "text/html").send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is index.html");
+    // A missing script is not the app
+    let resp = fetch!(b"GET", format!("{}app.js", server.url()))
+        .header("accept", "*/*")
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn render_spa_roots(
+    #[with(&["--spa-root", "/dir1,/dir1/dir-no-index", "--spa-fallback", "test.html"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    std::fs::create_dir(server.path().join("dir1/dir-no-index"))?;
+    std::fs::write(server.path().join("dir1/dir-no-index/test.html"), "admin")?;
+    let resp = reqwest::blocking::get(format!("{}dir1/users/1", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir1/test.html");
+    let resp = reqwest::blocking::get(format!("{}dir1/dir-no-index/users", server.url()))?;
+    assert_eq!(resp.text()?, "admin");
+    // The folder of the app without an index
+    let resp = reqwest::blocking::get(format!("{}dir1/dir-no-index/", server.url()))?;
+    assert_eq!(resp.text()?, "admin");
+    let resp = reqwest::blocking::get(format!("{}dir2/users/1", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(format!("{}dir2/test.txt", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir2/test.txt");
+    Ok(())
+}
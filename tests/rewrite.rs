@@ -0,0 +1,61 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+fn client() -> Result<reqwest::blocking::Client, Error> {
+    Ok(reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?)
+}
+
+#[rstest]
+fn redirect(
+    #[with(&["--redirect", "/old/*=/dir1/$1", "--redirect", "/go=302:https://example.com/"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = client()?
+        .get(format!("{}old/test.txt?hash", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 301);
+    assert_eq!(resp.headers()["location"], "/dir1/test.txt?hash");
+    let resp = client()?.get(format!("{}go", server.url())).send()?;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.headers()["location"], "https://example.com/");
+    let resp = client()?.get(format!("{}go/", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn rewrite(#[with(&["--rewrite", "/latest/*=/dir1/$1"])] server: TestServer) -> Result<(), Error> {
+    let resp = client()?
+        .get(format!("{}latest/test.txt", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir1/test.txt");
+    let resp = client()?
+        .get(format!("{}latest/?simple", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.text()?.contains("test.txt"));
+    Ok(())
+}
+
+#[rstest]
+fn rewrite_checks_auth_of_target(
+    #[with(&["--auth", "user:pass@/dir1:rw", "--auth", "@/dir2", "--rewrite", "/pub/*=/dir1/$1"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = client()?
+        .get(format!("{}pub/test.txt", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = client()?
+        .get(format!("{}pub/test.txt", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
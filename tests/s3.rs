@@ -0,0 +1,516 @@
+mod fixtures;
+mod utils;
+
+use assert_cmd::prelude::*;
+use fixtures::{port, wait_for_port, Error};
+use rstest::rstest;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Keys a page of the mock's listings holds, so that listings take several pages
+const PAGE_SIZE: usize = 2;
+
+/// The parts of the multipart uploads in progress, by their ids
+type Uploads = HashMap<String, BTreeMap<u32, Vec<u8>>>;
+
+/// An S3 server keeping the objects of the bucket `bucket` in memory, with the requests
+/// it took
+#[derive(Clone, Default)]
+struct MockS3 {
+    objects: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    uploads: Arc<Mutex<Uploads>>,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockS3 {
+    fn start(objects: &[(&str, &[u8])]) -> Result<(Self, u16), Error> {
+        let mock = Self::default();
+        for (key, data) in objects {
+            mock.put(key, data);
+        }
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = mock.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    let _ = server.serve(stream);
+                });
+            }
+        });
+        Ok((mock, port))
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: &str, data: &[u8]) {
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), data.to_vec());
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn log(&self) -> Vec<String> {
+        self.log.lock().unwrap().clone()
+    }
+
+    fn serve(&self, stream: TcpStream) -> Result<(), Error> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default().to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.to_lowercase(), value.trim().to_string());
+                }
+                None => break,
+            }
+        }
+        let length = headers
+            .get("content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let query: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        let key = path.strip_prefix("/bucket/").unwrap_or_default();
+        let key = percent_encoding::percent_decode_str(key)
+            .decode_utf8()?
+            .to_string();
+        let mut entry = format!("{method} {key}");
+        let mut names: Vec<_> = query.keys().cloned().collect();
+        names.sort();
+        if !names.is_empty() {
+            entry.push_str(&format!("?{}", names.join("&")));
+        }
+        if let Some(range) = headers.get("range") {
+            entry.push_str(&format!(" {range}"));
+        }
+        self.log.lock().unwrap().push(entry);
+
+        let signed = headers
+            .get("authorization")
+            .is_some_and(|v| v.starts_with("AWS4-HMAC-SHA256 Credential=test-key/"))
+            && headers.get("x-amz-content-sha256").map(|v| v.as_str()) == Some("UNSIGNED-PAYLOAD")
+            && headers.contains_key("x-amz-date");
+        let (status, extra, body) = if !signed {
+            (
+                403,
+                vec![],
+                b"<Error><Code>AccessDenied</Code></Error>".to_vec(),
+            )
+        } else {
+            self.handle(&method, &key, &query, &headers, body)
+        };
+        let mut stream = stream;
+        let mut head = format!(
+            "HTTP/1.1 {status} S3\r\nconnection: close\r\ncontent-length: {}\r\n",
+            body.len()
+        );
+        for (name, value) in extra {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        if method != "HEAD" {
+            stream.write_all(&body)?;
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &self,
+        method: &str,
+        key: &str,
+        query: &HashMap<String, String>,
+        headers: &HashMap<String, String>,
+        body: Vec<u8>,
+    ) -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+        let not_found = (
+            404,
+            vec![],
+            b"<Error><Code>NoSuchKey</Code></Error>".to_vec(),
+        );
+        match method {
+            "GET" if query.get("list-type").map(|v| v.as_str()) == Some("2") => {
+                (200, vec![], self.list(query).into_bytes())
+            }
+            "HEAD" | "GET" => {
+                let data = match self.get(key) {
+                    Some(v) => v,
+                    None => return not_found,
+                };
+                let mut extra = vec![
+                    ("etag", etag(&data)),
+                    ("last-modified", "Wed, 01 Jan 2025 00:00:00 GMT".to_string()),
+                ];
+                let range = headers
+                    .get("range")
+                    .and_then(|v| v.strip_prefix("bytes="))
+                    .and_then(|v| v.split_once('-'))
+                    .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)));
+                match range {
+                    Some((start, end)) if method == "GET" => {
+                        let end: usize = std::cmp::min(end, data.len() - 1);
+                        extra.push((
+                            "content-range",
+                            format!("bytes {start}-{end}/{}", data.len()),
+                        ));
+                        (206, extra, data[start..=end].to_vec())
+                    }
+                    _ => (200, extra, data),
+                }
+            }
+            "PUT" if query.contains_key("partNumber") => {
+                let number = query["partNumber"].parse().unwrap();
+                let mut uploads = self.uploads.lock().unwrap();
+                let parts = match uploads.get_mut(&query["uploadId"]) {
+                    Some(v) => v,
+                    None => return not_found,
+                };
+                let extra = vec![("etag", etag(&body))];
+                parts.insert(number, body);
+                (200, extra, vec![])
+            }
+            "PUT" => {
+                let data = match headers.get("x-amz-copy-source") {
+                    Some(source) => {
+                        let source = percent_encoding::percent_decode_str(source)
+                            .decode_utf8_lossy()
+                            .to_string();
+                        match source.strip_prefix("/bucket/").and_then(|v| self.get(v)) {
+                            Some(v) => v,
+                            None => return not_found,
+                        }
+                    }
+                    None => body,
+                };
+                let extra = vec![("etag", etag(&data))];
+                self.put(key, &data);
+                (200, extra, vec![])
+            }
+            "POST" if query.contains_key("uploads") => {
+                let id = format!("upload-{}", self.uploads.lock().unwrap().len());
+                self.uploads
+                    .lock()
+                    .unwrap()
+                    .insert(id.clone(), BTreeMap::new());
+                let output = format!(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>{key}</Key><UploadId>{id}</UploadId></InitiateMultipartUploadResult>"
+                );
+                (200, vec![], output.into_bytes())
+            }
+            "POST" if query.contains_key("uploadId") => {
+                let parts = match self.uploads.lock().unwrap().remove(&query["uploadId"]) {
+                    Some(v) => v,
+                    None => return not_found,
+                };
+                let body = String::from_utf8_lossy(&body).to_string();
+                assert_eq!(body.matches("<Part>").count(), parts.len());
+                let data: Vec<u8> = parts.into_values().flatten().collect();
+                self.put(key, &data);
+                let output = format!(
+                    "<CompleteMultipartUploadResult><Key>{key}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+                    etag(&data)
+                );
+                (200, vec![], output.into_bytes())
+            }
+            "DELETE" if query.contains_key("uploadId") => {
+                self.uploads.lock().unwrap().remove(&query["uploadId"]);
+                (204, vec![], vec![])
+            }
+            "DELETE" => {
+                self.objects.lock().unwrap().remove(key);
+                (204, vec![], vec![])
+            }
+            _ => (405, vec![], vec![]),
+        }
+    }
+
+    /// A page of the keys, and of their prefixes up to the delimiter, after the index in the
+    /// continuation token
+    fn list(&self, query: &HashMap<String, String>) -> String {
+        let prefix = query.get("prefix").cloned().unwrap_or_default();
+        let delimiter = query.get("delimiter");
+        let max_keys: usize = query
+            .get("max-keys")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(PAGE_SIZE)
+            .min(PAGE_SIZE);
+        let start: usize = query
+            .get("continuation-token")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let mut items: Vec<(bool, String, usize)> = vec![];
+        for (key, data) in self.objects.lock().unwrap().iter() {
+            let rest = match key.strip_prefix(&prefix) {
+                Some(v) => v,
+                None => continue,
+            };
+            match delimiter.and_then(|v| rest.find(v.as_str())) {
+                Some(i) => {
+                    let common = format!("{prefix}{}", &rest[..=i]);
+                    if !items.iter().any(|(_, v, _)| *v == common) {
+                        items.push((true, common, 0));
+                    }
+                }
+                None => items.push((false, key.clone(), data.len())),
+            }
+        }
+        let end = std::cmp::min(start + max_keys, items.len());
+        let mut output = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>bucket</Name><Prefix>{prefix}</Prefix><IsTruncated>{}</IsTruncated>",
+            end < items.len()
+        );
+        for (is_prefix, name, size) in &items[start..end] {
+            if *is_prefix {
+                output.push_str(&format!(
+                    "<CommonPrefixes><Prefix>{name}</Prefix></CommonPrefixes>"
+                ));
+            } else {
+                output.push_str(&format!(
+                    "<Contents><Key>{name}</Key><LastModified>2025-01-01T00:00:00.000Z</LastModified><ETag>&quot;0&quot;</ETag><Size>{size}</Size></Contents>"
+                ));
+            }
+        }
+        if end < items.len() {
+            output.push_str(&format!(
+                "<NextContinuationToken>{end}</NextContinuationToken>"
+            ));
+        }
+        output.push_str("</ListBucketResult>");
+        output
+    }
+}
+
+fn etag(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Serves `s3://bucket/prefix` of the mock
+fn serve(mock_port: u16, port: u16, args: &[&str]) -> Result<Child, Error> {
+    let child = Command::cargo_bin("dufs")?
+        .arg("s3://bucket/prefix")
+        .arg("-p")
+        .arg(port.to_string())
+        .args(args)
+        .env("AWS_ACCESS_KEY_ID", "test-key")
+        .env("AWS_SECRET_ACCESS_KEY", "test-secret")
+        .env("AWS_REGION", "us-east-1")
+        .env("AWS_ENDPOINT_URL", format!("http://127.0.0.1:{mock_port}"))
+        .env_remove("AWS_ENDPOINT_URL_S3")
+        .env_remove("AWS_SESSION_TOKEN")
+        .stdout(Stdio::null())
+        .spawn()?;
+    wait_for_port(port);
+    Ok(child)
+}
+
+const OBJECTS: &[(&str, &[u8])] = &[
+    ("prefix/a.txt", b"hello world"),
+    ("prefix/c.txt", b"c"),
+    ("prefix/dir/b.txt", b"b"),
+    ("prefix/dir/sub/d.txt", b"d"),
+    ("prefix/e.txt", b"e"),
+    ("other/x.txt", b"x"),
+];
+
+#[rstest]
+fn s3_list_and_get(port: u16) -> Result<(), Error> {
+    let (_mock, mock_port) = MockS3::start(OBJECTS)?;
+    let mut child = serve(mock_port, port, &[])?;
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/?simple"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "dir/\na.txt\nc.txt\ne.txt\n");
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir/?simple"))?;
+    assert_eq!(resp.text()?, "sub/\nb.txt\n");
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/a.txt"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+    assert!(resp.headers().contains_key("etag"));
+    assert_eq!(resp.text()?, "hello world");
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/x.txt"))?;
+    assert_eq!(resp.status(), 404);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn s3_get_range(port: u16) -> Result<(), Error> {
+    let (mock, mock_port) = MockS3::start(OBJECTS)?;
+    let mut child = serve(mock_port, port, &[])?;
+
+    let resp = fetch!(b"GET", format!("http://localhost:{port}/a.txt"))
+        .header("range", "bytes=6-")
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 6-10/11"
+    );
+    assert_eq!(resp.text()?, "world");
+    assert!(mock
+        .log()
+        .contains(&"GET prefix/a.txt?response-content-encoding bytes=6-10".to_string()));
+
+    let resp = fetch!(b"GET", format!("http://localhost:{port}/a.txt"))
+        .header("range", "bytes=20-")
+        .send()?;
+    assert_eq!(resp.status(), 416);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn s3_put(port: u16) -> Result<(), Error> {
+    let (mock, mock_port) = MockS3::start(OBJECTS)?;
+    let mut child = serve(mock_port, port, &["--allow-upload"])?;
+
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/new.txt"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(mock.get("prefix/new.txt").as_deref(), Some(&b"abc"[..]));
+
+    // Larger than a part
+    let data: Vec<u8> = (0..20 << 20).map(|v| (v % 251) as u8).collect();
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/dir/big.bin"))
+        .body(data.clone())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(mock.get("prefix/dir/big.bin") == Some(data));
+    let log = mock.log();
+    assert!(log.contains(&"POST prefix/dir/big.bin?uploads".to_string()));
+    assert!(log.contains(&"PUT prefix/dir/big.bin?partNumber&uploadId".to_string()));
+    assert!(log.contains(&"POST prefix/dir/big.bin?uploadId".to_string()));
+
+    // Overwriting takes `--allow-delete`
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/a.txt"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn s3_put_too_large(port: u16) -> Result<(), Error> {
+    let (mock, mock_port) = MockS3::start(OBJECTS)?;
+    let mut child = serve(
+        mock_port,
+        port,
+        &["--allow-upload", "--max-upload-size", "4"],
+    )?;
+
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/new.txt"))
+        .body(b"abcdef".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    assert_eq!(mock.get("prefix/new.txt"), None);
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn s3_webdav(port: u16) -> Result<(), Error> {
+    let (mock, mock_port) = MockS3::start(OBJECTS)?;
+    let mut child = serve(mock_port, port, &["-A"])?;
+
+    let resp = fetch!(b"PROPFIND", format!("http://localhost:{port}/dir/")).send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/dir/</D:href>"));
+    assert!(body.contains("<D:href>/dir/b.txt</D:href>"));
+    assert!(body.contains("<D:href>/dir/sub/</D:href>"));
+
+    let resp = fetch!(b"PROPFIND", format!("http://localhost:{port}/"))
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = fetch!(b"MKCOL", format!("http://localhost:{port}/new")).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(mock.get("prefix/new/").as_deref(), Some(&b""[..]));
+    let resp = fetch!(b"MKCOL", format!("http://localhost:{port}/new")).send()?;
+    assert_eq!(resp.status(), 405);
+
+    let resp = fetch!(b"MOVE", format!("http://localhost:{port}/a.txt"))
+        .header("Destination", format!("http://localhost:{port}/new/a.txt"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(mock.get("prefix/a.txt"), None);
+    assert_eq!(
+        mock.get("prefix/new/a.txt").as_deref(),
+        Some(&b"hello world"[..])
+    );
+
+    let resp = fetch!(b"COPY", format!("http://localhost:{port}/dir"))
+        .header("Destination", format!("http://localhost:{port}/new/dir"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        mock.get("prefix/new/dir/sub/d.txt").as_deref(),
+        Some(&b"d"[..])
+    );
+    assert_eq!(mock.get("prefix/dir/sub/d.txt").as_deref(), Some(&b"d"[..]));
+
+    let resp = fetch!(b"COPY", format!("http://localhost:{port}/c.txt"))
+        .header("Destination", format!("http://localhost:{port}/e.txt"))
+        .header("Overwrite", "F")
+        .send()?;
+    assert_eq!(resp.status(), 412);
+
+    let resp = fetch!(b"DELETE", format!("http://localhost:{port}/new")).send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!mock.keys().iter().any(|v| v.starts_with("prefix/new/")));
+    assert!(mock.keys().contains(&"other/x.txt".to_string()));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn s3_exclude(port: u16) -> Result<(), Error> {
+    let (_mock, mock_port) = MockS3::start(OBJECTS)?;
+    let mut child = serve(mock_port, port, &["--exclude", "dir"])?;
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/?simple"))?;
+    assert_eq!(resp.text()?, "a.txt\nc.txt\ne.txt\n");
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir/b.txt"))?;
+    assert_eq!(resp.status(), 404);
+
+    child.kill()?;
+    Ok(())
+}
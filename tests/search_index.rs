@@ -0,0 +1,45 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::time::{Duration, Instant};
+
+/// Searches until the result is as expected, the index is kept up to date in the background
+fn wait_search(server: &TestServer, search: &str, expected: &str) -> Result<(), Error> {
+    let url = format!("{}?q={search}&simple", server.url());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let text = reqwest::blocking::get(&url)?.text()?;
+        if text == expected {
+            return Ok(());
+        }
+        if Instant::now() > deadline {
+            panic!("search `{search}` returned {text:?}, expected {expected:?}");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[rstest]
+fn search_index(
+    #[with(&["-A", "--search-index", "--hidden", "secret*"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q=test.html&simple", server.url()))?;
+    assert!(resp.text()?.lines().any(|v| v == "dir1/test.html"));
+
+    std::fs::create_dir(server.path().join("dir1/Found"))?;
+    std::fs::write(server.path().join("dir1/Found/needle.txt"), "")?;
+    std::fs::write(server.path().join("dir1/secret-needle.txt"), "")?;
+    wait_search(&server, "NEEDLE", "dir1/Found/needle.txt\n")?;
+
+    std::fs::rename(
+        server.path().join("dir1/Found"),
+        server.path().join("dir2/Moved"),
+    )?;
+    wait_search(&server, "needle", "dir2/Moved/needle.txt\n")?;
+
+    std::fs::remove_dir_all(server.path().join("dir2/Moved"))?;
+    wait_search(&server, "needle", "")?;
+    Ok(())
+}
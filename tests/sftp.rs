@@ -0,0 +1,149 @@
+#![cfg(feature = "sftp")]
+
+mod fixtures;
+mod utils;
+
+use fixtures::{port, server, Error, TestServer};
+use russh::client::{self, Config};
+use russh::keys::PublicKey;
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::StatusCode;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+struct Client;
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+/// Logs in with the password, returning `None` if turned down
+async fn connect(port: u16, user: &str, pass: &str) -> Result<Option<SftpSession>, Error> {
+    let mut session =
+        client::connect(Arc::new(Config::default()), ("127.0.0.1", port), Client).await?;
+    if !session.authenticate_password(user, pass).await?.success() {
+        return Ok(None);
+    }
+    let channel = session.channel_open_session().await?;
+    channel.request_subsystem(true, "sftp").await?;
+    Ok(Some(SftpSession::new(channel.into_stream()).await?))
+}
+
+fn sftp_server(args: &[&str]) -> (TestServer, u16) {
+    let sftp_port = port();
+    let sftp_port_arg = sftp_port.to_string();
+    let mut all_args = vec!["--sftp-port", sftp_port_arg.as_str()];
+    all_args.extend_from_slice(args);
+    (server(&all_args), sftp_port)
+}
+
+fn status(err: russh_sftp::client::error::Error) -> StatusCode {
+    match err {
+        russh_sftp::client::error::Error::Status(v) => v.status_code,
+        err => panic!("unexpected error {err}"),
+    }
+}
+
+#[tokio::test]
+async fn sftp_list_and_read() -> Result<(), Error> {
+    let (_server, port) = sftp_server(&["--hidden", ".git"]);
+    let sftp = connect(port, "anonymous", "").await?.unwrap();
+    assert_eq!(sftp.canonicalize(".").await?, "/");
+    let names: Vec<String> = sftp.read_dir("/").await?.map(|v| v.file_name()).collect();
+    assert!(names.iter().any(|v| v == "dir1"));
+    assert!(names.iter().any(|v| v == "test.txt"));
+    assert!(!names.iter().any(|v| v == ".git"));
+    assert_eq!(sftp.read("test.txt").await?, b"This is test.txt");
+    let meta = sftp.metadata("dir1/test.txt").await?;
+    assert_eq!(meta.size, Some(21));
+    assert!(sftp.metadata("dir1").await?.is_dir());
+    let mut file = sftp.open("/dir1/../dir1/test.txt").await?;
+    let mut data = String::new();
+    file.read_to_string(&mut data).await?;
+    assert_eq!(data, "This is dir1/test.txt");
+    assert_eq!(
+        status(sftp.metadata("missing.txt").await.unwrap_err()),
+        StatusCode::NoSuchFile
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn sftp_changes() -> Result<(), Error> {
+    let (server, port) = sftp_server(&["-A"]);
+    let sftp = connect(port, "anonymous", "guest").await?.unwrap();
+    let mut file = sftp.create("dir1/new.txt").await?;
+    file.write_all(b"Hello ").await?;
+    file.write_all(b"SFTP").await?;
+    file.shutdown().await?;
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/new.txt"))?,
+        "Hello SFTP"
+    );
+    sftp.create_dir("newdir").await?;
+    assert!(server.path().join("newdir").is_dir());
+    sftp.rename("dir1/new.txt", "newdir/moved.txt").await?;
+    assert!(server.path().join("newdir/moved.txt").is_file());
+    assert_eq!(
+        status(sftp.remove_dir("newdir").await.unwrap_err()),
+        StatusCode::Failure
+    );
+    sftp.remove_file("newdir/moved.txt").await?;
+    sftp.remove_dir("newdir").await?;
+    assert!(!server.path().join("newdir").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sftp_read_only() -> Result<(), Error> {
+    let (server, port) = sftp_server(&[]);
+    let sftp = connect(port, "anonymous", "").await?.unwrap();
+    assert_eq!(
+        status(sftp.create_dir("newdir").await.unwrap_err()),
+        StatusCode::PermissionDenied
+    );
+    assert_eq!(
+        status(sftp.remove_file("test.txt").await.unwrap_err()),
+        StatusCode::PermissionDenied
+    );
+    assert!(sftp.write("new.txt", b"new").await.is_err());
+    assert!(server.path().join("test.txt").exists());
+    assert!(!server.path().join("new.txt").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sftp_auth() -> Result<(), Error> {
+    let (server, port) = sftp_server(&["-a", "user:pass@/:rw", "-a", "@/dir1", "-A"]);
+    assert!(connect(port, "user", "wrong").await?.is_none());
+
+    let sftp = connect(port, "anonymous", "").await?.unwrap();
+    assert_eq!(
+        status(sftp.read("test.txt").await.unwrap_err()),
+        StatusCode::NoSuchFile
+    );
+    assert_eq!(sftp.read("dir1/test.txt").await?, b"This is dir1/test.txt");
+    let names: Vec<String> = sftp.read_dir("/").await?.map(|v| v.file_name()).collect();
+    assert_eq!(names, vec!["dir1"]);
+    assert_eq!(
+        status(sftp.create_dir("dir1/newdir").await.unwrap_err()),
+        StatusCode::PermissionDenied
+    );
+
+    let sftp = connect(port, "user", "pass").await?.unwrap();
+    assert_eq!(sftp.read("test.txt").await?, b"This is test.txt");
+    sftp.create_dir("dir1/newdir").await?;
+    assert!(server.path().join("dir1/newdir").is_dir());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sftp_no_anonymous() -> Result<(), Error> {
+    let (_server, port) = sftp_server(&["-a", "user:pass@/:rw"]);
+    assert!(connect(port, "anonymous", "").await?.is_none());
+    Ok(())
+}
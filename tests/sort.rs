@@ -27,3 +27,43 @@ fn search_dir_sort_by_name(server: TestServer) -> Result<(), Error> {
     assert_eq!(paths1, paths2);
     Ok(())
 }
+
+#[rstest]
+fn ls_dir_sort_by_ext(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?sort=ext", server.url()))?;
+    let paths = self::utils::retrieve_index_paths(&resp.text()?);
+    let files: Vec<&String> = paths.iter().filter(|v| !v.ends_with('/')).collect();
+    assert_eq!(files, ["😀.bin", "index.html", "test.html", "test.txt"]);
+    assert!(paths[0].ends_with('/'));
+    Ok(())
+}
+
+#[rstest]
+fn ls_dir_dirs_first(server: TestServer) -> Result<(), Error> {
+    let url = server.url();
+    let resp = reqwest::blocking::get(format!("{url}?sort=name&order=desc&dirs_first"))?;
+    let paths = self::utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths[0].ends_with('/'));
+    assert!(!paths[paths.len() - 1].ends_with('/'));
+    let resp = reqwest::blocking::get(format!("{url}?sort=name&dirs_first=false"))?;
+    let paths = self::utils::retrieve_index_paths(&resp.text()?);
+    let index = |name: &str| paths.get_index_of(name).unwrap();
+    assert!(index("dir1/") < index("index.html"));
+    assert!(index("index.html") < index("test.txt"));
+    assert!(index("test.html") < index("😀.bin"));
+    Ok(())
+}
+
+#[rstest]
+fn ls_dir_default_sort(
+    #[with(&["--default-sort", "name:desc"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = server.url();
+    let resp = reqwest::blocking::get(url.clone())?;
+    let paths1 = self::utils::retrieve_index_paths(&resp.text()?);
+    let resp = reqwest::blocking::get(format!("{url}?sort=name&order=asc"))?;
+    let mut paths2 = self::utils::retrieve_index_paths(&resp.text()?);
+    paths2.reverse();
+    assert_eq!(paths1, paths2);
+    Ok(())
+}
@@ -44,3 +44,24 @@ fn allow_symlink(
     assert!(paths.contains(&format!("{dir}/")));
     Ok(())
 }
+
+#[rstest]
+fn allow_symlink_paths(
+    #[with(&["--allow-symlink", "/public/**"])] server: TestServer,
+    tmpdir: TempDir,
+) -> Result<(), Error> {
+    std::fs::create_dir(server.path().join("public"))?;
+    symlink_dir(tmpdir.path(), server.path().join("public/foo")).expect("Couldn't create symlink");
+    symlink_dir(tmpdir.path(), server.path().join("foo")).expect("Couldn't create symlink");
+    let resp = reqwest::blocking::get(format!("{}public/foo/index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{}public/", server.url()))?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("foo/"));
+    let resp = reqwest::blocking::get(format!("{}foo/index.html", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(server.url())?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(!paths.contains("foo/"));
+    Ok(())
+}
@@ -0,0 +1,130 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{port, server, Error, TestServer};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Just enough of a TFTP client
+struct Tftp {
+    socket: UdpSocket,
+    port: u16,
+}
+
+impl Tftp {
+    fn new(port: u16) -> Result<Self, Error> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(Duration::from_secs(10)))?;
+        Ok(Self { socket, port })
+    }
+
+    fn request(&self, opcode: u8, name: &str, options: &[(&str, &str)]) -> Result<(), Error> {
+        let mut packet = vec![0, opcode];
+        for field in [name, "octet"]
+            .into_iter()
+            .chain(options.iter().flat_map(|(k, v)| [*k, *v]))
+        {
+            packet.extend_from_slice(field.as_bytes());
+            packet.push(0);
+        }
+        self.socket.send_to(&packet, ("127.0.0.1", self.port))?;
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<(Vec<u8>, SocketAddr), Error> {
+        let mut buf = [0; 1024];
+        let (len, peer) = self.socket.recv_from(&mut buf)?;
+        Ok((buf[..len].to_vec(), peer))
+    }
+
+    fn ack(&self, block: u16, peer: SocketAddr) -> Result<(), Error> {
+        let [high, low] = block.to_be_bytes();
+        self.socket.send_to(&[0, 4, high, low], peer)?;
+        Ok(())
+    }
+
+    /// Reads the data blocks that follow, acking each, until a short one
+    fn read_blocks(&self, blksize: usize) -> Result<Vec<Vec<u8>>, Error> {
+        let mut blocks = vec![];
+        loop {
+            let (packet, peer) = self.recv()?;
+            assert_eq!(&packet[..2], &[0, 3]);
+            let block = u16::from_be_bytes([packet[2], packet[3]]);
+            assert_eq!(block as usize, blocks.len() + 1);
+            self.ack(block, peer)?;
+            blocks.push(packet[4..].to_vec());
+            if packet.len() - 4 < blksize {
+                return Ok(blocks);
+            }
+        }
+    }
+
+    /// The error code a request is refused with
+    fn error_code(&self) -> Result<u16, Error> {
+        let (packet, _) = self.recv()?;
+        assert_eq!(&packet[..2], &[0, 5]);
+        Ok(u16::from_be_bytes([packet[2], packet[3]]))
+    }
+}
+
+fn tftp_server(args: &[&str]) -> (TestServer, Tftp) {
+    let tftp_port = port();
+    let tftp_port_arg = tftp_port.to_string();
+    let mut all_args = vec!["--tftp-port", tftp_port_arg.as_str()];
+    all_args.extend_from_slice(args);
+    (server(&all_args), Tftp::new(tftp_port).unwrap())
+}
+
+#[test]
+fn tftp_read() -> Result<(), Error> {
+    let (_server, tftp) = tftp_server(&[]);
+    tftp.request(1, "test.txt", &[])?;
+    assert_eq!(tftp.read_blocks(512)?, vec![b"This is test.txt".to_vec()]);
+    tftp.request(1, "\\dir1\\test.txt", &[])?;
+    assert_eq!(
+        tftp.read_blocks(512)?,
+        vec![b"This is dir1/test.txt".to_vec()]
+    );
+    Ok(())
+}
+
+#[test]
+fn tftp_options() -> Result<(), Error> {
+    let (_server, tftp) = tftp_server(&[]);
+    tftp.request(1, "test.txt", &[("blksize", "8"), ("tsize", "0")])?;
+    let (packet, peer) = tftp.recv()?;
+    assert_eq!(packet, b"\x00\x06blksize\x008\x00tsize\x0016\x00");
+    tftp.ack(0, peer)?;
+    assert_eq!(
+        tftp.read_blocks(8)?,
+        vec![b"This is ".to_vec(), b"test.txt".to_vec(), vec![]]
+    );
+    Ok(())
+}
+
+#[test]
+fn tftp_refused() -> Result<(), Error> {
+    let (_server, tftp) = tftp_server(&["--hidden", ".git"]);
+    tftp.request(1, "missing.txt", &[])?;
+    assert_eq!(tftp.error_code()?, 1);
+    tftp.request(1, "dir1", &[])?;
+    assert_eq!(tftp.error_code()?, 1);
+    tftp.request(1, ".git/test.txt", &[])?;
+    assert_eq!(tftp.error_code()?, 1);
+    tftp.request(2, "new.txt", &[])?;
+    assert_eq!(tftp.error_code()?, 2);
+    Ok(())
+}
+
+#[test]
+fn tftp_anonymous_only() -> Result<(), Error> {
+    let (_server, tftp) = tftp_server(&["-a", "user:pass@/:rw", "-a", "@/dir1"]);
+    tftp.request(1, "test.txt", &[])?;
+    assert_eq!(tftp.error_code()?, 1);
+    tftp.request(1, "dir1/test.txt", &[])?;
+    assert_eq!(
+        tftp.read_blocks(512)?,
+        vec![b"This is dir1/test.txt".to_vec()]
+    );
+    Ok(())
+}
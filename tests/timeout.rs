@@ -0,0 +1,93 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Reads until the server closes the connection, however it does
+fn read_until_closed(stream: &mut TcpStream) -> Vec<u8> {
+    let mut data = vec![];
+    let mut buf = [0; 65536];
+    while let Ok(n @ 1..) = stream.read(&mut buf) {
+        data.extend_from_slice(&buf[..n]);
+    }
+    data
+}
+
+#[rstest]
+fn read_timeout_idle(#[with(&["--read-timeout", "1s"])] server: TestServer) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let start = Instant::now();
+    assert!(read_until_closed(&mut stream).is_empty());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+#[rstest]
+fn read_timeout_headers(
+    #[with(&["--read-timeout", "1s"])] server: TestServer,
+) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n")?;
+    let start = Instant::now();
+    read_until_closed(&mut stream);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+#[rstest]
+fn read_timeout_body(
+    #[with(&["-A", "--read-timeout", "1s"])] server: TestServer,
+) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")?;
+    let resp = String::from_utf8(read_until_closed(&mut stream))?;
+    assert!(resp.starts_with("HTTP/1.1 408"));
+    assert!(!server.path().join("file1").exists());
+
+    // A body that keeps coming, however slowly, is fine
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    stream.write_all(b"PUT /file2 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\n")?;
+    for byte in b"abc" {
+        sleep(Duration::from_millis(600));
+        stream.write_all(&[*byte])?;
+    }
+    let mut buf = [0; 12];
+    stream.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"HTTP/1.1 201");
+    Ok(())
+}
+
+#[rstest]
+fn write_timeout(#[with(&["--write-timeout", "1s"])] server: TestServer) -> Result<(), Error> {
+    let size = 200 * 1024 * 1024;
+    std::fs::File::create(server.path().join("large.bin"))?.set_len(size)?;
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    stream.write_all(b"GET /large.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+    // Take nothing while the server fills the socket buffers
+    sleep(Duration::from_secs(3));
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let data = read_until_closed(&mut stream);
+    assert!((data.len() as u64) < size);
+    Ok(())
+}
+
+#[rstest]
+fn no_timeouts(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    sleep(Duration::from_millis(1500));
+    stream.write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\na")?;
+    sleep(Duration::from_millis(1500));
+    stream.write_all(b"bc")?;
+    let mut buf = [0; 12];
+    stream.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"HTTP/1.1 201");
+    Ok(())
+}
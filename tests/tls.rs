@@ -32,6 +32,157 @@ fn tls_works(#[case] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+/// HSTS is only sent over TLS.
+#[rstest]
+fn tls_security_headers(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+        "--security-headers",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client.get(server.url()).send()?.error_for_status()?;
+    assert_eq!(
+        resp.headers().get("strict-transport-security").unwrap(),
+        "max-age=31536000"
+    );
+    Ok(())
+}
+
+/// Clients below the minimum TLS version are refused.
+#[rstest]
+fn tls_min_version(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+        "--tls-min-version", "1.3",
+        "--tls-ciphers", "TLS_AES_256_GCM_SHA384,TLS13_CHACHA20_POLY1305_SHA256",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    client.get(server.url()).send()?.error_for_status()?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .max_tls_version(reqwest::tls::Version::TLS_1_2)
+        .build()?;
+    assert!(client.get(server.url()).send().is_err());
+    Ok(())
+}
+
+/// Plain HTTP on the redirect port is sent to the same path and query over HTTPS.
+#[rstest]
+fn tls_http_redirect() -> Result<(), Error> {
+    let redirect_port = port();
+    let server = server(vec![
+        "--tls-cert".to_string(),
+        "tests/data/cert.pem".to_string(),
+        "--tls-key".to_string(),
+        "tests/data/key_pkcs8.pem".to_string(),
+        "--http-redirect-port".to_string(),
+        redirect_port.to_string(),
+    ]);
+    let client = ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let resp = client
+        .get(format!("http://localhost:{redirect_port}/dir1/?q=a%20b"))
+        .send()?;
+    assert_eq!(resp.status(), 301);
+    assert_eq!(
+        resp.headers().get("location").unwrap(),
+        &format!("https://localhost:{}/dir1/?q=a%20b", server.port())
+    );
+    Ok(())
+}
+
+/// The redirect port is only for TLS.
+#[rstest]
+fn http_redirect_without_tls() -> Result<(), Error> {
+    let port = port().to_string();
+    Command::cargo_bin("dufs")?
+        .args(["--http-redirect-port", "8080", "--port", &port])
+        .assert()
+        .failure()
+        .stderr(contains("Cannot use http-redirect-port without tls-cert"));
+
+    Ok(())
+}
+
+/// Unknown cipher suites, or none for the TLS versions, throw errors.
+#[rstest]
+#[case(
+    "TLS_RSA_WITH_RC4_128_SHA",
+    "Unknown TLS cipher suite `TLS_RSA_WITH_RC4_128_SHA`"
+)]
+#[case(
+    "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+    "Invalid TLS ciphers for the TLS versions"
+)]
+fn wrong_tls_ciphers(#[case] ciphers: &str, #[case] error: &str) -> Result<(), Error> {
+    let port = port().to_string();
+    Command::cargo_bin("dufs")?
+        .args([
+            "--tls-cert",
+            "tests/data/cert.pem",
+            "--tls-key",
+            "tests/data/key_pkcs8.pem",
+            "--tls-min-version",
+            "1.3",
+            "--tls-ciphers",
+            ciphers,
+            "--port",
+            &port,
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(error));
+
+    Ok(())
+}
+
+/// Replaced certificates are served after SIGHUP.
+#[cfg(unix)]
+#[rstest]
+fn tls_reload_on_sighup() -> Result<(), Error> {
+    let certs = assert_fs::TempDir::new()?;
+    let (cert_file, key_file) = (certs.path().join("cert.pem"), certs.path().join("key.pem"));
+    std::fs::copy("tests/data/cert.pem", &cert_file)?;
+    std::fs::copy("tests/data/key_pkcs8.pem", &key_file)?;
+    let server = server(vec![
+        "--tls-cert".to_string(),
+        cert_file.display().to_string(),
+        "--tls-key".to_string(),
+        key_file.display().to_string(),
+    ]);
+    let peer_certificate = || -> Result<Vec<u8>, Error> {
+        let client = ClientBuilder::new()
+            .danger_accept_invalid_certs(true)
+            .tls_info(true)
+            .build()?;
+        let resp = client.get(server.url()).send()?.error_for_status()?;
+        let info = resp.extensions().get::<reqwest::tls::TlsInfo>().unwrap();
+        Ok(info.peer_certificate().unwrap().to_vec())
+    };
+    let old_cert = peer_certificate()?;
+
+    std::fs::copy("tests/data/cert_ecdsa.pem", &cert_file)?;
+    std::fs::copy("tests/data/key_ecdsa.pem", &key_file)?;
+    std::process::Command::new("kill")
+        .args(["-HUP", &server.pid().to_string()])
+        .status()?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let new_cert = peer_certificate()?;
+    assert_ne!(old_cert, new_cert);
+    Ok(())
+}
+
 /// Wrong path for cert throws error.
 #[rstest]
 fn wrong_path_cert() -> Result<(), Error> {
@@ -71,3 +222,43 @@ fn wrong_path_key() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// ACME certificates can't be combined with a certificate file.
+#[rstest]
+fn acme_conflicts_with_tls_cert() -> Result<(), Error> {
+    let port = port().to_string();
+    Command::cargo_bin("dufs")?
+        .args([
+            "--tls-cert",
+            "tests/data/cert.pem",
+            "--tls-key",
+            "tests/data/key_pkcs8.pem",
+            "--acme-domain",
+            "example.com",
+            "--port",
+            &port,
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Cannot use both acme-domain and tls-cert"));
+
+    Ok(())
+}
+
+/// The ACME cache holds private keys, it must not be served.
+#[rstest]
+fn acme_cache_dir_inside_serve_path() -> Result<(), Error> {
+    let port = port().to_string();
+    let tmpdir = assert_fs::TempDir::new()?;
+    let cache_dir = tmpdir.path().join("acme");
+    Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .args(["--acme-domain", "example.com", "--acme-cache-dir"])
+        .arg(&cache_dir)
+        .args(["--port", &port])
+        .assert()
+        .failure()
+        .stderr(contains("must not be inside the serve path"));
+
+    Ok(())
+}
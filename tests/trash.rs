@@ -0,0 +1,131 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::TempDir;
+use fixtures::{server, Error};
+use rstest::rstest;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+fn trash_list(url: &str) -> Result<Vec<Value>, Error> {
+    let resp = reqwest::blocking::get(format!("{url}?trash"))?;
+    assert_eq!(resp.status(), 200);
+    let value: Value = resp.text()?.parse()?;
+    Ok(value.as_array().unwrap().clone())
+}
+
+#[rstest]
+fn trash_delete_and_restore() -> Result<(), Error> {
+    let trash = TempDir::new()?;
+    let server = server(vec![
+        "-A".to_string(),
+        "--trash-dir".to_string(),
+        trash.path().display().to_string(),
+    ]);
+    let resp = fetch!(b"DELETE", format!("{}dir1/test.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"DELETE", format!("{}dir2", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    assert!(!server.path().join("dir1/test.txt").exists());
+    assert!(!server.path().join("dir2").exists());
+
+    let items = trash_list(server.url().as_str())?;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["path"], "dir2");
+    assert_eq!(items[1]["path"], "dir1/test.txt");
+
+    let id = items[1]["id"].as_str().unwrap();
+    let resp = fetch!(b"POST", format!("{}?restore={id}", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/test.txt"))?,
+        "This is dir1/test.txt"
+    );
+    let resp = fetch!(b"POST", format!("{}?restore={id}", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+
+    std::fs::create_dir(server.path().join("dir2"))?;
+    let id = items[0]["id"].as_str().unwrap();
+    let resp = fetch!(b"POST", format!("{}?restore={id}", server.url())).send()?;
+    assert_eq!(resp.status(), 409);
+    assert_eq!(trash_list(server.url().as_str())?.len(), 1);
+    Ok(())
+}
+
+#[rstest]
+fn trash_replaced_entries() -> Result<(), Error> {
+    let trash = TempDir::new()?;
+    let server = server(vec![
+        "-A".to_string(),
+        "--trash-dir".to_string(),
+        trash.path().display().to_string(),
+    ]);
+    let resp = fetch!(b"PUT", format!("{}dir1/test.txt", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"COPY", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir2", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+
+    let items = trash_list(server.url().as_str())?;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["path"], "dir2");
+    assert_eq!(items[1]["path"], "dir1/test.txt");
+    let id = items[1]["id"].as_str().unwrap();
+    std::fs::remove_file(server.path().join("dir1/test.txt"))?;
+    let resp = fetch!(b"POST", format!("{}?restore={id}", server.url())).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/test.txt"))?,
+        "This is dir1/test.txt"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn trash_needs_root_readwrite() -> Result<(), Error> {
+    let trash = TempDir::new()?;
+    let server = server(vec![
+        "-A".to_string(),
+        "-a".to_string(),
+        "admin:admin@/:rw".to_string(),
+        "-a".to_string(),
+        "user:pass@/dir1:rw".to_string(),
+        "--trash-dir".to_string(),
+        trash.path().display().to_string(),
+    ]);
+    let resp = fetch!(b"GET", format!("{}?trash", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}?trash", server.url()))
+        .basic_auth("admin", Some("admin"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn trash_purge() -> Result<(), Error> {
+    let trash = TempDir::new()?;
+    let server = server(vec![
+        "-A".to_string(),
+        "--trash-dir".to_string(),
+        trash.path().display().to_string(),
+        "--trash-retention".to_string(),
+        "1s".to_string(),
+    ]);
+    let resp = fetch!(b"DELETE", format!("{}dir1", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(trash_list(server.url().as_str())?.len(), 1);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !trash_list(server.url().as_str())?.is_empty() {
+        assert!(Instant::now() < deadline, "trash not purged");
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    assert_eq!(std::fs::read_dir(trash.path().join("files"))?.count(), 0);
+    Ok(())
+}
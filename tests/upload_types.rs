@@ -0,0 +1,86 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+
+/// The start of a png, enough to be told apart
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+/// The start of an executable, enough to be told apart
+fn elf() -> Vec<u8> {
+    let mut head = b"\x7fELF\x02\x01\x01".to_vec();
+    head.resize(64, 0);
+    head
+}
+
+#[rstest]
+fn upload_allow_ext(
+    #[with(&["-A", "--upload-allow-ext", "png,jpg"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}a.png", server.url()))
+        .body(PNG)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{}a.txt", server.url()))
+        .body("text")
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    assert_eq!(
+        resp.text()?,
+        "`a.txt` is not a type of file that can be uploaded"
+    );
+    assert!(!server.path().join("a.txt").exists());
+    // An executable passed off as an image
+    let resp = fetch!(b"PUT", format!("{}b.png", server.url()))
+        .body(elf())
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    assert!(!server.path().join("b.png").exists());
+    Ok(())
+}
+
+#[rstest]
+fn upload_deny_ext(
+    #[with(&["-A", "--upload-deny-ext", "exe,elf"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}a.txt", server.url()))
+        .body("text")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{}a.EXE", server.url()))
+        .body("text")
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    // Refused before the existing file is truncated
+    let resp = fetch!(b"PUT", format!("{}test.html", server.url()))
+        .body(elf())
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    assert!(std::fs::metadata(server.path().join("test.html"))?.len() > 0);
+    Ok(())
+}
+
+#[rstest]
+fn upload_types_move_copy(
+    #[with(&["-A", "--upload-deny-ext", "exe"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"MOVE", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}test.exe", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    let resp = fetch!(b"COPY", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}test.exe", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    assert!(!server.path().join("test.exe").exists());
+    let resp = fetch!(b"MOVE", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}moved.html", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"MOVE", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir.exe", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
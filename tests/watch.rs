@@ -0,0 +1,93 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Reads the events of the stream in the background, as `event` and `data` pairs
+fn watch(url: String) -> mpsc::Receiver<(String, String)> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(None)
+            .build()
+            .unwrap();
+        let resp = client.get(url).send().unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        let mut event = String::new();
+        for line in BufReader::new(resp).lines() {
+            let line = line.unwrap();
+            if let Some(v) = line.strip_prefix("event: ") {
+                event = v.to_string();
+            } else if let Some(v) = line.strip_prefix("data: ") {
+                if tx.send((event.clone(), v.to_string())).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Waits for an event about the entry, skipping the others
+fn wait_event(rx: &mpsc::Receiver<(String, String)>, kind: &str, name: &str) {
+    loop {
+        let (event, data) = rx.recv_timeout(Duration::from_secs(10)).expect("no event");
+        if event == kind && data.contains(&format!(r#""name":"{name}""#)) {
+            return;
+        }
+    }
+}
+
+#[rstest]
+fn watch_dir(
+    #[with(&["-A", "--hidden", "*.tmp", "--exclude", "*.secret"])] server: TestServer,
+) -> Result<(), Error> {
+    let rx = watch(format!("{}dir1/?watch", server.url()));
+    // Give the watcher time to start
+    std::thread::sleep(Duration::from_millis(500));
+
+    std::fs::write(server.path().join("dir1/hidden.tmp"), "x")?;
+    let resp = fetch!(b"PUT", format!("{}dir1/new.txt", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    wait_event(&rx, "create", "new.txt");
+
+    let resp = fetch!(b"DELETE", format!("{}dir1/new.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    wait_event(&rx, "delete", "new.txt");
+
+    // Nothing is told about hidden or excluded entries nor other folders
+    std::fs::write(server.path().join("dir1/excluded.secret"), "x")?;
+    std::fs::write(server.path().join("dir2/other.txt"), "x")?;
+    std::fs::write(server.path().join("dir1/last.txt"), "x")?;
+    loop {
+        let (event, data) = rx.recv_timeout(Duration::from_secs(10)).expect("no event");
+        assert!(!data.contains("hidden.tmp"));
+        assert!(!data.contains("excluded.secret"));
+        assert!(!data.contains("other.txt"));
+        if event == "create" && data.contains(r#""name":"last.txt""#) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[rstest]
+fn watch_needs_read_access(
+    #[with(&["-a", "user:pass@/dir1"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}dir2/?watch", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
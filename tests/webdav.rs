@@ -40,6 +40,150 @@ fn propfind_dir_depth0(server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn propfind_dir_depth_infinity(
+    #[with(&["--dav-infinite-depth"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", server.url())
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.starts_with("<?xml"));
+    assert!(body.ends_with("</D:multistatus>"));
+    assert!(body.contains("<D:href>/</D:href>"));
+    assert!(body.contains("<D:href>/dir1/</D:href>"));
+    assert!(body.contains("<D:href>/dir1/test.html</D:href>"));
+    assert!(body.contains("<D:href>/dir4/hidden</D:href>"));
+    Ok(())
+}
+
+#[rstest]
+fn propfind_dir_depth_infinity_hidden(
+    #[with(&["--dav-infinite-depth", "--hidden", "dir1"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", server.url())
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(!body.contains("/dir1/"));
+    assert!(body.contains("<D:href>/dir2/test.html</D:href>"));
+    Ok(())
+}
+
+#[rstest]
+fn propfind_dir_depth_infinity_not_allow(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", server.url())
+        .header("depth", "infinity")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<D:propfind-finite-depth/>"));
+    Ok(())
+}
+
+fn sync_collection_body(token: &str, level: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:sync-collection xmlns:D="DAV:">
+<D:sync-token>{token}</D:sync-token>
+<D:sync-level>{level}</D:sync-level>
+<D:prop><D:getetag/></D:prop>
+</D:sync-collection>"#
+    )
+}
+
+fn extract_sync_token(body: &str) -> &str {
+    let start = body.find("<D:sync-token>").unwrap() + "<D:sync-token>".len();
+    let end = body.find("</D:sync-token>").unwrap();
+    &body[start..end]
+}
+
+#[rstest]
+fn sync_collection_initial(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body("", "1"))
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/test.html</D:href>"));
+    assert!(body.contains("<D:href>/dir1/</D:href>"));
+    assert!(!body.contains("<D:href>/dir1/test.html</D:href>"));
+    assert!(!extract_sync_token(&body).is_empty());
+    Ok(())
+}
+
+#[rstest]
+fn sync_collection_changes(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    // Let the fixtures age past the mtime slack
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body("", "1"))
+        .send()?;
+    let body = resp.text()?;
+    let token = extract_sync_token(&body);
+    let resp = fetch!(b"PUT", format!("{}new.html", server.url()))
+        .body("abc")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"DELETE", format!("{}test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body(token, "1"))
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/new.html</D:href>"));
+    assert!(
+        body.contains("<D:href>/test.html</D:href>\n<D:status>HTTP/1.1 404 Not Found</D:status>")
+    );
+    assert!(!body.contains("<D:href>/index.html</D:href>"));
+    assert_ne!(extract_sync_token(&body), token);
+    Ok(())
+}
+
+#[rstest]
+fn sync_collection_invalid_token(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body("urn:dufs:sync:0-0", "1"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<D:valid-sync-token/>"));
+    Ok(())
+}
+
+#[rstest]
+fn sync_collection_infinite(
+    #[with(&["--dav-infinite-depth"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body("", "infinite"))
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    assert!(resp.text()?.contains("<D:href>/dir1/test.html</D:href>"));
+    Ok(())
+}
+
+#[rstest]
+fn sync_collection_infinite_not_allow(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body("", "infinite"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<D:sync-traversal-supported/>"));
+    Ok(())
+}
+
+#[rstest]
+fn report_unsupported(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(r#"<D:expand-property xmlns:D="DAV:"/>"#)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<D:supported-report/>"));
+    Ok(())
+}
+
 #[rstest]
 fn propfind_404(server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"PROPFIND", format!("{}404", server.url())).send()?;
@@ -70,6 +214,60 @@ fn propfind_file(server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn propfind_file_allprop(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", format!("{}test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:getetag>\""));
+    assert!(body.contains("<D:getcontenttype>text/html</D:getcontenttype>"));
+    assert!(body.contains("<D:creationdate>"));
+    Ok(())
+}
+
+#[rstest]
+fn propfind_file_prop(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", format!("{}test.html", server.url()))
+        .body(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:" xmlns:x="urn:x">
+<D:prop><D:getetag/><D:getcontentlength/><x:foo/></D:prop>
+</D:propfind>"#,
+        )
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:getetag>"));
+    assert!(body.contains("<D:getcontentlength>17</D:getcontentlength>"));
+    assert!(!body.contains("<D:displayname>"));
+    assert!(body.contains(r#"<foo xmlns="urn:x"/>"#));
+    assert!(body.contains("<D:status>HTTP/1.1 404 Not Found</D:status>"));
+    Ok(())
+}
+
+#[rstest]
+fn propfind_dir_propname(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", format!("{}dir1", server.url()))
+        .header("depth", "0")
+        .body(r#"<propfind xmlns="DAV:"><propname/></propfind>"#)
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:displayname/>"));
+    assert!(body.contains("<D:resourcetype/>"));
+    assert!(!body.contains("<D:displayname>dir1</D:displayname>"));
+    Ok(())
+}
+
+#[rstest]
+fn propfind_invalid_body(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PROPFIND", format!("{}dir1", server.url()))
+        .body("<propfind")
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
 #[rstest]
 fn proppatch_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"PROPPATCH", format!("{}test.html", server.url())).send()?;
@@ -113,12 +311,111 @@ fn copy_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"COPY", format!("{}test.html", server.url()))
         .header("Destination", &new_url)
         .send()?;
-    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.status(), 201);
     let resp = reqwest::blocking::get(new_url)?;
     assert_eq!(resp.status(), 200);
     Ok(())
 }
 
+#[rstest]
+fn copy_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let new_url = format!("{}dir5", server.url());
+    let resp = fetch!(b"COPY", format!("{}dir1", server.url()))
+        .header("Destination", &new_url)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    for f in FILES {
+        let resp = reqwest::blocking::get(format!("{new_url}/{}", utils::encode_uri(f)))?;
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = reqwest::blocking::get(format!("{}dir1/test.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn copy_dir_hidden(
+    #[with(&["-A", "--hidden", "*.txt", "--exclude", "secret"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::create_dir(server.path().join("dir1/secret"))?;
+    std::fs::write(server.path().join("dir1/secret/a"), "abc")?;
+    let resp = fetch!(b"COPY", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir5", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("dir5/test.html").exists());
+    assert!(!server.path().join("dir5/test.txt").exists());
+    assert!(!server.path().join("dir5/secret").exists());
+    Ok(())
+}
+
+#[rstest]
+fn copy_dir_depth0(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let new_url = format!("{}dir5", server.url());
+    let resp = fetch!(b"COPY", format!("{}dir1", server.url()))
+        .header("Destination", &new_url)
+        .header("Depth", "0")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = reqwest::blocking::get(format!("{new_url}/"))?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{new_url}/test.html"))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn copy_dir_depth1(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"COPY", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir5", server.url()))
+        .header("Depth", "1")
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn copy_dir_into_itself(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"COPY", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir1/dir5", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn copy_file_overwrite(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"COPY", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}index.html", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.text()?, "This is test.html");
+    Ok(())
+}
+
+#[rstest]
+fn copy_file_overwrite_false(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"COPY", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}index.html", server.url()))
+        .header("Overwrite", "F")
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn copy_file_overwrite_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"COPY", format!("{}test.html", server.url()))
+        .header("Destination", format!("{}test2.html", server.url()))
+        .header("Overwrite", "X")
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
 #[rstest]
 fn copy_not_allow_upload(server: TestServer) -> Result<(), Error> {
     let new_url = format!("{}test2.html", server.url());
@@ -146,7 +443,7 @@ fn move_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"MOVE", &origin_url)
         .header("Destination", &new_url)
         .send()?;
-    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.status(), 201);
     let resp = reqwest::blocking::get(new_url)?;
     assert_eq!(resp.status(), 200);
     let resp = reqwest::blocking::get(origin_url)?;
@@ -154,6 +451,80 @@ fn move_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn move_file_if_match(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let origin_url = format!("{}test.html", server.url());
+    let new_url = format!("{}test2.html", server.url());
+    let resp = fetch!(b"MOVE", &origin_url)
+        .header("Destination", &new_url)
+        .header("If-Match", r#""0-0""#)
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = reqwest::blocking::get(&origin_url)?;
+    assert_eq!(resp.status(), 200);
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let resp = fetch!(b"MOVE", &origin_url)
+        .header("Destination", &new_url)
+        .header("If-Match", etag)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn move_dir_overwrite(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"MOVE", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir2", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(format!("{}dir1/test.html", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    let resp = reqwest::blocking::get(format!("{}dir2/test.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn move_dir_overwrite_false(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"MOVE", format!("{}dir1", server.url()))
+        .header("Destination", format!("{}dir2", server.url()))
+        .header("Overwrite", "F")
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    let resp = reqwest::blocking::get(format!("{}dir1/", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn move_to_itself(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let origin_url = format!("{}test.html", server.url());
+    let resp = fetch!(b"MOVE", &origin_url)
+        .header("Destination", &origin_url)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = reqwest::blocking::get(origin_url)?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn move_to_parent(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::create_dir(server.path().join("dir1/dir1"))?;
+    std::fs::write(server.path().join("dir1/dir1/test.html"), "abc")?;
+    let resp = fetch!(b"MOVE", format!("{}dir1/dir1", server.url()))
+        .header("Destination", format!("{}dir1", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = reqwest::blocking::get(format!("{}dir1/dir1/test.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"COPY", format!("{}dir1/dir1", server.url()))
+        .header("Destination", format!("{}dir1", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
 #[rstest]
 fn move_not_allow_upload(#[with(&["--allow-delete"])] server: TestServer) -> Result<(), Error> {
     let origin_url = format!("{}test.html", server.url());
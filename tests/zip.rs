@@ -0,0 +1,179 @@
+mod fixtures;
+mod utils;
+
+use async_zip::base::read::mem::ZipFileReader;
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::fs::{self, File};
+
+const ZIP64_EOCDR_SIGNATURE: &[u8] = b"PK\x06\x06";
+const ZIP64_EOCDL_SIGNATURE: &[u8] = b"PK\x06\x07";
+
+/// The names and sizes of the entries, as a Zip64-aware reader sees them
+fn read_entries(data: Vec<u8>) -> Result<Vec<(String, u64)>, Error> {
+    let reader = futures::executor::block_on(ZipFileReader::new(data))?;
+    let mut entries = vec![];
+    for entry in reader.file().entries() {
+        entries.push((
+            entry.filename().as_str()?.to_string(),
+            entry.uncompressed_size(),
+        ));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Whether the archive ends with the Zip64 end of central directory records
+fn has_zip64_records(data: &[u8]) -> bool {
+    // The locator comes right before the 22-byte end of central directory record
+    let locator = data.len() - 22 - 20;
+    &data[locator..locator + 4] == ZIP64_EOCDL_SIGNATURE
+        && data.windows(4).any(|v| v == ZIP64_EOCDR_SIGNATURE)
+}
+
+#[rstest]
+fn zip_large_files(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let dir = server.path().join("large");
+    fs::create_dir(&dir)?;
+    // Sparse, so it costs no disk space, and zeros deflate to next to nothing
+    File::create(dir.join("a.bin"))?.set_len(4_300_000_000)?;
+    fs::write(dir.join("b.txt"), "after 4GB")?;
+    // Deflating 4GB takes a while
+    let resp = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()?
+        .get(format!("{}large?zip", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let data = resp.bytes()?.to_vec();
+    assert!(has_zip64_records(&data));
+    assert_eq!(
+        read_entries(data)?,
+        vec![
+            ("a.bin".to_string(), 4_300_000_000),
+            ("b.txt".to_string(), 9)
+        ]
+    );
+    Ok(())
+}
+
+#[rstest]
+fn zip_many_entries(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let dir = server.path().join("many");
+    fs::create_dir(&dir)?;
+    for i in 0..65600 {
+        File::create(dir.join(format!("{i}.txt")))?;
+    }
+    let resp = reqwest::blocking::get(format!("{}many?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let data = resp.bytes()?.to_vec();
+    assert!(has_zip64_records(&data));
+    assert_eq!(read_entries(data)?.len(), 65600);
+    Ok(())
+}
+
+#[rstest]
+fn zip_no_zip64(#[with(&["-A", "--no-zip64"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1?zip", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let data = resp.bytes()?.to_vec();
+    assert!(!has_zip64_records(&data));
+    assert_eq!(
+        read_entries(data)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>(),
+        vec!["index.html", "test.html", "test.txt", "😀.bin"]
+    );
+    Ok(())
+}
+
+#[rstest]
+fn zip_no_zip64_refused(#[with(&["-A", "--no-zip64"])] server: TestServer) -> Result<(), Error> {
+    let dir = server.path().join("large");
+    fs::create_dir(&dir)?;
+    File::create(dir.join("a.bin"))?.set_len(4_500_000_000)?;
+    let resp = reqwest::blocking::get(format!("{}large?zip", server.url()))?;
+    assert_eq!(resp.status(), 422);
+    assert_eq!(
+        resp.text()?,
+        "The zip archive would need Zip64, `a.bin` is over 4GB"
+    );
+
+    File::create(dir.join("a.bin"))?.set_len(3_000_000_000)?;
+    File::create(dir.join("b.bin"))?.set_len(3_000_000_000)?;
+    let resp = reqwest::blocking::get(format!("{}large?zip", server.url()))?;
+    assert_eq!(resp.status(), 422);
+    assert_eq!(
+        resp.text()?,
+        "The zip archive would need Zip64, it would be over 4GB"
+    );
+
+    let dir = server.path().join("many");
+    fs::create_dir(&dir)?;
+    for i in 0..65536 {
+        File::create(dir.join(format!("{i}.txt")))?;
+    }
+    let resp = fetch!(b"HEAD", format!("{}many?zip", server.url())).send()?;
+    assert_eq!(resp.status(), 422);
+    let resp = reqwest::blocking::get(format!("{}many?zip", server.url()))?;
+    assert_eq!(
+        resp.text()?,
+        "The zip archive would need Zip64, it has 65536 entries, more than 65535"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn zip_archive_progress(
+    #[with(&["-A", "--archive-progress"])] server: TestServer,
+) -> Result<(), Error> {
+    let size: u64 = fs::read_dir(server.path().join("dir1"))?
+        .map(|v| v.unwrap().metadata().unwrap())
+        .filter(|v| v.is_file())
+        .map(|v| v.len())
+        .sum();
+    // Followed before the download starts
+    let events = reqwest::blocking::get(format!(
+        "{}__dufs_v{}_downloads/abc-1",
+        server.url(),
+        env!("CARGO_PKG_VERSION")
+    ))?;
+    assert_eq!(events.status(), 200);
+    assert_eq!(
+        events.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+    let resp = reqwest::blocking::get(format!("{}dir1?zip&download-id=abc-1", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("x-archive-size").unwrap(),
+        &size.to_string()
+    );
+    assert_eq!(resp.headers().get("x-download-id").unwrap(), "abc-1");
+    resp.bytes()?;
+    let events = events.text()?;
+    assert!(events.ends_with(&format!(
+        "event: done\ndata: {{\"sent\":{size},\"total\":{size},\"done\":true}}\n\n"
+    )));
+
+    let resp = reqwest::blocking::get(format!("{}dir1?zip", server.url()))?;
+    assert_eq!(resp.headers().get("x-download-id").unwrap().len(), 36);
+    let resp = reqwest::blocking::get(format!("{}dir1?zip&download-id=a/b", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn zip_no_archive_progress(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1?zip&download-id=abc", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert!(!resp.headers().contains_key("x-archive-size"));
+    let resp = reqwest::blocking::get(format!(
+        "{}__dufs_v{}_downloads/abc",
+        server.url(),
+        env!("CARGO_PKG_VERSION")
+    ))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}